@@ -0,0 +1,14 @@
+fn main() {
+    println!("cargo:rerun-if-changed=proto/hourcoin.proto");
+
+    #[cfg(feature = "grpc")]
+    {
+        // A vendored protoc, so building with `--features grpc` needs no system install
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().expect("vendored protoc"));
+        // The generated `connect` needs the 2021 prelude; clients build a `Channel` themselves
+        tonic_build::configure()
+            .build_transport(false)
+            .compile_protos(&["proto/hourcoin.proto"], &["proto"])
+            .expect("failed to compile proto/hourcoin.proto");
+    }
+}
@@ -12,10 +12,10 @@ fn main() {
     println!("=== Hourcoin Proof of Time Demo ===\n");
 
     // Set up the validator with medium difficulty
-    let difficulty = 0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF;
+    let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
     let mut validator = Validator::new(difficulty);
 
-    println!("1. Validator initialized with difficulty: 0x{:X}", difficulty);
+    println!("1. Validator initialized with difficulty: {}", difficulty);
     println!("   Block count: {}\n", validator.get_block_count());
 
     // Start the first mining round
@@ -44,14 +44,16 @@ fn main() {
             to_addr: "Alice".to_owned(),
             value: 2.0,
             timestamp: valid_timestamp,
+            lock_until: None,
         }],
+        witnesses: vec![],
     };
 
-    let mut genesis_block = Block::new(0, valid_timestamp, vec![0; 32], vec![coinbase]);
+    let mut genesis_block = Block::new(0, valid_timestamp, BlockHash::ZERO, vec![coinbase]);
     genesis_block.mine(difficulty);
 
     println!("   Block mined! Hash: {}", hex::encode(&genesis_block.hash));
-    println!("   Nonce: {}\n", genesis_block.nonce);
+    println!("   Nonce: {}\n", genesis_block.header.nonce);
 
     // Submit the block
     let result = validator.validate_block_submission(genesis_block.clone(), "Alice".to_string());
@@ -83,11 +85,13 @@ fn main() {
             to_addr: "Alice".to_owned(),
             value: 2.0,
             timestamp: timestamp2,
+            lock_until: None,
         }],
+        witnesses: vec![],
     };
 
-    let prev_hash = genesis_block.hash.clone();
-    let mut block2 = Block::new(1, timestamp2, prev_hash.clone(), vec![coinbase2]);
+    let prev_hash = genesis_block.hash;
+    let mut block2 = Block::new(1, timestamp2, prev_hash, vec![coinbase2]);
     block2.mine(difficulty);
 
     let result2 = validator.validate_block_submission(block2, "Alice".to_string());
@@ -119,7 +123,9 @@ fn main() {
             to_addr: "Bob".to_owned(),
             value: 2.0,
             timestamp: valid_timestamp3,
+            lock_until: None,
         }],
+        witnesses: vec![],
     };
 
     let mut block3 = Block::new(1, valid_timestamp3, prev_hash, vec![coinbase3]);
@@ -0,0 +1,45 @@
+//! Regtest Simulation
+//!
+//! Runs five miners of very different hashrates against an in-process
+//! validator in virtual time, once with fixed lockouts and once with
+//! lockouts that double for repeat winners, and prints how the blocks
+//! were shared out under each.
+//!
+//! Usage:
+//!   cargo run --example simulation [blocks] [seed]
+
+use blockchainlib::simulation::{SimulatedMiner, Simulation};
+use blockchainlib::{ChainParams, LockoutPolicy};
+use std::env;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let blocks = args.get(1).and_then(|arg| arg.parse().ok()).unwrap_or(1_000);
+    let seed = args.get(2).and_then(|arg| arg.parse().ok()).unwrap_or(42);
+
+    let params = ChainParams::regtest();
+    let miners = vec![
+        SimulatedMiner::new("whale", 200.0),
+        SimulatedMiner::new("pool", 80.0),
+        SimulatedMiner::new("rig", 20.0),
+        SimulatedMiner::new("laptop", 5.0),
+        SimulatedMiner::new("phone", 1.0),
+    ];
+
+    println!("=== Hourcoin Regtest Simulation ===");
+    println!("{} blocks, seed {}, lockout {}ms, tonce challenge {}ms\n",
+        blocks, seed, params.lockout_duration_ms, params.tonce_challenge_duration_ms);
+
+    let policies = [
+        ("Fixed lockouts", LockoutPolicy::Fixed),
+        ("Exponential lockouts", LockoutPolicy::Exponential { max_duration_ms: 16 * params.lockout_duration_ms }),
+    ];
+    for (name, policy) in policies {
+        let mut simulation = Simulation::new(params.clone(), miners.clone(), seed);
+        simulation.validator_mut().set_lockout_policy(policy);
+        let report = simulation.run(blocks, u128::MAX);
+
+        println!("--- {} ---", name);
+        println!("{}", report.summary());
+    }
+}
@@ -0,0 +1,92 @@
+/*
+ * C bindings for the Hourcoin core types.
+ *
+ * Build the library with the `ffi` feature, for example:
+ *
+ *   cargo rustc --release --lib --no-default-features --features ffi --crate-type cdylib
+ *
+ * Conventions:
+ *
+ * - Functions return HOURCOIN_OK or a negative status. After a failure,
+ *   hourcoin_last_error() describes it until the thread's next call.
+ * - Hashes and keys are 32 raw bytes. Headers are passed in their canonical
+ *   encoding, whose last 8 bytes are the big-endian nonce.
+ * - Transactions and PSBTs are the JSON the validator and wallets exchange.
+ *   Strings returned through `char **` belong to the caller, who frees them
+ *   with hourcoin_string_free().
+ * - Timestamps are TAI milliseconds.
+ */
+
+#ifndef HOURCOIN_H
+#define HOURCOIN_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define HOURCOIN_OK 0
+/* A null pointer, invalid UTF-8, or malformed JSON or header bytes */
+#define HOURCOIN_ERR_INVALID_ARGUMENT (-1)
+/* Well-formed input that fails a consensus check */
+#define HOURCOIN_ERR_REJECTED (-2)
+/* An output buffer too small for the result */
+#define HOURCOIN_ERR_BUFFER_TOO_SMALL (-3)
+/* A bug in the library; the call had no effect */
+#define HOURCOIN_ERR_INTERNAL (-4)
+
+#define HOURCOIN_HASH_LEN 32
+/* Longest canonical header encoding (with UTXO and signed round commitments) */
+#define HOURCOIN_MAX_HEADER_LEN 228
+
+/* The message describing the calling thread's last failure, or NULL */
+const char *hourcoin_last_error(void);
+
+/* Free a string returned by this library */
+void hourcoin_string_free(char *value);
+
+/* Hash a canonically encoded block header */
+int32_t hourcoin_block_hash(const uint8_t *header, size_t header_len, uint8_t out_hash[HOURCOIN_HASH_LEN]);
+
+/* Canonically encode a header given as BlockHeaderData JSON */
+int32_t hourcoin_header_encode(const char *header_json, uint8_t *out, size_t out_capacity, size_t *out_len);
+
+/*
+ * Verify a header on `network` ("mainnet", "testnet" or "regtest"). Pass a
+ * NULL `parent` for a genesis header; with a parent, the header must extend
+ * it, be later, and (with `check_tonce`) pass its tonce.
+ */
+int32_t hourcoin_verify_header(const uint8_t *header, size_t header_len,
+                               const uint8_t *parent, size_t parent_len,
+                               const char *network, bool check_tonce);
+
+/* The tonce a block must pass after the block `prev_hash` mined at `prev_timestamp` */
+int32_t hourcoin_tonce(const uint8_t prev_hash[HOURCOIN_HASH_LEN], uint64_t prev_timestamp,
+                       const char *network, uint8_t *out_tonce);
+
+/* Whether `timestamp` passes `tonce` */
+bool hourcoin_passes_tonce(uint8_t tonce, uint64_t timestamp);
+
+/* Hash a transaction given as JSON */
+int32_t hourcoin_tx_hash(const char *tx_json, uint8_t out_hash[HOURCOIN_HASH_LEN]);
+
+/* The public key of a secret key */
+int32_t hourcoin_public_key(const uint8_t secret_key[32], uint8_t out_public_key[32]);
+
+/* The address paying to `public_key`; free it with hourcoin_string_free() */
+int32_t hourcoin_address(const uint8_t public_key[32], char **out_address);
+
+/* Sign every input of a PSBT that `secret_key` can authorize */
+int32_t hourcoin_tx_sign(const char *psbt_json, const uint8_t secret_key[32], char **out_psbt_json);
+
+/* Turn a fully signed PSBT into a transaction ready to submit */
+int32_t hourcoin_tx_finalize(const char *psbt_json, char **out_tx_json);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* HOURCOIN_H */
@@ -0,0 +1,362 @@
+//! How fairly rounds were run, from the decision log
+//!
+//! The decision log records every judged block with the round it was judged
+//! in, which is enough to check the validator after the fact:
+//!
+//! - whether the tonces it drew look like they came from its randomness, or
+//!   lean towards values that favour someone
+//! - how wins are spread across the miners that took part
+//! - how long into a round the winning block tends to arrive
+//! - how evenly block rewards ended up shared (the Gini coefficient: 0 when
+//!   every miner earned the same, towards 1 when one miner earned it all)
+//!
+//! A `FairnessReport` serializes to JSON, and `to_csv` flattens it into
+//! `metric,key,value` rows for spreadsheets.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+use serde::Serialize;
+use crate::canonical::CanonicalDeserialize;
+use crate::decision_log::{DecisionLog, DecisionRecord, DecisionSource};
+use crate::tonce::MAX_TONCE_BITS;
+use crate::Block;
+
+/// Width of a time-to-acceptance bucket when none is given
+pub const DEFAULT_BUCKET_MS: u128 = 60_000;
+
+/// The `result` of an accepted block
+const ACCEPTED: &str = "accepted";
+
+/// Tonces drawn across the logged rounds, against what fair draws would give
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TonceDistribution {
+    pub tonce_bits: u8,
+    /// Rounds whose tonce was logged while its challenge was live
+    pub rounds: u64,
+    /// Rounds that drew each tonce
+    pub counts: BTreeMap<u8, u64>,
+    /// Pearson's statistic against the expected draws; large values mean the
+    /// tonces are unlikely to be fair
+    pub chi_squared: f64,
+    pub degrees_of_freedom: u32,
+    /// 1 minus the total variation distance from the expected draws: 1 when
+    /// the tonces fall exactly as expected
+    pub uniformity: f64,
+}
+
+/// One miner's part in the logged rounds
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MinerShare {
+    pub miner_id: String,
+    /// Blocks the miner submitted
+    pub submissions: u64,
+    /// Blocks of the miner's that were accepted
+    pub wins: u64,
+    /// The miner's part of all wins
+    pub win_share: f64,
+    /// Coinbase value of the miner's accepted blocks
+    pub rewards: f64,
+}
+
+/// Accepted blocks that arrived in `[start_ms, end_ms)` after their round started
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HistogramBucket {
+    pub start_ms: u128,
+    pub end_ms: u128,
+    pub count: u64,
+}
+
+/// Fairness measures over a run of logged decisions
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FairnessReport {
+    /// Decisions the report covers
+    pub decisions: u64,
+    /// Distinct rounds among them
+    pub rounds: u64,
+    pub tonces: TonceDistribution,
+    /// Every miner that submitted, most wins first
+    pub miners: Vec<MinerShare>,
+    pub time_to_acceptance: Vec<HistogramBucket>,
+    /// Gini coefficient of rewards across the miners that submitted
+    pub reward_gini: f64,
+}
+
+impl FairnessReport {
+    /// Analyse `records` from a validator drawing `tonce_bits`-bit tonces,
+    /// bucketing acceptance times `bucket_ms` wide
+    ///
+    /// Blocks proposed by peers count towards the rounds and tonces, but not
+    /// towards any miner's wins, since they weren't mined against this validator.
+    pub fn from_records(records: &[DecisionRecord], tonce_bits: u8, bucket_ms: u128) -> Result<Self, String> {
+        if bucket_ms == 0 {
+            return Err("Bucket width must be positive".to_string());
+        }
+        if tonce_bits == 0 || tonce_bits > MAX_TONCE_BITS {
+            return Err(format!("Tonce bits must be between 1 and {}", MAX_TONCE_BITS));
+        }
+
+        let rounds: BTreeSet<u128> = records.iter().map(|record| record.round_start).collect();
+
+        // a round's tonce is the same in every record judged while its challenge was live
+        let mut round_tonces = BTreeMap::new();
+        for record in records.iter().filter(|record| record.challenge_seconds_remaining > 0) {
+            if let Some(tonce) = record.tonce {
+                round_tonces.entry(record.round_start).or_insert(tonce);
+            }
+        }
+        let tonces = TonceDistribution::new(round_tonces.values().copied(), tonce_bits)?;
+
+        let mut miners: HashMap<&str, MinerShare> = HashMap::new();
+        let mut acceptance_times = Vec::new();
+        for record in records.iter().filter(|record| record.source == DecisionSource::Miner) {
+            let share = miners.entry(&record.miner_id).or_insert_with(|| MinerShare {
+                miner_id: record.miner_id.clone(),
+                submissions: 0,
+                wins: 0,
+                win_share: 0.0,
+                rewards: 0.0,
+            });
+            share.submissions += 1;
+            if record.result == ACCEPTED {
+                share.wins += 1;
+                share.rewards += coinbase_value(record)?;
+                acceptance_times.push(record.judged_at.saturating_sub(record.round_start));
+            }
+        }
+
+        let mut miners: Vec<MinerShare> = miners.into_values().collect();
+        let total_wins: u64 = miners.iter().map(|share| share.wins).sum();
+        for share in &mut miners {
+            share.win_share = if total_wins == 0 { 0.0 } else { share.wins as f64 / total_wins as f64 };
+        }
+        miners.sort_by(|a, b| b.wins.cmp(&a.wins).then_with(|| a.miner_id.cmp(&b.miner_id)));
+        let rewards: Vec<f64> = miners.iter().map(|share| share.rewards).collect();
+
+        Ok(FairnessReport {
+            decisions: records.len() as u64,
+            rounds: rounds.len() as u64,
+            tonces,
+            reward_gini: gini(&rewards),
+            miners,
+            time_to_acceptance: histogram(&acceptance_times, bucket_ms),
+        })
+    }
+
+    /// Analyse the decision log at `path` (see `from_records`)
+    pub fn from_log(path: &Path, tonce_bits: u8, bucket_ms: u128) -> Result<Self, String> {
+        Self::from_records(&DecisionLog::read(path)?, tonce_bits, bucket_ms)
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| format!("Failed to encode report: {}", e))
+    }
+
+    /// The report as `metric,key,value` rows, `key` naming the tonce, miner
+    /// or bucket a row is about
+    pub fn to_csv(&self) -> String {
+        let mut rows = vec![
+            ("decisions", String::new(), self.decisions.to_string()),
+            ("rounds", String::new(), self.rounds.to_string()),
+            ("tonce_rounds", String::new(), self.tonces.rounds.to_string()),
+        ];
+        for (tonce, count) in &self.tonces.counts {
+            rows.push(("tonce_count", tonce.to_string(), count.to_string()));
+        }
+        rows.push(("tonce_chi_squared", String::new(), self.tonces.chi_squared.to_string()));
+        rows.push(("tonce_degrees_of_freedom", String::new(), self.tonces.degrees_of_freedom.to_string()));
+        rows.push(("tonce_uniformity", String::new(), self.tonces.uniformity.to_string()));
+        for share in &self.miners {
+            rows.push(("miner_submissions", share.miner_id.clone(), share.submissions.to_string()));
+            rows.push(("miner_wins", share.miner_id.clone(), share.wins.to_string()));
+            rows.push(("miner_win_share", share.miner_id.clone(), share.win_share.to_string()));
+            rows.push(("miner_rewards", share.miner_id.clone(), share.rewards.to_string()));
+        }
+        for bucket in &self.time_to_acceptance {
+            rows.push(("time_to_acceptance_ms", format!("{}-{}", bucket.start_ms, bucket.end_ms), bucket.count.to_string()));
+        }
+        rows.push(("reward_gini", String::new(), self.reward_gini.to_string()));
+
+        let mut csv = String::from("metric,key,value\n");
+        for (metric, key, value) in rows {
+            csv.push_str(&format!("{},{},{}\n", metric, csv_field(&key), value));
+        }
+        csv
+    }
+}
+
+impl TonceDistribution {
+    fn new(tonces: impl Iterator<Item = u8>, tonce_bits: u8) -> Result<Self, String> {
+        let expected = expected_tonces(tonce_bits);
+        let mut counts: BTreeMap<u8, u64> = expected.keys().map(|&tonce| (tonce, 0)).collect();
+        for tonce in tonces {
+            match counts.get_mut(&tonce) {
+                Some(count) => *count += 1,
+                None => return Err(format!("Tonce {} can't be drawn with {} bits", tonce, tonce_bits)),
+            }
+        }
+
+        let rounds: u64 = counts.values().sum();
+        let (mut chi_squared, mut distance) = (0.0, 0.0);
+        if rounds > 0 {
+            for (tonce, probability) in &expected {
+                let observed = counts[tonce] as f64;
+                let expected = probability * rounds as f64;
+                chi_squared += (observed - expected).powi(2) / expected;
+                distance += (observed / rounds as f64 - probability).abs();
+            }
+        }
+
+        Ok(TonceDistribution {
+            tonce_bits,
+            rounds,
+            counts,
+            chi_squared,
+            degrees_of_freedom: expected.len() as u32 - 1,
+            uniformity: 1.0 - distance / 2.0,
+        })
+    }
+}
+
+/// Chance of drawing each tonce from fair randomness
+///
+/// `tonce_from_randomness` masks off `tonce_bits` bits and turns 0 into 1, so
+/// 1 comes up twice as often as any other tonce.
+fn expected_tonces(tonce_bits: u8) -> BTreeMap<u8, f64> {
+    let values = 1u16 << tonce_bits;
+    (1..values)
+        .map(|tonce| (tonce as u8, if tonce == 1 { 2.0 } else { 1.0 } / values as f64))
+        .collect()
+}
+
+/// Total output value of the coinbase in a logged block
+fn coinbase_value(record: &DecisionRecord) -> Result<f64, String> {
+    let bytes = hex::decode(&record.block).map_err(|e| format!("Invalid hex for decision {}: {}", record.seq, e))?;
+    let block = Block::from_canonical_bytes(&bytes).map_err(|e| format!("Invalid block in decision {}: {}", record.seq, e))?;
+    Ok(block.body.transactions.first()
+        .filter(|coinbase| coinbase.inputs.is_empty())
+        .map_or(0.0, |coinbase| coinbase.output_sum()))
+}
+
+/// Gini coefficient of `values`: 0 when they're all equal
+fn gini(values: &[f64]) -> f64 {
+    let total: f64 = values.iter().sum();
+    if values.is_empty() || total <= 0.0 {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let n = sorted.len() as f64;
+    let weighted: f64 = sorted.iter().enumerate().map(|(i, value)| (i + 1) as f64 * value).sum();
+    2.0 * weighted / (n * total) - (n + 1.0) / n
+}
+
+/// Count `times` in consecutive `bucket_ms`-wide buckets from 0 to the latest
+fn histogram(times: &[u128], bucket_ms: u128) -> Vec<HistogramBucket> {
+    let Some(&latest) = times.iter().max() else {
+        return Vec::new();
+    };
+    let mut buckets: Vec<HistogramBucket> = (0..=latest / bucket_ms)
+        .map(|i| HistogramBucket { start_ms: i * bucket_ms, end_ms: (i + 1) * bucket_ms, count: 0 })
+        .collect();
+    for time in times {
+        buckets[(time / bucket_ms) as usize].count += 1;
+    }
+    buckets
+}
+
+/// Quote a CSV field if it holds a comma, quote or line break
+pub(crate) fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::encode_block;
+    use crate::{BlockHash, Transaction};
+    use crate::transaction::Output;
+
+    fn record(miner_id: &str, round_start: u128, tonce: u8, result: &str, reward: f64) -> DecisionRecord {
+        let coinbase = Transaction {
+            inputs: vec![],
+            outputs: vec![Output { to_addr: miner_id.to_string(), value: reward, timestamp: 0, lock_until: None }],
+            witnesses: vec![],
+        };
+        let block = Block::new(1, round_start, BlockHash::ZERO, vec![coinbase]);
+        DecisionRecord {
+            seq: 0,
+            judged_at: round_start + 90_000,
+            source: DecisionSource::Miner,
+            miner_id: miner_id.to_string(),
+            height: 1,
+            block_hash: block.hash.to_string(),
+            block: encode_block(&block),
+            salt: None,
+            round_start,
+            tonce: Some(tonce),
+            challenge_seconds_remaining: 30,
+            result: result.to_string(),
+            detail: None,
+            attempts_used: 1,
+        }
+    }
+
+    #[test]
+    fn test_fairness_report() {
+        let mut records = vec![
+            record("alice", 0, 1, ACCEPTED, 50.0),
+            record("bob", 0, 1, "rejected_stale_round", 50.0),
+            record("alice", 600_000, 3, ACCEPTED, 50.0),
+            record("alice", 1_200_000, 2, ACCEPTED, 50.0),
+            record("carol", 1_200_000, 2, "rejected_tonce", 50.0),
+        ];
+        records[2].judged_at = 600_000 + 150_000;
+        // a peer's block counts as a round but not as anyone's win
+        records.push(DecisionRecord { source: DecisionSource::Peer, ..record("dave", 1_800_000, 3, ACCEPTED, 50.0) });
+
+        let report = FairnessReport::from_records(&records, 2, 60_000).unwrap();
+        assert_eq!((report.decisions, report.rounds), (6, 4));
+        assert_eq!(report.tonces.counts, BTreeMap::from([(1, 1), (2, 1), (3, 2)]));
+        assert_eq!(report.tonces.degrees_of_freedom, 2);
+        // expected 2, 1, 1 of 4: 0.5 + 0 + 1
+        assert!((report.tonces.chi_squared - 1.5).abs() < 1e-9);
+        assert!((report.tonces.uniformity - 0.75).abs() < 1e-9);
+
+        let ids: Vec<(&str, u64, u64)> = report.miners.iter()
+            .map(|share| (share.miner_id.as_str(), share.submissions, share.wins))
+            .collect();
+        assert_eq!(ids, vec![("alice", 3, 3), ("bob", 1, 0), ("carol", 1, 0)]);
+        assert_eq!((report.miners[0].win_share, report.miners[0].rewards), (1.0, 150.0));
+        // one of three miners took every reward
+        assert!((report.reward_gini - 2.0 / 3.0).abs() < 1e-9);
+
+        let counts: Vec<u64> = report.time_to_acceptance.iter().map(|bucket| bucket.count).collect();
+        assert_eq!(counts, vec![0, 2, 1]);
+        assert_eq!(report.time_to_acceptance[2].start_ms, 120_000);
+
+        let csv = report.to_csv();
+        assert!(csv.starts_with("metric,key,value\n"));
+        assert!(csv.contains("\nminer_wins,alice,3\n"));
+        assert!(csv.contains("\ntime_to_acceptance_ms,60000-120000,2\n"));
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["miners"][0]["miner_id"], "alice");
+    }
+
+    #[test]
+    fn test_even_rewards_and_bad_input() {
+        let records = vec![record("alice", 0, 1, ACCEPTED, 50.0), record("bob", 600_000, 2, ACCEPTED, 50.0)];
+        let report = FairnessReport::from_records(&records, 2, DEFAULT_BUCKET_MS).unwrap();
+        assert_eq!(report.reward_gini, 0.0);
+        assert_eq!(report.miners[1].win_share, 0.5);
+
+        assert!(FairnessReport::from_records(&[], 2, DEFAULT_BUCKET_MS).unwrap().miners.is_empty());
+        assert!(FairnessReport::from_records(&records, 2, 0).is_err());
+        // tonce 3 is out of reach of 1 bit
+        assert!(FairnessReport::from_records(&[record("alice", 0, 3, ACCEPTED, 50.0)], 1, DEFAULT_BUCKET_MS).is_err());
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+    }
+}
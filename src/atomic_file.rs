@@ -0,0 +1,95 @@
+//! Crash-safe file replacement
+//!
+//! State files (miner stats, peer books, snapshots, key files) are replaced
+//! whole. The new contents go to a sibling `.tmp` file, which is synced to
+//! disk before it is renamed over the old one, and the directory is synced
+//! after the rename. A crash or power loss at any point leaves either the
+//! complete old file or the complete new one, never a truncated mix.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// Replace `path` with `contents`
+pub fn atomic_write(path: &Path, contents: &[u8]) -> Result<(), String> {
+    replace(path, contents, false)
+}
+
+/// Replace `path` with `contents`, readable only by its owner where the platform allows
+pub fn atomic_write_private(path: &Path, contents: &[u8]) -> Result<(), String> {
+    replace(path, contents, true)
+}
+
+fn replace(path: &Path, contents: &[u8], private: bool) -> Result<(), String> {
+    let tmp = path.with_extension("tmp");
+    write_synced(&tmp, contents, private).map_err(|e| format!("Failed to write {}: {}", tmp.display(), e))?;
+    std::fs::rename(&tmp, path).map_err(|e| format!("Failed to replace {}: {}", path.display(), e))?;
+    sync_parent(path).map_err(|e| format!("Failed to sync the directory of {}: {}", path.display(), e))
+}
+
+fn write_synced(path: &Path, contents: &[u8], private: bool) -> std::io::Result<()> {
+    let mut file = open_options(private).open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+#[cfg(unix)]
+fn open_options(private: bool) -> OpenOptions {
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    if private {
+        options.mode(0o600);
+    }
+    options
+}
+
+#[cfg(not(unix))]
+fn open_options(_private: bool) -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    options
+}
+
+// The rename is only durable once the directory entry is
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> std::io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+// Directories can't be opened for syncing here; the rename is as durable as the platform makes it
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_atomic_write_replaces_file() {
+        let dir = std::env::temp_dir().join(format!("hourcoin-atomic-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state.json");
+
+        atomic_write(&path, b"old").unwrap();
+        atomic_write(&path, b"new").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        assert!(!path.with_extension("tmp").exists());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let key = dir.join("node.key");
+            atomic_write_private(&key, b"secret").unwrap();
+            assert_eq!(std::fs::metadata(&key).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+//! Hourcoin Block Explorer
+//!
+//! Serves blocks, transactions and addresses as HTML pages, and as JSON
+//! under `/api` (see the `explorer` module for the paths)
+//!
+//! Usage:
+//!   explorer [address] --validator <validator_address>
+//!   explorer [address] --chain <path>
+//!   explorer [address] --snapshot <path>
+//!   explorer [address] --blocks <dir>
+//!
+//! All forms accept `--network <mainnet|testnet|regtest>`, `--log-level <level>` and `--log-json`.
+//! Pages are served on `address` (default: 127.0.0.1:8000). `--validator` asks a
+//! running validator, which only has headers, balances, histories and merkle proofs
+//! to give. `--chain` loads a file written by `Blockchain::export`, and `--snapshot`
+//! one saved by `validator --snapshot`; both are replayed under the network's rules
+//! once at startup and then served in full. `--blocks` reads the block store a
+//! `validator --block-store` writes, a block at a time and as it grows; it has
+//! full blocks and transactions but no address pages. It keeps the last
+//! `--block-cache <n>` blocks (256 by default) and `--header-cache <n>` headers
+//! (16384) it read decoded; `/storage` shows how often they were hit.
+
+use blockchainlib::block_store::{BlockCacheConfig, BlockStore};
+use blockchainlib::explorer::{self, ChainIndex, ExplorerSource};
+use blockchainlib::logging::LogConfig;
+use blockchainlib::{ChainParams, MinerClient, Network};
+use std::env;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::{error, info};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Parse command line arguments
+    let mut args: Vec<String> = env::args().collect();
+    LogConfig::from_args(&mut args).init();
+
+    let mut network = Network::Mainnet;
+    if let Some(network_arg) = take_value(&mut args, "--network") {
+        network = network_arg.parse().unwrap_or_else(|e| {
+            error!(error = %e, "invalid --network");
+            std::process::exit(1);
+        });
+    }
+    let params = ChainParams::for_network(network);
+
+    let validator_address = take_value(&mut args, "--validator");
+    let chain_file = take_value(&mut args, "--chain");
+    let snapshot_file = take_value(&mut args, "--snapshot");
+    let block_store = take_value(&mut args, "--blocks");
+    let mut cache = BlockCacheConfig::default();
+    if let Some(blocks) = take_value(&mut args, "--block-cache") {
+        cache.blocks = parse_capacity("--block-cache", &blocks);
+    }
+    if let Some(headers) = take_value(&mut args, "--header-cache") {
+        cache.headers = parse_capacity("--header-cache", &headers);
+    }
+
+    info!("=== Hourcoin Block Explorer ===");
+
+    let source = match (validator_address, chain_file, snapshot_file, block_store) {
+        (Some(validator_address), None, None, None) => {
+            info!(validator = %validator_address, "Exploring validator");
+            ExplorerSource::Validator(MinerClient::new("explorer".to_string(), validator_address))
+        }
+        (None, Some(path), None, None) => load(ChainIndex::from_chain_file(Path::new(&path), &params), &path),
+        (None, None, Some(path), None) => load(ChainIndex::from_snapshot(Path::new(&path), params), &path),
+        (None, None, None, Some(dir)) => match BlockStore::open_read_only(&dir) {
+            Ok(mut store) => {
+                store.set_cache_config(cache);
+                info!(dir, blocks = store.len(), "Opened block store");
+                ExplorerSource::Store(Mutex::new(store))
+            }
+            Err(e) => {
+                error!(dir, error = %e, "failed to open block store");
+                std::process::exit(1);
+            }
+        },
+        _ => {
+            error!("pass exactly one of --validator <address>, --chain <path>, --snapshot <path> or --blocks <dir>");
+            std::process::exit(1);
+        }
+    };
+
+    let address = if args.len() > 1 {
+        args[1].clone()
+    } else {
+        "127.0.0.1:8000".to_string()
+    };
+
+    if let Err(e) = explorer::serve(address, source).await {
+        error!(error = %e, "explorer failed");
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// The loaded chain, or exit
+fn load(index: Result<ChainIndex, String>, path: &str) -> ExplorerSource {
+    match index {
+        Ok(index) => {
+            info!(path, blocks = index.blockchain().blocks.len(), "Loaded chain");
+            ExplorerSource::Chain(index)
+        }
+        Err(e) => {
+            error!(path, error = %e, "failed to load chain");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// A cache capacity, or exit
+fn parse_capacity(flag: &str, value: &str) -> usize {
+    value.parse().unwrap_or_else(|_| {
+        error!(value, "{} must be a number of entries", flag);
+        std::process::exit(1);
+    })
+}
+
+/// Remove `flag <value>` from the arguments and return the value
+fn take_value(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let pos = args.iter().position(|arg| arg == flag)?;
+    args.remove(pos);
+    if pos < args.len() {
+        Some(args.remove(pos))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,85 @@
+//! Hourcoin Genesis Generator
+//!
+//! Mines a genesis block and prints its definition: the `GenesisConfig`
+//! to put in a network's `ChainParams`, as JSON
+//!
+//! Usage:
+//!   genesis [--network <mainnet|testnet|regtest>] [--timestamp <ms>] [--message <text>]
+//!           [--premine <address>=<value>]...
+//!
+//! The block is mined against the network's difficulty (default: mainnet).
+//! `--timestamp` is in TAI milliseconds (default: now). Each `--premine` adds an
+//! output paid by the genesis coinbase; without any, the coinbase pays nothing.
+//! Only the JSON goes to stdout, so it can be redirected straight into a file.
+
+use blockchainlib::{now, ChainParams, Network};
+use blockchainlib::chain_params::GenesisConfig;
+use std::env;
+
+fn main() {
+    // Parse command line arguments
+    let mut args: Vec<String> = env::args().collect();
+
+    let mut network = Network::Mainnet;
+    if let Some(pos) = args.iter().position(|arg| arg == "--network") {
+        args.remove(pos);
+        if pos < args.len() {
+            network = args.remove(pos).parse().unwrap_or_else(|e| {
+                eprintln!("✗ Invalid --network: {}", e);
+                std::process::exit(1);
+            });
+        }
+    }
+
+    let mut timestamp = now();
+    if let Some(pos) = args.iter().position(|arg| arg == "--timestamp") {
+        args.remove(pos);
+        if pos < args.len() {
+            match args.remove(pos).parse::<u128>() {
+                Ok(ms) => timestamp = ms,
+                Err(e) => {
+                    eprintln!("✗ Invalid --timestamp: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    let mut message = String::new();
+    if let Some(pos) = args.iter().position(|arg| arg == "--message") {
+        args.remove(pos);
+        if pos < args.len() {
+            message = args.remove(pos);
+        }
+    }
+
+    let mut genesis = GenesisConfig::new(timestamp, message);
+    while let Some(pos) = args.iter().position(|arg| arg == "--premine") {
+        args.remove(pos);
+        if pos < args.len() {
+            let premine = args.remove(pos);
+            let parsed = premine.split_once('=')
+                .and_then(|(address, value)| Some((address.to_string(), value.parse::<f64>().ok()?)));
+            match parsed {
+                Some((address, value)) if value > 0.0 => genesis = genesis.with_premine(address, value),
+                _ => {
+                    eprintln!("✗ Invalid --premine '{}', expected <address>=<value>", premine);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    let params = ChainParams::for_network(network);
+    eprintln!("Mining {:?} genesis block at difficulty {}...", network, params.difficulty);
+    genesis.mine(params.difficulty);
+    eprintln!("✓ Mined genesis block {} (nonce {})", hex::encode(genesis.block().hash), genesis.nonce);
+
+    match serde_json::to_string_pretty(&genesis) {
+        Ok(json) => println!("{}", json),
+        Err(e) => {
+            eprintln!("✗ Failed to encode genesis: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Hourcoin Key Generator
+//!
+//! Creates and manages the identity keys used by validators and miners,
+//! keeping each encrypted under a passphrase (see the `keystore` module)
+//!
+//! Usage:
+//!   keygen new <path> [--kind <node|vrf|miner>] [--force]
+//!   keygen show <path>
+//!   keygen rotate <path>
+//!   keygen passwd <path>
+//!   keygen federation <dir> --members <n> --threshold <t> [--force]
+//!
+//! `new` creates a key (a node key by default) and refuses to overwrite an
+//! existing file without `--force`. `show` prints a file's kind, public key and
+//! address without asking for its passphrase, and checks its rotation signature.
+//! `rotate` replaces the key with a new one signed over by the old, keeping the
+//! old file at `<path>.<created_at>.old`. `passwd` changes the passphrase.
+//! Passphrases are prompted for, or read from `HOURCOIN_KEY_PASSPHRASE`.
+//!
+//! `federation` deals a threshold federation key as a trusted dealer: it
+//! writes the public `federation.pub` and one `member-<i>.key` share file per
+//! member into `<dir>`, all under one passphrase. Hand each member its share
+//! (and have them `passwd` it), then delete the share files here.
+//!
+//! Validators load keys with `--node-key-file <path>` and `--vrf-key-file <path>`,
+//! and federation shares with `--federation <dir>/federation.pub --federation-share-file <path>`;
+//! miners are paid at the address `keygen` prints for a miner key.
+
+use blockchainlib::federation::Federation;
+use blockchainlib::keystore::{read_new_passphrase, read_passphrase, KdfParams, KeyFile, KeyKind};
+use std::env;
+use std::path::Path;
+
+fn main() {
+    // Parse command line arguments
+    let mut args: Vec<String> = env::args().collect();
+
+    let force = args.iter().any(|arg| arg == "--force");
+    args.retain(|arg| arg != "--force");
+
+    let mut kind = KeyKind::Node;
+    if let Some(pos) = args.iter().position(|arg| arg == "--kind") {
+        args.remove(pos);
+        if pos < args.len() {
+            kind = args.remove(pos).parse().unwrap_or_else(|e| {
+                eprintln!("✗ {}", e);
+                std::process::exit(1);
+            });
+        }
+    }
+
+    let mut count = |flag: &str| {
+        let pos = args.iter().position(|arg| arg == flag)?;
+        args.remove(pos);
+        (pos < args.len()).then(|| args.remove(pos).parse::<u16>().unwrap_or_else(|e| {
+            eprintln!("✗ Invalid {}: {}", flag, e);
+            std::process::exit(1);
+        }))
+    };
+    let members = count("--members");
+    let threshold = count("--threshold");
+
+    if args.len() < 3 {
+        eprintln!("✗ Usage: keygen <new|show|rotate|passwd|federation> <path> [--kind <node|vrf|miner>] [--force]");
+        std::process::exit(1);
+    }
+    let path = Path::new(&args[2]);
+
+    let result = match args[1].as_str() {
+        "new" => new_key(path, kind, force),
+        "show" => KeyFile::load(path).map(|file| print_key(&file)),
+        "rotate" => rotate_key(path),
+        "passwd" => change_passphrase(path),
+        "federation" => match (members, threshold) {
+            (Some(members), Some(threshold)) => deal_federation(path, members, threshold, force),
+            _ => Err("federation needs --members <n> and --threshold <t>".to_string()),
+        },
+        command => Err(format!("Unknown command '{}'", command)),
+    };
+    if let Err(e) = result {
+        eprintln!("✗ {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn new_key(path: &Path, kind: KeyKind, force: bool) -> Result<(), String> {
+    if path.exists() && !force {
+        return Err(format!("{} already exists; pass --force to overwrite it", path.display()));
+    }
+    let passphrase = read_new_passphrase("New passphrase: ")?;
+    let file = KeyFile::generate(kind, &passphrase, KdfParams::default())?;
+    file.save(path)?;
+    println!("✓ Created {} key {}", kind, path.display());
+    print_key(&file);
+    Ok(())
+}
+
+fn rotate_key(path: &Path) -> Result<(), String> {
+    let old = KeyFile::load(path)?;
+    let passphrase = read_passphrase(&format!("Passphrase for {}: ", path.display()))?;
+    // check the passphrase before asking for a new one
+    old.open(&passphrase)?;
+    let new_passphrase = read_new_passphrase("Passphrase for the new key: ")?;
+    let new = old.rotate(&passphrase, &new_passphrase)?;
+
+    let retired = format!("{}.{}.old", path.display(), old.created_at);
+    old.save(Path::new(&retired))?;
+    new.save(path)?;
+    println!("✓ Rotated {} (the old key is kept in {})", path.display(), retired);
+    print_key(&new);
+    Ok(())
+}
+
+fn change_passphrase(path: &Path) -> Result<(), String> {
+    let file = KeyFile::load(path)?;
+    let passphrase = read_passphrase(&format!("Passphrase for {}: ", path.display()))?;
+    file.open(&passphrase)?;
+    let new_passphrase = read_new_passphrase("New passphrase: ")?;
+    file.change_passphrase(&passphrase, &new_passphrase)?.save(path)?;
+    println!("✓ Changed the passphrase of {}", path.display());
+    Ok(())
+}
+
+fn deal_federation(dir: &Path, members: u16, threshold: u16, force: bool) -> Result<(), String> {
+    let public_path = dir.join("federation.pub");
+    if public_path.exists() && !force {
+        return Err(format!("{} already exists; pass --force to overwrite it", public_path.display()));
+    }
+    let (federation, shares) = Federation::trusted_dealer_keygen(members, threshold)?;
+    let passphrase = read_new_passphrase("Passphrase for the share files: ")?;
+
+    std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    for share in &shares {
+        let path = dir.join(format!("member-{}.key", share.member_id()));
+        KeyFile::seal(KeyKind::FederationShare, &share.secret_share(), &passphrase, KdfParams::default())?.save(&path)?;
+    }
+    federation.save(&public_path)?;
+    println!("✓ Dealt a {}-of-{} federation into {}", threshold, members, dir.display());
+    println!("  Group key:  {}", hex::encode(federation.group_public_key()));
+    println!("  Hand each member its member-<i>.key privately, then delete them here");
+    Ok(())
+}
+
+fn print_key(file: &KeyFile) {
+    println!("  Kind:       {}", file.kind);
+    println!("  Public key: {}", file.public_key);
+    if let Some(address) = file.address() {
+        println!("  Address:    {}", address);
+    }
+    if let Some(rotation) = &file.rotated_from {
+        let status = match file.verify_rotation() {
+            Ok(()) => "signature verified".to_string(),
+            Err(e) => format!("INVALID: {}", e),
+        };
+        println!("  Replaces:   {} ({})", rotation.previous_public_key, status);
+    }
+}
@@ -1,16 +1,221 @@
 /// Hourcoin Miner Client
 ///
 /// Standalone miner binary that connects to a validator and mines blocks
+///
+/// Usage:
+///   miner [miner_id] [validator_address] [reward_address] [--log-level <level>] [--log-json]
+///         [--vrf-pubkey <hex>] [--receipt-pubkey <hex>] [--threads <n>] [--timeout <secs>] [--retries <n>]
+///         [--transport <tcp|tls|quic|noise>] [--tls-ca <path>] [--compression <list>]
+///         [--noise-key <path>] [--noise-validator-key <hex>]
+///         [--heartbeat <secs>] [--max-missed-heartbeats <n>] [--pool <address>] [--tui]
+///         [--faucet]
+///
+/// With `--vrf-pubkey`, every round must carry a VRF proof from that validator key.
+/// With `--receipt-pubkey`, block receipts are checked against that validator node key.
+/// `--threads` sets how many cores search for a nonce (default: all of them).
+/// `--timeout` bounds the wait for each validator reply (default: 30), and
+/// `--retries` how often a failed connection or read-only request is retried (default: 4).
+/// `--transport tls` or `quic` (in builds with those features) encrypts the connection;
+/// `--tls-ca` names the validator's certificate (default: validator-cert.pem).
+/// `--transport noise` (with `--features noise`) authenticates both ends by static key:
+/// the miner's lives in `--noise-key` (default: <miner_id>.noise.key, generated if missing)
+/// and the validator's must be pinned with `--noise-validator-key`.
+/// `--compression zstd,deflate` asks the validator to compress large frames.
+/// The validator is pinged every `--heartbeat` seconds (default: 5) and given up on
+/// after `--max-missed-heartbeats` unanswered pings in a row (default: 3).
+/// With `--pool`, the miner works for the pool at that address instead of a validator,
+/// submitting shares that are credited to the reward address (see the `pool` binary).
+/// `--tui` (with `--features tui`) shows a dashboard of the round, hashrate, lockout and
+/// recent log lines instead of printing the log; press q to quit.
+/// `--faucet` asks a test network validator's faucet to pay the reward address, then exits.
 
 use blockchainlib::MinerClient;
+use blockchainlib::logging::LogConfig;
+use blockchainlib::network::{Compression, Heartbeat, RetryPolicy, Timeouts};
+use blockchainlib::network::stream::Dialer;
+use blockchainlib::pool::PoolClient;
+use std::sync::Arc;
 use std::env;
+use std::time::Duration;
+use tracing::{error, info, warn};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    println!("=== Hourcoin Miner Client ===\n");
-
     // Parse command line arguments
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let log_config = LogConfig::from_args(&mut args);
+    let tui = args.iter().any(|arg| arg == "--tui");
+    args.retain(|arg| arg != "--tui");
+    let faucet = args.iter().any(|arg| arg == "--faucet");
+    args.retain(|arg| arg != "--faucet");
+    #[cfg(feature = "tui")]
+    let logs = blockchainlib::dashboard::LogBuffer::new();
+    #[cfg(feature = "tui")]
+    {
+        if tui && !args.iter().any(|arg| arg == "--pool") {
+            log_config.init_with_writer(logs.clone());
+        } else {
+            log_config.init();
+        }
+    }
+    #[cfg(not(feature = "tui"))]
+    log_config.init();
+    if tui && (cfg!(not(feature = "tui")) || args.iter().any(|arg| arg == "--pool")) {
+        error!("--tui needs a build with --features tui, and can't be used with --pool");
+        std::process::exit(1);
+    }
+
+    let mut vrf_public_key = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--vrf-pubkey") {
+        args.remove(pos);
+        if pos < args.len() {
+            match hex::decode(args.remove(pos)) {
+                Ok(key) => vrf_public_key = Some(key),
+                Err(e) => {
+                    error!(error = %e, "invalid --vrf-pubkey");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    let mut receipt_public_key = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--receipt-pubkey") {
+        args.remove(pos);
+        if pos < args.len() {
+            match hex::decode(args.remove(pos)) {
+                Ok(key) => receipt_public_key = Some(key),
+                Err(e) => {
+                    error!(error = %e, "invalid --receipt-pubkey");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    let mut mining_threads = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--threads") {
+        args.remove(pos);
+        if pos < args.len() {
+            match args.remove(pos).parse::<usize>() {
+                Ok(threads) => mining_threads = Some(threads),
+                Err(e) => {
+                    error!(error = %e, "invalid --threads");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    let mut compression = Vec::new();
+    if let Some(pos) = args.iter().position(|arg| arg == "--compression") {
+        args.remove(pos);
+        if pos < args.len() {
+            match Compression::parse_list(&args.remove(pos)) {
+                Ok(list) => compression = list,
+                Err(e) => {
+                    error!(error = %e, "invalid --compression");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    let mut reply_timeout = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--timeout") {
+        args.remove(pos);
+        if pos < args.len() {
+            match args.remove(pos).parse::<u64>() {
+                Ok(secs) => reply_timeout = Some(Duration::from_secs(secs.max(1))),
+                Err(e) => {
+                    error!(error = %e, "invalid --timeout");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    let mut retries = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--retries") {
+        args.remove(pos);
+        if pos < args.len() {
+            match args.remove(pos).parse::<u32>() {
+                Ok(n) => retries = Some(n),
+                Err(e) => {
+                    error!(error = %e, "invalid --retries");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    let mut heartbeat = Heartbeat::default();
+    if let Some(pos) = args.iter().position(|arg| arg == "--heartbeat") {
+        args.remove(pos);
+        if pos < args.len() {
+            match args.remove(pos).parse::<u64>() {
+                Ok(secs) => heartbeat.interval = Duration::from_secs(secs.max(1)),
+                Err(e) => {
+                    error!(error = %e, "invalid --heartbeat");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    if let Some(pos) = args.iter().position(|arg| arg == "--max-missed-heartbeats") {
+        args.remove(pos);
+        if pos < args.len() {
+            match args.remove(pos).parse::<u32>() {
+                Ok(n) => heartbeat.max_missed = n.max(1),
+                Err(e) => {
+                    error!(error = %e, "invalid --max-missed-heartbeats");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    let mut transport = "tcp".to_string();
+    if let Some(pos) = args.iter().position(|arg| arg == "--transport") {
+        args.remove(pos);
+        if pos < args.len() {
+            transport = args.remove(pos);
+        }
+    }
+
+    let mut tls_ca = "validator-cert.pem".to_string();
+    if let Some(pos) = args.iter().position(|arg| arg == "--tls-ca") {
+        args.remove(pos);
+        if pos < args.len() {
+            tls_ca = args.remove(pos);
+        }
+    }
+
+    let mut noise_key = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--noise-key") {
+        args.remove(pos);
+        if pos < args.len() {
+            noise_key = Some(args.remove(pos));
+        }
+    }
+
+    let mut noise_validator_key = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--noise-validator-key") {
+        args.remove(pos);
+        if pos < args.len() {
+            noise_validator_key = Some(args.remove(pos));
+        }
+    }
+
+    let mut pool_address = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--pool") {
+        args.remove(pos);
+        if pos < args.len() {
+            pool_address = Some(args.remove(pos));
+        }
+    }
+
+    info!("=== Hourcoin Miner Client ===");
 
     let miner_id = if args.len() > 1 {
         args[1].clone()
@@ -30,31 +235,83 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         miner_id.clone()
     };
 
-    println!("Configuration:");
-    println!("  Miner ID: {}", miner_id);
-    println!("  Validator: {}", validator_address);
-    println!("  Reward Address: {}", reward_address);
-    println!();
+    if let Some(pool_address) = pool_address {
+        info!(worker = %miner_id, pool = %pool_address, payout_address = %reward_address, "mining for a pool");
+        let mut worker = PoolClient::new(miner_id, reward_address, pool_address);
+        if let Some(threads) = mining_threads {
+            worker.set_mining_threads(threads);
+        }
+        if let Some(read) = reply_timeout {
+            worker.set_timeouts(Timeouts { read, ..Timeouts::default() });
+        }
+        worker.start_mining().await?;
+        return Ok(());
+    }
+
+    info!(%miner_id, validator = %validator_address, %reward_address, "configuration");
 
     // Create miner client
-    let client = MinerClient::new(miner_id.clone(), validator_address.clone());
+    let mut client = MinerClient::new(miner_id.clone(), validator_address.clone());
+    if let Some(key) = vrf_public_key {
+        client.set_vrf_public_key(key);
+    }
+    if let Some(key) = receipt_public_key {
+        client.set_receipt_public_key(key);
+    }
+    if let Some(threads) = mining_threads {
+        client.set_mining_threads(threads);
+    }
+    if let Some(read) = reply_timeout {
+        client.set_timeouts(Timeouts { read, ..Timeouts::default() });
+    }
+    if let Some(retries) = retries {
+        client.set_retry_policy(RetryPolicy { max_attempts: retries.saturating_add(1), ..RetryPolicy::default() });
+    }
+    client.set_compression(compression);
+    client.set_heartbeat(heartbeat);
+    if transport != "tcp" {
+        let config = TransportConfig {
+            kind: transport,
+            tls_ca,
+            noise_key: noise_key.unwrap_or_else(|| format!("{}.noise.key", miner_id)),
+            noise_validator_key,
+        };
+        match dialer(&config) {
+            Ok(dialer) => client.set_dialer(dialer),
+            Err(e) => {
+                error!(error = %e, "invalid --transport");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if faucet {
+        match client.request_faucet(&reward_address).await {
+            Ok(tx_hash) => info!(%reward_address, tx = %tx_hash, "faucet payment is waiting for the next block"),
+            Err(e) => {
+                error!(error = %e, "faucet request failed");
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
 
     // Get initial round info
-    println!("Connecting to validator...");
+    info!("connecting to validator");
     match client.get_round_info().await {
         Ok(info) => {
-            println!("✓ Connected to validator");
-            println!("\nCurrent Round Info:");
-            println!("  Tonce: {}", info.tonce.unwrap_or(0));
-            println!("  Challenge time remaining: {} seconds", info.challenge_seconds_remaining);
-            println!("  Difficulty: {}", info.difficulty);
-            println!("  Attempted miners: {}", info.attempted_miners);
-            println!("  Active lockouts: {}", info.active_lockouts);
-            println!();
+            info!(
+                tonce = info.tonce.unwrap_or(0),
+                challenge_seconds_remaining = info.challenge_seconds_remaining,
+                difficulty = %info.difficulty,
+                attempted_miners = info.attempted_miners,
+                active_lockouts = info.active_lockouts,
+                remaining_attempts = ?info.remaining_attempts,
+                "connected to validator"
+            );
         }
         Err(e) => {
-            eprintln!("✗ Failed to connect to validator: {}", e);
-            eprintln!("Make sure the validator is running on {}", validator_address);
+            error!(error = %e, "failed to connect to validator; make sure it is running on {}", validator_address);
             return Ok(());
         }
     }
@@ -63,27 +320,141 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     match client.check_lockout().await {
         Ok((is_locked, seconds_remaining)) => {
             if is_locked {
-                println!("⏳ Currently in lockout period: {} seconds remaining\n", seconds_remaining);
+                info!(seconds_remaining, "currently in lockout period");
             } else {
-                println!("✓ Ready to mine!\n");
+                info!("ready to mine");
             }
         }
         Err(e) => {
-            eprintln!("Warning: Could not check lockout status: {}", e);
+            warn!(error = %e, "could not check lockout status");
+        }
+    }
+
+    #[cfg(feature = "tui")]
+    {
+        if tui {
+            return run_dashboard(client, miner_id, validator_address, reward_address, logs).await;
         }
     }
 
     // Start mining
-    println!("Starting continuous mining...");
-    println!("Press Ctrl+C to stop\n");
+    info!("starting continuous mining, press Ctrl+C to stop");
+
+    client.start_mining(&reward_address).await?;
+
+    Ok(())
+}
+
+/// How often the dashboard's figures are fetched from the validator
+#[cfg(feature = "tui")]
+const DASHBOARD_REFRESH: Duration = Duration::from_secs(2);
 
-    let difficulty = 0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF; // This will be queried from validator
-    client.start_mining(
-        vec![0; 32], // Genesis prev hash
-        0,           // Starting index
-        difficulty,
-        &reward_address,
-    ).await?;
+/// Mine in the background and show the dashboard until the operator quits
+#[cfg(feature = "tui")]
+async fn run_dashboard(
+    client: MinerClient,
+    miner_id: String,
+    validator_address: String,
+    reward_address: String,
+    logs: blockchainlib::dashboard::LogBuffer,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use blockchainlib::dashboard::{self, MinerDashboard};
+    use std::sync::Mutex;
 
+    let client = Arc::new(client);
+    let state = Arc::new(Mutex::new(MinerDashboard::new(&miner_id, &validator_address)));
+
+    let (poller, shown) = (client.clone(), state.clone());
+    tokio::spawn(async move {
+        loop {
+            refresh_dashboard(&poller, &shown).await;
+            tokio::time::sleep(DASHBOARD_REFRESH).await;
+        }
+    });
+
+    // Mine until the operator quits; if mining stops first, keep showing why
+    let mut display = tokio::task::spawn_blocking(move || dashboard::run(|frame| state.lock().unwrap().render(frame, &logs)));
+    tokio::select! {
+        shown = &mut display => return Ok(shown??),
+        mined = client.start_mining(&reward_address) => {
+            if let Err(e) = mined {
+                error!(error = %e, "mining stopped");
+            }
+        }
+    }
+    display.await??;
     Ok(())
 }
+
+/// Fetch the round, tip, lockout and stats for the dashboard
+#[cfg(feature = "tui")]
+async fn refresh_dashboard(client: &MinerClient, dashboard: &std::sync::Mutex<blockchainlib::dashboard::MinerDashboard>) {
+    let figures = async {
+        let round = client.get_round_info().await?;
+        let (_, height, _) = client.get_chain_tip().await?;
+        let (locked, lockout_seconds) = client.check_lockout().await?;
+        let stats = client.get_miner_stats(None).await?;
+        Ok::<_, Box<dyn std::error::Error>>((round, height, locked.then_some(lockout_seconds), stats.blocks_accepted))
+    }.await;
+
+    let mut dashboard = dashboard.lock().unwrap();
+    dashboard.hashrate = client.hashrate();
+    dashboard.validator_time = client.validator_now();
+    match figures {
+        Ok((round, height, lockout_seconds_remaining, blocks_accepted)) => {
+            dashboard.update_round(&round);
+            dashboard.height = Some(height);
+            dashboard.lockout_seconds_remaining = lockout_seconds_remaining;
+            dashboard.blocks_accepted = blocks_accepted;
+            dashboard.error = None;
+        }
+        Err(e) => dashboard.error = Some(e.to_string()),
+    }
+}
+
+/// How to reach the validator: `--transport` and the keys it uses
+// Each field is only read by the transports compiled into this build
+#[allow(dead_code)]
+struct TransportConfig {
+    kind: String,
+    tls_ca: String,
+    noise_key: String,
+    noise_validator_key: Option<String>,
+}
+
+/// Connect over TLS, QUIC or Noise
+fn dialer(config: &TransportConfig) -> Result<Arc<dyn Dialer>, String> {
+    match config.kind.as_str() {
+        #[cfg(feature = "tls")]
+        "tls" | "quic" => tls_dialer(config),
+        #[cfg(feature = "noise")]
+        "noise" => noise_dialer(config),
+        other => Err(format!("Transport {} isn't in this build (see --features tls, quic and noise)", other)),
+    }
+}
+
+/// Connect over TLS or QUIC, trusting the certificate in `--tls-ca`
+#[cfg(feature = "tls")]
+fn tls_dialer(config: &TransportConfig) -> Result<Arc<dyn Dialer>, String> {
+    use blockchainlib::network::tls::{TlsDialer, TlsTrust};
+
+    let trust = TlsTrust::load(std::path::Path::new(&config.tls_ca))?;
+    match config.kind.as_str() {
+        "tls" => Ok(Arc::new(TlsDialer::new(&trust)?)),
+        #[cfg(feature = "quic")]
+        "quic" => Ok(Arc::new(blockchainlib::network::quic::QuicDialer::new(&trust).map_err(|e| e.to_string())?)),
+        other => Err(format!("Transport {} isn't in this build (see --features quic)", other)),
+    }
+}
+
+/// Connect over Noise as the key in `--noise-key`, pinning `--noise-validator-key`
+#[cfg(feature = "noise")]
+fn noise_dialer(config: &TransportConfig) -> Result<Arc<dyn Dialer>, String> {
+    use blockchainlib::network::noise::{parse_public_key, NoiseDialer, NoiseKey};
+
+    let validator_key = config.noise_validator_key.as_deref()
+        .ok_or_else(|| "--transport noise needs --noise-validator-key <hex>".to_string())?;
+    let key = NoiseKey::load_or_generate(std::path::Path::new(&config.noise_key))?;
+    info!(public_key = %hex::encode(key.public_key()), "Noise key (give this to validators that allowlist miners)");
+    Ok(Arc::new(NoiseDialer::new(key, parse_public_key(validator_key)?)))
+}
@@ -0,0 +1,112 @@
+//! Hourcoin Mining Pool
+//!
+//! Standalone pool binary: mines against a validator as one miner and hands
+//! the work out to workers (`miner --pool <address>`), paying block rewards
+//! out in proportion to the shares each worker finds
+//!
+//! Usage:
+//!   pool [address] [validator_address] [--pool-id <id>] [--reward-address <address>]
+//!        [--share-factor <n>] [--ledger <path>] [--timeout <secs>]
+//!        [--log-level <level>] [--log-json]
+//!
+//! Workers connect on `address` (default: 127.0.0.1:3333). The pool mines as
+//! `--pool-id` (default: pool) and is paid at `--reward-address` (default: the pool ID).
+//! Shares are `--share-factor` times easier than blocks (default: 1024).
+//! What each worker is owed is kept in `--ledger` (default: pool-ledger.json).
+//! `--timeout` bounds the wait for each validator reply (default: 30).
+
+use blockchainlib::MinerClient;
+use blockchainlib::logging::LogConfig;
+use blockchainlib::network::Timeouts;
+use blockchainlib::pool::PoolServer;
+use blockchainlib::pool::server::DEFAULT_SHARE_FACTOR;
+use std::env;
+use std::time::Duration;
+use tracing::{error, info};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Parse command line arguments
+    let mut args: Vec<String> = env::args().collect();
+    LogConfig::from_args(&mut args).init();
+
+    let mut pool_id = "pool".to_string();
+    if let Some(pos) = args.iter().position(|arg| arg == "--pool-id") {
+        args.remove(pos);
+        if pos < args.len() {
+            pool_id = args.remove(pos);
+        }
+    }
+
+    let mut reward_address = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--reward-address") {
+        args.remove(pos);
+        if pos < args.len() {
+            reward_address = Some(args.remove(pos));
+        }
+    }
+
+    let mut share_factor = DEFAULT_SHARE_FACTOR;
+    if let Some(pos) = args.iter().position(|arg| arg == "--share-factor") {
+        args.remove(pos);
+        if pos < args.len() {
+            match args.remove(pos).parse::<u128>() {
+                Ok(factor) => share_factor = factor,
+                Err(e) => {
+                    error!(error = %e, "invalid --share-factor");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    let mut ledger_path = "pool-ledger.json".to_string();
+    if let Some(pos) = args.iter().position(|arg| arg == "--ledger") {
+        args.remove(pos);
+        if pos < args.len() {
+            ledger_path = args.remove(pos);
+        }
+    }
+
+    let mut reply_timeout = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--timeout") {
+        args.remove(pos);
+        if pos < args.len() {
+            match args.remove(pos).parse::<u64>() {
+                Ok(secs) => reply_timeout = Some(Duration::from_secs(secs.max(1))),
+                Err(e) => {
+                    error!(error = %e, "invalid --timeout");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    info!("=== Hourcoin Mining Pool ===");
+
+    let address = if args.len() > 1 {
+        args[1].clone()
+    } else {
+        "127.0.0.1:3333".to_string()
+    };
+
+    let validator_address = if args.len() > 2 {
+        args[2].clone()
+    } else {
+        "127.0.0.1:8080".to_string()
+    };
+
+    let reward_address = reward_address.unwrap_or_else(|| pool_id.clone());
+
+    info!(%address, validator = %validator_address, %pool_id, %reward_address, share_factor, %ledger_path, "configuration");
+
+    let mut client = MinerClient::new(pool_id, validator_address);
+    if let Some(read) = reply_timeout {
+        client.set_timeouts(Timeouts { read, ..Timeouts::default() });
+    }
+
+    let mut pool = PoolServer::new(address, client, reward_address);
+    pool.set_share_factor(share_factor);
+    pool.set_ledger_path(ledger_path);
+    pool.start().await
+}
@@ -2,16 +2,357 @@
 ///
 /// Standalone validator binary that runs the Proof of Time consensus
 /// and accepts connections from miner clients
+///
+/// Usage:
+///   validator [address] [difficulty]
+///   validator doctor [address] [difficulty]
+///   validator --soak [address] [difficulty]   (dev builds only)
+///   validator admin <admin_address> <command> [argument]
+///
+/// All forms accept `--network <mainnet|testnet|regtest>`, `--log-level <level>` and `--log-json`.
+/// Pass `--vrf-key <hex secret>` to derive round challenges from a VRF (a fresh
+/// key is generated with `--vrf-key new`). `--commit-reveal` makes miners commit to
+/// their timestamp before submitting during the tonce challenge. Accepted blocks get a
+/// receipt signed with `--node-key <hex secret>` (an ephemeral key if omitted).
+/// `--node-key-file <path>` and `--vrf-key-file <path>` load the keys from files made
+/// by `keygen`, asking for their passphrase (or reading `HOURCOIN_KEY_PASSPHRASE`).
+/// On test networks, `--faucet-key-file <path>` (a `keygen --kind miner` key) pays
+/// `--faucet-amount <n>` (10 by default) from that key's address to clients that ask,
+/// at most once an hour per address and five times an hour per client IP.
+///
+/// To require agreement between several validators, pass
+/// `--validators <hex pubkey,...>` (including our own node key), `--peers <addr,...>`
+/// and optionally `--quorum <n>` (a strict majority by default).
+/// Members of a threshold federation (dealt by `keygen federation`) add
+/// `--federation <federation.pub> --federation-share-file <member key>` to co-sign
+/// a receipt for every accepted block with the other members; it needs `--validators`.
+/// Validators also find each other from `--seeds <addr,...>`, telling peers to reach them at
+/// `--advertise <address>` (the listen address by default), and keep what they learn in
+/// `--peers-file <path>` across restarts.
+///
+/// `--resync <secs>` resyncs the clock in the background and warns when it is
+/// more than `--max-clock-offset <ms>` (1000 by default) from external time;
+/// with `--pause-on-drift` blocks are refused until it recovers.
+/// `--refund-timing-failures` gives a miner its attempt back when a mined block
+/// fails only the timestamp window or tonce check, and `--attempts-per-round <n>`
+/// allows more than one counted submission per round. `--max-lockout <secs>` doubles a
+/// miner's lockout for each consecutive block they win, up to that many seconds.
+/// `--snapshot <path>` saves the chain, lockouts and round every minute and restores
+/// them at startup, after replaying the saved blocks.
+/// `--decision-log <path>` records every judged block, its outcome and the round
+/// it was judged in, and replays what the snapshot missed after a crash.
+/// `--block-store <dir>` appends accepted blocks to memory-mapped flat files that
+/// `explorer --blocks <dir>` can serve.
+/// `--fast-sync <peer>` starts a validator with an empty chain from the peer's signed
+/// UTXO snapshot, checked against the peer's headers, instead of replaying every block.
+/// The snapshot must be signed by `--fast-sync-key <hex>` or a member of `--validators`.
+/// `--webhooks <url,...>` POSTs accepted blocks, miner lockouts and new rounds as JSON,
+/// signed with `--webhook-secret <secret>` (or `HOURCOIN_WEBHOOK_SECRET`) if given.
+/// `validator audit <path> [miner_id|block_hash]` prints the logged decisions,
+/// optionally only those for one miner or block.
+/// `validator analytics <path> [--network <name>] [--bucket-secs <n>] [--csv]` reports
+/// on round fairness from the log: tonce draws, wins and rewards per miner, and
+/// how long winning blocks took, as JSON or CSV.
+/// Miner statistics are kept in `--stats-file <path>` across restarts and served
+/// as JSON over HTTP with `--rest <address>`. Builds with `--features grpc` also serve
+/// the miner API over gRPC with `--grpc <address>` (see `proto/hourcoin.proto`).
+/// Indexers can subscribe to raw blocks and transactions on `--pubsub <address>`.
+/// Requests are rate limited per client IP and per miner ID; `--ip-rate <per sec>`,
+/// `--miner-rate <per sec>` and `--max-connections-per-ip <n>` change the limits.
+/// Messages over `--max-frame-size <bytes>` (1MB by default) close the connection.
+/// `--compression <list>` limits the frame compression miners may negotiate
+/// (`zstd,deflate` by default, `none` to turn it off).
+/// `--transport <tcp|tls|quic|noise>` selects how miners connect (TLS and QUIC need a build with
+/// `--features tls` or `--features quic`). The certificate and key are read from
+/// `--tls-cert <path>` and `--tls-key <path>`; if neither file exists a self-signed pair is
+/// generated and saved there. Miners trust the certificate with `--tls-ca`.
+/// `--transport noise` (with `--features noise`) uses a static key from `--noise-key <path>`
+/// (generated if missing) and admits only the miner keys listed in `--noise-miners <path>`,
+/// or any miner if that isn't given.
+/// Miners that ping are expected to every `--heartbeat <secs>` (5 by default), and are
+/// disconnected after `--max-missed-heartbeats <n>` (3 by default) go by in silence.
+/// Clients idle for `--read-timeout <secs>` (30 by default) are disconnected, and peer
+/// requests that fail are retried up to `--peer-retries <n>` times.
+/// `--admin <address>` accepts operator commands on a separate port, authorized by
+/// `--admin-token <token>` (or `HOURCOIN_ADMIN_TOKEN`; a random token is logged if neither is set).
+/// `validator admin` sends one: `pause`, `resume`, `new-round`, `set-difficulty <hex>`,
+/// `ban <miner_id>`, `unban <miner_id>`, `state`, `snapshot` or `export-rounds <path>`
+/// (the recent rounds, as CSV for a `.csv` path and JSON otherwise).
+/// `--leap-seconds <path>` refreshes the leap second table at startup and daily after,
+/// caching the downloaded list at `path` for when the download fails.
+/// `--tui` (with `--features tui`) shows a dashboard of the chain, connections, lockouts,
+/// round, recent decisions and time sync instead of printing the log; press q to quit.
 
-use blockchainlib::ValidatorServer;
+use blockchainlib::{AttemptPolicy, ChainParams, Difficulty, LockoutPolicy, Network, ValidatorServer};
+use blockchainlib::analytics::FairnessReport;
+use blockchainlib::consensus::ValidatorSet;
+use blockchainlib::decision_log::DecisionLog;
+use blockchainlib::doctor::run_diagnostics;
+use blockchainlib::faucet::{Faucet, FaucetConfig};
+use blockchainlib::federation::{Federation, FederationMember};
+use blockchainlib::keystore::{unlock, KeyKind};
+use blockchainlib::leap_seconds::{LeapSecondUpdater, LEAP_SECONDS_REFRESH_INTERVAL};
+use blockchainlib::logging::LogConfig;
+use blockchainlib::node_key::NodeKey;
+use blockchainlib::network::{Compression, Heartbeat, RetryPolicy, TimeResyncConfig, Timeouts};
+use blockchainlib::network::admin::{send_command, AdminCommand, AdminResponse};
+use blockchainlib::network::rate_limit::RateLimitConfig;
+use blockchainlib::network::stream::Listener;
+use blockchainlib::network::webhook::WebhookConfig;
+use blockchainlib::vrf::VrfKeypair;
 use std::env;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{error, info, warn};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    println!("=== Hourcoin Validator Server ===\n");
-
     // Parse command line arguments
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let log_config = LogConfig::from_args(&mut args);
+
+    let doctor = args.len() > 1 && args[1] == "doctor";
+    if doctor {
+        args.remove(1);
+    }
+
+    let admin_token = take_value(&mut args, "--admin-token").or_else(|| env::var("HOURCOIN_ADMIN_TOKEN").ok());
+    if args.len() > 1 && args[1] == "admin" {
+        run_admin_command(&args[2..], admin_token).await;
+        return Ok(());
+    }
+    if args.len() > 1 && args[1] == "audit" {
+        run_audit(&args[2..]);
+        return Ok(());
+    }
+    if args.len() > 1 && args[1] == "analytics" {
+        run_analytics(args.split_off(2));
+        return Ok(());
+    }
+
+    let soak = args.iter().any(|arg| arg == "--soak");
+    args.retain(|arg| arg != "--soak");
+
+    let commit_reveal = args.iter().any(|arg| arg == "--commit-reveal");
+    args.retain(|arg| arg != "--commit-reveal");
+
+    let pause_on_drift = args.iter().any(|arg| arg == "--pause-on-drift");
+    args.retain(|arg| arg != "--pause-on-drift");
+
+    let refund_timing_failures = args.iter().any(|arg| arg == "--refund-timing-failures");
+    args.retain(|arg| arg != "--refund-timing-failures");
+
+    let tui = args.iter().any(|arg| arg == "--tui");
+    args.retain(|arg| arg != "--tui");
+    if tui && !cfg!(feature = "tui") {
+        eprintln!("✗ --tui needs a build with --features tui");
+        std::process::exit(1);
+    }
+
+    if soak && !cfg!(debug_assertions) {
+        eprintln!("✗ --soak is only available in dev builds");
+        std::process::exit(1);
+    }
+
+    let mut network = Network::Mainnet;
+    if let Some(pos) = args.iter().position(|arg| arg == "--network") {
+        args.remove(pos);
+        if pos < args.len() {
+            network = args.remove(pos).parse().unwrap_or_else(|e| {
+                eprintln!("✗ {}", e);
+                std::process::exit(1);
+            });
+        }
+    }
+    let params = ChainParams::for_network(network);
+
+    let mut vrf_keypair = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--vrf-key") {
+        args.remove(pos);
+        if pos < args.len() {
+            let key = args.remove(pos);
+            let keypair = if key == "new" {
+                Ok(VrfKeypair::generate())
+            } else {
+                hex::decode(&key)
+                    .map_err(|e| format!("Invalid VRF key hex: {}", e))
+                    .and_then(|bytes| VrfKeypair::from_secret_bytes(&bytes))
+            };
+            vrf_keypair = Some(keypair.unwrap_or_else(|e| {
+                eprintln!("✗ {}", e);
+                std::process::exit(1);
+            }));
+        }
+    }
+    if let Some(path) = take_value(&mut args, "--vrf-key-file") {
+        let keypair = unlock(Path::new(&path), KeyKind::Vrf)
+            .and_then(|secret| VrfKeypair::from_secret_bytes(&secret));
+        vrf_keypair = Some(keypair.unwrap_or_else(|e| {
+            eprintln!("✗ {}", e);
+            std::process::exit(1);
+        }));
+    }
+
+    let mut node_key_hex = None;
+    if let Some(pos) = args.iter().position(|arg| arg == "--node-key") {
+        args.remove(pos);
+        if pos < args.len() {
+            node_key_hex = Some(args.remove(pos));
+        }
+    }
+    if let Some(path) = take_value(&mut args, "--node-key-file") {
+        match unlock(Path::new(&path), KeyKind::Node) {
+            Ok(secret) => node_key_hex = Some(hex::encode(secret)),
+            Err(e) => {
+                eprintln!("✗ {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let federation_path = take_value(&mut args, "--federation");
+    let federation_share_path = take_value(&mut args, "--federation-share-file");
+
+    let faucet_amount = take_value(&mut args, "--faucet-amount").map(|amount| match amount.parse::<f64>() {
+        Ok(amount) if amount > 0.0 && amount.is_finite() => amount,
+        _ => {
+            eprintln!("✗ Invalid faucet amount: {}", amount);
+            std::process::exit(1);
+        }
+    });
+    let faucet = take_value(&mut args, "--faucet-key-file").map(|path| {
+        if network == Network::Mainnet {
+            eprintln!("✗ The faucet is only for test networks; pass --network testnet or regtest");
+            std::process::exit(1);
+        }
+        let key = unlock(Path::new(&path), KeyKind::Miner).and_then(|secret| NodeKey::from_secret_bytes(&secret));
+        let config = FaucetConfig { amount: faucet_amount.unwrap_or(FaucetConfig::default().amount), ..FaucetConfig::default() };
+        Faucet::new(key.unwrap_or_else(|e| {
+            eprintln!("✗ {}", e);
+            std::process::exit(1);
+        }), config)
+    });
+
+    let validators = take_list(&mut args, "--validators");
+    let peers = take_list(&mut args, "--peers");
+    let seeds = take_list(&mut args, "--seeds");
+    let advertise = take_value(&mut args, "--advertise");
+    let peers_file = take_value(&mut args, "--peers-file");
+    let quorum = take_value(&mut args, "--quorum").map(|q| q.parse::<usize>().unwrap_or_else(|e| {
+        eprintln!("✗ Invalid quorum: {}", e);
+        std::process::exit(1);
+    }));
+
+    let resync_secs = take_value(&mut args, "--resync").map(|secs| secs.parse::<u64>().unwrap_or_else(|e| {
+        eprintln!("✗ Invalid resync interval: {}", e);
+        std::process::exit(1);
+    }));
+    let max_clock_offset = take_value(&mut args, "--max-clock-offset").map(|ms| ms.parse::<u128>().unwrap_or_else(|e| {
+        eprintln!("✗ Invalid clock offset: {}", e);
+        std::process::exit(1);
+    }));
+    let time_resync = resync_secs.map(|secs| {
+        let defaults = TimeResyncConfig::default();
+        TimeResyncConfig {
+            interval: Duration::from_secs(secs.max(1)),
+            max_offset_ms: max_clock_offset.unwrap_or(defaults.max_offset_ms),
+            pause_on_drift,
+        }
+    });
+
+    let leap_seconds_cache = take_value(&mut args, "--leap-seconds");
+    let stats_file = take_value(&mut args, "--stats-file");
+    let snapshot_file = take_value(&mut args, "--snapshot");
+    let decision_log = take_value(&mut args, "--decision-log");
+    let block_store = take_value(&mut args, "--block-store");
+    let fast_sync = take_value(&mut args, "--fast-sync");
+    let fast_sync_key = take_value(&mut args, "--fast-sync-key");
+    let webhook_urls = take_list(&mut args, "--webhooks");
+    let webhook_secret = take_value(&mut args, "--webhook-secret").or_else(|| env::var("HOURCOIN_WEBHOOK_SECRET").ok());
+    let rest_address = take_value(&mut args, "--rest");
+    let pubsub_address = take_value(&mut args, "--pubsub");
+    let grpc_address = take_value(&mut args, "--grpc");
+    let admin_address = take_value(&mut args, "--admin");
+
+    let mut rate_limits = RateLimitConfig::default();
+    if let Some(rate) = take_value(&mut args, "--ip-rate") {
+        rate_limits.per_ip.per_second = parse_rate(&rate);
+    }
+    if let Some(rate) = take_value(&mut args, "--miner-rate") {
+        rate_limits.per_miner.per_second = parse_rate(&rate);
+    }
+    if let Some(n) = take_value(&mut args, "--max-connections-per-ip") {
+        rate_limits.max_connections_per_ip = n.parse::<usize>().unwrap_or_else(|e| {
+            eprintln!("✗ Invalid connection limit: {}", e);
+            std::process::exit(1);
+        });
+    }
+
+    let max_frame_len = take_value(&mut args, "--max-frame-size").map(|n| n.parse::<usize>().unwrap_or_else(|e| {
+        eprintln!("✗ Invalid frame size: {}", e);
+        std::process::exit(1);
+    }));
+
+    let compression = take_value(&mut args, "--compression").map(|list| Compression::parse_list(&list).unwrap_or_else(|e| {
+        eprintln!("✗ Invalid compression: {}", e);
+        std::process::exit(1);
+    }));
+
+    let read_timeout = take_value(&mut args, "--read-timeout").map(|secs| secs.parse::<u64>().unwrap_or_else(|e| {
+        eprintln!("✗ Invalid read timeout: {}", e);
+        std::process::exit(1);
+    }));
+    let mut heartbeat = Heartbeat::default();
+    if let Some(secs) = take_value(&mut args, "--heartbeat") {
+        heartbeat.interval = Duration::from_secs(secs.parse::<u64>().unwrap_or_else(|e| {
+            eprintln!("✗ Invalid heartbeat interval: {}", e);
+            std::process::exit(1);
+        }).max(1));
+    }
+    if let Some(n) = take_value(&mut args, "--max-missed-heartbeats") {
+        heartbeat.max_missed = n.parse::<u32>().unwrap_or_else(|e| {
+            eprintln!("✗ Invalid missed heartbeat limit: {}", e);
+            std::process::exit(1);
+        }).max(1);
+    }
+    let peer_retries = take_value(&mut args, "--peer-retries").map(|n| n.parse::<u32>().unwrap_or_else(|e| {
+        eprintln!("✗ Invalid peer retries: {}", e);
+        std::process::exit(1);
+    }));
+
+    let transport = TransportConfig {
+        kind: take_value(&mut args, "--transport").unwrap_or_else(|| "tcp".to_string()),
+        #[cfg(feature = "tls")]
+        tls_cert: take_value(&mut args, "--tls-cert").unwrap_or_else(|| "validator-cert.pem".to_string()),
+        #[cfg(feature = "tls")]
+        tls_key: take_value(&mut args, "--tls-key").unwrap_or_else(|| "validator-key.pem".to_string()),
+        #[cfg(feature = "noise")]
+        noise_key: take_value(&mut args, "--noise-key").unwrap_or_else(|| "validator.noise.key".to_string()),
+        #[cfg(feature = "noise")]
+        noise_miners: take_value(&mut args, "--noise-miners"),
+    };
+    // Flags for transports left out of this build are still consumed, so they aren't read as the address
+    #[cfg(not(feature = "tls"))]
+    for flag in ["--tls-cert", "--tls-key"] {
+        take_value(&mut args, flag);
+    }
+    #[cfg(not(feature = "noise"))]
+    for flag in ["--noise-key", "--noise-miners"] {
+        take_value(&mut args, flag);
+    }
+
+    let attempts_per_round = take_value(&mut args, "--attempts-per-round").map_or(1, |n| n.parse::<u32>().unwrap_or_else(|e| {
+        eprintln!("✗ Invalid attempts per round: {}", e);
+        std::process::exit(1);
+    }));
+    let attempt_policy = AttemptPolicy { attempts_per_round: attempts_per_round.max(1), refund_timing_failures };
+    let lockout_policy = take_value(&mut args, "--max-lockout").map_or(LockoutPolicy::Fixed, |secs| match secs.parse::<u64>() {
+        Ok(secs) => LockoutPolicy::Exponential { max_duration_ms: u128::from(secs) * 1000 },
+        Err(e) => {
+            eprintln!("✗ Invalid max lockout: {}", e);
+            std::process::exit(1);
+        }
+    });
 
     let address = if args.len() > 1 {
         args[1].clone()
@@ -20,23 +361,462 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     let difficulty = if args.len() > 2 {
-        u128::from_str_radix(&args[2].trim_start_matches("0x"), 16)
-            .unwrap_or(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF)
+        args[2].parse::<Difficulty>()
+            .unwrap_or(params.difficulty)
     } else {
-        0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF
+        params.difficulty
+    };
+    let params = params.with_difficulty(difficulty);
+
+    if doctor {
+        println!("=== Hourcoin Validator Doctor ===\n");
+        println!("Checking validator for {} (difficulty {})...\n", address, difficulty);
+
+        let report = run_diagnostics(&address, &params, node_key_hex.as_deref(), block_store.as_deref().map(std::path::Path::new)).await;
+        println!("{}\n", report);
+
+        if !report.passed() {
+            eprintln!("✗ Validator is not ready to accept miners");
+            std::process::exit(1);
+        }
+
+        println!("✓ Validator is ready to accept miners");
+        return Ok(());
+    }
+
+    let node_key = match &node_key_hex {
+        Some(key) => hex::decode(key)
+            .map_err(|e| format!("Invalid node key hex: {}", e))
+            .and_then(|bytes| NodeKey::from_secret_bytes(&bytes))
+            .unwrap_or_else(|e| {
+                eprintln!("✗ {}", e);
+                std::process::exit(1);
+            }),
+        None => NodeKey::generate(),
+    };
+
+    let validator_set = if validators.is_empty() {
+        None
+    } else {
+        if node_key_hex.is_none() {
+            eprintln!("✗ --validators requires a persistent --node-key");
+            std::process::exit(1);
+        }
+        let members: Result<Vec<Vec<u8>>, String> = validators.iter()
+            .map(|pk| hex::decode(pk).map_err(|e| format!("Invalid validator key hex: {}", e)))
+            .collect();
+        let set = members.and_then(|members| match quorum {
+            Some(quorum) => ValidatorSet::new(members, quorum),
+            None => ValidatorSet::with_majority(members),
+        });
+        Some(set.unwrap_or_else(|e| {
+            eprintln!("✗ {}", e);
+            std::process::exit(1);
+        }))
+    };
+
+    let federation = match (federation_path, federation_share_path) {
+        (None, None) => None,
+        (Some(_), Some(_)) if validator_set.is_none() => {
+            eprintln!("✗ --federation requires --validators, so members hold the blocks they co-sign");
+            std::process::exit(1);
+        }
+        (Some(path), Some(share_path)) => {
+            let loaded = Federation::load(Path::new(&path)).and_then(|federation| {
+                let secret = unlock(Path::new(&share_path), KeyKind::FederationShare)?;
+                let member = FederationMember::from_secret_share(&federation, &secret)?;
+                Ok((federation, member))
+            });
+            Some(loaded.unwrap_or_else(|e| {
+                eprintln!("✗ {}", e);
+                std::process::exit(1);
+            }))
+        }
+        _ => {
+            eprintln!("✗ --federation and --federation-share-file go together");
+            std::process::exit(1);
+        }
     };
 
-    println!("Configuration:");
-    println!("  Address: {}", address);
-    println!("  Difficulty: 0x{:X}", difficulty);
-    println!();
+    #[cfg(feature = "tui")]
+    let logs = blockchainlib::dashboard::LogBuffer::new();
+    #[cfg(feature = "tui")]
+    {
+        if tui {
+            log_config.init_with_writer(logs.clone());
+        } else {
+            log_config.init();
+        }
+    }
+    #[cfg(not(feature = "tui"))]
+    log_config.init();
+
+    info!("=== Hourcoin Validator Server ===");
+    info!(%address, ?network, %difficulty, soak, commit_reveal, ?attempt_policy, ?lockout_policy, "configuration");
+
+    if let Some(cache_path) = leap_seconds_cache {
+        let updater = LeapSecondUpdater::new(cache_path);
+        match updater.update().await {
+            Ok(table) => info!(entries = table.len(), expires = ?table.expires_at(), "leap second table updated"),
+            Err(e) => warn!(error = %e, "leap second update failed; using the built-in table"),
+        }
+        tokio::spawn(updater.run(LEAP_SECONDS_REFRESH_INTERVAL));
+    }
 
     // Create and start the validator server
-    let mut server = ValidatorServer::new(difficulty, address);
+    let mut server = ValidatorServer::new_with_params(params, address.clone());
+    if transport.kind != "tcp" {
+        match bind_listener(&transport, &address).await {
+            Ok(listener) => server.set_listener(listener),
+            Err(e) => {
+                eprintln!("✗ {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    server.set_soak_mode(soak);
+    server.set_commit_reveal(commit_reveal);
+    server.set_attempt_policy(attempt_policy);
+    server.set_lockout_policy(lockout_policy);
+    server.set_rate_limits(rate_limits);
+    if let Some(max_frame_len) = max_frame_len {
+        server.set_max_frame_len(max_frame_len);
+    }
+    if let Some(compression) = compression {
+        server.set_compression(compression);
+    }
+    server.set_heartbeat(heartbeat);
+    if let Some(secs) = read_timeout {
+        server.set_timeouts(Timeouts { read: Duration::from_secs(secs.max(1)), ..Timeouts::default() });
+    }
+    if let Some(retries) = peer_retries {
+        server.set_peer_retry_policy(RetryPolicy { max_attempts: retries.saturating_add(1), ..RetryPolicy::default() });
+    }
+    if let Some(path) = stats_file {
+        server.set_stats_path(path);
+    }
+    if let Some(path) = snapshot_file {
+        server.set_snapshot_path(path);
+    }
+    if let Some(path) = decision_log {
+        server.set_decision_log_path(path);
+    }
+    if let Some(dir) = block_store {
+        server.set_block_store_path(dir);
+    }
+    if let Some(peer) = fast_sync {
+        let trusted_key = fast_sync_key.map(|key| hex::decode(key).unwrap_or_else(|e| {
+            eprintln!("✗ Invalid fast sync key hex: {}", e);
+            std::process::exit(1);
+        }));
+        if trusted_key.is_none() && validator_set.is_none() {
+            eprintln!("✗ --fast-sync requires --fast-sync-key or --validators to check the snapshot's signer");
+            std::process::exit(1);
+        }
+        server.set_fast_sync_peer(peer, trusted_key);
+    }
+    if !webhook_urls.is_empty() {
+        server.set_webhooks(WebhookConfig { urls: webhook_urls, secret: webhook_secret, ..WebhookConfig::default() });
+    }
+    server.set_seeds(seeds);
+    if let Some(address) = advertise {
+        server.set_advertised_address(address);
+    }
+    if let Some(path) = peers_file {
+        server.set_peers_path(path);
+    }
+    if let Some(address) = rest_address {
+        server.set_rest_address(address);
+    }
+    if let Some(address) = pubsub_address {
+        server.set_pubsub_address(address);
+    }
+    if let Some(address) = grpc_address {
+        #[cfg(feature = "grpc")]
+        server.set_grpc_address(address);
+        #[cfg(not(feature = "grpc"))]
+        {
+            eprintln!("✗ --grpc {} needs a build with --features grpc", address);
+            std::process::exit(1);
+        }
+    }
+    if let Some(address) = admin_address {
+        let token = admin_token.unwrap_or_else(|| {
+            let token = hex::encode(rand::random::<[u8; 16]>());
+            warn!(%token, "no --admin-token given; generated one for this run");
+            token
+        });
+        server.set_admin(address, token);
+    }
+    info!(public_key = %hex::encode(node_key.public_key()), ephemeral = node_key_hex.is_none(), "receipt signing key");
+    server.set_node_key(node_key);
+    if let Some(validator_set) = validator_set {
+        server.set_consensus(validator_set, peers);
+    }
+    if let Some((federation, member)) = federation {
+        server.set_federation(federation, member);
+    }
+    if let Some(config) = time_resync {
+        server.set_time_resync(config);
+    }
+    if let Some(keypair) = vrf_keypair {
+        info!(public_key = %hex::encode(keypair.public_key()), "miners can pin this key with --vrf-pubkey");
+        server.set_vrf_keypair(keypair);
+    }
+    if let Some(faucet) = faucet {
+        info!(address = faucet.address(), "fund the faucet by paying this address");
+        server.set_faucet(faucet);
+    }
+
+    info!("starting Proof of Time consensus");
+
+    #[cfg(feature = "tui")]
+    {
+        if tui {
+            return run_dashboard(server, address, logs).await;
+        }
+    }
+
+    if let Err(e) = server.start().await {
+        error!(error = %e, "validator server stopped");
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+/// How often the dashboard's figures are read from the validator
+#[cfg(feature = "tui")]
+const DASHBOARD_REFRESH: Duration = Duration::from_secs(1);
+
+/// Serve miners and show the dashboard until the operator quits
+#[cfg(feature = "tui")]
+async fn run_dashboard(
+    mut server: ValidatorServer,
+    address: String,
+    logs: blockchainlib::dashboard::LogBuffer,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use blockchainlib::dashboard::{self, ValidatorDashboard};
+    use std::sync::{Arc, Mutex};
 
-    println!("Starting Proof of Time consensus...\n");
+    let handle = server.handle();
+    let state = Arc::new(Mutex::new(ValidatorDashboard::new(&address)));
 
-    server.start().await?;
+    let shown = state.clone();
+    tokio::spawn(async move {
+        loop {
+            let connections = handle.open_connections();
+            let shown = Arc::clone(&shown);
+            handle.validator().call(move |validator| shown.lock().unwrap().update(validator, connections)).await;
+            tokio::time::sleep(DASHBOARD_REFRESH).await;
+        }
+    });
 
+    // Serve until the operator quits; if the server stops first, keep showing why
+    let mut display = tokio::task::spawn_blocking(move || dashboard::run(|frame| state.lock().unwrap().render(frame, &logs)));
+    tokio::select! {
+        shown = &mut display => return Ok(shown??),
+        served = server.start() => {
+            if let Err(e) = served {
+                error!(error = %e, "validator server stopped");
+            }
+        }
+    }
+    display.await??;
     Ok(())
 }
+
+/// How miners reach this validator: `--transport` and the key files it uses
+struct TransportConfig {
+    kind: String,
+    #[cfg(feature = "tls")]
+    tls_cert: String,
+    #[cfg(feature = "tls")]
+    tls_key: String,
+    #[cfg(feature = "noise")]
+    noise_key: String,
+    #[cfg(feature = "noise")]
+    noise_miners: Option<String>,
+}
+
+/// Listen for miners over TLS, QUIC or Noise
+async fn bind_listener(config: &TransportConfig, address: &str) -> Result<Box<dyn Listener>, String> {
+    match config.kind.as_str() {
+        #[cfg(feature = "tls")]
+        "tls" | "quic" => bind_tls_listener(config, address).await,
+        #[cfg(feature = "noise")]
+        "noise" => bind_noise_listener(config, address).await,
+        other => Err(format!("Can't listen on {} over {}: not in this build (see --features tls, quic and noise)", address, other)),
+    }
+}
+
+/// Listen over TLS or QUIC, loading or generating the certificate
+#[cfg(feature = "tls")]
+async fn bind_tls_listener(config: &TransportConfig, address: &str) -> Result<Box<dyn Listener>, String> {
+    use blockchainlib::network::stream::host_of;
+    use blockchainlib::network::tls::{TlsIdentity, TlsListener};
+    use std::path::Path;
+
+    let mut names = vec!["localhost".to_string()];
+    let host = host_of(address);
+    if host.parse::<std::net::IpAddr>().map_or(true, |ip| !ip.is_unspecified()) {
+        names.push(host.to_string());
+    }
+    let identity = TlsIdentity::load_or_generate(Path::new(&config.tls_cert), Path::new(&config.tls_key), names)?;
+    info!(certificate = %config.tls_cert, "miners can trust this certificate with --tls-ca");
+
+    let listener: Box<dyn Listener> = match config.kind.as_str() {
+        "tls" => Box::new(TlsListener::bind(address, &identity).await.map_err(|e| e.to_string())?),
+        #[cfg(feature = "quic")]
+        "quic" => Box::new(blockchainlib::network::quic::QuicListener::bind(address, &identity).await.map_err(|e| e.to_string())?),
+        other => return Err(format!("Transport {} isn't in this build (see --features quic)", other)),
+    };
+    Ok(listener)
+}
+
+/// Listen over Noise, loading or generating the static key
+#[cfg(feature = "noise")]
+async fn bind_noise_listener(config: &TransportConfig, address: &str) -> Result<Box<dyn Listener>, String> {
+    use blockchainlib::network::noise::{load_allowlist, NoiseKey, NoiseListener};
+    use std::path::Path;
+
+    let key = NoiseKey::load_or_generate(Path::new(&config.noise_key))?;
+    info!(public_key = %hex::encode(key.public_key()), "miners pin this Noise key with --noise-validator-key");
+    let allowed = match &config.noise_miners {
+        Some(path) => {
+            let allowed = load_allowlist(Path::new(path))?;
+            info!(miners = allowed.len(), "only allowlisted Noise keys may connect");
+            Some(allowed)
+        }
+        None => None,
+    };
+    Ok(Box::new(NoiseListener::bind(address, key, allowed).await.map_err(|e| e.to_string())?))
+}
+
+/// Send one `validator admin` command and print the answer, exiting non-zero on failure
+async fn run_admin_command(args: &[String], token: Option<String>) {
+    let token = token.unwrap_or_else(|| {
+        eprintln!("✗ validator admin needs --admin-token or HOURCOIN_ADMIN_TOKEN");
+        std::process::exit(1);
+    });
+    let argument = || args.get(2).cloned().unwrap_or_else(|| {
+        eprintln!("✗ {} needs an argument", args[1]);
+        std::process::exit(1);
+    });
+    let command = match args.get(1).map(String::as_str) {
+        Some("pause") => AdminCommand::Pause,
+        Some("resume") => AdminCommand::Resume,
+        Some("new-round") => AdminCommand::ForceNewRound,
+        Some("set-difficulty") => AdminCommand::SetDifficulty { difficulty: argument() },
+        Some("ban") => AdminCommand::BanMiner { miner_id: argument() },
+        Some("unban") => AdminCommand::UnbanMiner { miner_id: argument() },
+        Some("state") => AdminCommand::DumpState,
+        Some("snapshot") => AdminCommand::TriggerSnapshot,
+        Some("export-rounds") => AdminCommand::ExportRounds { path: argument() },
+        _ => {
+            eprintln!("✗ Usage: validator admin <admin_address> <pause|resume|new-round|set-difficulty|ban|unban|state|snapshot|export-rounds> [argument]");
+            std::process::exit(1);
+        }
+    };
+
+    match send_command(&args[0], &token, command).await {
+        Ok(AdminResponse::Done { message }) => println!("✓ {}", message),
+        Ok(AdminResponse::State(state)) => match serde_json::to_string_pretty(&state) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("✗ {}", e);
+                std::process::exit(1);
+            }
+        },
+        Ok(AdminResponse::Error { message }) => {
+            eprintln!("✗ {}", message);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("✗ Can't reach the admin endpoint at {}: {}", args[0], e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `validator audit <path> [miner_id|block_hash]`: print logged decisions as JSON lines
+fn run_audit(args: &[String]) {
+    let path = args.first().unwrap_or_else(|| {
+        eprintln!("✗ Usage: validator audit <decision_log> [miner_id|block_hash]");
+        std::process::exit(1);
+    });
+    let records = DecisionLog::read(std::path::Path::new(path)).unwrap_or_else(|e| {
+        eprintln!("✗ {}", e);
+        std::process::exit(1);
+    });
+    let filter = args.get(1);
+    for record in records {
+        if filter.is_some_and(|f| *f != record.miner_id && *f != record.block_hash) {
+            continue;
+        }
+        match serde_json::to_string(&record) {
+            Ok(json) => println!("{}", json),
+            Err(e) => eprintln!("✗ {}", e),
+        }
+    }
+}
+
+fn run_analytics(mut args: Vec<String>) {
+    let csv = args.iter().any(|arg| arg == "--csv");
+    args.retain(|arg| arg != "--csv");
+    let network: Network = take_value(&mut args, "--network").map_or(Ok(Network::Mainnet), |name| name.parse())
+        .unwrap_or_else(|e| {
+            eprintln!("✗ {}", e);
+            std::process::exit(1);
+        });
+    let bucket_secs = take_value(&mut args, "--bucket-secs").map_or(60, |secs| {
+        secs.parse::<u64>().ok().filter(|secs| *secs > 0).unwrap_or_else(|| {
+            eprintln!("✗ Invalid bucket width (seconds): {}", secs);
+            std::process::exit(1);
+        })
+    });
+    let path = args.first().unwrap_or_else(|| {
+        eprintln!("✗ Usage: validator analytics <decision_log> [--network <name>] [--bucket-secs <n>] [--csv]");
+        std::process::exit(1);
+    });
+
+    let tonce_bits = ChainParams::for_network(network).tonce_bits;
+    let report = FairnessReport::from_log(Path::new(path), tonce_bits, u128::from(bucket_secs) * 1000)
+        .and_then(|report| if csv { Ok(report.to_csv()) } else { report.to_json() });
+    match report {
+        Ok(report) => println!("{}", report.trim_end()),
+        Err(e) => {
+            eprintln!("✗ {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Parse a requests-per-second rate, exiting on anything but a positive number
+fn parse_rate(rate: &str) -> f64 {
+    match rate.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => rate,
+        _ => {
+            eprintln!("✗ Invalid rate (requests per second): {}", rate);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Remove `flag <value>` from the arguments and return the value
+fn take_value(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let pos = args.iter().position(|arg| arg == flag)?;
+    args.remove(pos);
+    if pos < args.len() {
+        Some(args.remove(pos))
+    } else {
+        None
+    }
+}
+
+/// Remove `flag <a,b,c>` from the arguments and return the items
+fn take_list(args: &mut Vec<String>, flag: &str) -> Vec<String> {
+    take_value(args, flag)
+        .map(|list| list.split(',').filter(|s| !s.is_empty()).map(str::to_string).collect())
+        .unwrap_or_default()
+}
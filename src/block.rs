@@ -1,17 +1,18 @@
 use std::fmt::{self, Debug, Formatter};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use super::*;
+use crate::canonical::{CanonicalDeserialize, CanonicalReader, CanonicalSerialize};
+use crate::merkle::{merkle_root, MerkleProof};
 
+// a block is a header, which is all that gets hashed, and a body carrying the transactions
 #[derive(Clone)]
 pub struct Block {
-	pub index: u32, // block index
-	pub timestamp: u128, // timestamp of when block is created
+	pub header: BlockHeader,
 	pub hash: BlockHash, // current block hash
-	pub prev_block_hash: BlockHash, //prev block hash
-	pub nonce: u64, // for mining
-	pub transactions: Vec<Transaction>, // will change for transactions
-
-
-
+	pub body: BlockBody,
 }
 
 impl Debug for Block {
@@ -20,62 +21,382 @@ impl Debug for Block {
 		// 	&self.index, &hex::encode(&self.hash), &self.timestamp, &self.transactions.len(), &self.nonce
 		// )
 		write!(f, "[Block #{} - hash: {}, timestamp: {}, nonce: {}]: transactions: {}",
-				&self.index, &hex::encode(&self.hash), &self.timestamp, &self.nonce, &self.transactions.len())
+				&self.header.index, &hex::encode(&self.hash), &self.header.timestamp, &self.header.nonce, &self.body.transactions.len())
 	}
 }
 
 impl Block { 
 	pub fn new(index: u32, timestamp: u128,  prev_block_hash: BlockHash, transactions: Vec<Transaction>,) -> Self {
+		let body = BlockBody { transactions };
 		Block {
-			index, 
-			timestamp, 
-			hash: vec![0; 32], 
-			prev_block_hash, 
-			nonce: 0, 
-			transactions,
+			header: BlockHeader {
+				index, 
+				timestamp, 
+				prev_block_hash, 
+				utxo_commitment: None,
+				round: None,
+				merkle_root: body.merkle_root(),
+				bits: Difficulty::EASIEST, // set by `mine`
+				nonce: Nonce(0), 
+			},
+			hash: BlockHash::ZERO, 
+			body,
 		}
 	}
 
-	pub fn mine (&mut self, difficulty: u128){
+	// a block from its parts; the header must already commit to the body's merkle root
+	pub fn from_parts (header: BlockHeader, body: BlockBody) -> Self {
+		let hash = header.hash();
+		Block { header, hash, body }
+	}
+
+	// recompute the header's merkle root after the transactions change
+	pub fn update_merkle_root (&mut self) {
+		self.header.merkle_root = self.body.merkle_root();
+	}
+
+	// whether the header's merkle root matches the transactions
+	pub fn has_valid_merkle_root (&self) -> bool {
+		self.header.merkle_root == self.body.merkle_root()
+	}
+
+	// the checks a validator makes on a submitted block that a miner can make too, run at `time_sync`'s time
+	// a block failing any of them would only be rejected, and cost the miner its attempt for the round
+	pub fn validate_against (&self, tip: &BlockHash, difficulty: Difficulty, tonce: &TonceChallenge, time_sync: &TimeSync) -> Result<(), String> {
+		if self.header.prev_block_hash != *tip {
+			return Err(format!("Stale tip: block builds on {}, the chain is at {}", self.header.prev_block_hash, tip));
+		}
+		if self.header.bits != difficulty {
+			return Err(format!("Block commits to difficulty {}, the chain requires {}", self.header.bits, difficulty));
+		}
+		let hash = self.hash();
+		if hash != self.hash || !difficulty.is_met_by(&hash) {
+			return Err("Block hash does not meet its difficulty".to_string());
+		}
+		if !self.has_valid_merkle_root() {
+			return Err("Merkle root does not match the transactions".to_string());
+		}
+		if tonce.prev_block_hash != *tip {
+			return Err("Tonce challenge is for an earlier round".to_string());
+		}
+		if !time_sync.validate_timestamp(self.header.timestamp) {
+			return Err(format!("Timestamp {} is outside the window around {}", self.header.timestamp, time_sync.trusted_now()));
+		}
+		if !tonce.clone().validate_timestamp(self.header.timestamp, time_sync.trusted_now()) {
+			return Err(format!("Timestamp {} fails tonce {}", self.header.timestamp, tonce.tonce));
+		}
+		Ok(())
+	}
+
+	// mine the block at `difficulty`, which the header records
+	pub fn mine (&mut self, difficulty: Difficulty){
+		self.header.bits = difficulty;
 		for nonce_attempt in 0..(u64::max_value()){
-			self.nonce = nonce_attempt;
+			self.header.nonce = Nonce(nonce_attempt);
 			let hash = self.hash();
-			if check_blockhash(&hash, difficulty){
+			if difficulty.is_met_by(&hash){
 				self.hash = hash;
 				return;
 			}
 	}
 }
+
+	// like `mine`, but splits the nonce space into one contiguous range per thread
+	// returns the first valid hash any thread finds, or None if the whole nonce space fails
+	pub fn mine_parallel (&mut self, difficulty: Difficulty, num_threads: usize) -> Option<BlockHash> {
+		self.mine_with_progress(difficulty, num_threads, Duration::MAX, |_| {})
+	}
+
+	// `mine_parallel` that calls `on_progress` every `interval`, and once more when mining stops
+	pub fn mine_with_progress<F: FnMut(MiningProgress)> (&mut self, difficulty: Difficulty, num_threads: usize, interval: Duration, on_progress: F) -> Option<BlockHash> {
+		self.header.bits = difficulty;
+		self.mine_cancellable(difficulty, num_threads, interval, &AtomicBool::new(false), on_progress)
+	}
+
+	// `mine_with_progress` that gives up, returning None, once `cancel` is set
+	// searches for a hash meeting `target` but leaves the header's bits alone, so a pool
+	// worker can look for shares easier than the difficulty the block commits to
+	pub fn mine_cancellable<F: FnMut(MiningProgress)> (&mut self, target: Difficulty, num_threads: usize, interval: Duration, cancel: &AtomicBool, mut on_progress: F) -> Option<BlockHash> {
+		let num_threads = num_threads.max(1) as u64;
+		let range_len = u64::MAX / num_threads;
+		let found = AtomicBool::new(false);
+		let hashes = AtomicU64::new(0);
+		let winner: Mutex<Option<(u64, BlockHash)>> = Mutex::new(None);
+		let started = Instant::now();
+		let progress = |hashes: &AtomicU64| MiningProgress {
+			hashes: hashes.load(Ordering::Relaxed),
+			elapsed: started.elapsed(),
+		};
+
+		thread::scope(|scope| {
+			let (done_tx, done_rx) = mpsc::channel();
+
+			for t in 0..num_threads {
+				let mut header = self.header.clone();
+				let (found, hashes, winner, done_tx) = (&found, &hashes, &winner, done_tx.clone());
+				let start = t * range_len;
+				let end = if t == num_threads - 1 { u64::MAX } else { start + range_len };
+
+				scope.spawn(move || {
+					let mut unreported = 0; // hashes not yet added to the shared counter
+					for nonce_attempt in start..end {
+						if found.load(Ordering::Relaxed) || cancel.load(Ordering::Relaxed) {
+							break;
+						}
+						header.nonce = Nonce(nonce_attempt);
+						let hash = header.hash();
+						unreported += 1;
+						if unreported == PROGRESS_BATCH {
+							hashes.fetch_add(unreported, Ordering::Relaxed);
+							unreported = 0;
+						}
+						if target.is_met_by(&hash) {
+							// only the first thread to get here reports its nonce
+							if !found.swap(true, Ordering::SeqCst) {
+								*winner.lock().unwrap() = Some((nonce_attempt, hash));
+							}
+							break;
+						}
+					}
+					hashes.fetch_add(unreported, Ordering::Relaxed);
+					let _ = done_tx.send(());
+				});
+			}
+			drop(done_tx);
+
+			// report on this thread until every worker has stopped
+			let mut running = num_threads;
+			while running > 0 {
+				match done_rx.recv_timeout(interval) {
+					Ok(()) => running -= 1,
+					Err(mpsc::RecvTimeoutError::Timeout) => on_progress(progress(&hashes)),
+					Err(mpsc::RecvTimeoutError::Disconnected) => break,
+				}
+			}
+		});
+		on_progress(progress(&hashes));
+
+		let (nonce, hash) = winner.into_inner().unwrap()?;
+		self.header.nonce = Nonce(nonce);
+		self.hash = hash;
+		Some(hash)
+	}
+
+	pub fn transaction_hashes (&self) -> Vec<Txid> {
+		self.body.transaction_hashes()
+	}
+
+	// proof that the transaction at `position` is under this block's merkle root
+	pub fn merkle_proof (&self, position: usize) -> Option<MerkleProof> {
+		MerkleProof::new(&self.transaction_hashes(), position)
+	}
+
+	// the coinbase's extra-nonce, if it has one
+	pub fn extra_nonce (&self) -> Option<u64> {
+		self.body.transactions.first()?.extra_nonce()
+	}
+
+	// moves the coinbase on to the next extra-nonce and restarts the nonce search
+	// returns false if there's no coinbase, or its extra-nonce has run out too
+	pub fn roll_extra_nonce (&mut self) -> bool {
+		let next = match self.extra_nonce() {
+			None => 0,
+			Some(extra_nonce) => match extra_nonce.checked_add(1) {
+				Some(next) => next,
+				None => return false,
+			},
+		};
+		match self.body.transactions.first_mut().filter(|coinbase| coinbase.inputs.is_empty()) {
+			Some(coinbase) => {
+				coinbase.set_extra_nonce(next);
+				self.update_merkle_root();
+				self.header.nonce = Nonce(0);
+				true
+			}
+			None => false,
+		}
+	}
+
+	// like `mine`, but rolls the extra-nonce each time nonces up to `max_nonce` are used up
+	pub fn mine_with_extra_nonce (&mut self, difficulty: Difficulty, max_nonce: u64) -> Option<BlockHash> {
+		self.header.bits = difficulty;
+		loop {
+			for nonce_attempt in 0..=max_nonce {
+				self.header.nonce = Nonce(nonce_attempt);
+				let hash = self.hash();
+				if difficulty.is_met_by(&hash) {
+					self.hash = hash;
+					return Some(hash);
+				}
+			}
+			if !self.roll_extra_nonce() {
+				return None;
+			}
+		}
+	}
 }
 
+// hashes a mining thread counts locally before adding them to the shared total
+const PROGRESS_BATCH: u64 = 256;
 
+// how far a mining run has got
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MiningProgress {
+	pub hashes: u64, // nonces tried so far, across all threads
+	pub elapsed: Duration,
+}
+
+impl MiningProgress {
+	// hashes per second
+	pub fn hashrate (&self) -> f64 {
+		let seconds = self.elapsed.as_secs_f64();
+		if seconds > 0.0 { self.hashes as f64 / seconds } else { 0.0 }
+	}
+}
 
+// the merkle root isn't encoded, since it follows from the transactions
+impl CanonicalSerialize for Block {
+	fn write_canonical (&self, out: &mut Vec<u8>) {
+		self.header.index.write_canonical(out);
+		self.header.timestamp.write_canonical(out);
+		self.header.prev_block_hash.write_canonical(out);
+		self.header.utxo_commitment.write_canonical(out);
+		self.header.round.write_canonical(out);
+		self.header.bits.write_canonical(out);
+		self.header.nonce.write_canonical(out);
+		self.body.transactions.write_canonical(out);
+	}
+}
+
+impl CanonicalDeserialize for Block {
+	fn read_canonical (reader: &mut CanonicalReader) -> Result<Self, String> {
+		let index = u32::read_canonical(reader)?;
+		let timestamp = u128::read_canonical(reader)?;
+		let prev_block_hash = BlockHash::read_canonical(reader)?;
+		let utxo_commitment = Option::read_canonical(reader)?;
+		let round = Option::read_canonical(reader)?;
+		let bits = Difficulty::read_canonical(reader)?;
+		let nonce = Nonce::read_canonical(reader)?;
+		let body = BlockBody { transactions: Vec::read_canonical(reader)? };
+		let header = BlockHeader { index, timestamp, prev_block_hash, utxo_commitment, round, merkle_root: body.merkle_root(), bits, nonce };
+		Ok(Block::from_parts(header, body)) // the hash isn't encoded either
+	}
+}
+
+// the block's hash is the hash of its header, so a header alone is enough to check proof of work
 impl Hashable for Block {
+	type Hash = BlockHash;
+
+	fn bytes (&self) -> Vec<u8> {
+		self.header.canonical_bytes()
+	}
+}
+
+// the hashed part of a block: everything but the transactions, which it commits to by merkle root
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockHeader {
+	pub index: u32,
+	pub timestamp: u128,
+	pub prev_block_hash: BlockHash,
+	pub utxo_commitment: Option<BlockHash>,
+	pub round: Option<RoundCommitment>, // the validator round the block was mined in, with its tonce
+	pub merkle_root: BlockHash, // root of the tree over the transaction hashes (see `merkle`)
+	pub bits: Difficulty, // the difficulty the block was mined at, in compact form
+	pub nonce: Nonce,
+}
+
+impl CanonicalSerialize for BlockHeader {
+	fn write_canonical (&self, out: &mut Vec<u8>) {
+		self.index.write_canonical(out);
+		self.timestamp.write_canonical(out);
+		self.prev_block_hash.write_canonical(out);
+		self.utxo_commitment.write_canonical(out);
+		self.round.write_canonical(out);
+		self.merkle_root.write_canonical(out);
+		self.bits.write_canonical(out);
+		self.nonce.write_canonical(out);
+	}
+}
+
+impl CanonicalDeserialize for BlockHeader {
+	fn read_canonical (reader: &mut CanonicalReader) -> Result<Self, String> {
+		Ok(BlockHeader {
+			index: u32::read_canonical(reader)?,
+			timestamp: u128::read_canonical(reader)?,
+			prev_block_hash: BlockHash::read_canonical(reader)?,
+			utxo_commitment: Option::read_canonical(reader)?,
+			round: Option::read_canonical(reader)?,
+			merkle_root: BlockHash::read_canonical(reader)?,
+			bits: Difficulty::read_canonical(reader)?,
+			nonce: Nonce::read_canonical(reader)?,
+		})
+	}
+}
+
+impl Hashable for BlockHeader {
+	type Hash = BlockHash;
+
 	fn bytes (&self) -> Vec<u8> {
-		let mut bytes = vec![];
+		self.canonical_bytes()
+	}
+}
 
-		bytes.extend(&u32_bytes(&self.index));
-		bytes.extend(&u128_bytes(&self.timestamp));
-		bytes.extend(&self.prev_block_hash);
-		bytes.extend(&u64_bytes(&self.nonce));
-		bytes.extend(self.transactions.iter()
-									    .flat_map(|transaction| transaction.bytes())
-									    .collect::<Vec<u8>>()
-		);
+// the validator round a block was mined in: when the round started, and the tonce it set.
+// with the parent's hash, round_start names the round; the tonce can be checked against the parent,
+// and the validator's signature shows it announced that tonce for that round (see `receipt::sign_round`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundCommitment {
+	pub round_start: u128, // validator time the round started at (TAI milliseconds)
+	pub tonce: u8,
+	pub signature: Option<Vec<u8>>, // the validator's round signature, if it has a node key
+}
 
+impl CanonicalSerialize for RoundCommitment {
+	fn write_canonical (&self, out: &mut Vec<u8>) {
+		self.round_start.write_canonical(out);
+		self.tonce.write_canonical(out);
+		self.signature.write_canonical(out);
+	}
+}
 
-		bytes
+impl CanonicalDeserialize for RoundCommitment {
+	fn read_canonical (reader: &mut CanonicalReader) -> Result<Self, String> {
+		Ok(RoundCommitment {
+			round_start: u128::read_canonical(reader)?,
+			tonce: u8::read_canonical(reader)?,
+			signature: Option::read_canonical(reader)?,
+		})
 	}
 }
 
-pub fn check_blockhash (hash: &BlockHash, difficulty: u128) -> bool {
-	difficulty > difficulty_bytes_as_u128(&hash)
+// the transactions of a block
+#[derive(Clone, Default)]
+pub struct BlockBody {
+	pub transactions: Vec<Transaction>,
+}
+
+impl BlockBody {
+	pub fn transaction_hashes (&self) -> Vec<Txid> {
+		self.transactions.iter().map(|transaction| transaction.hash()).collect()
+	}
+
+	pub fn merkle_root (&self) -> BlockHash {
+		merkle_root(&self.transaction_hashes())
+	}
+}
+
+// only the pool server calls this outside tests
+#[cfg_attr(not(feature = "node"), allow(dead_code))]
+pub fn check_blockhash (hash: &BlockHash, difficulty: Difficulty) -> bool {
+	difficulty.is_met_by(hash)
 }
 
 #[cfg(test)]
 mod tests {
 	use super::*;
 	use crate::transaction::{Transaction, Output};
+	use crate::time_source::MockTimeSource;
+	use std::sync::Arc;
 
 	#[test]
 	fn test_block_creation() {
@@ -85,47 +406,182 @@ mod tests {
 				to_addr: "Alice".to_owned(),
 				value: 2.0,
 				timestamp: now(),
+				lock_until: None,
 			}],
+			witnesses: vec![],
 		}];
 
-		let block = Block::new(0, now(), vec![0; 32], transactions);
-		assert_eq!(block.index, 0);
-		assert_eq!(block.nonce, 0);
-		assert_eq!(block.prev_block_hash, vec![0; 32]);
-		assert_eq!(block.transactions.len(), 1);
+		let block = Block::new(0, now(), BlockHash::ZERO, transactions);
+		assert_eq!(block.header.index, 0);
+		assert_eq!(block.header.nonce, Nonce(0));
+		assert_eq!(block.header.prev_block_hash, BlockHash::ZERO);
+		assert_eq!(block.body.transactions.len(), 1);
 	}
 
 	#[test]
 	fn test_block_mining() {
-		let difficulty = 0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF;
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
 		let transactions = vec![Transaction {
 			inputs: vec![],
 			outputs: vec![Output {
 				to_addr: "Alice".to_owned(),
 				value: 2.0,
 				timestamp: now(),
+				lock_until: None,
 			}],
+			witnesses: vec![],
 		}];
 
-		let mut block = Block::new(0, now(), vec![0; 32], transactions);
+		let mut block = Block::new(0, now(), BlockHash::ZERO, transactions);
 		block.mine(difficulty);
 
 		assert!(check_blockhash(&block.hash, difficulty));
-		assert!(block.nonce > 0);
+		assert!(block.header.nonce > Nonce(0));
+	}
+
+	#[test]
+	fn test_block_mining_parallel() {
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let transactions = vec![Transaction {
+			inputs: vec![],
+			outputs: vec![Output {
+				to_addr: "Alice".to_owned(),
+				value: 2.0,
+				timestamp: 1000,
+				lock_until: None,
+			}],
+			witnesses: vec![],
+		}];
+
+		let mut block = Block::new(0, 1000, BlockHash::ZERO, transactions);
+		let hash = block.mine_parallel(difficulty, 4).unwrap();
+		assert_eq!(block.hash, hash);
+		assert_eq!(block.hash(), hash);
+		assert!(check_blockhash(&hash, difficulty));
+
+		// One thread searches the same way as `mine`
+		let mut serial = block.clone();
+		serial.mine(difficulty);
+		let mut single = block.clone();
+		single.mine_parallel(difficulty, 1);
+		assert_eq!(single.header.nonce, serial.header.nonce);
+	}
+
+	#[test]
+	fn test_mining_progress() {
+		let mut block = Block::new(0, 1000, BlockHash::ZERO, vec![]);
+
+		let mut reports = vec![];
+		let hash = block.mine_with_progress(Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF), 2, Duration::from_millis(1), |progress| reports.push(progress));
+		assert!(hash.is_some());
+
+		// The final report counts every hash tried, including the winner
+		let last = *reports.last().unwrap();
+		assert!(last.hashes >= 1);
+		assert!(reports.windows(2).all(|pair| pair[0].hashes <= pair[1].hashes));
+
+		let progress = MiningProgress { hashes: 5000, elapsed: Duration::from_millis(2500) };
+		assert_eq!(progress.hashrate(), 2000.0);
+		assert_eq!(MiningProgress { hashes: 5, elapsed: Duration::ZERO }.hashrate(), 0.0);
+	}
+
+	#[test]
+	fn test_extra_nonce() {
+		let coinbase = Transaction {
+			inputs: vec![],
+			outputs: vec![Output { to_addr: "Alice".to_owned(), value: 2.0, timestamp: 1000, lock_until: None }],
+			witnesses: vec![],
+		};
+		let mut block = Block::new(0, 1000, BlockHash::ZERO, vec![coinbase]);
+		assert_eq!(block.extra_nonce(), None);
+
+		// Each extra-nonce gives the same header nonce a different hash
+		let before = block.hash();
+		assert!(block.roll_extra_nonce());
+		assert_eq!(block.extra_nonce(), Some(0));
+		assert_ne!(block.hash(), before);
+
+		// Four nonces per extra-nonce can't reach this difficulty without rolling
+		let difficulty = Difficulty::from_target(0x0000FFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let hash = block.mine_with_extra_nonce(difficulty, 3).unwrap();
+		assert!(check_blockhash(&hash, difficulty));
+		assert!(block.extra_nonce().unwrap() > 0);
+		assert_eq!(block.body.transactions[0].witnesses.len(), 1);
+
+		// A block without a coinbase has nothing to roll
+		let mut empty = Block::new(0, 1000, BlockHash::ZERO, vec![]);
+		assert!(!empty.roll_extra_nonce());
+		assert_eq!(empty.mine_with_extra_nonce(Difficulty::from_target(0), 3), None);
+	}
+
+	#[test]
+	fn test_validate_against() {
+		let difficulty = Difficulty::from_target(0x0FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let tip = BlockHash::digest(b"tip");
+		let now = 1_000_000;
+		let tonce = TonceChallenge::for_round(&tip, 7, 30_000, now);
+		let mut time_sync = TimeSync::new_with_tolerance(500);
+		time_sync.set_time_source(Arc::new(MockTimeSource::new(now)));
+
+		let timestamp = find_valid_timestamp(7, now, 10_000).unwrap();
+		let mut block = Block::new(1, timestamp, tip, vec![]);
+		block.mine(difficulty);
+		assert_eq!(block.validate_against(&tip, difficulty, &tonce, &time_sync), Ok(()));
+
+		// A new block on the chain, or a new round, makes the block stale
+		let next_tip = BlockHash::digest(b"next");
+		assert!(block.validate_against(&next_tip, difficulty, &tonce, &time_sync).unwrap_err().starts_with("Stale tip"));
+		let next_round = TonceChallenge::for_round(&next_tip, 7, 30_000, now);
+		assert!(block.validate_against(&tip, difficulty, &next_round, &time_sync).is_err());
+		assert!(block.validate_against(&tip, Difficulty::from_target(1), &tonce, &time_sync).is_err());
+
+		// Out of the timestamp window, or failing the tonce while the challenge lasts
+		let mut late = time_sync.clone();
+		late.set_time_source(Arc::new(MockTimeSource::new(timestamp + 400_000)));
+		assert!(block.validate_against(&tip, difficulty, &tonce, &late).is_err());
+		let failing = (now..).find(|t| !passes_tonce(7, *t)).unwrap();
+		let mut block = Block::new(1, failing, tip, vec![]);
+		block.mine(difficulty);
+		assert!(block.validate_against(&tip, difficulty, &tonce, &time_sync).is_err());
+		let expired = TonceChallenge::for_round(&tip, 7, 0, now);
+		assert_eq!(block.validate_against(&tip, difficulty, &expired, &time_sync), Ok(()));
+	}
+
+	#[test]
+	fn test_header_and_body() {
+		let coinbase = Transaction {
+			inputs: vec![],
+			outputs: vec![Output { to_addr: "Alice".to_owned(), value: 2.0, timestamp: 1000, lock_until: None }],
+			witnesses: vec![],
+		};
+		let mut block = Block::new(0, 1000, BlockHash::ZERO, vec![coinbase]);
+		assert!(block.has_valid_merkle_root());
+		assert_eq!(block.hash(), block.header.hash());
+
+		// Changing a transaction changes the hash only once the header's root is updated
+		let before = block.hash();
+		block.body.transactions[0].set_extra_nonce(7);
+		assert!(!block.has_valid_merkle_root());
+		assert_eq!(block.hash(), before);
+		block.update_merkle_root();
+		assert_ne!(block.hash(), before);
+
+		let rebuilt = Block::from_parts(block.header.clone(), block.body.clone());
+		assert_eq!(rebuilt.hash, block.hash());
 	}
 
 	#[test]
 	fn test_check_blockhash() {
-		let easy_difficulty = 0x0FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF;
-		let hard_difficulty = 0x000000FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF;
+		let easy_difficulty = Difficulty::from_target(0x0FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let hard_difficulty = Difficulty::from_target(0x000000FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
 
 		// Hash with small value in last 16 bytes should pass easy difficulty
 		// The difficulty_bytes_as_u128 function reads the last 16 bytes (indices 16-31)
-		let easy_hash = vec![255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+		let easy_hash = BlockHash::from_bytes([255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
 		assert!(check_blockhash(&easy_hash, easy_difficulty));
 
 		// Hash with large value in last 16 bytes should fail hard difficulty
-		let hard_hash = vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255];
+		let hard_hash = BlockHash::from_bytes([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255]);
 		assert!(!check_blockhash(&hard_hash, hard_difficulty));
 	}
 
@@ -137,21 +593,23 @@ mod tests {
 				to_addr: "Alice".to_owned(),
 				value: 2.0,
 				timestamp: 1000,
+				lock_until: None,
 			}],
+			witnesses: vec![],
 		}];
 
-		let block1 = Block::new(0, 1000, vec![0; 32], transactions.clone());
+		let block1 = Block::new(0, 1000, BlockHash::ZERO, transactions.clone());
 		let hash1 = block1.hash();
 
-		let block2 = Block::new(0, 1000, vec![0; 32], transactions.clone());
+		let block2 = Block::new(0, 1000, BlockHash::ZERO, transactions.clone());
 		let hash2 = block2.hash();
 
 		// Same block data should produce same hash
 		assert_eq!(hash1, hash2);
 
 		// Different nonce should produce different hash
-		let mut block3 = Block::new(0, 1000, vec![0; 32], transactions.clone());
-		block3.nonce = 1;
+		let mut block3 = Block::new(0, 1000, BlockHash::ZERO, transactions.clone());
+		block3.header.nonce = Nonce(1);
 		let hash3 = block3.hash();
 		assert_ne!(hash1, hash3);
 	}
@@ -0,0 +1,541 @@
+//! Flat-file block storage, read through memory maps
+//!
+//! A `BlockStore` is a directory of data files, `blk00000.dat`,
+//! `blk00001.dat` and so on, that blocks are appended to in height order,
+//! and an index file, `index.dat`, saying where each block is. Every data
+//! file is created at its full size (`DATA_FILE_LEN` unless the store was
+//! created with another) as a sparse file, and mapped into memory once, the
+//! first time a block in it is read. Reading a block decodes it straight out
+//! of the map, so a scan over millions of blocks only keeps the pages it
+//! touches resident, and the operating system can drop them again.
+//!
+//! A block is stored as its header, merkle root included, followed by its
+//! transactions, both canonically encoded (see `canonical`), so
+//! `BlockStore::header` decodes the header without the transactions.
+//!
+//! The index starts with a 32-byte header: the magic bytes `HRBX`, the layout
+//! version, the data file length and the `CANONICAL_VERSION` the blocks are
+//! encoded at (a store from another version is refused, not misread). It
+//! then holds one 16-byte entry per block: the data
+//! file number, the offset in it and the length, all big-endian. The index
+//! is kept in memory too. Entries are written after their block, so a crash
+//! can at worst leave a last entry pointing at a block that never reached
+//! the disk; `BlockStore::open` drops trailing entries that don't decode.
+//!
+//! Recently read blocks and headers are kept decoded in LRU caches (see
+//! `BlockCacheConfig`), so an explorer asked for the same hot blocks over and
+//! over doesn't decode them from the map each time. `BlockStore::cache_stats`
+//! reports their hits and misses.
+//!
+//! One process writes a store. Others, like an explorer following a running
+//! validator, open it with `BlockStore::open_read_only` and call
+//! `BlockStore::refresh` to pick up the blocks appended since.
+
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use memmap2::Mmap;
+use serde::Serialize;
+use crate::canonical::{self, CanonicalDeserialize, CanonicalReader, CanonicalSerialize, CANONICAL_VERSION};
+use crate::lru_cache::{CacheStats, LruCache};
+use crate::{Block, BlockBody, BlockHeader};
+
+/// Size data files are created at, unless a store was created with another
+pub const DATA_FILE_LEN: u64 = 128 * 1024 * 1024;
+
+/// Bumped whenever the layout changes incompatibly
+pub const BLOCK_STORE_VERSION: u32 = 2;
+
+/// Decoded blocks a store keeps by default
+pub const BLOCK_CACHE_CAPACITY: usize = 256;
+
+/// Decoded headers a store keeps by default
+pub const HEADER_CACHE_CAPACITY: usize = 16_384;
+
+const INDEX_MAGIC: &[u8; 4] = b"HRBX";
+const INDEX_FILE: &str = "index.dat";
+
+/// Length of each index entry
+const ENTRY_LEN: usize = 16;
+
+/// Length of the index header, which takes the place of two entries
+const HEADER_LEN: usize = 2 * ENTRY_LEN;
+
+// where a block's bytes are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Location {
+    file: u32,
+    offset: u64,
+    len: u32,
+}
+
+impl Location {
+    fn range(&self) -> Range<usize> {
+        self.offset as usize..self.offset as usize + self.len as usize
+    }
+
+    fn to_bytes(self) -> [u8; ENTRY_LEN] {
+        let mut bytes = [0; ENTRY_LEN];
+        bytes[..4].copy_from_slice(&self.file.to_be_bytes());
+        bytes[4..12].copy_from_slice(&self.offset.to_be_bytes());
+        bytes[12..].copy_from_slice(&self.len.to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Location {
+            file: u32::from_be_bytes(bytes[..4].try_into().unwrap()),
+            offset: u64::from_be_bytes(bytes[4..12].try_into().unwrap()),
+            len: u32::from_be_bytes(bytes[12..].try_into().unwrap()),
+        }
+    }
+}
+
+/// How many recently read blocks and headers a `BlockStore` keeps decoded; 0 turns a cache off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockCacheConfig {
+    pub blocks: usize,
+    pub headers: usize,
+}
+
+impl Default for BlockCacheConfig {
+    fn default() -> Self {
+        BlockCacheConfig { blocks: BLOCK_CACHE_CAPACITY, headers: HEADER_CACHE_CAPACITY }
+    }
+}
+
+/// Hits and misses of a store's caches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BlockCacheStats {
+    pub blocks: CacheStats,
+    pub headers: CacheStats,
+}
+
+/// Blocks in flat files, by height
+#[derive(Debug)]
+pub struct BlockStore {
+    dir: PathBuf,
+    file_len: u64,
+    index: File,
+    // where the block at each height is
+    locations: Vec<Location>,
+    // the data file being appended to
+    data: Option<(u32, File)>,
+    // one per data file, mapped on first read
+    maps: Vec<OnceLock<Mmap>>,
+    read_only: bool,
+    blocks: Mutex<LruCache<u32, Block>>,
+    headers: Mutex<LruCache<u32, BlockHeader>>,
+}
+
+impl BlockStore {
+    /// Open the store in `dir`, creating it if there is none
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, String> {
+        Self::open_with_file_len(dir, DATA_FILE_LEN)
+    }
+
+    /// Open the store in `dir`, creating it with data files of `file_len` bytes if there is none
+    ///
+    /// An existing store keeps the data file length it was created with.
+    pub fn open_with_file_len(dir: impl AsRef<Path>, file_len: u64) -> Result<Self, String> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let index_path = dir.join(INDEX_FILE);
+        let mut index = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&index_path)
+            .map_err(|e| format!("Failed to open {}: {}", index_path.display(), e))?;
+        let mut bytes = vec![];
+        index.read_to_end(&mut bytes).map_err(|e| format!("Failed to read {}: {}", index_path.display(), e))?;
+
+        let file_len = if bytes.is_empty() {
+            let mut header = [0; HEADER_LEN];
+            header[..4].copy_from_slice(INDEX_MAGIC);
+            header[4..8].copy_from_slice(&BLOCK_STORE_VERSION.to_be_bytes());
+            header[8..16].copy_from_slice(&file_len.to_be_bytes());
+            header[16..20].copy_from_slice(&CANONICAL_VERSION.to_be_bytes());
+            index.write_all(&header).map_err(|e| format!("Failed to write {}: {}", index_path.display(), e))?;
+            bytes.extend_from_slice(&header);
+            file_len
+        } else {
+            read_index_header(&bytes, &index_path)?
+        };
+
+        let mut store = BlockStore::new(dir, file_len, index, false);
+        store.add_entries(&bytes[HEADER_LEN..]);
+        // drop entries a crash left pointing at missing or partly written blocks
+        if store.drop_unreadable() || !(bytes.len() - HEADER_LEN).is_multiple_of(ENTRY_LEN) {
+            let len = store.len();
+            store.truncate(len)?;
+        }
+        Ok(store)
+    }
+
+    /// Open the store in `dir` for reading only, leaving it as it is for its writer
+    pub fn open_read_only(dir: impl AsRef<Path>) -> Result<Self, String> {
+        let dir = dir.as_ref().to_path_buf();
+        let index_path = dir.join(INDEX_FILE);
+        let mut index = File::open(&index_path).map_err(|e| format!("Failed to open {}: {}", index_path.display(), e))?;
+        let mut bytes = vec![];
+        index.read_to_end(&mut bytes).map_err(|e| format!("Failed to read {}: {}", index_path.display(), e))?;
+        let file_len = read_index_header(&bytes, &index_path)?;
+
+        let mut store = BlockStore::new(dir, file_len, index, true);
+        store.add_entries(&bytes[HEADER_LEN..]);
+        store.drop_unreadable();
+        Ok(store)
+    }
+
+    /// Pick up blocks the writer has indexed since the store was opened, returning the new length
+    pub fn refresh(&mut self) -> Result<u32, String> {
+        if self.read_only {
+            let mut bytes = vec![];
+            self.index.seek(SeekFrom::Start(entry_offset(self.locations.len())))
+                .and_then(|_| self.index.read_to_end(&mut bytes))
+                .map_err(|e| format!("Failed to read the block index: {}", e))?;
+            self.add_entries(&bytes);
+        }
+        Ok(self.len())
+    }
+
+    fn new(dir: PathBuf, file_len: u64, index: File, read_only: bool) -> Self {
+        let config = BlockCacheConfig::default();
+        BlockStore {
+            dir,
+            file_len,
+            index,
+            locations: vec![],
+            data: None,
+            maps: vec![],
+            read_only,
+            blocks: Mutex::new(LruCache::new(config.blocks)),
+            headers: Mutex::new(LruCache::new(config.headers)),
+        }
+    }
+
+    /// Resize the block and header caches, forgetting what no longer fits
+    pub fn set_cache_config(&mut self, config: BlockCacheConfig) {
+        self.blocks.get_mut().unwrap().set_capacity(config.blocks);
+        self.headers.get_mut().unwrap().set_capacity(config.headers);
+    }
+
+    pub fn cache_stats(&self) -> BlockCacheStats {
+        BlockCacheStats {
+            blocks: self.blocks.lock().unwrap().stats(),
+            headers: self.headers.lock().unwrap().stats(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Number of blocks stored; they are at heights `0..len`
+    pub fn len(&self) -> u32 {
+        self.locations.len() as u32
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    /// Store `block` at the next height
+    ///
+    /// The block's index must be that height. Starts a new data file when
+    /// the block doesn't fit in the current one.
+    pub fn append(&mut self, block: &Block) -> Result<(), String> {
+        if self.read_only {
+            return Err(format!("{} is open read-only", self.dir.display()));
+        }
+        if block.header.index != self.len() {
+            return Err(format!("Block {} can't be stored at height {}", block.header.index, self.len()));
+        }
+        let mut bytes = block.header.canonical_bytes();
+        block.body.transactions.write_canonical(&mut bytes);
+        if bytes.len() as u64 > self.file_len {
+            return Err(format!("Block {} is {} bytes, more than a data file holds", block.header.index, bytes.len()));
+        }
+
+        let location = match self.locations.last() {
+            Some(last) if last.offset + u64::from(last.len) + bytes.len() as u64 <= self.file_len => {
+                Location { file: last.file, offset: last.offset + u64::from(last.len), len: bytes.len() as u32 }
+            }
+            Some(last) => Location { file: last.file + 1, offset: 0, len: bytes.len() as u32 },
+            None => Location { file: 0, offset: 0, len: bytes.len() as u32 },
+        };
+        let data = self.data_file(location.file)?;
+        data.seek(SeekFrom::Start(location.offset))
+            .and_then(|_| data.write_all(&bytes))
+            .map_err(|e| format!("Failed to write block {}: {}", block.header.index, e))?;
+        self.index.seek(SeekFrom::Start(entry_offset(self.locations.len())))
+            .and_then(|_| self.index.write_all(&location.to_bytes()))
+            .map_err(|e| format!("Failed to index block {}: {}", block.header.index, e))?;
+        self.locations.push(location);
+        Ok(())
+    }
+
+    /// The stored bytes of the block at `height`, borrowed from the map
+    pub fn raw_block(&self, height: u32) -> Result<&[u8], String> {
+        let location = self.locations.get(height as usize)
+            .ok_or_else(|| format!("No block at height {}", height))?;
+        let map = self.map(location.file)?;
+        map.get(location.range())
+            .ok_or_else(|| format!("Block {} lies past the end of {}", height, self.data_path(location.file).display()))
+    }
+
+    /// The header of the block at `height`, without decoding its transactions
+    pub fn header(&self, height: u32) -> Result<BlockHeader, String> {
+        if let Some(header) = self.headers.lock().unwrap().get(&height) {
+            return Ok(header.clone());
+        }
+        let header = BlockHeader::read_canonical(&mut CanonicalReader::new(self.raw_block(height)?))?;
+        self.headers.lock().unwrap().insert(height, header.clone());
+        Ok(header)
+    }
+
+    pub fn block(&self, height: u32) -> Result<Block, String> {
+        if let Some(block) = self.blocks.lock().unwrap().get(&height) {
+            return Ok(block.clone());
+        }
+        let block = self.decode_block(height)?;
+        self.blocks.lock().unwrap().insert(height, block.clone());
+        Ok(block)
+    }
+
+    fn decode_block(&self, height: u32) -> Result<Block, String> {
+        let mut reader = CanonicalReader::new(self.raw_block(height)?);
+        let header = BlockHeader::read_canonical(&mut reader)?;
+        let body = BlockBody { transactions: Vec::read_canonical(&mut reader)? };
+        reader.finish()?;
+        if body.merkle_root() != header.merkle_root {
+            return Err(format!("Stored block {} doesn't match its merkle root", height));
+        }
+        Ok(Block::from_parts(header, body))
+    }
+
+    /// Check that every indexed block decodes, sits at its height and links to the one before it
+    pub fn verify(&self) -> Result<(), String> {
+        let mut prev_hash = None;
+        for height in 0..self.len() {
+            let block = self.decode_block(height)?;
+            if block.header.index != height {
+                return Err(format!("Stored block at height {} has index {}", height, block.header.index));
+            }
+            if prev_hash.is_some_and(|hash| hash != block.header.prev_block_hash) {
+                return Err(format!("Stored block {} doesn't link to block {}", height, height - 1));
+            }
+            prev_hash = Some(block.hash);
+        }
+        Ok(())
+    }
+
+    /// Whether `dir` already holds a block store index
+    pub fn exists(dir: impl AsRef<Path>) -> bool {
+        dir.as_ref().join(INDEX_FILE).exists()
+    }
+
+    /// Keep only the first `len` blocks, e.g. before storing the other side of a reorganization
+    pub fn truncate(&mut self, len: u32) -> Result<(), String> {
+        if self.read_only {
+            return Err(format!("{} is open read-only", self.dir.display()));
+        }
+        self.locations.truncate(len as usize);
+        self.blocks.get_mut().unwrap().retain(|height| *height < len);
+        self.headers.get_mut().unwrap().retain(|height| *height < len);
+        let files = self.locations.last().map_or(0, |location| location.file as usize + 1);
+        // unmap before removing, which some platforms refuse for mapped files
+        self.maps.truncate(files);
+        if self.data.as_ref().is_some_and(|(file, _)| *file as usize >= files) {
+            self.data = None;
+        }
+        for file in files as u32.. {
+            let path = self.data_path(file);
+            if !path.exists() {
+                break;
+            }
+            fs::remove_file(&path).map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+        }
+        self.index.set_len(entry_offset(self.locations.len()))
+            .map_err(|e| format!("Failed to truncate the block index: {}", e))
+    }
+
+    /// Flush stored blocks and the index to disk
+    pub fn sync(&self) -> Result<(), String> {
+        if let Some((_, data)) = &self.data {
+            data.sync_data().map_err(|e| format!("Failed to sync block data: {}", e))?;
+        }
+        self.index.sync_data().map_err(|e| format!("Failed to sync the block index: {}", e))
+    }
+
+    // index the entries in `bytes`, ignoring a partly written last one
+    fn add_entries(&mut self, bytes: &[u8]) {
+        self.locations.extend(bytes.chunks_exact(ENTRY_LEN).map(Location::from_bytes));
+        let files = self.locations.last().map_or(0, |location| location.file as usize + 1);
+        while self.maps.len() < files {
+            self.maps.push(OnceLock::new());
+        }
+    }
+
+    // forget trailing entries whose blocks can't be read back, returning whether there were any
+    fn drop_unreadable(&mut self) -> bool {
+        let indexed = self.locations.len();
+        while let Some(height) = self.len().checked_sub(1) {
+            if self.decode_block(height).is_ok_and(|block| block.header.index == height) {
+                break;
+            }
+            self.locations.pop();
+        }
+        self.locations.len() != indexed
+    }
+
+    fn data_path(&self, file: u32) -> PathBuf {
+        self.dir.join(format!("blk{:05}.dat", file))
+    }
+
+    // the data file to append to, created at full length if it's new
+    fn data_file(&mut self, file: u32) -> Result<&mut File, String> {
+        if self.data.as_ref().is_none_or(|(open, _)| *open != file) {
+            let path = self.data_path(file);
+            let data = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)
+                .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+            data.set_len(self.file_len).map_err(|e| format!("Failed to size {}: {}", path.display(), e))?;
+            self.data = Some((file, data));
+            while self.maps.len() <= file as usize {
+                self.maps.push(OnceLock::new());
+            }
+        }
+        Ok(&mut self.data.as_mut().unwrap().1)
+    }
+
+    fn map(&self, file: u32) -> Result<&Mmap, String> {
+        let cell = self.maps.get(file as usize)
+            .ok_or_else(|| format!("No data file {}", file))?;
+        if let Some(map) = cell.get() {
+            return Ok(map);
+        }
+        let path = self.data_path(file);
+        let data = File::open(&path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        // SAFETY: data files are only written by appending past the indexed
+        // blocks, and only shrunk or removed through `&mut self`, after the
+        // map is dropped; a store must not be shared with another writer.
+        let map = unsafe { Mmap::map(&data) }.map_err(|e| format!("Failed to map {}: {}", path.display(), e))?;
+        Ok(cell.get_or_init(|| map))
+    }
+}
+
+// where the index entry for `height` starts
+fn entry_offset(height: usize) -> u64 {
+    (HEADER_LEN + height * ENTRY_LEN) as u64
+}
+
+// the data file length, if `bytes` start with a valid index header
+fn read_index_header(bytes: &[u8], path: &Path) -> Result<u64, String> {
+    if bytes.len() < 8 || &bytes[..4] != INDEX_MAGIC {
+        return Err(format!("{} is not a block store index", path.display()));
+    }
+    let version = u32::from_be_bytes(bytes[4..8].try_into().unwrap());
+    if version != BLOCK_STORE_VERSION {
+        return Err(format!("Unsupported block store version {} (expected {})", version, BLOCK_STORE_VERSION));
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(format!("{} is not a block store index", path.display()));
+    }
+    canonical::check_version(u32::from_be_bytes(bytes[16..20].try_into().unwrap()))
+        .map_err(|e| format!("Blocks in {}: {}", path.display(), e))?;
+    Ok(u64::from_be_bytes(bytes[8..16].try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tx_builder::TransactionBuilder;
+    use crate::{BlockHash, Difficulty};
+
+    fn blocks(count: u32) -> Vec<Block> {
+        let mut prev_block_hash = BlockHash::ZERO;
+        (0..count).map(|index| {
+            let timestamp = 1_700_000_000_000 + u128::from(index) * 1000;
+            let coinbase = TransactionBuilder::new(timestamp).pay_to("alice", 2.0).build_coinbase().unwrap();
+            let mut block = Block::new(index, timestamp, prev_block_hash, vec![coinbase]);
+            block.mine(Difficulty::EASIEST);
+            prev_block_hash = block.hash;
+            block
+        }).collect()
+    }
+
+    #[test]
+    fn test_block_store() {
+        let dir = std::env::temp_dir().join(format!("hourcoin-block-store-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let blocks = blocks(10);
+        let record_len = blocks[0].header.canonical_bytes().len() + blocks[0].body.transactions.canonical_bytes().len();
+
+        // three blocks to a data file
+        let mut store = BlockStore::open_with_file_len(&dir, record_len as u64 * 3 + 10).unwrap();
+        for block in &blocks[..8] {
+            store.append(block).unwrap();
+        }
+        assert_eq!(store.append(&blocks[9]), Err("Block 9 can't be stored at height 8".to_string()));
+        assert_eq!(store.len(), 8);
+        assert!(dir.join("blk00002.dat").exists());
+        assert_eq!(store.block(4).unwrap().canonical_bytes(), blocks[4].canonical_bytes());
+        assert_eq!(store.header(7).unwrap(), blocks[7].header);
+        assert_eq!(store.block(7).unwrap().hash, blocks[7].hash);
+        assert!(store.block(8).is_err());
+
+        // repeated reads come from the caches
+        store.header(7).unwrap();
+        let stats = store.cache_stats();
+        assert_eq!((stats.blocks.hits, stats.blocks.misses, stats.headers.hits, stats.headers.misses), (0, 3, 1, 1));
+        store.set_cache_config(BlockCacheConfig { blocks: 1, headers: 0 });
+        assert_eq!(store.cache_stats().blocks.entries, 1);
+        assert_eq!(store.header(7).unwrap(), blocks[7].header);
+        assert_eq!(store.cache_stats().headers.entries, 0);
+
+        // a reorganization rewrites the tail, and the caches forget it
+        store.truncate(5).unwrap();
+        assert_eq!(store.cache_stats().blocks.entries, 0);
+        assert!(!dir.join("blk00002.dat").exists());
+        for block in &blocks[5..8] {
+            store.append(block).unwrap();
+        }
+        store.sync().unwrap();
+
+        // a reader follows the writer without changing the store
+        let mut reader = BlockStore::open_read_only(&dir).unwrap();
+        assert_eq!(reader.len(), 8);
+        store.append(&blocks[8]).unwrap();
+        assert_eq!(reader.refresh(), Ok(9));
+        assert_eq!(reader.block(8).unwrap().hash, blocks[8].hash);
+        assert!(reader.truncate(0).is_err());
+        drop((store, reader));
+
+        // the file length and index survive reopening
+        let mut store = BlockStore::open(&dir).unwrap();
+        store.append(&blocks[9]).unwrap();
+        assert_eq!(store.len(), 10);
+        assert_eq!(store.block(9).unwrap().canonical_bytes(), blocks[9].canonical_bytes());
+        assert!(dir.join("blk00003.dat").exists());
+        drop(store);
+
+        // an entry for a block that never reached the disk is dropped
+        let mut index = OpenOptions::new().append(true).open(dir.join(INDEX_FILE)).unwrap();
+        index.write_all(&Location { file: 3, offset: record_len as u64, len: record_len as u32 }.to_bytes()).unwrap();
+        index.write_all(&[0; 5]).unwrap();
+        drop(index);
+        let store = BlockStore::open(&dir).unwrap();
+        assert_eq!(store.len(), 10);
+        assert_eq!(fs::metadata(dir.join(INDEX_FILE)).unwrap().len(), entry_offset(10));
+
+        // a store whose blocks are encoded at another canonical version is refused
+        let mut index = fs::read(dir.join(INDEX_FILE)).unwrap();
+        index[16..20].copy_from_slice(&(CANONICAL_VERSION - 1).to_be_bytes());
+        fs::write(dir.join(INDEX_FILE), &index).unwrap();
+        assert!(BlockStore::open_read_only(&dir).unwrap_err().contains("canonical version"));
+
+        fs::write(dir.join(INDEX_FILE), b"not an index").unwrap();
+        assert!(BlockStore::open(&dir).unwrap_err().contains("not a block store index"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,10 +1,60 @@
 use super::*;
-use std::collections::HashSet;
+use crate::canonical::CanonicalSerialize;
+use crate::chain_file::{ChainFile, ChainFormat};
+use crate::chain_params::{ChainParams, GenesisConfig};
+use crate::consensus_engine::{Challenge, ConsensusEngine, Hybrid, ProofOfWork};
+use crate::channels;
+use crate::htlc;
+use crate::signature_cache::SignatureCache;
+use crate::transaction::Output;
+use crate::utxo_snapshot::UtxoSnapshot;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::{mpsc, Arc};
 
 pub struct Blockchain {
 	pub blocks: Vec<Block>,
 	unspent_outputs: HashSet<BlockHash>,
-	difficulty: u128,
+	difficulty: Difficulty,
+	coinbase_value: f64,
+	demurrage_rate: f64, // hourly decay applied to inputs when they are spent
+	address_index: HashMap<Address, AddressEntry>, // per-address UTXOs and history, updated as blocks are applied
+	difficulty_history: Vec<(usize, Difficulty)>, // (first height, difficulty) for every difficulty the chain has used
+	median_time_span: usize, // blocks whose median timestamp a new block must exceed
+	time_tolerance: u128, // how far past validated time a block may be timestamped (ms)
+	checkpoints: BTreeMap<u32, BlockHash>, // height -> hash the block there must have
+	genesis_value: Option<f64>, // what the genesis coinbase pays, when the network fixes its genesis block
+	chain_work: u128, // sum of the work of every block, which decides between competing branches
+	consensus: Box<dyn ConsensusEngine>, // what a block must prove beyond the common rules
+	reorg_listeners: Vec<mpsc::Sender<Reorg>>, // told about every reorganization
+	signature_cache: Arc<SignatureCache>, // signatures already verified, shared with replicas
+	base: Option<Arc<UtxoSnapshot>>, // the UTXO set the chain was synced from; blocks up to its height hold only headers
+}
+
+/// A switch to a heavier branch, as sent to `subscribe_reorgs` listeners
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reorg {
+	pub fork_height: usize, // first height the branches disagree at
+	pub removed: Vec<BlockHash>, // blocks dropped, oldest first
+	pub added: Vec<BlockHash>, // blocks applied in their place, oldest first
+	pub total_work: u128, // the chain's cumulative work afterwards
+}
+
+/// A transaction that sent or received value for an address
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddressTx {
+	pub block_index: u32,
+	pub block_timestamp: u128,
+	pub tx_hash: Txid,
+	pub received: f64, // sum of outputs paying the address
+	pub sent: f64, // sum of inputs spent from the address
+}
+
+#[derive(Default)]
+struct AddressEntry {
+	unspent: HashMap<BlockHash, Output>, // output hash -> output
+	history: Vec<AddressTx>,
 }
 
 #[derive(Debug)]
@@ -18,7 +68,26 @@ pub enum BlockValidationErr {
 	InsufficientInputValue,
 	InvalidCoinbaseTransaction,
 	InvalidDifficultyUpdate,
-	InvalidTransactionTimestamp
+	InvalidTransactionTimestamp,
+	LockedOutput,
+	UnsatisfiedHtlc,
+	InvalidChannelClose,
+	InvalidSignature,
+	InconsistentUtxoSet,
+	TimestampBeforeMedianTimePast,
+	TimestampTooFarAhead,
+	MissingCoinbaseTransaction,
+	MultipleCoinbaseTransactions,
+	DuplicateInput,
+	CheckpointMismatch,
+	BelowCheckpoint,
+	InvalidUtxoCommitment,
+	InvalidMerkleRoot,
+	MismatchedDifficulty,
+	InsufficientWork,
+	FailedTonceChallenge,
+	MissingRoundCommitment,
+	InvalidRoundCommitment
 }
 
 impl Blockchain {
@@ -26,62 +95,332 @@ impl Blockchain {
 		Blockchain {
 			blocks: vec![],
 			unspent_outputs: HashSet::new(),
-			difficulty: 23, // this value must be updated immediatelty after  
+			difficulty: Difficulty::from_target(23), // this value must be updated immediatelty after  
+			coinbase_value: ChainParams::mainnet().coinbase_value,
+			demurrage_rate: ChainParams::mainnet().demurrage_rate_per_hour,
+			address_index: HashMap::new(),
+			difficulty_history: vec![(0, Difficulty::from_target(23))],
+			median_time_span: ChainParams::mainnet().median_time_span,
+			time_tolerance: ChainParams::mainnet().time_tolerance_ms,
+			checkpoints: BTreeMap::new(),
+			genesis_value: None,
+			chain_work: 0,
+			consensus: Box::new(ProofOfWork),
+			reorg_listeners: vec![],
+			signature_cache: Arc::new(SignatureCache::default()),
+			base: None,
 		}
 	}
 
-	pub fn new_with_diff (diff: u128) -> Self {
+	pub fn new_with_diff (diff: Difficulty) -> Self {
 		Blockchain {
 			blocks: vec![],
 			unspent_outputs: HashSet::new(),
 			difficulty: diff, // this value must be updated immediatelty after  
+			coinbase_value: ChainParams::mainnet().coinbase_value,
+			demurrage_rate: ChainParams::mainnet().demurrage_rate_per_hour,
+			address_index: HashMap::new(),
+			difficulty_history: vec![(0, diff)],
+			median_time_span: ChainParams::mainnet().median_time_span,
+			time_tolerance: ChainParams::mainnet().time_tolerance_ms,
+			checkpoints: BTreeMap::new(),
+			genesis_value: None,
+			chain_work: 0,
+			consensus: Box::new(ProofOfWork),
+			reorg_listeners: vec![],
+			signature_cache: Arc::new(SignatureCache::default()),
+			base: None,
+		}
+	}
+
+	pub fn new_with_params (params: &ChainParams) -> Self {
+		let mut checkpoints: BTreeMap<u32, BlockHash> = params.checkpoints.iter()
+			.map(|checkpoint| (checkpoint.height, checkpoint.hash))
+			.collect();
+		// a fixed genesis block is checkpointed like any other
+		if let Some(genesis) = &params.genesis {
+			checkpoints.insert(0, genesis.block().hash);
+		}
+		Blockchain {
+			blocks: vec![],
+			unspent_outputs: HashSet::new(),
+			difficulty: params.difficulty,
+			coinbase_value: params.coinbase_value,
+			demurrage_rate: params.demurrage_rate_per_hour,
+			address_index: HashMap::new(),
+			difficulty_history: vec![(0, params.difficulty)],
+			median_time_span: params.median_time_span,
+			time_tolerance: params.time_tolerance_ms,
+			checkpoints,
+			genesis_value: params.genesis.as_ref().map(|genesis| genesis.premine_total()),
+			chain_work: 0,
+			consensus: if params.hybrid_consensus { Box::new(Hybrid::new(params)) } else { Box::new(ProofOfWork) },
+			reorg_listeners: vec![],
+			signature_cache: Arc::new(SignatureCache::default()),
+			base: None,
+		}
+	}
+
+	// a chain following `params`, with `genesis` as its first block
+	pub fn with_genesis (params: &ChainParams, genesis: &GenesisConfig) -> Result<Self, BlockValidationErr> {
+		let mut blockchain = Blockchain::new_with_params(&params.clone().with_genesis(genesis.clone()));
+		blockchain.update_with_block(genesis.block())?;
+		Ok(blockchain)
+	}
+
+	/// A chain synced from `snapshot` instead of replayed from genesis
+	///
+	/// `headers` run from genesis to the snapshot's block, and are checked as
+	/// blocks' headers are: links, timestamps, difficulty, checkpoints and the
+	/// consensus rules. `next`, the block after the snapshot's, must commit
+	/// (`utxo_commitment`) to exactly the snapshot's outputs, and is then
+	/// validated and applied in full on top of them, so the chain returned
+	/// always holds it. The chain holds the headers up to the snapshot's block
+	/// without their transactions, so address histories start at the
+	/// snapshot, and that block becomes a checkpoint, since nothing before it
+	/// can be replayed.
+	pub fn from_utxo_snapshot (params: &ChainParams, headers: &[BlockHeader], snapshot: UtxoSnapshot, next: Block) -> Result<Self, String> {
+		let height = snapshot.height as usize;
+		let header = headers.get(height)
+			.ok_or_else(|| format!("A snapshot at height {} needs the headers up to it", height))?;
+		if header.hash() != snapshot.block_hash {
+			return Err(format!("Snapshot is of block {}, but the header at height {} is {}", snapshot.block_hash, height, header.hash()));
+		}
+		if next.header.utxo_commitment != Some(snapshot.commitment()) {
+			return Err(format!("The header at height {} doesn't commit to the snapshot's outputs", height + 1));
+		}
+		snapshot.check_outputs()?;
+
+		let mut blockchain = Blockchain::new_with_params(params);
+		for header in &headers[..=height] {
+			if header.bits.is_harder_than(&blockchain.difficulty) {
+				blockchain.update_difficulty(header.bits)
+					.map_err(|e| format!("Header {} has an invalid difficulty: {:?}", header.index, e))?;
+			}
+			let index = header.index;
+			blockchain.append_header(header.clone())
+				.map_err(|e| format!("Header {} is invalid: {:?}", index, e))?;
+		}
+		blockchain.checkpoints.insert(snapshot.height, snapshot.block_hash);
+		blockchain.apply_base(&snapshot);
+		blockchain.base = Some(Arc::new(snapshot));
+
+		let index = next.header.index;
+		if next.header.bits.is_harder_than(&blockchain.difficulty) {
+			blockchain.update_difficulty(next.header.bits)
+				.map_err(|e| format!("Block {} has an invalid difficulty: {:?}", index, e))?;
 		}
+		blockchain.update_with_block(next)
+			.map_err(|e| format!("Block {} after the snapshot is invalid: {:?}", index, e))?;
+		Ok(blockchain)
 	}
 
-	pub fn update_difficulty (&mut self, diff:u128) -> Result<(), BlockValidationErr> {
-		if self.difficulty < diff{
+	// start the UTXO set and address index from the outputs of the snapshot the chain was synced from
+	fn apply_base (&mut self, snapshot: &UtxoSnapshot) {
+		for output in &snapshot.outputs {
+			let hash = output.hash();
+			self.unspent_outputs.insert(hash);
+			self.address_index.entry(output.to_addr.clone()).or_default()
+				.unspent.insert(hash, output.clone());
+		}
+	}
+
+	/// The snapshot the chain was synced from, if it wasn't replayed from genesis
+	pub fn utxo_base (&self) -> Option<&UtxoSnapshot> {
+		self.base.as_deref()
+	}
+
+	// the first height whose block the chain holds in full
+	fn replay_start (&self) -> usize {
+		self.base.as_ref().map_or(0, |base| base.height as usize + 1)
+	}
+
+	/// The UTXO set as it stood after the block at `height`, unsigned
+	///
+	/// Built by undoing the blocks after it from the current set, so it fails
+	/// for heights the chain doesn't hold in full.
+	pub fn utxo_snapshot (&self, height: usize) -> Result<UtxoSnapshot, String> {
+		if height >= self.blocks.len() {
+			return Err(format!("No block at height {}", height));
+		}
+		if height + 1 < self.replay_start() {
+			return Err(format!("The chain holds no transactions before height {}", self.replay_start()));
+		}
+		let mut outputs: HashMap<BlockHash, Output> = self.address_index.values()
+			.flat_map(|entry| entry.unspent.iter().map(|(hash, output)| (*hash, output.clone())))
+			.collect();
+		for block in self.blocks[height + 1..].iter().rev() {
+			for transaction in block.body.transactions.iter().rev() {
+				for output in &transaction.outputs {
+					outputs.remove(&output.hash());
+				}
+				for input in &transaction.inputs {
+					outputs.insert(input.hash(), input.clone());
+				}
+			}
+		}
+		Ok(UtxoSnapshot::new(height as u32, self.blocks[height].hash, outputs.into_values().collect()))
+	}
+
+	// what the coinbase of the block at `height` must pay
+	fn coinbase_value_at (&self, height: usize) -> f64 {
+		match self.genesis_value {
+			Some(value) if height == 0 => value,
+			_ => self.coinbase_value,
+		}
+	}
+
+	pub fn update_difficulty (&mut self, diff: Difficulty) -> Result<(), BlockValidationErr> {
+		if diff.is_easier_than(&self.difficulty) {
 			return Err(BlockValidationErr::InvalidDifficultyUpdate);
 		}
 		else{
 			self.difficulty = diff;
+			// a change before any block at this height replaces the earlier one
+			let height = self.blocks.len();
+			match self.difficulty_history.last_mut() {
+				Some(last) if last.0 == height => last.1 = diff,
+				_ => self.difficulty_history.push((height, diff)),
+			}
 			Ok(())
 		}
 
 	}
 
-	pub fn get_difficulty (&self) -> u128 {
+	pub fn get_difficulty (&self) -> Difficulty {
 		self.difficulty
 	}
 
+	// (first height, difficulty) for every difficulty the chain has used
+	pub fn difficulty_history (&self) -> &[(usize, Difficulty)] {
+		&self.difficulty_history
+	}
+
+	// the difficulty a block at `height` had to meet
+	pub fn difficulty_at (&self, height: usize) -> Difficulty {
+		self.difficulty_history.iter()
+			.take_while(|(first_height, _)| *first_height <= height)
+			.last()
+			.map_or(self.difficulty, |(_, difficulty)| *difficulty)
+	}
+
+	// the scheme deciding which blocks may extend the chain (proof of work unless set)
+	pub fn consensus_engine (&self) -> &dyn ConsensusEngine {
+		self.consensus.as_ref()
+	}
+
+	// switch to another consensus scheme; blocks already in the chain aren't checked again
+	pub fn set_consensus_engine (&mut self, engine: impl ConsensusEngine + 'static) {
+		self.consensus = Box::new(engine);
+	}
+
+	// what the next block must meet under the chain's consensus engine
+	pub fn next_challenge (&self) -> Challenge {
+		self.consensus.next_challenge(self)
+	}
+
+	// cumulative work of every block in the chain; the heaviest chain, not the longest, wins a fork
+	pub fn total_work (&self) -> u128 {
+		self.chain_work
+	}
+
 	pub fn update_with_block (&mut self, block:Block) -> Result<(), BlockValidationErr> {
-		let i = self.blocks.len();
-		// block index test
-		if block.index != i as u32 {
-			return Err(BlockValidationErr::MismatchedIndex);
+		self.append_block(block, false)?;
+		self.index_block(self.blocks.len() - 1);
+		Ok(())
+	}
+
+	/// Append `blocks` in order, all of them or none
+	///
+	/// Each block is validated as `update_with_block` does, and its stored hash
+	/// must also be the hash of its header, as `verify_chain` checks. What a
+	/// block can be checked for on its own (its hash, merkle root and
+	/// signatures) is checked for the whole batch at once, in parallel; links,
+	/// difficulty, timestamps and spends are then checked block by block as
+	/// they are applied. If any block is invalid the chain is left as it was,
+	/// and the height of the first bad block is returned with the reason.
+	pub fn update_with_blocks (&mut self, blocks: Vec<Block>) -> Result<(), (u32, BlockValidationErr)> {
+		let height = self.blocks.len();
+		let chain_work = self.chain_work;
+		let cache = &self.signature_cache;
+		let checked: Vec<Result<(), BlockValidationErr>> = blocks.par_iter().map(|block| Self::check_contents(block, cache)).collect();
+
+		for (i, (block, checked)) in blocks.into_iter().zip(checked).enumerate() {
+			if let Err(e) = checked.and_then(|_| self.append_block(block, true)) {
+				self.undo_append(height, chain_work);
+				return Err(((height + i) as u32, e));
+			}
 		}
-		// failed prescribed difficulty value...should make sure block is storing valid difficulty tho
-		else if !block::check_blockhash(&block.hash(), self.difficulty) {
+		for i in height..self.blocks.len() {
+			self.index_block(i);
+		}
+		Ok(())
+	}
+
+	// the checks a block can pass or fail without the chain: its hash, merkle root and signatures
+	fn check_contents (block: &Block, cache: &SignatureCache) -> Result<(), BlockValidationErr> {
+		if block.hash != block.hash() {
 			return Err(BlockValidationErr::InvalidHash);
 		}
-		else if i != 0{
-			// not genesis block
-			let prev_block = &self.blocks[i-1];
-			if block.timestamp <= prev_block.timestamp {
-				return Err(BlockValidationErr::AchronologicalTimestamp);
-			}
-			else if block.prev_block_hash != prev_block.hash {
-				return Err(BlockValidationErr::MismatchedPreviousHash);
-			}
+		if !block.has_valid_merkle_root() {
+			return Err(BlockValidationErr::InvalidMerkleRoot);
 		}
-		else{
-			// genesis block
-			if block.prev_block_hash != vec![0; 32] {
-				return Err(BlockValidationErr::InvalidGenesisBlockFormat);
+		// key and multisig addresses can only be spent with signatures from their owners
+		cache.verify_transactions(&block.body.transactions).map_err(|_| BlockValidationErr::InvalidSignature)
+	}
+
+	/// Check that `transaction` carries the signatures its key and multisig inputs need
+	///
+	/// Verified signatures are remembered, so a block carrying the transaction
+	/// later doesn't check them again (see `signature_cache`).
+	pub fn verify_signatures (&self, transaction: &Transaction) -> Result<(), String> {
+		self.signature_cache.verify_transactions([transaction])
+	}
+
+	pub fn signature_cache (&self) -> &SignatureCache {
+		&self.signature_cache
+	}
+
+	// drop the blocks appended from `height` up, which haven't been indexed yet,
+	// and put back the UTXO set, work and consensus state they changed
+	fn undo_append (&mut self, height: usize, chain_work: u128) {
+		for block in self.blocks.split_off(height).iter().rev() {
+			for transaction in block.body.transactions.iter().rev() {
+				let created = transaction.output_hashes();
+				self.unspent_outputs.retain(|output| !created.contains(output));
+				self.unspent_outputs.extend(transaction.input_hashes());
 			}
 		}
+		self.chain_work = chain_work;
+		self.consensus = self.consensus.reset();
+		for block in &self.blocks {
+			self.consensus.on_block_accepted(block);
+		}
+	}
+
+	// validate `block` against the chain and apply it, leaving the address index to the caller;
+	// `contents_checked` skips the merkle root and signature checks `check_contents` already did
+	fn append_block (&mut self, block: Block, contents_checked: bool) -> Result<(), BlockValidationErr> {
+		let i = self.blocks.len();
+		self.check_header(&block)?;
+
+		// the hash covers only the header, so the header must commit to these transactions
+		if !contents_checked && !block.has_valid_merkle_root() {
+			return Err(BlockValidationErr::InvalidMerkleRoot);
+		}
+
+		// a committed UTXO set must be the one the block actually spends from
+		if block.header.utxo_commitment.as_ref().is_some_and(|commitment| *commitment != self.utxo_commitment()) {
+			return Err(BlockValidationErr::InvalidUtxoCommitment);
+		}
 
-		if let Some((coinbase, transactions)) = block.transactions.split_first() {
-			if !coinbase.is_coinbase() {
+		// every block pays exactly one coinbase, and it comes first
+		if block.body.transactions.is_empty() {
+			return Err(BlockValidationErr::MissingCoinbaseTransaction);
+		}
+
+		if let Some((coinbase, transactions)) = block.body.transactions.split_first() {
+			if !coinbase.is_coinbase_with_value(self.coinbase_value_at(i)) {
 				return Err(BlockValidationErr::InvalidCoinbaseTransaction);
 			}
 
@@ -90,19 +429,54 @@ impl Blockchain {
 			let mut total_fee = 0.0;
 
 			for transaction in transactions {
+				if transaction.inputs.is_empty() {
+					return Err(BlockValidationErr::MultipleCoinbaseTransactions);
+				}
+
 				let input_hashes = transaction.input_hashes();
 
-				// first condition is if there is a leftover input that didn't come from unspent output
-				// second condition is that there is an input hash that has been used twice
-				if !(&input_hashes - &self.unspent_outputs).is_empty() || !(&input_hashes & &block_spent).is_empty(){
+				// an input listed twice in one transaction, or already spent earlier in this block
+				if input_hashes.len() != transaction.inputs.len() || !(&input_hashes & &block_spent).is_empty() {
+					return Err(BlockValidationErr::DuplicateInput);
+				}
+
+				// an input that didn't come from an unspent output
+				if !(&input_hashes - &self.unspent_outputs).is_empty() {
 					return Err(BlockValidationErr::InvalidInput);
 				}
 
 				let inputs = &transaction.inputs;
 				let outputs = &transaction.outputs;
 
+				// time-locked inputs can only be spent once the chain has reached their lock time
+				if inputs.iter().any(|input| !input.is_spendable_at(block.header.timestamp)) {
+					return Err(BlockValidationErr::LockedOutput);
+				}
+
+				// inputs paying to an HTLC must be redeemed or refunded according to their contract
+				for input in inputs.iter().filter(|input| htlc::is_htlc_address(&input.to_addr)) {
+					let value = input.spendable_value_at(block.header.timestamp, self.demurrage_rate);
+					if htlc::check_input(input, transaction, value, block.header.timestamp).is_err() {
+						return Err(BlockValidationErr::UnsatisfiedHtlc);
+					}
+				}
+
+				// channel funding outputs can only be spent by closing the channel on its signed state
+				for input in inputs.iter().filter(|input| channels::is_channel_address(&input.to_addr)) {
+					if channels::check_input(input, transaction, block.header.timestamp).is_err() {
+						return Err(BlockValidationErr::InvalidChannelClose);
+					}
+				}
+
+
 				for output in outputs {
 					let out_time = &output.timestamp; // time of output
+
+					// demurrage runs from an output's timestamp, so one dated after its block would decay late
+					if *out_time > block.header.timestamp {
+						return Err(BlockValidationErr::InvalidTransactionTimestamp);
+					}
+
 					for input in inputs {
 						let in_time = &input.timestamp;
 
@@ -113,7 +487,8 @@ impl Blockchain {
 					}
 				}
 
-				let input_sum = transaction.input_sum();
+				// with demurrage, inputs are only worth what is left of them at the block's timestamp
+				let input_sum = transaction.input_sum_at(block.header.timestamp, self.demurrage_rate);
 				let output_sum = transaction.output_sum();
 
 				if output_sum > input_sum {
@@ -130,6 +505,10 @@ impl Blockchain {
 			if coinbase.output_sum() < total_fee {
 				return Err(BlockValidationErr::InvalidCoinbaseTransaction);
 			}
+			// key and multisig addresses can only be spent with signatures from their owners
+			if !contents_checked && self.signature_cache.verify_transactions(transactions).is_err() {
+				return Err(BlockValidationErr::InvalidSignature);
+			}
 			else{
 				block_created.extend(coinbase.output_hashes());
 			}
@@ -139,16 +518,513 @@ impl Blockchain {
 
 		}
 
+		self.chain_work = self.chain_work.saturating_add(block.header.bits.work());
+		self.consensus.on_block_accepted(&block);
 		self.blocks.push(block);
 
 		Ok(())
 	}
+
+	// the checks on a block's header alone: index, checkpoint, difficulty, consensus rules, link and timestamps
+	fn check_header (&self, block: &Block) -> Result<(), BlockValidationErr> {
+		let i = self.blocks.len();
+		// block index test
+		if block.header.index != i as u32 {
+			return Err(BlockValidationErr::MismatchedIndex);
+		}
+		// a checkpoint rules out any other history at its height before doing real work
+		else if self.checkpoints.get(&block.header.index).is_some_and(|hash| *hash != block.hash()) {
+			return Err(BlockValidationErr::CheckpointMismatch);
+		}
+		// the header must claim the difficulty in force, and its hash must meet it
+		else if block.header.bits != self.difficulty {
+			return Err(BlockValidationErr::MismatchedDifficulty);
+		}
+		// the consensus engine decides what else the block must prove, such as the hash meeting the difficulty
+		else if let Err(e) = self.consensus.validate_block(self, block) {
+			return Err(e);
+		}
+		else if i != 0{
+			// not genesis block
+			let prev_block = &self.blocks[i-1];
+			if block.header.timestamp <= prev_block.header.timestamp {
+				return Err(BlockValidationErr::AchronologicalTimestamp);
+			}
+			else if self.median_time_past().is_some_and(|median| block.header.timestamp <= median) {
+				return Err(BlockValidationErr::TimestampBeforeMedianTimePast);
+			}
+			else if block.header.prev_block_hash != prev_block.hash {
+				return Err(BlockValidationErr::MismatchedPreviousHash);
+			}
+		}
+		else{
+			// genesis block
+			if block.header.prev_block_hash != BlockHash::ZERO {
+				return Err(BlockValidationErr::InvalidGenesisBlockFormat);
+			}
+		}
+		Ok(())
+	}
+
+	// append a block's header without its transactions, for the blocks under a UTXO snapshot
+	fn append_header (&mut self, header: BlockHeader) -> Result<(), BlockValidationErr> {
+		let block = Block::from_parts(header, BlockBody { transactions: vec![] });
+		self.check_header(&block)?;
+		self.chain_work = self.chain_work.saturating_add(block.header.bits.work());
+		self.consensus.on_block_accepted(&block);
+		self.blocks.push(block);
+		Ok(())
+	}
+
+	// like `update_with_block`, but also rejects blocks timestamped more than the
+	// time tolerance past `validated_time` (the node's synced clock)
+	pub fn update_with_block_at (&mut self, block:Block, validated_time: u128) -> Result<(), BlockValidationErr> {
+		if block.header.timestamp > validated_time + self.time_tolerance {
+			return Err(BlockValidationErr::TimestampTooFarAhead);
+		}
+		self.update_with_block(block)
+	}
+
+	/// Median timestamp of the last `median_time_span` blocks (None for an empty chain)
+	pub fn median_time_past (&self) -> Option<u128> {
+		let recent = &self.blocks[self.blocks.len().saturating_sub(self.median_time_span.max(1))..];
+		let mut timestamps: Vec<u128> = recent.iter().map(|block| block.header.timestamp).collect();
+		timestamps.sort_unstable();
+		timestamps.get(timestamps.len() / 2).copied()
+	}
+
+	// records every transaction of the applied block at `height` against the addresses it touches
+	fn index_block (&mut self, height: usize) {
+		let block = &self.blocks[height];
+		for transaction in &block.body.transactions {
+			let mut touched:Vec<(&Address, f64, f64)> = vec![]; // (address, received, sent) in first-seen order
+
+			for input in &transaction.inputs {
+				if let Some(entry) = self.address_index.get_mut(&input.to_addr) {
+					entry.unspent.remove(&input.hash());
+				}
+				match touched.iter_mut().find(|(addr, _, _)| *addr == &input.to_addr) {
+					Some(t) => t.2 += input.value,
+					None => touched.push((&input.to_addr, 0.0, input.value)),
+				}
+			}
+
+			for output in &transaction.outputs {
+				self.address_index.entry(output.to_addr.clone()).or_default()
+					.unspent.insert(output.hash(), output.clone());
+				match touched.iter_mut().find(|(addr, _, _)| *addr == &output.to_addr) {
+					Some(t) => t.1 += output.value,
+					None => touched.push((&output.to_addr, output.value, 0.0)),
+				}
+			}
+
+			let tx_hash = transaction.hash();
+			for (addr, received, sent) in touched {
+				self.address_index.entry(addr.clone()).or_default().history.push(AddressTx {
+					block_index: block.header.index,
+					block_timestamp: block.header.timestamp,
+					tx_hash,
+					received,
+					sent,
+				});
+			}
+		}
+	}
+
+	/// Total value of the unspent outputs paying an address
+	pub fn balance_of (&self, address: &Address) -> f64 {
+		self.address_index.get(address)
+			.map(|entry| entry.unspent.values().map(|output| output.value).sum())
+			.unwrap_or(0.0)
+	}
+
+	/// What an address could spend in a block at `time`: unlocked outputs, after demurrage
+	pub fn spendable_balance_of (&self, address: &Address, time: u128) -> f64 {
+		self.address_index.get(address)
+			.map(|entry| entry.unspent.values()
+				.filter(|output| output.is_spendable_at(time))
+				.map(|output| output.spendable_value_at(time, self.demurrage_rate))
+				.sum())
+			.unwrap_or(0.0)
+	}
+
+	/// Unspent outputs paying an address, oldest first (candidates for coin selection)
+	pub fn unspent_outputs_of (&self, address: &Address) -> Vec<Output> {
+		let mut outputs: Vec<Output> = self.address_index.get(address)
+			.map(|entry| entry.unspent.values().cloned().collect())
+			.unwrap_or_default();
+		outputs.sort_by_key(|output| (output.timestamp, output.hash()));
+		outputs
+	}
+
+	/// Transactions that sent or received value for an address, oldest first
+	pub fn history_of (&self, address: &Address) -> &[AddressTx] {
+		self.address_index.get(address)
+			.map(|entry| entry.history.as_slice())
+			.unwrap_or(&[])
+	}
+
+	pub fn is_unspent (&self, output_hash: &BlockHash) -> bool {
+		self.unspent_outputs.contains(output_hash)
+	}
+
+	pub fn unspent_output_count (&self) -> usize {
+		self.unspent_outputs.len()
+	}
+
+	// the unspent output hashes, sorted
+	pub fn unspent_output_hashes (&self) -> Vec<&BlockHash> {
+		let mut unspent: Vec<&BlockHash> = self.unspent_outputs.iter().collect();
+		unspent.sort_unstable();
+		unspent
+	}
+
+	/// SHA-256 of the sorted unspent output hashes
+	///
+	/// The next block may carry this in its header, so a light client or a
+	/// fast-syncing node can check a UTXO set it was handed without replaying history.
+	pub fn utxo_commitment (&self) -> BlockHash {
+		BlockHash::digest(&self.unspent_output_hashes().canonical_bytes())
+	}
+
+	/// Re-validate every block from genesis, as if it were arriving for the first time
+	///
+	/// A chain synced from a UTXO snapshot replays from the snapshot instead,
+	/// checking only the hashes of the headers below it.
+	///
+	/// Checks stored hashes, hash links, the difficulty in force at each height,
+	/// index continuity, timestamps, coinbase rules and every transaction, then
+	/// that the tracked UTXO set matches the replay. Run this on a chain loaded
+	/// from disk or received from a peer. On failure, returns the height of the
+	/// first bad block (or the chain length, if only the UTXO set is off).
+	pub fn verify_chain (&self) -> Result<(), (u32, BlockValidationErr)> {
+		let mut replay = self.empty_replica();
+
+		for (height, block) in self.blocks.iter().enumerate() {
+			if block.hash != block.hash() {
+				return Err((height as u32, BlockValidationErr::InvalidHash));
+			}
+			// blocks under the snapshot the chain was synced from are already in the replica
+			if height < self.replay_start() {
+				continue;
+			}
+			replay.difficulty = self.difficulty_at(height);
+			replay.update_with_block(block.clone()).map_err(|e| (height as u32, e))?;
+		}
+
+		if replay.unspent_outputs != self.unspent_outputs {
+			return Err((self.blocks.len() as u32, BlockValidationErr::InconsistentUtxoSet));
+		}
+		Ok(())
+	}
+
+	/// Write the chain to `path`: JSON Lines if it ends in `.jsonl`, compact binary otherwise (see `chain_file`)
+	pub fn export (&self, path: &Path) -> Result<(), String> {
+		if self.base.is_some() {
+			return Err(format!("The chain was synced from a UTXO snapshot and holds no transactions before height {}", self.replay_start()));
+		}
+		let file = ChainFile {
+			difficulty_history: self.difficulty_history.clone(),
+			blocks: self.blocks.clone(),
+		};
+		file.save(path, ChainFormat::from_path(path))
+	}
+
+	/// Read a chain written by `export`, re-validating every block under this chain's rules
+	///
+	/// Blocks this chain already has must match the file's; the rest are
+	/// appended. Nothing changes unless every block in the file is valid.
+	/// Returns how many blocks were added.
+	pub fn import (&mut self, path: &Path) -> Result<usize, String> {
+		let file = ChainFile::load(path)?;
+		if let Some(height) = self.blocks.iter().zip(&file.blocks).position(|(ours, theirs)| ours.hash != theirs.hash) {
+			return Err(format!("{} diverges from this chain at height {}", path.display(), height));
+		}
+		if file.blocks.len() <= self.blocks.len() {
+			return Ok(0);
+		}
+
+		let mut replay = self.empty_replica();
+		let start = self.replay_start();
+		let mut difficulties = file.difficulty_history.iter().peekable();
+		// the replica already holds the blocks under a snapshot, and the difficulties they used
+		while difficulties.next_if(|(first_height, _)| *first_height < start).is_some() {}
+		for (height, block) in file.blocks.into_iter().enumerate().skip(start) {
+			while let Some((_, difficulty)) = difficulties.next_if(|(first_height, _)| *first_height <= height) {
+				replay.update_difficulty(*difficulty)
+					.map_err(|e| format!("Difficulty at height {} in {} doesn't apply: {:?}", height, path.display(), e))?;
+			}
+			// the file carries hashes, but only the hash of what's actually there counts
+			if block.hash != block.hash() {
+				return Err(format!("Block {} in {} is invalid: {:?}", height, path.display(), BlockValidationErr::InvalidHash));
+			}
+			replay.update_with_block(block)
+				.map_err(|e| format!("Block {} in {} is invalid: {:?}", height, path.display(), e))?;
+		}
+		// changes made since the last block
+		for (_, difficulty) in difficulties {
+			replay.update_difficulty(*difficulty)
+				.map_err(|e| format!("Difficulty in {} doesn't apply: {:?}", path.display(), e))?;
+		}
+		if replay.difficulty_history.is_empty() {
+			replay.difficulty_history.push((0, replay.difficulty));
+		}
+
+		let added = replay.blocks.len() - self.blocks.len();
+		*self = replay;
+		Ok(added)
+	}
+
+	// same rules and checkpoints, no blocks yet beyond the snapshot the chain was synced from, if any
+	fn empty_replica (&self) -> Blockchain {
+		let mut replica = Blockchain {
+			blocks: vec![],
+			unspent_outputs: HashSet::new(),
+			difficulty: self.difficulty_at(0),
+			coinbase_value: self.coinbase_value,
+			demurrage_rate: self.demurrage_rate,
+			address_index: HashMap::new(),
+			difficulty_history: vec![],
+			median_time_span: self.median_time_span,
+			time_tolerance: self.time_tolerance,
+			checkpoints: self.checkpoints.clone(),
+			genesis_value: self.genesis_value,
+			chain_work: 0,
+			consensus: self.consensus.reset(),
+			reorg_listeners: vec![],
+			signature_cache: Arc::clone(&self.signature_cache),
+			base: None,
+		};
+		if let Some(base) = &self.base {
+			let start = self.replay_start();
+			replica.blocks = self.blocks[..start].to_vec();
+			for block in &replica.blocks {
+				replica.chain_work = replica.chain_work.saturating_add(block.header.bits.work());
+				replica.consensus.on_block_accepted(block);
+			}
+			replica.difficulty = self.difficulty_at(start);
+			replica.difficulty_history = self.difficulty_history.iter()
+				.filter(|(first_height, _)| *first_height < start)
+				.copied()
+				.collect();
+			replica.apply_base(base);
+			replica.base = Some(Arc::clone(base));
+		}
+		replica
+	}
+
+	/// Require the block at `height` to have `hash`
+	///
+	/// Fails if the chain already holds a different block there.
+	pub fn add_checkpoint (&mut self, height: u32, hash: &BlockHash) -> Result<(), BlockValidationErr> {
+		if self.blocks.get(height as usize).is_some_and(|block| block.hash != *hash) {
+			return Err(BlockValidationErr::CheckpointMismatch);
+		}
+		self.checkpoints.insert(height, *hash);
+		Ok(())
+	}
+
+	/// Height of the highest checkpointed block the chain contains
+	pub fn last_checkpoint_height (&self) -> Option<u32> {
+		self.checkpoints.keys().rev().find(|height| (**height as usize) < self.blocks.len()).copied()
+	}
+
+	/// Drop every block from `height` up, so a competing branch can be applied
+	///
+	/// Returns the removed blocks. Refused with `BelowCheckpoint` if that would
+	/// remove a checkpointed block. State is rebuilt by replaying the kept blocks.
+	pub fn rewind_to (&mut self, height: usize) -> Result<Vec<Block>, BlockValidationErr> {
+		if height >= self.blocks.len() {
+			return Ok(vec![]);
+		}
+		if self.last_checkpoint_height().is_some_and(|checkpoint| checkpoint as usize >= height) {
+			return Err(BlockValidationErr::BelowCheckpoint);
+		}
+
+		let mut replay = self.empty_replica();
+		for (i, block) in self.blocks[..height].iter().enumerate().skip(self.replay_start()) {
+			replay.difficulty = self.difficulty_at(i);
+			replay.update_with_block(block.clone())?;
+		}
+
+		let removed = self.blocks.split_off(height);
+		self.unspent_outputs = replay.unspent_outputs;
+		self.address_index = replay.address_index;
+		self.chain_work = replay.chain_work;
+		self.consensus = replay.consensus;
+		self.difficulty_history.retain(|(first_height, _)| *first_height <= height);
+		self.difficulty = self.difficulty_at(height);
+		Ok(removed)
+	}
+
+	/// Replace the blocks from `height` up with `branch`, if the branch carries more work
+	///
+	/// Fork choice follows cumulative work: the branch wins only if it adds more
+	/// work than the blocks it replaces, and a tie keeps the chain already held.
+	/// Branch blocks may raise the difficulty, never lower it, and are validated
+	/// like any other block; nothing changes unless all of them are valid.
+	/// Returns the replaced blocks.
+	pub fn reorganize (&mut self, height: usize, branch: Vec<Block>) -> Result<Vec<Block>, BlockValidationErr> {
+		if height > self.blocks.len() {
+			return Err(BlockValidationErr::MismatchedIndex);
+		}
+		let branch_work = branch.iter().fold(0u128, |work, block| work.saturating_add(block.header.bits.work()));
+		let replaced_work = self.blocks[height..].iter().fold(0u128, |work, block| work.saturating_add(block.header.bits.work()));
+		if branch_work <= replaced_work {
+			return Err(BlockValidationErr::InsufficientWork);
+		}
+		if self.last_checkpoint_height().is_some_and(|checkpoint| checkpoint as usize >= height) {
+			return Err(BlockValidationErr::BelowCheckpoint);
+		}
+
+		let mut replay = self.empty_replica();
+		for (i, block) in self.blocks[..height].iter().enumerate().skip(self.replay_start()) {
+			replay.difficulty = self.difficulty_at(i);
+			replay.update_with_block(block.clone())?;
+		}
+		replay.difficulty_history = self.difficulty_history.iter()
+			.filter(|(first_height, _)| *first_height <= height)
+			.copied()
+			.collect();
+		replay.difficulty = self.difficulty_at(height);
+		for block in branch {
+			if block.header.bits.is_harder_than(&replay.difficulty) {
+				replay.update_difficulty(block.header.bits)?;
+			}
+			replay.update_with_block(block)?;
+		}
+		// a difficulty raised ahead of the next block still holds
+		if self.difficulty.is_harder_than(&replay.difficulty) {
+			replay.update_difficulty(self.difficulty)?;
+		}
+
+		let removed = self.blocks.split_off(height);
+		replay.reorg_listeners = std::mem::take(&mut self.reorg_listeners);
+		*self = replay;
+		let reorg = self.reorg_since(height, &removed);
+		self.reorg_listeners.retain(|listener| listener.send(reorg.clone()).is_ok());
+		Ok(removed)
+	}
+
+	/// Adopt `other`, a chain received from a peer, if it carries more work than ours
+	///
+	/// The blocks after the last one both chains share are validated under our
+	/// own rules and checkpoints, as `reorganize` does; nothing about `other`'s
+	/// state is trusted. Fork choice follows cumulative work, so on equal
+	/// difficulty the longer chain wins, and a tie keeps ours. Returns the
+	/// reorganization if `other` was adopted, also sent to `subscribe_reorgs`
+	/// listeners, and `None` if ours is at least as heavy or already holds all
+	/// of `other`.
+	pub fn compare_and_adopt (&mut self, other: &Blockchain) -> Result<Option<Reorg>, BlockValidationErr> {
+		let common = self.blocks.iter().zip(&other.blocks)
+			.take_while(|(ours, theirs)| ours.hash == theirs.hash)
+			.count();
+		if common == other.blocks.len() {
+			return Ok(None);
+		}
+
+		let branch = other.blocks[common..].to_vec();
+		match self.reorganize(common, branch) {
+			Ok(removed) => Ok(Some(self.reorg_since(common, &removed))),
+			Err(BlockValidationErr::InsufficientWork) => Ok(None),
+			Err(e) => Err(e),
+		}
+	}
+
+	// the reorganization that replaced `removed` with the blocks from `height` up
+	fn reorg_since (&self, height: usize, removed: &[Block]) -> Reorg {
+		Reorg {
+			fork_height: height,
+			removed: removed.iter().map(|block| block.hash).collect(),
+			added: self.blocks[height..].iter().map(|block| block.hash).collect(),
+			total_work: self.chain_work,
+		}
+	}
+
+	/// Hear about every reorganization from now on, including adoptions by `compare_and_adopt`
+	pub fn subscribe_reorgs (&mut self) -> mpsc::Receiver<Reorg> {
+		let (sender, receiver) = mpsc::channel();
+		self.reorg_listeners.push(sender);
+		receiver
+	}
+
+	// replays the whole chain and checks it against the incrementally maintained state
+	pub fn check_invariants (&self) -> Result<(), String> {
+		let mut expected_unspent:HashSet<BlockHash> = HashSet::new();
+		let mut values:HashMap<BlockHash, f64> = HashMap::new();
+		let mut minted = 0.0;
+		// a chain synced from a UTXO snapshot starts from its outputs, all of them minted before it
+		for output in self.base.iter().flat_map(|base| &base.outputs) {
+			expected_unspent.insert(output.hash());
+			values.insert(output.hash(), output.value);
+			minted += output.value;
+		}
+
+		for (i, block) in self.blocks.iter().enumerate() {
+			if block.header.index != i as u32 {
+				return Err(format!("block at position {} has index {}", i, block.header.index));
+			}
+			if i > 0 {
+				let prev_block = &self.blocks[i-1];
+				if block.header.prev_block_hash != prev_block.hash {
+					return Err(format!("block #{} does not link to block #{}", i, i-1));
+				}
+				if block.header.timestamp <= prev_block.header.timestamp {
+					return Err(format!("block #{} timestamp is not after block #{}", i, i-1));
+				}
+			}
+
+			let mut block_spent:HashSet<BlockHash> = HashSet::new();
+			let mut block_created:HashSet<BlockHash> = HashSet::new();
+
+			for transaction in &block.body.transactions {
+				if transaction.is_coinbase_with_value(self.coinbase_value_at(i)) {
+					minted += transaction.output_sum();
+				}
+				block_spent.extend(transaction.input_hashes());
+				for output in &transaction.outputs {
+					values.insert(output.hash(), output.value);
+				}
+				block_created.extend(transaction.output_hashes());
+			}
+
+			expected_unspent.retain(|output| !block_spent.contains(output));
+			expected_unspent.extend(block_created);
+		}
+
+		if expected_unspent != self.unspent_outputs {
+			return Err(format!("tracked UTXO set has {} entries but chain replay gives {}",
+				self.unspent_outputs.len(), expected_unspent.len()));
+		}
+
+		let indexed_unspent:HashSet<BlockHash> = self.address_index.values()
+			.flat_map(|entry| entry.unspent.keys().cloned())
+			.collect();
+		if indexed_unspent != expected_unspent {
+			return Err(format!("address index has {} unspent outputs but chain replay gives {}",
+				indexed_unspent.len(), expected_unspent.len()));
+		}
+
+		let work = self.blocks.iter().fold(0u128, |work, block| work.saturating_add(block.header.bits.work()));
+		if work != self.chain_work {
+			return Err(format!("tracked chain work is {} but the blocks add up to {}", self.chain_work, work));
+		}
+
+		let unspent_value:f64 = expected_unspent.iter()
+			.map(|hash| values.get(hash).copied().unwrap_or(0.0))
+			.sum();
+		if unspent_value > minted + 1e-9 {
+			return Err(format!("unspent value {} exceeds minted supply {}", unspent_value, minted));
+		}
+
+		Ok(())
+	}
 }
 
 #[cfg(test)]
 mod tests {
 	use super::*;
-	use crate::transaction::{Transaction, Output};
+	use crate::transaction::{Transaction, Output, Witness};
 	use crate::now;
 
 	fn create_coinbase_transaction(value: f64, to_addr: &str, timestamp: u128) -> Transaction {
@@ -158,7 +1034,9 @@ mod tests {
 				to_addr: to_addr.to_owned(),
 				value,
 				timestamp,
+				lock_until: None,
 			}],
+			witnesses: vec![],
 		}
 	}
 
@@ -166,25 +1044,25 @@ mod tests {
 	fn test_blockchain_creation() {
 		let blockchain = Blockchain::new();
 		assert_eq!(blockchain.blocks.len(), 0);
-		assert_eq!(blockchain.get_difficulty(), 23);
+		assert_eq!(blockchain.get_difficulty(), Difficulty::from_target(23));
 	}
 
 	#[test]
 	fn test_blockchain_with_custom_difficulty() {
-		let custom_diff = 0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF;
+		let custom_diff = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
 		let blockchain = Blockchain::new_with_diff(custom_diff);
 		assert_eq!(blockchain.get_difficulty(), custom_diff);
 	}
 
 	#[test]
 	fn test_add_genesis_block() {
-		let difficulty = 0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF;
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
 		let mut blockchain = Blockchain::new_with_diff(difficulty);
 
 		let mut genesis_block = Block::new(
 			0,
 			now(),
-			vec![0; 32],
+			BlockHash::ZERO,
 			vec![create_coinbase_transaction(2.0, "Alice", now())],
 		);
 		genesis_block.mine(difficulty);
@@ -195,13 +1073,13 @@ mod tests {
 
 	#[test]
 	fn test_invalid_genesis_block_prev_hash() {
-		let difficulty = 0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF;
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
 		let mut blockchain = Blockchain::new_with_diff(difficulty);
 
 		let mut genesis_block = Block::new(
 			0,
 			now(),
-			vec![1; 32], // Invalid prev hash - should be all zeros
+			BlockHash::from_bytes([1; 32]), // Invalid prev hash - should be all zeros
 			vec![create_coinbase_transaction(2.0, "Alice", now())],
 		);
 		genesis_block.mine(difficulty);
@@ -214,18 +1092,18 @@ mod tests {
 
 	#[test]
 	fn test_add_second_block() {
-		let difficulty = 0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF;
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
 		let mut blockchain = Blockchain::new_with_diff(difficulty);
 
 		let timestamp1 = now();
 		let mut genesis_block = Block::new(
 			0,
 			timestamp1,
-			vec![0; 32],
+			BlockHash::ZERO,
 			vec![create_coinbase_transaction(2.0, "Alice", timestamp1)],
 		);
 		genesis_block.mine(difficulty);
-		let genesis_hash = genesis_block.hash.clone();
+		let genesis_hash = genesis_block.hash;
 		blockchain.update_with_block(genesis_block).unwrap();
 
 		// Add second block
@@ -244,17 +1122,17 @@ mod tests {
 
 	#[test]
 	fn test_mismatched_index() {
-		let difficulty = 0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF;
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
 		let mut blockchain = Blockchain::new_with_diff(difficulty);
 
 		let mut genesis_block = Block::new(
 			0,
 			now(),
-			vec![0; 32],
+			BlockHash::ZERO,
 			vec![create_coinbase_transaction(2.0, "Alice", now())],
 		);
 		genesis_block.mine(difficulty);
-		let genesis_hash = genesis_block.hash.clone();
+		let genesis_hash = genesis_block.hash;
 		blockchain.update_with_block(genesis_block).unwrap();
 
 		// Try to add block with wrong index
@@ -274,18 +1152,18 @@ mod tests {
 
 	#[test]
 	fn test_achronological_timestamp() {
-		let difficulty = 0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF;
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
 		let mut blockchain = Blockchain::new_with_diff(difficulty);
 
 		let timestamp = now();
 		let mut genesis_block = Block::new(
 			0,
 			timestamp,
-			vec![0; 32],
+			BlockHash::ZERO,
 			vec![create_coinbase_transaction(2.0, "Alice", timestamp)],
 		);
 		genesis_block.mine(difficulty);
-		let genesis_hash = genesis_block.hash.clone();
+		let genesis_hash = genesis_block.hash;
 		blockchain.update_with_block(genesis_block).unwrap();
 
 		// Try to add block with earlier timestamp
@@ -303,16 +1181,45 @@ mod tests {
 		));
 	}
 
+	#[test]
+	fn test_median_time_past_and_future_limit() {
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let params = ChainParams::mainnet().with_difficulty(difficulty);
+		let mut blockchain = Blockchain::new_with_params(&params);
+		assert_eq!(blockchain.median_time_past(), None);
+
+		let mut prev_hash = BlockHash::ZERO;
+		for i in 0..13u32 {
+			let timestamp = 1000 * (i as u128 + 1);
+			let mut block = Block::new(i, timestamp, prev_hash, vec![create_coinbase_transaction(2.0, "Alice", timestamp)]);
+			block.mine(difficulty);
+			prev_hash = block.hash;
+			blockchain.update_with_block_at(block, timestamp).unwrap();
+		}
+		// Median of the last 11 timestamps (3000..=13000)
+		assert_eq!(blockchain.median_time_past(), Some(8000));
+
+		// More than the tolerance past validated time
+		let timestamp = 20_000;
+		let mut block = Block::new(13, timestamp, prev_hash, vec![create_coinbase_transaction(2.0, "Alice", timestamp)]);
+		block.mine(difficulty);
+		assert!(matches!(
+			blockchain.update_with_block_at(block.clone(), timestamp - params.time_tolerance_ms - 1),
+			Err(BlockValidationErr::TimestampTooFarAhead)
+		));
+		assert!(blockchain.update_with_block_at(block, timestamp - params.time_tolerance_ms).is_ok());
+	}
+
 	#[test]
 	fn test_invalid_coinbase_transaction() {
-		let difficulty = 0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF;
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
 		let mut blockchain = Blockchain::new_with_diff(difficulty);
 
 		// Genesis block with non-coinbase transaction (wrong value)
 		let mut genesis_block = Block::new(
 			0,
 			now(),
-			vec![0; 32],
+			BlockHash::ZERO,
 			vec![create_coinbase_transaction(5.0, "Alice", now())], // Wrong value
 		);
 		genesis_block.mine(difficulty);
@@ -323,21 +1230,64 @@ mod tests {
 		));
 	}
 
+	#[test]
+	fn test_coinbase_and_duplicate_input_rules() {
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let mut blockchain = Blockchain::new_with_diff(difficulty);
+
+		let mut empty_block = Block::new(0, 1000, BlockHash::ZERO, vec![]);
+		empty_block.mine(difficulty);
+		assert!(matches!(blockchain.update_with_block(empty_block), Err(BlockValidationErr::MissingCoinbaseTransaction)));
+
+		let mut genesis_block = Block::new(0, 1000, BlockHash::ZERO, vec![create_coinbase_transaction(2.0, "Alice", 1000)]);
+		genesis_block.mine(difficulty);
+		let genesis_hash = genesis_block.hash;
+		let alice_coin = genesis_block.body.transactions[0].outputs[0].clone();
+		blockchain.update_with_block(genesis_block).unwrap();
+
+		let mine = |transactions: Vec<Transaction>| {
+			let mut block = Block::new(1, 2000, genesis_hash, transactions);
+			block.mine(difficulty);
+			block
+		};
+		let coinbase = || create_coinbase_transaction(2.0, "Miner", 2000);
+		let spend = |inputs: Vec<Output>, value: f64| Transaction {
+			inputs,
+			outputs: vec![Output { to_addr: "Bob".to_owned(), value, timestamp: 2000, lock_until: None }],
+			witnesses: vec![],
+		};
+
+		// A second coinbase, even a zero-value one
+		let block = mine(vec![coinbase(), create_coinbase_transaction(0.0, "Miner", 2000)]);
+		assert!(matches!(blockchain.update_with_block(block), Err(BlockValidationErr::MultipleCoinbaseTransactions)));
+
+		// The same input twice in one transaction would count its value twice
+		let block = mine(vec![coinbase(), spend(vec![alice_coin.clone(), alice_coin.clone()], 4.0)]);
+		assert!(matches!(blockchain.update_with_block(block), Err(BlockValidationErr::DuplicateInput)));
+
+		// ...and it can't be spent by two transactions in a block either
+		let block = mine(vec![coinbase(), spend(vec![alice_coin.clone()], 2.0), spend(vec![alice_coin.clone()], 2.0)]);
+		assert!(matches!(blockchain.update_with_block(block), Err(BlockValidationErr::DuplicateInput)));
+
+		let block = mine(vec![coinbase(), spend(vec![alice_coin], 2.0)]);
+		assert!(blockchain.update_with_block(block).is_ok());
+	}
+
 	#[test]
 	fn test_transaction_timestamp_validation() {
-		let difficulty = 0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF;
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
 		let mut blockchain = Blockchain::new_with_diff(difficulty);
 
 		let timestamp = 1000;
 		let mut genesis_block = Block::new(
 			0,
 			timestamp,
-			vec![0; 32],
+			BlockHash::ZERO,
 			vec![create_coinbase_transaction(2.0, "Alice", timestamp)],
 		);
 		genesis_block.mine(difficulty);
-		let genesis_hash = genesis_block.hash.clone();
-		let first_output = genesis_block.transactions[0].outputs[0].clone();
+		let genesis_hash = genesis_block.hash;
+		let first_output = genesis_block.body.transactions[0].outputs[0].clone();
 		blockchain.update_with_block(genesis_block).unwrap();
 
 		// Try to create transaction with output timestamp before input timestamp
@@ -353,7 +1303,9 @@ mod tests {
 						to_addr: "Bob".to_owned(),
 						value: 1.5,
 						timestamp: 500, // Before input timestamp - should fail
+						lock_until: None,
 					}],
+					witnesses: vec![],
 				},
 			],
 		);
@@ -363,19 +1315,769 @@ mod tests {
 			blockchain.update_with_block(block2),
 			Err(BlockValidationErr::InvalidTransactionTimestamp)
 		));
+
+		// An output dated after its block is rejected too
+		let spend_at = |output_timestamp| {
+			let mut block = Block::new(
+				1,
+				timestamp + 1000,
+				genesis_hash,
+				vec![
+					create_coinbase_transaction(2.0, "Miner", timestamp + 1000),
+					Transaction {
+						inputs: vec![first_output.clone()],
+						outputs: vec![Output { to_addr: "Bob".to_owned(), value: 1.5, timestamp: output_timestamp, lock_until: None }],
+						witnesses: vec![],
+					},
+				],
+			);
+			block.mine(difficulty);
+			block
+		};
+		assert!(matches!(
+			blockchain.update_with_block(spend_at(timestamp + 1001)),
+			Err(BlockValidationErr::InvalidTransactionTimestamp)
+		));
+		assert!(blockchain.update_with_block(spend_at(timestamp + 1000)).is_ok());
+	}
+
+	#[test]
+	fn test_time_locked_output() {
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let mut blockchain = Blockchain::new_with_diff(difficulty);
+
+		let timestamp = 1000;
+		let mut vested = create_coinbase_transaction(2.0, "Alice", timestamp);
+		vested.outputs[0].lock_until = Some(timestamp + 5000);
+		let mut genesis_block = Block::new(0, timestamp, BlockHash::ZERO, vec![vested]);
+		genesis_block.mine(difficulty);
+		let genesis_hash = genesis_block.hash;
+		let locked_output = genesis_block.body.transactions[0].outputs[0].clone();
+		blockchain.update_with_block(genesis_block).unwrap();
+
+		let spend = |block_timestamp: u128| Transaction {
+			inputs: vec![locked_output.clone()],
+			outputs: vec![Output {
+				to_addr: "Bob".to_owned(),
+				value: 2.0,
+				timestamp: block_timestamp,
+				lock_until: None,
+			}],
+			witnesses: vec![],
+		};
+
+		// Spending before the lock time is rejected
+		let mut early = Block::new(
+			1,
+			timestamp + 1000,
+			genesis_hash,
+			vec![create_coinbase_transaction(2.0, "Miner", timestamp + 1000), spend(timestamp + 1000)],
+		);
+		early.mine(difficulty);
+		assert!(matches!(
+			blockchain.update_with_block(early),
+			Err(BlockValidationErr::LockedOutput)
+		));
+
+		// A block timestamped at the lock time may spend it
+		let mut unlocked = Block::new(
+			1,
+			timestamp + 5000,
+			genesis_hash,
+			vec![create_coinbase_transaction(2.0, "Miner", timestamp + 5000), spend(timestamp + 5000)],
+		);
+		unlocked.mine(difficulty);
+		assert!(blockchain.update_with_block(unlocked).is_ok());
+		assert_eq!(blockchain.balance_of(&"Bob".to_owned()), 2.0);
+	}
+
+	#[test]
+	fn test_demurrage_limits_spending() {
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let params = ChainParams::mainnet().with_difficulty(difficulty).with_demurrage(0.5);
+		let mut blockchain = Blockchain::new_with_params(&params);
+
+		let timestamp = 1000;
+		let mut genesis_block = Block::new(
+			0,
+			timestamp,
+			BlockHash::ZERO,
+			vec![create_coinbase_transaction(2.0, "Alice", timestamp)],
+		);
+		genesis_block.mine(difficulty);
+		let genesis_hash = genesis_block.hash;
+		let alice_coin = genesis_block.body.transactions[0].outputs[0].clone();
+		blockchain.update_with_block(genesis_block).unwrap();
+
+		// One hour later Alice's 2.0 has decayed to 1.0
+		let an_hour_later = timestamp + 3_600_000;
+		assert_eq!(blockchain.balance_of(&"Alice".to_owned()), 2.0);
+		assert!((blockchain.spendable_balance_of(&"Alice".to_owned(), an_hour_later) - 1.0).abs() < 1e-9);
+
+		let spend = |value: f64| Transaction {
+			inputs: vec![alice_coin.clone()],
+			outputs: vec![Output {
+				to_addr: "Bob".to_owned(),
+				value,
+				timestamp: an_hour_later,
+				lock_until: None,
+			}],
+			witnesses: vec![],
+		};
+
+		let mut overspend = Block::new(
+			1,
+			an_hour_later,
+			genesis_hash,
+			vec![create_coinbase_transaction(2.0, "Miner", an_hour_later), spend(1.5)],
+		);
+		overspend.mine(difficulty);
+		assert!(matches!(
+			blockchain.update_with_block(overspend),
+			Err(BlockValidationErr::InsufficientInputValue)
+		));
+
+		let mut decayed = Block::new(
+			1,
+			an_hour_later,
+			genesis_hash,
+			vec![create_coinbase_transaction(2.0, "Miner", an_hour_later), spend(1.0)],
+		);
+		decayed.mine(difficulty);
+		assert!(blockchain.update_with_block(decayed).is_ok());
+	}
+
+	#[test]
+	fn test_htlc_spend_rules() {
+		use crate::htlc::{self, HtlcContract};
+		use crate::node_key::NodeKey;
+		use crate::wallet::address_from_public_key;
+
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let mut blockchain = Blockchain::new_with_diff(difficulty);
+
+		let timestamp = 1000;
+		let (alice, bob) = (NodeKey::generate(), NodeKey::generate());
+		let bob_address = address_from_public_key(&bob.public_key());
+		let contract = HtlcContract::new(htlc::payment_hash(b"secret"), &bob_address,
+			&address_from_public_key(&alice.public_key()), timestamp + 10_000);
+		let funding = Transaction {
+			inputs: vec![],
+			outputs: vec![htlc::create_htlc_output(&contract, 2.0, timestamp)],
+			witnesses: vec![],
+		};
+		let mut genesis_block = Block::new(0, timestamp, BlockHash::ZERO, vec![funding]);
+		genesis_block.mine(difficulty);
+		let genesis_hash = genesis_block.hash;
+		let htlc_output = genesis_block.body.transactions[0].outputs[0].clone();
+		blockchain.update_with_block(genesis_block).unwrap();
+
+		let block_with = |spend: Transaction| {
+			let mut block = Block::new(
+				1,
+				timestamp + 1000,
+				genesis_hash,
+				vec![create_coinbase_transaction(2.0, "Miner", timestamp + 1000), spend],
+			);
+			block.mine(difficulty);
+			block
+		};
+
+		// Refunding early, or spending without any witness, is rejected
+		let mut early_refund = htlc::refund_after_timeout(&htlc_output, &contract, &alice, timestamp + 10_000).unwrap();
+		early_refund.outputs[0].timestamp = timestamp + 1000;
+		assert!(matches!(
+			blockchain.update_with_block(block_with(early_refund)),
+			Err(BlockValidationErr::UnsatisfiedHtlc)
+		));
+
+		let redeem = htlc::redeem_with_preimage(&htlc_output, &contract, b"secret", &bob, timestamp + 1000).unwrap();
+		let mut bare = redeem.clone();
+		bare.witnesses.clear();
+		assert!(matches!(
+			blockchain.update_with_block(block_with(bare)),
+			Err(BlockValidationErr::UnsatisfiedHtlc)
+		));
+
+		// the preimage alone doesn't let anyone else burn the output into fees
+		let mut burned = redeem.clone();
+		burned.outputs[0].value = 1.0;
+		assert!(matches!(
+			blockchain.update_with_block(block_with(burned)),
+			Err(BlockValidationErr::UnsatisfiedHtlc)
+		));
+
+		// nor sign for the recipient
+		let mut forged = redeem.clone();
+		if let Some(Witness::Signature { signature, .. }) = forged.witnesses.last_mut() {
+			signature[0] ^= 1;
+		}
+		assert!(matches!(
+			blockchain.update_with_block(block_with(forged)),
+			Err(BlockValidationErr::InvalidSignature)
+		));
+
+		assert!(blockchain.update_with_block(block_with(redeem)).is_ok());
+		assert_eq!(blockchain.balance_of(&bob_address), 2.0);
+	}
+
+	#[test]
+	fn test_key_address_requires_signature() {
+		use crate::psbt::PartiallySignedTransaction;
+		use crate::wallet::{Mnemonic, Wallet};
+
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let mut blockchain = Blockchain::new_with_diff(difficulty);
+		let wallet = Wallet::from_mnemonic(&Mnemonic::from_entropy(&[7; 16]).unwrap(), "");
+		let address = wallet.receive_address(0).unwrap();
+
+		let timestamp = 1000;
+		let mut genesis_block = Block::new(0, timestamp, BlockHash::ZERO, vec![create_coinbase_transaction(2.0, &address, timestamp)]);
+		genesis_block.mine(difficulty);
+		let genesis_hash = genesis_block.hash;
+		let funds = genesis_block.body.transactions[0].outputs[0].clone();
+		blockchain.update_with_block(genesis_block).unwrap();
+
+		let block_with = |spend: Transaction| {
+			let mut block = Block::new(
+				1,
+				timestamp + 1000,
+				genesis_hash,
+				vec![create_coinbase_transaction(2.0, "Miner", timestamp + 1000), spend],
+			);
+			block.mine(difficulty);
+			block
+		};
+		let unsigned = Transaction {
+			inputs: vec![funds],
+			outputs: vec![Output { to_addr: "Bob".to_owned(), value: 2.0, timestamp: timestamp + 1000, lock_until: None }],
+			witnesses: vec![],
+		};
+
+		assert!(matches!(
+			blockchain.update_with_block(block_with(unsigned.clone())),
+			Err(BlockValidationErr::InvalidSignature)
+		));
+
+		let mut psbt = PartiallySignedTransaction::new(&unsigned);
+		wallet.sign(&mut psbt).unwrap();
+		let signed = psbt.finalize().unwrap();
+
+		// Redirecting the payment after signing invalidates the signature
+		let mut redirected = signed.clone();
+		redirected.outputs[0].to_addr = "Mallory".to_owned();
+		assert!(matches!(
+			blockchain.update_with_block(block_with(redirected)),
+			Err(BlockValidationErr::InvalidSignature)
+		));
+
+		// a signature checked on admission to the mempool isn't checked again in the block
+		assert_eq!(blockchain.verify_signatures(&signed), Ok(()));
+		let (hits, misses) = blockchain.signature_cache().hits_and_misses();
+		assert!(blockchain.update_with_block(block_with(signed)).is_ok());
+		assert_eq!(blockchain.signature_cache().hits_and_misses(), (hits + 1, misses));
+		assert_eq!(blockchain.balance_of(&"Bob".to_owned()), 2.0);
+	}
+
+	#[test]
+	fn test_check_invariants() {
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let mut blockchain = Blockchain::new_with_diff(difficulty);
+
+		let timestamp = now();
+		let mut genesis_block = Block::new(
+			0,
+			timestamp,
+			BlockHash::ZERO,
+			vec![create_coinbase_transaction(2.0, "Alice", timestamp)],
+		);
+		genesis_block.mine(difficulty);
+		blockchain.update_with_block(genesis_block).unwrap();
+		assert!(blockchain.check_invariants().is_ok());
+
+		// Tampering with a stored block breaks the UTXO replay
+		blockchain.blocks[0].body.transactions[0].outputs[0].to_addr = "Mallory".to_owned();
+		assert!(blockchain.check_invariants().is_err());
+	}
+
+	#[test]
+	fn test_verify_chain() {
+		let easy = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let hard = Difficulty::from_target(0x000FFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let mut blockchain = Blockchain::new_with_diff(easy);
+
+		let mut genesis_block = Block::new(0, 1000, BlockHash::ZERO, vec![create_coinbase_transaction(2.0, "Alice", 1000)]);
+		genesis_block.mine(easy);
+		let genesis_hash = genesis_block.hash;
+		blockchain.update_with_block(genesis_block).unwrap();
+
+		blockchain.update_difficulty(hard).unwrap();
+		let mut block2 = Block::new(1, 2000, genesis_hash, vec![create_coinbase_transaction(2.0, "Bob", 2000)]);
+		// a block must be mined at the difficulty in force, not the one it was started at
+		block2.mine(easy);
+		assert!(matches!(blockchain.update_with_block(block2.clone()), Err(BlockValidationErr::MismatchedDifficulty)));
+		block2.mine(hard);
+		let block2_hash = block2.hash;
+		blockchain.update_with_block(block2).unwrap();
+
+		assert_eq!((blockchain.difficulty_at(0), blockchain.difficulty_at(1), blockchain.difficulty_at(9)), (easy, hard, hard));
+		assert!(blockchain.verify_chain().is_ok());
+
+		// A block appended without validation leaves the tracked UTXO set behind
+		let mut block3 = Block::new(2, 3000, block2_hash, vec![create_coinbase_transaction(2.0, "Carol", 3000)]);
+		block3.mine(hard);
+		blockchain.blocks.push(block3);
+		assert!(matches!(blockchain.verify_chain(), Err((3, BlockValidationErr::InconsistentUtxoSet))));
+
+		// A stored hash that doesn't match the block's contents
+		blockchain.blocks[1].header.nonce.0 += 1;
+		assert!(matches!(blockchain.verify_chain(), Err((1, BlockValidationErr::InvalidHash))));
+	}
+
+	#[test]
+	fn test_export_import() {
+		let easy = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let hard = Difficulty::from_target(0x000FFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let mut blockchain = Blockchain::new_with_diff(easy);
+		let mut genesis_block = Block::new(0, 1000, BlockHash::ZERO, vec![create_coinbase_transaction(2.0, "Alice", 1000)]);
+		genesis_block.mine(easy);
+		let genesis_hash = genesis_block.hash;
+		blockchain.update_with_block(genesis_block).unwrap();
+		blockchain.update_difficulty(hard).unwrap();
+		let mut block2 = Block::new(1, 2000, genesis_hash, vec![create_coinbase_transaction(2.0, "Bob", 2000)]);
+		block2.mine(hard);
+		blockchain.update_with_block(block2).unwrap();
+
+		for name in ["chain.jsonl", "chain.bin"] {
+			let path = std::env::temp_dir().join(format!("hourcoin-{}-{}", std::process::id(), name));
+			blockchain.export(&path).unwrap();
+
+			let mut imported = Blockchain::new_with_diff(easy);
+			assert_eq!(imported.import(&path).unwrap(), 2);
+			assert_eq!(imported.blocks.last().unwrap().hash, blockchain.blocks.last().unwrap().hash);
+			assert_eq!(imported.difficulty_history(), blockchain.difficulty_history());
+			assert_eq!(imported.utxo_commitment(), blockchain.utxo_commitment());
+			// importing again adds nothing
+			assert_eq!(imported.import(&path).unwrap(), 0);
+
+			// a node with stricter rules refuses the chain and keeps what it had
+			let mut strict = Blockchain::new_with_diff(hard);
+			assert!(strict.import(&path).is_err());
+			assert!(strict.blocks.is_empty());
+			std::fs::remove_file(&path).unwrap();
+		}
+
+		// a tampered block fails re-validation
+		let path = std::env::temp_dir().join(format!("hourcoin-{}-tampered.jsonl", std::process::id()));
+		blockchain.export(&path).unwrap();
+		let tampered = std::fs::read_to_string(&path).unwrap().replace("\"Bob\"", "\"Mallory\"");
+		std::fs::write(&path, tampered).unwrap();
+		assert!(Blockchain::new_with_diff(easy).import(&path).is_err());
+
+		// so does a file whose blocks were hashed under another canonical version
+		blockchain.export(&path).unwrap();
+		let current = format!("\"canonical_version\":{}", crate::canonical::CANONICAL_VERSION);
+		let older = std::fs::read_to_string(&path).unwrap().replace(&current, "\"canonical_version\":2");
+		std::fs::write(&path, older).unwrap();
+		let error = Blockchain::new_with_diff(easy).import(&path).unwrap_err();
+		assert!(error.contains("canonical version 2"), "{}", error);
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn test_with_genesis() {
+		let params = ChainParams::regtest();
+		let mut genesis = GenesisConfig::new(1_000, "Launch day").with_premine("Alice", 100.0);
+		genesis.mine(params.difficulty);
+
+		// every node derives the same block from the config
+		let blockchain = Blockchain::with_genesis(&params, &genesis).unwrap();
+		assert_eq!(blockchain.blocks[0].hash, Blockchain::with_genesis(&params, &genesis).unwrap().blocks[0].hash);
+		assert_eq!(blockchain.balance_of(&"Alice".to_string()), 100.0);
+		assert!(blockchain.check_invariants().is_ok());
+
+		// the premine is only allowed in the configured genesis block
+		assert!(matches!(Blockchain::new_with_params(&params).update_with_block(genesis.block()), Err(BlockValidationErr::InvalidCoinbaseTransaction)));
+		let mut other = genesis.clone().with_premine("Mallory", 1.0);
+		other.mine(params.difficulty);
+		let mut configured = Blockchain::new_with_params(&params.clone().with_genesis(genesis));
+		assert!(matches!(configured.update_with_block(other.block()), Err(BlockValidationErr::CheckpointMismatch)));
+	}
+
+	#[test]
+	fn test_checkpoints() {
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let mut blocks: Vec<Block> = vec![];
+		for i in 0..3u32 {
+			let timestamp = 1000 * (i as u128 + 1);
+			let prev_hash = blocks.last().map_or(BlockHash::ZERO, |block| block.hash);
+			let mut block = Block::new(i, timestamp, prev_hash, vec![create_coinbase_transaction(2.0, &format!("Miner{}", i), timestamp)]);
+			block.mine(difficulty);
+			blocks.push(block);
+		}
+
+		// A node configured with a checkpoint refuses any other block at that height
+		let params = ChainParams::mainnet().with_difficulty(difficulty).with_checkpoint(0, &blocks[0].hash);
+		let mut blockchain = Blockchain::new_with_params(&params);
+		let mut fake_genesis = Block::new(0, 500, BlockHash::ZERO, vec![create_coinbase_transaction(2.0, "Mallory", 500)]);
+		fake_genesis.mine(difficulty);
+		assert!(matches!(blockchain.update_with_block(fake_genesis), Err(BlockValidationErr::CheckpointMismatch)));
+
+		for block in &blocks {
+			blockchain.update_with_block(block.clone()).unwrap();
+		}
+		assert!(matches!(blockchain.add_checkpoint(1, &blocks[2].hash), Err(BlockValidationErr::CheckpointMismatch)));
+		blockchain.add_checkpoint(1, &blocks[1].hash).unwrap();
+		assert_eq!(blockchain.last_checkpoint_height(), Some(1));
+
+		// Blocks above the last checkpoint can be rewound; the state follows
+		let removed = blockchain.rewind_to(2).unwrap();
+		assert_eq!(removed.len(), 1);
+		assert_eq!(blockchain.blocks.len(), 2);
+		assert_eq!(blockchain.balance_of(&"Miner2".to_owned()), 0.0);
+		assert!(blockchain.verify_chain().is_ok());
+		blockchain.update_with_block(removed[0].clone()).unwrap();
+
+		// ...but not the checkpointed block
+		assert!(matches!(blockchain.rewind_to(1), Err(BlockValidationErr::BelowCheckpoint)));
+		assert_eq!(blockchain.blocks.len(), 3);
+	}
+
+	#[test]
+	fn test_reorganize_follows_work() {
+		let easy = Difficulty::from_target(0x7FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let hard = Difficulty::from_target(0x1FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let mine = |index: u32, prev_hash: BlockHash, miner: &str, difficulty: Difficulty| {
+			let timestamp = 1000 * (index as u128 + 1);
+			let mut block = Block::new(index, timestamp, prev_hash, vec![create_coinbase_transaction(2.0, miner, timestamp)]);
+			block.mine(difficulty);
+			block
+		};
+
+		let mut blockchain = Blockchain::new_with_diff(easy);
+		let genesis = mine(0, BlockHash::ZERO, "Alice", easy);
+		let genesis_hash = genesis.hash;
+		blockchain.update_with_block(genesis).unwrap();
+		let mut prev_hash = genesis_hash;
+		for index in 1..3 {
+			let block = mine(index, prev_hash, "Bob", easy);
+			prev_hash = block.hash;
+			blockchain.update_with_block(block).unwrap();
+		}
+		assert_eq!(blockchain.total_work(), 3 * easy.work());
+
+		// A branch only as heavy as the blocks it replaces loses the tie
+		let carol: Vec<Block> = (1..3).map(|index| mine(index, genesis_hash, "Carol", easy)).collect();
+		assert!(matches!(blockchain.reorganize(1, carol), Err(BlockValidationErr::InsufficientWork)));
+
+		// One block at four times the difficulty outweighs two easy ones
+		let dave = mine(1, genesis_hash, "Dave", hard);
+		let removed = blockchain.reorganize(1, vec![dave]).unwrap();
+		assert_eq!(removed.len(), 2);
+		assert_eq!(blockchain.blocks.len(), 2);
+		assert_eq!(blockchain.total_work(), easy.work() + hard.work());
+		assert_eq!(blockchain.get_difficulty(), hard);
+		assert_eq!(blockchain.balance_of(&"Bob".to_owned()), 0.0);
+		assert_eq!(blockchain.balance_of(&"Dave".to_owned()), 2.0);
+		assert!(blockchain.check_invariants().is_ok());
+		assert!(blockchain.verify_chain().is_ok());
+
+		// The old branch can't come back: it is now lighter, and too easy to extend the chain
+		assert!(matches!(blockchain.reorganize(1, removed.clone()), Err(BlockValidationErr::InsufficientWork)));
+		let mut invalid = removed;
+		for index in 3..6 {
+			invalid.push(mine(index, invalid.last().unwrap().hash, "Bob", easy));
+		}
+		assert!(matches!(blockchain.reorganize(1, invalid), Err(BlockValidationErr::MismatchedDifficulty)));
+		assert_eq!(blockchain.blocks.len(), 2);
+	}
+
+	#[test]
+	fn test_compare_and_adopt() {
+		let difficulty = Difficulty::from_target(0x7FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let next_block = |chain: &Blockchain, miner: &str, value: f64| {
+			let index = chain.blocks.len() as u32;
+			let prev_hash = chain.blocks.last().map_or(BlockHash::ZERO, |block| block.hash);
+			let timestamp = 1000 * (index as u128 + 1);
+			let mut block = Block::new(index, timestamp, prev_hash, vec![create_coinbase_transaction(value, miner, timestamp)]);
+			block.mine(difficulty);
+			block
+		};
+		let extend = |chain: &mut Blockchain, count: usize, miner: &str| {
+			for _ in 0..count {
+				let block = next_block(chain, miner, 2.0);
+				chain.update_with_block(block).unwrap();
+			}
+		};
+
+		let mut ours = Blockchain::new_with_diff(difficulty);
+		extend(&mut ours, 3, "Alice");
+		let reorgs = ours.subscribe_reorgs();
+
+		// an empty chain, a copy of ours, or a chain only as heavy as ours changes nothing
+		let mut behind = Blockchain::new_with_diff(difficulty);
+		assert!(matches!(ours.compare_and_adopt(&behind), Ok(None)));
+		behind.update_with_block(ours.blocks[0].clone()).unwrap();
+		assert!(matches!(ours.compare_and_adopt(&behind), Ok(None)));
+		let mut theirs = Blockchain::new_with_diff(difficulty);
+		theirs.update_with_block(ours.blocks[0].clone()).unwrap();
+		extend(&mut theirs, 2, "Bob");
+		assert!(matches!(ours.compare_and_adopt(&theirs), Ok(None)));
+		assert!(reorgs.try_recv().is_err());
+
+		// a peer chain that breaks our rules is refused, however heavy
+		let mut invalid = Blockchain::new_with_diff(difficulty);
+		for block in &theirs.blocks {
+			invalid.update_with_block(block.clone()).unwrap();
+		}
+		invalid.coinbase_value = 50.0;
+		let block = next_block(&invalid, "Mallory", 50.0);
+		invalid.update_with_block(block).unwrap();
+		assert!(matches!(ours.compare_and_adopt(&invalid), Err(BlockValidationErr::InvalidCoinbaseTransaction)));
+		assert_eq!(ours.blocks.len(), 3);
+
+		// one more block outweighs ours
+		let removed = vec![ours.blocks[1].hash, ours.blocks[2].hash];
+		extend(&mut theirs, 1, "Bob");
+		let reorg = ours.compare_and_adopt(&theirs).unwrap().unwrap();
+		assert_eq!(reorg.fork_height, 1);
+		assert_eq!(reorg.removed, removed);
+		assert_eq!(reorg.added, theirs.blocks[1..].iter().map(|block| block.hash).collect::<Vec<_>>());
+		assert_eq!(reorg.total_work, theirs.total_work());
+		assert_eq!(reorgs.try_recv(), Ok(reorg));
+		assert_eq!(ours.balance_of(&"Alice".to_owned()), 2.0);
+		assert_eq!(ours.balance_of(&"Bob".to_owned()), 6.0);
+		assert!(ours.check_invariants().is_ok());
+
+		// extending the adopted chain is a reorganization that removes nothing
+		extend(&mut theirs, 1, "Bob");
+		let reorg = ours.compare_and_adopt(&theirs).unwrap().unwrap();
+		assert_eq!((reorg.fork_height, reorg.removed.len(), reorg.added.len()), (4, 0, 1));
+		assert_eq!(ours.blocks.len(), 5);
+	}
+
+	#[test]
+	fn test_update_with_blocks() {
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let mine = |index: u32, prev_hash: BlockHash, transactions: Vec<Transaction>| {
+			let mut block = Block::new(index, 1000 * (index as u128 + 1), prev_hash, transactions);
+			block.mine(difficulty);
+			block
+		};
+		let genesis = mine(0, BlockHash::ZERO, vec![create_coinbase_transaction(2.0, "Alice", 1000)]);
+		let payment = Transaction {
+			inputs: vec![genesis.body.transactions[0].outputs[0].clone()],
+			outputs: vec![Output { to_addr: "Bob".to_owned(), value: 2.0, timestamp: 2000, lock_until: None }],
+			witnesses: vec![],
+		};
+		let block2 = mine(1, genesis.hash, vec![create_coinbase_transaction(2.0, "Miner", 2000), payment]);
+		let block3 = mine(2, block2.hash, vec![create_coinbase_transaction(2.0, "Miner", 3000)]);
+
+		// the same state as applying the blocks one at a time
+		let mut one_by_one = Blockchain::new_with_diff(difficulty);
+		for block in [&genesis, &block2, &block3] {
+			one_by_one.update_with_block(block.clone()).unwrap();
+		}
+		let mut batched = Blockchain::new_with_diff(difficulty);
+		batched.update_with_blocks(vec![genesis.clone(), block2.clone()]).unwrap();
+		batched.update_with_blocks(vec![block3.clone()]).unwrap();
+		assert_eq!(batched.utxo_commitment(), one_by_one.utxo_commitment());
+		assert_eq!(batched.total_work(), one_by_one.total_work());
+		assert_eq!(batched.history_of(&"Alice".to_owned()).len(), 2);
+		assert_eq!(batched.balance_of(&"Bob".to_owned()), 2.0);
+		assert!(batched.check_invariants().is_ok());
+
+		// a bad block anywhere in the batch leaves the chain as it was, even after earlier blocks applied
+		let mut chain = Blockchain::new_with_diff(difficulty);
+		chain.update_with_blocks(vec![genesis.clone()]).unwrap();
+		let commitment = chain.utxo_commitment();
+		let mut tampered = block3.clone();
+		tampered.body.transactions[0].outputs[0].value = 50.0;
+		assert!(matches!(chain.update_with_blocks(vec![block2.clone(), tampered]), Err((2, BlockValidationErr::InvalidMerkleRoot))));
+		let unlinked = mine(2, genesis.hash, vec![create_coinbase_transaction(2.0, "Miner", 3000)]);
+		assert!(matches!(chain.update_with_blocks(vec![block2.clone(), unlinked]), Err((2, BlockValidationErr::MismatchedPreviousHash))));
+		assert_eq!((chain.blocks.len(), chain.utxo_commitment(), chain.total_work()), (1, commitment, difficulty.work()));
+		assert_eq!(chain.balance_of(&"Bob".to_owned()), 0.0);
+		assert!(chain.check_invariants().is_ok());
+
+		chain.update_with_blocks(vec![block2, block3]).unwrap();
+		assert_eq!(chain.utxo_commitment(), one_by_one.utxo_commitment());
+	}
+
+	#[test]
+	fn test_utxo_commitment() {
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let mut blockchain = Blockchain::new_with_diff(difficulty);
+		let empty_commitment = blockchain.utxo_commitment();
+
+		let mut genesis_block = Block::new(0, 1000, BlockHash::ZERO, vec![create_coinbase_transaction(2.0, "Alice", 1000)]);
+		genesis_block.header.utxo_commitment = Some(empty_commitment);
+		genesis_block.mine(difficulty);
+		let genesis_hash = genesis_block.hash;
+		blockchain.update_with_block(genesis_block).unwrap();
+		assert_ne!(blockchain.utxo_commitment(), empty_commitment);
+
+		// A stale commitment is rejected, the current one accepted
+		let block = |commitment: &BlockHash| {
+			let mut block = Block::new(1, 2000, genesis_hash, vec![create_coinbase_transaction(2.0, "Bob", 2000)]);
+			block.header.utxo_commitment = Some(*commitment);
+			block.mine(difficulty);
+			block
+		};
+		assert!(matches!(blockchain.update_with_block(block(&empty_commitment)), Err(BlockValidationErr::InvalidUtxoCommitment)));
+		let current = blockchain.utxo_commitment();
+		assert!(blockchain.update_with_block(block(&current)).is_ok());
+		assert!(blockchain.verify_chain().is_ok());
+	}
+
+	#[test]
+	fn test_from_utxo_snapshot() {
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let params = ChainParams::regtest().with_difficulty(difficulty);
+		let mut blockchain = Blockchain::new_with_params(&params);
+		let alice = create_coinbase_transaction(2.0, "Alice", 1000);
+		let spend = Transaction {
+			inputs: alice.outputs.clone(),
+			outputs: vec![Output { to_addr: "Carol".to_owned(), value: 1.5, timestamp: 3000, lock_until: None }],
+			witnesses: vec![],
+		};
+		let bodies = vec![
+			vec![alice],
+			vec![create_coinbase_transaction(2.0, "Bob", 2000)],
+			vec![create_coinbase_transaction(2.0, "Bob", 3000), spend],
+			vec![create_coinbase_transaction(2.0, "Bob", 4000)],
+		];
+		for (index, transactions) in bodies.into_iter().enumerate() {
+			let timestamp = 1000 * (index as u128 + 1);
+			let prev_hash = blockchain.blocks.last().map_or(BlockHash::ZERO, |block| block.hash);
+			let mut block = Block::new(index as u32, timestamp, prev_hash, transactions);
+			block.header.utxo_commitment = Some(blockchain.utxo_commitment());
+			block.mine(difficulty);
+			blockchain.update_with_block(block).unwrap();
+		}
+
+		let key = crate::node_key::NodeKey::generate();
+		let snapshot = blockchain.utxo_snapshot(1).unwrap().sign(&key);
+		assert_eq!(snapshot.outputs.len(), 2);
+		let headers: Vec<BlockHeader> = blockchain.blocks.iter().map(|block| block.header.clone()).collect();
+		let next = blockchain.blocks[2].clone();
+
+		// the block after the snapshot's must commit to exactly its outputs
+		assert!(Blockchain::from_utxo_snapshot(&params, &headers[..1], snapshot.clone(), next.clone()).is_err());
+		let mut forged = snapshot.clone();
+		forged.outputs[0].value = 100.0;
+		assert!(Blockchain::from_utxo_snapshot(&params, &headers, forged, next.clone()).err().unwrap().contains("doesn't commit"));
+		let mut bad_header = headers.clone();
+		bad_header[1].timestamp = 500;
+		assert!(Blockchain::from_utxo_snapshot(&params, &bad_header, snapshot.clone(), next.clone()).is_err());
+
+		// and is checked like any other block: linked, mined and valid against the snapshot
+		let mut unlinked = next.clone();
+		unlinked.header.prev_block_hash = BlockHash::ZERO;
+		unlinked.mine(difficulty);
+		assert!(Blockchain::from_utxo_snapshot(&params, &headers, snapshot.clone(), unlinked).err().unwrap().contains("MismatchedPreviousHash"));
+		let mut overspent = next.clone();
+		overspent.body.transactions[1].outputs[0].value = 3.0;
+		overspent.update_merkle_root();
+		overspent.mine(difficulty);
+		assert!(Blockchain::from_utxo_snapshot(&params, &headers, snapshot.clone(), overspent).is_err());
+
+		let mut synced = Blockchain::from_utxo_snapshot(&params, &headers, snapshot, next).unwrap();
+		assert_eq!((synced.blocks.len(), synced.utxo_base().map(|base| base.height)), (3, Some(1)));
+		assert_eq!(synced.total_work(), 3 * difficulty.work());
+		synced.update_with_blocks(blockchain.blocks[3..].to_vec()).unwrap();
+		assert_eq!(synced.utxo_commitment(), blockchain.utxo_commitment());
+		assert_eq!(synced.balance_of(&"Carol".to_owned()), 1.5);
+		assert!(synced.check_invariants().is_ok());
+		assert!(synced.verify_chain().is_ok());
+
+		// nothing under the snapshot can be replayed or exported
+		assert!(matches!(synced.rewind_to(1), Err(BlockValidationErr::BelowCheckpoint)));
+		assert!(synced.utxo_snapshot(0).is_err());
+		assert!(synced.export(&std::env::temp_dir().join("hourcoin-pruned.jsonl")).is_err());
+		assert_eq!(synced.rewind_to(3).unwrap().len(), 1);
+		assert_eq!(synced.utxo_snapshot(2).unwrap().commitment(), headers[3].utxo_commitment.unwrap());
+	}
+
+	#[test]
+	fn test_merkle_root_commits_to_body() {
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let mut blockchain = Blockchain::new_with_diff(difficulty);
+
+		let mut genesis_block = Block::new(0, 1000, BlockHash::ZERO, vec![create_coinbase_transaction(2.0, "Alice", 1000)]);
+		genesis_block.mine(difficulty);
+
+		// Swapping the body leaves the hash valid but breaks the header's merkle root
+		let mut swapped = genesis_block.clone();
+		swapped.body.transactions = vec![create_coinbase_transaction(2.0, "Mallory", 1000)];
+		assert!(block::check_blockhash(&swapped.hash(), difficulty));
+		assert!(matches!(blockchain.update_with_block(swapped), Err(BlockValidationErr::InvalidMerkleRoot)));
+		assert!(blockchain.update_with_block(genesis_block).is_ok());
+	}
+
+	#[test]
+	fn test_balance_and_history() {
+		let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+		let mut blockchain = Blockchain::new_with_diff(difficulty);
+
+		let timestamp = 1000;
+		let mut genesis_block = Block::new(
+			0,
+			timestamp,
+			BlockHash::ZERO,
+			vec![create_coinbase_transaction(2.0, "Alice", timestamp)],
+		);
+		genesis_block.mine(difficulty);
+		let genesis_hash = genesis_block.hash;
+		let alice_coin = genesis_block.body.transactions[0].outputs[0].clone();
+		blockchain.update_with_block(genesis_block).unwrap();
+
+		assert_eq!(blockchain.balance_of(&"Alice".to_owned()), 2.0);
+		assert_eq!(blockchain.history_of(&"Alice".to_owned()).len(), 1);
+
+		let payment = Transaction {
+			inputs: vec![alice_coin],
+			outputs: vec![
+				Output { to_addr: "Bob".to_owned(), value: 1.5, timestamp: timestamp + 1000, lock_until: None },
+				Output { to_addr: "Alice".to_owned(), value: 0.25, timestamp: timestamp + 1000, lock_until: None },
+			],
+			witnesses: vec![],
+		};
+		let mut block2 = Block::new(
+			1,
+			timestamp + 1000,
+			genesis_hash,
+			vec![create_coinbase_transaction(2.0, "Miner", timestamp + 1000), payment.clone()],
+		);
+		block2.mine(difficulty);
+		blockchain.update_with_block(block2).unwrap();
+
+		assert_eq!(blockchain.balance_of(&"Alice".to_owned()), 0.25);
+		assert_eq!(blockchain.balance_of(&"Bob".to_owned()), 1.5);
+		assert_eq!(blockchain.balance_of(&"Miner".to_owned()), 2.0);
+		assert_eq!(blockchain.balance_of(&"Nobody".to_owned()), 0.0);
+		assert_eq!(blockchain.unspent_outputs_of(&"Alice".to_owned()).iter().map(|output| output.value).collect::<Vec<f64>>(), vec![0.25]);
+		assert!(blockchain.unspent_outputs_of(&"Nobody".to_owned()).is_empty());
+		assert!(blockchain.history_of(&"Nobody".to_owned()).is_empty());
+
+		let alice = blockchain.history_of(&"Alice".to_owned());
+		assert_eq!(alice.len(), 2);
+		assert_eq!((alice[1].block_index, alice[1].received, alice[1].sent), (1, 0.25, 2.0));
+		assert_eq!(alice[1].tx_hash, payment.hash());
+		assert!(blockchain.check_invariants().is_ok());
 	}
 
 	#[test]
 	fn test_difficulty_update() {
-		let mut blockchain = Blockchain::new_with_diff(100);
+		let mut blockchain = Blockchain::new_with_diff(Difficulty::from_target(100));
 
 		// Can reduce difficulty
-		assert!(blockchain.update_difficulty(50).is_ok());
-		assert_eq!(blockchain.get_difficulty(), 50);
+		assert!(blockchain.update_difficulty(Difficulty::from_target(50)).is_ok());
+		assert_eq!(blockchain.get_difficulty(), Difficulty::from_target(50));
 
 		// Cannot increase difficulty
 		assert!(matches!(
-			blockchain.update_difficulty(100),
+			blockchain.update_difficulty(Difficulty::from_target(100)),
 			Err(BlockValidationErr::InvalidDifficultyUpdate)
 		));
 	}
@@ -0,0 +1,455 @@
+//! Canonical byte encoding of chain data
+//!
+//! Block, transaction and output hashes, and the signatures over them, are
+//! computed over this encoding. The miner, pool and peer protocols carry
+//! blocks, transactions and outputs in it (see `Canonical`), as do snapshots,
+//! binary chain files, the block store, `rawblock` publications and the FFI.
+//!
+//! A different byte for the same block is a different hash, so any change is a
+//! hard fork. Such a change bumps `CANONICAL_VERSION` and adds golden vectors
+//! for the new version next to those of the earlier ones, at the bottom of
+//! this file. Files that store canonical bytes (chain files, snapshots, the
+//! block store) record the version they were written at and refuse another.
+//!
+//! Layout rules:
+//!
+//! - Integers (`u8`, `u32`, `u64`, `u128`) are fixed width, big-endian.
+//! - `f64` is its IEEE-754 bit pattern, big-endian.
+//! - `bool` is one byte: `0x00` or `0x01`.
+//! - Strings (UTF-8) and byte strings are a `u32` length, then the bytes.
+//! - Sequences are a `u32` count, then each item. Fixed-size arrays have no count.
+//! - `Option` is `0x00` for `None`, or `0x01` followed by the value.
+//! - Enums are a one-byte tag, then the variant's fields in declaration order.
+//! - Structs are their fields in declaration order. Derived fields, such as a
+//!   block's own hash, are left out.
+
+use std::convert::TryInto;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Bumped whenever the encoding of any hashed type changes
+///
+/// Version 2 added the block's difficulty bits, and version 3 its round.
+pub const CANONICAL_VERSION: u32 = 3;
+
+/// Fail unless stored bytes were encoded at `CANONICAL_VERSION`
+pub fn check_version(version: u32) -> Result<(), String> {
+    if version != CANONICAL_VERSION {
+        return Err(format!("Encoded at canonical version {}, expected {}", version, CANONICAL_VERSION));
+    }
+    Ok(())
+}
+
+/// A type with a specified, stable byte encoding
+pub trait CanonicalSerialize {
+    /// Append the canonical encoding to `out`
+    fn write_canonical(&self, out: &mut Vec<u8>);
+
+    fn canonical_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.write_canonical(&mut out);
+        out
+    }
+}
+
+/// A type that can be decoded from its canonical encoding
+pub trait CanonicalDeserialize: Sized {
+    fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String>;
+
+    /// Decode a value that must take up all of `bytes`
+    fn from_canonical_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = CanonicalReader::new(bytes);
+        let value = Self::read_canonical(&mut reader)?;
+        reader.finish()?;
+        Ok(value)
+    }
+}
+
+/// A value that serializes as its canonical encoding, hex encoded
+///
+/// The miner, pool and peer protocols carry blocks, transactions and outputs
+/// in this form, so a peer receives the exact bytes the sender hashed.
+#[derive(Clone, PartialEq)]
+pub struct Canonical<T>(pub T);
+
+// shown as the bytes it travels as
+impl<T: CanonicalSerialize> std::fmt::Debug for Canonical<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Canonical({})", hex::encode(self.0.canonical_bytes()))
+    }
+}
+
+impl<T: CanonicalSerialize> Serialize for Canonical<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(self.0.canonical_bytes()))
+    }
+}
+
+impl<'de, T: CanonicalDeserialize> Deserialize<'de> for Canonical<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = hex::decode(String::deserialize(deserializer)?).map_err(serde::de::Error::custom)?;
+        T::from_canonical_bytes(&bytes).map(Canonical).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Cursor over canonically encoded bytes
+pub struct CanonicalReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> CanonicalReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        CanonicalReader { bytes, position: 0 }
+    }
+
+    /// Take the next `len` bytes
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.position.checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| format!("Unexpected end of data at byte {}", self.position))?;
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    /// Read an enum tag
+    pub fn tag(&mut self) -> Result<u8, String> {
+        u8::read_canonical(self)
+    }
+
+    /// Fail if any bytes are left over
+    pub fn finish(&self) -> Result<(), String> {
+        if self.position != self.bytes.len() {
+            return Err(format!("{} trailing bytes", self.bytes.len() - self.position));
+        }
+        Ok(())
+    }
+}
+
+macro_rules! canonical_int {
+    ($($int:ty),*) => {$(
+        impl CanonicalSerialize for $int {
+            fn write_canonical(&self, out: &mut Vec<u8>) {
+                out.extend(&self.to_be_bytes());
+            }
+        }
+
+        impl CanonicalDeserialize for $int {
+            fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+                Ok(<$int>::from_be_bytes(reader.take_array()?))
+            }
+        }
+    )*};
+}
+
+canonical_int!(u8, u32, u64, u128);
+
+impl CanonicalSerialize for f64 {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        out.extend(&self.to_bits().to_be_bytes());
+    }
+}
+
+impl CanonicalDeserialize for f64 {
+    fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+        Ok(f64::from_bits(u64::read_canonical(reader)?))
+    }
+}
+
+impl CanonicalSerialize for bool {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+impl CanonicalDeserialize for bool {
+    fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+        match reader.tag()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(format!("Invalid bool byte {}", other)),
+        }
+    }
+}
+
+/// Encode a length or count prefix
+pub fn write_len(len: usize, out: &mut Vec<u8>) {
+    (len as u32).write_canonical(out);
+}
+
+fn read_len(reader: &mut CanonicalReader) -> Result<usize, String> {
+    Ok(u32::read_canonical(reader)? as usize)
+}
+
+impl CanonicalSerialize for str {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        write_len(self.len(), out);
+        out.extend(self.as_bytes());
+    }
+}
+
+impl CanonicalSerialize for String {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        self.as_str().write_canonical(out);
+    }
+}
+
+impl CanonicalDeserialize for String {
+    fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+        let len = read_len(reader)?;
+        String::from_utf8(reader.take(len)?.to_vec()).map_err(|e| format!("Invalid UTF-8: {}", e))
+    }
+}
+
+// A `Vec<u8>` is a count followed by single bytes, which is the byte string layout
+impl<T: CanonicalSerialize> CanonicalSerialize for [T] {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        write_len(self.len(), out);
+        for item in self {
+            item.write_canonical(out);
+        }
+    }
+}
+
+impl<T: CanonicalSerialize> CanonicalSerialize for Vec<T> {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        self.as_slice().write_canonical(out);
+    }
+}
+
+impl<T: CanonicalDeserialize> CanonicalDeserialize for Vec<T> {
+    fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+        let len = read_len(reader)?;
+        // every item takes at least a byte, so a bogus count can't force a huge allocation
+        let mut items = Vec::with_capacity(len.min(reader.bytes.len() - reader.position));
+        for _ in 0..len {
+            items.push(T::read_canonical(reader)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T: CanonicalSerialize, const N: usize> CanonicalSerialize for [T; N] {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        for item in self {
+            item.write_canonical(out);
+        }
+    }
+}
+
+impl<T: CanonicalDeserialize, const N: usize> CanonicalDeserialize for [T; N] {
+    fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+        let items = (0..N).map(|_| T::read_canonical(reader)).collect::<Result<Vec<T>, String>>()?;
+        items.try_into().map_err(|_| "Array length mismatch".to_string())
+    }
+}
+
+// a reference encodes as the value it points to
+impl<T: CanonicalSerialize + ?Sized> CanonicalSerialize for &T {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        (**self).write_canonical(out);
+    }
+}
+
+impl<T: CanonicalSerialize> CanonicalSerialize for Option<T> {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.write_canonical(out);
+            }
+        }
+    }
+}
+
+impl<T: CanonicalDeserialize> CanonicalDeserialize for Option<T> {
+    fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+        match reader.tag()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::read_canonical(reader)?)),
+            other => Err(format!("Invalid option tag {}", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashable::Hashable;
+    use crate::htlc::HtlcContract;
+    use crate::transaction::{Output, Transaction, Witness};
+    use crate::{BlockHash, Nonce};
+    use crate::{Block, BlockHeader};
+
+    fn output(lock_until: Option<u128>) -> Output {
+        Output { to_addr: "Alice".to_owned(), value: 2.0, timestamp: 1000, lock_until }
+    }
+
+    fn transaction() -> Transaction {
+        Transaction {
+            inputs: vec![output(None)],
+            outputs: vec![output(Some(5000))],
+            witnesses: vec![Witness::Htlc {
+                contract: HtlcContract::new(vec![0xab; 2], "B", "A", 7),
+                preimage: Some(vec![0xcd]),
+            }],
+        }
+    }
+
+    fn block() -> Block {
+        let mut block = Block::new(1, 1000, BlockHash::from_bytes([0x11; 32]), vec![transaction()]);
+        block.header.nonce = Nonce(42);
+        block
+    }
+
+    // Golden vectors for CANONICAL_VERSION 3: these bytes and hashes are consensus.
+    // If a change to the code makes one of these fail, the change is a hard fork;
+    // bump the version and add vectors for it, keeping the earlier versions' below.
+
+    #[test]
+    fn test_golden_vectors_version() {
+        assert_eq!(CANONICAL_VERSION, 3, "add golden vectors for the new version");
+    }
+
+    const OUTPUT_HEX: &str = concat!(
+        "00000005", "416c696365",                          // to_addr "Alice"
+        "4000000000000000",                                // value 2.0
+        "000000000000000000000000000003e8",                // timestamp 1000
+        "00",                                              // lock_until None
+    );
+
+    #[test]
+    fn test_output_golden_vector() {
+        assert_eq!(hex::encode(output(None).canonical_bytes()), OUTPUT_HEX);
+        assert_eq!(
+            hex::encode(output(Some(5000)).canonical_bytes()),
+            OUTPUT_HEX.strip_suffix("00").unwrap().to_owned() + "01" + "00000000000000000000000000001388"
+        );
+        assert_eq!(hex::encode(output(None).hash()), "a3afb77874c4018d41975ca05b93e69ef38b63ea42e40d027b97742c19a424f2");
+    }
+
+    #[test]
+    fn test_transaction_golden_vector() {
+        let expected = String::new()
+            + "00000001" + OUTPUT_HEX                                            // inputs
+            + "00000001" + OUTPUT_HEX.strip_suffix("00").unwrap()
+                + "01" + "00000000000000000000000000001388"                      // outputs
+            + "00000001" + "01"                                                  // witnesses: Htlc
+                + "00000002" + "abab"                                            // payment_hash
+                + "00000001" + "42" + "00000001" + "41"                          // recipient, refund_address
+                + "00000000000000000000000000000007"                             // timeout
+                + "01" + "00000001" + "cd";                                      // preimage
+        assert_eq!(hex::encode(transaction().canonical_bytes()), expected);
+        assert_eq!(hex::encode(transaction().hash()), "d5705e593cb14f926595c62bb48023be6b21cabaa3018e44308318a3c2e6f78f");
+    }
+
+    #[test]
+    fn test_block_golden_vector() {
+        let expected = String::new()
+            + "00000001"                                                         // index
+            + "000000000000000000000000000003e8"                                 // timestamp
+            + "00000020" + &"11".repeat(32)                                       // prev_block_hash
+            + "00"                                                               // utxo_commitment None
+            + "00"                                                               // round None
+            + "1100ffff"                                                         // bits
+            + "000000000000002a"                                                 // nonce
+            + "00000001" + &hex::encode(transaction().canonical_bytes());        // transactions
+        assert_eq!(hex::encode(block().canonical_bytes()), expected);
+
+        // The block hash covers the header, which swaps the transactions for their merkle root
+        let header = String::new()
+            + "00000001"                                                         // index
+            + "000000000000000000000000000003e8"                                 // timestamp
+            + "00000020" + &"11".repeat(32)                                       // prev_block_hash
+            + "00"                                                               // utxo_commitment None
+            + "00"                                                               // round None
+            + "00000020" + &hex::encode(transaction().hash())                    // merkle_root of one transaction
+            + "1100ffff"                                                         // bits
+            + "000000000000002a";                                                // nonce
+        assert_eq!(hex::encode(block().header.canonical_bytes()), header);
+        assert_eq!(hex::encode(block().hash()), "79b17dec54c590c774dc53b9b17f357af2e7000dbeff0b4158113cad92398080");
+    }
+
+    // The golden block's header at `version`, or the block itself without a merkle root
+    fn block_at_version(version: u32, merkle_root: Option<&str>) -> String {
+        let mut hex = String::new()
+            + "00000001"                                                         // index
+            + "000000000000000000000000000003e8"                                 // timestamp
+            + "00000020" + &"11".repeat(32)                                       // prev_block_hash
+            + "00";                                                              // utxo_commitment None
+        if version >= 3 {
+            hex += "00";                                                         // round None
+        }
+        if let Some(merkle_root) = merkle_root {
+            hex = hex + "00000020" + merkle_root;                                // merkle_root
+        }
+        if version >= 2 {
+            hex += "1100ffff";                                                   // bits
+        }
+        hex += "000000000000002a";                                               // nonce
+        if merkle_root.is_none() {
+            hex = hex + "00000001" + &hex::encode(transaction().canonical_bytes()); // transactions
+        }
+        hex
+    }
+
+    #[test]
+    fn test_golden_vectors_across_versions() {
+        let merkle_root = hex::encode(transaction().hash());
+        let hashes = [
+            (1, "d36d60079d20038233d586783fbe874887d4b5d7068d33fff3e38b2de1ce6523"),
+            (2, "02de716a6263edf03758d06df2a917258538875308ae0919c6bb201fba355762"),
+            (3, "79b17dec54c590c774dc53b9b17f357af2e7000dbeff0b4158113cad92398080"),
+        ];
+        for (version, hash) in hashes {
+            let header = hex::decode(block_at_version(version, Some(&merkle_root))).unwrap();
+            assert_eq!(BlockHash::digest(&header).to_string(), hash, "version {}", version);
+
+            let bytes = hex::decode(block_at_version(version, None)).unwrap();
+            if version == CANONICAL_VERSION {
+                assert_eq!(bytes, block().canonical_bytes());
+                assert_eq!(header, block().header.canonical_bytes());
+            } else {
+                // Earlier bytes don't decode as the same block, which is why stored bytes carry their version
+                assert_ne!(Block::from_canonical_bytes(&bytes).map(|block| block.hash), Ok(block().hash()));
+                assert!(BlockHeader::from_canonical_bytes(&header).is_err(), "version {}", version);
+            }
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let block = block();
+        let decoded = Block::from_canonical_bytes(&block.canonical_bytes()).unwrap();
+        assert_eq!(decoded.canonical_bytes(), block.canonical_bytes());
+        assert_eq!(decoded.hash, decoded.hash());
+
+        let transaction = transaction();
+        let decoded = Transaction::from_canonical_bytes(&transaction.canonical_bytes()).unwrap();
+        assert_eq!(decoded.hash(), transaction.hash());
+    }
+
+    #[test]
+    fn test_rejects_malformed_input() {
+        let bytes = output(None).canonical_bytes();
+        assert!(Output::from_canonical_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(Output::from_canonical_bytes(&trailing).is_err());
+
+        let mut bad_option = bytes;
+        *bad_option.last_mut().unwrap() = 2;
+        assert!(Output::from_canonical_bytes(&bad_option).is_err());
+
+        // A huge count fails cleanly instead of allocating
+        assert!(Vec::<Output>::from_canonical_bytes(&[0xff; 4]).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+//! Portable chain files
+//!
+//! `Blockchain::export` writes a chain's blocks, and the difficulty history
+//! they were mined under, in one of two formats:
+//!
+//! - JSON Lines (`.jsonl`): a `ChainFileHeader` on the first line, then one
+//!   block per line in the same JSON form the REST API's `/blocks` serves
+//!   (`BlockData`). Easy to inspect with `jq`, `grep` or a spreadsheet.
+//! - Binary (anything else): the magic bytes `HRCN`, then the header and the
+//!   blocks in their canonical encoding (see the `canonical` module).
+//!
+//! Both headers record the `CANONICAL_VERSION` the blocks were hashed under,
+//! and a file from another version is refused rather than misread.
+//!
+//! Neither format is trusted on import: `Blockchain::import` runs every block
+//! through the importing chain's own rules, as if it had just been mined.
+
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::atomic_file::atomic_write;
+use crate::canonical::{self, write_len, CanonicalDeserialize, CanonicalReader, CanonicalSerialize, CANONICAL_VERSION};
+use crate::network::protocol::BlockData;
+use crate::{Block, Difficulty};
+
+/// Bumped whenever either layout changes incompatibly
+pub const CHAIN_FILE_VERSION: u32 = 3;
+
+/// First bytes of a binary chain file
+const BINARY_MAGIC: &[u8; 4] = b"HRCN";
+
+/// How a chain file is laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainFormat {
+    JsonLines,
+    Binary,
+}
+
+impl ChainFormat {
+    /// JSON Lines for `.jsonl` and `.json` paths, binary for anything else
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("jsonl") | Some("json") => ChainFormat::JsonLines,
+            _ => ChainFormat::Binary,
+        }
+    }
+}
+
+/// What precedes the blocks
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainFileHeader {
+    pub version: u32,
+    /// Encoding the blocks' hashes are computed over
+    pub canonical_version: u32,
+    /// Number of blocks that follow
+    pub blocks: u64,
+    /// First height and compact bits of every difficulty the chain has used
+    pub difficulty_history: Vec<(u64, Difficulty)>,
+}
+
+/// The contents of a chain file
+#[derive(Debug, Clone)]
+pub struct ChainFile {
+    /// First height of every difficulty the chain has used
+    pub difficulty_history: Vec<(usize, Difficulty)>,
+    pub blocks: Vec<Block>,
+}
+
+impl ChainFile {
+    /// Write the file to `path` in `format`
+    pub fn save(&self, path: &Path, format: ChainFormat) -> Result<(), String> {
+        let bytes = match format {
+            ChainFormat::JsonLines => self.to_json_lines()?,
+            ChainFormat::Binary => self.to_binary(),
+        };
+        atomic_write(path, &bytes)
+    }
+
+    /// Read a file written by `save`, in either format
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let file = if bytes.starts_with(BINARY_MAGIC) {
+            Self::from_binary(&bytes[BINARY_MAGIC.len()..])
+        } else {
+            Self::from_json_lines(&bytes)
+        };
+        file.map_err(|e| format!("Invalid chain file {}: {}", path.display(), e))
+    }
+
+    fn header(&self) -> ChainFileHeader {
+        ChainFileHeader {
+            version: CHAIN_FILE_VERSION,
+            canonical_version: CANONICAL_VERSION,
+            blocks: self.blocks.len() as u64,
+            difficulty_history: self.difficulty_history.iter()
+                .map(|(height, difficulty)| (*height as u64, *difficulty))
+                .collect(),
+        }
+    }
+
+    fn to_json_lines(&self) -> Result<Vec<u8>, String> {
+        let mut out = serde_json::to_vec(&self.header()).map_err(|e| format!("Failed to encode header: {}", e))?;
+        out.push(b'\n');
+        for block in &self.blocks {
+            serde_json::to_writer(&mut out, &BlockData::from_block(block))
+                .map_err(|e| format!("Failed to encode block {}: {}", block.header.index, e))?;
+            out.push(b'\n');
+        }
+        Ok(out)
+    }
+
+    fn from_json_lines(bytes: &[u8]) -> Result<Self, String> {
+        let text = std::str::from_utf8(bytes).map_err(|e| format!("Not UTF-8: {}", e))?;
+        let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+        let header: ChainFileHeader = serde_json::from_str(lines.next().ok_or("Empty file")?)
+            .map_err(|e| format!("Invalid header: {}", e))?;
+        let difficulty_history = Self::check_header(&header)?;
+
+        let blocks = lines.enumerate()
+            .map(|(i, line)| serde_json::from_str::<BlockData>(line)
+                .map_err(|e| format!("Invalid block on line {}: {}", i + 2, e))
+                .and_then(|data| data.to_block()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if blocks.len() as u64 != header.blocks {
+            return Err(format!("Header promises {} blocks, found {}", header.blocks, blocks.len()));
+        }
+        Ok(ChainFile { difficulty_history, blocks })
+    }
+
+    fn to_binary(&self) -> Vec<u8> {
+        let mut out = BINARY_MAGIC.to_vec();
+        CHAIN_FILE_VERSION.write_canonical(&mut out);
+        CANONICAL_VERSION.write_canonical(&mut out);
+        write_len(self.difficulty_history.len(), &mut out);
+        for (height, difficulty) in &self.difficulty_history {
+            (*height as u64).write_canonical(&mut out);
+            difficulty.write_canonical(&mut out);
+        }
+        self.blocks.write_canonical(&mut out);
+        out
+    }
+
+    fn from_binary(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = CanonicalReader::new(bytes);
+        let version = u32::read_canonical(&mut reader)?;
+        if version != CHAIN_FILE_VERSION {
+            return Err(format!("Version {}, expected {}", version, CHAIN_FILE_VERSION));
+        }
+        canonical::check_version(u32::read_canonical(&mut reader)?)?;
+        let count = u32::read_canonical(&mut reader)?;
+        let difficulty_history = (0..count)
+            .map(|_| Ok((u64::read_canonical(&mut reader)? as usize, Difficulty::read_canonical(&mut reader)?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        let blocks = Vec::<Block>::read_canonical(&mut reader)?;
+        reader.finish()?;
+        Ok(ChainFile { difficulty_history, blocks })
+    }
+
+    fn check_header(header: &ChainFileHeader) -> Result<Vec<(usize, Difficulty)>, String> {
+        if header.version != CHAIN_FILE_VERSION {
+            return Err(format!("Version {}, expected {}", header.version, CHAIN_FILE_VERSION));
+        }
+        canonical::check_version(header.canonical_version)?;
+        Ok(header.difficulty_history.iter()
+            .map(|(height, difficulty)| (*height as usize, *difficulty))
+            .collect())
+    }
+}
@@ -0,0 +1,281 @@
+//! Chain parameters for Hourcoin networks
+//!
+//! Consensus constants (lockout length, tonce window, coinbase value, time
+//! tolerance, difficulty) used to be hardcoded in each module. They now live
+//! in `ChainParams`, which is threaded through `Blockchain`, `Validator`,
+//! `TonceChallenge` and `TimeSync`. Presets exist for mainnet, testnet and a
+//! fast regtest mode meant for integration tests.
+//!
+//! A network may also fix its first block with a `GenesisConfig`. The block
+//! is derived from the config alone, so every node builds the same one (see
+//! `Blockchain::with_genesis` and the `genesis` binary, which mines it).
+
+use serde::{Deserialize, Serialize};
+use crate::transaction::{Output, Transaction, Witness};
+use crate::{Block, BlockHash, Difficulty, Hashable, Nonce};
+use crate::tonce::check_tonce_bits;
+
+/// Which network a set of parameters belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+impl std::str::FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            "regtest" => Ok(Network::Regtest),
+            other => Err(format!("Unknown network '{}'", other)),
+        }
+    }
+}
+
+/// Consensus parameters shared by every node on a network
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainParams {
+    pub network: Network,
+    /// How long a miner must wait after an accepted block (milliseconds)
+    pub lockout_duration_ms: u128,
+    /// How long the tonce divisibility challenge lasts before it drops to 1 (milliseconds)
+    pub tonce_challenge_duration_ms: u128,
+    /// Number of hash bits used for the tonce (1 to `MAX_TONCE_BITS`, giving a divisor up to 2^bits - 1)
+    pub tonce_bits: u8,
+    /// Value of the coinbase output in every block
+    pub coinbase_value: f64,
+    /// Maximum allowed deviation into the future from trusted time (milliseconds)
+    pub time_tolerance_ms: u128,
+    /// Maximum allowed age of a block timestamp, to absorb network delays (milliseconds)
+    pub max_timestamp_age_ms: u128,
+    /// Proof-of-work target for blocks
+    pub difficulty: Difficulty,
+    /// Fraction of an unspent output's value lost per hour since its timestamp (0 disables demurrage)
+    #[serde(default)]
+    pub demurrage_rate_per_hour: f64,
+    /// Number of recent blocks whose median timestamp a new block must exceed
+    #[serde(default = "default_median_time_span")]
+    pub median_time_span: usize,
+    /// Blocks the chain must contain: a syncing node rejects any other history at these heights
+    #[serde(default)]
+    pub checkpoints: Vec<Checkpoint>,
+    /// The network's first block, if it is fixed in advance
+    #[serde(default)]
+    pub genesis: Option<GenesisConfig>,
+    /// Whether blocks must commit to their validator round, so replay checks the tonce too
+    #[serde(default)]
+    pub hybrid_consensus: bool,
+}
+
+/// Everything needed to rebuild a network's genesis block
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GenesisConfig {
+    /// Block timestamp (TAI milliseconds), also given to every premine output
+    pub timestamp: u128,
+    /// Text stamped into the coinbase, e.g. a headline proving the block wasn't mined earlier
+    pub message: String,
+    /// Outputs paid by the genesis coinbase instead of the usual coinbase value
+    pub premine: Vec<PremineOutput>,
+    /// Difficulty the block was mined at, recorded in its header
+    #[serde(default)]
+    pub bits: Difficulty,
+    /// Header nonce that makes the block meet the difficulty (found by `mine`)
+    #[serde(default)]
+    pub nonce: Nonce,
+}
+
+/// One premine payment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PremineOutput {
+    pub address: String,
+    pub value: f64,
+}
+
+impl GenesisConfig {
+    pub fn new(timestamp: u128, message: impl Into<String>) -> Self {
+        GenesisConfig { timestamp, message: message.into(), premine: vec![], bits: Difficulty::EASIEST, nonce: Nonce(0) }
+    }
+
+    /// Same config with an extra premine output
+    pub fn with_premine(mut self, address: impl Into<String>, value: f64) -> Self {
+        self.premine.push(PremineOutput { address: address.into(), value });
+        self
+    }
+
+    /// Total value the genesis coinbase pays
+    pub fn premine_total(&self) -> f64 {
+        self.premine.iter().map(|output| output.value).sum()
+    }
+
+    /// The genesis block this config describes
+    pub fn block(&self) -> Block {
+        let coinbase = Transaction {
+            inputs: vec![],
+            outputs: self.premine.iter()
+                .map(|output| Output {
+                    to_addr: output.address.clone(),
+                    value: output.value,
+                    timestamp: self.timestamp,
+                    lock_until: None,
+                })
+                .collect(),
+            witnesses: vec![Witness::Message(self.message.clone())],
+        };
+        let mut block = Block::new(0, self.timestamp, BlockHash::ZERO, vec![coinbase]);
+        block.header.bits = self.bits;
+        block.header.nonce = self.nonce;
+        block.hash = block.hash();
+        block
+    }
+
+    /// Find a nonce that makes the block meet `difficulty`
+    pub fn mine(&mut self, difficulty: Difficulty) {
+        let mut block = self.block();
+        block.mine(difficulty);
+        self.bits = difficulty;
+        self.nonce = block.header.nonce;
+    }
+}
+
+/// A block height and the hash the block there must have
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub height: u32,
+    pub hash: BlockHash,
+}
+
+fn default_median_time_span() -> usize {
+    11
+}
+
+impl ChainParams {
+    /// Production parameters: 1-hour lockout, 60 second tonce window
+    pub fn mainnet() -> Self {
+        ChainParams {
+            network: Network::Mainnet,
+            lockout_duration_ms: 3_600_000,
+            tonce_challenge_duration_ms: 60_000,
+            tonce_bits: 5,
+            coinbase_value: 2.0,
+            time_tolerance_ms: 500,
+            max_timestamp_age_ms: 300_000,
+            difficulty: Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF),
+            demurrage_rate_per_hour: 0.0,
+            median_time_span: default_median_time_span(),
+            checkpoints: vec![],
+            genesis: None,
+            hybrid_consensus: false,
+        }
+    }
+
+    /// Public test network: same rules as mainnet with shorter waits
+    pub fn testnet() -> Self {
+        ChainParams {
+            network: Network::Testnet,
+            lockout_duration_ms: 600_000,
+            tonce_challenge_duration_ms: 30_000,
+            time_tolerance_ms: 1_000,
+            ..Self::mainnet()
+        }
+    }
+
+    /// Local regression testing: near-instant rounds and trivial difficulty
+    pub fn regtest() -> Self {
+        ChainParams {
+            network: Network::Regtest,
+            lockout_duration_ms: 5_000,
+            tonce_challenge_duration_ms: 1_000,
+            time_tolerance_ms: 5_000,
+            difficulty: Difficulty::from_target(0x0FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF),
+            ..Self::mainnet()
+        }
+    }
+
+    /// Preset parameters for a network
+    pub fn for_network(network: Network) -> Self {
+        match network {
+            Network::Mainnet => Self::mainnet(),
+            Network::Testnet => Self::testnet(),
+            Network::Regtest => Self::regtest(),
+        }
+    }
+
+    /// Same parameters with a different difficulty
+    pub fn with_difficulty(mut self, difficulty: Difficulty) -> Self {
+        self.difficulty = difficulty;
+        self
+    }
+
+    /// Same parameters with an extra checkpoint
+    pub fn with_checkpoint(mut self, height: u32, hash: &BlockHash) -> Self {
+        self.checkpoints.retain(|checkpoint| checkpoint.height != height);
+        self.checkpoints.push(Checkpoint { height, hash: *hash });
+        self.checkpoints.sort_by_key(|checkpoint| checkpoint.height);
+        self
+    }
+
+    /// Same parameters with a fixed genesis block
+    pub fn with_genesis(mut self, genesis: GenesisConfig) -> Self {
+        self.genesis = Some(genesis);
+        self
+    }
+
+    /// Same parameters with demurrage enabled at the given hourly rate
+    pub fn with_demurrage(mut self, rate_per_hour: f64) -> Self {
+        self.demurrage_rate_per_hour = rate_per_hour;
+        self
+    }
+
+    /// Same parameters with hybrid proof of work and proof of time consensus
+    pub fn with_hybrid_consensus(mut self) -> Self {
+        self.hybrid_consensus = true;
+        self
+    }
+
+    /// Fails if the parameters can't be used, such as a tonce wider than `MAX_TONCE_BITS`
+    pub fn check(&self) -> Result<(), String> {
+        check_tonce_bits(self.tonce_bits)
+    }
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        Self::mainnet()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mainnet_matches_original_constants() {
+        let params = ChainParams::mainnet();
+        assert_eq!(params.lockout_duration_ms, 3_600_000);
+        assert_eq!(params.tonce_challenge_duration_ms, 60_000);
+        assert_eq!(params.tonce_bits, 5);
+        assert_eq!(params.coinbase_value, 2.0);
+        assert_eq!(params.time_tolerance_ms, 500);
+    }
+
+    #[test]
+    fn test_regtest_is_fast() {
+        let mainnet = ChainParams::mainnet();
+        let regtest = ChainParams::regtest();
+        assert!(regtest.lockout_duration_ms < mainnet.lockout_duration_ms);
+        assert!(regtest.tonce_challenge_duration_ms < mainnet.tonce_challenge_duration_ms);
+        assert!(regtest.difficulty.is_easier_than(&mainnet.difficulty));
+    }
+
+    #[test]
+    fn test_network_from_str() {
+        assert_eq!("testnet".parse::<Network>(), Ok(Network::Testnet));
+        assert_eq!("RegTest".parse::<Network>(), Ok(Network::Regtest));
+        assert!("devnet".parse::<Network>().is_err());
+        assert_eq!(ChainParams::for_network(Network::Regtest), ChainParams::regtest());
+    }
+}
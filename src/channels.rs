@@ -0,0 +1,621 @@
+//! Bidirectional payment channels
+//!
+//! Two parties lock hours into a funding output paying to the channel address
+//! (`chan` + hex hash of the channel terms). After that they pay each other by
+//! exchanging `SignedState`s off chain. A state is a balance split both parties
+//! have signed with their sr25519 keys, and each new state has a higher
+//! sequence number.
+//!
+//! Each state also carries a revocation hash from each party. When a party
+//! moves to a newer state, it hands over the secret for its old revocation
+//! hash. A channel closes in one of two ways:
+//!
+//! - **Cooperative:** both sign a final `closing` state, and both payouts are
+//!   spendable immediately.
+//! - **Unilateral:** one party publishes the latest state alone. The
+//!   counterparty is paid directly. The closer's own payout goes into an HTLC
+//!   locked to the closer's revocation hash. The closer can refund it to itself
+//!   after the dispute period. If the state had been revoked, the counterparty
+//!   holds the preimage and can take the whole payout (see `penalize`).
+//!
+//! Block validation enforces these spending rules through a
+//! `Witness::ChannelClose` on the closing transaction.
+
+use std::collections::BTreeMap;
+use sha2::{Digest, Sha256};
+use crate::BlockHash;
+use crate::canonical::{CanonicalDeserialize, CanonicalReader, CanonicalSerialize};
+use crate::hashable::Hashable;
+use crate::htlc::{self, HtlcContract};
+use crate::node_key::{verify_signature, NodeKey};
+use crate::transaction::{Output, Transaction, Witness};
+use crate::u64_bytes;
+
+/// Prefix of addresses that pay to a channel
+pub const CHANNEL_ADDRESS_PREFIX: &str = "chan";
+
+/// Signing context for channel states
+const STATE_SIGNING_CONTEXT: &[u8] = b"hourcoin-channel-state";
+
+/// One side of a channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelParty {
+    /// Where this party's balance is paid on close
+    pub address: String,
+    /// Key that signs channel states
+    pub public_key: Vec<u8>,
+}
+
+/// The fixed terms of a channel, committed to by its address
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelTerms {
+    pub parties: [ChannelParty; 2],
+    /// How long a unilateral closer must wait to claim its own payout (milliseconds)
+    pub dispute_period_ms: u128,
+}
+
+impl ChannelTerms {
+    /// The address a funding output for this channel pays to
+    pub fn address(&self) -> String {
+        format!("{}{}", CHANNEL_ADDRESS_PREFIX, hex::encode(self.hash()))
+    }
+
+    /// An output locking `value` into the channel
+    pub fn funding_output(&self, value: f64, timestamp: u128) -> Output {
+        Output {
+            to_addr: self.address(),
+            value,
+            timestamp,
+            lock_until: None,
+        }
+    }
+
+    /// The outputs a close must pay for a state, in party order (zero balances are omitted)
+    pub fn close_outputs(&self, state: &ChannelState, unilateral: Option<&UnilateralClose>, timestamp: u128) -> Vec<Output> {
+        (0..2)
+            .filter(|&i| state.balances[i] > 0.0)
+            .map(|i| {
+                let to_addr = match unilateral {
+                    Some(close) if close.closer == i => self.penalty_contract(state, close).address(),
+                    _ => self.parties[i].address.clone(),
+                };
+                Output { to_addr, value: state.balances[i], timestamp, lock_until: None }
+            })
+            .collect()
+    }
+
+    /// HTLC holding a unilateral closer's payout: refundable after the timeout,
+    /// or claimable at once by the counterparty with the closer's revocation secret
+    pub fn penalty_contract(&self, state: &ChannelState, close: &UnilateralClose) -> HtlcContract {
+        HtlcContract::new(
+            state.revocation_hashes[close.closer].clone(),
+            &self.parties[1 - close.closer].address,
+            &self.parties[close.closer].address,
+            close.timeout,
+        )
+    }
+}
+
+impl CanonicalSerialize for ChannelParty {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        self.address.write_canonical(out);
+        self.public_key.write_canonical(out);
+    }
+}
+
+impl CanonicalDeserialize for ChannelParty {
+    fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+        Ok(ChannelParty {
+            address: String::read_canonical(reader)?,
+            public_key: Vec::read_canonical(reader)?,
+        })
+    }
+}
+
+impl CanonicalSerialize for ChannelTerms {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        self.parties.write_canonical(out);
+        self.dispute_period_ms.write_canonical(out);
+    }
+}
+
+impl CanonicalDeserialize for ChannelTerms {
+    fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+        Ok(ChannelTerms {
+            parties: <[ChannelParty; 2]>::read_canonical(reader)?,
+            dispute_period_ms: u128::read_canonical(reader)?,
+        })
+    }
+}
+
+impl Hashable for ChannelTerms {
+    type Hash = BlockHash;
+
+    fn bytes(&self) -> Vec<u8> {
+        self.canonical_bytes()
+    }
+}
+
+/// A balance split between the two parties
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelState {
+    pub sequence: u64,
+    pub balances: [f64; 2],
+    /// Each party's revocation hash for this state (empty until that party fills it in)
+    pub revocation_hashes: [Vec<u8>; 2],
+    /// A final state for a cooperative close
+    pub closing: bool,
+}
+
+impl ChannelState {
+    fn signing_bytes(&self, terms: &ChannelTerms) -> Vec<u8> {
+        let mut bytes = terms.hash().to_vec();
+        self.write_canonical(&mut bytes);
+        bytes
+    }
+}
+
+impl CanonicalSerialize for ChannelState {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        self.sequence.write_canonical(out);
+        self.balances.write_canonical(out);
+        self.revocation_hashes.write_canonical(out);
+        self.closing.write_canonical(out);
+    }
+}
+
+impl CanonicalDeserialize for ChannelState {
+    fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+        Ok(ChannelState {
+            sequence: u64::read_canonical(reader)?,
+            balances: <[f64; 2]>::read_canonical(reader)?,
+            revocation_hashes: <[Vec<u8>; 2]>::read_canonical(reader)?,
+            closing: bool::read_canonical(reader)?,
+        })
+    }
+}
+
+/// A channel state with the parties' signatures (empty until signed)
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedState {
+    pub state: ChannelState,
+    pub signatures: [Vec<u8>; 2],
+}
+
+impl SignedState {
+    /// Check that both parties signed this state
+    pub fn verify(&self, terms: &ChannelTerms) -> Result<(), String> {
+        let message = self.state.signing_bytes(terms);
+        for (party, signature) in terms.parties.iter().zip(&self.signatures) {
+            verify_signature(&party.public_key, STATE_SIGNING_CONTEXT, &message, signature)
+                .map_err(|e| format!("Channel state not signed by {}: {}", party.address, e))?;
+        }
+        Ok(())
+    }
+}
+
+impl CanonicalSerialize for SignedState {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        self.state.write_canonical(out);
+        self.signatures.write_canonical(out);
+    }
+}
+
+impl CanonicalDeserialize for SignedState {
+    fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+        Ok(SignedState {
+            state: ChannelState::read_canonical(reader)?,
+            signatures: <[Vec<u8>; 2]>::read_canonical(reader)?,
+        })
+    }
+}
+
+/// Which party closed a channel alone, and when its own payout unlocks
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnilateralClose {
+    pub closer: usize,
+    /// Refund timeout of the closer's payout; at least the dispute period after the closing block
+    pub timeout: u128,
+}
+
+impl CanonicalSerialize for UnilateralClose {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        (self.closer as u32).write_canonical(out);
+        self.timeout.write_canonical(out);
+    }
+}
+
+impl CanonicalDeserialize for UnilateralClose {
+    fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+        Ok(UnilateralClose {
+            closer: u32::read_canonical(reader)? as usize,
+            timeout: u128::read_canonical(reader)?,
+        })
+    }
+}
+
+/// One party's view of a channel
+pub struct PaymentChannel {
+    terms: ChannelTerms,
+    /// Our index in `terms.parties`
+    me: usize,
+    key: NodeKey,
+    funding_output: Output,
+    /// Latest state both parties signed (sequence 0 is unsigned until the opening exchange)
+    current: SignedState,
+    /// A state we proposed or countersigned that is not yet in effect
+    pending: Option<SignedState>,
+    /// Our previous state, until the counterparty revokes theirs
+    superseded: Option<ChannelState>,
+    /// Counterparty revocation secrets for superseded states, by sequence
+    revoked: BTreeMap<u64, Vec<u8>>,
+}
+
+impl PaymentChannel {
+    /// Track a channel funded by `funding_output` with the opening balances
+    ///
+    /// Before broadcasting the funding transaction, the parties should sign the opening
+    /// balances with a zero payment (`propose_payment(0.0)`). That way either party
+    /// can close the channel alone.
+    pub fn open(terms: ChannelTerms, key: NodeKey, funding_output: Output, balances: [f64; 2]) -> Result<Self, String> {
+        let me = terms.parties.iter()
+            .position(|party| party.public_key == key.public_key())
+            .ok_or("Key does not belong to either channel party")?;
+        if funding_output.to_addr != terms.address() {
+            return Err("Funding output does not pay to the channel".to_string());
+        }
+        if balances.iter().any(|b| *b < 0.0) || balances[0] + balances[1] > funding_output.value {
+            return Err("Opening balances exceed the funding output".to_string());
+        }
+
+        let current = SignedState {
+            state: ChannelState {
+                sequence: 0,
+                balances,
+                revocation_hashes: [vec![], vec![]],
+                closing: false,
+            },
+            signatures: [vec![], vec![]],
+        };
+
+        Ok(PaymentChannel { terms, me, key, funding_output, current, pending: None, superseded: None, revoked: BTreeMap::new() })
+    }
+
+    pub fn terms(&self) -> &ChannelTerms {
+        &self.terms
+    }
+
+    /// Our current balance
+    pub fn balance(&self) -> f64 {
+        self.current.state.balances[self.me]
+    }
+
+    /// The latest state both parties signed
+    pub fn current_state(&self) -> &SignedState {
+        &self.current
+    }
+
+    fn other(&self) -> usize {
+        1 - self.me
+    }
+
+    /// Secret behind our revocation hash for a state
+    fn revocation_secret(&self, sequence: u64) -> Vec<u8> {
+        let mut preimage = self.key.secret_bytes().to_vec();
+        preimage.extend(self.terms.hash().as_bytes());
+        preimage.extend(&u64_bytes(&sequence));
+        Sha256::digest(&preimage).to_vec()
+    }
+
+    fn sign_state(&self, state: &ChannelState) -> Vec<u8> {
+        self.key.sign(STATE_SIGNING_CONTEXT, &state.signing_bytes(&self.terms))
+    }
+
+    fn next_state(&self, balances: [f64; 2], closing: bool) -> ChannelState {
+        let sequence = self.current.state.sequence + 1;
+        let mut revocation_hashes = [vec![], vec![]];
+        revocation_hashes[self.me] = htlc::payment_hash(&self.revocation_secret(sequence));
+        ChannelState { sequence, balances, revocation_hashes, closing }
+    }
+
+    /// Step 1 (payer): propose a state moving `amount` to the counterparty
+    pub fn propose_payment(&mut self, amount: f64) -> Result<ChannelState, String> {
+        if self.current.state.closing {
+            return Err("Channel is closing".to_string());
+        }
+        if amount < 0.0 || amount > self.balance() {
+            return Err(format!("Cannot pay {} from a balance of {}", amount, self.balance()));
+        }
+
+        let mut balances = self.current.state.balances;
+        balances[self.me] -= amount;
+        balances[self.other()] += amount;
+
+        let state = self.next_state(balances, false);
+        self.pending = Some(SignedState { state: state.clone(), signatures: [vec![], vec![]] });
+        Ok(state)
+    }
+
+    /// Step 1 (either party): propose a final state for a cooperative close
+    pub fn propose_close(&mut self) -> Result<ChannelState, String> {
+        let state = self.next_state(self.current.state.balances, true);
+        self.pending = Some(SignedState { state: state.clone(), signatures: [vec![], vec![]] });
+        Ok(state)
+    }
+
+    /// Step 2 (payee): check a proposal never lowers our balance, add our revocation hash and sign
+    pub fn countersign(&mut self, mut proposal: ChannelState) -> Result<SignedState, String> {
+        let current = &self.current.state;
+        if proposal.sequence != current.sequence + 1 {
+            return Err(format!("Expected sequence {}, got {}", current.sequence + 1, proposal.sequence));
+        }
+        if proposal.revocation_hashes[self.other()].is_empty() {
+            return Err("Proposal is missing the counterparty's revocation hash".to_string());
+        }
+        if proposal.balances[self.me] < current.balances[self.me]
+            || proposal.balances[0] + proposal.balances[1] > current.balances[0] + current.balances[1] + 1e-9
+            || proposal.balances.iter().any(|b| *b < 0.0) {
+            return Err("Proposal takes value from us".to_string());
+        }
+        if proposal.closing && proposal.balances != current.balances {
+            return Err("A closing state must keep the current balances".to_string());
+        }
+
+        proposal.revocation_hashes[self.me] = htlc::payment_hash(&self.revocation_secret(proposal.sequence));
+        let mut signatures = [vec![], vec![]];
+        signatures[self.me] = self.sign_state(&proposal);
+
+        let signed = SignedState { state: proposal, signatures };
+        self.pending = Some(signed.clone());
+        Ok(signed)
+    }
+
+    /// Step 3 (payer): sign the countersigned state, adopt it, and revoke our previous state
+    pub fn finalize(&mut self, mut signed: SignedState) -> Result<(SignedState, Vec<u8>), String> {
+        let proposed = self.pending.take().ok_or("No proposal outstanding")?;
+        let mut expected = proposed.state;
+        expected.revocation_hashes[self.other()] = signed.state.revocation_hashes[self.other()].clone();
+        if signed.state != expected {
+            return Err("Countersigned state differs from our proposal".to_string());
+        }
+
+        signed.signatures[self.me] = self.sign_state(&signed.state);
+        signed.verify(&self.terms)?;
+
+        let revocation = self.revocation_secret(self.current.state.sequence);
+        self.superseded = Some(std::mem::replace(&mut self.current, signed.clone()).state);
+        Ok((signed, revocation))
+    }
+
+    /// Step 4 (payee): adopt the fully signed state given the payer's revocation secret,
+    /// returning our own revocation secret for the previous state
+    pub fn complete(&mut self, signed: SignedState, revocation: &[u8]) -> Result<Vec<u8>, String> {
+        let countersigned = self.pending.take().ok_or("No countersigned state outstanding")?;
+        if signed.state != countersigned.state {
+            return Err("Signed state differs from the one we countersigned".to_string());
+        }
+        signed.verify(&self.terms)?;
+        let previous = self.current.state.clone();
+        self.store_revocation(&previous, revocation)?;
+
+        let own_revocation = self.revocation_secret(self.current.state.sequence);
+        self.current = signed;
+        Ok(own_revocation)
+    }
+
+    /// Step 5 (payer): store the payee's revocation secret for the previous state
+    pub fn receive_revocation(&mut self, revocation: &[u8]) -> Result<(), String> {
+        let superseded = self.superseded.take().ok_or("No revocation outstanding")?;
+        self.store_revocation(&superseded, revocation)
+    }
+
+    fn store_revocation(&mut self, state: &ChannelState, revocation: &[u8]) -> Result<(), String> {
+        let expected = &state.revocation_hashes[self.other()];
+        // The unsigned opening state has nothing to revoke
+        if expected.is_empty() {
+            return Ok(());
+        }
+        if htlc::payment_hash(revocation) != *expected {
+            return Err("Revocation secret does not match the state".to_string());
+        }
+        self.revoked.insert(state.sequence, revocation.to_vec());
+        Ok(())
+    }
+
+    /// Spend the funding output with a fully signed closing state
+    pub fn cooperative_close(&self, timestamp: u128) -> Result<Transaction, String> {
+        if !self.current.state.closing {
+            return Err("Current state is not a closing state".to_string());
+        }
+        self.close(None, timestamp)
+    }
+
+    /// Spend the funding output with the latest state without the counterparty
+    pub fn unilateral_close(&self, timestamp: u128) -> Result<Transaction, String> {
+        let close = UnilateralClose {
+            closer: self.me,
+            timeout: timestamp + self.terms.dispute_period_ms,
+        };
+        self.close(Some(close), timestamp)
+    }
+
+    fn close(&self, unilateral: Option<UnilateralClose>, timestamp: u128) -> Result<Transaction, String> {
+        self.current.verify(&self.terms)?;
+
+        Ok(Transaction {
+            inputs: vec![self.funding_output.clone()],
+            outputs: self.terms.close_outputs(&self.current.state, unilateral.as_ref(), timestamp),
+            witnesses: vec![Witness::ChannelClose {
+                terms: self.terms.clone(),
+                state: self.current.clone(),
+                unilateral,
+            }],
+        })
+    }
+
+    /// If the counterparty closed with a state we hold a revocation secret for, claim its payout
+    ///
+    /// The claim is signed with `payout_key`, the key of our party's address.
+    pub fn penalize(&self, close_tx: &Transaction, payout_key: &NodeKey, timestamp: u128) -> Option<Transaction> {
+        let (state, close) = close_tx.witnesses.iter().find_map(|witness| match witness {
+            Witness::ChannelClose { terms, state, unilateral: Some(close) }
+                if *terms == self.terms && close.closer == self.other() => Some((state, close)),
+            _ => None,
+        })?;
+        let secret = self.revoked.get(&state.state.sequence)?;
+
+        let contract = self.terms.penalty_contract(&state.state, close);
+        let payout = close_tx.outputs.iter().find(|output| output.to_addr == contract.address())?;
+        htlc::redeem_with_preimage(payout, &contract, secret, payout_key, timestamp).ok()
+    }
+}
+
+/// Whether an address pays to a channel
+pub fn is_channel_address(address: &str) -> bool {
+    address.len() == CHANNEL_ADDRESS_PREFIX.len() + 64
+        && address.strip_prefix(CHANNEL_ADDRESS_PREFIX)
+            .is_some_and(|hash| hash.bytes().all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase()))
+}
+
+/// Check that an input paying to a channel address is closed according to the channel's rules
+pub(crate) fn check_input(input: &Output, transaction: &Transaction, block_timestamp: u128) -> Result<(), String> {
+    let (terms, state, unilateral) = transaction.witnesses.iter()
+        .find_map(|witness| match witness {
+            Witness::ChannelClose { terms, state, unilateral } if terms.address() == input.to_addr => {
+                Some((terms, state, unilateral))
+            }
+            _ => None,
+        })
+        .ok_or_else(|| format!("No witness for channel input {}", input.to_addr))?;
+
+    state.verify(terms)?;
+    if state.state.balances.iter().any(|b| *b < 0.0) || state.state.balances[0] + state.state.balances[1] > input.value {
+        return Err("Channel state pays out more than the funding output".to_string());
+    }
+
+    match unilateral {
+        None if !state.state.closing => return Err("Cooperative close requires a closing state".to_string()),
+        Some(close) if close.closer > 1 => return Err("Unknown closing party".to_string()),
+        Some(close) if close.timeout < block_timestamp + terms.dispute_period_ms => {
+            return Err("Closer's payout unlocks before the dispute period ends".to_string());
+        }
+        _ => {}
+    }
+
+    let expected = terms.close_outputs(&state.state, unilateral.as_ref(), block_timestamp);
+    let matches = transaction.outputs.len() == expected.len()
+        && transaction.outputs.iter().zip(&expected)
+            .all(|(output, expected)| output.to_addr == expected.to_addr && output.value == expected.value);
+    if !matches {
+        return Err("Close does not pay the channel state".to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::address_from_public_key;
+
+    fn open_channel() -> (PaymentChannel, PaymentChannel) {
+        let alice_key = NodeKey::generate();
+        let bob_key = NodeKey::generate();
+        // each party is paid to the address of its state key
+        let party = |key: &NodeKey| ChannelParty { address: address_from_public_key(&key.public_key()), public_key: key.public_key() };
+        let terms = ChannelTerms {
+            parties: [party(&alice_key), party(&bob_key)],
+            dispute_period_ms: 3_600_000,
+        };
+        let funding = terms.funding_output(2.0, 1000);
+        assert!(is_channel_address(&funding.to_addr));
+
+        let mut alice = PaymentChannel::open(terms.clone(), alice_key, funding.clone(), [2.0, 0.0]).unwrap();
+        let mut bob = PaymentChannel::open(terms, bob_key, funding, [2.0, 0.0]).unwrap();
+        pay(&mut alice, &mut bob, 0.0);
+        (alice, bob)
+    }
+
+    /// Run the full update exchange
+    fn pay(payer: &mut PaymentChannel, payee: &mut PaymentChannel, amount: f64) {
+        let proposal = payer.propose_payment(amount).unwrap();
+        let countersigned = payee.countersign(proposal).unwrap();
+        let (signed, revocation) = payer.finalize(countersigned).unwrap();
+        let payee_revocation = payee.complete(signed, &revocation).unwrap();
+        payer.receive_revocation(&payee_revocation).unwrap();
+    }
+
+    #[test]
+    fn test_is_channel_address() {
+        assert!(!is_channel_address("chan"));
+        assert!(!is_channel_address("channel_fund"));
+        assert!(!is_channel_address(&format!("chan{}", "A".repeat(64))));
+        assert!(is_channel_address(&format!("chan{}", "a".repeat(64))));
+    }
+
+    #[test]
+    fn test_payments_update_balances() {
+        let (mut alice, mut bob) = open_channel();
+
+        pay(&mut alice, &mut bob, 0.5);
+        pay(&mut bob, &mut alice, 0.25);
+        assert_eq!((alice.balance(), bob.balance()), (1.75, 0.25));
+        assert_eq!(alice.current_state(), bob.current_state());
+        assert_eq!(alice.current_state().state.sequence, 3);
+
+        // Neither side can be made to pay more than it holds, or sign away its own value
+        assert!(bob.propose_payment(1.0).is_err());
+        let mut theft = alice.propose_payment(0.0).unwrap();
+        theft.balances = [2.0, 0.0];
+        assert!(bob.countersign(theft).is_err());
+    }
+
+    #[test]
+    fn test_cooperative_close() {
+        let (mut alice, mut bob) = open_channel();
+        pay(&mut alice, &mut bob, 0.5);
+
+        assert!(alice.cooperative_close(5000).is_err());
+
+        let proposal = alice.propose_close().unwrap();
+        let countersigned = bob.countersign(proposal).unwrap();
+        let (signed, revocation) = alice.finalize(countersigned).unwrap();
+        bob.complete(signed, &revocation).unwrap();
+
+        let close = bob.cooperative_close(5000).unwrap();
+        assert_eq!(check_input(&close.inputs[0], &close, 5000), Ok(()));
+        let parties = &bob.terms().parties;
+        assert_eq!(close.outputs.iter().map(|o| (o.to_addr.as_str(), o.value)).collect::<Vec<_>>(),
+            vec![(parties[0].address.as_str(), 1.5), (parties[1].address.as_str(), 0.5)]);
+
+        // Changing the split breaks the close
+        let mut tampered = close.clone();
+        tampered.outputs[0].value = 2.0;
+        tampered.outputs.pop();
+        assert!(check_input(&tampered.inputs[0], &tampered, 5000).is_err());
+    }
+
+    #[test]
+    fn test_unilateral_close_and_penalty() {
+        let (mut alice, mut bob) = open_channel();
+        pay(&mut alice, &mut bob, 0.5);
+
+        // Alice's payout waits out the dispute period; Bob's is paid directly
+        let honest = alice.unilateral_close(5000).unwrap();
+        assert_eq!(check_input(&honest.inputs[0], &honest, 5000), Ok(()));
+        let bob_address = bob.terms().parties[1].address.clone();
+        assert_eq!(honest.outputs[1].to_addr, bob_address);
+        assert!(htlc::is_htlc_address(&honest.outputs[0].to_addr));
+        assert!(bob.penalize(&honest, &bob.key, 6000).is_none());
+
+        // A closing block later than the timestamp used to build the close shortens the dispute period
+        assert!(check_input(&honest.inputs[0], &honest, 6000).is_err());
+
+        // Once Alice has paid Bob again, closing with the old state forfeits her payout
+        pay(&mut alice, &mut bob, 0.5);
+        let penalty = bob.penalize(&honest, &bob.key, 6000).expect("old state is revoked");
+        assert_eq!(penalty.outputs[0].to_addr, bob_address);
+        assert_eq!(penalty.outputs[0].value, 1.5);
+        assert_eq!(htlc::check_input(&honest.outputs[0], &penalty, 1.5, 6000), Ok(()));
+    }
+}
@@ -0,0 +1,65 @@
+//! Commit–reveal for miner timestamps
+//!
+//! While the tonce challenge is active, a miner could otherwise keep trying
+//! timestamps right up to the deadline. With commit–reveal enabled, a miner
+//! first sends `SHA-256(miner_id || timestamp || salt)` to the validator, and
+//! only a block whose timestamp and salt open that commitment is accepted.
+//!
+//! The validator keeps the two phases apart using `committed_at`: a reveal
+//! must come at least half the challenge after its commitment, and a
+//! commitment made during the challenge must be revealed before it expires,
+//! so commits for the challenge close at its midpoint. Each miner gets one
+//! commitment per round and none after their first submission, so a rejected
+//! reveal can't be followed by a commitment to a different timestamp.
+
+use sha2::{Digest, Sha256};
+use crate::u128_bytes;
+
+/// Length of the random salt miners mix into their commitment
+pub const SALT_LEN: usize = 32;
+
+/// Hash commitment to a candidate block timestamp
+pub fn timestamp_commitment(miner_id: &str, timestamp: u128, salt: &[u8]) -> Vec<u8> {
+    let mut preimage = miner_id.as_bytes().to_vec();
+    preimage.extend(&u128_bytes(&timestamp));
+    preimage.extend(salt);
+    Sha256::digest(&preimage).to_vec()
+}
+
+/// Fresh random salt for a commitment
+pub fn generate_salt() -> Vec<u8> {
+    (0..SALT_LEN).map(|_| rand::random::<u8>()).collect()
+}
+
+/// A commitment held by the validator for the current round
+#[derive(Debug, Clone)]
+pub struct TimestampCommitment {
+    pub commitment: Vec<u8>,
+    pub committed_at: u128,
+}
+
+impl TimestampCommitment {
+    /// Check that a revealed timestamp and salt open this commitment
+    pub fn opens_with(&self, miner_id: &str, timestamp: u128, salt: &[u8]) -> bool {
+        timestamp_commitment(miner_id, timestamp, salt) == self.commitment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commitment_opens_only_with_original_values() {
+        let salt = generate_salt();
+        let commitment = TimestampCommitment {
+            commitment: timestamp_commitment("alice", 1000, &salt),
+            committed_at: 900,
+        };
+
+        assert!(commitment.opens_with("alice", 1000, &salt));
+        assert!(!commitment.opens_with("alice", 1001, &salt));
+        assert!(!commitment.opens_with("bob", 1000, &salt));
+        assert!(!commitment.opens_with("alice", 1000, &generate_salt()));
+    }
+}
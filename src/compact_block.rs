@@ -0,0 +1,218 @@
+//! Compact block relay
+//!
+//! Peer validators have usually seen most of a block's transactions already,
+//! in other miners' submissions for the same round. Instead of the full block
+//! a proposer sends a `CompactBlock`: the header, a short ID for each
+//! transaction, and the few transactions the peer can't have (always the
+//! coinbase). The peer rebuilds the block from its `TransactionPool` and asks
+//! for the transactions it is missing by position.
+//!
+//! Short IDs are keyed by the block hash, so a collision can't be prepared in
+//! advance for every block. A rebuilt block is checked against the header's
+//! merkle root, so a collision costs a round trip, not a wrong block.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use sha2::{Digest, Sha256};
+use crate::{Block, BlockBody, BlockHash, BlockHeader, Txid};
+use crate::hashable::Hashable;
+use crate::transaction::Transaction;
+
+/// Transactions a pool remembers before dropping the oldest
+pub const TRANSACTION_POOL_CAPACITY: usize = 10_000;
+
+/// Short ID of a transaction within the block with hash `block_hash`
+pub fn short_tx_id(block_hash: &BlockHash, tx_hash: &Txid) -> u64 {
+    let mut preimage = block_hash.to_vec();
+    preimage.extend(tx_hash.as_bytes());
+    let digest = Sha256::digest(&preimage);
+    u64::from_le_bytes([digest[0], digest[1], digest[2], digest[3], digest[4], digest[5], digest[6], digest[7]])
+}
+
+/// Recently seen transactions, by hash
+pub struct TransactionPool {
+    transactions: HashMap<Txid, Transaction>,
+    /// Hashes in insertion order, oldest first
+    order: VecDeque<Txid>,
+    capacity: usize,
+}
+
+impl TransactionPool {
+    pub fn new(capacity: usize) -> Self {
+        TransactionPool {
+            transactions: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Remember a transaction, forgetting the oldest past capacity
+    pub fn insert(&mut self, transaction: Transaction) {
+        let hash = transaction.hash();
+        if self.transactions.contains_key(&hash) {
+            return;
+        }
+        self.transactions.insert(hash, transaction);
+        self.order.push_back(hash);
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.transactions.remove(&oldest);
+            }
+        }
+    }
+
+    /// Remember every transaction in a block except the coinbase
+    pub fn insert_block(&mut self, block: &Block) {
+        for transaction in block.body.transactions.iter().skip(1) {
+            self.insert(transaction.clone());
+        }
+    }
+
+    pub fn get(&self, tx_hash: &Txid) -> Option<&Transaction> {
+        self.transactions.get(tx_hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    /// Pooled transactions by their short ID in the block with hash `block_hash`
+    ///
+    /// IDs shared by more than one pooled transaction are left out.
+    fn by_short_id(&self, block_hash: &BlockHash) -> HashMap<u64, &Transaction> {
+        let mut found = HashMap::new();
+        let mut ambiguous = HashSet::new();
+        for (tx_hash, transaction) in &self.transactions {
+            let id = short_tx_id(block_hash, tx_hash);
+            if found.insert(id, transaction).is_some() {
+                ambiguous.insert(id);
+            }
+        }
+        found.retain(|id, _| !ambiguous.contains(id));
+        found
+    }
+}
+
+/// A block as the header, short transaction IDs and the transactions sent in full
+#[derive(Clone)]
+pub struct CompactBlock {
+    pub header: BlockHeader,
+    /// Short ID of every transaction, in block order
+    pub short_ids: Vec<u64>,
+    /// Transactions sent in full, by position in the block
+    pub prefilled: Vec<(u32, Transaction)>,
+}
+
+impl CompactBlock {
+    /// Compact form of `block`, sending the coinbase in full
+    pub fn from_block(block: &Block) -> Self {
+        Self::from_block_with(block, &[0])
+    }
+
+    /// Compact form of `block`, also sending the transactions at `positions` in full
+    pub fn from_block_with(block: &Block, positions: &[u32]) -> Self {
+        let block_hash = block.header.hash();
+        let short_ids = block.transaction_hashes().iter()
+            .map(|tx_hash| short_tx_id(&block_hash, tx_hash))
+            .collect();
+        let mut positions = positions.to_vec();
+        positions.sort_unstable();
+        positions.dedup();
+        let prefilled = positions.into_iter()
+            .filter_map(|position| Some((position, block.body.transactions.get(position as usize)?.clone())))
+            .collect();
+
+        CompactBlock { header: block.header.clone(), short_ids, prefilled }
+    }
+
+    /// Rebuild the block from prefilled and pooled transactions
+    ///
+    /// Returns the positions of the transactions that couldn't be found if the
+    /// block can't be rebuilt. If the pool supplied a transaction that turns
+    /// out wrong, every position not sent in full is requested.
+    pub fn reconstruct(&self, pool: &TransactionPool) -> Result<Block, Vec<u32>> {
+        let block_hash = self.header.hash();
+        let mut transactions: Vec<Option<Transaction>> = vec![None; self.short_ids.len()];
+        for (position, transaction) in &self.prefilled {
+            if let Some(slot) = transactions.get_mut(*position as usize) {
+                *slot = Some(transaction.clone());
+            }
+        }
+
+        let pooled = pool.by_short_id(&block_hash);
+        let mut missing = vec![];
+        for (position, slot) in transactions.iter_mut().enumerate() {
+            if slot.is_none() {
+                match pooled.get(&self.short_ids[position]) {
+                    Some(transaction) => *slot = Some((*transaction).clone()),
+                    None => missing.push(position as u32),
+                }
+            }
+        }
+        if !missing.is_empty() {
+            return Err(missing);
+        }
+
+        let body = BlockBody { transactions: transactions.into_iter().flatten().collect() };
+        if body.merkle_root() != self.header.merkle_root {
+            let prefilled: HashSet<u32> = self.prefilled.iter().map(|(position, _)| *position).collect();
+            return Err((0..self.short_ids.len() as u32).filter(|position| !prefilled.contains(position)).collect());
+        }
+        Ok(Block::from_parts(self.header.clone(), body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Output;
+
+    fn transaction(to_addr: &str, inputs: usize) -> Transaction {
+        let output = |value| Output { to_addr: to_addr.to_owned(), value, timestamp: 1000, lock_until: None };
+        Transaction {
+            inputs: (0..inputs).map(|i| output(i as f64)).collect(),
+            outputs: vec![output(1.0)],
+            witnesses: vec![],
+        }
+    }
+
+    #[test]
+    fn test_compact_block_reconstruction() {
+        let block = Block::new(3, 1000, BlockHash::from_bytes([7; 32]), vec![
+            transaction("Miner", 0),
+            transaction("Alice", 1),
+            transaction("Bob", 2),
+        ]);
+        let compact = CompactBlock::from_block(&block);
+        assert_eq!(compact.prefilled.len(), 1);
+
+        // A peer that has only seen Alice's transaction asks for Bob's
+        let mut pool = TransactionPool::new(TRANSACTION_POOL_CAPACITY);
+        pool.insert(transaction("Alice", 1));
+        pool.insert(transaction("Carol", 1));
+        assert_eq!(compact.reconstruct(&pool).err(), Some(vec![2]));
+
+        // ...and rebuilds the block once it is sent in full
+        let rebuilt = CompactBlock::from_block_with(&block, &[0, 2]).reconstruct(&pool).unwrap();
+        assert_eq!(rebuilt.hash, block.hash());
+        assert_eq!(rebuilt.transaction_hashes(), block.transaction_hashes());
+
+        pool.insert_block(&block);
+        assert!(compact.reconstruct(&pool).is_ok());
+    }
+
+    #[test]
+    fn test_transaction_pool_capacity() {
+        let mut pool = TransactionPool::new(2);
+        for name in ["Alice", "Bob", "Carol"] {
+            pool.insert(transaction(name, 1));
+        }
+        pool.insert(transaction("Carol", 1));
+        assert_eq!(pool.len(), 2);
+        assert!(pool.get(&transaction("Alice", 1).hash()).is_none());
+        assert!(pool.get(&transaction("Carol", 1).hash()).is_some());
+    }
+}
@@ -0,0 +1,336 @@
+//! Multi-validator agreement on block acceptance
+//!
+//! A single timekeeper is a single point of trust. With consensus enabled, a
+//! set of validators (e.g. three timekeepers needing 2-of-3) each sign an
+//! `AcceptanceVote` for the block they accepted at a height. A block is only
+//! canonical once a `QuorumCertificate` holds votes from a quorum of distinct
+//! members for the same block hash.
+//!
+//! Every member votes at most once per height, and the quorum must be a strict
+//! majority, so two conflicting blocks can never both gather a quorum.
+//!
+//! A validator applies a block to its chain as soon as it accepts it, so the
+//! blocks past its highest certificate are provisional: a certificate for a
+//! different block at a height it holds rolls its chain back to that height
+//! (`Validator::add_certificate`), and the certified block is then synced
+//! from peers.
+
+use std::collections::{BTreeMap, HashMap};
+use crate::node_key::{verify_signature, NodeKey};
+use crate::{u32_bytes, BlockHash};
+
+/// Signing context for acceptance votes
+const VOTE_SIGNING_CONTEXT: &[u8] = b"hourcoin-acceptance-vote";
+
+/// The validators taking part in consensus and how many must agree
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidatorSet {
+    members: Vec<Vec<u8>>,
+    quorum: usize,
+}
+
+impl ValidatorSet {
+    /// Create a validator set from member public keys and a quorum size
+    pub fn new(members: Vec<Vec<u8>>, quorum: usize) -> Result<Self, String> {
+        if members.is_empty() {
+            return Err("Validator set must have at least one member".to_string());
+        }
+        for (i, member) in members.iter().enumerate() {
+            if members[..i].contains(member) {
+                return Err(format!("Duplicate validator {}", hex::encode(member)));
+            }
+        }
+        if quorum > members.len() {
+            return Err(format!("Quorum {} is invalid for {} validators", quorum, members.len()));
+        }
+        // two quorums must share a member, or conflicting blocks could both be finalized
+        if quorum * 2 <= members.len() {
+            return Err(format!("Quorum {} is not a majority of {} validators", quorum, members.len()));
+        }
+
+        Ok(ValidatorSet { members, quorum })
+    }
+
+    /// Create a validator set where a strict majority must agree (2-of-3, 3-of-4, ...)
+    pub fn with_majority(members: Vec<Vec<u8>>) -> Result<Self, String> {
+        let quorum = members.len() / 2 + 1;
+        Self::new(members, quorum)
+    }
+
+    /// Whether a public key belongs to the set
+    pub fn contains(&self, public_key: &[u8]) -> bool {
+        self.members.iter().any(|member| member == public_key)
+    }
+
+    /// Member public keys
+    pub fn members(&self) -> &[Vec<u8>] {
+        &self.members
+    }
+
+    /// Number of distinct votes needed to finalize a block
+    pub fn quorum(&self) -> usize {
+        self.quorum
+    }
+}
+
+/// A validator's signed statement that it accepted a block at a height
+#[derive(Debug, Clone, PartialEq)]
+pub struct AcceptanceVote {
+    pub block_hash: BlockHash,
+    pub height: u32,
+    /// Public key of the voting validator
+    pub voter: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl AcceptanceVote {
+    /// Sign a vote for a block
+    pub fn sign(node_key: &NodeKey, block_hash: &BlockHash, height: u32) -> Self {
+        AcceptanceVote {
+            block_hash: *block_hash,
+            height,
+            voter: node_key.public_key(),
+            signature: node_key.sign(VOTE_SIGNING_CONTEXT, &Self::signing_bytes(block_hash, height)),
+        }
+    }
+
+    /// Check the vote's signature
+    pub fn verify(&self) -> Result<(), String> {
+        let message = Self::signing_bytes(&self.block_hash, self.height);
+        verify_signature(&self.voter, VOTE_SIGNING_CONTEXT, &message, &self.signature)
+    }
+
+    fn signing_bytes(block_hash: &BlockHash, height: u32) -> Vec<u8> {
+        let mut bytes = u32_bytes(&height).to_vec();
+        bytes.extend(block_hash.as_bytes());
+        bytes
+    }
+}
+
+/// Votes from a quorum of validators finalizing one block
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuorumCertificate {
+    pub block_hash: BlockHash,
+    pub height: u32,
+    pub votes: Vec<AcceptanceVote>,
+}
+
+impl QuorumCertificate {
+    /// Check that a quorum of distinct members signed this block
+    pub fn verify(&self, validator_set: &ValidatorSet) -> Result<(), String> {
+        let mut voters: Vec<&[u8]> = vec![];
+
+        for vote in &self.votes {
+            if vote.block_hash != self.block_hash || vote.height != self.height {
+                return Err("Certificate contains a vote for a different block".to_string());
+            }
+            if !validator_set.contains(&vote.voter) {
+                return Err(format!("Vote from non-member {}", hex::encode(&vote.voter)));
+            }
+            if voters.contains(&vote.voter.as_slice()) {
+                continue;
+            }
+            vote.verify()?;
+            voters.push(&vote.voter);
+        }
+
+        if voters.len() < validator_set.quorum() {
+            return Err(format!("Certificate has {} of {} required votes", voters.len(), validator_set.quorum()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Vote collection and finality tracking for one validator
+#[derive(Debug)]
+pub struct Consensus {
+    validator_set: ValidatorSet,
+    /// Votes seen per height, keyed by voter
+    votes: HashMap<u32, HashMap<Vec<u8>, AcceptanceVote>>,
+    /// Certificates for finalized heights
+    finalized: BTreeMap<u32, QuorumCertificate>,
+}
+
+impl Consensus {
+    /// Start tracking consensus for a validator set
+    pub fn new(validator_set: ValidatorSet) -> Self {
+        Consensus {
+            validator_set,
+            votes: HashMap::new(),
+            finalized: BTreeMap::new(),
+        }
+    }
+
+    /// The validators taking part in consensus
+    pub fn validator_set(&self) -> &ValidatorSet {
+        &self.validator_set
+    }
+
+    /// Sign and record our own vote for a block
+    ///
+    /// Refuses to vote for a second block at a height we already voted on.
+    pub fn vote_for(&mut self, node_key: &NodeKey, block_hash: &BlockHash, height: u32) -> Result<AcceptanceVote, String> {
+        let vote = AcceptanceVote::sign(node_key, block_hash, height);
+        self.add_vote(vote.clone())?;
+        Ok(vote)
+    }
+
+    /// Record a vote, returning a certificate if it completes a quorum
+    pub fn add_vote(&mut self, vote: AcceptanceVote) -> Result<Option<QuorumCertificate>, String> {
+        if !self.validator_set.contains(&vote.voter) {
+            return Err(format!("Vote from non-member {}", hex::encode(&vote.voter)));
+        }
+        vote.verify()?;
+
+        let height = vote.height;
+        let block_hash = vote.block_hash;
+        let votes = self.votes.entry(height).or_default();
+
+        match votes.get(&vote.voter) {
+            Some(existing) if existing.block_hash != vote.block_hash => {
+                return Err(format!("Validator {} already voted for a different block at height {}",
+                    hex::encode(&vote.voter), height));
+            }
+            Some(_) => return Ok(None),
+            None => {
+                votes.insert(vote.voter.clone(), vote);
+            }
+        }
+
+        if self.finalized.contains_key(&height) {
+            return Ok(None);
+        }
+
+        let matching: Vec<AcceptanceVote> = votes.values()
+            .filter(|vote| vote.block_hash == block_hash)
+            .cloned()
+            .collect();
+        if matching.len() < self.validator_set.quorum() {
+            return Ok(None);
+        }
+
+        let certificate = QuorumCertificate { block_hash, height, votes: matching };
+        self.finalized.insert(height, certificate.clone());
+        Ok(Some(certificate))
+    }
+
+    /// Record a certificate produced by another validator
+    pub fn add_certificate(&mut self, certificate: QuorumCertificate) -> Result<(), String> {
+        certificate.verify(&self.validator_set)?;
+
+        if let Some(existing) = self.finalized.get(&certificate.height) {
+            if existing.block_hash != certificate.block_hash {
+                return Err(format!("Conflicting certificates at height {}", certificate.height));
+            }
+            return Ok(());
+        }
+
+        self.finalized.insert(certificate.height, certificate);
+        Ok(())
+    }
+
+    /// Whether the block at a height has been finalized
+    pub fn is_finalized(&self, height: u32) -> bool {
+        self.finalized.contains_key(&height)
+    }
+
+    /// Certificate finalizing a height, if any
+    pub fn certificate(&self, height: u32) -> Option<&QuorumCertificate> {
+        self.finalized.get(&height)
+    }
+
+    /// Highest finalized height
+    pub fn finalized_height(&self) -> Option<u32> {
+        self.finalized.keys().next_back().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn three_validators() -> (Vec<NodeKey>, ValidatorSet) {
+        let keys: Vec<NodeKey> = (0..3).map(|_| NodeKey::generate()).collect();
+        let set = ValidatorSet::with_majority(keys.iter().map(|k| k.public_key()).collect()).unwrap();
+        (keys, set)
+    }
+
+    #[test]
+    fn test_validator_set() {
+        let (keys, set) = three_validators();
+        assert_eq!(set.quorum(), 2);
+        assert!(set.contains(&keys[0].public_key()));
+        assert!(!set.contains(&NodeKey::generate().public_key()));
+
+        assert!(ValidatorSet::new(vec![], 1).is_err());
+        assert!(ValidatorSet::new(vec![vec![1; 32]], 2).is_err());
+        assert!(ValidatorSet::new(vec![vec![1; 32], vec![1; 32]], 1).is_err());
+
+        // Quorums must be a strict majority
+        let members: Vec<Vec<u8>> = keys.iter().map(|k| k.public_key()).collect();
+        assert!(ValidatorSet::new(members.clone(), 1).is_err());
+        assert!(ValidatorSet::new(members[..2].to_vec(), 1).is_err());
+        assert!(ValidatorSet::new(members[..2].to_vec(), 2).is_ok());
+        assert!(ValidatorSet::new(members, 3).is_ok());
+    }
+
+    #[test]
+    fn test_two_of_three_finalizes() {
+        let (keys, set) = three_validators();
+        let mut consensus = Consensus::new(set.clone());
+
+        assert_eq!(consensus.vote_for(&keys[0], &BlockHash::from_bytes([1; 32]), 0).map(|v| v.voter), Ok(keys[0].public_key()));
+        assert!(!consensus.is_finalized(0));
+
+        // A repeated vote does not count twice
+        assert_eq!(consensus.add_vote(AcceptanceVote::sign(&keys[0], &BlockHash::from_bytes([1; 32]), 0)), Ok(None));
+        assert!(!consensus.is_finalized(0));
+
+        let certificate = consensus.add_vote(AcceptanceVote::sign(&keys[1], &BlockHash::from_bytes([1; 32]), 0))
+            .unwrap()
+            .expect("quorum reached");
+        assert!(consensus.is_finalized(0));
+        assert_eq!(consensus.finalized_height(), Some(0));
+        assert_eq!(certificate.verify(&set), Ok(()));
+
+        // Another validator can adopt the certificate
+        let mut other = Consensus::new(set);
+        assert_eq!(other.add_certificate(certificate), Ok(()));
+        assert!(other.is_finalized(0));
+    }
+
+    #[test]
+    fn test_conflicting_votes() {
+        let (keys, set) = three_validators();
+        let mut consensus = Consensus::new(set);
+
+        consensus.vote_for(&keys[0], &BlockHash::from_bytes([1; 32]), 0).unwrap();
+        assert!(consensus.vote_for(&keys[0], &BlockHash::from_bytes([2; 32]), 0).is_err());
+
+        // Split votes never reach quorum
+        assert_eq!(consensus.add_vote(AcceptanceVote::sign(&keys[1], &BlockHash::from_bytes([2; 32]), 0)), Ok(None));
+        assert!(!consensus.is_finalized(0));
+
+        assert!(consensus.add_vote(AcceptanceVote::sign(&NodeKey::generate(), &BlockHash::from_bytes([1; 32]), 0)).is_err());
+    }
+
+    #[test]
+    fn test_certificate_requires_quorum() {
+        let (keys, set) = three_validators();
+
+        let short = QuorumCertificate {
+            block_hash: BlockHash::from_bytes([1; 32]),
+            height: 0,
+            votes: vec![AcceptanceVote::sign(&keys[0], &BlockHash::from_bytes([1; 32]), 0), AcceptanceVote::sign(&keys[0], &BlockHash::from_bytes([1; 32]), 0)],
+        };
+        assert!(short.verify(&set).is_err());
+
+        let mixed = QuorumCertificate {
+            block_hash: BlockHash::from_bytes([1; 32]),
+            height: 0,
+            votes: vec![AcceptanceVote::sign(&keys[0], &BlockHash::from_bytes([1; 32]), 0), AcceptanceVote::sign(&keys[1], &BlockHash::from_bytes([2; 32]), 0)],
+        };
+        assert!(mixed.verify(&set).is_err());
+    }
+}
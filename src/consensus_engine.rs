@@ -0,0 +1,320 @@
+//! Pluggable block consensus
+//!
+//! Every `Blockchain` checks the same structural rules: indexes, hash links,
+//! timestamps, the coinbase and every transaction. What else a block must
+//! prove to be accepted is up to the chain's `ConsensusEngine`.
+//!
+//! - `ProofOfWork` only asks that the block's hash meet the difficulty. It is
+//!   the default, and what the validator's chain uses. The validator runs the
+//!   tonce challenge itself, with its own clock and optional VRF randomness.
+//! - `ProofOfTime` also asks that the block's timestamp pass the tonce derived
+//!   from the previous block, as `spv::verify_header` does. Since the tonce
+//!   only depends on the chain, any node can replay a proof of time chain
+//!   without the validator.
+//! - `Hybrid` also asks that the block commit to the validator round it was
+//!   mined in, with that round's tonce, and that its timestamp pass it. The
+//!   validator checks submissions against the round it is running, and any
+//!   node replaying the chain checks the committed tonce against the previous
+//!   block, so the proof of time lives in the blocks themselves. Chains whose
+//!   `ChainParams` set `hybrid_consensus` use it from the start.
+//!
+//! Researchers can compare the two on the same blocks, or implement the trait
+//! for a new scheme and give it to `Blockchain::set_consensus_engine`.
+
+use crate::blockchain::BlockValidationErr;
+use crate::chain_params::ChainParams;
+use crate::hashable::Hashable;
+use crate::tonce::{passes_tonce, TonceChallenge};
+use crate::{Block, Blockchain, Difficulty};
+
+/// What the next block of a chain has to meet
+#[derive(Debug, Clone)]
+pub struct Challenge {
+    /// Target the block's hash must meet
+    pub difficulty: Difficulty,
+    /// Tonce challenge the block's timestamp must pass, if the scheme has one
+    pub tonce: Option<TonceChallenge>,
+}
+
+/// A scheme deciding which blocks may extend a chain
+pub trait ConsensusEngine: Send + Sync {
+    /// Short name, for logs and comparisons
+    fn name(&self) -> &'static str;
+
+    /// Check what the scheme asks of `block`, the next block of `chain`
+    ///
+    /// Runs after the block's index, checkpoint and difficulty bits have
+    /// been checked, and before its links, timestamps and transactions.
+    fn validate_block(&self, chain: &Blockchain, block: &Block) -> Result<(), BlockValidationErr>;
+
+    /// What the next block of `chain` must meet
+    fn next_challenge(&self, chain: &Blockchain) -> Challenge;
+
+    /// Called once `block` has passed every check and joined the chain
+    fn on_block_accepted(&mut self, _block: &Block) {}
+
+    /// The engine as it was before seeing any block
+    ///
+    /// Chains replay their blocks from genesis to rewind or verify
+    /// themselves; the replay runs on this copy.
+    fn reset(&self) -> Box<dyn ConsensusEngine>;
+}
+
+/// Blocks need only meet the difficulty
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ProofOfWork;
+
+impl ConsensusEngine for ProofOfWork {
+    fn name(&self) -> &'static str {
+        "proof-of-work"
+    }
+
+    fn validate_block(&self, chain: &Blockchain, block: &Block) -> Result<(), BlockValidationErr> {
+        if chain.get_difficulty().is_met_by(&block.hash()) {
+            Ok(())
+        } else {
+            Err(BlockValidationErr::InvalidHash)
+        }
+    }
+
+    fn next_challenge(&self, chain: &Blockchain) -> Challenge {
+        Challenge { difficulty: chain.get_difficulty(), tonce: None }
+    }
+
+    fn reset(&self) -> Box<dyn ConsensusEngine> {
+        Box::new(*self)
+    }
+}
+
+/// Blocks must meet the difficulty and pass the tonce of the block before them
+///
+/// The tonce comes from the previous block's hash and timestamp under the
+/// network's tonce parameters. A block timestamped after the challenge window
+/// passes any tonce, as it does on the validator. The genesis block has no
+/// tonce to pass.
+#[derive(Debug, Clone)]
+pub struct ProofOfTime {
+    params: ChainParams,
+}
+
+impl ProofOfTime {
+    /// Tonce challenges sized by `params` (bit-width and window length)
+    pub fn new(params: &ChainParams) -> Self {
+        ProofOfTime { params: params.clone() }
+    }
+
+    /// The tonce the next block of `chain` must pass
+    pub fn tonce_for(&self, chain: &Blockchain) -> Option<TonceChallenge> {
+        chain.blocks.last()
+            .map(|tip| TonceChallenge::new_with_params(&tip.hash, tip.header.timestamp, &self.params))
+    }
+}
+
+impl ConsensusEngine for ProofOfTime {
+    fn name(&self) -> &'static str {
+        "proof-of-time"
+    }
+
+    fn validate_block(&self, chain: &Blockchain, block: &Block) -> Result<(), BlockValidationErr> {
+        ProofOfWork.validate_block(chain, block)?;
+        let timestamp = block.header.timestamp;
+        if self.tonce_for(chain).is_some_and(|mut tonce| !tonce.validate_timestamp(timestamp, timestamp)) {
+            return Err(BlockValidationErr::FailedTonceChallenge);
+        }
+        Ok(())
+    }
+
+    fn next_challenge(&self, chain: &Blockchain) -> Challenge {
+        Challenge { difficulty: chain.get_difficulty(), tonce: self.tonce_for(chain) }
+    }
+
+    fn reset(&self) -> Box<dyn ConsensusEngine> {
+        Box::new(self.clone())
+    }
+}
+
+/// Blocks must meet the difficulty and commit to the round they were mined in
+///
+/// The committed tonce must be the one derived from the previous block, or 1
+/// if the block is timestamped after the challenge window, when the validator
+/// drops the challenge. Either way the block's timestamp must pass it. The
+/// round's start time isn't checked on replay, since it comes from the
+/// validator's clock; it names the round the validator checked the block
+/// against. The genesis block needs no commitment.
+#[derive(Debug, Clone)]
+pub struct Hybrid {
+    proof_of_time: ProofOfTime,
+}
+
+impl Hybrid {
+    /// Tonce challenges sized by `params` (bit-width and window length)
+    pub fn new(params: &ChainParams) -> Self {
+        Hybrid { proof_of_time: ProofOfTime::new(params) }
+    }
+}
+
+impl ConsensusEngine for Hybrid {
+    fn name(&self) -> &'static str {
+        "hybrid"
+    }
+
+    fn validate_block(&self, chain: &Blockchain, block: &Block) -> Result<(), BlockValidationErr> {
+        ProofOfWork.validate_block(chain, block)?;
+        let Some(tonce) = self.proof_of_time.tonce_for(chain) else {
+            return Ok(());
+        };
+        let round = block.header.round.as_ref().ok_or(BlockValidationErr::MissingRoundCommitment)?;
+        let timestamp = block.header.timestamp;
+        let expired = timestamp.saturating_sub(tonce.prev_block_timestamp) >= tonce.challenge_duration_ms;
+        if round.tonce != tonce.tonce && !(expired && round.tonce == 1) {
+            return Err(BlockValidationErr::InvalidRoundCommitment);
+        }
+        if !passes_tonce(round.tonce, timestamp) {
+            return Err(BlockValidationErr::FailedTonceChallenge);
+        }
+        Ok(())
+    }
+
+    fn next_challenge(&self, chain: &Blockchain) -> Challenge {
+        self.proof_of_time.next_challenge(chain)
+    }
+
+    fn reset(&self) -> Box<dyn ConsensusEngine> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tx_builder::TransactionBuilder;
+    use crate::{BlockHash, RoundCommitment};
+
+    fn mined_block(chain: &Blockchain, params: &ChainParams, timestamp: u128) -> Block {
+        committed_block(chain, params, timestamp, None)
+    }
+
+    fn committed_block(chain: &Blockchain, params: &ChainParams, timestamp: u128, round: Option<RoundCommitment>) -> Block {
+        let coinbase = TransactionBuilder::new(timestamp).pay_to("alice", params.coinbase_value).build_coinbase().unwrap();
+        let prev_hash = chain.blocks.last().map_or(BlockHash::ZERO, |tip| tip.hash);
+        let mut block = Block::new(chain.blocks.len() as u32, timestamp, prev_hash, vec![coinbase]);
+        block.header.round = round;
+        block.mine(chain.get_difficulty());
+        block
+    }
+
+    #[test]
+    fn test_proof_of_time_requires_the_tonce() {
+        let mut params = ChainParams::regtest();
+        params.tonce_bits = 8;
+        let mut chain = Blockchain::new_with_params(&params);
+        chain.set_consensus_engine(ProofOfTime::new(&params));
+        assert_eq!(chain.consensus_engine().name(), "proof-of-time");
+        assert!(chain.next_challenge().tonce.is_none());
+        chain.update_with_block(mined_block(&chain, &params, 1_000_000)).unwrap();
+
+        let tonce = chain.next_challenge().tonce.unwrap();
+        assert!(tonce.tonce > 1);
+        let window = tonce.prev_block_timestamp + 1..tonce.prev_block_timestamp + tonce.challenge_duration_ms;
+        let failing = window.clone().find(|timestamp| !passes_tonce(tonce.tonce, *timestamp)).unwrap();
+        let passing = window.clone().find(|timestamp| passes_tonce(tonce.tonce, *timestamp)).unwrap();
+
+        // proof of work alone takes either block
+        let mut pow = Blockchain::new_with_params(&params);
+        pow.update_with_block(chain.blocks[0].clone()).unwrap();
+        assert!(pow.next_challenge().tonce.is_none());
+        assert!(pow.update_with_block(mined_block(&pow, &params, failing)).is_ok());
+
+        assert!(matches!(chain.update_with_block(mined_block(&chain, &params, failing)),
+            Err(BlockValidationErr::FailedTonceChallenge)));
+        chain.update_with_block(mined_block(&chain, &params, passing)).unwrap();
+        // after the window, any timestamp passes
+        let late = passing + params.tonce_challenge_duration_ms;
+        chain.update_with_block(mined_block(&chain, &params, late)).unwrap();
+        assert!(chain.verify_chain().is_ok());
+    }
+
+    #[test]
+    fn test_hybrid_checks_the_committed_round() {
+        let mut params = ChainParams::regtest().with_hybrid_consensus();
+        params.tonce_bits = 8;
+        let mut chain = Blockchain::new_with_params(&params);
+        assert_eq!(chain.consensus_engine().name(), "hybrid");
+        // the genesis block has no round to commit to
+        chain.update_with_block(mined_block(&chain, &params, 1_000_000)).unwrap();
+
+        let tonce = chain.next_challenge().tonce.unwrap();
+        assert!(tonce.tonce > 2);
+        let window = tonce.prev_block_timestamp + 1..tonce.prev_block_timestamp + tonce.challenge_duration_ms;
+        let failing = window.clone().find(|timestamp| !passes_tonce(tonce.tonce, *timestamp)).unwrap();
+        let passing = window.clone().find(|timestamp| passes_tonce(tonce.tonce, *timestamp)).unwrap();
+        let round = |tonce: u8| Some(RoundCommitment { round_start: 1_000_500, tonce, signature: None });
+        assert!(matches!(chain.update_with_block(mined_block(&chain, &params, passing)),
+            Err(BlockValidationErr::MissingRoundCommitment)));
+        // the tonce is the one derived from the previous block, not one that suits the timestamp
+        assert!(matches!(chain.update_with_block(committed_block(&chain, &params, failing, round(1))),
+            Err(BlockValidationErr::InvalidRoundCommitment)));
+        assert!(matches!(chain.update_with_block(committed_block(&chain, &params, failing, round(tonce.tonce))),
+            Err(BlockValidationErr::FailedTonceChallenge)));
+        chain.update_with_block(committed_block(&chain, &params, passing, round(tonce.tonce))).unwrap();
+
+        // after the window the validator drops the challenge to 1
+        let late = passing + params.tonce_challenge_duration_ms;
+        chain.update_with_block(committed_block(&chain, &params, late, round(1))).unwrap();
+        assert!(chain.verify_chain().is_ok());
+
+        // the commitment is part of the hash
+        let mut tampered = chain.blocks[1].clone();
+        tampered.header.round = None;
+        assert_ne!(tampered.header.hash(), chain.blocks[1].hash);
+    }
+
+    /// Accepts `limit` blocks, then refuses every other one
+    struct Limited {
+        limit: usize,
+        accepted: usize,
+    }
+
+    impl ConsensusEngine for Limited {
+        fn name(&self) -> &'static str {
+            "limited"
+        }
+
+        fn validate_block(&self, chain: &Blockchain, block: &Block) -> Result<(), BlockValidationErr> {
+            if self.accepted >= self.limit {
+                return Err(BlockValidationErr::InvalidHash);
+            }
+            ProofOfWork.validate_block(chain, block)
+        }
+
+        fn next_challenge(&self, chain: &Blockchain) -> Challenge {
+            ProofOfWork.next_challenge(chain)
+        }
+
+        fn on_block_accepted(&mut self, _block: &Block) {
+            self.accepted += 1;
+        }
+
+        fn reset(&self) -> Box<dyn ConsensusEngine> {
+            Box::new(Limited { limit: self.limit, accepted: 0 })
+        }
+    }
+
+    #[test]
+    fn test_engine_state_follows_rewinds() {
+        let params = ChainParams::regtest();
+        let mut chain = Blockchain::new_with_params(&params);
+        chain.set_consensus_engine(Limited { limit: 3, accepted: 0 });
+        for n in 0..3 {
+            chain.update_with_block(mined_block(&chain, &params, 1_000_000 + n * 1000)).unwrap();
+        }
+        assert!(chain.update_with_block(mined_block(&chain, &params, 1_010_000)).is_err());
+
+        // the rewound chain's engine has only seen the kept block
+        assert_eq!(chain.rewind_to(1).unwrap().len(), 2);
+        for n in 1..3 {
+            chain.update_with_block(mined_block(&chain, &params, 1_500_000 + n * 1000)).unwrap();
+        }
+        assert!(chain.update_with_block(mined_block(&chain, &params, 1_510_000)).is_err());
+    }
+}
@@ -0,0 +1,457 @@
+//! Terminal dashboards for the binaries
+//!
+//! Built with `--features tui` and shown with `--tui`. Log lines then go to a
+//! `LogBuffer` instead of stdout (see `LogConfig::init_with_writer`), and the
+//! dashboard shows the most recent of them under the figures the binary
+//! polls, so an operator can check on a rig at a glance.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::Frame;
+use tracing_subscriber::fmt::MakeWriter;
+use crate::decision_log::DecisionSource;
+use crate::network::protocol::RoundInfoData;
+use crate::time_sync::TimeSyncStatus;
+use crate::validator::RecentDecision;
+use crate::{estimate_mining_time, Difficulty, Validator};
+
+/// Log lines kept for display
+pub const LOG_CAPACITY: usize = 500;
+
+/// How often the display is redrawn, which is also the longest a key press waits
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Decisions listed on the validator dashboard
+const SHOWN_DECISIONS: usize = 8;
+
+/// The most recent log lines, shared by the logger and the display
+#[derive(Debug, Clone, Default)]
+pub struct LogBuffer {
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl LogBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The last `count` lines, oldest first
+    pub fn recent(&self, count: usize) -> Vec<String> {
+        let lines = self.lines.lock().unwrap();
+        lines.iter().skip(lines.len().saturating_sub(count)).cloned().collect()
+    }
+
+    fn push(&self, line: String) {
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() == LOG_CAPACITY {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+}
+
+/// Collects one formatted event, handing its lines to the buffer when dropped
+pub struct LogWriter {
+    buffer: LogBuffer,
+    pending: Vec<u8>,
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        for line in String::from_utf8_lossy(&self.pending).lines().filter(|line| !line.is_empty()) {
+            self.buffer.push(line.to_string());
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogBuffer {
+    type Writer = LogWriter;
+
+    fn make_writer(&'a self) -> LogWriter {
+        LogWriter { buffer: self.clone(), pending: Vec::new() }
+    }
+}
+
+/// What the miner dashboard shows, refreshed from the validator by the binary
+#[derive(Debug, Clone, Default)]
+pub struct MinerDashboard {
+    pub miner_id: String,
+    pub validator: String,
+    /// Height of the block being mined
+    pub height: Option<u32>,
+    pub round_start: Option<u128>,
+    pub tonce: Option<u8>,
+    pub challenge_seconds_remaining: u64,
+    pub difficulty: Difficulty,
+    pub remaining_attempts: Option<u32>,
+    /// The validator's clock at the last refresh
+    pub validator_time: u128,
+    /// Hashes per second of the latest mining run
+    pub hashrate: f64,
+    /// Seconds left of our lockout, while serving one
+    pub lockout_seconds_remaining: Option<u64>,
+    pub blocks_accepted: u64,
+    /// Why the last refresh failed, until one succeeds
+    pub error: Option<String>,
+}
+
+impl MinerDashboard {
+    pub fn new(miner_id: &str, validator: &str) -> Self {
+        MinerDashboard {
+            miner_id: miner_id.to_string(),
+            validator: validator.to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn update_round(&mut self, info: &RoundInfoData) {
+        self.round_start = Some(info.round_start);
+        self.tonce = info.tonce;
+        self.challenge_seconds_remaining = info.challenge_seconds_remaining;
+        self.difficulty = info.difficulty;
+        self.remaining_attempts = info.remaining_attempts;
+    }
+
+    /// The figures, one line each
+    pub fn summary(&self) -> Vec<String> {
+        let round = match (self.height, self.round_start) {
+            (Some(height), Some(start)) => format!("block {}, started {} ago", height,
+                format_duration(self.validator_time.saturating_sub(start) / 1000)),
+            (None, Some(start)) => format!("started {} ago", format_duration(self.validator_time.saturating_sub(start) / 1000)),
+            _ => "waiting for the validator".to_string(),
+        };
+        let tonce = match self.tonce {
+            Some(tonce) if self.challenge_seconds_remaining > 0 => format!("{} ({} of challenge left)",
+                tonce, format_duration(u128::from(self.challenge_seconds_remaining))),
+            Some(tonce) => format!("{} (challenge over, any timestamp)", tonce),
+            None => "-".to_string(),
+        };
+        let attempts = match self.remaining_attempts {
+            Some(attempts) => attempts.to_string(),
+            None => "-".to_string(),
+        };
+        let estimate = estimate_mining_time(self.difficulty, self.hashrate);
+        let eta = match (estimate.median(), estimate.quantile(0.9)) {
+            (Some(median), Some(p90)) => format!("{} median, {} for 90%",
+                format_duration(median.as_secs().into()), format_duration(p90.as_secs().into())),
+            _ => "-".to_string(),
+        };
+        let lockout = match self.lockout_seconds_remaining {
+            Some(seconds) => format!("{} left", format_duration(u128::from(seconds))),
+            None => "none".to_string(),
+        };
+
+        let mut lines = vec![
+            format!("Round        {}", round),
+            format!("Tonce        {}", tonce),
+            format!("Difficulty   {:.2}", self.difficulty.relative()),
+            format!("Attempts     {}", attempts),
+            format!("Hashrate     {:.0} H/s", self.hashrate),
+            format!("Block ETA    {}", eta),
+            format!("Lockout      {}", lockout),
+            format!("Accepted     {} blocks", self.blocks_accepted),
+        ];
+        if let Some(error) = &self.error {
+            lines.push(format!("Error        {}", error));
+        }
+        lines
+    }
+
+    pub fn render(&self, frame: &mut Frame, logs: &LogBuffer) {
+        let title = format!(" Hourcoin miner {} @ {} ", self.miner_id, self.validator);
+        render_sections(frame, &[(title, self.summary())], logs);
+    }
+}
+
+/// What the validator dashboard shows, read from the running server by the binary
+#[derive(Debug, Clone, Default)]
+pub struct ValidatorDashboard {
+    pub address: String,
+    /// Blocks in the chain
+    pub height: usize,
+    pub difficulty: Difficulty,
+    /// Client connections open now
+    pub connections: usize,
+    pub active_lockouts: usize,
+    /// Miners who have submitted this round
+    pub attempted_miners: usize,
+    pub round_start: u128,
+    pub tonce: Option<u8>,
+    pub challenge_seconds_remaining: u64,
+    /// The validator's clock at the last refresh
+    pub validator_time: u128,
+    pub paused: bool,
+    pub clock_drift_paused: bool,
+    pub time_sync: Option<TimeSyncStatus>,
+    /// Most recent last
+    pub decisions: Vec<RecentDecision>,
+}
+
+impl ValidatorDashboard {
+    pub fn new(address: &str) -> Self {
+        ValidatorDashboard {
+            address: address.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Read the figures from `validator`, which has `connections` clients connected
+    pub fn update(&mut self, validator: &Validator, connections: usize) {
+        let round = validator.get_round_info();
+        self.height = validator.blockchain.blocks.len();
+        self.difficulty = validator.get_difficulty();
+        self.connections = connections;
+        self.active_lockouts = round.active_lockouts;
+        self.attempted_miners = round.attempted_miners;
+        self.round_start = round.round_start;
+        self.tonce = round.tonce;
+        self.challenge_seconds_remaining = round.challenge_seconds_remaining;
+        self.validator_time = validator.get_current_time();
+        self.paused = validator.is_paused();
+        self.clock_drift_paused = validator.is_clock_drift_paused();
+        self.time_sync = Some(validator.time_sync().status());
+        self.decisions = validator.recent_decisions().iter().cloned().collect();
+    }
+
+    /// The figures, one line each
+    pub fn summary(&self) -> Vec<String> {
+        let tonce = match self.tonce {
+            Some(tonce) if self.challenge_seconds_remaining > 0 => format!("{} ({} of challenge left)",
+                tonce, format_duration(u128::from(self.challenge_seconds_remaining))),
+            Some(tonce) => format!("{} (challenge over, any timestamp)", tonce),
+            None => "-".to_string(),
+        };
+        let acceptance = match (self.paused, self.clock_drift_paused) {
+            (true, _) => "paused by an operator",
+            (false, true) => "paused, clock out of sync",
+            (false, false) => "open",
+        };
+        vec![
+            format!("Height       {} blocks", self.height),
+            format!("Difficulty   {:.2}", self.difficulty.relative()),
+            format!("Round        started {} ago, {} miners attempted",
+                format_duration(self.validator_time.saturating_sub(self.round_start) / 1000), self.attempted_miners),
+            format!("Tonce        {}", tonce),
+            format!("Connections  {}", self.connections),
+            format!("Lockouts     {} active", self.active_lockouts),
+            format!("Blocks       {}", acceptance),
+            format!("Time sync    {}", self.time_sync_health()),
+        ]
+    }
+
+    /// Agreed offset, healthy sources and the age of the last sync
+    fn time_sync_health(&self) -> String {
+        let status = match &self.time_sync {
+            Some(status) => status,
+            None => return "-".to_string(),
+        };
+        let last_sync = match status.last_sync_time {
+            Some(time) => format!("synced {} ago", format_duration(self.validator_time.saturating_sub(time) / 1000)),
+            None => "never synced".to_string(),
+        };
+        let healthy = status.sources.iter()
+            .filter(|source| source.successes > 0 && source.last_error.is_none() && !source.outlier)
+            .count();
+        let offset = match (status.offset_ms, status.confidence_ms) {
+            (Some(offset), Some(confidence)) => format!("offset {:+}ms ±{}ms, ", offset, confidence),
+            _ => String::new(),
+        };
+        format!("{}{}/{} sources healthy, {}", offset, healthy, status.sources.len(), last_sync)
+    }
+
+    /// The latest decisions, newest first
+    pub fn decision_lines(&self) -> Vec<String> {
+        if self.decisions.is_empty() {
+            return vec!["none yet".to_string()];
+        }
+        self.decisions.iter().rev().take(SHOWN_DECISIONS).map(|decision| {
+            let source = match decision.source {
+                DecisionSource::Miner => "miner",
+                DecisionSource::Peer => "peer",
+            };
+            format!("{:>8} ago  #{:<6} {:<5} {:<20} {}",
+                format_duration(self.validator_time.saturating_sub(decision.judged_at) / 1000),
+                decision.height, source, decision.miner_id, decision.result)
+        }).collect()
+    }
+
+    pub fn render(&self, frame: &mut Frame, logs: &LogBuffer) {
+        let sections = [
+            (format!(" Hourcoin validator @ {} ", self.address), self.summary()),
+            (" Recent decisions ".to_string(), self.decision_lines()),
+        ];
+        render_sections(frame, &sections, logs);
+    }
+}
+
+/// `seconds` as e.g. "1h 02m", "4m 05s" or "12s"
+fn format_duration(seconds: u128) -> String {
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+/// Each section's lines in a box under its title, with as many recent log lines as fit below them
+pub fn render_sections(frame: &mut Frame, sections: &[(String, Vec<String>)], logs: &LogBuffer) {
+    let constraints = sections.iter()
+        .map(|(_, lines)| Constraint::Length(lines.len() as u16 + 2))
+        .chain(std::iter::once(Constraint::Min(3)));
+    let areas = Layout::vertical(constraints).split(frame.area());
+
+    for ((title, lines), area) in sections.iter().zip(areas.iter()) {
+        let section = Paragraph::new(lines.join("\n"))
+            .block(Block::default().borders(Borders::ALL).title(title.as_str()));
+        frame.render_widget(section, *area);
+    }
+
+    let bottom = areas[sections.len()];
+    let lines = logs.recent(bottom.height.saturating_sub(2) as usize);
+    let log = Paragraph::new(lines.join("\n"))
+        .block(Block::default().borders(Borders::ALL).title(" Log (q to quit) "));
+    frame.render_widget(log, bottom);
+}
+
+/// Redraw with `draw` until the operator presses q, Esc or Ctrl+C
+///
+/// Takes over the terminal and blocks, so run it on a thread of its own
+/// (`tokio::task::spawn_blocking`). The terminal is restored however it returns.
+pub fn run<F: FnMut(&mut Frame)>(mut draw: F) -> io::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let result = (|| loop {
+        terminal.draw(&mut draw)?;
+        if event::poll(REDRAW_INTERVAL)? {
+            if let Event::Key(key) = event::read()? {
+                if is_quit(&key) {
+                    return Ok(());
+                }
+            }
+        }
+    })();
+    ratatui::restore();
+    result
+}
+
+/// Raw mode swallows the interrupt signal, so Ctrl+C arrives as a key
+fn is_quit(key: &KeyEvent) -> bool {
+    key.kind == KeyEventKind::Press && match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => true,
+        KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    #[test]
+    fn test_log_buffer_keeps_recent_lines() {
+        let logs = LogBuffer::new();
+        for i in 0..LOG_CAPACITY + 5 {
+            let mut writer = logs.make_writer();
+            writeln!(writer, "line {}", i).unwrap();
+        }
+        let recent = logs.recent(3);
+        assert_eq!(recent, vec![
+            format!("line {}", LOG_CAPACITY + 2),
+            format!("line {}", LOG_CAPACITY + 3),
+            format!("line {}", LOG_CAPACITY + 4),
+        ]);
+        assert_eq!(logs.recent(usize::MAX).len(), LOG_CAPACITY);
+    }
+
+    fn screen(terminal: &Terminal<TestBackend>) -> String {
+        terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect()
+    }
+
+    #[test]
+    fn test_miner_dashboard() {
+        let mut dashboard = MinerDashboard::new("alice", "127.0.0.1:8080");
+        assert_eq!(dashboard.summary()[0], "Round        waiting for the validator");
+
+        dashboard.height = Some(12);
+        dashboard.round_start = Some(1_000_000);
+        dashboard.validator_time = 1_000_000 + 125_000;
+        dashboard.tonce = Some(7);
+        dashboard.challenge_seconds_remaining = 20;
+        dashboard.lockout_seconds_remaining = Some(3_720);
+        assert_eq!(dashboard.summary()[5], "Block ETA    -");
+        dashboard.hashrate = 1234.4;
+        dashboard.difficulty = Difficulty::from_target(0x00000FFFFFFFFFFFFFFFFFFFFFFFFFFF);
+        dashboard.blocks_accepted = 3;
+        let summary = dashboard.summary();
+        assert_eq!(summary[0], "Round        block 12, started 2m 05s ago");
+        assert_eq!(summary[1], "Tonce        7 (20s of challenge left)");
+        assert_eq!(summary[4], "Hashrate     1234 H/s");
+        assert_eq!(summary[5], "Block ETA    9m 48s median, 32m 35s for 90%");
+        assert_eq!(summary[6], "Lockout      1h 02m left");
+        assert_eq!(summary[7], "Accepted     3 blocks");
+
+        let logs = LogBuffer::new();
+        writeln!(logs.make_writer(), "block mined").unwrap();
+        let mut terminal = Terminal::new(TestBackend::new(80, 20)).unwrap();
+        terminal.draw(|frame| dashboard.render(frame, &logs)).unwrap();
+        let screen = screen(&terminal);
+        assert!(screen.contains("Hourcoin miner alice"));
+        assert!(screen.contains("block mined"));
+    }
+
+    #[test]
+    fn test_validator_dashboard() {
+        use crate::time_source::{MockTimeSource, TimeSource};
+        use crate::{find_valid_timestamp, Block, BlockHash, ChainParams, ValidationResult};
+
+        let params = ChainParams::regtest();
+        let clock = MockTimeSource::new(50_000_000);
+        let mut validator = Validator::new_with_params(params.clone());
+        validator.set_time_source(Arc::new(clock.clone()));
+        validator.start_new_round();
+
+        let timestamp = find_valid_timestamp(validator.get_current_tonce().unwrap(), clock.now(), 100000).unwrap();
+        clock.set(timestamp);
+        let coinbase = crate::tx_builder::TransactionBuilder::new(timestamp).pay_to("alice", 2.0).build_coinbase().unwrap();
+        let mut block = Block::new(0, timestamp, BlockHash::ZERO, vec![coinbase]);
+        block.mine(params.difficulty);
+        clock.advance(1_500);
+        assert_eq!(validator.validate_block_submission(block, "alice".to_string()), ValidationResult::Accepted);
+        clock.advance(5_000);
+
+        let mut dashboard = ValidatorDashboard::new("0.0.0.0:8080");
+        dashboard.update(&validator, 3);
+        let summary = dashboard.summary();
+        assert_eq!(summary[0], "Height       1 blocks");
+        assert_eq!(summary[2], "Round        started 5s ago, 0 miners attempted");
+        assert_eq!(summary[4], "Connections  3");
+        assert_eq!(summary[5], "Lockouts     1 active");
+        assert_eq!(summary[6], "Blocks       open");
+        assert!(summary[7].starts_with("Time sync    0/"));
+        assert!(summary[7].ends_with("never synced"));
+        assert_eq!(dashboard.decision_lines(), vec![format!("{:>8} ago  #{:<6} {:<5} {:<20} {}", "5s", 0, "miner", "alice", "accepted")]);
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+        terminal.draw(|frame| dashboard.render(frame, &LogBuffer::new())).unwrap();
+        let screen = screen(&terminal);
+        assert!(screen.contains("Hourcoin validator @ 0.0.0.0:8080"));
+        assert!(screen.contains("Recent decisions"));
+    }
+}
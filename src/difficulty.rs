@@ -0,0 +1,246 @@
+//! Proof of work difficulty in compact form
+//!
+//! A block meets its difficulty when the last 16 bytes of its hash, read as a
+//! little-endian `u128`, are below a target. Passing that target around as a
+//! bare `u128` meant a 34-digit hex string in every message, so `Difficulty`
+//! stores it the way Bitcoin's `nBits` does: one byte giving the target's
+//! length in bytes and a three byte mantissa holding its leading bytes.
+//!
+//! Only targets with three significant bytes can be written this way;
+//! `from_target` rounds any other down to the nearest one, so a rounded
+//! difficulty is never easier than the target it came from. Two difficulties
+//! are equal exactly when their targets are.
+
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::canonical::{CanonicalDeserialize, CanonicalReader, CanonicalSerialize};
+use crate::endian::difficulty_bytes_as_u128;
+use crate::BlockHash;
+
+// the mantissa is signed in Bitcoin's encoding; a set sign bit is never produced
+const SIGN_BIT: u32 = 0x0080_0000;
+const MANTISSA_MASK: u32 = 0x007f_ffff;
+
+/// A proof of work target, kept in compact form
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Difficulty(u32);
+
+impl Difficulty {
+    /// The easiest difficulty there is: nearly every hash meets it
+    pub const EASIEST: Difficulty = Difficulty::from_target(u128::MAX);
+
+    /// The difficulty whose target is `target`, rounded down to three significant bytes
+    pub const fn from_target(target: u128) -> Difficulty {
+        let mut size = (128 - target.leading_zeros()).div_ceil(8);
+        let mut mantissa = if size <= 3 {
+            (target << (8 * (3 - size))) as u32
+        } else {
+            (target >> (8 * (size - 3))) as u32
+        };
+        if mantissa & SIGN_BIT != 0 {
+            mantissa >>= 8;
+            size += 1;
+        }
+        Difficulty((size << 24) | mantissa)
+    }
+
+    /// Decode compact bits, rejecting negative and out of range targets
+    pub fn from_compact(bits: u32) -> Result<Difficulty, String> {
+        if bits & SIGN_BIT != 0 {
+            return Err(format!("Compact difficulty 0x{:08x} is negative", bits));
+        }
+        let size = bits >> 24;
+        let mantissa = bits & MANTISSA_MASK;
+        let target = if size <= 3 {
+            u128::from(mantissa >> (8 * (3 - size)))
+        } else {
+            let shift = 8 * (size - 3);
+            if mantissa != 0 && 32 - mantissa.leading_zeros() + shift > 128 {
+                return Err(format!("Compact difficulty 0x{:08x} overflows a 128-bit target", bits));
+            }
+            u128::from(mantissa).checked_shl(shift).unwrap_or(0)
+        };
+        // re-encoding gives every target a single representation
+        Ok(Difficulty::from_target(target))
+    }
+
+    /// The compact bits, as stored in block headers and sent on the wire
+    pub fn to_compact(&self) -> u32 {
+        self.0
+    }
+
+    /// The target a hash must be below
+    pub fn target(&self) -> u128 {
+        let size = self.0 >> 24;
+        let mantissa = u128::from(self.0 & MANTISSA_MASK);
+        if size <= 3 {
+            mantissa >> (8 * (3 - size))
+        } else {
+            mantissa << (8 * (size - 3))
+        }
+    }
+
+    /// How many times harder than `EASIEST` this is, the number usually quoted as "the difficulty"
+    pub fn relative(&self) -> f64 {
+        Self::EASIEST.target() as f64 / self.target() as f64
+    }
+
+    /// The difficulty `relative` times harder than `EASIEST`; anything below 1 gives `EASIEST`
+    pub fn from_relative(relative: f64) -> Difficulty {
+        if relative <= 1.0 {
+            return Self::EASIEST;
+        }
+        Difficulty::from_target((Self::EASIEST.target() as f64 / relative) as u128)
+    }
+
+    /// Hashes expected to meet this difficulty, which is what a block mined at it adds to its chain's work
+    ///
+    /// That is 2^128 / (target + 1), computed without leaving `u128`. An
+    /// impossible difficulty (a zero target) saturates.
+    pub fn work(&self) -> u128 {
+        let target = self.target();
+        (!target / target.saturating_add(1)).saturating_add(1)
+    }
+
+    /// Whether `hash` is proof of work at this difficulty
+    pub fn is_met_by(&self, hash: &BlockHash) -> bool {
+        difficulty_bytes_as_u128(hash.as_bytes()).expect("block hashes are 32 bytes") < self.target()
+    }
+
+    /// Whether fewer hashes meet this difficulty than `other`
+    pub fn is_harder_than(&self, other: &Difficulty) -> bool {
+        self.target() < other.target()
+    }
+
+    /// Whether more hashes meet this difficulty than `other`
+    pub fn is_easier_than(&self, other: &Difficulty) -> bool {
+        self.target() > other.target()
+    }
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Self::EASIEST
+    }
+}
+
+/// The target in hex, as operators have always written difficulties
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:X}", self.target())
+    }
+}
+
+impl fmt::Debug for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Difficulty(0x{:08x})", self.0)
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = String;
+
+    /// Parse a hex target, with or without `0x`, rounding it like `from_target`
+    fn from_str(s: &str) -> Result<Self, String> {
+        u128::from_str_radix(s.trim_start_matches("0x"), 16)
+            .map(Difficulty::from_target)
+            .map_err(|e| format!("Invalid difficulty '{}': {}", s, e))
+    }
+}
+
+impl Serialize for Difficulty {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Difficulty {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Difficulty::from_compact(u32::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
+impl CanonicalSerialize for Difficulty {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        self.0.write_canonical(out);
+    }
+}
+
+impl CanonicalDeserialize for Difficulty {
+    fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+        let bits = u32::read_canonical(reader)?;
+        let difficulty = Difficulty::from_compact(bits)?;
+        // a header must carry the one encoding of its target, or its hash would have two forms
+        if difficulty.to_compact() != bits {
+            return Err(format!("Compact difficulty 0x{:08x} is not normalized", bits));
+        }
+        Ok(difficulty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_encoding() {
+        let difficulty = Difficulty::from_target(0x00FF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF);
+        assert_eq!(difficulty.to_compact(), 0x1000_ffff);
+        assert_eq!(difficulty.target(), 0x00FF_FF00_0000_0000_0000_0000_0000_0000);
+        assert_eq!(Difficulty::EASIEST.to_compact(), 0x1100_ffff);
+        assert_eq!(Difficulty::from_target(0x12_3456).to_compact(), 0x0312_3456);
+        assert_eq!(Difficulty::from_target(0x80).to_compact(), 0x0200_8000);
+        assert_eq!(Difficulty::from_target(0).target(), 0);
+
+        // Exactly representable targets survive the round trip
+        for target in [1, 0xFF, 0x7F_FFFF, 0x1234_5600_0000, 0x7FFF_FF00_0000_0000_0000_0000_0000_0000] {
+            let difficulty = Difficulty::from_target(target);
+            assert_eq!(difficulty.target(), target);
+            assert_eq!(Difficulty::from_compact(difficulty.to_compact()), Ok(difficulty));
+        }
+
+        // Unnormalized bits decode to the same difficulty
+        assert_eq!(Difficulty::from_compact(0x0400_1234).unwrap().to_compact(), 0x0312_3400);
+        assert!(Difficulty::from_compact(0x0480_0000).is_err());
+        assert!(Difficulty::from_compact(0x1201_0000).is_err());
+        assert!(Difficulty::from_compact(0x1100_ffff).is_ok());
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let easy = Difficulty::from_target(0x0FFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF);
+        let hard = Difficulty::from_target(0x00FF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF);
+        assert!(hard.is_harder_than(&easy));
+        assert!(easy.is_easier_than(&hard));
+        assert!(!easy.is_harder_than(&easy));
+
+        assert_eq!(Difficulty::EASIEST.relative(), 1.0);
+        assert!((hard.relative() / easy.relative() - 16.0).abs() < 0.01);
+        assert_eq!(Difficulty::from_relative(hard.relative()), hard);
+        assert_eq!(Difficulty::from_relative(0.5), Difficulty::EASIEST);
+
+        assert_eq!(Difficulty::from_target((1 << 120) - 1).work(), 256);
+        assert_eq!(Difficulty::EASIEST.work(), 1);
+        assert!(hard.work() > easy.work());
+        assert_eq!(Difficulty::from_target(0).work(), u128::MAX);
+
+        let mut hash = [0; 32];
+        hash[31] = 0x01;
+        assert!(easy.is_met_by(&BlockHash::from_bytes(hash)));
+        assert!(!hard.is_met_by(&BlockHash::from_bytes(hash)));
+    }
+
+    #[test]
+    fn test_text_and_wire_forms() {
+        let difficulty: Difficulty = "0x00FFFF00000000000000000000000000".parse().unwrap();
+        assert_eq!(difficulty.to_string(), "0xFFFF00000000000000000000000000");
+        assert_eq!(difficulty.to_string().parse::<Difficulty>(), Ok(difficulty));
+        assert!("0xZZ".parse::<Difficulty>().is_err());
+
+        assert_eq!(serde_json::to_string(&difficulty).unwrap(), "268500991");
+        assert!(serde_json::from_str::<Difficulty>("8388608").is_err());
+        assert_eq!(Difficulty::from_canonical_bytes(&difficulty.canonical_bytes()), Ok(difficulty));
+        assert!(Difficulty::from_canonical_bytes(&0x0400_1234u32.canonical_bytes()).is_err());
+    }
+}
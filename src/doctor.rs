@@ -0,0 +1,456 @@
+//! Validator self-test and diagnostics for Hourcoin
+//!
+//! `validator doctor` runs these checks before an operator opens a node to
+//! miners. Each check produces a pass/warn/fail/skip line so problems with
+//! the clock, network or chain configuration surface before the first round
+//! starts rather than as rejected blocks.
+
+use std::fmt::{self, Display, Formatter};
+use std::net::TcpListener;
+use std::path::Path;
+use crate::block_store::BlockStore;
+use crate::leap_seconds::{current_table, is_near_leap_second, next_leap_second};
+use crate::node_key::NodeKey;
+use crate::time_sync::{estimate_offset, TimeSync};
+use crate::tonce::TonceChallenge;
+use crate::{now, now_utc, Block, BlockHash, Blockchain, ChainParams, Difficulty};
+
+/// Number of clock samples taken by the monotonicity check
+const CLOCK_SAMPLES: usize = 10_000;
+
+/// HTTP time sources only report whole seconds, so offsets below this are noise
+const EXTERNAL_TIME_RESOLUTION_MS: u128 = 1_000;
+
+/// Difficulties harder than this need more than ~2^32 hashes per block
+const SLOW_MINING_DIFFICULTY: Difficulty = Difficulty::from_target(1 << 96);
+
+/// Round timings longer than this are a configuration mistake (one week)
+const MAX_ROUND_DURATION_MS: u128 = 7 * 24 * 3_600_000;
+
+/// Outcome of a single diagnostic check
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+    Skip,
+}
+
+impl Display for CheckStatus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let label = match self {
+            CheckStatus::Pass => "PASS",
+            CheckStatus::Warn => "WARN",
+            CheckStatus::Fail => "FAIL",
+            CheckStatus::Skip => "SKIP",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+/// Result of a single diagnostic check
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl CheckResult {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        CheckResult {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Structured report produced by `run_diagnostics`
+#[derive(Debug, Clone, Default)]
+pub struct DoctorReport {
+    pub checks: Vec<CheckResult>,
+}
+
+impl DoctorReport {
+    /// True if no check failed (warnings and skips are allowed)
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.status != CheckStatus::Fail)
+    }
+
+    /// Number of checks with the given status
+    pub fn count(&self, status: CheckStatus) -> usize {
+        self.checks.iter().filter(|c| c.status == status).count()
+    }
+}
+
+impl Display for DoctorReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for check in &self.checks {
+            writeln!(f, "  [{}] {:<16} {}", check.status, check.name, check.detail)?;
+        }
+        write!(f, "\n  {} passed, {} warnings, {} failed, {} skipped",
+            self.count(CheckStatus::Pass),
+            self.count(CheckStatus::Warn),
+            self.count(CheckStatus::Fail),
+            self.count(CheckStatus::Skip))
+    }
+}
+
+/// Run every diagnostic check for a validator that would listen on `address` with `params`
+///
+/// `node_key` is the hex secret the validator would sign receipts with, if any,
+/// and `block_store` the directory it would keep its chain in.
+pub async fn run_diagnostics(address: &str, params: &ChainParams, node_key: Option<&str>, block_store: Option<&Path>) -> DoctorReport {
+    let time_sync = TimeSync::new();
+
+    DoctorReport {
+        checks: vec![
+            check_storage(block_store),
+            check_time_source(&time_sync).await,
+            check_port(address),
+            check_key_material(node_key),
+            check_chain_params(params, block_store),
+            check_clock_monotonicity(),
+            check_leap_seconds(),
+        ],
+    }
+}
+
+/// Open the block store in `dir` and check that its index matches the data files
+///
+/// Without a `--block-store` the validator keeps its chain in memory, so
+/// there is nothing on disk to check.
+pub fn check_storage(dir: Option<&Path>) -> CheckResult {
+    let dir = match dir {
+        Some(dir) => dir,
+        None => return CheckResult::new("storage", CheckStatus::Skip, "chain state is kept in memory; no --block-store configured"),
+    };
+    if !BlockStore::exists(dir) {
+        return match dir.metadata() {
+            Ok(meta) if meta.permissions().readonly() =>
+                CheckResult::new("storage", CheckStatus::Fail, format!("{} is not writable", dir.display())),
+            _ => CheckResult::new("storage", CheckStatus::Pass, format!("no block store in {} yet; it will be created at startup", dir.display())),
+        };
+    }
+
+    match BlockStore::open_read_only(dir).and_then(|store| store.verify().map(|_| store.len())) {
+        Ok(len) => CheckResult::new("storage", CheckStatus::Pass, format!("{} blocks in {}, index matches the data files", len, dir.display())),
+        Err(e) => CheckResult::new("storage", CheckStatus::Fail, e),
+    }
+}
+
+/// Query the external time sources and compare their median to the local TAI clock
+pub async fn check_time_source(time_sync: &TimeSync) -> CheckResult {
+    let readings = time_sync.poll_sources().await;
+    let offsets: Vec<i128> = readings.iter().filter_map(|reading| reading.as_ref().ok().copied()).collect();
+    let reachable = format!("{} of {} sources reachable", offsets.len(), readings.len());
+
+    match estimate_offset(&offsets) {
+        Some(estimate) => {
+            let offset = estimate.offset_ms;
+            let allowed = time_sync.tolerance_ms + EXTERNAL_TIME_RESOLUTION_MS;
+
+            if offset.unsigned_abs() <= allowed {
+                CheckResult::new("time source", CheckStatus::Pass,
+                    format!("{}, median offset {}ms (±{}ms)", reachable, offset, estimate.confidence_ms))
+            } else {
+                CheckResult::new("time source", CheckStatus::Fail,
+                    format!("{}, median offset {}ms exceeds {}ms", reachable, offset, allowed))
+            }
+        }
+        None => {
+            let errors: Vec<String> = readings.into_iter().filter_map(Result::err).collect();
+            CheckResult::new("time source", CheckStatus::Warn,
+                format!("unreachable ({}), validator will fall back to system time", errors.join("; ")))
+        }
+    }
+}
+
+/// Make sure the listen address can actually be bound
+pub fn check_port(address: &str) -> CheckResult {
+    match TcpListener::bind(address) {
+        Ok(_) => CheckResult::new("port", CheckStatus::Pass, format!("{} is bindable", address)),
+        Err(e) => CheckResult::new("port", CheckStatus::Fail, format!("cannot bind {}: {}", address, e)),
+    }
+}
+
+/// Make sure the receipt signing key loads
+pub fn check_key_material(node_key: Option<&str>) -> CheckResult {
+    let hex_key = match node_key {
+        Some(hex_key) => hex_key,
+        None => return CheckResult::new("key material", CheckStatus::Warn,
+            "no --node-key given; receipts will be signed with an ephemeral key"),
+    };
+
+    match hex::decode(hex_key).map_err(|e| e.to_string()).and_then(|bytes| NodeKey::from_secret_bytes(&bytes)) {
+        Ok(key) => CheckResult::new("key material", CheckStatus::Pass,
+            format!("node key {}", hex::encode(key.public_key()))),
+        Err(e) => CheckResult::new("key material", CheckStatus::Fail, format!("cannot load node key: {}", e)),
+    }
+}
+
+/// Sanity-check `params`, and that the chain's genesis block follows them
+///
+/// The genesis block is the one the params fix, or else block 0 of the block
+/// store in `block_store`. A node with neither has no genesis to check yet.
+pub fn check_chain_params(params: &ChainParams, block_store: Option<&Path>) -> CheckResult {
+    let difficulty = params.difficulty;
+    if difficulty.target() == 0 {
+        return CheckResult::new("chain params", CheckStatus::Fail, "difficulty 0x0 can never be satisfied");
+    }
+    if let Err(e) = params.check() {
+        return CheckResult::new("chain params", CheckStatus::Fail, e);
+    }
+    for (name, duration_ms) in [
+        ("tonce challenge duration", params.tonce_challenge_duration_ms),
+        ("lockout duration", params.lockout_duration_ms),
+    ] {
+        if duration_ms == 0 || duration_ms > MAX_ROUND_DURATION_MS {
+            return CheckResult::new("chain params", CheckStatus::Fail,
+                format!("{} {}ms is outside 1-{}ms", name, duration_ms, MAX_ROUND_DURATION_MS));
+        }
+    }
+
+    let tonce = TonceChallenge::new_with_params(&BlockHash::ZERO, now(), params).get_tonce();
+    let max_tonce = (1u16 << params.tonce_bits) - 1;
+    if tonce == 0 || u16::from(tonce) > max_tonce {
+        return CheckResult::new("chain params", CheckStatus::Fail,
+            format!("genesis round derived tonce {} outside 1-{}", tonce, max_tonce));
+    }
+
+    let genesis = match genesis_block(params, block_store).and_then(|genesis| match genesis {
+        Some(genesis) => check_genesis(params, genesis).map(Some),
+        None => Ok(None),
+    }) {
+        Ok(Some(hash)) => format!("genesis {}", hash),
+        Ok(None) => "no genesis block yet".to_string(),
+        Err(e) => return CheckResult::new("chain params", CheckStatus::Fail, e),
+    };
+
+    if difficulty.is_harder_than(&SLOW_MINING_DIFFICULTY) {
+        CheckResult::new("chain params", CheckStatus::Warn,
+            format!("difficulty {} needs more than 2^32 hashes per block", difficulty))
+    } else {
+        CheckResult::new("chain params", CheckStatus::Pass,
+            format!("difficulty {}, genesis tonce {} of 1-{}, {}", difficulty, tonce, max_tonce, genesis))
+    }
+}
+
+// the genesis block the node would start from: the stored one, which must be the one the params fix
+fn genesis_block(params: &ChainParams, block_store: Option<&Path>) -> Result<Option<Block>, String> {
+    let stored = match block_store {
+        Some(dir) if BlockStore::exists(dir) => {
+            let store = BlockStore::open_read_only(dir)?;
+            if store.is_empty() { None } else { Some(store.block(0)?) }
+        }
+        _ => None,
+    };
+    let configured = params.genesis.as_ref().map(|genesis| genesis.block());
+
+    match (stored, configured) {
+        (Some(stored), Some(configured)) if stored.hash != configured.hash => Err(format!(
+            "block 0 in the block store is {}, but the chain params fix genesis {}", stored.hash, configured.hash)),
+        (stored, configured) => Ok(stored.or(configured)),
+    }
+}
+
+// replay `genesis` as the first block of a chain following `params`
+fn check_genesis(params: &ChainParams, genesis: Block) -> Result<BlockHash, String> {
+    if genesis.header.bits != params.difficulty {
+        return Err(format!("genesis difficulty {} doesn't match the params' {}", genesis.header.bits, params.difficulty));
+    }
+    let coinbase_value = params.genesis.as_ref().map_or(params.coinbase_value, |genesis| genesis.premine_total());
+    if !genesis.body.transactions.first().is_some_and(|coinbase| coinbase.is_coinbase_with_value(coinbase_value)) {
+        return Err(format!("genesis coinbase doesn't pay the params' {}", coinbase_value));
+    }
+
+    let hash = genesis.hash;
+    let mut chain = Blockchain::new_with_params(params);
+    chain.update_with_block(genesis).map_err(|e| format!("genesis block is invalid: {:?}", e))?;
+    chain.verify_chain().map_err(|(_, e)| format!("genesis block fails verification: {:?}", e))?;
+    Ok(hash)
+}
+
+/// Make sure the leap second table still covers the present
+pub fn check_leap_seconds() -> CheckResult {
+    let table = current_table();
+    let expires = table.expires_at().map_or("unknown".to_string(), |at| at.format("%Y-%m-%d").to_string());
+
+    if table.is_expired(now_utc() as i64 / 1000) {
+        return CheckResult::new("leap seconds", CheckStatus::Warn,
+            format!("table expired {}; leap seconds announced since may be missing", expires));
+    }
+    match next_leap_second() {
+        Some(at) => CheckResult::new("leap seconds", CheckStatus::Pass,
+            format!("{} entries, next leap second {}, valid until {}", table.len(), at.format("%Y-%m-%d"), expires)),
+        None => CheckResult::new("leap seconds", CheckStatus::Pass,
+            format!("{} entries, none scheduled, valid until {}", table.len(), expires)),
+    }
+}
+
+/// Sample the TAI clock and make sure it never goes backwards
+pub fn check_clock_monotonicity() -> CheckResult {
+    let mut prev = now();
+    for _ in 0..CLOCK_SAMPLES {
+        let current = now();
+        if current < prev {
+            return CheckResult::new("clock", CheckStatus::Fail,
+                format!("clock went backwards by {}ms", prev - current));
+        }
+        prev = current;
+    }
+
+    if is_near_leap_second(now_utc() as i64) {
+        CheckResult::new("clock", CheckStatus::Warn, "monotonic, but within 1s of a leap second")
+    } else {
+        CheckResult::new("clock", CheckStatus::Pass, format!("monotonic over {} samples", CLOCK_SAMPLES))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_passed() {
+        let mut report = DoctorReport::default();
+        report.checks.push(CheckResult::new("a", CheckStatus::Pass, ""));
+        report.checks.push(CheckResult::new("b", CheckStatus::Warn, ""));
+        report.checks.push(CheckResult::new("c", CheckStatus::Skip, ""));
+        assert!(report.passed());
+
+        report.checks.push(CheckResult::new("d", CheckStatus::Fail, ""));
+        assert!(!report.passed());
+        assert_eq!(report.count(CheckStatus::Fail), 1);
+    }
+
+    #[test]
+    fn test_check_storage() {
+        use crate::tx_builder::TransactionBuilder;
+        use crate::Block;
+
+        assert_eq!(check_storage(None).status, CheckStatus::Skip);
+
+        let dir = std::env::temp_dir().join(format!("hourcoin-doctor-storage-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(check_storage(Some(&dir)).status, CheckStatus::Pass);
+
+        let mut store = BlockStore::open(&dir).unwrap();
+        let mut prev_block_hash = BlockHash::ZERO;
+        for index in 0..3 {
+            let timestamp = 1_700_000_000_000 + u128::from(index) * 1000;
+            let coinbase = TransactionBuilder::new(timestamp).pay_to("alice", 2.0).build_coinbase().unwrap();
+            // the last block doesn't link to the one before it
+            let prev = if index == 2 { BlockHash::ZERO } else { prev_block_hash };
+            let mut block = Block::new(index, timestamp, prev, vec![coinbase]);
+            block.mine(Difficulty::EASIEST);
+            prev_block_hash = block.hash;
+            store.append(&block).unwrap();
+            store.sync().unwrap();
+
+            let check = check_storage(Some(&dir));
+            if index < 2 {
+                assert_eq!(check.status, CheckStatus::Pass, "{}", check.detail);
+                assert!(check.detail.starts_with(&format!("{} blocks", index + 1)));
+            } else {
+                assert_eq!(check.status, CheckStatus::Fail);
+                assert!(check.detail.contains("doesn't link"));
+            }
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_check_port() {
+        assert_eq!(check_port("127.0.0.1:0").status, CheckStatus::Pass);
+        assert_eq!(check_port("not an address").status, CheckStatus::Fail);
+    }
+
+    #[test]
+    fn test_check_port_in_use() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        assert_eq!(check_port(&address).status, CheckStatus::Fail);
+    }
+
+    #[test]
+    fn test_check_key_material() {
+        let secret = hex::encode(NodeKey::generate().secret_bytes());
+        assert_eq!(check_key_material(Some(&secret)).status, CheckStatus::Pass);
+        assert_eq!(check_key_material(Some("zz")).status, CheckStatus::Fail);
+        assert_eq!(check_key_material(None).status, CheckStatus::Warn);
+    }
+
+    #[test]
+    fn test_check_chain_params() {
+        let params = |target| ChainParams::mainnet().with_difficulty(Difficulty::from_target(target));
+        assert_eq!(check_chain_params(&params(0), None).status, CheckStatus::Fail);
+        assert_eq!(check_chain_params(&params(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF), None).status, CheckStatus::Pass);
+        assert_eq!(check_chain_params(&params(0xFF), None).status, CheckStatus::Warn);
+        assert_eq!(check_chain_params(&ChainParams::regtest(), None).status, CheckStatus::Pass);
+
+        // the tonce width comes from the params, and a bad one fails rather than panicking
+        let mut wide = params(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+        wide.tonce_bits = 8;
+        assert!(check_chain_params(&wide, None).detail.contains("of 1-255"));
+        wide.tonce_bits = 9;
+        assert_eq!(check_chain_params(&wide, None).status, CheckStatus::Fail);
+
+        let mut timing = ChainParams::regtest();
+        timing.tonce_challenge_duration_ms = 0;
+        assert_eq!(check_chain_params(&timing, None).status, CheckStatus::Fail);
+        timing = ChainParams::regtest();
+        timing.lockout_duration_ms = u128::MAX;
+        assert!(check_chain_params(&timing, None).detail.contains("lockout duration"));
+    }
+
+    #[test]
+    fn test_check_chain_params_genesis() {
+        use crate::chain_params::GenesisConfig;
+        use crate::tx_builder::TransactionBuilder;
+
+        let params = ChainParams::regtest();
+        let dir = std::env::temp_dir().join(format!("hourcoin-doctor-genesis-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store_genesis = |coinbase_value: f64| {
+            let _ = std::fs::remove_dir_all(&dir);
+            let coinbase = TransactionBuilder::new(1_700_000_000_000).pay_to("alice", coinbase_value).build_coinbase().unwrap();
+            let mut block = Block::new(0, 1_700_000_000_000, BlockHash::ZERO, vec![coinbase]);
+            block.mine(params.difficulty);
+            let mut store = BlockStore::open(&dir).unwrap();
+            store.append(&block).unwrap();
+            store.sync().unwrap();
+            block.hash
+        };
+
+        assert!(check_chain_params(&params, Some(&dir)).detail.contains("no genesis block yet"));
+
+        let hash = store_genesis(params.coinbase_value);
+        let check = check_chain_params(&params, Some(&dir));
+        assert_eq!(check.status, CheckStatus::Pass, "{}", check.detail);
+        assert!(check.detail.contains(&hash.to_string()));
+
+        // a stored genesis mined under other params
+        let harder = params.clone().with_difficulty(Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF));
+        assert!(check_chain_params(&harder, Some(&dir)).detail.contains("genesis difficulty"));
+        store_genesis(params.coinbase_value + 1.0);
+        assert!(check_chain_params(&params, Some(&dir)).detail.contains("coinbase"));
+
+        // a configured genesis must be the one in the store
+        let mut config = GenesisConfig::new(1_600_000_000_000, "genesis").with_premine("alice", 5.0);
+        config.mine(params.difficulty);
+        let fixed = params.clone().with_genesis(config);
+        assert_eq!(check_chain_params(&fixed, None).status, CheckStatus::Pass);
+        let check = check_chain_params(&fixed, Some(&dir));
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(check.detail.contains("block 0 in the block store"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_check_clock_monotonicity() {
+        assert_ne!(check_clock_monotonicity().status, CheckStatus::Fail);
+    }
+}
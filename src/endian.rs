@@ -0,0 +1,74 @@
+//! Byte order for hashed integers
+//!
+//! Signing preimages, tonce inputs and the proof of work check all lay
+//! integers out least significant byte first. This module is the one place
+//! that order is chosen: writers return fixed-size arrays, and readers check
+//! the input length and return an error instead of indexing past the end.
+//!
+//! The canonical wire encoding (see `canonical`) is big-endian and separate.
+
+use std::convert::TryInto;
+use crate::hashes::HASH_LEN;
+
+pub fn u32_bytes(u: &u32) -> [u8; 4] {
+    u.to_le_bytes()
+}
+
+pub fn u64_bytes(u: &u64) -> [u8; 8] {
+    u.to_le_bytes()
+}
+
+pub fn u128_bytes(u: &u128) -> [u8; 16] {
+    u.to_le_bytes()
+}
+
+/// Exactly `N` bytes, or an error naming what was being read
+fn exact<const N: usize>(bytes: &[u8], what: &str) -> Result<[u8; N], String> {
+    bytes.try_into()
+        .map_err(|_| format!("{} must be {} bytes, got {}", what, N, bytes.len()))
+}
+
+pub fn u32_from_bytes(bytes: &[u8]) -> Result<u32, String> {
+    exact(bytes, "u32").map(u32::from_le_bytes)
+}
+
+pub fn u64_from_bytes(bytes: &[u8]) -> Result<u64, String> {
+    exact(bytes, "u64").map(u64::from_le_bytes)
+}
+
+pub fn u128_from_bytes(bytes: &[u8]) -> Result<u128, String> {
+    exact(bytes, "u128").map(u128::from_le_bytes)
+}
+
+/// The value a hash is compared against the difficulty with: its last 16 bytes, little-endian
+pub fn difficulty_bytes_as_u128(hash: &[u8]) -> Result<u128, String> {
+    if hash.len() != HASH_LEN {
+        return Err(format!("Hash must be {} bytes, got {}", HASH_LEN, hash.len()));
+    }
+    u128_from_bytes(&hash[HASH_LEN - 16..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_little_endian() {
+        assert_eq!(u32_bytes(&0x0403_0201), [1, 2, 3, 4]);
+        assert_eq!(u64_from_bytes(&u64_bytes(&u64::MAX)).unwrap(), u64::MAX);
+        assert_eq!(u128_from_bytes(&u128_bytes(&0x1234)).unwrap(), 0x1234);
+
+        assert!(u32_from_bytes(&[1, 2, 3]).is_err());
+        assert!(u64_from_bytes(&[0; 9]).is_err());
+    }
+
+    #[test]
+    fn test_difficulty_reads_last_half() {
+        let mut hash = [0xff; HASH_LEN];
+        hash[16..].copy_from_slice(&u128_bytes(&7));
+        assert_eq!(difficulty_bytes_as_u128(&hash).unwrap(), 7);
+
+        assert!(difficulty_bytes_as_u128(&hash[..31]).is_err());
+        assert!(difficulty_bytes_as_u128(&[]).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! How long mining a block is likely to take
+//!
+//! Every hash meets a difficulty with the same small chance, whatever came
+//! before it, so the hashes a block needs follow a geometric distribution:
+//! most blocks are found well before the mean and a few take several times as
+//! long. The same holds for timestamps and the tonce, since a timestamp passes
+//! a tonce of n about once in n tries. Both estimates give the whole
+//! distribution, so a display can say "half the time within 40s, nine times
+//! in ten within 2m" instead of quoting a mean that most rounds miss.
+
+use std::time::Duration;
+use crate::Difficulty;
+
+/// Trials until the first success, each succeeding with `probability`
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Geometric {
+    probability: f64,
+}
+
+impl Geometric {
+    fn mean(&self) -> f64 {
+        1.0 / self.probability
+    }
+
+    /// Chance of a success within `trials` trials
+    fn probability_within(&self, trials: f64) -> f64 {
+        if self.probability >= 1.0 {
+            return if trials >= 1.0 { 1.0 } else { 0.0 };
+        }
+        -(trials.max(0.0) * (-self.probability).ln_1p()).exp_m1()
+    }
+
+    /// Fewest trials that succeed with at least `probability` (clamped below 1)
+    fn quantile(&self, probability: f64) -> f64 {
+        if self.probability >= 1.0 {
+            return 1.0;
+        }
+        let probability = probability.clamp(0.0, 1.0 - f64::EPSILON);
+        ((-probability).ln_1p() / (-self.probability).ln_1p()).ceil().max(1.0)
+    }
+}
+
+/// How long mining at a difficulty takes at a given hashrate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MiningEstimate {
+    hashes: Geometric,
+    /// Hashes per second
+    pub hashrate: f64,
+}
+
+/// Estimate the time to mine a block at `difficulty`, hashing `hashrate` times a second
+pub fn estimate_mining_time(difficulty: Difficulty, hashrate: f64) -> MiningEstimate {
+    MiningEstimate {
+        hashes: Geometric { probability: 1.0 / difficulty.work() as f64 },
+        hashrate,
+    }
+}
+
+impl MiningEstimate {
+    /// Hashes a block takes on average (the difficulty's work)
+    pub fn expected_hashes(&self) -> f64 {
+        self.hashes.mean()
+    }
+
+    /// Average time to a block, or `None` without a hashrate
+    pub fn mean(&self) -> Option<Duration> {
+        self.duration(self.hashes.mean())
+    }
+
+    /// Time within which half of all blocks are found
+    pub fn median(&self) -> Option<Duration> {
+        self.quantile(0.5)
+    }
+
+    /// Time within which a block is found with `probability`, e.g. 0.9 for nine times in ten
+    pub fn quantile(&self, probability: f64) -> Option<Duration> {
+        self.duration(self.hashes.quantile(probability))
+    }
+
+    /// Chance of finding a block within `time`
+    pub fn probability_within(&self, time: Duration) -> f64 {
+        self.hashes.probability_within(time.as_secs_f64() * self.hashrate.max(0.0))
+    }
+
+    fn duration(&self, hashes: f64) -> Option<Duration> {
+        (self.hashrate > 0.0).then(|| Duration::try_from_secs_f64(hashes / self.hashrate).unwrap_or(Duration::MAX))
+    }
+}
+
+/// How many timestamps a miner tries before one passes a tonce
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TonceEstimate {
+    pub tonce: u8,
+    attempts: Geometric,
+}
+
+/// Estimate the timestamps tried before one passes `tonce`
+///
+/// A tonce of 0 or 1 passes every timestamp.
+pub fn estimate_tonce_attempts(tonce: u8) -> TonceEstimate {
+    TonceEstimate {
+        tonce,
+        attempts: Geometric { probability: 1.0 / f64::from(tonce.max(1)) },
+    }
+}
+
+impl TonceEstimate {
+    /// Chance that any one timestamp passes
+    pub fn pass_probability(&self) -> f64 {
+        self.attempts.probability
+    }
+
+    /// Timestamps tried on average
+    pub fn mean(&self) -> f64 {
+        self.attempts.mean()
+    }
+
+    /// Timestamps within which half of all searches succeed
+    pub fn median(&self) -> u64 {
+        self.quantile(0.5)
+    }
+
+    /// Timestamps within which a search succeeds with `probability`
+    pub fn quantile(&self, probability: f64) -> u64 {
+        self.attempts.quantile(probability) as u64
+    }
+
+    /// Chance that one of `attempts` timestamps passes
+    pub fn probability_within(&self, attempts: u64) -> f64 {
+        self.attempts.probability_within(attempts as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tonce::passes_tonce;
+
+    #[test]
+    fn test_mining_time_distribution() {
+        let difficulty = Difficulty::from_target(0x00000FFFFFFFFFFFFFFFFFFFFFFFFFFF);
+        let estimate = estimate_mining_time(difficulty, difficulty.work() as f64 / 60.0);
+        assert_eq!(estimate.expected_hashes(), difficulty.work() as f64);
+        assert_eq!(estimate.mean().unwrap().as_secs(), 60);
+
+        // Most blocks come before the mean, a few long after it
+        let median = estimate.median().unwrap().as_secs_f64();
+        assert!((median - 60.0 * 2f64.ln()).abs() < 0.1, "{}", median);
+        assert!((estimate.probability_within(Duration::from_secs(60)) - 0.632).abs() < 0.001);
+        let p90 = estimate.quantile(0.9).unwrap();
+        assert!((estimate.probability_within(p90) - 0.9).abs() < 0.001);
+        assert!(p90 > Duration::from_secs(120));
+
+        assert_eq!(estimate_mining_time(difficulty, 0.0).mean(), None);
+        assert_eq!(estimate_mining_time(difficulty, 0.0).probability_within(Duration::from_secs(60)), 0.0);
+    }
+
+    #[test]
+    fn test_tonce_attempts() {
+        let estimate = estimate_tonce_attempts(8);
+        assert_eq!(estimate.mean(), 8.0);
+        // 1 - (7/8)^n first reaches one half at n = 6
+        assert_eq!(estimate.median(), 6);
+        assert!(estimate.probability_within(5) < 0.5 && estimate.probability_within(6) >= 0.5);
+        assert!(estimate.quantile(0.99) > 30);
+
+        for tonce in [0, 1] {
+            let estimate = estimate_tonce_attempts(tonce);
+            assert_eq!((estimate.pass_probability(), estimate.median(), estimate.quantile(0.99)), (1.0, 1, 1));
+        }
+
+        // Close to what searching real timestamps takes
+        let passed = (0..80_000u128).filter(|timestamp| passes_tonce(8, *timestamp)).count();
+        assert!((passed as f64 / 80_000.0 - estimate.pass_probability()).abs() < 0.01);
+    }
+}
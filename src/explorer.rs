@@ -0,0 +1,791 @@
+//! Block explorer
+//!
+//! The `explorer` binary serves a chain as small HTML pages, and the same
+//! data as JSON when a path is prefixed with `/api`:
+//!
+//! - `/` or `/blocks?before=<height>` — the newest blocks, `BLOCKS_PER_PAGE` at a time
+//! - `/blocks/<height>` — a block's header and transactions
+//! - `/tx/<txid>?height=<height>` — a transaction and its merkle proof
+//! - `/address/<address>` — an address's balance and history
+//! - `/storage` — hits and misses of a block store's caches
+//!
+//! Pages come from an `ExplorerSource`. A `ChainIndex`, loaded from a chain
+//! file or a validator snapshot, holds every block in full. A validator asked
+//! over the miner protocol only answers for headers, balances, histories and
+//! merkle proofs, so its block pages have no transaction list, and its
+//! transaction pages need the block height (the links on address pages carry
+//! it) and show the verified proof in place of the outputs.
+//!
+//! A `BlockStore` written by a validator (see `block_store`) is read in
+//! place, a block at a time, so it serves chains too long to load. Block and
+//! transaction pages are complete, but transactions need the block height
+//! there too, and there are no address pages. New blocks the validator
+//! stores show up on the next request, and hot blocks are answered from the
+//! store's caches.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info};
+use crate::block::BlockHeader;
+use crate::block_store::{BlockCacheStats, BlockStore};
+use crate::merkle::MerkleProof;
+use crate::network::protocol::{AddressTxData, MerkleProofData, OutputData};
+use crate::snapshot::ValidatorSnapshot;
+use crate::{tai_to_utc_millis, Block, BlockHash, Blockchain, ChainParams, Difficulty, Hashable, MinerClient, Transaction, Txid, Validator};
+
+/// Blocks on one page of the block list
+pub const BLOCKS_PER_PAGE: u32 = 20;
+
+/// Requests larger than this are refused
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+/// Longest a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const HTML: &str = "text/html; charset=utf-8";
+const JSON: &str = "application/json";
+
+const STYLE: &str = "body{font-family:sans-serif;max-width:64em;margin:1em auto;padding:0 1em}\
+td,th{text-align:left;padding:.2em .8em .2em 0}code{word-break:break-all}.error{color:#b00}";
+
+/// A page of the explorer
+#[derive(Debug, Clone, PartialEq)]
+pub enum Page {
+    /// The newest blocks below height `before`, or below the tip
+    Blocks { before: Option<u32> },
+    Block(u32),
+    /// A transaction, in the block at `height` if given
+    Transaction { txid: Txid, height: Option<u32> },
+    Address(String),
+    /// The block store's cache metrics
+    Storage,
+}
+
+/// What a page is served as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Html,
+    Json,
+}
+
+impl Format {
+    /// JSON for paths under `/api`, HTML for the rest
+    pub fn of_path(path: &str) -> Format {
+        match path.strip_prefix("/api") {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') || rest.starts_with('?') => Format::Json,
+            _ => Format::Html,
+        }
+    }
+}
+
+impl Page {
+    /// The page at `path`, which may include a query string
+    pub fn parse(path: &str) -> Result<Page, ExplorerError> {
+        let (path, query) = path.split_once('?').unwrap_or((path, ""));
+        let path = match Format::of_path(path) {
+            Format::Json => &path["/api".len()..],
+            Format::Html => path,
+        };
+        let param = |name: &str| query.split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value);
+        let height = |value: &str| value.parse::<u32>()
+            .map_err(|_| ExplorerError::BadRequest(format!("Invalid height '{}'", value)));
+
+        let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+        match segments.as_slice() {
+            [] | ["blocks"] => Ok(Page::Blocks { before: param("before").map(height).transpose()? }),
+            ["blocks", block] => Ok(Page::Block(height(block)?)),
+            ["tx", txid] => Ok(Page::Transaction {
+                txid: txid.parse().map_err(ExplorerError::BadRequest)?,
+                height: param("height").map(height).transpose()?,
+            }),
+            ["address", address] => Ok(Page::Address(address.to_string())),
+            ["storage"] => Ok(Page::Storage),
+            _ => Err(ExplorerError::NotFound(format!("No page at {}", path))),
+        }
+    }
+}
+
+/// Why a page couldn't be served
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExplorerError {
+    BadRequest(String),
+    NotFound(String),
+    /// The source can't answer this, like a validator asked for a transaction's outputs
+    Unavailable(String),
+    /// The validator couldn't be reached, or sent something unusable
+    Source(String),
+}
+
+impl ExplorerError {
+    /// The HTTP status to answer with
+    pub fn status(&self) -> u16 {
+        match self {
+            ExplorerError::BadRequest(_) => 400,
+            ExplorerError::NotFound(_) => 404,
+            ExplorerError::Unavailable(_) => 501,
+            ExplorerError::Source(_) => 502,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ExplorerError::BadRequest(message)
+            | ExplorerError::NotFound(message)
+            | ExplorerError::Unavailable(message)
+            | ExplorerError::Source(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for ExplorerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+/// A row of the block list
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockSummary {
+    pub height: u32,
+    pub hash: BlockHash,
+    pub timestamp: u128,
+    pub bits: Difficulty,
+    /// Number of transactions; None when the source only has headers
+    pub transactions: Option<usize>,
+}
+
+impl BlockSummary {
+    fn new(header: &BlockHeader, transactions: Option<usize>) -> Self {
+        BlockSummary {
+            height: header.index,
+            hash: header.hash(),
+            timestamp: header.timestamp,
+            bits: header.bits,
+            transactions,
+        }
+    }
+}
+
+/// One page of blocks, newest first
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockList {
+    pub blocks: Vec<BlockSummary>,
+    /// `before` for the page of older blocks, if there are any
+    pub older: Option<u32>,
+}
+
+/// A row of a block's transaction list
+#[derive(Debug, Clone, Serialize)]
+pub struct TransactionSummary {
+    pub txid: Txid,
+    pub inputs: usize,
+    pub outputs: usize,
+    /// Sum of the outputs
+    pub value: f64,
+}
+
+/// A block's header and, when the source has it, its transactions
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockDetail {
+    pub height: u32,
+    pub hash: BlockHash,
+    pub prev_block_hash: BlockHash,
+    pub merkle_root: BlockHash,
+    pub timestamp: u128,
+    pub bits: Difficulty,
+    pub nonce: u64,
+    pub transactions: Option<Vec<TransactionSummary>>,
+}
+
+impl BlockDetail {
+    fn new(header: &BlockHeader, transactions: Option<&[Transaction]>) -> Self {
+        BlockDetail {
+            height: header.index,
+            hash: header.hash(),
+            prev_block_hash: header.prev_block_hash,
+            merkle_root: header.merkle_root,
+            timestamp: header.timestamp,
+            bits: header.bits,
+            nonce: header.nonce.0,
+            transactions: transactions.map(|transactions| transactions.iter()
+                .map(|transaction| TransactionSummary {
+                    txid: transaction.hash(),
+                    inputs: transaction.inputs.len(),
+                    outputs: transaction.outputs.len(),
+                    value: transaction.outputs.iter().map(|output| output.value).sum(),
+                })
+                .collect()),
+        }
+    }
+}
+
+/// A transaction, where it was confirmed and the proof that it was
+#[derive(Debug, Clone, Serialize)]
+pub struct TransactionDetail {
+    pub txid: Txid,
+    pub height: u32,
+    pub block_hash: BlockHash,
+    /// None when the source only has proofs
+    pub inputs: Option<Vec<OutputData>>,
+    pub outputs: Option<Vec<OutputData>>,
+    /// Proof of inclusion under the block's merkle root
+    pub proof: MerkleProofData,
+}
+
+/// An address's balance and the transactions touching it, oldest first
+#[derive(Debug, Clone, Serialize)]
+pub struct AddressPage {
+    pub address: String,
+    pub balance: f64,
+    pub transactions: Vec<AddressTxData>,
+}
+
+/// The data behind a page, which is what `/api` paths return
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum PageData {
+    Blocks(BlockList),
+    Block(BlockDetail),
+    Transaction(TransactionDetail),
+    Address(AddressPage),
+    Storage(BlockCacheStats),
+}
+
+/// A chain held in full, with every transaction indexed by hash
+pub struct ChainIndex {
+    blockchain: Blockchain,
+    // height of the block holding each transaction, and its position there
+    transactions: HashMap<Txid, (u32, usize)>,
+}
+
+impl ChainIndex {
+    pub fn new(blockchain: Blockchain) -> Self {
+        let transactions = blockchain.blocks.iter()
+            .flat_map(|block| block.body.transactions.iter().enumerate()
+                .map(move |(position, transaction)| (transaction.hash(), (block.header.index, position))))
+            .collect();
+        ChainIndex { blockchain, transactions }
+    }
+
+    /// Replay a chain file (see `chain_file`) under `params`
+    pub fn from_chain_file(path: &Path, params: &ChainParams) -> Result<Self, String> {
+        let mut blockchain = Blockchain::new_with_params(params);
+        blockchain.import(path)?;
+        Ok(Self::new(blockchain))
+    }
+
+    /// Restore a validator's snapshot (see `snapshot`) under `params`
+    pub fn from_snapshot(path: &Path, params: ChainParams) -> Result<Self, String> {
+        let mut validator = Validator::new_with_params(params);
+        validator.restore(ValidatorSnapshot::load(path)?)?;
+        Ok(Self::new(validator.blockchain))
+    }
+
+    pub fn blockchain(&self) -> &Blockchain {
+        &self.blockchain
+    }
+
+    fn page(&self, page: &Page) -> Result<PageData, ExplorerError> {
+        let blocks = &self.blockchain.blocks;
+        match page {
+            Page::Blocks { before } => {
+                let (heights, older) = page_heights(blocks.len() as u32, *before);
+                let blocks = heights.rev()
+                    .map(|height| &blocks[height as usize])
+                    .map(|block| BlockSummary::new(&block.header, Some(block.body.transactions.len())))
+                    .collect();
+                Ok(PageData::Blocks(BlockList { blocks, older }))
+            }
+            Page::Block(height) => {
+                let block = blocks.get(*height as usize)
+                    .ok_or_else(|| ExplorerError::NotFound(format!("No block at height {}", height)))?;
+                Ok(PageData::Block(BlockDetail::new(&block.header, Some(&block.body.transactions))))
+            }
+            Page::Transaction { txid, height } => {
+                let (block_height, position) = self.transactions.get(txid)
+                    .filter(|(block_height, _)| height.is_none_or(|height| height == *block_height))
+                    .copied()
+                    .ok_or_else(|| ExplorerError::NotFound(format!("No transaction {}", txid)))?;
+                let block = &blocks[block_height as usize];
+                let transaction = &block.body.transactions[position];
+                let proof = MerkleProof::new(&block.body.transaction_hashes(), position)
+                    .expect("indexed transactions are in their block");
+                Ok(PageData::Transaction(TransactionDetail {
+                    txid: *txid,
+                    height: block_height,
+                    block_hash: block.hash,
+                    inputs: Some(transaction.inputs.iter().map(OutputData::from_output).collect()),
+                    outputs: Some(transaction.outputs.iter().map(OutputData::from_output).collect()),
+                    proof: MerkleProofData::from_proof(&proof),
+                }))
+            }
+            Page::Address(address) => Ok(PageData::Address(AddressPage {
+                address: address.clone(),
+                balance: self.blockchain.balance_of(address),
+                transactions: self.blockchain.history_of(address).iter().map(AddressTxData::from_address_tx).collect(),
+            })),
+            Page::Storage => Err(no_storage()),
+        }
+    }
+}
+
+/// Where the explorer gets its pages
+pub enum ExplorerSource {
+    Chain(ChainIndex),
+    Validator(MinerClient),
+    /// A block store, opened read-only
+    Store(Mutex<BlockStore>),
+}
+
+impl ExplorerSource {
+    /// The data behind `page`
+    pub async fn page(&self, page: &Page) -> Result<PageData, ExplorerError> {
+        match self {
+            ExplorerSource::Chain(index) => index.page(page),
+            ExplorerSource::Validator(client) => validator_page(client, page).await,
+            ExplorerSource::Store(store) => store_page(&mut store.lock().unwrap(), page),
+        }
+    }
+
+    /// Status code, content type and body for a GET of `path`
+    pub async fn respond(&self, path: &str) -> (u16, &'static str, String) {
+        let format = Format::of_path(path);
+        let data = match Page::parse(path) {
+            Ok(page) => self.page(&page).await,
+            Err(e) => Err(e),
+        };
+        match (data, format) {
+            (Ok(data), Format::Json) => (200, JSON, serde_json::to_string(&data).unwrap_or_default()),
+            (Ok(data), Format::Html) => (200, HTML, render_html(&data)),
+            (Err(e), Format::Json) => (e.status(), JSON, serde_json::json!({ "error": e.message() }).to_string()),
+            (Err(e), Format::Html) => (e.status(), HTML, layout("Error", &format!("<p class=\"error\">{}</p>\n", escape(e.message())))),
+        }
+    }
+}
+
+// heights on the page of blocks below `before`, and where the next page starts
+fn page_heights(block_count: u32, before: Option<u32>) -> (Range<u32>, Option<u32>) {
+    let end = before.map_or(block_count, |before| before.min(block_count));
+    let start = end.saturating_sub(BLOCKS_PER_PAGE);
+    (start..end, Some(start).filter(|start| *start > 0))
+}
+
+fn source_error(e: Box<dyn std::error::Error>) -> ExplorerError {
+    ExplorerError::Source(e.to_string())
+}
+
+async fn validator_headers(client: &MinerClient, heights: Range<u32>) -> Result<Vec<BlockHeader>, ExplorerError> {
+    client.get_headers(heights.start, heights.end - heights.start).await
+        .map_err(source_error)?
+        .iter()
+        .map(|header| header.to_header().map_err(ExplorerError::Source))
+        .collect()
+}
+
+async fn validator_header(client: &MinerClient, height: u32) -> Result<BlockHeader, ExplorerError> {
+    validator_headers(client, height..height.saturating_add(1)).await?
+        .pop()
+        .ok_or_else(|| ExplorerError::NotFound(format!("No block at height {}", height)))
+}
+
+async fn validator_page(client: &MinerClient, page: &Page) -> Result<PageData, ExplorerError> {
+    match page {
+        Page::Blocks { before } => {
+            let (_, block_count, _) = client.get_chain_tip().await.map_err(source_error)?;
+            let (heights, older) = page_heights(block_count, *before);
+            let blocks = validator_headers(client, heights).await?.iter().rev()
+                .map(|header| BlockSummary::new(header, None))
+                .collect();
+            Ok(PageData::Blocks(BlockList { blocks, older }))
+        }
+        Page::Block(height) => Ok(PageData::Block(BlockDetail::new(&validator_header(client, *height).await?, None))),
+        Page::Transaction { height: None, .. } => Err(ExplorerError::Unavailable(
+            "A validator can only find a transaction in a given block; add ?height=<height>".to_string(),
+        )),
+        Page::Transaction { txid, height: Some(height) } => {
+            let header = validator_header(client, *height).await?;
+            let proof = client.get_transaction_proof(*height, txid.as_bytes()).await
+                .map_err(|e| ExplorerError::NotFound(e.to_string()))?;
+            if !proof.verify(txid, &header.merkle_root) {
+                return Err(ExplorerError::Source(format!("The validator's proof of {} doesn't match block {}", txid, height)));
+            }
+            Ok(PageData::Transaction(TransactionDetail {
+                txid: *txid,
+                height: *height,
+                block_hash: header.hash(),
+                inputs: None,
+                outputs: None,
+                proof: MerkleProofData::from_proof(&proof),
+            }))
+        }
+        Page::Address(address) => Ok(PageData::Address(AddressPage {
+            address: address.clone(),
+            balance: client.get_balance(address).await.map_err(source_error)?,
+            transactions: client.get_history(address).await.map_err(source_error)?,
+        })),
+        Page::Storage => Err(no_storage()),
+    }
+}
+
+fn store_block(store: &BlockStore, height: u32) -> Result<Block, ExplorerError> {
+    if height >= store.len() {
+        return Err(ExplorerError::NotFound(format!("No block at height {}", height)));
+    }
+    store.block(height).map_err(ExplorerError::Source)
+}
+
+fn store_page(store: &mut BlockStore, page: &Page) -> Result<PageData, ExplorerError> {
+    let block_count = store.refresh().map_err(ExplorerError::Source)?;
+    match page {
+        Page::Blocks { before } => {
+            let (heights, older) = page_heights(block_count, *before);
+            let blocks = heights.rev()
+                .map(|height| store_block(store, height)
+                    .map(|block| BlockSummary::new(&block.header, Some(block.body.transactions.len()))))
+                .collect::<Result<_, _>>()?;
+            Ok(PageData::Blocks(BlockList { blocks, older }))
+        }
+        Page::Block(height) => {
+            let block = store_block(store, *height)?;
+            Ok(PageData::Block(BlockDetail::new(&block.header, Some(&block.body.transactions))))
+        }
+        Page::Transaction { height: None, .. } => Err(ExplorerError::Unavailable(
+            "A block store can only find a transaction in a given block; add ?height=<height>".to_string(),
+        )),
+        Page::Transaction { txid, height: Some(height) } => {
+            let block = store_block(store, *height)?;
+            let hashes = block.body.transaction_hashes();
+            let position = hashes.iter().position(|hash| hash == txid)
+                .ok_or_else(|| ExplorerError::NotFound(format!("No transaction {} in block {}", txid, height)))?;
+            let transaction = &block.body.transactions[position];
+            let proof = MerkleProof::new(&hashes, position).expect("the transaction is in its block");
+            Ok(PageData::Transaction(TransactionDetail {
+                txid: *txid,
+                height: *height,
+                block_hash: block.hash,
+                inputs: Some(transaction.inputs.iter().map(OutputData::from_output).collect()),
+                outputs: Some(transaction.outputs.iter().map(OutputData::from_output).collect()),
+                proof: MerkleProofData::from_proof(&proof),
+            }))
+        }
+        Page::Address(_) => Err(ExplorerError::Unavailable("A block store has no address index".to_string())),
+        Page::Storage => Ok(PageData::Storage(store.cache_stats())),
+    }
+}
+
+fn no_storage() -> ExplorerError {
+    ExplorerError::Unavailable("Only a block store has caches to report on".to_string())
+}
+
+/// Serve the explorer on `address` until the listener fails
+///
+/// Requests are answered one at a time, since the validator queries behind
+/// a page can't be moved to another task.
+pub async fn serve(address: String, source: ExplorerSource) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(&address).await?;
+    info!(%address, "Explorer listening");
+
+    loop {
+        let (socket, peer) = listener.accept().await?;
+        match tokio::time::timeout(REQUEST_TIMEOUT, handle_request(socket, &source)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => debug!(%peer, error = %e, "Explorer request failed"),
+            Err(_) => debug!(%peer, "Explorer request timed out"),
+        }
+    }
+}
+
+async fn handle_request(mut socket: TcpStream, source: &ExplorerSource) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let n = socket.read(&mut buffer).await?;
+        if n == 0 || request.len() + n > MAX_REQUEST_LEN {
+            break;
+        }
+        request.extend_from_slice(&buffer[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split_whitespace();
+    let (status, content_type, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(path)) => source.respond(path).await,
+        (Some(_), Some(_)) => (405, JSON, serde_json::json!({ "error": "Only GET is supported" }).to_string()),
+        _ => (400, JSON, serde_json::json!({ "error": "Malformed request" }).to_string()),
+    };
+
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        501 => "Not Implemented",
+        _ => "Bad Gateway",
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, reason, content_type, body.len(), body
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+/// The HTML page showing `data`
+pub fn render_html(data: &PageData) -> String {
+    match data {
+        PageData::Blocks(list) => {
+            let mut body = String::from("<table>\n<tr><th>Height</th><th>Hash</th><th>Time</th><th>Difficulty</th><th>Transactions</th></tr>\n");
+            for block in &list.blocks {
+                body.push_str(&format!(
+                    "<tr><td><a href=\"/blocks/{0}\">{0}</a></td><td><code>{1}</code></td><td>{2}</td><td>{3:.2}</td><td>{4}</td></tr>\n",
+                    block.height, block.hash, format_time(block.timestamp), block.bits.relative(),
+                    block.transactions.map_or("—".to_string(), |count| count.to_string()),
+                ));
+            }
+            body.push_str("</table>\n");
+            if list.blocks.is_empty() {
+                body.push_str("<p>No blocks yet.</p>\n");
+            }
+            if let Some(older) = list.older {
+                body.push_str(&format!("<p><a href=\"/blocks?before={}\">Older blocks</a></p>\n", older));
+            }
+            layout("Blocks", &body)
+        }
+        PageData::Block(block) => {
+            let previous = match block.height.checked_sub(1) {
+                Some(height) => format!("<a href=\"/blocks/{}\"><code>{}</code></a>", height, block.prev_block_hash),
+                None => format!("<code>{}</code>", block.prev_block_hash),
+            };
+            let mut body = format!(
+                "<table>\n<tr><th>Hash</th><td><code>{}</code></td></tr>\n<tr><th>Previous</th><td>{}</td></tr>\n\
+                 <tr><th>Merkle root</th><td><code>{}</code></td></tr>\n<tr><th>Time</th><td>{} ({} ms TAI)</td></tr>\n\
+                 <tr><th>Difficulty</th><td>{:.2} (bits 0x{:08x})</td></tr>\n<tr><th>Nonce</th><td>{}</td></tr>\n</table>\n",
+                block.hash, previous, block.merkle_root, format_time(block.timestamp), block.timestamp,
+                block.bits.relative(), block.bits.to_compact(), block.nonce,
+            );
+            body.push_str("<h2>Transactions</h2>\n");
+            match &block.transactions {
+                Some(transactions) => {
+                    body.push_str("<table>\n<tr><th>Transaction</th><th>Inputs</th><th>Outputs</th><th>Value</th></tr>\n");
+                    for transaction in transactions {
+                        body.push_str(&format!(
+                            "<tr><td><a href=\"/tx/{0}?height={1}\"><code>{0}</code></a></td><td>{2}</td><td>{3}</td><td>{4}</td></tr>\n",
+                            transaction.txid, block.height, transaction.inputs, transaction.outputs, transaction.value,
+                        ));
+                    }
+                    body.push_str("</table>\n");
+                }
+                None => body.push_str("<p>This source only serves block headers.</p>\n"),
+            }
+            layout(&format!("Block {}", block.height), &body)
+        }
+        PageData::Transaction(transaction) => {
+            let mut body = format!(
+                "<table>\n<tr><th>Hash</th><td><code>{0}</code></td></tr>\n\
+                 <tr><th>Block</th><td><a href=\"/blocks/{1}\">{1}</a> <code>{2}</code></td></tr>\n</table>\n",
+                transaction.txid, transaction.height, transaction.block_hash,
+            );
+            for (title, outputs) in [("Inputs", &transaction.inputs), ("Outputs", &transaction.outputs)] {
+                body.push_str(&format!("<h2>{}</h2>\n", title));
+                match outputs {
+                    Some(outputs) if outputs.is_empty() => body.push_str("<p>None.</p>\n"),
+                    Some(outputs) => body.push_str(&output_table(outputs)),
+                    None => body.push_str("<p>This source only serves merkle proofs.</p>\n"),
+                }
+            }
+            body.push_str(&format!(
+                "<h2>Merkle proof</h2>\n<p>Leaf {} of {}, with {} sibling hashes.</p>\n",
+                transaction.proof.index, transaction.proof.leaf_count, transaction.proof.siblings.len(),
+            ));
+            layout("Transaction", &body)
+        }
+        PageData::Address(page) => {
+            let mut body = format!("<p>Balance: {}</p>\n<h2>History</h2>\n", page.balance);
+            if page.transactions.is_empty() {
+                body.push_str("<p>No transactions.</p>\n");
+            } else {
+                body.push_str("<table>\n<tr><th>Block</th><th>Time</th><th>Transaction</th><th>Received</th><th>Sent</th></tr>\n");
+                for transaction in page.transactions.iter().rev() {
+                    body.push_str(&format!(
+                        "<tr><td><a href=\"/blocks/{0}\">{0}</a></td><td>{1}</td><td><a href=\"/tx/{2}?height={0}\"><code>{2}</code></a></td><td>{3}</td><td>{4}</td></tr>\n",
+                        transaction.block_index, format_time(transaction.block_timestamp),
+                        escape(&transaction.tx_hash), transaction.received, transaction.sent,
+                    ));
+                }
+                body.push_str("</table>\n");
+            }
+            layout(&format!("Address {}", escape(&page.address)), &body)
+        }
+        PageData::Storage(stats) => {
+            let mut body = String::from("<table>\n<tr><th>Cache</th><th>Hits</th><th>Misses</th><th>Entries</th><th>Capacity</th></tr>\n");
+            for (name, cache) in [("Blocks", &stats.blocks), ("Headers", &stats.headers)] {
+                body.push_str(&format!(
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                    name, cache.hits, cache.misses, cache.entries, cache.capacity,
+                ));
+            }
+            body.push_str("</table>\n");
+            layout("Storage", &body)
+        }
+    }
+}
+
+fn output_table(outputs: &[OutputData]) -> String {
+    let mut table = String::from("<table>\n<tr><th>Address</th><th>Value</th><th>Locked until</th></tr>\n");
+    for output in outputs {
+        table.push_str(&format!(
+            "<tr><td><a href=\"/address/{0}\">{0}</a></td><td>{1}</td><td>{2}</td></tr>\n",
+            escape(&output.to_addr), output.value, output.lock_until.map_or("—".to_string(), format_time),
+        ));
+    }
+    table.push_str("</table>\n");
+    table
+}
+
+// `title` is HTML already
+fn layout(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0} · Hourcoin explorer</title><style>{1}</style></head>\n\
+         <body><nav><a href=\"/\">Hourcoin explorer</a></nav>\n<h1>{0}</h1>\n{2}</body></html>\n",
+        title, STYLE, body,
+    )
+}
+
+// a TAI timestamp as a UTC date
+fn format_time(timestamp: u128) -> String {
+    DateTime::<Utc>::from_timestamp_millis(tai_to_utc_millis(timestamp as i64))
+        .map_or_else(|| timestamp.to_string(), |time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tx_builder::TransactionBuilder;
+
+    fn chain(blocks: u32) -> ChainIndex {
+        let mut blockchain = Blockchain::new_with_diff(Difficulty::EASIEST);
+        let mut prev_block_hash = BlockHash::ZERO;
+        for index in 0..blocks {
+            let timestamp = 1_700_000_000_000 + u128::from(index) * 1000;
+            let coinbase = TransactionBuilder::new(timestamp).pay_to("alice", 2.0).build_coinbase().unwrap();
+            let mut block = Block::new(index, timestamp, prev_block_hash, vec![coinbase]);
+            block.mine(Difficulty::EASIEST);
+            prev_block_hash = block.hash;
+            blockchain.update_with_block(block).unwrap();
+        }
+        ChainIndex::new(blockchain)
+    }
+
+    #[test]
+    fn test_parse_paths() {
+        assert_eq!(Page::parse("/"), Ok(Page::Blocks { before: None }));
+        assert_eq!(Page::parse("/api/blocks?before=40"), Ok(Page::Blocks { before: Some(40) }));
+        assert_eq!(Page::parse("/blocks/7"), Ok(Page::Block(7)));
+        assert_eq!(Page::parse("/api/address/alice"), Ok(Page::Address("alice".to_string())));
+        assert_eq!(Page::parse("/api/storage"), Ok(Page::Storage));
+        let txid = Txid::digest(b"tx");
+        assert_eq!(Page::parse(&format!("/tx/{}?height=3", txid)), Ok(Page::Transaction { txid, height: Some(3) }));
+
+        assert_eq!(Format::of_path("/api/blocks/7"), Format::Json);
+        assert_eq!(Format::of_path("/apiary"), Format::Html);
+        assert_eq!(Page::parse("/blocks/tip").unwrap_err().status(), 400);
+        assert_eq!(Page::parse("/tx/abcd").unwrap_err().status(), 400);
+        assert_eq!(Page::parse("/apiary").unwrap_err().status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_chain_pages() {
+        let index = chain(25);
+        let source = ExplorerSource::Chain(index);
+
+        let (status, content_type, body) = source.respond("/api").await;
+        assert_eq!((status, content_type), (200, JSON));
+        let list: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(list["blocks"].as_array().unwrap().len(), BLOCKS_PER_PAGE as usize);
+        assert_eq!(list["blocks"][0]["height"], 24);
+        assert_eq!(list["older"], 5);
+        let list: serde_json::Value = serde_json::from_str(&source.respond("/api/blocks?before=5").await.2).unwrap();
+        assert_eq!(list["blocks"].as_array().unwrap().len(), 5);
+        assert!(list["older"].is_null());
+
+        let ExplorerSource::Chain(index) = &source else { unreachable!() };
+        let block = &index.blockchain().blocks[3];
+        let txid = block.body.transactions[0].hash();
+        let (status, content_type, html) = source.respond("/blocks/3").await;
+        assert_eq!((status, content_type), (200, HTML));
+        assert!(html.contains(&block.hash.to_string()));
+        assert!(html.contains(&format!("/tx/{}?height=3", txid)));
+
+        let detail: serde_json::Value = serde_json::from_str(&source.respond(&format!("/api/tx/{}", txid)).await.2).unwrap();
+        assert_eq!(detail["height"], 3);
+        assert_eq!(detail["outputs"][0]["to_addr"], "alice");
+        let proof: MerkleProofData = serde_json::from_value(detail["proof"].clone()).unwrap();
+        assert!(proof.to_proof().unwrap().verify(&txid, &block.header.merkle_root));
+        assert_eq!(source.respond(&format!("/api/tx/{}?height=4", txid)).await.0, 404);
+
+        let address: serde_json::Value = serde_json::from_str(&source.respond("/api/address/alice").await.2).unwrap();
+        assert_eq!(address["balance"], index.blockchain().balance_of(&"alice".to_string()));
+        assert_eq!(address["transactions"].as_array().unwrap().len(), 25);
+
+        let (status, _, html) = source.respond("/address/<script>").await;
+        assert_eq!(status, 200);
+        assert!(html.contains("&lt;script&gt;") && !html.contains("<script>"));
+        assert_eq!(source.respond("/api/blocks/25").await.0, 404);
+        assert_eq!(source.respond("/api/storage").await.0, 501);
+    }
+
+    #[tokio::test]
+    async fn test_store_pages() {
+        let dir = std::env::temp_dir().join(format!("hourcoin-explorer-blocks-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let index = chain(25);
+        let mut writer = BlockStore::open(&dir).unwrap();
+        for block in &index.blockchain().blocks[..24] {
+            writer.append(block).unwrap();
+        }
+        let source = ExplorerSource::Store(Mutex::new(BlockStore::open_read_only(&dir).unwrap()));
+
+        let list: serde_json::Value = serde_json::from_str(&source.respond("/api").await.2).unwrap();
+        assert_eq!(list["blocks"][0]["height"], 23);
+        assert_eq!(list["blocks"][0]["transactions"], 1);
+        // blocks stored since show up on the next request
+        writer.append(&index.blockchain().blocks[24]).unwrap();
+        let list: serde_json::Value = serde_json::from_str(&source.respond("/api").await.2).unwrap();
+        assert_eq!((list["blocks"][0]["height"].clone(), list["older"].clone()), (24.into(), 5.into()));
+
+        let block = &index.blockchain().blocks[3];
+        let txid = block.body.transactions[0].hash();
+        let detail: serde_json::Value = serde_json::from_str(&source.respond(&format!("/api/tx/{}?height=3", txid)).await.2).unwrap();
+        assert_eq!(detail["block_hash"], block.hash.to_string());
+        assert_eq!(detail["outputs"][0]["to_addr"], "alice");
+        assert_eq!(source.respond(&format!("/api/tx/{}?height=4", txid)).await.0, 404);
+        assert_eq!(source.respond(&format!("/api/tx/{}", txid)).await.0, 501);
+        assert_eq!(source.respond("/api/address/alice").await.0, 501);
+        assert_eq!(source.respond("/api/blocks/25").await.0, 404);
+
+        // the second list and the look in block 4 were answered from the cache
+        let stats: serde_json::Value = serde_json::from_str(&source.respond("/api/storage").await.2).unwrap();
+        assert_eq!((stats["blocks"]["hits"].clone(), stats["blocks"]["misses"].clone()), (20.into(), 22.into()));
+        assert_eq!(source.respond("/storage").await.0, 200);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
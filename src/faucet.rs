@@ -0,0 +1,250 @@
+//! Testnet faucet
+//!
+//! A validator on a test network can hand out small amounts from a funded
+//! key, so miners and wallet developers can transact without mining first.
+//! Clients send `RequestFaucet` with the address to pay; the validator builds
+//! a payment of `FaucetConfig::amount`, signs it with the faucet key and puts
+//! it in its mempool, where the next block picks it up.
+//!
+//! Each client IP and each paid address has its own token bucket, so one
+//! client can't drain the faucet by asking for many addresses or from many
+//! connections. The faucet only spends confirmed outputs that no pooled
+//! transaction spends already; its change is spendable once that block is
+//! mined, so a faucet funded with one output makes one payment per block.
+//! Fund it with several outputs to serve more requests between blocks.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use crate::hashable::Hashable;
+use crate::network::rate_limit::{RateLimit, RateLimiter};
+use crate::node_key::NodeKey;
+use crate::transaction::{Output, TX_SIGNING_CONTEXT};
+use crate::tx_builder::TransactionBuilder;
+use crate::wallet::address_from_public_key;
+use crate::{Txid, Validator};
+
+/// Longest address the faucet pays to
+const MAX_ADDRESS_LEN: usize = 128;
+
+/// What the faucet pays and how often
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaucetConfig {
+    /// Paid to each request
+    pub amount: f64,
+    /// Fee per byte the faucet's payments carry
+    pub fee_per_byte: f64,
+    /// Requests per client IP address
+    pub per_ip: RateLimit,
+    /// Payments to each address
+    pub per_address: RateLimit,
+}
+
+impl Default for FaucetConfig {
+    fn default() -> Self {
+        FaucetConfig {
+            amount: 10.0,
+            fee_per_byte: 0.0,
+            per_ip: RateLimit { burst: 5, per_second: 5.0 / 3600.0 },
+            per_address: RateLimit { burst: 1, per_second: 1.0 / 3600.0 },
+        }
+    }
+}
+
+/// Why a faucet request wasn't paid
+#[derive(Debug, Clone, PartialEq)]
+pub enum FaucetError {
+    InvalidAddress(String),
+    /// The IP or address asked too recently; try again after this long
+    RateLimited(Duration),
+    /// The faucet has nothing it can spend now
+    Unavailable(String),
+}
+
+impl fmt::Display for FaucetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FaucetError::InvalidAddress(message) | FaucetError::Unavailable(message) => f.write_str(message),
+            FaucetError::RateLimited(retry_after) => {
+                write!(f, "Faucet already paid recently; try again in {}s", retry_after.as_secs().max(1))
+            }
+        }
+    }
+}
+
+/// A payment the faucet put in the mempool
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaucetPayment {
+    pub tx_hash: Txid,
+    pub amount: f64,
+}
+
+/// Pays small amounts from one key to whoever asks, within rate limits
+pub struct Faucet {
+    key: NodeKey,
+    address: String,
+    config: FaucetConfig,
+    per_ip: RateLimiter<IpAddr>,
+    per_address: RateLimiter<String>,
+}
+
+impl Faucet {
+    /// A faucet spending the outputs paid to `key`'s address
+    pub fn new(key: NodeKey, config: FaucetConfig) -> Self {
+        let address = address_from_public_key(&key.public_key());
+        Faucet {
+            key,
+            address,
+            config,
+            per_ip: RateLimiter::new(config.per_ip),
+            per_address: RateLimiter::new(config.per_address),
+        }
+    }
+
+    /// Where to send funds for the faucet to give away
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn config(&self) -> FaucetConfig {
+        self.config
+    }
+
+    /// Confirmed outputs of the faucet that no pooled transaction spends yet
+    pub fn spendable_outputs(&self, validator: &Validator) -> Vec<Output> {
+        validator.blockchain.unspent_outputs_of(&self.address).into_iter()
+            .filter(|output| validator.mempool().spender_of(&output.hash()).is_none())
+            .collect()
+    }
+
+    /// What the faucet could pay out now, after demurrage
+    pub fn balance(&self, validator: &Validator) -> f64 {
+        let now = validator.get_current_time();
+        let demurrage_rate = validator.get_params().demurrage_rate_per_hour;
+        self.spendable_outputs(validator).iter()
+            .filter(|output| output.is_spendable_at(now))
+            .map(|output| output.spendable_value_at(now, demurrage_rate))
+            .sum()
+    }
+
+    /// Pay `address` for a request from `ip` made at `now`
+    ///
+    /// The payment is built before the rate limits are charged, so a request
+    /// the faucet can't pay doesn't use up the client's allowance.
+    pub fn request(&mut self, validator: &mut Validator, ip: IpAddr, address: &str, now: Instant) -> Result<FaucetPayment, FaucetError> {
+        let address = address.trim();
+        if address.is_empty() || address.len() > MAX_ADDRESS_LEN || address.chars().any(char::is_whitespace) {
+            return Err(FaucetError::InvalidAddress(format!("Invalid address '{}'", address)));
+        }
+        if address == self.address {
+            return Err(FaucetError::InvalidAddress("The faucet can't pay itself".to_string()));
+        }
+
+        let outputs = self.spendable_outputs(validator);
+        if outputs.is_empty() {
+            let pending = !validator.blockchain.unspent_outputs_of(&self.address).is_empty();
+            return Err(FaucetError::Unavailable(if pending {
+                "Faucet funds are waiting for the next block; try again then".to_string()
+            } else {
+                "Faucet is out of funds".to_string()
+            }));
+        }
+
+        let psbt = TransactionBuilder::new(validator.get_current_time())
+            .add_utxos(outputs)
+            .pay_to(address, self.config.amount)
+            .with_change_address(&self.address)
+            .with_fee_rate(self.config.fee_per_byte)
+            .with_demurrage(validator.get_params().demurrage_rate_per_hour)
+            .build_psbt();
+        let transaction = psbt
+            .and_then(|mut psbt| {
+                let message = psbt.signing_hash()?;
+                let signature = self.key.sign(TX_SIGNING_CONTEXT, message.as_bytes());
+                let public_key = self.key.public_key();
+                for index in 0..psbt.inputs.len() {
+                    psbt.add_signature(index, &public_key, &signature)?;
+                }
+                psbt.finalize()
+            })
+            .map_err(FaucetError::Unavailable)?;
+
+        self.per_ip.check(&ip, now).map_err(FaucetError::RateLimited)?;
+        self.per_address.check(&address.to_string(), now).map_err(FaucetError::RateLimited)?;
+
+        let tx_hash = transaction.hash();
+        validator.submit_transaction(transaction, None).map_err(FaucetError::Unavailable)?;
+        tracing::info!(%address, amount = self.config.amount, tx = %tx_hash.to_string(), "faucet paid");
+        Ok(FaucetPayment { tx_hash, amount: self.config.amount })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::chain_params::GenesisConfig;
+    use crate::time_source::MockTimeSource;
+    use crate::ChainParams;
+
+    /// A validator whose genesis pays the faucet `outputs` outputs worth 100 each
+    fn funded_validator(faucet: &Faucet, outputs: u32) -> Validator {
+        let mut params = ChainParams::regtest();
+        let mut genesis = GenesisConfig::new(40_000_000, "faucet test");
+        for n in 0..outputs {
+            // outputs of equal value, address and timestamp would have the same hash
+            genesis = genesis.with_premine(faucet.address(), 100.0 + f64::from(n) * 1e-9);
+        }
+        genesis.mine(params.difficulty);
+        params.genesis = Some(genesis);
+
+        let mut validator = Validator::new_with_params(params);
+        validator.set_time_source(Arc::new(MockTimeSource::new(50_000_000)));
+        validator.start_new_round();
+        validator
+    }
+
+    #[test]
+    fn test_faucet_pays_within_limits() {
+        let config = FaucetConfig { amount: 5.0, ..FaucetConfig::default() };
+        let mut faucet = Faucet::new(NodeKey::from_secret_bytes(&[7; 32]).unwrap(), config);
+        let mut validator = funded_validator(&faucet, 3);
+        assert!((faucet.balance(&validator) - 300.0).abs() < 1e-6);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let now = Instant::now();
+
+        let payment = faucet.request(&mut validator, ip, "alice", now).unwrap();
+        assert_eq!(payment.amount, 5.0);
+        let pooled = &validator.mempool().get(&payment.tx_hash).unwrap().transaction;
+        assert!(pooled.outputs.iter().any(|output| output.to_addr == "alice" && output.value == 5.0));
+        assert!((faucet.balance(&validator) - 200.0).abs() < 1e-6);
+
+        // the same address again, even from elsewhere, waits an hour
+        let other_ip: IpAddr = "10.0.0.2".parse().unwrap();
+        assert!(matches!(faucet.request(&mut validator, other_ip, "alice", now), Err(FaucetError::RateLimited(_))));
+        assert!(faucet.request(&mut validator, other_ip, "bob", now).is_ok());
+        assert!(faucet.request(&mut validator, other_ip, "carol", now).is_ok());
+
+        // every output is spent by a pooled payment until the next block
+        let error = faucet.request(&mut validator, ip, "dave", now).unwrap_err();
+        assert!(error.to_string().contains("next block"), "{}", error);
+        assert!(matches!(faucet.request(&mut validator, ip, "", now), Err(FaucetError::InvalidAddress(_))));
+        assert_eq!(validator.mempool().len(), 3);
+    }
+
+    #[test]
+    fn test_faucet_per_ip_limit() {
+        let config = FaucetConfig { per_ip: RateLimit { burst: 1, per_second: 0.0 }, ..FaucetConfig::default() };
+        let mut faucet = Faucet::new(NodeKey::from_secret_bytes(&[8; 32]).unwrap(), config);
+        let mut validator = funded_validator(&faucet, 2);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(faucet.request(&mut validator, ip, "alice", now).is_ok());
+        assert!(matches!(faucet.request(&mut validator, ip, "bob", now), Err(FaucetError::RateLimited(_))));
+
+        let empty = Faucet::new(NodeKey::from_secret_bytes(&[9; 32]).unwrap(), FaucetConfig::default())
+            .request(&mut validator, ip, "bob", now);
+        assert_eq!(empty, Err(FaucetError::Unavailable("Faucet is out of funds".to_string())));
+    }
+}
@@ -0,0 +1,589 @@
+//! Threshold-signature validator federation
+//!
+//! An alternative to exchanging individual votes (see `consensus`): N
+//! validators each hold a share of one group key, and any `threshold` of them
+//! can jointly produce a single ordinary Schnorr signature over a block
+//! receipt. A block is final once its receipt carries a valid group
+//! signature, which anyone can check against the federation's one public key
+//! without knowing who the members are.
+//!
+//! Signing uses FROST (RFC 9591) over ristretto255 with SHA-512 in two rounds:
+//! each participating member publishes nonce commitments, then signs the
+//! package the coordinator builds from them. The coordinator aggregates the
+//! shares into the group signature. The protocol itself is `frost-core`'s;
+//! only the ciphersuite (the ristretto255 group and the RFC's hash functions)
+//! is defined here, because the audited `frost-ristretto255` crate that
+//! provides it isn't among this crate's dependencies yet.
+//!
+//! The federation's public description (`Federation::save`) holds no
+//! secrets. Each member's 32-byte signing share is kept in an encrypted
+//! `KeyKind::FederationShare` key file and rebuilt into a `FederationMember`
+//! with `FederationMember::from_secret_share`.
+
+use std::collections::BTreeMap;
+use std::convert::{TryFrom, TryInto};
+use std::path::Path;
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::Identity;
+use frost_core::keys::{IdentifierList, KeyPackage, PublicKeyPackage, SigningShare, VerifyingShare};
+use frost_core::round1::{SigningCommitments, SigningNonces};
+use frost_core::round2::SignatureShare;
+use frost_core::{Ciphersuite, Field, FieldError, Group, GroupError, Identifier, Signature, SigningPackage};
+use rand::rngs::OsRng;
+use rand::{CryptoRng, RngCore};
+use sha2::{Digest, Sha512};
+use crate::receipt::BlockReceipt;
+use crate::BlockHash;
+
+/// Context string from RFC 9591 section 6.2
+const CONTEXT_STRING: &str = "FROST-RISTRETTO255-SHA512-v1";
+
+/// The ristretto255 scalar field
+#[derive(Clone, Copy)]
+pub struct RistrettoScalarField;
+
+impl Field for RistrettoScalarField {
+    type Scalar = Scalar;
+    type Serialization = [u8; 32];
+
+    fn zero() -> Self::Scalar {
+        Scalar::ZERO
+    }
+
+    fn one() -> Self::Scalar {
+        Scalar::ONE
+    }
+
+    fn invert(scalar: &Self::Scalar) -> Result<Self::Scalar, FieldError> {
+        if *scalar == Scalar::ZERO {
+            Err(FieldError::InvalidZeroScalar)
+        } else {
+            Ok(scalar.invert())
+        }
+    }
+
+    fn random<R: RngCore + CryptoRng>(rng: &mut R) -> Self::Scalar {
+        Scalar::random(rng)
+    }
+
+    fn serialize(scalar: &Self::Scalar) -> Self::Serialization {
+        scalar.to_bytes()
+    }
+
+    fn little_endian_serialize(scalar: &Self::Scalar) -> Self::Serialization {
+        scalar.to_bytes()
+    }
+
+    fn deserialize(buf: &Self::Serialization) -> Result<Self::Scalar, FieldError> {
+        Option::from(Scalar::from_canonical_bytes(*buf)).ok_or(FieldError::MalformedScalar)
+    }
+}
+
+/// The ristretto255 prime-order group
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct RistrettoGroup;
+
+impl Group for RistrettoGroup {
+    type Field = RistrettoScalarField;
+    type Element = RistrettoPoint;
+    type Serialization = [u8; 32];
+
+    fn cofactor() -> Scalar {
+        Scalar::ONE
+    }
+
+    fn identity() -> Self::Element {
+        RistrettoPoint::identity()
+    }
+
+    fn generator() -> Self::Element {
+        RISTRETTO_BASEPOINT_POINT
+    }
+
+    fn serialize(element: &Self::Element) -> Result<Self::Serialization, GroupError> {
+        if *element == Self::identity() {
+            return Err(GroupError::InvalidIdentityElement);
+        }
+        Ok(element.compress().to_bytes())
+    }
+
+    fn deserialize(buf: &Self::Serialization) -> Result<Self::Element, GroupError> {
+        match CompressedRistretto::from_slice(buf.as_ref()).ok().and_then(|c| c.decompress()) {
+            Some(point) if point == Self::identity() => Err(GroupError::InvalidIdentityElement),
+            Some(point) => Ok(point),
+            None => Err(GroupError::MalformedElement),
+        }
+    }
+}
+
+/// Hash `CONTEXT_STRING || tag || m` with SHA-512
+fn hash_with_tag(tag: &[u8], m: &[u8]) -> [u8; 64] {
+    let mut hasher = Sha512::new();
+    hasher.update(CONTEXT_STRING.as_bytes());
+    hasher.update(tag);
+    hasher.update(m);
+    hasher.finalize().into()
+}
+
+fn hash_to_scalar(tag: &[u8], m: &[u8]) -> Scalar {
+    Scalar::from_bytes_mod_order_wide(&hash_with_tag(tag, m))
+}
+
+/// FROST(ristretto255, SHA-512) ciphersuite
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Ristretto255Sha512;
+
+impl Ciphersuite for Ristretto255Sha512 {
+    const ID: &'static str = CONTEXT_STRING;
+
+    type Group = RistrettoGroup;
+    type HashOutput = [u8; 64];
+    type SignatureSerialization = [u8; 64];
+
+    fn H1(m: &[u8]) -> Scalar {
+        hash_to_scalar(b"rho", m)
+    }
+
+    fn H2(m: &[u8]) -> Scalar {
+        hash_to_scalar(b"chal", m)
+    }
+
+    fn H3(m: &[u8]) -> Scalar {
+        hash_to_scalar(b"nonce", m)
+    }
+
+    fn H4(m: &[u8]) -> Self::HashOutput {
+        hash_with_tag(b"msg", m)
+    }
+
+    fn H5(m: &[u8]) -> Self::HashOutput {
+        hash_with_tag(b"com", m)
+    }
+
+    fn HDKG(m: &[u8]) -> Option<Scalar> {
+        Some(hash_to_scalar(b"dkg", m))
+    }
+
+    fn HID(m: &[u8]) -> Option<Scalar> {
+        Some(hash_to_scalar(b"id", m))
+    }
+}
+
+/// Round-one nonce commitments published by a signing member
+pub type FederationCommitments = SigningCommitments<Ristretto255Sha512>;
+
+/// The message and commitments every signer signs over in round two
+pub type FederationSigningPackage = SigningPackage<Ristretto255Sha512>;
+
+/// A member's share of the group signature
+pub type FederationSignatureShare = SignatureShare<Ristretto255Sha512>;
+
+fn member_identifier(member_id: u16) -> Result<Identifier<Ristretto255Sha512>, String> {
+    Identifier::try_from(member_id).map_err(|e| format!("Invalid member id {}: {}", member_id, e))
+}
+
+// the member id a default identifier was derived from
+fn member_id_of(identifier: &Identifier<Ristretto255Sha512>) -> Result<u16, String> {
+    (1..=u16::MAX)
+        .find(|id| member_identifier(*id).ok() == Some(*identifier))
+        .ok_or_else(|| "Key share has a non-default identifier".to_string())
+}
+
+/// The public key of a 32-byte signing share, as recorded in its key file
+pub fn share_public_key(secret: &[u8; 32]) -> Result<Vec<u8>, String> {
+    let share = SigningShare::<Ristretto255Sha512>::deserialize(secret)
+        .map_err(|e| format!("Invalid federation share: {}", e))?;
+    VerifyingShare::from(share).serialize()
+        .map_err(|e| format!("Invalid federation share: {}", e))
+}
+
+/// Hex encode round-one commitments for the wire
+pub fn encode_commitments(commitments: &FederationCommitments) -> Result<String, String> {
+    commitments.serialize().map(hex::encode).map_err(|e| format!("Invalid commitments: {}", e))
+}
+
+pub fn decode_commitments(text: &str) -> Result<FederationCommitments, String> {
+    let bytes = hex::decode(text).map_err(|e| format!("Invalid commitments hex: {}", e))?;
+    FederationCommitments::deserialize(&bytes).map_err(|e| format!("Invalid commitments: {}", e))
+}
+
+/// Hex encode a signing package for the wire
+pub fn encode_signing_package(package: &FederationSigningPackage) -> Result<String, String> {
+    package.serialize().map(hex::encode).map_err(|e| format!("Invalid signing package: {}", e))
+}
+
+pub fn decode_signing_package(text: &str) -> Result<FederationSigningPackage, String> {
+    let bytes = hex::decode(text).map_err(|e| format!("Invalid signing package hex: {}", e))?;
+    FederationSigningPackage::deserialize(&bytes).map_err(|e| format!("Invalid signing package: {}", e))
+}
+
+/// Hex encode a signature share for the wire
+pub fn encode_signature_share(share: &FederationSignatureShare) -> String {
+    hex::encode(share.serialize())
+}
+
+pub fn decode_signature_share(text: &str) -> Result<FederationSignatureShare, String> {
+    let bytes = hex::decode(text).map_err(|e| format!("Invalid signature share hex: {}", e))?;
+    FederationSignatureShare::deserialize(&bytes).map_err(|e| format!("Invalid signature share: {}", e))
+}
+
+/// Public description of a federation: its members, threshold and group key
+#[derive(Debug, Clone, PartialEq)]
+pub struct Federation {
+    public_key_package: PublicKeyPackage<Ristretto255Sha512>,
+    members: Vec<u16>,
+    threshold: u16,
+}
+
+impl Federation {
+    /// Split a fresh group key into `members` shares, any `threshold` of which can sign
+    ///
+    /// Uses a trusted dealer: whoever runs this sees the whole key and must hand
+    /// each member its share privately and then discard the shares.
+    pub fn trusted_dealer_keygen(members: u16, threshold: u16) -> Result<(Self, Vec<FederationMember>), String> {
+        let (shares, public_key_package) = frost_core::keys::generate_with_dealer(
+            members, threshold, IdentifierList::Default, &mut OsRng)
+            .map_err(|e| format!("Key generation failed: {}", e))?;
+
+        let mut federation_members = vec![];
+        for (id, member_id) in shares.keys().zip(1..=members) {
+            debug_assert_eq!(*id, member_identifier(member_id)?);
+            let key_package = KeyPackage::try_from(shares[id].clone())
+                .map_err(|e| format!("Invalid share for member {}: {}", member_id, e))?;
+            federation_members.push(FederationMember { member_id, key_package, nonces: None });
+        }
+
+        let federation = Federation {
+            public_key_package,
+            members: (1..=members).collect(),
+            threshold,
+        };
+        Ok((federation, federation_members))
+    }
+
+    /// Serialize the federation's public key package (members, threshold and keys; not secret)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.public_key_package.serialize().expect("public key package serializes")
+    }
+
+    /// Load a federation from `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let public_key_package = PublicKeyPackage::<Ristretto255Sha512>::deserialize(bytes)
+            .map_err(|e| format!("Invalid federation: {}", e))?;
+        let threshold = public_key_package.min_signers().ok_or("Federation doesn't record its threshold")?;
+        let members = public_key_package.verifying_shares().keys()
+            .map(member_id_of)
+            .collect::<Result<Vec<u16>, String>>()?;
+        Ok(Federation { public_key_package, members, threshold })
+    }
+
+    /// Write the federation's public description, hex encoded
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        std::fs::write(path, hex::encode(self.to_bytes()) + "\n")
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let bytes = hex::decode(text.trim()).map_err(|e| format!("Invalid federation file {}: {}", path.display(), e))?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Member ids (1 to N)
+    pub fn members(&self) -> &[u16] {
+        &self.members
+    }
+
+    /// How many members must co-sign
+    pub fn threshold(&self) -> u16 {
+        self.threshold
+    }
+
+    /// The group public key signatures verify against (32 bytes)
+    pub fn group_public_key(&self) -> Vec<u8> {
+        self.public_key_package.verifying_key().serialize()
+            .expect("group key is never the identity")
+    }
+
+    /// Start collecting commitments and shares for a message
+    pub fn signing_session(&self, message: &[u8]) -> SigningSession<'_> {
+        SigningSession {
+            federation: self,
+            message: message.to_vec(),
+            commitments: BTreeMap::new(),
+            shares: BTreeMap::new(),
+        }
+    }
+
+    /// Check a group signature over a message
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), String> {
+        verify_group_signature(&self.group_public_key(), message, signature)
+    }
+
+    /// Whether a receipt carries a valid group signature, making its block final
+    pub fn is_final(&self, receipt: &FederatedReceipt) -> bool {
+        receipt.verify(&self.group_public_key()).is_ok()
+    }
+}
+
+/// Check a FROST group signature against a group public key
+pub fn verify_group_signature(group_public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<(), String> {
+    let verifying_key = frost_core::VerifyingKey::<Ristretto255Sha512>::deserialize(group_public_key)
+        .map_err(|e| format!("Invalid group public key: {}", e))?;
+    let signature = Signature::<Ristretto255Sha512>::deserialize(signature)
+        .map_err(|e| format!("Invalid group signature: {}", e))?;
+
+    verifying_key.verify(message, &signature)
+        .map_err(|e| format!("Group signature does not verify: {}", e))
+}
+
+/// One validator's share of the federation key
+pub struct FederationMember {
+    member_id: u16,
+    key_package: KeyPackage<Ristretto255Sha512>,
+    /// Nonces for the signing round in progress (never reused)
+    nonces: Option<SigningNonces<Ristretto255Sha512>>,
+}
+
+impl FederationMember {
+    /// Load a member from its serialized key share
+    pub fn from_share_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let key_package = KeyPackage::<Ristretto255Sha512>::deserialize(bytes)
+            .map_err(|e| format!("Invalid key share: {}", e))?;
+        let member_id = member_id_of(key_package.identifier())?;
+
+        Ok(FederationMember { member_id, key_package, nonces: None })
+    }
+
+    /// Rebuild a member of `federation` from its 32-byte signing share (see `secret_share`)
+    ///
+    /// The member is found by the share's public key, so a share from another
+    /// federation is refused.
+    pub fn from_secret_share(federation: &Federation, secret: &[u8; 32]) -> Result<Self, String> {
+        let signing_share = SigningShare::<Ristretto255Sha512>::deserialize(secret)
+            .map_err(|e| format!("Invalid federation share: {}", e))?;
+        let verifying_share = VerifyingShare::from(signing_share);
+        let identifier = federation.public_key_package.verifying_shares().iter()
+            .find(|(_, share)| **share == verifying_share)
+            .map(|(identifier, _)| *identifier)
+            .ok_or("The share doesn't belong to any member of this federation")?;
+
+        let key_package = KeyPackage::new(identifier, signing_share, verifying_share,
+            *federation.public_key_package.verifying_key(), federation.threshold);
+        Ok(FederationMember { member_id: member_id_of(&identifier)?, key_package, nonces: None })
+    }
+
+    /// The 32-byte signing share (keep private), as stored in a `KeyKind::FederationShare` key file
+    pub fn secret_share(&self) -> [u8; 32] {
+        self.key_package.signing_share().serialize().try_into().expect("ristretto255 scalars are 32 bytes")
+    }
+
+    /// Serialize this member's key share (keep private)
+    pub fn share_bytes(&self) -> Vec<u8> {
+        self.key_package.serialize().expect("key package serializes")
+    }
+
+    /// This member's id within the federation
+    pub fn member_id(&self) -> u16 {
+        self.member_id
+    }
+
+    /// Round one: generate fresh nonces and publish their commitments
+    ///
+    /// A member takes part in one signing session at a time: committing again
+    /// replaces the nonces of the session in progress.
+    pub fn commit(&mut self) -> FederationCommitments {
+        let (nonces, commitments) = frost_core::round1::commit(self.key_package.signing_share(), &mut OsRng);
+        self.nonces = Some(nonces);
+        commitments
+    }
+
+    /// Round two: sign the package built from everyone's commitments
+    ///
+    /// The nonces from `commit` are consumed, so each commitment signs exactly once.
+    pub fn sign(&mut self, package: &FederationSigningPackage) -> Result<FederationSignatureShare, String> {
+        let nonces = self.nonces.take().ok_or("No commitment outstanding; call commit first")?;
+        frost_core::round2::sign(package, &nonces, &self.key_package)
+            .map_err(|e| format!("Signing failed: {}", e))
+    }
+}
+
+/// Coordinator state for producing one group signature
+pub struct SigningSession<'a> {
+    federation: &'a Federation,
+    message: Vec<u8>,
+    commitments: BTreeMap<Identifier<Ristretto255Sha512>, FederationCommitments>,
+    shares: BTreeMap<Identifier<Ristretto255Sha512>, FederationSignatureShare>,
+}
+
+impl SigningSession<'_> {
+    /// Record a member's round-one commitments
+    pub fn add_commitments(&mut self, member_id: u16, commitments: FederationCommitments) -> Result<(), String> {
+        if !self.federation.members.contains(&member_id) {
+            return Err(format!("{} is not a federation member", member_id));
+        }
+        self.commitments.insert(member_identifier(member_id)?, commitments);
+        Ok(())
+    }
+
+    /// Build the package signers sign over, once enough members have committed
+    pub fn signing_package(&self) -> Result<FederationSigningPackage, String> {
+        if self.commitments.len() < self.federation.threshold as usize {
+            return Err(format!("{} of {} required commitments", self.commitments.len(), self.federation.threshold));
+        }
+        Ok(SigningPackage::new(self.commitments.clone(), &self.message))
+    }
+
+    /// Record a member's round-two signature share
+    pub fn add_share(&mut self, member_id: u16, share: FederationSignatureShare) -> Result<(), String> {
+        let id = member_identifier(member_id)?;
+        if !self.commitments.contains_key(&id) {
+            return Err(format!("Member {} did not commit in this session", member_id));
+        }
+        self.shares.insert(id, share);
+        Ok(())
+    }
+
+    /// Combine the shares into the 64-byte group signature
+    pub fn aggregate(&self) -> Result<Vec<u8>, String> {
+        let package = self.signing_package()?;
+        let signature = frost_core::aggregate(&package, &self.shares, &self.federation.public_key_package)
+            .map_err(|e| format!("Aggregation failed: {}", e))?;
+        signature.serialize().map_err(|e| format!("Invalid signature: {}", e))
+    }
+}
+
+/// A block receipt co-signed by a threshold of federation members
+#[derive(Debug, Clone, PartialEq)]
+pub struct FederatedReceipt {
+    pub block_hash: BlockHash,
+    pub height: u32,
+    pub miner_id: String,
+    pub accepted_at: u128,
+    /// FROST group signature (64 bytes)
+    pub signature: Vec<u8>,
+}
+
+impl FederatedReceipt {
+    /// The bytes members sign for a receipt (same layout as a single-validator receipt)
+    pub fn message(block_hash: &BlockHash, height: u32, miner_id: &str, accepted_at: u128) -> Vec<u8> {
+        BlockReceipt::signing_bytes(block_hash, height, miner_id, accepted_at)
+    }
+
+    /// Check the group signature against the federation's public key
+    pub fn verify(&self, group_public_key: &[u8]) -> Result<(), String> {
+        let message = Self::message(&self.block_hash, self.height, &self.miner_id, self.accepted_at);
+        verify_group_signature(group_public_key, &message, &self.signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run both FROST rounds with the given members
+    fn co_sign(federation: &Federation, members: &mut [&mut FederationMember], message: &[u8]) -> Result<Vec<u8>, String> {
+        let mut session = federation.signing_session(message);
+        for member in members.iter_mut() {
+            let commitments = member.commit();
+            session.add_commitments(member.member_id(), commitments)?;
+        }
+
+        let package = session.signing_package()?;
+        for member in members.iter_mut() {
+            let share = member.sign(&package)?;
+            session.add_share(member.member_id(), share)?;
+        }
+
+        session.aggregate()
+    }
+
+    #[test]
+    fn test_two_of_three_co_sign_receipt() {
+        let (federation, mut members) = Federation::trusted_dealer_keygen(3, 2).unwrap();
+        assert_eq!(federation.members(), &[1, 2, 3]);
+        assert_eq!(federation.threshold(), 2);
+
+        let message = FederatedReceipt::message(&BlockHash::from_bytes([9; 32]), 4, "alice", 1_000_000);
+        let (first, rest) = members.split_at_mut(1);
+        let signature = co_sign(&federation, &mut [&mut first[0], &mut rest[1]], &message).unwrap();
+
+        let receipt = FederatedReceipt {
+            block_hash: BlockHash::from_bytes([9; 32]),
+            height: 4,
+            miner_id: "alice".to_string(),
+            accepted_at: 1_000_000,
+            signature,
+        };
+        assert!(federation.is_final(&receipt));
+        assert_eq!(receipt.verify(&federation.group_public_key()), Ok(()));
+
+        let mut tampered = receipt.clone();
+        tampered.height = 5;
+        assert!(!federation.is_final(&tampered));
+
+        let (other, _) = Federation::trusted_dealer_keygen(3, 2).unwrap();
+        assert!(!other.is_final(&receipt));
+    }
+
+    #[test]
+    fn test_below_threshold_cannot_sign() {
+        let (federation, mut members) = Federation::trusted_dealer_keygen(3, 2).unwrap();
+        assert!(co_sign(&federation, &mut [&mut members[0]], b"message").is_err());
+    }
+
+    #[test]
+    fn test_nonces_are_single_use() {
+        let (federation, mut members) = Federation::trusted_dealer_keygen(2, 2).unwrap();
+        let mut session = federation.signing_session(b"message");
+        for member in members.iter_mut() {
+            let commitments = member.commit();
+            session.add_commitments(member.member_id(), commitments).unwrap();
+        }
+        let package = session.signing_package().unwrap();
+
+        assert!(members[0].sign(&package).is_ok());
+        assert!(members[0].sign(&package).is_err());
+        assert!(session.add_commitments(7, members[1].commit()).is_err());
+    }
+
+    #[test]
+    fn test_share_roundtrip() {
+        let (federation, members) = Federation::trusted_dealer_keygen(3, 2).unwrap();
+        let mut restored: Vec<FederationMember> = members.iter()
+            .map(|m| FederationMember::from_share_bytes(&m.share_bytes()).unwrap())
+            .collect();
+        assert_eq!(restored[2].member_id(), 3);
+
+        let (a, b) = restored.split_at_mut(2);
+        assert!(co_sign(&federation, &mut [&mut a[1], &mut b[0]], b"message")
+            .and_then(|sig| federation.verify(b"message", &sig))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_federation_persistence() {
+        let (federation, members) = Federation::trusted_dealer_keygen(3, 2).unwrap();
+        let loaded = Federation::from_bytes(&federation.to_bytes()).unwrap();
+        assert_eq!(loaded, federation);
+
+        let mut restored: Vec<FederationMember> = members.iter()
+            .map(|m| FederationMember::from_secret_share(&loaded, &m.secret_share()).unwrap())
+            .collect();
+        assert_eq!(restored.iter().map(FederationMember::member_id).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(restored[1].share_bytes(), members[1].share_bytes());
+        assert_eq!(share_public_key(&members[0].secret_share()).unwrap(),
+            members[0].key_package.verifying_share().serialize().unwrap());
+
+        let (a, b) = restored.split_at_mut(1);
+        assert!(co_sign(&loaded, &mut [&mut a[0], &mut b[1]], b"message")
+            .and_then(|sig| federation.verify(b"message", &sig))
+            .is_ok());
+
+        // A share only loads into its own federation
+        let (other, _) = Federation::trusted_dealer_keygen(3, 2).unwrap();
+        assert!(FederationMember::from_secret_share(&other, &members[0].secret_share()).is_err());
+    }
+}
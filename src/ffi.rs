@@ -0,0 +1,450 @@
+//! C bindings for the core types (`ffi` feature)
+//!
+//! A stable C ABI over header hashing and verification, tonce checks and
+//! transaction signing, so mining software written in C or C++ can use the
+//! consensus rules instead of reimplementing them. `include/hourcoin.h`
+//! declares everything here.
+//!
+//! Conventions:
+//!
+//! - Functions return `HOURCOIN_OK` or a negative status. After a failure,
+//!   `hourcoin_last_error` describes it until the thread's next call.
+//! - Hashes and keys are 32 raw bytes. Headers are passed in their canonical
+//!   encoding, whose last 8 bytes are the big-endian nonce.
+//! - Transactions and PSBTs are the JSON the validator and wallets exchange.
+//!   Strings returned through `char **` belong to the caller, who frees them
+//!   with `hourcoin_string_free`.
+//! - Timestamps are TAI milliseconds as `uint64_t`.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use crate::canonical::{CanonicalDeserialize, CanonicalSerialize};
+use crate::chain_params::{ChainParams, Network};
+use crate::hashable::Hashable;
+use crate::hashes::HASH_LEN;
+use crate::network::protocol::{BlockHeaderData, TransactionData};
+use crate::node_key::NodeKey;
+use crate::psbt::PartiallySignedTransaction;
+use crate::spv::verify_header;
+use crate::tonce::{passes_tonce, TonceChallenge};
+use crate::wallet::address_from_public_key;
+use crate::{BlockHash, BlockHeader};
+
+pub const HOURCOIN_OK: i32 = 0;
+/// A null pointer, invalid UTF-8, or malformed JSON or header bytes
+pub const HOURCOIN_ERR_INVALID_ARGUMENT: i32 = -1;
+/// Well-formed input that fails a consensus check
+pub const HOURCOIN_ERR_REJECTED: i32 = -2;
+/// An output buffer too small for the result
+pub const HOURCOIN_ERR_BUFFER_TOO_SMALL: i32 = -3;
+/// A bug in the library; the call had no effect
+pub const HOURCOIN_ERR_INTERNAL: i32 = -4;
+
+/// Longest canonical header encoding (with UTXO and signed round commitments)
+pub const HOURCOIN_MAX_HEADER_LEN: usize = 228;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+enum FfiError {
+    InvalidArgument(String),
+    Rejected(String),
+    BufferTooSmall(String),
+}
+
+impl FfiError {
+    fn status(&self) -> i32 {
+        match self {
+            FfiError::InvalidArgument(_) => HOURCOIN_ERR_INVALID_ARGUMENT,
+            FfiError::Rejected(_) => HOURCOIN_ERR_REJECTED,
+            FfiError::BufferTooSmall(_) => HOURCOIN_ERR_BUFFER_TOO_SMALL,
+        }
+    }
+
+    fn message(self) -> String {
+        match self {
+            FfiError::InvalidArgument(message) | FfiError::Rejected(message) | FfiError::BufferTooSmall(message) => message,
+        }
+    }
+}
+
+fn set_last_error(message: String) {
+    // an interior NUL would cut the message short, not lose it
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Run the body of an exported function, turning errors and panics into a status
+fn call(body: impl FnOnce() -> Result<(), FfiError>) -> i32 {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+    match catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => HOURCOIN_OK,
+        Ok(Err(error)) => {
+            let status = error.status();
+            set_last_error(error.message());
+            status
+        }
+        Err(_) => {
+            set_last_error("Internal error in hourcoin".to_string());
+            HOURCOIN_ERR_INTERNAL
+        }
+    }
+}
+
+fn invalid(message: String) -> FfiError {
+    FfiError::InvalidArgument(message)
+}
+
+unsafe fn bytes_arg<'a>(ptr: *const u8, len: usize, name: &str) -> Result<&'a [u8], FfiError> {
+    if ptr.is_null() {
+        return Err(invalid(format!("{} is null", name)));
+    }
+    Ok(std::slice::from_raw_parts(ptr, len))
+}
+
+unsafe fn hash_arg(ptr: *const u8, name: &str) -> Result<[u8; HASH_LEN], FfiError> {
+    let mut hash = [0; HASH_LEN];
+    hash.copy_from_slice(bytes_arg(ptr, HASH_LEN, name)?);
+    Ok(hash)
+}
+
+unsafe fn str_arg<'a>(ptr: *const c_char, name: &str) -> Result<&'a str, FfiError> {
+    if ptr.is_null() {
+        return Err(invalid(format!("{} is null", name)));
+    }
+    CStr::from_ptr(ptr).to_str().map_err(|e| invalid(format!("{} is not UTF-8: {}", name, e)))
+}
+
+unsafe fn write_bytes(out: *mut u8, bytes: &[u8], name: &str) -> Result<(), FfiError> {
+    if out.is_null() {
+        return Err(invalid(format!("{} is null", name)));
+    }
+    ptr::copy_nonoverlapping(bytes.as_ptr(), out, bytes.len());
+    Ok(())
+}
+
+unsafe fn write_string(out: *mut *mut c_char, value: String) -> Result<(), FfiError> {
+    if out.is_null() {
+        return Err(invalid("Output string is null".to_string()));
+    }
+    let value = CString::new(value).map_err(|_| invalid("Result contains a NUL byte".to_string()))?;
+    *out = value.into_raw();
+    Ok(())
+}
+
+fn network_params(name: &str) -> Result<ChainParams, FfiError> {
+    name.parse::<Network>().map(ChainParams::for_network).map_err(invalid)
+}
+
+fn header_from_bytes(bytes: &[u8]) -> Result<BlockHeader, FfiError> {
+    BlockHeader::from_canonical_bytes(bytes).map_err(|e| invalid(format!("Invalid header: {}", e)))
+}
+
+fn secret_key(bytes: [u8; HASH_LEN]) -> Result<NodeKey, FfiError> {
+    NodeKey::from_secret_bytes(&bytes).map_err(invalid)
+}
+
+/// The message describing the calling thread's last failure, or NULL
+///
+/// The string belongs to the library and stays valid until the thread's next call.
+#[no_mangle]
+pub extern "C" fn hourcoin_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+/// Free a string returned by this library
+///
+/// # Safety
+///
+/// `value` must be NULL or a string returned through a `char **` by this
+/// library, and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn hourcoin_string_free(value: *mut c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(value));
+    }
+}
+
+/// Hash a canonically encoded block header into `out_hash` (32 bytes)
+///
+/// # Safety
+///
+/// `header` must point to `header_len` readable bytes and `out_hash` to 32 writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn hourcoin_block_hash(header: *const u8, header_len: usize, out_hash: *mut u8) -> i32 {
+    call(|| {
+        let header = header_from_bytes(bytes_arg(header, header_len, "header")?)?;
+        write_bytes(out_hash, header.hash().as_bytes(), "out_hash")
+    })
+}
+
+/// Canonically encode a header given as `BlockHeaderData` JSON
+///
+/// Writes at most `out_capacity` bytes (`HOURCOIN_MAX_HEADER_LEN` is always
+/// enough) and stores the length in `out_len`.
+///
+/// # Safety
+///
+/// `header_json` must be a NUL-terminated string, `out` must point to
+/// `out_capacity` writable bytes and `out_len` to a writable `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn hourcoin_header_encode(header_json: *const c_char, out: *mut u8, out_capacity: usize, out_len: *mut usize) -> i32 {
+    call(|| {
+        let data: BlockHeaderData = serde_json::from_str(str_arg(header_json, "header_json")?)
+            .map_err(|e| invalid(format!("Invalid header JSON: {}", e)))?;
+        let bytes = data.to_header().map_err(invalid)?.canonical_bytes();
+        if out.is_null() || out_len.is_null() {
+            return Err(invalid("Output buffer is null".to_string()));
+        }
+        if bytes.len() > out_capacity {
+            return Err(FfiError::BufferTooSmall(format!("Header needs {} bytes, buffer holds {}", bytes.len(), out_capacity)));
+        }
+        ptr::copy_nonoverlapping(bytes.as_ptr(), out, bytes.len());
+        *out_len = bytes.len();
+        Ok(())
+    })
+}
+
+/// Verify a header on `network` (`mainnet`, `testnet` or `regtest`)
+///
+/// Checks the header meets its difficulty, which may not be easier than the
+/// network's. Given its parent, also checks it extends the parent, is later,
+/// and (with `check_tonce`) passes the parent's tonce; pass a NULL `parent`
+/// for a genesis header. Returns `HOURCOIN_ERR_REJECTED` for a header that fails.
+///
+/// # Safety
+///
+/// `header` must point to `header_len` readable bytes, `parent` must be NULL or
+/// point to `parent_len` readable bytes, and `network` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn hourcoin_verify_header(
+    header: *const u8,
+    header_len: usize,
+    parent: *const u8,
+    parent_len: usize,
+    network: *const c_char,
+    check_tonce: bool,
+) -> i32 {
+    call(|| {
+        let header = header_from_bytes(bytes_arg(header, header_len, "header")?)?;
+        let parent = if parent.is_null() {
+            None
+        } else {
+            let parent = header_from_bytes(bytes_arg(parent, parent_len, "parent")?)?;
+            let parent_hash = parent.hash();
+            Some((parent, parent_hash))
+        };
+        let params = network_params(str_arg(network, "network")?)?;
+        verify_header(&header, parent.as_ref().map(|(parent, hash)| (parent, hash)), &params, check_tonce)
+            .map(|_| ())
+            .map_err(FfiError::Rejected)
+    })
+}
+
+/// The tonce a block must pass after the block with `prev_hash` (32 bytes) mined at `prev_timestamp`
+///
+/// # Safety
+///
+/// `prev_hash` must point to 32 readable bytes, `network` must be a
+/// NUL-terminated string and `out_tonce` must point to a writable byte.
+#[no_mangle]
+pub unsafe extern "C" fn hourcoin_tonce(prev_hash: *const u8, prev_timestamp: u64, network: *const c_char, out_tonce: *mut u8) -> i32 {
+    call(|| {
+        let prev_hash = BlockHash::from_bytes(hash_arg(prev_hash, "prev_hash")?);
+        let params = network_params(str_arg(network, "network")?)?;
+        if out_tonce.is_null() {
+            return Err(invalid("out_tonce is null".to_string()));
+        }
+        *out_tonce = TonceChallenge::new_with_params(&prev_hash, u128::from(prev_timestamp), &params).get_tonce();
+        Ok(())
+    })
+}
+
+/// Whether `timestamp` passes `tonce`
+#[no_mangle]
+pub extern "C" fn hourcoin_passes_tonce(tonce: u8, timestamp: u64) -> bool {
+    passes_tonce(tonce, u128::from(timestamp))
+}
+
+/// Hash a transaction given as JSON into `out_hash` (32 bytes)
+///
+/// # Safety
+///
+/// `tx_json` must be a NUL-terminated string and `out_hash` must point to 32 writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn hourcoin_tx_hash(tx_json: *const c_char, out_hash: *mut u8) -> i32 {
+    call(|| {
+        let data: TransactionData = serde_json::from_str(str_arg(tx_json, "tx_json")?)
+            .map_err(|e| invalid(format!("Invalid transaction JSON: {}", e)))?;
+        let transaction = data.to_transaction().map_err(invalid)?;
+        write_bytes(out_hash, transaction.hash().as_bytes(), "out_hash")
+    })
+}
+
+/// The public key (32 bytes) of a secret key (32 bytes)
+///
+/// # Safety
+///
+/// `secret_key` must point to 32 readable bytes and `out_public_key` to 32 writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn hourcoin_public_key(secret_key: *const u8, out_public_key: *mut u8) -> i32 {
+    call(|| {
+        let public_key = self::secret_key(hash_arg(secret_key, "secret_key")?)?.public_key();
+        write_bytes(out_public_key, &public_key, "out_public_key")
+    })
+}
+
+/// The address paying to the key with `public_key` (32 bytes)
+///
+/// # Safety
+///
+/// `public_key` must point to 32 readable bytes and `out_address` to a writable `char *`.
+#[no_mangle]
+pub unsafe extern "C" fn hourcoin_address(public_key: *const u8, out_address: *mut *mut c_char) -> i32 {
+    call(|| write_string(out_address, address_from_public_key(&hash_arg(public_key, "public_key")?)))
+}
+
+/// Sign every input of a PSBT (JSON) that `secret_key` can authorize
+///
+/// Writes the PSBT with the new signatures to `out_psbt_json`.
+///
+/// # Safety
+///
+/// `psbt_json` must be a NUL-terminated string, `secret_key` must point to 32
+/// readable bytes and `out_psbt_json` to a writable `char *`.
+#[no_mangle]
+pub unsafe extern "C" fn hourcoin_tx_sign(psbt_json: *const c_char, secret_key: *const u8, out_psbt_json: *mut *mut c_char) -> i32 {
+    call(|| {
+        let mut psbt = PartiallySignedTransaction::from_json(str_arg(psbt_json, "psbt_json")?).map_err(invalid)?;
+        let key = self::secret_key(hash_arg(secret_key, "secret_key")?)?;
+        psbt.sign_with_key(&key).map_err(invalid)?;
+        write_string(out_psbt_json, psbt.to_json().map_err(invalid)?)
+    })
+}
+
+/// Turn a fully signed PSBT (JSON) into a transaction (JSON) ready to submit
+///
+/// Returns `HOURCOIN_ERR_REJECTED` if an input still lacks signatures.
+///
+/// # Safety
+///
+/// `psbt_json` must be a NUL-terminated string and `out_tx_json` must point to a writable `char *`.
+#[no_mangle]
+pub unsafe extern "C" fn hourcoin_tx_finalize(psbt_json: *const c_char, out_tx_json: *mut *mut c_char) -> i32 {
+    call(|| {
+        let psbt = PartiallySignedTransaction::from_json(str_arg(psbt_json, "psbt_json")?).map_err(invalid)?;
+        let transaction = psbt.finalize().map_err(FfiError::Rejected)?;
+        let json = serde_json::to_string(&TransactionData::from_transaction(&transaction))
+            .map_err(|e| invalid(format!("Failed to serialize transaction: {}", e)))?;
+        write_string(out_tx_json, json)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Output, Transaction};
+    use crate::{Block, RoundCommitment};
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(hourcoin_last_error()) }.to_string_lossy().into_owned()
+    }
+
+    unsafe fn take_string(value: *mut c_char) -> String {
+        let string = CStr::from_ptr(value).to_string_lossy().into_owned();
+        hourcoin_string_free(value);
+        string
+    }
+
+    #[test]
+    fn test_header_hash_and_verify() {
+        let difficulty = ChainParams::regtest().difficulty;
+        let mut genesis = Block::new(0, 1_000, BlockHash::ZERO, vec![]);
+        genesis.mine(difficulty);
+        let mut block = Block::new(1, 2_000, genesis.hash, vec![]);
+        block.header.utxo_commitment = Some(BlockHash::digest(b"utxos"));
+        block.header.round = Some(RoundCommitment { round_start: 1_500, tonce: 1, signature: Some(vec![0; 64]) });
+        block.mine(difficulty);
+        let parent = genesis.header.canonical_bytes();
+        let header = block.header.canonical_bytes();
+        assert_eq!(header.len(), HOURCOIN_MAX_HEADER_LEN);
+        let regtest = CString::new("regtest").unwrap();
+
+        unsafe {
+            let mut hash = [0u8; 32];
+            assert_eq!(hourcoin_block_hash(header.as_ptr(), header.len(), hash.as_mut_ptr()), HOURCOIN_OK);
+            assert_eq!(hash, *block.hash.as_bytes());
+
+            let json = CString::new(serde_json::to_string(&BlockHeaderData::from_header(&block.header, None)).unwrap()).unwrap();
+            let mut encoded = [0u8; HOURCOIN_MAX_HEADER_LEN];
+            let mut len = 0;
+            assert_eq!(hourcoin_header_encode(json.as_ptr(), encoded.as_mut_ptr(), encoded.len(), &mut len), HOURCOIN_OK);
+            assert_eq!(&encoded[..len], &header[..]);
+            assert_eq!(hourcoin_header_encode(json.as_ptr(), encoded.as_mut_ptr(), 8, &mut len), HOURCOIN_ERR_BUFFER_TOO_SMALL);
+
+            assert_eq!(hourcoin_verify_header(parent.as_ptr(), parent.len(), ptr::null(), 0, regtest.as_ptr(), false), HOURCOIN_OK);
+            assert_eq!(hourcoin_verify_header(header.as_ptr(), header.len(), parent.as_ptr(), parent.len(), regtest.as_ptr(), false), HOURCOIN_OK);
+            assert_eq!(hourcoin_verify_header(parent.as_ptr(), parent.len(), header.as_ptr(), header.len(), regtest.as_ptr(), false), HOURCOIN_ERR_REJECTED);
+            assert!(last_error().contains("does not follow"), "{}", last_error());
+
+            assert_eq!(hourcoin_block_hash(header.as_ptr(), header.len() - 1, hash.as_mut_ptr()), HOURCOIN_ERR_INVALID_ARGUMENT);
+            assert_eq!(hourcoin_block_hash(ptr::null(), 0, hash.as_mut_ptr()), HOURCOIN_ERR_INVALID_ARGUMENT);
+            let nowhere = CString::new("nowhere").unwrap();
+            assert_eq!(hourcoin_verify_header(header.as_ptr(), header.len(), ptr::null(), 0, nowhere.as_ptr(), false), HOURCOIN_ERR_INVALID_ARGUMENT);
+        }
+    }
+
+    #[test]
+    fn test_tonce() {
+        let prev_hash = BlockHash::digest(b"parent");
+        let regtest = CString::new("regtest").unwrap();
+        let expected = TonceChallenge::new_with_params(&prev_hash, 5_000, &ChainParams::regtest()).get_tonce();
+
+        let mut tonce = 0;
+        assert_eq!(unsafe { hourcoin_tonce(prev_hash.as_bytes().as_ptr(), 5_000, regtest.as_ptr(), &mut tonce) }, HOURCOIN_OK);
+        assert_eq!(tonce, expected);
+        assert!(hourcoin_passes_tonce(1, 12_345));
+        assert_eq!(hourcoin_passes_tonce(tonce, 12_345), passes_tonce(tonce, 12_345));
+    }
+
+    #[test]
+    fn test_sign_and_finalize() {
+        let secret = [3u8; 32];
+        let key = NodeKey::from_secret_bytes(&secret).unwrap();
+        let transaction = Transaction {
+            inputs: vec![Output { to_addr: address_from_public_key(&key.public_key()), value: 5.0, timestamp: 1_000, lock_until: None }],
+            outputs: vec![Output { to_addr: "carol".to_string(), value: 5.0, timestamp: 2_000, lock_until: None }],
+            witnesses: vec![],
+        };
+        let unsigned = CString::new(PartiallySignedTransaction::new(&transaction).to_json().unwrap()).unwrap();
+
+        unsafe {
+            let mut public_key = [0u8; 32];
+            assert_eq!(hourcoin_public_key(secret.as_ptr(), public_key.as_mut_ptr()), HOURCOIN_OK);
+            let mut address = ptr::null_mut();
+            assert_eq!(hourcoin_address(public_key.as_ptr(), &mut address), HOURCOIN_OK);
+            assert_eq!(take_string(address), transaction.inputs[0].to_addr);
+
+            let mut tx_json = ptr::null_mut();
+            assert_eq!(hourcoin_tx_finalize(unsigned.as_ptr(), &mut tx_json), HOURCOIN_ERR_REJECTED);
+
+            let mut signed = ptr::null_mut();
+            assert_eq!(hourcoin_tx_sign(unsigned.as_ptr(), secret.as_ptr(), &mut signed), HOURCOIN_OK);
+            let signed = CString::new(take_string(signed)).unwrap();
+            assert_eq!(hourcoin_tx_finalize(signed.as_ptr(), &mut tx_json), HOURCOIN_OK);
+            let tx_json = CString::new(take_string(tx_json)).unwrap();
+
+            let data: TransactionData = serde_json::from_str(tx_json.to_str().unwrap()).unwrap();
+            let signed_tx = data.to_transaction().unwrap();
+            assert_eq!(crate::signature_cache::verify_transaction(&signed_tx), Ok(()));
+
+            let mut hash = [0u8; 32];
+            assert_eq!(hourcoin_tx_hash(tx_json.as_ptr(), hash.as_mut_ptr()), HOURCOIN_OK);
+            assert_eq!(hash, *signed_tx.hash().as_bytes());
+        }
+    }
+}
@@ -1,9 +1,11 @@
 use super::*;
 pub trait Hashable {
+	// what the hash identifies: a `BlockHash` for blocks, a `Txid` for transactions
+	type Hash: From<[u8; hashes::HASH_LEN]>;
+
 	fn bytes (&self) -> Vec<u8>;
 
-	fn hash (&self) -> BlockHash {
-		crypto_hash::digest(crypto_hash::Algorithm::SHA256, &self.bytes())
+	fn hash (&self) -> Self::Hash {
+		Self::Hash::from(*BlockHash::digest(&self.bytes()).as_bytes())
 	}
 }
-
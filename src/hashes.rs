@@ -0,0 +1,200 @@
+//! Typed hashes and nonces
+//!
+//! Block and transaction hashes used to be bare `Vec<u8>`s, so any bytes
+//! (a signature, an address, a hash of the wrong thing) could be passed where
+//! a block hash was expected. `BlockHash` and `Txid` are 32-byte SHA-256
+//! digests that only convert to and from raw bytes explicitly; `Nonce` is the
+//! proof of work counter in a block header.
+//!
+//! All three keep the wire formats they replace: hashes serialize as hex
+//! strings and canonically as a length-prefixed byte string, and a nonce as a
+//! plain integer, so no hash or message changes.
+
+use std::convert::TryInto;
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use crate::canonical::{CanonicalDeserialize, CanonicalReader, CanonicalSerialize};
+
+/// Length of every hash, in bytes
+pub const HASH_LEN: usize = 32;
+
+macro_rules! hash_newtype {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+        pub struct $name([u8; HASH_LEN]);
+
+        impl $name {
+            /// All zero bytes
+            pub const ZERO: $name = $name([0; HASH_LEN]);
+
+            pub const fn from_bytes(bytes: [u8; HASH_LEN]) -> Self {
+                $name(bytes)
+            }
+
+            /// The hash in `bytes`, which must be exactly 32 long
+            pub fn from_slice(bytes: &[u8]) -> Result<Self, String> {
+                bytes.try_into()
+                    .map($name)
+                    .map_err(|_| format!("{} must be {} bytes, got {}", stringify!($name), HASH_LEN, bytes.len()))
+            }
+
+            /// The SHA-256 digest of `bytes`
+            pub fn digest(bytes: &[u8]) -> Self {
+                let digest = Sha256::digest(bytes);
+                Self::from_slice(&digest).expect("SHA-256 digests are 32 bytes")
+            }
+
+            pub fn as_bytes(&self) -> &[u8; HASH_LEN] {
+                &self.0
+            }
+
+            pub fn to_vec(&self) -> Vec<u8> {
+                self.0.to_vec()
+            }
+        }
+
+        impl From<[u8; HASH_LEN]> for $name {
+            fn from(bytes: [u8; HASH_LEN]) -> Self {
+                $name(bytes)
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(&hex::encode(self.0))
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}({})", stringify!($name), self)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = String;
+
+            /// Parse 64 hex digits
+            fn from_str(s: &str) -> Result<Self, String> {
+                let bytes = hex::decode(s).map_err(|e| format!("Invalid {} hex: {}", stringify!($name), e))?;
+                Self::from_slice(&bytes)
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&self.to_string())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let hex = String::deserialize(deserializer)?;
+                hex.parse().map_err(serde::de::Error::custom)
+            }
+        }
+
+        // a length-prefixed byte string, the layout hashes had as `Vec<u8>`
+        impl CanonicalSerialize for $name {
+            fn write_canonical(&self, out: &mut Vec<u8>) {
+                self.0[..].write_canonical(out);
+            }
+        }
+
+        impl CanonicalDeserialize for $name {
+            fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+                Self::from_slice(&Vec::<u8>::read_canonical(reader)?)
+            }
+        }
+    };
+}
+
+hash_newtype!(
+    /// Hash of a block header, and of the other chain objects identified by hash (outputs, contracts)
+    BlockHash
+);
+
+hash_newtype!(
+    /// Hash of a transaction
+    Txid
+);
+
+/// The proof of work counter in a block header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Nonce(pub u64);
+
+impl Nonce {
+    /// The next nonce, or None once the space is used up
+    pub fn checked_next(self) -> Option<Nonce> {
+        self.0.checked_add(1).map(Nonce)
+    }
+}
+
+impl From<u64> for Nonce {
+    fn from(nonce: u64) -> Self {
+        Nonce(nonce)
+    }
+}
+
+impl fmt::Display for Nonce {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for Nonce {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Nonce)
+    }
+}
+
+impl CanonicalSerialize for Nonce {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        self.0.write_canonical(out);
+    }
+}
+
+impl CanonicalDeserialize for Nonce {
+    fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+        u64::read_canonical(reader).map(Nonce)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_conversions() {
+        let hash = BlockHash::digest(b"hourcoin");
+        let hex = hash.to_string();
+        assert_eq!(hex.len(), 64);
+        assert_eq!(hex.parse::<BlockHash>().unwrap(), hash);
+        assert_eq!(BlockHash::from_slice(&hash.to_vec()).unwrap(), hash);
+
+        assert!(BlockHash::from_slice(&[0; 31]).is_err());
+        assert!("abcd".parse::<Txid>().is_err());
+        assert!("zz".repeat(32).parse::<Txid>().is_err());
+
+        // the same JSON and canonical bytes as the `Vec<u8>` hashes they replace
+        assert_eq!(serde_json::to_string(&hash).unwrap(), format!("\"{}\"", hex));
+        assert_eq!(serde_json::from_str::<BlockHash>(&format!("\"{}\"", hex)).unwrap(), hash);
+        assert_eq!(hash.canonical_bytes(), hash.to_vec().canonical_bytes());
+        assert_eq!(BlockHash::from_canonical_bytes(&hash.canonical_bytes()).unwrap(), hash);
+
+        assert_eq!(serde_json::to_string(&Nonce(42)).unwrap(), "42");
+        assert_eq!(Nonce(u64::MAX).checked_next(), None);
+    }
+}
@@ -0,0 +1,320 @@
+//! Hash-time-locked contracts for atomic swaps
+//!
+//! An HTLC output pays to the hash of its contract terms (`htlc` + hex
+//! digest), much like pay-to-script-hash. To spend it, a transaction reveals the
+//! terms in a `Witness::Htlc`, and then either:
+//!
+//! - redeems: it reveals a preimage of `payment_hash` and pays only the recipient; or
+//! - refunds: it is in a block timestamped at or after `timeout` and pays only the
+//!   refund address.
+//!
+//! Either way the payee must be a key address and sign the transaction, and
+//! its outputs must carry the HTLC's value less at most `MAX_HTLC_FEE`.
+//! Otherwise anyone who saw the preimage, or the timeout pass, could spend the
+//! output into fees, since only where the outputs go would be checked.
+//!
+//! Redeeming publishes the preimage on chain. The counterparty can then use it to
+//! claim the matching HTLC on the other chain. Payment hashes are SHA-256, as on Bitcoin.
+
+use sha2::{Digest, Sha256};
+use crate::BlockHash;
+use crate::canonical::{CanonicalDeserialize, CanonicalReader, CanonicalSerialize};
+use crate::hashable::Hashable;
+use crate::node_key::{NodeKey, Signer};
+use crate::transaction::{Output, Transaction, Witness, TX_SIGNING_CONTEXT};
+use crate::wallet::{address_from_public_key, is_key_address};
+
+/// Prefix of addresses that pay to an HTLC
+pub const HTLC_ADDRESS_PREFIX: &str = "htlc";
+
+/// Most of an HTLC's value a spend may leave to fees
+pub const MAX_HTLC_FEE: f64 = 0.01;
+
+/// The terms of a hash-time-locked contract
+#[derive(Debug, Clone, PartialEq)]
+pub struct HtlcContract {
+    /// SHA-256 of the secret that unlocks the redeem path
+    pub payment_hash: Vec<u8>,
+    /// Who is paid when the preimage is revealed
+    pub recipient: String,
+    /// Who is paid back once the timeout has passed
+    pub refund_address: String,
+    /// Earliest block timestamp (TAI milliseconds) at which a refund is allowed
+    pub timeout: u128,
+}
+
+impl HtlcContract {
+    /// Create a contract locked to a payment hash
+    pub fn new(payment_hash: Vec<u8>, recipient: &str, refund_address: &str, timeout: u128) -> Self {
+        HtlcContract {
+            payment_hash,
+            recipient: recipient.to_string(),
+            refund_address: refund_address.to_string(),
+            timeout,
+        }
+    }
+
+    /// The address an HTLC output with these terms pays to
+    pub fn address(&self) -> String {
+        format!("{}{}", HTLC_ADDRESS_PREFIX, hex::encode(self.hash()))
+    }
+
+    /// Who a spend revealing `preimage` (or none, for a refund) must pay
+    fn payee(&self, preimage: Option<&[u8]>) -> &str {
+        match preimage {
+            Some(_) => &self.recipient,
+            None => &self.refund_address,
+        }
+    }
+
+    /// Check that `transaction`, spending an output of these terms worth `value`, follows them
+    ///
+    /// The payee's signature must be among the witnesses; it is verified with
+    /// the transaction's other signatures (see `signature_cache::required_signatures`).
+    pub fn check_spend(&self, preimage: Option<&[u8]>, transaction: &Transaction, value: f64, block_timestamp: u128) -> Result<(), String> {
+        let payee = match preimage {
+            Some(preimage) => {
+                if payment_hash(preimage) != self.payment_hash {
+                    return Err("Preimage does not match the payment hash".to_string());
+                }
+                &self.recipient
+            }
+            None => {
+                if block_timestamp < self.timeout {
+                    return Err(format!("Refund not allowed before {}", self.timeout));
+                }
+                &self.refund_address
+            }
+        };
+
+        if let Some(output) = transaction.outputs.iter().find(|output| &output.to_addr != payee) {
+            return Err(format!("HTLC spend pays {} instead of {}", output.to_addr, payee));
+        }
+        let paid = transaction.output_sum();
+        if paid < value - MAX_HTLC_FEE {
+            return Err(format!("HTLC spend pays {} of {}, leaving more than {} in fees", paid, value, MAX_HTLC_FEE));
+        }
+        payee_signer(payee, transaction)?;
+        Ok(())
+    }
+}
+
+impl CanonicalSerialize for HtlcContract {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        self.payment_hash.write_canonical(out);
+        self.recipient.write_canonical(out);
+        self.refund_address.write_canonical(out);
+        self.timeout.write_canonical(out);
+    }
+}
+
+impl CanonicalDeserialize for HtlcContract {
+    fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+        Ok(HtlcContract {
+            payment_hash: Vec::read_canonical(reader)?,
+            recipient: String::read_canonical(reader)?,
+            refund_address: String::read_canonical(reader)?,
+            timeout: u128::read_canonical(reader)?,
+        })
+    }
+}
+
+impl Hashable for HtlcContract {
+    type Hash = BlockHash;
+
+    fn bytes(&self) -> Vec<u8> {
+        self.canonical_bytes()
+    }
+}
+
+/// SHA-256 payment hash of a secret
+pub fn payment_hash(preimage: &[u8]) -> Vec<u8> {
+    Sha256::digest(preimage).to_vec()
+}
+
+/// Whether an address pays to an HTLC
+pub fn is_htlc_address(address: &str) -> bool {
+    address.len() == HTLC_ADDRESS_PREFIX.len() + 64
+        && address.strip_prefix(HTLC_ADDRESS_PREFIX)
+            .is_some_and(|hash| hash.bytes().all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase()))
+}
+
+/// An output locking `value` into the contract
+pub fn create_htlc_output(contract: &HtlcContract, value: f64, timestamp: u128) -> Output {
+    Output {
+        to_addr: contract.address(),
+        value,
+        timestamp,
+        lock_until: None,
+    }
+}
+
+/// Spend an HTLC output to its recipient by revealing the preimage, signed with the recipient's `key`
+pub fn redeem_with_preimage(htlc_output: &Output, contract: &HtlcContract, preimage: &[u8], key: &NodeKey, timestamp: u128) -> Result<Transaction, String> {
+    if htlc_output.to_addr != contract.address() {
+        return Err("Output does not pay to this contract".to_string());
+    }
+    if payment_hash(preimage) != contract.payment_hash {
+        return Err("Preimage does not match the payment hash".to_string());
+    }
+
+    spend(htlc_output, contract, Some(preimage.to_vec()), key, timestamp)
+}
+
+/// Spend an HTLC output back to its refund address once the timeout has passed, signed with that address's `key`
+pub fn refund_after_timeout(htlc_output: &Output, contract: &HtlcContract, key: &NodeKey, timestamp: u128) -> Result<Transaction, String> {
+    if htlc_output.to_addr != contract.address() {
+        return Err("Output does not pay to this contract".to_string());
+    }
+    if timestamp < contract.timeout {
+        return Err(format!("Refund not allowed before {}", contract.timeout));
+    }
+
+    spend(htlc_output, contract, None, key, timestamp)
+}
+
+fn spend(htlc_output: &Output, contract: &HtlcContract, preimage: Option<Vec<u8>>, key: &NodeKey, timestamp: u128) -> Result<Transaction, String> {
+    let payee = contract.payee(preimage.as_deref());
+    let public_key = key.public_key();
+    if address_from_public_key(&public_key) != payee {
+        return Err(format!("Key does not sign for {}", payee));
+    }
+
+    let mut transaction = Transaction {
+        inputs: vec![htlc_output.clone()],
+        outputs: vec![Output {
+            to_addr: payee.to_string(),
+            value: htlc_output.value,
+            timestamp,
+            lock_until: None,
+        }],
+        witnesses: vec![Witness::Htlc {
+            contract: contract.clone(),
+            preimage,
+        }],
+    };
+    let signature = key.sign(TX_SIGNING_CONTEXT, transaction.signing_hash().as_bytes());
+    transaction.witnesses.push(Witness::Signature { public_key, signature });
+    Ok(transaction)
+}
+
+// the contract and preimage a witness of `transaction` opens `input` with
+fn input_witness<'a>(input: &Output, transaction: &'a Transaction) -> Result<(&'a HtlcContract, Option<&'a [u8]>), String> {
+    transaction.witnesses.iter()
+        .find_map(|witness| match witness {
+            Witness::Htlc { contract, preimage } if contract.address() == input.to_addr => Some((contract, preimage.as_deref())),
+            _ => None,
+        })
+        .ok_or_else(|| format!("No witness for HTLC input {}", input.to_addr))
+}
+
+// the signature `transaction` carries from `payee`, which must be a key address
+fn payee_signer<'a>(payee: &str, transaction: &'a Transaction) -> Result<Signer<'a>, String> {
+    if !is_key_address(payee) {
+        return Err(format!("HTLC payee {} is not a key address and can't sign", payee));
+    }
+    transaction.witnesses.iter()
+        .find_map(|witness| match witness {
+            Witness::Signature { public_key, signature } if address_from_public_key(public_key) == payee => {
+                Some((public_key.as_slice(), signature.as_slice()))
+            }
+            _ => None,
+        })
+        .ok_or_else(|| format!("No signature from HTLC payee {}", payee))
+}
+
+/// Check that an input paying to an HTLC address, worth `value` at the block's timestamp, is opened by one of the transaction's witnesses
+pub(crate) fn check_input(input: &Output, transaction: &Transaction, value: f64, block_timestamp: u128) -> Result<(), String> {
+    let (contract, preimage) = input_witness(input, transaction)?;
+    contract.check_spend(preimage, transaction, value, block_timestamp)
+}
+
+/// The public key and signature of the payee that must verify for `transaction` to spend `input`, an HTLC address
+pub(crate) fn input_signer<'a>(input: &Output, transaction: &'a Transaction) -> Result<Signer<'a>, String> {
+    let (contract, preimage) = input_witness(input, transaction)?;
+    payee_signer(contract.payee(preimage), transaction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Parties {
+        alice: NodeKey,
+        bob: NodeKey,
+        contract: HtlcContract,
+    }
+
+    // Bob redeems, Alice is refunded
+    fn parties() -> Parties {
+        let alice = NodeKey::generate();
+        let bob = NodeKey::generate();
+        let contract = HtlcContract::new(payment_hash(b"secret"), &address_from_public_key(&bob.public_key()),
+            &address_from_public_key(&alice.public_key()), 10_000);
+        Parties { alice, bob, contract }
+    }
+
+    #[test]
+    fn test_redeem_with_preimage() {
+        let Parties { alice, bob, contract } = parties();
+        let output = create_htlc_output(&contract, 2.0, 1000);
+        assert!(is_htlc_address(&output.to_addr));
+
+        assert!(redeem_with_preimage(&output, &contract, b"wrong", &bob, 2000).is_err());
+        assert!(redeem_with_preimage(&output, &contract, b"secret", &alice, 2000).is_err());
+
+        let redeem = redeem_with_preimage(&output, &contract, b"secret", &bob, 2000).unwrap();
+        assert_eq!(redeem.outputs[0].to_addr, contract.recipient);
+        assert_eq!(check_input(&output, &redeem, 2.0, 2000), Ok(()));
+        assert_eq!(input_signer(&output, &redeem).unwrap().0, bob.public_key().as_slice());
+
+        // Redirecting the payment invalidates the spend
+        let mut stolen = redeem.clone();
+        stolen.outputs[0].to_addr = "Mallory".to_owned();
+        assert!(check_input(&output, &stolen, 2.0, 2000).is_err());
+
+        // So does spending it into fees, or without the recipient's signature
+        let mut burned = redeem.clone();
+        burned.outputs[0].value = 2.0 - MAX_HTLC_FEE * 2.0;
+        assert!(check_input(&output, &burned, 2.0, 2000).unwrap_err().contains("fees"));
+        let mut unsigned = redeem.clone();
+        unsigned.witnesses.retain(|witness| matches!(witness, Witness::Htlc { .. }));
+        assert!(check_input(&output, &unsigned, 2.0, 2000).unwrap_err().contains("No signature"));
+    }
+
+    #[test]
+    fn test_refund_after_timeout() {
+        let Parties { alice, contract, .. } = parties();
+        let output = create_htlc_output(&contract, 2.0, 1000);
+
+        assert!(refund_after_timeout(&output, &contract, &alice, 9_999).is_err());
+
+        let refund = refund_after_timeout(&output, &contract, &alice, 10_000).unwrap();
+        assert_eq!(refund.outputs[0].to_addr, contract.refund_address);
+        assert_eq!(check_input(&output, &refund, 2.0, 10_000), Ok(()));
+
+        // The block, not the transaction, decides whether the timeout has passed
+        assert!(check_input(&output, &refund, 2.0, 9_999).is_err());
+    }
+
+    #[test]
+    fn test_witness_must_match_contract() {
+        let Parties { contract, .. } = parties();
+        let output = create_htlc_output(&contract, 2.0, 1000);
+
+        let mallory = NodeKey::generate();
+        let address = address_from_public_key(&mallory.public_key());
+        let other = HtlcContract::new(payment_hash(b"other"), &address, &address, 0);
+        let mut spend = refund_after_timeout(&create_htlc_output(&other, 2.0, 1000), &other, &mallory, 2000).unwrap();
+        spend.inputs = vec![output.clone()];
+        assert!(check_input(&output, &spend, 2.0, 2000).is_err());
+    }
+
+    #[test]
+    fn test_is_htlc_address() {
+        assert!(is_htlc_address(&parties().contract.address()));
+        assert!(!is_htlc_address("htlc"));
+        assert!(!is_htlc_address("htlcs_are_fun"));
+        assert!(!is_htlc_address(&parties().contract.address().to_uppercase().replacen("HTLC", "htlc", 1)));
+    }
+}
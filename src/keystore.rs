@@ -0,0 +1,373 @@
+//! Encrypted identity key files
+//!
+//! Validator node keys, VRF keys and miner keys are all 32-byte sr25519
+//! seeds, and a federation member's signing share is a 32-byte scalar (see
+//! the `federation` module). `keygen` keeps each in a small JSON `KeyFile`: the public key in
+//! the clear, so a file can be identified without its passphrase, and the
+//! seed encrypted with ChaCha20-Poly1305 under a key stretched from the
+//! passphrase by Argon2id. The kind and public key are authenticated along
+//! with the seed, so neither can be swapped without the file failing to open.
+//!
+//! A rotated key records the key it replaced, with a signature by the old
+//! key over the new public key, so peers that trusted the old key can
+//! check the handover.
+
+use std::convert::TryInto;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use crate::atomic_file::atomic_write_private;
+use crate::federation::share_public_key;
+use crate::node_key::{verify_signature, NodeKey};
+use crate::vrf::VrfKeypair;
+use crate::wallet::address_from_public_key;
+
+/// Bumped whenever the file layout changes incompatibly
+pub const KEY_FILE_VERSION: u32 = 1;
+
+/// Environment variable read instead of prompting for a passphrase
+pub const PASSPHRASE_ENV: &str = "HOURCOIN_KEY_PASSPHRASE";
+
+/// Signing context of the old key's signature over its replacement
+pub const KEY_ROTATION_CONTEXT: &[u8] = b"hourcoin-key-rotation";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// What a key is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyKind {
+    /// A validator's node key, signing receipts and votes
+    Node,
+    /// A validator's key for round challenge VRFs
+    Vrf,
+    /// A miner's key, paid at its key address
+    Miner,
+    /// A validator's share of a federation key, dealt by `keygen federation`
+    #[serde(rename = "federation")]
+    FederationShare,
+}
+
+impl KeyKind {
+    /// The public key of `secret` used as this kind of key
+    pub fn public_key(&self, secret: &[u8; 32]) -> Result<Vec<u8>, String> {
+        match self {
+            KeyKind::Node | KeyKind::Miner => NodeKey::from_secret_bytes(secret).map(|key| key.public_key()),
+            KeyKind::Vrf => VrfKeypair::from_secret_bytes(secret).map(|key| key.public_key()),
+            KeyKind::FederationShare => share_public_key(secret),
+        }
+    }
+
+    /// The address coins are paid to, for kinds that are paid
+    pub fn address(&self, public_key: &[u8]) -> Option<String> {
+        match self {
+            KeyKind::Node | KeyKind::Miner => Some(address_from_public_key(public_key)),
+            KeyKind::Vrf | KeyKind::FederationShare => None,
+        }
+    }
+}
+
+impl fmt::Display for KeyKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            KeyKind::Node => "node",
+            KeyKind::Vrf => "vrf",
+            KeyKind::Miner => "miner",
+            KeyKind::FederationShare => "federation",
+        })
+    }
+}
+
+impl FromStr for KeyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "node" => Ok(KeyKind::Node),
+            "vrf" => Ok(KeyKind::Vrf),
+            "miner" => Ok(KeyKind::Miner),
+            "federation" => Ok(KeyKind::FederationShare),
+            _ => Err(format!("Unknown key kind '{}', expected node, vrf, miner or federation", s)),
+        }
+    }
+}
+
+/// Argon2id cost and salt
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    #[serde(default)]
+    pub salt: String, // Hex encoded
+}
+
+impl Default for KdfParams {
+    /// OWASP's minimum for Argon2id: 19 MiB, two passes
+    fn default() -> Self {
+        KdfParams { memory_kib: 19 * 1024, iterations: 2, parallelism: 1, salt: String::new() }
+    }
+}
+
+impl KdfParams {
+    fn derive_key(&self, passphrase: &str) -> Result<[u8; 32], String> {
+        let salt = hex::decode(&self.salt).map_err(|e| format!("Invalid salt hex: {}", e))?;
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32))
+            .map_err(|e| format!("Invalid key derivation parameters: {}", e))?;
+        let mut key = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| format!("Key derivation failed: {}", e))?;
+        Ok(key)
+    }
+}
+
+/// The key a rotated key replaced
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyRotation {
+    pub previous_public_key: String, // Hex encoded
+    /// The previous key's signature over the new public key
+    pub signature: String, // Hex encoded
+}
+
+/// An identity key, encrypted at rest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyFile {
+    pub version: u32,
+    pub kind: KeyKind,
+    pub public_key: String, // Hex encoded
+    /// When the key was created (TAI ms)
+    pub created_at: u128,
+    #[serde(default)]
+    pub rotated_from: Option<KeyRotation>,
+    pub kdf: KdfParams,
+    nonce: String, // Hex encoded
+    ciphertext: String, // Hex encoded
+}
+
+impl KeyFile {
+    /// Encrypt `secret` under `passphrase`, with a fresh salt
+    pub fn seal(kind: KeyKind, secret: &[u8; 32], passphrase: &str, mut kdf: KdfParams) -> Result<Self, String> {
+        let public_key = hex::encode(kind.public_key(secret)?);
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut nonce);
+        kdf.salt = hex::encode(salt);
+
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&kdf.derive_key(passphrase)?));
+        let aad = associated_data(kind, &public_key);
+        let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), Payload { msg: secret, aad: &aad })
+            .map_err(|_| "Failed to encrypt the key".to_string())?;
+
+        Ok(KeyFile {
+            version: KEY_FILE_VERSION,
+            kind,
+            public_key,
+            created_at: crate::now(),
+            rotated_from: None,
+            kdf,
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    /// A new random key of `kind`, encrypted under `passphrase`
+    ///
+    /// Federation shares can't be generated alone; they are dealt together.
+    pub fn generate(kind: KeyKind, passphrase: &str, kdf: KdfParams) -> Result<Self, String> {
+        if kind == KeyKind::FederationShare {
+            return Err("Federation shares are dealt together by `keygen federation`".to_string());
+        }
+        Self::seal(kind, &NodeKey::generate().secret_bytes(), passphrase, kdf)
+    }
+
+    /// Decrypt the secret seed
+    pub fn open(&self, passphrase: &str) -> Result<[u8; 32], String> {
+        let nonce = hex::decode(&self.nonce).map_err(|e| format!("Invalid nonce hex: {}", e))?;
+        let ciphertext = hex::decode(&self.ciphertext).map_err(|e| format!("Invalid ciphertext hex: {}", e))?;
+        if nonce.len() != NONCE_LEN {
+            return Err(format!("Nonce must be {} bytes, got {}", NONCE_LEN, nonce.len()));
+        }
+
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&self.kdf.derive_key(passphrase)?));
+        let aad = associated_data(self.kind, &self.public_key);
+        let secret = cipher.decrypt(Nonce::from_slice(&nonce), Payload { msg: &ciphertext, aad: &aad })
+            .map_err(|_| "Wrong passphrase, or the key file was modified".to_string())?;
+        let secret: [u8; 32] = secret.try_into().map_err(|_| "Decrypted key is not 32 bytes".to_string())?;
+        if hex::encode(self.kind.public_key(&secret)?) != self.public_key {
+            return Err("Decrypted key doesn't match the file's public key".to_string());
+        }
+        Ok(secret)
+    }
+
+    /// The same key under a new passphrase
+    pub fn change_passphrase(&self, passphrase: &str, new_passphrase: &str) -> Result<Self, String> {
+        let mut file = Self::seal(self.kind, &self.open(passphrase)?, new_passphrase, self.kdf.clone())?;
+        file.created_at = self.created_at;
+        file.rotated_from = self.rotated_from.clone();
+        Ok(file)
+    }
+
+    /// A new key of the same kind, signed over by this one
+    pub fn rotate(&self, passphrase: &str, new_passphrase: &str) -> Result<Self, String> {
+        if self.kind == KeyKind::FederationShare {
+            return Err("A federation share can't be rotated alone; deal a new federation instead".to_string());
+        }
+        let old_key = NodeKey::from_secret_bytes(&self.open(passphrase)?)?;
+        let mut file = Self::generate(self.kind, new_passphrase, self.kdf.clone())?;
+        let new_public_key = hex::decode(&file.public_key).map_err(|e| format!("Invalid public key hex: {}", e))?;
+        file.rotated_from = Some(KeyRotation {
+            previous_public_key: self.public_key.clone(),
+            signature: hex::encode(old_key.sign(KEY_ROTATION_CONTEXT, &new_public_key)),
+        });
+        Ok(file)
+    }
+
+    /// Check the previous key's signature, if this key replaced one
+    pub fn verify_rotation(&self) -> Result<(), String> {
+        let Some(rotation) = &self.rotated_from else {
+            return Ok(());
+        };
+        let decode = |name: &str, text: &str| hex::decode(text).map_err(|e| format!("Invalid {} hex: {}", name, e));
+        verify_signature(
+            &decode("previous public key", &rotation.previous_public_key)?,
+            KEY_ROTATION_CONTEXT,
+            &decode("public key", &self.public_key)?,
+            &decode("rotation signature", &rotation.signature)?,
+        )
+    }
+
+    /// The address paying to this key, for kinds that are paid
+    pub fn address(&self) -> Option<String> {
+        hex::decode(&self.public_key).ok().and_then(|public_key| self.kind.address(&public_key))
+    }
+
+    /// Write the file, readable only by its owner where the platform allows
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| format!("Failed to encode key file: {}", e))?;
+        atomic_write_private(path, json.as_bytes())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let file: KeyFile = serde_json::from_str(&text).map_err(|e| format!("Invalid key file {}: {}", path.display(), e))?;
+        if file.version != KEY_FILE_VERSION {
+            return Err(format!("Key file {} is version {}, expected {}", path.display(), file.version, KEY_FILE_VERSION));
+        }
+        Ok(file)
+    }
+}
+
+// what the cipher authenticates besides the seed
+fn associated_data(kind: KeyKind, public_key: &str) -> Vec<u8> {
+    format!("hourcoin-key-file:{}:{}:{}", KEY_FILE_VERSION, kind, public_key).into_bytes()
+}
+
+#[cfg(feature = "node")]
+/// The passphrase from `HOURCOIN_KEY_PASSPHRASE`, or typed at a hidden prompt
+pub fn read_passphrase(prompt: &str) -> Result<String, String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    rpassword::prompt_password(prompt).map_err(|e| format!("Failed to read passphrase: {}", e))
+}
+
+#[cfg(feature = "node")]
+/// Like `read_passphrase`, but a typed passphrase must be entered twice
+pub fn read_new_passphrase(prompt: &str) -> Result<String, String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    let passphrase = rpassword::prompt_password(prompt).map_err(|e| format!("Failed to read passphrase: {}", e))?;
+    let confirmation = rpassword::prompt_password("Repeat passphrase: ").map_err(|e| format!("Failed to read passphrase: {}", e))?;
+    if passphrase != confirmation {
+        return Err("Passphrases don't match".to_string());
+    }
+    Ok(passphrase)
+}
+
+#[cfg(feature = "node")]
+/// Decrypt the key of `kind` at `path`, asking for its passphrase
+pub fn unlock(path: &Path, kind: KeyKind) -> Result<[u8; 32], String> {
+    let file = KeyFile::load(path)?;
+    if file.kind != kind {
+        return Err(format!("{} holds a {} key, not a {} key", path.display(), file.kind, kind));
+    }
+    file.open(&read_passphrase(&format!("Passphrase for {}: ", path.display()))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // cheap enough for debug builds
+    fn test_kdf() -> KdfParams {
+        KdfParams { memory_kib: 64, iterations: 1, parallelism: 1, salt: String::new() }
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let key = NodeKey::generate();
+        let file = KeyFile::seal(KeyKind::Node, &key.secret_bytes(), "correct horse", test_kdf()).unwrap();
+        assert_eq!(file.public_key, hex::encode(key.public_key()));
+        assert_eq!(file.address(), Some(address_from_public_key(&key.public_key())));
+        assert_eq!(file.open("correct horse"), Ok(key.secret_bytes()));
+        assert!(file.open("wrong horse").is_err());
+
+        // the kind and public key are authenticated with the seed
+        let mut relabeled = file.clone();
+        relabeled.kind = KeyKind::Miner;
+        assert!(relabeled.open("correct horse").is_err());
+
+        let moved = file.change_passphrase("correct horse", "battery staple").unwrap();
+        assert_eq!(moved.open("battery staple"), Ok(key.secret_bytes()));
+        assert_ne!(moved.kdf.salt, file.kdf.salt);
+
+        let path = std::env::temp_dir().join(format!("hourcoin-keystore-{}.json", std::process::id()));
+        file.save(&path).unwrap();
+        assert_eq!(KeyFile::load(&path), Ok(file));
+        std::fs::remove_file(&path).unwrap();
+
+        let vrf = KeyFile::generate(KeyKind::Vrf, "", test_kdf()).unwrap();
+        assert_eq!(vrf.address(), None);
+        assert!(VrfKeypair::from_secret_bytes(&vrf.open("").unwrap()).is_ok());
+        assert_eq!("vrf".parse::<KeyKind>(), Ok(KeyKind::Vrf));
+    }
+
+    #[test]
+    fn test_rotation_is_signed_by_the_old_key() {
+        let old = KeyFile::generate(KeyKind::Miner, "old", test_kdf()).unwrap();
+        let new = old.rotate("old", "new").unwrap();
+        assert_ne!(new.public_key, old.public_key);
+        assert_eq!(new.rotated_from.as_ref().unwrap().previous_public_key, old.public_key);
+        assert!(new.verify_rotation().is_ok());
+        assert!(new.open("new").is_ok());
+        assert!(old.rotate("wrong", "new").is_err());
+
+        let mut forged = new.clone();
+        forged.rotated_from.as_mut().unwrap().previous_public_key = KeyFile::generate(KeyKind::Miner, "", test_kdf()).unwrap().public_key;
+        assert!(forged.verify_rotation().is_err());
+    }
+
+    #[test]
+    fn test_federation_share_file() {
+        use crate::federation::{Federation, FederationMember};
+
+        let (federation, members) = Federation::trusted_dealer_keygen(3, 2).unwrap();
+        let file = KeyFile::seal(KeyKind::FederationShare, &members[1].secret_share(), "share", test_kdf()).unwrap();
+        assert_eq!(file.address(), None);
+        assert!(serde_json::to_string(&file).unwrap().contains(r#""kind":"federation""#));
+
+        let member = FederationMember::from_secret_share(&federation, &file.open("share").unwrap()).unwrap();
+        assert_eq!(member.member_id(), 2);
+        assert!(file.rotate("share", "new").is_err());
+        assert!(KeyFile::generate(KeyKind::FederationShare, "share", test_kdf()).is_err());
+    }
+}
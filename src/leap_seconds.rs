@@ -8,14 +8,37 @@
 /// - Time to appear to go backwards
 /// - Duplicate timestamps
 /// - Consensus disagreements between nodes
-
-use chrono::{DateTime, Utc, NaiveDateTime};
-use std::sync::OnceLock;
+///
+/// The table starts out as the built-in copy below. A `LeapSecondUpdater`
+/// downloads the IETF `leap-seconds.list`, checks its hash and expiry, caches
+/// it on disk and swaps it in without a restart. The hash only catches a
+/// truncated or corrupted download; it is not a signature, so the list is
+/// only as trustworthy as the HTTPS connection it came over.
+
+use chrono::{DateTime, Utc};
+use sha1::{Digest, Sha1};
+#[cfg(feature = "node")]
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock, RwLock};
+#[cfg(feature = "node")]
+use std::time::Duration;
+#[cfg(feature = "node")]
+use tracing::{info, warn};
+
+/// Where the IETF publishes the leap second list (mirrored from IERS Bulletin C)
+pub const LEAP_SECONDS_LIST_URL: &str = "https://data.iana.org/time-zones/tzdb/leap-seconds.list";
+
+/// How often `LeapSecondUpdater::run` refreshes the table; lists are published twice a year
+#[cfg(feature = "node")]
+pub const LEAP_SECONDS_REFRESH_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Seconds from the NTP epoch (1900), used by leap-seconds.list, to the Unix epoch
+const NTP_UNIX_EPOCH_DELTA_SECS: i64 = 2_208_988_800;
 
 /// Leap second offset data
 /// Source: IERS Bulletin C (International Earth Rotation Service)
 /// https://www.iers.org/IERS/EN/Publications/Bulletins/bulletins.html
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 struct LeapSecond {
     /// Unix timestamp (in seconds) when leap second is introduced
     timestamp: i64,
@@ -23,64 +46,210 @@ struct LeapSecond {
     tai_offset: i32,
 }
 
-/// Leap second table (updated as of January 2025)
-/// Format: (Unix timestamp, TAI-UTC offset)
-static LEAP_SECONDS: OnceLock<Vec<LeapSecond>> = OnceLock::new();
+/// A list of leap seconds and how long it is valid for
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeapSecondTable {
+    entries: Vec<LeapSecond>,
+    /// When the list was last updated (Unix seconds)
+    updated: i64,
+    /// When the list stops being valid; leap seconds after this are not yet known (Unix seconds)
+    expires: i64,
+}
 
-fn get_leap_seconds() -> &'static Vec<LeapSecond> {
-    LEAP_SECONDS.get_or_init(|| {
-        vec![
+impl LeapSecondTable {
+    /// The table compiled into this build (leap-seconds.list of July 2026)
+    pub fn builtin() -> Self {
+        let entries = [
             // Historical leap seconds since 1972
-            LeapSecond { timestamp: 63072000, tai_offset: 10 },   // 1972-01-01
-            LeapSecond { timestamp: 78796800, tai_offset: 11 },   // 1972-07-01
-            LeapSecond { timestamp: 94694400, tai_offset: 12 },   // 1973-01-01
-            LeapSecond { timestamp: 126230400, tai_offset: 13 },  // 1974-01-01
-            LeapSecond { timestamp: 157766400, tai_offset: 14 },  // 1975-01-01
-            LeapSecond { timestamp: 189302400, tai_offset: 15 },  // 1976-01-01
-            LeapSecond { timestamp: 220924800, tai_offset: 16 },  // 1977-01-01
-            LeapSecond { timestamp: 252460800, tai_offset: 17 },  // 1978-01-01
-            LeapSecond { timestamp: 283996800, tai_offset: 18 },  // 1979-01-01
-            LeapSecond { timestamp: 315532800, tai_offset: 19 },  // 1980-01-01
-            LeapSecond { timestamp: 362793600, tai_offset: 20 },  // 1981-07-01
-            LeapSecond { timestamp: 394329600, tai_offset: 21 },  // 1982-07-01
-            LeapSecond { timestamp: 425865600, tai_offset: 22 },  // 1983-07-01
-            LeapSecond { timestamp: 489024000, tai_offset: 23 },  // 1985-07-01
-            LeapSecond { timestamp: 567993600, tai_offset: 24 },  // 1988-01-01
-            LeapSecond { timestamp: 631152000, tai_offset: 25 },  // 1990-01-01
-            LeapSecond { timestamp: 662688000, tai_offset: 26 },  // 1991-01-01
-            LeapSecond { timestamp: 709948800, tai_offset: 27 },  // 1992-07-01
-            LeapSecond { timestamp: 741484800, tai_offset: 28 },  // 1993-07-01
-            LeapSecond { timestamp: 773020800, tai_offset: 29 },  // 1994-07-01
-            LeapSecond { timestamp: 820454400, tai_offset: 30 },  // 1996-01-01
-            LeapSecond { timestamp: 867715200, tai_offset: 31 },  // 1997-07-01
-            LeapSecond { timestamp: 915148800, tai_offset: 32 },  // 1999-01-01
-            LeapSecond { timestamp: 1136073600, tai_offset: 33 }, // 2006-01-01
-            LeapSecond { timestamp: 1230768000, tai_offset: 34 }, // 2009-01-01
-            LeapSecond { timestamp: 1341100800, tai_offset: 35 }, // 2012-07-01
-            LeapSecond { timestamp: 1435708800, tai_offset: 36 }, // 2015-07-01
-            LeapSecond { timestamp: 1483228800, tai_offset: 37 }, // 2017-01-01
-            // Note: Update this table when new leap seconds are announced
-            // Check IERS Bulletin C: https://www.iers.org/IERS/EN/Publications/Bulletins/bulletins.html
-        ]
-    })
-}
+            (63072000, 10),   // 1972-01-01
+            (78796800, 11),   // 1972-07-01
+            (94694400, 12),   // 1973-01-01
+            (126230400, 13),  // 1974-01-01
+            (157766400, 14),  // 1975-01-01
+            (189302400, 15),  // 1976-01-01
+            (220924800, 16),  // 1977-01-01
+            (252460800, 17),  // 1978-01-01
+            (283996800, 18),  // 1979-01-01
+            (315532800, 19),  // 1980-01-01
+            (362793600, 20),  // 1981-07-01
+            (394329600, 21),  // 1982-07-01
+            (425865600, 22),  // 1983-07-01
+            (489024000, 23),  // 1985-07-01
+            (567993600, 24),  // 1988-01-01
+            (631152000, 25),  // 1990-01-01
+            (662688000, 26),  // 1991-01-01
+            (709948800, 27),  // 1992-07-01
+            (741484800, 28),  // 1993-07-01
+            (773020800, 29),  // 1994-07-01
+            (820454400, 30),  // 1996-01-01
+            (867715200, 31),  // 1997-07-01
+            (915148800, 32),  // 1999-01-01
+            (1136073600, 33), // 2006-01-01
+            (1230768000, 34), // 2009-01-01
+            (1341100800, 35), // 2012-07-01
+            (1435708800, 36), // 2015-07-01
+            (1483228800, 37), // 2017-01-01
+        ];
+        LeapSecondTable {
+            entries: entries.iter().map(|&(timestamp, tai_offset)| LeapSecond { timestamp, tai_offset }).collect(),
+            updated: 1783296000, // 2026-07-06
+            expires: 1814140800, // 2027-06-28
+        }
+    }
 
-/// Get the TAI-UTC offset for a given Unix timestamp
-fn get_tai_offset(unix_seconds: i64) -> i32 {
-    let leap_seconds = get_leap_seconds();
+    /// Parse a leap-seconds.list file, checking its hash
+    ///
+    /// The `#h` line is a SHA-1 of the digits of the `#$` (updated), `#@`
+    /// (expires) and data lines, so a truncated or corrupted file is refused.
+    /// Anyone can recompute it, so it says nothing about who wrote the list.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut updated = None;
+        let mut expires = None;
+        let mut hash = None;
+        let mut digits = String::new();
+        let mut entries: Vec<LeapSecond> = vec![];
+
+        for line in text.lines() {
+            let line = line.trim();
+            if let Some(value) = line.strip_prefix("#$") {
+                updated = Some(parse_ntp_seconds(value)?);
+                digits.extend(value.chars().filter(char::is_ascii_digit));
+            } else if let Some(value) = line.strip_prefix("#@") {
+                expires = Some(parse_ntp_seconds(value)?);
+                digits.extend(value.chars().filter(char::is_ascii_digit));
+            } else if let Some(value) = line.strip_prefix("#h") {
+                // Each word is printed without leading zeros
+                let words: Result<Vec<u32>, _> = value.split_whitespace().map(|word| u32::from_str_radix(word, 16)).collect();
+                hash = Some(words.map_err(|e| format!("Invalid leap second list hash: {}", e))?
+                    .iter().map(|word| format!("{:08x}", word)).collect::<String>());
+            } else if line.is_empty() || line.starts_with('#') {
+                continue;
+            } else {
+                let data = line.split('#').next().unwrap_or_default();
+                let mut fields = data.split_whitespace();
+                let (Some(time), Some(offset)) = (fields.next(), fields.next()) else {
+                    return Err(format!("Invalid leap second entry: {}", line));
+                };
+                let timestamp = parse_ntp_seconds(time)?;
+                let tai_offset = offset.parse::<i32>().map_err(|e| format!("Invalid TAI offset {}: {}", offset, e))?;
+                if entries.last().is_some_and(|last| last.timestamp >= timestamp) {
+                    return Err(format!("Leap second entries out of order at {}", time));
+                }
+                entries.push(LeapSecond { timestamp, tai_offset });
+                digits.extend(data.chars().filter(char::is_ascii_digit));
+            }
+        }
 
-    // Find the most recent leap second before this timestamp
-    let mut offset = 10; // Initial offset in 1972
+        let expected = hash.ok_or_else(|| "Leap second list has no hash".to_string())?;
+        let actual = hex::encode(Sha1::digest(digits.as_bytes()));
+        if actual != expected {
+            return Err("Leap second list hash does not match its contents".to_string());
+        }
+        if entries.is_empty() {
+            return Err("Leap second list has no entries".to_string());
+        }
+
+        Ok(LeapSecondTable {
+            entries,
+            updated: updated.ok_or_else(|| "Leap second list has no update time".to_string())?,
+            expires: expires.ok_or_else(|| "Leap second list has no expiry".to_string())?,
+        })
+    }
 
-    for leap in leap_seconds {
-        if unix_seconds >= leap.timestamp {
-            offset = leap.tai_offset;
-        } else {
-            break;
+    /// TAI-UTC offset in effect at `unix_seconds`
+    fn tai_offset(&self, unix_seconds: i64) -> i32 {
+        // Find the most recent leap second before this timestamp
+        let mut offset = 10; // Initial offset in 1972
+
+        for leap in &self.entries {
+            if unix_seconds >= leap.timestamp {
+                offset = leap.tai_offset;
+            } else {
+                break;
+            }
         }
+
+        offset
     }
 
-    offset
+    /// When the list was last updated
+    pub fn updated_at(&self) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp(self.updated, 0)
+    }
+
+    /// When the list stops being valid
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp(self.expires, 0)
+    }
+
+    /// Whether the list may be missing leap seconds announced since it was published
+    pub fn is_expired(&self, unix_seconds: i64) -> bool {
+        unix_seconds >= self.expires
+    }
+
+    /// First leap second after `unix_seconds` (Unix seconds)
+    pub fn next_after(&self, unix_seconds: i64) -> Option<i64> {
+        self.entries.iter().map(|leap| leap.timestamp).find(|&timestamp| timestamp > unix_seconds)
+    }
+
+    /// Number of leap seconds listed
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Refuse a table that contradicts this one's leap seconds
+    ///
+    /// Published leap seconds never change, so a list that moves one, or a
+    /// newer list that drops one, is corrupt.
+    fn check_consistent(&self, other: &LeapSecondTable) -> Result<(), String> {
+        if let Some((known, _)) = self.entries.iter().zip(&other.entries).find(|(known, new)| known != new) {
+            return Err(format!("Leap second list disagrees with the known leap second at {}", known.timestamp));
+        }
+        if other.updated >= self.updated && other.entries.len() < self.entries.len() {
+            return Err("Leap second list is missing known leap seconds".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn parse_ntp_seconds(value: &str) -> Result<i64, String> {
+    let value = value.trim();
+    let ntp = value.parse::<i64>().map_err(|e| format!("Invalid NTP time {}: {}", value, e))?;
+    Ok(ntp - NTP_UNIX_EPOCH_DELTA_SECS)
+}
+
+/// The leap second table in use, swapped whole when an update is installed
+static LEAP_SECONDS: OnceLock<RwLock<Arc<LeapSecondTable>>> = OnceLock::new();
+
+fn table_lock() -> &'static RwLock<Arc<LeapSecondTable>> {
+    LEAP_SECONDS.get_or_init(|| RwLock::new(Arc::new(LeapSecondTable::builtin())))
+}
+
+/// The leap second table currently in use
+pub fn current_table() -> Arc<LeapSecondTable> {
+    Arc::clone(&table_lock().read().unwrap())
+}
+
+/// Replace the leap second table in use
+///
+/// The new table must keep every leap second of the current one; it only adds
+/// newly announced ones and a later expiry. An older list than the one in use is ignored.
+pub fn install_table(table: LeapSecondTable) -> Result<(), String> {
+    let mut current = table_lock().write().unwrap();
+    current.check_consistent(&table)?;
+    if table.updated >= current.updated {
+        *current = Arc::new(table);
+    }
+    Ok(())
+}
+
+/// Get the TAI-UTC offset for a given Unix timestamp
+fn get_tai_offset(unix_seconds: i64) -> i32 {
+    current_table().tai_offset(unix_seconds)
 }
 
 /// Convert UTC timestamp to TAI (International Atomic Time)
@@ -93,9 +262,11 @@ pub fn utc_to_tai_millis(utc_millis: i64) -> i64 {
 
 /// Convert TAI timestamp to UTC
 pub fn tai_to_utc_millis(tai_millis: i64) -> i64 {
-    // Approximate UTC seconds for lookup
-    let approx_utc_seconds = (tai_millis / 1000) - 37; // 37 is current max offset
-    let offset = get_tai_offset(approx_utc_seconds);
+    let table = current_table();
+    // Approximate UTC seconds for lookup, using the latest offset
+    let latest_offset = table.entries.last().map_or(10, |leap| leap.tai_offset);
+    let approx_utc_seconds = (tai_millis / 1000) - latest_offset as i64;
+    let offset = table.tai_offset(approx_utc_seconds);
     tai_millis - (offset as i64 * 1000)
 }
 
@@ -110,9 +281,9 @@ pub fn now_tai_millis() -> i64 {
 /// Returns true if within 1 second of a known leap second
 pub fn is_near_leap_second(utc_millis: i64) -> bool {
     let utc_seconds = utc_millis / 1000;
-    let leap_seconds = get_leap_seconds();
+    let table = current_table();
 
-    for leap in leap_seconds {
+    for leap in &table.entries {
         let diff = (utc_seconds - leap.timestamp).abs();
         if diff <= 1 {
             return true;
@@ -124,11 +295,12 @@ pub fn is_near_leap_second(utc_millis: i64) -> bool {
 
 /// Get information about the next scheduled leap second
 /// Returns None if no leap second is scheduled
+///
+/// Leap seconds are announced about six months ahead, so this only knows
+/// about one once an updated table has been installed.
 pub fn next_leap_second() -> Option<DateTime<Utc>> {
-    // In practice, this would query IERS Bulletin C for announcements
-    // Leap seconds are announced at least 8 weeks in advance
-    // For now, return None as no future leap seconds are in the table
-    None
+    let now = Utc::now().timestamp();
+    current_table().next_after(now).and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
 }
 
 /// Validate that a time difference is monotonically increasing
@@ -137,6 +309,83 @@ pub fn validate_time_ordering(prev_tai_millis: i64, curr_tai_millis: i64) -> boo
     curr_tai_millis > prev_tai_millis
 }
 
+/// Keeps the leap second table current from the published leap-seconds.list
+#[cfg(feature = "node")]
+pub struct LeapSecondUpdater {
+    url: String,
+    /// Where the last good list is kept between runs
+    cache_path: PathBuf,
+}
+
+#[cfg(feature = "node")]
+impl LeapSecondUpdater {
+    /// Fetch from `LEAP_SECONDS_LIST_URL`, caching the list at `cache_path`
+    pub fn new(cache_path: impl Into<PathBuf>) -> Self {
+        LeapSecondUpdater {
+            url: LEAP_SECONDS_LIST_URL.to_string(),
+            cache_path: cache_path.into(),
+        }
+    }
+
+    /// Fetch the list from a mirror instead
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = url.into();
+        self
+    }
+
+    /// Read and verify the cached list
+    pub fn load_cached(&self) -> Result<LeapSecondTable, String> {
+        let text = std::fs::read_to_string(&self.cache_path)
+            .map_err(|e| format!("Failed to read {}: {}", self.cache_path.display(), e))?;
+        LeapSecondTable::parse(&text)
+    }
+
+    /// Download and verify the list, caching it if it is still valid
+    pub async fn fetch(&self) -> Result<LeapSecondTable, String> {
+        let text = reqwest::get(&self.url).await
+            .map_err(|e| format!("Failed to fetch {}: {}", self.url, e))?
+            .error_for_status()
+            .map_err(|e| format!("Failed to fetch {}: {}", self.url, e))?
+            .text().await
+            .map_err(|e| format!("Failed to read {}: {}", self.url, e))?;
+
+        let table = LeapSecondTable::parse(&text)?;
+        if table.is_expired(Utc::now().timestamp()) {
+            return Err(format!("Leap second list from {} has expired", self.url));
+        }
+        std::fs::write(&self.cache_path, text)
+            .map_err(|e| format!("Failed to write {}: {}", self.cache_path.display(), e))?;
+        Ok(table)
+    }
+
+    /// Install the freshest list available: downloaded, else cached
+    ///
+    /// Returns the table now in use. Errors only if neither could be installed,
+    /// in which case the current table stays in place.
+    pub async fn update(&self) -> Result<Arc<LeapSecondTable>, String> {
+        let table = match self.fetch().await {
+            Ok(table) => table,
+            Err(fetch_error) => self.load_cached()
+                .map_err(|cache_error| format!("{}; {}", fetch_error, cache_error))?,
+        };
+        install_table(table)?;
+        Ok(current_table())
+    }
+
+    /// Update the table every `interval`, logging the outcome, until the task is dropped
+    ///
+    /// A failed update leaves the table in use and is retried at the next interval.
+    pub async fn run(self, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            match self.update().await {
+                Ok(table) => info!(entries = table.len(), expires = ?table.expires_at(), "leap second table updated"),
+                Err(e) => warn!(error = %e, expires = ?current_table().expires_at(), "leap second update failed; keeping the current table"),
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -259,7 +508,8 @@ mod tests {
 
     #[test]
     fn test_leap_second_table_is_sorted() {
-        let leap_seconds = get_leap_seconds();
+        let table = current_table();
+        let leap_seconds = &table.entries;
 
         for i in 1..leap_seconds.len() {
             // Timestamps should be increasing
@@ -269,4 +519,91 @@ mod tests {
             assert_eq!(leap_seconds[i].tai_offset, leap_seconds[i-1].tai_offset + 1);
         }
     }
+
+    /// leap-seconds.list of July 2026, without its comments
+    const LEAP_SECONDS_LIST: &str = "\
+#$\t3992284800\n\
+#@\t4023129600\n\
+2272060800      10      # 1 Jan 1972\n\
+2287785600      11      # 1 Jul 1972\n\
+2303683200      12      # 1 Jan 1973\n\
+2335219200      13      # 1 Jan 1974\n\
+2366755200      14      # 1 Jan 1975\n\
+2398291200      15      # 1 Jan 1976\n\
+2429913600      16      # 1 Jan 1977\n\
+2461449600      17      # 1 Jan 1978\n\
+2492985600      18      # 1 Jan 1979\n\
+2524521600      19      # 1 Jan 1980\n\
+2571782400      20      # 1 Jul 1981\n\
+2603318400      21      # 1 Jul 1982\n\
+2634854400      22      # 1 Jul 1983\n\
+2698012800      23      # 1 Jul 1985\n\
+2776982400      24      # 1 Jan 1988\n\
+2840140800      25      # 1 Jan 1990\n\
+2871676800      26      # 1 Jan 1991\n\
+2918937600      27      # 1 Jul 1992\n\
+2950473600      28      # 1 Jul 1993\n\
+2982009600      29      # 1 Jul 1994\n\
+3029443200      30      # 1 Jan 1996\n\
+3076704000      31      # 1 Jul 1997\n\
+3124137600      32      # 1 Jan 1999\n\
+3345062400      33      # 1 Jan 2006\n\
+3439756800      34      # 1 Jan 2009\n\
+3550089600      35      # 1 Jul 2012\n\
+3644697600      36      # 1 Jul 2015\n\
+3692217600      37      # 1 Jan 2017\n\
+#h\tae9c7fe a63be085 15bf660e 8fe336c2 69da28d8\n\
+";
+
+    #[test]
+    fn test_parse_leap_seconds_list() {
+        let table = LeapSecondTable::parse(LEAP_SECONDS_LIST).unwrap();
+        assert_eq!(table, LeapSecondTable::builtin());
+        assert_eq!(table.tai_offset(1483228800), 37);
+        assert!(!table.is_expired(1814140799));
+        assert!(table.is_expired(1814140800));
+
+        // Any change to the data breaks the hash
+        let edited = LEAP_SECONDS_LIST.replace("3692217600      37", "3692217600      38");
+        assert!(LeapSecondTable::parse(&edited).is_err());
+        let without_hash: String = LEAP_SECONDS_LIST.lines().filter(|line| !line.starts_with("#h")).map(|line| format!("{}\n", line)).collect();
+        assert!(LeapSecondTable::parse(&without_hash).is_err());
+    }
+
+    #[test]
+    fn test_install_scheduled_leap_second() {
+        // A far-future leap second, so the rest of the suite is unaffected
+        let mut table = LeapSecondTable::builtin();
+        table.entries.push(LeapSecond { timestamp: 4102444800, tai_offset: 38 }); // 2100-01-01
+        table.updated += 1;
+        table.expires = 4118083200;
+        assert_eq!(table.next_after(1483228800), Some(4102444800));
+        assert_eq!(LeapSecondTable::builtin().next_after(1483228800), None);
+
+        // A list that rewrites history is refused
+        let mut rewritten = table.clone();
+        rewritten.entries[5].tai_offset += 1;
+        assert!(install_table(rewritten).is_err());
+
+        install_table(table).unwrap();
+        assert_eq!(next_leap_second().map(|at| at.timestamp()), Some(4102444800));
+        assert_eq!(utc_to_tai_millis(4102444800000), 4102444800000 + 38000);
+        assert_eq!(tai_to_utc_millis(4102444800000 + 38000), 4102444800000);
+
+        // An older list doesn't replace a newer one
+        install_table(LeapSecondTable::builtin()).unwrap();
+        assert_eq!(current_table().len(), 29);
+    }
+
+    #[test]
+    #[cfg(feature = "node")]
+    fn test_updater_loads_cache() {
+        let path = std::env::temp_dir().join(format!("hourcoin-leap-seconds-{}.list", std::process::id()));
+        let updater = LeapSecondUpdater::new(&path);
+        assert!(updater.load_cached().is_err());
+
+        std::fs::write(&path, LEAP_SECONDS_LIST).unwrap();
+        assert_eq!(updater.load_cached().unwrap().expires_at().unwrap().timestamp(), 1814140800);
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -1,4 +1,3 @@
-type BlockHash = Vec<u8>;
 type Address = String;
 
 use chrono::Utc;
@@ -26,90 +25,93 @@ pub fn now_utc() -> u128 {
 	Utc::now().timestamp_millis() as u128
 }
 
-pub fn u32_bytes (u: &u32) -> [u8; 4] {
-    [
-        (u >> 8 * 0x0) as u8,
-        (u >> 8 * 0x1) as u8,
-        (u >> 8 * 0x2) as u8,
-        (u >> 8 * 0x3) as u8,
-    ]
-}
-
-pub fn u64_bytes (u: &u64) -> [u8; 8] {
-    [
-        (u >> 8 * 0x0) as u8,
-        (u >> 8 * 0x1) as u8,
-        (u >> 8 * 0x2) as u8,
-        (u >> 8 * 0x3) as u8,
-
-        (u >> 8 * 0x4) as u8,
-        (u >> 8 * 0x5) as u8,
-        (u >> 8 * 0x6) as u8,
-        (u >> 8 * 0x7) as u8,
-    ]
-}
-
-pub fn u128_bytes (u: &u128) -> [u8; 16] {
-    [
-        (u >> 8 * 0x0) as u8,
-        (u >> 8 * 0x1) as u8,
-        (u >> 8 * 0x2) as u8,
-        (u >> 8 * 0x3) as u8,
-
-        (u >> 8 * 0x4) as u8,
-        (u >> 8 * 0x5) as u8,
-        (u >> 8 * 0x6) as u8,
-        (u >> 8 * 0x7) as u8,
-
-        (u >> 8 * 0x8) as u8,
-        (u >> 8 * 0x9) as u8,
-        (u >> 8 * 0xa) as u8,
-        (u >> 8 * 0xb) as u8,
-
-        (u >> 8 * 0xc) as u8,
-        (u >> 8 * 0xd) as u8,
-        (u >> 8 * 0xe) as u8,
-        (u >> 8 * 0xf) as u8,
-    ]
-}
-
-pub fn difficulty_bytes_as_u128 (v: &Vec<u8>) -> u128 {
-    ((v[31] as u128) << 0xf * 8) |
-    ((v[30] as u128) << 0xe * 8) |
-    ((v[29] as u128) << 0xd * 8) |
-    ((v[28] as u128) << 0xc * 8) |
-    ((v[27] as u128) << 0xb * 8) |
-    ((v[26] as u128) << 0xa * 8) |
-    ((v[25] as u128) << 0x9 * 8) |
-    ((v[24] as u128) << 0x8 * 8) |
-    ((v[23] as u128) << 0x7 * 8) |
-    ((v[22] as u128) << 0x6 * 8) |
-    ((v[21] as u128) << 0x5 * 8) |
-    ((v[20] as u128) << 0x4 * 8) |
-    ((v[19] as u128) << 0x3 * 8) |
-    ((v[18] as u128) << 0x2 * 8) |
-    ((v[17] as u128) << 0x1 * 8) |
-    ((v[16] as u128) << 0x0 * 8)
-}
+pub mod endian;
+pub use crate::endian::{difficulty_bytes_as_u128, u128_bytes, u32_bytes, u64_bytes};
 
 mod block;
-pub use crate::block::Block;
+pub use crate::block::{Block, BlockBody, BlockHeader, MiningProgress, RoundCommitment};
 mod hashable;
 pub use crate::hashable::Hashable;
+pub mod hashes;
+pub use crate::hashes::{BlockHash, Nonce, Txid};
 mod blockchain;
-pub use crate::blockchain::Blockchain;
+pub use crate::blockchain::{Blockchain, AddressTx, BlockValidationErr, Reorg};
+pub mod canonical;
+pub mod difficulty;
+pub use crate::difficulty::Difficulty;
+pub mod chain_file;
+pub mod merkle;
 pub mod transaction;
 pub use crate::transaction::Transaction;
+pub mod htlc;
+pub mod channels;
+pub mod multisig;
+pub mod psbt;
+pub mod tx_builder;
+pub mod signature_cache;
 
 // Proof of Time modules
+pub mod analytics;
+pub mod atomic_file;
+#[cfg(feature = "node")]
+pub mod block_store;
+pub mod chain_params;
+pub mod commitment;
+pub mod compact_block;
+pub mod consensus;
+pub mod consensus_engine;
+pub mod decision_log;
+pub mod estimate;
+#[cfg(feature = "node")]
+pub mod faucet;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod federation;
+pub mod mempool;
+pub mod miner_stats;
+pub mod time_source;
 pub mod time_sync;
+pub mod keystore;
+pub mod lru_cache;
+pub mod node_key;
+pub mod receipt;
+pub mod round_history;
+#[cfg(feature = "node")]
+pub mod simulation;
+pub mod snapshot;
+pub mod sntp;
+pub mod spv;
 pub mod tonce;
+pub mod utxo_snapshot;
+#[cfg(feature = "node")]
 pub mod validator;
+#[cfg(feature = "node")]
+pub mod validation_hook;
+pub mod vrf;
+pub mod wallet;
+pub mod watch;
 
 // Network modules
 pub mod network;
-
+#[cfg(feature = "node")]
+pub mod pool;
+
+// Operator tooling
+#[cfg(feature = "tui")]
+pub mod dashboard;
+#[cfg(feature = "node")]
+pub mod doctor;
+#[cfg(feature = "node")]
+pub mod explorer;
+#[cfg(feature = "node")]
+pub mod logging;
+
+pub use crate::chain_params::{ChainParams, Network};
+pub use crate::consensus_engine::{Challenge, ConsensusEngine, Hybrid, ProofOfTime, ProofOfWork};
+pub use crate::estimate::{estimate_mining_time, estimate_tonce_attempts, MiningEstimate, TonceEstimate};
 pub use crate::time_sync::TimeSync;
-pub use crate::tonce::{TonceChallenge, find_valid_timestamp};
-pub use crate::validator::{Validator, MinerSession, ValidationResult, RoundInfo};
+pub use crate::tonce::{passes_tonce, valid_timestamps, TimestampCandidates, TimestampWindow, TonceChallenge, find_valid_timestamp, find_valid_timestamp_parallel};
+#[cfg(feature = "node")]
+pub use crate::validator::{Validator, MinerSession, ValidationResult, RoundInfo, LockoutPolicy, AttemptPolicy};
+#[cfg(feature = "node")]
 pub use crate::network::{ValidatorServer, MinerClient};
\ No newline at end of file
@@ -0,0 +1,100 @@
+//! Structured logging setup for the Hourcoin binaries
+//!
+//! The validator and miner log through `tracing`. The level comes from
+//! `--log-level` (falling back to `RUST_LOG`, then `info`), and `--log-json`
+//! switches to one JSON object per line for log aggregation.
+
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// Logging options parsed from the command line
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogConfig {
+    /// Filter directive, e.g. `info` or `blockchainlib=debug`
+    pub level: Option<String>,
+    /// Emit JSON lines instead of human readable output
+    pub json: bool,
+}
+
+impl LogConfig {
+    /// Remove `--log-level <level>` and `--log-json` from `args`, leaving positional arguments
+    pub fn from_args(args: &mut Vec<String>) -> Self {
+        let json = args.iter().any(|arg| arg == "--log-json");
+        args.retain(|arg| arg != "--log-json");
+
+        let mut level = None;
+        if let Some(pos) = args.iter().position(|arg| arg == "--log-level") {
+            args.remove(pos);
+            if pos < args.len() {
+                level = Some(args.remove(pos));
+            }
+        }
+
+        LogConfig { level, json }
+    }
+
+    /// Install the global tracing subscriber
+    pub fn init(&self) {
+        self.install(std::io::stdout, true);
+    }
+
+    /// Install the global tracing subscriber, writing plain lines (no colour codes) to `writer`
+    ///
+    /// The terminal dashboards use this to show recent lines without them
+    /// being printed over the display.
+    pub fn init_with_writer<W>(&self, writer: W)
+    where
+        W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+    {
+        self.install(writer, false);
+    }
+
+    fn install<W>(&self, writer: W, ansi: bool)
+    where
+        W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+    {
+        let filter = match &self.level {
+            Some(level) => EnvFilter::new(level),
+            None => EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_LEVEL)),
+        };
+
+        let builder = tracing_subscriber::fmt().with_env_filter(filter).with_writer(writer).with_ansi(ansi);
+        if self.json {
+            builder.json().init();
+        } else {
+            builder.init();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_log_config_from_args() {
+        let mut argv = args(&["validator", "--log-level", "debug", "0.0.0.0:8080", "--log-json"]);
+        let config = LogConfig::from_args(&mut argv);
+
+        assert_eq!(config.level, Some("debug".to_string()));
+        assert!(config.json);
+        assert_eq!(argv, args(&["validator", "0.0.0.0:8080"]));
+    }
+
+    #[test]
+    fn test_log_config_defaults() {
+        let mut argv = args(&["miner", "alice"]);
+        let config = LogConfig::from_args(&mut argv);
+
+        assert_eq!(config.level, None);
+        assert!(!config.json);
+        assert_eq!(argv, args(&["miner", "alice"]));
+    }
+}
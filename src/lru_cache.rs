@@ -0,0 +1,142 @@
+//! A bounded map that forgets the least recently used entry first
+//!
+//! `LruCache` backs the signature cache (see `signature_cache`) and the block
+//! and header caches in front of a `BlockStore`. Each entry remembers when it
+//! was last used on a logical clock, and a second map orders entries by that
+//! clock so the oldest can be found without a scan. Lookups count as hits or
+//! misses, which the owners report as metrics.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use serde::Serialize;
+
+/// How well a cache is doing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    /// Lookups answered from the cache
+    pub hits: u64,
+    /// Lookups that weren't
+    pub misses: u64,
+    /// Entries held now
+    pub entries: usize,
+    pub capacity: usize,
+}
+
+/// Up to `capacity` entries, most recently used kept longest
+#[derive(Debug, Clone)]
+pub struct LruCache<K, V> {
+    capacity: usize,
+    entries: HashMap<K, (u64, V)>, // value and when it was last looked up or added
+    by_use: BTreeMap<u64, K>, // the same keys, oldest use first
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    /// Hold up to `capacity` entries; a capacity of 0 holds none
+    pub fn new(capacity: usize) -> Self {
+        LruCache {
+            capacity,
+            entries: HashMap::new(),
+            by_use: BTreeMap::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats { hits: self.hits, misses: self.misses, entries: self.entries.len(), capacity: self.capacity }
+    }
+
+    /// The entry for `key`, counted as a hit and marked used, or a miss
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        match self.entries.get_mut(key) {
+            Some((last_used, value)) => {
+                self.by_use.remove(last_used);
+                *last_used = self.clock;
+                self.by_use.insert(self.clock, key.clone());
+                self.clock += 1;
+                self.hits += 1;
+                Some(value)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Add or replace the entry for `key`, forgetting the least recently used if there's no room
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some((last_used, _)) = self.entries.insert(key.clone(), (self.clock, value)) {
+            self.by_use.remove(&last_used);
+        }
+        self.by_use.insert(self.clock, key);
+        self.clock += 1;
+        self.evict();
+    }
+
+    /// Keep only the entries whose key passes `keep`
+    pub fn retain(&mut self, mut keep: impl FnMut(&K) -> bool) {
+        let by_use = &mut self.by_use;
+        self.entries.retain(|key, (last_used, _)| {
+            let kept = keep(key);
+            if !kept {
+                by_use.remove(last_used);
+            }
+            kept
+        });
+    }
+
+    /// Hold up to `capacity` entries from now on, forgetting the least recently used that don't fit
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.entries.len() > self.capacity {
+            if let Some((_, oldest)) = self.by_use.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_cache() {
+        let mut cache = LruCache::new(2);
+        cache.insert(1, "one");
+        cache.insert(2, "two");
+        assert_eq!(cache.get(&1), Some(&"one"));
+        // 2 is now the least recently used
+        cache.insert(3, "three");
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 1, entries: 2, capacity: 2 });
+
+        cache.insert(1, "uno");
+        cache.retain(|key| *key != 3);
+        assert_eq!((cache.len(), cache.get(&1)), (1, Some(&"uno")));
+        cache.set_capacity(0);
+        assert!(cache.is_empty());
+        cache.insert(4, "four");
+        assert!(cache.is_empty());
+    }
+}
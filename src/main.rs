@@ -1,40 +1,31 @@
 use blockchainlib::*;
+use blockchainlib::logging::LogConfig;
 use rand::Rng; // used to generate random u128 numbers for timestamp examples
+use tracing::{info, info_span, warn};
 
 fn main() {
-	println!("=== Hourcoin: Proof of Time Blockchain ===\n");
+	let mut args: Vec<String> = std::env::args().collect();
+	LogConfig::from_args(&mut args).init();
+	info!("Hourcoin: Proof of Time Blockchain");
 
-	let difficulty = 0x0000FFFFFFFFFFFFFFFFFFFFFFFFFFFFF;
+	let difficulty = Difficulty::from_target(0x0000FFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
     let mut rng = rand::thread_rng();
 
-	println!("Part 1: Basic Blockchain Demo\n");
-	println!("Mining genesis block with traditional PoW...");
+	let part = info_span!("part", name = "basic blockchain").entered();
+	info!("mining genesis block with traditional PoW");
 
-	// example of genesis block with coinbase transaction
-	let mut genesis_block = Block::new(0, now(), vec![0; 32], vec![Transaction {
-																		inputs: vec![],
-																		outputs: vec![
-																			transaction::Output{
-																				value: 1.5,
-																				to_addr: "Alice".to_owned(),
-                                                                                timestamp: now()
-																			},
-																			transaction::Output{
-																				value: 0.5,
-																				to_addr: "Bob".to_owned(),
-                                                                                timestamp: now()
-																			}]}],);
+	// the genesis block is fixed by the chain parameters, paying a premine to Alice and Bob
+	let params = ChainParams::mainnet().with_difficulty(difficulty);
+	let mut genesis = chain_params::GenesisConfig::new(now(), "Hourcoin demo")
+		.with_premine("Alice", 1.5)
+		.with_premine("Bob", 0.5);
+	genesis.mine(difficulty);
+	info!(block = ?genesis.block(), "mined genesis block");
 
-	genesis_block.mine(difficulty);
-	println!("✓ Mined genesis block: {:?}", &genesis_block);
+	let mut blockchain = Blockchain::with_genesis(&params, &genesis).expect("Failed to add genesis block");
+	let last_hash = blockchain.blocks[0].hash;
 
-	let last_hash = genesis_block.hash.clone();
-
-	let mut blockchain = Blockchain::new_with_diff(difficulty);
-
-	blockchain.update_with_block(genesis_block).expect("Failed to add genesis block");
-
-	println!("\nMining second block...");
+	info!("mining second block");
 
 	 let mut block = Block::new(1, now(), last_hash, vec![
         Transaction {
@@ -44,56 +35,59 @@ fn main() {
                     to_addr: "Chris".to_owned(),
                     value: 2.0,
                     timestamp: rng.gen(),
+                    lock_until: None,
                 },
             ],
+            witnesses: vec![],
         },
         Transaction {
             inputs: vec![
-                blockchain.blocks[0].transactions[0].outputs[0].clone(),
+                blockchain.blocks[0].body.transactions[0].outputs[0].clone(),
             ],
             outputs: vec![
                 transaction::Output {
                     to_addr: "Alice".to_owned(),
                     value: 0.25,
                     timestamp: rng.gen(),
+                    lock_until: None,
                 },
                 transaction::Output {
                     to_addr: "Bob".to_owned(),
                     value: 0.5,
                     timestamp: rng.gen(),
+                    lock_until: None,
                 },
             ],
+            witnesses: vec![],
         },
     ],);
 
 	block.mine(blockchain.get_difficulty());
 
-    println!("✓ Mined block: {:?}", &block);
+    info!(?block, "mined block");
 
     blockchain.update_with_block(block).expect("Failed to add block");
 
-	println!("\n✓ Blockchain now has {} blocks", blockchain.blocks.len());
+	info!(blocks = blockchain.blocks.len(), "block added to the chain");
+	drop(part);
 
 	// Demonstrate proof of time system
-	println!("\n\nPart 2: Proof of Time Consensus Demo\n");
+	let part = info_span!("part", name = "proof of time").entered();
 
 	let mut validator = Validator::new(difficulty);
 	validator.start_new_round();
 
-	println!("Validator initialized with proof of time consensus");
 	let round_info = validator.get_round_info();
-	println!("Tonce for this round: {}", round_info.tonce.unwrap_or(0));
-	println!("Challenge duration: 60 seconds");
-	println!("Miner lockout period: 1 hour\n");
+	info!(tonce = round_info.tonce.unwrap_or(0), challenge_secs = 60, lockout_secs = 3600, "validator initialized with proof of time consensus");
 
 	// Simulate miner finding valid timestamp
 	let timestamp = now();
 	let tonce = validator.get_current_tonce().unwrap();
 
-	println!("Miner 'Alice' searching for valid timestamp with tonce {}...", tonce);
+	info!(miner = "Alice", tonce, "searching for a valid timestamp");
 
 	if let Some(valid_timestamp) = find_valid_timestamp(tonce, timestamp, 5000) {
-		println!("✓ Found valid timestamp: {}", valid_timestamp);
+		info!(timestamp = valid_timestamp, "found valid timestamp");
 
 		// Create and mine block
 		let coinbase = Transaction {
@@ -102,36 +96,64 @@ fn main() {
 				to_addr: "Alice".to_owned(),
 				value: 2.0,
 				timestamp: valid_timestamp,
+				lock_until: None,
 			}],
+			witnesses: vec![],
 		};
 
-		let mut new_block = Block::new(0, valid_timestamp, vec![0; 32], vec![coinbase]);
+		let mut new_block = Block::new(0, valid_timestamp, BlockHash::ZERO, vec![coinbase]);
 		new_block.mine(difficulty);
 
-		println!("✓ Block mined with hash: {}", hex::encode(&new_block.hash[..8]));
+		info!(hash = %hex::encode(&new_block.hash.as_bytes()[..8]), "block mined");
 
 		// Submit to validator
 		let result = validator.validate_block_submission(new_block, "Alice".to_string());
 
 		match result {
 			ValidationResult::Accepted => {
-				println!("✓ Block ACCEPTED by validator!");
-				println!("✓ Alice is now in 1-hour lockout period");
-				println!("  Lockout remaining: {} seconds", validator.get_miner_lockout_remaining("Alice"));
+				info!(lockout_remaining_secs = validator.get_miner_lockout_remaining("Alice"), "block accepted by validator; Alice is now locked out for an hour");
 			}
 			_ => {
-				println!("✗ Block rejected: {:?}", result);
+				warn!(?result, "block rejected");
 			}
 		}
 	} else {
-		println!("✗ Could not find valid timestamp within attempts");
+		warn!("could not find a valid timestamp within attempts");
 	}
-
-	println!("\n=== Summary ===");
-	println!("Traditional PoW blockchain: {} blocks", blockchain.blocks.len());
-	println!("Proof of Time validator: {} blocks", validator.get_block_count());
-	println!("\nProof of Time adds:");
-	println!("• Time-based mining challenges (tonce)");
-	println!("• Miner sacrifice protocol (hourly lockouts)");
-	println!("• Fair, time-valued consensus");
+	drop(part);
+
+	// Compare consensus engines on the same genesis block
+	let part = info_span!("part", name = "pluggable consensus").entered();
+
+	let mut pot_chain = Blockchain::with_genesis(&params, &genesis).expect("Failed to add genesis block");
+	pot_chain.set_consensus_engine(ProofOfTime::new(&params));
+	let pot_challenge = pot_chain.next_challenge();
+	let pot_tonce = pot_challenge.tonce.as_ref().map_or(1, |tonce| tonce.tonce);
+	info!(engine = blockchain.consensus_engine().name(), difficulty = %blockchain.next_challenge().difficulty, "asks only for difficulty");
+	info!(engine = pot_chain.consensus_engine().name(), tonce = pot_tonce, "also asks for a tonce");
+
+	let genesis_timestamp = pot_chain.blocks[0].header.timestamp;
+	match find_valid_timestamp(pot_tonce, genesis_timestamp + 1, 5000) {
+		Some(timestamp) => {
+			let coinbase = tx_builder::TransactionBuilder::new(timestamp)
+				.pay_to("Alice", params.coinbase_value)
+				.build_coinbase()
+				.expect("Failed to build coinbase");
+			let mut pot_block = Block::new(1, timestamp, pot_chain.blocks[0].hash, vec![coinbase]);
+			pot_block.mine(pot_challenge.difficulty);
+			match pot_chain.update_with_block(pot_block) {
+				Ok(()) => info!(timestamp, "block passes the tonce and joins the proof of time chain"),
+				Err(e) => warn!(error = ?e, "block rejected"),
+			}
+		}
+		None => warn!("could not find a valid timestamp within attempts"),
+	}
+	drop(part);
+
+	info!(
+		pow_blocks = blockchain.blocks.len(),
+		validator_blocks = validator.get_block_count(),
+		pot_blocks = pot_chain.blocks.len(),
+		"summary: proof of time adds time-based mining challenges (tonce), hourly miner lockouts and fair, time-valued consensus"
+	);
 }
@@ -0,0 +1,476 @@
+//! Transactions waiting to be mined
+//!
+//! The mempool holds transactions that spend unspent outputs but aren't in a
+//! block yet. Each may carry an `Expiry`, a height or time after which it
+//! must not be mined. Entries leave the pool in four ways, each reported as
+//! an `Eviction`:
+//!
+//! - their expiry passes (`Expired`), or they sit longer than
+//!   `MempoolConfig::max_age` (`TooOld`);
+//! - a block spends one of their inputs in another transaction (`Conflict`);
+//! - the pool is over `MempoolConfig::max_bytes` and they pay the lowest fee
+//!   rate (`LowFee`). A newcomer that would pay less than everything it
+//!   displaces is refused instead.
+//!
+//! Transactions that a block includes are simply removed, though their
+//! expiries are remembered until the block can no longer be rolled back, so
+//! `restore` can pool them again on the same terms. Two pooled
+//! transactions never spend the same output: a newcomer that spends an
+//! output a pooled transaction already spends is a replacement (replace by
+//! fee). It is let in only if the `ReplacementPolicy` allows: it must spend
+//! every input of each transaction it replaces and pay more, in total and
+//! per byte. The replaced transactions are evicted (`Replaced`).
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::canonical::CanonicalSerialize;
+use crate::hashable::Hashable;
+use crate::transaction::Transaction;
+use crate::{Block, BlockHash, Txid};
+
+/// Size and age limits for a mempool
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MempoolConfig {
+    /// Most transaction bytes (canonical encoding) held at once
+    pub max_bytes: usize,
+    /// How long a transaction may wait before it is dropped
+    pub max_age: Duration,
+    /// When a transaction may replace pooled ones spending the same outputs
+    pub replacement: ReplacementPolicy,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        MempoolConfig {
+            max_bytes: 5_000_000,
+            max_age: Duration::from_secs(72 * 3600),
+            replacement: ReplacementPolicy::default(),
+        }
+    }
+}
+
+/// Rules for replacing pooled transactions by fee
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplacementPolicy {
+    /// Whether conflicting transactions may replace pooled ones at all
+    pub enabled: bool,
+    /// How much more a replacement must pay than the total fees of everything it replaces
+    pub min_fee_increase: f64,
+    /// Most pooled transactions one replacement may evict
+    pub max_replaced: usize,
+}
+
+impl Default for ReplacementPolicy {
+    fn default() -> Self {
+        ReplacementPolicy {
+            enabled: true,
+            min_fee_increase: 0.000_01,
+            max_replaced: 100,
+        }
+    }
+}
+
+/// When a transaction stops being minable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Expiry {
+    /// Not in a block at this height or above
+    Height(u32),
+    /// Not after this time (TAI milliseconds)
+    Time(u128),
+}
+
+impl Expiry {
+    /// Whether a block at `height`, judged at `now`, is too late
+    pub fn has_passed(&self, height: u32, now: u128) -> bool {
+        match self {
+            Expiry::Height(expiry) => height >= *expiry,
+            Expiry::Time(expiry) => now >= *expiry,
+        }
+    }
+}
+
+/// A pooled transaction
+#[derive(Clone)]
+pub struct MempoolEntry {
+    pub transaction: Transaction,
+    pub tx_hash: Txid,
+    /// Input value the outputs don't claim
+    pub fee: f64,
+    /// Canonical encoding length (bytes)
+    pub size: usize,
+    /// When the transaction entered the pool (TAI milliseconds)
+    pub added_at: u128,
+    pub expiry: Option<Expiry>,
+}
+
+impl MempoolEntry {
+    /// Fee per byte
+    pub fn fee_rate(&self) -> f64 {
+        self.fee / self.size.max(1) as f64
+    }
+}
+
+/// Why a transaction left the pool without being mined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionReason {
+    Expired,
+    TooOld,
+    /// A block spent one of its inputs
+    Conflict,
+    /// Displaced by a better-paying transaction when the pool was full
+    LowFee,
+    /// Replaced by a higher-fee transaction spending the same outputs
+    Replaced,
+}
+
+/// A transaction dropped from the pool
+#[derive(Debug, Clone, PartialEq)]
+pub struct Eviction {
+    pub tx_hash: Txid,
+    pub reason: EvictionReason,
+}
+
+/// Pending transactions, by hash
+#[derive(Default)]
+pub struct Mempool {
+    config: MempoolConfig,
+    entries: HashMap<Txid, MempoolEntry>,
+    /// Input hash -> hash of the pooled transaction spending it
+    spends: HashMap<BlockHash, Txid>,
+    /// Sum of entry sizes
+    bytes: usize,
+    /// Block height -> expiries of the pooled transactions that block included
+    mined_expiries: BTreeMap<u32, Vec<(Txid, Expiry)>>,
+}
+
+impl Mempool {
+    pub fn new(config: MempoolConfig) -> Self {
+        Mempool { config, ..Mempool::default() }
+    }
+
+    pub fn config(&self) -> MempoolConfig {
+        self.config
+    }
+
+    /// Change the limits; the pool is trimmed the next time a transaction is added
+    pub fn set_config(&mut self, config: MempoolConfig) {
+        self.config = config;
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total size of the pooled transactions (bytes)
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn get(&self, tx_hash: &Txid) -> Option<&MempoolEntry> {
+        self.entries.get(tx_hash)
+    }
+
+    pub fn contains(&self, tx_hash: &Txid) -> bool {
+        self.entries.contains_key(tx_hash)
+    }
+
+    /// The pooled transaction spending the output with this hash, if any
+    pub fn spender_of(&self, input_hash: &BlockHash) -> Option<&Txid> {
+        self.spends.get(input_hash)
+    }
+
+    /// Pooled transactions, best fee rate first
+    pub fn by_fee_rate(&self) -> Vec<&MempoolEntry> {
+        let mut entries: Vec<&MempoolEntry> = self.entries.values().collect();
+        entries.sort_by(|a, b| b.fee_rate().total_cmp(&a.fee_rate()).then_with(|| a.added_at.cmp(&b.added_at)));
+        entries
+    }
+
+    /// Add a transaction that could go in the block at `height`, returning what it displaced
+    ///
+    /// The caller checks that the inputs exist and are unspent on chain; the
+    /// pool checks everything else.
+    pub fn insert(&mut self, transaction: Transaction, expiry: Option<Expiry>, height: u32, now: u128) -> Result<Vec<Eviction>, String> {
+        let tx_hash = transaction.hash();
+        if transaction.inputs.is_empty() {
+            return Err("Coinbase transactions can't be pooled".to_string());
+        }
+        if self.entries.contains_key(&tx_hash) {
+            return Err(format!("Transaction {} is already pooled", tx_hash));
+        }
+        if expiry.is_some_and(|expiry| expiry.has_passed(height, now)) {
+            return Err(format!("Transaction {} has expired", tx_hash));
+        }
+        let fee = transaction.input_sum() - transaction.output_sum();
+        if fee < 0.0 {
+            return Err(format!("Transaction {} pays out more than its inputs", tx_hash));
+        }
+        let size = transaction.canonical_bytes().len();
+        if size > self.config.max_bytes {
+            return Err(format!("Transaction {} is larger than the whole mempool", tx_hash));
+        }
+
+        let entry = MempoolEntry { transaction, tx_hash, fee, size, added_at: now, expiry };
+        let replaced = self.check_replacement(&entry)?;
+
+        // make room by dropping the cheapest transactions, if they all pay less than the newcomer
+        let mut displaced = vec![];
+        let mut freed: usize = replaced.iter().filter_map(|tx_hash| self.entries.get(tx_hash)).map(|victim| victim.size).sum();
+        let mut cheapest = self.by_fee_rate().into_iter().rev().filter(|victim| !replaced.contains(&victim.tx_hash));
+        while self.bytes - freed + size > self.config.max_bytes {
+            match cheapest.next() {
+                Some(victim) if victim.fee_rate() < entry.fee_rate() => {
+                    freed += victim.size;
+                    displaced.push(victim.tx_hash);
+                }
+                _ => return Err(format!("Mempool is full and transaction {} pays too low a fee rate", tx_hash)),
+            }
+        }
+
+        let mut evicted = vec![];
+        for (victims, reason) in [(replaced, EvictionReason::Replaced), (displaced, EvictionReason::LowFee)] {
+            for victim in victims {
+                self.remove(&victim);
+                evicted.push(Eviction { tx_hash: victim, reason });
+            }
+        }
+
+        for input in entry.transaction.input_hashes() {
+            self.spends.insert(input, tx_hash);
+        }
+        self.bytes += size;
+        self.entries.insert(tx_hash, entry);
+        Ok(evicted)
+    }
+
+    /// The pooled transactions `entry` would replace, if the replacement policy lets it
+    fn check_replacement(&self, entry: &MempoolEntry) -> Result<Vec<Txid>, String> {
+        let inputs = entry.transaction.input_hashes();
+        let mut conflicts: Vec<&MempoolEntry> = inputs.iter()
+            .filter_map(|input| self.spends.get(input))
+            .filter_map(|tx_hash| self.entries.get(tx_hash))
+            .collect();
+        conflicts.sort_by_key(|entry| entry.tx_hash);
+        conflicts.dedup_by(|a, b| a.tx_hash == b.tx_hash);
+
+        let tx_hash = entry.tx_hash;
+        let policy = self.config.replacement;
+        match conflicts.first() {
+            None => return Ok(vec![]),
+            Some(conflict) if !policy.enabled => {
+                return Err(format!("Transaction {} spends an output pooled transaction {} already spends",
+                    tx_hash, conflict.tx_hash));
+            }
+            _ => {}
+        }
+        if conflicts.len() > policy.max_replaced {
+            return Err(format!("Transaction {} would replace {} transactions, more than the {} allowed",
+                tx_hash, conflicts.len(), policy.max_replaced));
+        }
+        if let Some(conflict) = conflicts.iter().find(|conflict| !conflict.transaction.input_hashes().is_subset(&inputs)) {
+            return Err(format!("Transaction {} doesn't spend every input of {}, which it would replace",
+                tx_hash, conflict.tx_hash));
+        }
+        let replaced_fees: f64 = conflicts.iter().map(|conflict| conflict.fee).sum();
+        if entry.fee < replaced_fees + policy.min_fee_increase {
+            return Err(format!("Transaction {} pays a fee of {}, replacing needs at least {}",
+                tx_hash, entry.fee, replaced_fees + policy.min_fee_increase));
+        }
+        if let Some(conflict) = conflicts.iter().find(|conflict| conflict.fee_rate() >= entry.fee_rate()) {
+            return Err(format!("Transaction {} pays a lower fee rate than {}, which it would replace",
+                tx_hash, conflict.tx_hash));
+        }
+        Ok(conflicts.into_iter().map(|conflict| conflict.tx_hash).collect())
+    }
+
+    /// Drop everything `block` mined or made unminable
+    ///
+    /// Transactions in the block are removed without an eviction; others
+    /// spending the same outputs are evicted as conflicts.
+    pub fn remove_block(&mut self, block: &Block) -> Vec<Eviction> {
+        let mut evicted = vec![];
+        for transaction in block.body.transactions.iter().skip(1) {
+            let tx_hash = transaction.hash();
+            if let Some(entry) = self.remove(&tx_hash) {
+                if let Some(expiry) = entry.expiry {
+                    self.mined_expiries.entry(block.header.index).or_default().push((tx_hash, expiry));
+                }
+                continue;
+            }
+            for input in transaction.input_hashes() {
+                if let Some(conflict) = self.spends.get(&input).copied() {
+                    self.remove(&conflict);
+                    evicted.push(Eviction { tx_hash: conflict, reason: EvictionReason::Conflict });
+                }
+            }
+        }
+        evicted
+    }
+
+    /// Pool a transaction from a block rolled back to `height` again, with the expiry it had before
+    pub fn restore(&mut self, transaction: Transaction, height: u32, now: u128) -> Result<Vec<Eviction>, String> {
+        let tx_hash = transaction.hash();
+        let mut expiry = None;
+        for mined in self.mined_expiries.range_mut(height..).map(|(_, mined)| mined) {
+            if let Some(pos) = mined.iter().position(|(mined_hash, _)| *mined_hash == tx_hash) {
+                expiry = Some(mined.swap_remove(pos).1);
+                break;
+            }
+        }
+        self.insert(transaction, expiry, height, now)
+    }
+
+    /// Forget the expiries of transactions mined at or below `height`, which can't be rolled back
+    pub fn forget_mined(&mut self, height: u32) {
+        self.mined_expiries.retain(|mined_height, _| *mined_height > height);
+    }
+
+    /// Drop transactions too late for a block at `height` judged at `now`, or older than `max_age`
+    pub fn expire(&mut self, height: u32, now: u128) -> Vec<Eviction> {
+        let max_age = self.config.max_age.as_millis();
+        let mut stale: Vec<(Txid, EvictionReason)> = self.entries.values()
+            .filter_map(|entry| {
+                if entry.expiry.is_some_and(|expiry| expiry.has_passed(height, now)) {
+                    Some((entry.tx_hash, EvictionReason::Expired))
+                } else if now.saturating_sub(entry.added_at) > max_age {
+                    Some((entry.tx_hash, EvictionReason::TooOld))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        stale.sort_by_key(|(tx_hash, _)| *tx_hash);
+        stale.into_iter()
+            .map(|(tx_hash, reason)| {
+                self.remove(&tx_hash);
+                Eviction { tx_hash, reason }
+            })
+            .collect()
+    }
+
+    fn remove(&mut self, tx_hash: &Txid) -> Option<MempoolEntry> {
+        let entry = self.entries.remove(tx_hash)?;
+        for input in entry.transaction.input_hashes() {
+            self.spends.remove(&input);
+        }
+        self.bytes -= entry.size;
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Output;
+
+    fn spend(from: &str, value: f64, fee: f64) -> Transaction {
+        let input = Output { to_addr: from.to_string(), value, timestamp: 1_000, lock_until: None };
+        let output = Output { to_addr: "Bob".to_string(), value: value - fee, timestamp: 2_000, lock_until: None };
+        Transaction { inputs: vec![input], outputs: vec![output], witnesses: vec![] }
+    }
+
+    #[test]
+    fn test_expiry_conflicts_and_fee_eviction() {
+        let size = spend("Alice", 10.0, 1.0).canonical_bytes().len();
+        let mut mempool = Mempool::new(MempoolConfig { max_bytes: size * 2, max_age: Duration::from_secs(60), ..MempoolConfig::default() });
+
+        let cheap = spend("Alice", 10.0, 0.1);
+        let rich = spend("Carol", 10.0, 1.0);
+        assert!(mempool.insert(cheap.clone(), None, 5, 1_000).unwrap().is_empty());
+        assert!(mempool.insert(rich.clone(), Some(Expiry::Height(7)), 5, 1_000).unwrap().is_empty());
+
+        // a second spend of Alice's output paying less is refused while the first is pooled
+        assert!(mempool.insert(spend("Alice", 10.0, 0.05), None, 5, 1_000).is_err());
+
+        // full: a better-paying newcomer displaces the cheapest, a worse one is turned away
+        assert!(mempool.insert(spend("David", 10.0, 0.01), None, 5, 1_000).is_err());
+        let better = spend("Ellen", 10.0, 0.5);
+        let evicted = mempool.insert(better.clone(), None, 5, 1_000).unwrap();
+        assert_eq!(evicted, vec![Eviction { tx_hash: cheap.hash(), reason: EvictionReason::LowFee }]);
+        assert_eq!(mempool.by_fee_rate()[0].tx_hash, rich.hash());
+        assert_eq!(mempool.bytes(), size * 2);
+
+        // past its expiry height, then past the maximum age
+        assert_eq!(mempool.expire(7, 2_000), vec![Eviction { tx_hash: rich.hash(), reason: EvictionReason::Expired }]);
+        assert_eq!(mempool.expire(7, 62_000), vec![Eviction { tx_hash: better.hash(), reason: EvictionReason::TooOld }]);
+        assert!(mempool.is_empty());
+        assert_eq!(mempool.bytes(), 0);
+    }
+
+    #[test]
+    fn test_replace_by_fee() {
+        let mut mempool = Mempool::new(MempoolConfig::default());
+        let original = spend("Alice", 10.0, 0.1);
+        mempool.insert(original.clone(), None, 1, 1_000).unwrap();
+
+        // a bump below the minimum increase is refused
+        assert!(mempool.insert(spend("Alice", 10.0, 0.100_001), None, 1, 1_000).is_err());
+
+        // a replacement must spend every input of what it replaces
+        let mut pair = spend("Carol", 10.0, 0.1);
+        pair.inputs.push(spend("David", 5.0, 0.0).inputs[0].clone());
+        pair.outputs[0].value += 5.0;
+        mempool.insert(pair.clone(), None, 1, 1_000).unwrap();
+        assert!(mempool.insert(spend("Carol", 10.0, 1.0), None, 1, 1_000).is_err());
+        assert!(mempool.contains(&pair.hash()));
+
+        let bumped = spend("Alice", 10.0, 0.5);
+        let evicted = mempool.insert(bumped.clone(), None, 1, 1_000).unwrap();
+        assert_eq!(evicted, vec![Eviction { tx_hash: original.hash(), reason: EvictionReason::Replaced }]);
+        assert!(mempool.contains(&bumped.hash()) && !mempool.contains(&original.hash()));
+        assert_eq!(mempool.len(), 2);
+
+        // with replacement turned off, the first spend stays
+        let mut config = MempoolConfig::default();
+        config.replacement.enabled = false;
+        mempool.set_config(config);
+        assert!(mempool.insert(spend("Alice", 10.0, 1.0), None, 1, 1_000).is_err());
+        assert!(mempool.contains(&bumped.hash()));
+    }
+
+    #[test]
+    fn test_block_removes_mined_and_conflicting() {
+        let mut mempool = Mempool::new(MempoolConfig::default());
+        let mined = spend("Alice", 10.0, 1.0);
+        let conflicting = spend("Carol", 10.0, 1.0);
+        mempool.insert(mined.clone(), None, 1, 1_000).unwrap();
+        mempool.insert(conflicting.clone(), None, 1, 1_000).unwrap();
+
+        // the block mines Alice's spend and a different spend of Carol's output
+        let coinbase = Transaction { inputs: vec![], outputs: vec![], witnesses: vec![] };
+        let block = Block::new(1, 3_000, BlockHash::ZERO, vec![coinbase, mined, spend("Carol", 10.0, 2.0)]);
+        let evicted = mempool.remove_block(&block);
+        assert_eq!(evicted, vec![Eviction { tx_hash: conflicting.hash(), reason: EvictionReason::Conflict }]);
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn test_restore_keeps_expiry() {
+        let mut mempool = Mempool::new(MempoolConfig::default());
+        let expiring = spend("Alice", 10.0, 1.0);
+        let timed = spend("Carol", 10.0, 1.0);
+        mempool.insert(expiring.clone(), Some(Expiry::Height(7)), 5, 1_000).unwrap();
+        mempool.insert(timed.clone(), Some(Expiry::Time(3_500)), 5, 1_000).unwrap();
+        let coinbase = Transaction { inputs: vec![], outputs: vec![], witnesses: vec![] };
+        let block = Block::new(5, 3_000, BlockHash::ZERO, vec![coinbase, expiring.clone(), timed.clone()]);
+        mempool.remove_block(&block);
+
+        // rolled back before its expiry, a transaction is pooled with the same expiry
+        mempool.restore(expiring.clone(), 5, 4_000).unwrap();
+        assert_eq!(mempool.get(&expiring.hash()).unwrap().expiry, Some(Expiry::Height(7)));
+
+        // rolled back after it, it is refused rather than pooled without one
+        assert!(mempool.restore(timed.clone(), 5, 4_000).is_err());
+        assert!(!mempool.contains(&timed.hash()));
+
+        // once the block is final there is nothing to restore
+        mempool.remove_block(&block);
+        mempool.forget_mined(5);
+        assert!(mempool.mined_expiries.is_empty());
+    }
+}
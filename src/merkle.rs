@@ -0,0 +1,182 @@
+//! Merkle trees over a block's transactions
+//!
+//! A block header commits to its transactions through the root of a binary
+//! hash tree over their hashes, so a light client holding only headers can
+//! check that a transaction is in a block from a short `MerkleProof`.
+//!
+//! Each level hashes adjacent pairs, `SHA-256(left || right)`. A node left
+//! without a partner at the end of an odd-sized level moves up unchanged
+//! rather than being paired with itself, so two different transaction lists
+//! can never share a root. The root of an empty list is 32 zero bytes.
+
+use crate::canonical::{CanonicalDeserialize, CanonicalReader, CanonicalSerialize};
+use crate::{BlockHash, Txid};
+
+fn hash_pair(left: &BlockHash, right: &BlockHash) -> BlockHash {
+    let mut bytes = left.to_vec();
+    bytes.extend(right.as_bytes());
+    BlockHash::digest(&bytes)
+}
+
+fn next_level(level: &[BlockHash]) -> Vec<BlockHash> {
+    level.chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_pair(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+// the bottom level of the tree: the transaction hashes, as nodes
+fn leaf_level(leaves: &[Txid]) -> Vec<BlockHash> {
+    leaves.iter().map(|leaf| BlockHash::from_bytes(*leaf.as_bytes())).collect()
+}
+
+/// Root of the tree over `leaves`
+pub fn merkle_root(leaves: &[Txid]) -> BlockHash {
+    if leaves.is_empty() {
+        return BlockHash::ZERO;
+    }
+    let mut level = leaf_level(leaves);
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+/// The sibling hashes linking one leaf to the root
+#[derive(Debug, Clone, PartialEq)]
+pub struct MerkleProof {
+    /// Position of the leaf
+    pub index: u32,
+    /// Number of leaves in the tree
+    pub leaf_count: u32,
+    /// Sibling at each level, bottom up; levels where the node has no partner are skipped
+    pub siblings: Vec<BlockHash>,
+}
+
+impl MerkleProof {
+    /// Proof for the leaf at `index`, or None if it is out of range
+    pub fn new(leaves: &[Txid], index: usize) -> Option<Self> {
+        if index >= leaves.len() {
+            return None;
+        }
+
+        let mut siblings = vec![];
+        let mut level = leaf_level(leaves);
+        let mut position = index;
+        while level.len() > 1 {
+            let partner = position ^ 1;
+            if partner < level.len() {
+                siblings.push(level[partner]);
+            }
+            level = next_level(&level);
+            position /= 2;
+        }
+
+        Some(MerkleProof { index: index as u32, leaf_count: leaves.len() as u32, siblings })
+    }
+
+    /// The root this proof leads to from `leaf`, or None if the proof is malformed
+    pub fn root(&self, leaf: &Txid) -> Option<BlockHash> {
+        if self.index >= self.leaf_count {
+            return None;
+        }
+
+        let mut siblings = self.siblings.iter();
+        let mut hash = BlockHash::from_bytes(*leaf.as_bytes());
+        let mut position = self.index as usize;
+        let mut level_len = self.leaf_count as usize;
+        while level_len > 1 {
+            let partner = position ^ 1;
+            if partner < level_len {
+                let sibling = siblings.next()?;
+                hash = if position.is_multiple_of(2) { hash_pair(&hash, sibling) } else { hash_pair(sibling, &hash) };
+            }
+            position /= 2;
+            level_len = level_len.div_ceil(2);
+        }
+
+        if siblings.next().is_some() {
+            return None;
+        }
+        Some(hash)
+    }
+
+    /// Whether `leaf` is in the tree with this root
+    pub fn verify(&self, leaf: &Txid, root: &BlockHash) -> bool {
+        self.root(leaf).is_some_and(|computed| computed == *root)
+    }
+}
+
+impl CanonicalSerialize for MerkleProof {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        self.index.write_canonical(out);
+        self.leaf_count.write_canonical(out);
+        self.siblings.write_canonical(out);
+    }
+}
+
+impl CanonicalDeserialize for MerkleProof {
+    fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+        Ok(MerkleProof {
+            index: u32::read_canonical(reader)?,
+            leaf_count: u32::read_canonical(reader)?,
+            siblings: Vec::read_canonical(reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(n: u8) -> Vec<Txid> {
+        (0..n).map(|i| Txid::from_bytes([i; 32])).collect()
+    }
+
+    fn node(leaf: &Txid) -> BlockHash {
+        BlockHash::from_bytes(*leaf.as_bytes())
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        assert_eq!(merkle_root(&[]), BlockHash::ZERO);
+        assert_eq!(merkle_root(&leaves(1)), BlockHash::ZERO);
+
+        let three = leaves(3);
+        let expected = hash_pair(&hash_pair(&node(&three[0]), &node(&three[1])), &node(&three[2]));
+        assert_eq!(merkle_root(&three), expected);
+
+        // Repeating the odd leaf out doesn't give the same root
+        let mut four = three.clone();
+        four.push(three[2]);
+        assert_ne!(merkle_root(&four), expected);
+    }
+
+    #[test]
+    fn test_merkle_proofs() {
+        for n in 1..=9 {
+            let leaves = leaves(n);
+            let root = merkle_root(&leaves);
+            for (i, leaf) in leaves.iter().enumerate() {
+                let proof = MerkleProof::new(&leaves, i).unwrap();
+                assert!(proof.verify(leaf, &root), "leaf {} of {}", i, n);
+                assert!(!proof.verify(&Txid::from_bytes([0xff; 32]), &root));
+
+                let decoded = MerkleProof::from_canonical_bytes(&proof.canonical_bytes()).unwrap();
+                assert_eq!(decoded, proof);
+            }
+            assert!(MerkleProof::new(&leaves, n as usize).is_none());
+        }
+
+        // A proof claiming a different position fails
+        let leaves = leaves(4);
+        let mut proof = MerkleProof::new(&leaves, 1).unwrap();
+        proof.index = 2;
+        assert!(!proof.verify(&leaves[1], &merkle_root(&leaves)));
+        proof.index = 9;
+        assert_eq!(proof.root(&leaves[1]), None);
+    }
+}
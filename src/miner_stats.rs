@@ -0,0 +1,153 @@
+//! Per-miner statistics kept by the validator
+//!
+//! Every submission a validator judges is recorded against the miner that
+//! sent it: accepted blocks with how long into the round they came and the
+//! lockout they earned, rejections by reason. The book is plain data, saved
+//! and loaded as JSON so totals survive a validator restart.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::atomic_file::atomic_write;
+
+/// Totals for one miner
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MinerStats {
+    pub miner_id: String,
+    pub blocks_accepted: u64,
+    /// Rejected submissions, by reason (see `ValidationResult::reason`)
+    pub rejections: BTreeMap<String, u64>,
+    /// Sum over accepted blocks of the time from round start to acceptance (milliseconds)
+    pub total_solution_ms: u128,
+    /// Sum of the lockouts served for accepted blocks (milliseconds)
+    pub total_lockout_ms: u128,
+    /// When the miner's latest block was accepted (TAI milliseconds)
+    pub last_accepted_at: Option<u128>,
+}
+
+impl MinerStats {
+    pub fn new(miner_id: &str) -> Self {
+        MinerStats { miner_id: miner_id.to_string(), ..Self::default() }
+    }
+
+    /// Mean time from round start to an accepted block, once the miner has one
+    pub fn average_time_to_solution_ms(&self) -> Option<u128> {
+        if self.blocks_accepted == 0 {
+            return None;
+        }
+        Some(self.total_solution_ms / self.blocks_accepted as u128)
+    }
+
+    pub fn total_rejections(&self) -> u64 {
+        self.rejections.values().sum()
+    }
+}
+
+/// Statistics for every miner a validator has heard from
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MinerStatsBook {
+    miners: HashMap<String, MinerStats>,
+}
+
+impl MinerStatsBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entry(&mut self, miner_id: &str) -> &mut MinerStats {
+        self.miners.entry(miner_id.to_string()).or_insert_with(|| MinerStats::new(miner_id))
+    }
+
+    /// Record an accepted block, `solution_ms` into the round, earning `lockout_ms`
+    pub fn record_accepted(&mut self, miner_id: &str, solution_ms: u128, lockout_ms: u128, accepted_at: u128) {
+        let stats = self.entry(miner_id);
+        stats.blocks_accepted += 1;
+        stats.total_solution_ms += solution_ms;
+        stats.total_lockout_ms += lockout_ms;
+        stats.last_accepted_at = Some(accepted_at);
+    }
+
+    /// Record a rejected submission
+    pub fn record_rejection(&mut self, miner_id: &str, reason: &str) {
+        *self.entry(miner_id).rejections.entry(reason.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn get(&self, miner_id: &str) -> Option<&MinerStats> {
+        self.miners.get(miner_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.miners.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.miners.is_empty()
+    }
+
+    /// The top `limit` miners by blocks accepted, faster average solutions first on ties
+    pub fn leaderboard(&self, limit: usize) -> Vec<&MinerStats> {
+        let mut miners: Vec<&MinerStats> = self.miners.values().collect();
+        miners.sort_by(|a, b| {
+            b.blocks_accepted.cmp(&a.blocks_accepted)
+                .then(a.average_time_to_solution_ms().unwrap_or(u128::MAX)
+                    .cmp(&b.average_time_to_solution_ms().unwrap_or(u128::MAX)))
+                .then(a.miner_id.cmp(&b.miner_id))
+        });
+        miners.truncate(limit);
+        miners
+    }
+
+    /// Write the book to `path` as JSON
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(self)
+            .map_err(|e| format!("Failed to encode miner stats: {}", e))?;
+        atomic_write(path, &json)
+    }
+
+    /// Read a book written by `save`
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_slice(&json).map_err(|e| format!("Invalid miner stats in {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leaderboard_order() {
+        let mut book = MinerStatsBook::new();
+        book.record_accepted("alice", 30_000, 3_600_000, 1000);
+        book.record_accepted("bob", 10_000, 3_600_000, 2000);
+        book.record_accepted("bob", 50_000, 7_200_000, 3000);
+        book.record_accepted("carol", 20_000, 3_600_000, 4000);
+        book.record_rejection("dave", "tonce_challenge");
+        book.record_rejection("dave", "tonce_challenge");
+        book.record_rejection("alice", "invalid_timestamp");
+
+        let ids: Vec<&str> = book.leaderboard(10).iter().map(|stats| stats.miner_id.as_str()).collect();
+        assert_eq!(ids, vec!["bob", "carol", "alice", "dave"]);
+        assert_eq!(book.leaderboard(2).len(), 2);
+
+        let bob = book.get("bob").unwrap();
+        assert_eq!(bob.average_time_to_solution_ms(), Some(30_000));
+        assert_eq!(bob.total_lockout_ms, 10_800_000);
+        assert_eq!(bob.last_accepted_at, Some(3000));
+        assert_eq!(book.get("dave").unwrap().rejections["tonce_challenge"], 2);
+        assert_eq!(book.get("dave").unwrap().average_time_to_solution_ms(), None);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("hourcoin-miner-stats-{}.json", std::process::id()));
+        let mut book = MinerStatsBook::new();
+        book.record_accepted("alice", 30_000, 3_600_000, 1000);
+        book.record_rejection("bob", "lockout");
+
+        book.save(&path).unwrap();
+        assert_eq!(MinerStatsBook::load(&path).unwrap(), book);
+        std::fs::remove_file(&path).unwrap();
+        assert!(MinerStatsBook::load(&path).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+//! Multi-signature outputs
+//!
+//! A multisig output pays to the hash of a `MultisigPolicy` (`msig` + hex
+//! digest): a list of sr25519 public keys and how many of them must sign. To
+//! spend it, a transaction reveals the policy in a `Witness::Multisig` with at
+//! least `threshold` valid signatures over the transaction's signing hash.
+//!
+//! Cosigners usually sign offline, each adding their signature to the same
+//! partially signed transaction (see `psbt`).
+
+use crate::BlockHash;
+use crate::canonical::{CanonicalDeserialize, CanonicalReader, CanonicalSerialize};
+use crate::hashable::Hashable;
+use crate::node_key::{verify_signature, Signer};
+use crate::transaction::{Output, Transaction, Witness, TX_SIGNING_CONTEXT};
+
+/// Prefix of addresses that pay to a multisig policy
+pub const MULTISIG_ADDRESS_PREFIX: &str = "msig";
+
+/// An m-of-n signing policy
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultisigPolicy {
+    /// Signatures required to spend
+    pub threshold: usize,
+    /// Cosigner public keys; their order is part of the address
+    pub public_keys: Vec<Vec<u8>>,
+}
+
+impl MultisigPolicy {
+    /// A policy requiring `threshold` of `public_keys` to sign
+    pub fn new(threshold: usize, public_keys: Vec<Vec<u8>>) -> Result<Self, String> {
+        if threshold == 0 || threshold > public_keys.len() {
+            return Err(format!("Threshold {} is invalid for {} keys", threshold, public_keys.len()));
+        }
+        Ok(MultisigPolicy { threshold, public_keys })
+    }
+
+    /// The address an output governed by this policy pays to
+    pub fn address(&self) -> String {
+        format!("{}{}", MULTISIG_ADDRESS_PREFIX, hex::encode(self.hash()))
+    }
+
+    /// Position of a cosigner's key in the policy
+    pub fn position(&self, public_key: &[u8]) -> Option<usize> {
+        self.public_keys.iter().position(|key| key == public_key)
+    }
+
+    /// Check that enough cosigners signed `message`
+    ///
+    /// `signatures` lines up with `public_keys`; `None` marks a cosigner who did not sign.
+    pub fn check_signatures(&self, message: &[u8], signatures: &[Option<Vec<u8>>]) -> Result<(), String> {
+        for (public_key, signature) in self.signers(signatures)? {
+            verify_signature(public_key, TX_SIGNING_CONTEXT, message, signature)?;
+        }
+        Ok(())
+    }
+
+    /// The cosigner keys and signatures in `signatures`, if there are enough of them
+    ///
+    /// The policy is satisfied if every one of them verifies.
+    pub fn signers<'a>(&'a self, signatures: &'a [Option<Vec<u8>>]) -> Result<Vec<Signer<'a>>, String> {
+        if signatures.len() != self.public_keys.len() {
+            return Err(format!("Expected {} signature slots, got {}", self.public_keys.len(), signatures.len()));
+        }
+
+        let signers: Vec<Signer> = self.public_keys.iter().zip(signatures)
+            .filter_map(|(public_key, signature)| Some((public_key.as_slice(), signature.as_deref()?)))
+            .collect();
+        if signers.len() < self.threshold {
+            return Err(format!("{} of {} required signatures", signers.len(), self.threshold));
+        }
+        Ok(signers)
+    }
+}
+
+impl CanonicalSerialize for MultisigPolicy {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        (self.threshold as u32).write_canonical(out);
+        self.public_keys.write_canonical(out);
+    }
+}
+
+impl CanonicalDeserialize for MultisigPolicy {
+    fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+        let threshold = u32::read_canonical(reader)? as usize;
+        MultisigPolicy::new(threshold, Vec::read_canonical(reader)?)
+    }
+}
+
+impl Hashable for MultisigPolicy {
+    type Hash = BlockHash;
+
+    fn bytes(&self) -> Vec<u8> {
+        self.canonical_bytes()
+    }
+}
+
+/// Whether an address pays to a multisig policy
+pub fn is_multisig_address(address: &str) -> bool {
+    address.len() == MULTISIG_ADDRESS_PREFIX.len() + 64
+        && address.strip_prefix(MULTISIG_ADDRESS_PREFIX)
+            .is_some_and(|hash| hash.bytes().all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase()))
+}
+
+/// The cosigner keys and signatures that must all verify for `transaction` to spend `input`, a multisig address
+pub(crate) fn input_signers<'a>(input: &Output, transaction: &'a Transaction) -> Result<Vec<Signer<'a>>, String> {
+    let (policy, signatures) = transaction.witnesses.iter()
+        .find_map(|witness| match witness {
+            Witness::Multisig { policy, signatures } if policy.address() == input.to_addr => Some((policy, signatures)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("No witness for multisig input {}", input.to_addr))?;
+
+    policy.signers(signatures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node_key::NodeKey;
+    use crate::signature_cache::verify_transaction;
+
+    #[test]
+    fn test_two_of_three() {
+        let keys: Vec<NodeKey> = (0..3).map(|_| NodeKey::generate()).collect();
+        let policy = MultisigPolicy::new(2, keys.iter().map(|key| key.public_key()).collect()).unwrap();
+        assert!(is_multisig_address(&policy.address()));
+        assert!(!is_multisig_address("msig"));
+        assert!(!is_multisig_address("msig_is_just_a_name"));
+        assert!(MultisigPolicy::new(4, policy.public_keys.clone()).is_err());
+
+        let input = Output { to_addr: policy.address(), value: 3.0, timestamp: 1000, lock_until: None };
+        let mut transaction = Transaction {
+            inputs: vec![input.clone()],
+            outputs: vec![Output { to_addr: "Carol".to_owned(), value: 3.0, timestamp: 2000, lock_until: None }],
+            witnesses: vec![],
+        };
+        assert!(verify_transaction(&transaction).is_err());
+
+        let message = transaction.signing_hash();
+        let sign = |i: usize| Some(keys[i].sign(TX_SIGNING_CONTEXT, message.as_bytes()));
+
+        transaction.witnesses = vec![Witness::Multisig { policy: policy.clone(), signatures: vec![sign(0), None, None] }];
+        assert!(verify_transaction(&transaction).is_err());
+
+        transaction.witnesses = vec![Witness::Multisig { policy: policy.clone(), signatures: vec![sign(0), None, sign(2)] }];
+        assert_eq!(verify_transaction(&transaction), Ok(()));
+
+        // A signature in the wrong slot does not count
+        transaction.witnesses = vec![Witness::Multisig { policy, signatures: vec![sign(0), sign(2), None] }];
+        assert!(verify_transaction(&transaction).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+//! The validator as a single-owner task
+//!
+//! One task owns the `Validator`. Connections, the REST and admin endpoints
+//! and the background timers don't lock it; they send it commands through a
+//! `ValidatorHandle` and wait for the reply. Commands run one at a time, in
+//! the order they arrived, so two miners racing for a round are judged in
+//! the order their submissions reached the queue, and nothing that only
+//! reads the validator ever waits behind a lock held across a network call.
+//!
+//! A command is a closure over `&mut Validator`. It runs on the validator
+//! task, so it must not block: anything that waits on the network should
+//! gather what it needs in one command, do the waiting outside, and send the
+//! result back in another (see `ValidatorServer::resync_time`).
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, Span};
+use crate::Validator;
+
+/// Commands queued before senders wait for room
+pub const COMMAND_QUEUE_LEN: usize = 1024;
+
+type Command = Box<dyn FnOnce(&mut Validator) + Send>;
+
+/// A way to reach the validator task; clones all reach the same validator
+#[derive(Debug, Clone)]
+pub struct ValidatorHandle {
+    commands: mpsc::Sender<Command>,
+}
+
+/// The validator, waiting for its task to be run
+pub struct ValidatorTask {
+    validator: Validator,
+    commands: mpsc::Receiver<Command>,
+}
+
+impl ValidatorHandle {
+    /// A handle to `validator` and the task that will own it
+    ///
+    /// Commands sent before the task runs wait in the queue.
+    pub fn new(validator: Validator) -> (Self, ValidatorTask) {
+        let (commands, receiver) = mpsc::channel(COMMAND_QUEUE_LEN);
+        (ValidatorHandle { commands }, ValidatorTask { validator, commands: receiver })
+    }
+
+    /// Run `validator` on a task of its own (needs a Tokio runtime)
+    pub fn spawn(validator: Validator) -> Self {
+        let (handle, task) = Self::new(validator);
+        tokio::spawn(task.run());
+        handle
+    }
+
+    /// Run `command` on the validator and return what it returns
+    ///
+    /// The command runs in the caller's tracing span. Panics if the validator
+    /// task has stopped, or if the command itself panicked.
+    pub async fn call<R, F>(&self, command: F) -> R
+    where
+        F: FnOnce(&mut Validator) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (reply, answer) = oneshot::channel();
+        let span = Span::current();
+        let command: Command = Box::new(move |validator| {
+            let _enter = span.enter();
+            let _ = reply.send(command(validator));
+        });
+        if self.commands.send(command).await.is_err() {
+            panic!("The validator task has stopped");
+        }
+        answer.await.expect("The validator command panicked")
+    }
+}
+
+impl ValidatorTask {
+    /// Run commands until every handle is dropped
+    ///
+    /// A command that panics is reported and dropped; the validator keeps
+    /// serving the commands after it.
+    pub async fn run(mut self) {
+        while let Some(command) = self.commands.recv().await {
+            let validator = &mut self.validator;
+            if catch_unwind(AssertUnwindSafe(|| command(validator))).is_err() {
+                error!("validator command panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChainParams;
+
+    #[tokio::test]
+    async fn test_commands_run_in_order() {
+        let validator = ValidatorHandle::spawn(Validator::new_with_params(ChainParams::regtest()));
+        // join! polls the calls in order, so that's the order they're queued in
+        let replies = tokio::join!(
+            validator.call(|validator| { validator.set_paused(true); validator.is_paused() }),
+            validator.call(|validator| { let was_paused = validator.is_paused(); validator.set_paused(false); was_paused }),
+            validator.call(|validator| validator.is_paused()),
+        );
+        assert_eq!(replies, (true, true, false));
+    }
+
+    #[tokio::test]
+    async fn test_panicking_command_leaves_the_validator_running() {
+        let validator = ValidatorHandle::spawn(Validator::new_with_params(ChainParams::regtest()));
+        let crashed = tokio::spawn({
+            let validator = validator.clone();
+            async move { validator.call(|_| panic!("bad command")).await }
+        });
+        assert!(crashed.await.is_err());
+        assert_eq!(validator.call(|validator| validator.get_block_count()).await, 0);
+    }
+}
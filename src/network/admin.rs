@@ -0,0 +1,232 @@
+//! Admin endpoint for validator operators
+//!
+//! Operators change a running validator through a separate port instead of
+//! restarting it. The port speaks the same length-prefixed JSON frames as the
+//! miner protocol, but every `AdminRequest` must carry the admin token; a
+//! request with the wrong token is answered with an error and the connection
+//! is closed. Bind the port to a private interface: the token authenticates
+//! requests, it doesn't encrypt them.
+
+use std::path::Path;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tracing::{debug, info, warn};
+use crate::{Difficulty, Validator};
+use crate::round_history::ExportFormat;
+use super::actor::ValidatorHandle;
+use super::protocol::RoundInfoData;
+use super::retry::Timeouts;
+use super::transport::{Transport, DEFAULT_MAX_FRAME_LEN};
+
+/// Writes the validator's state to disk, returning the files written
+pub type Snapshot = Arc<dyn Fn(&Validator) -> Result<Vec<String>, String> + Send + Sync>;
+
+/// One operator command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AdminCommand {
+    /// Refuse blocks until `Resume`
+    Pause,
+    Resume,
+    /// Abandon the current round and start a new one
+    ForceNewRound,
+    /// Lower the target blocks must meet (hex, as in `RoundInfoData`)
+    SetDifficulty { difficulty: String },
+    BanMiner { miner_id: String },
+    UnbanMiner { miner_id: String },
+    /// Report the validator's state
+    DumpState,
+    /// Save the validator's state to its configured files now
+    TriggerSnapshot,
+    /// Write the recent rounds to `path` on the validator's host, as CSV if it ends in `.csv`
+    ExportRounds { path: String },
+}
+
+/// A command and the token that authorizes it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminRequest {
+    pub token: String,
+    pub command: AdminCommand,
+}
+
+/// The answer to one `AdminRequest`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AdminResponse {
+    /// The command was carried out
+    Done { message: String },
+    State(Box<AdminStateData>),
+    Error { message: String },
+}
+
+/// What `DumpState` reports
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminStateData {
+    pub block_count: usize,
+    /// Hash of the last block (hex), if there is one
+    pub tip_hash: Option<String>,
+    pub round: RoundInfoData,
+    pub paused: bool,
+    pub clock_drift_paused: bool,
+    pub banned_miners: Vec<String>,
+    pub unspent_outputs: usize,
+    pub clock_offset_ms: i128,
+}
+
+impl AdminStateData {
+    pub fn from_validator(validator: &Validator) -> Self {
+        AdminStateData {
+            block_count: validator.blockchain.blocks.len(),
+            tip_hash: validator.blockchain.blocks.last().map(|block| block.hash.to_string()),
+            round: RoundInfoData::from_round_info(&validator.get_round_info(), validator.get_difficulty()),
+            paused: validator.is_paused(),
+            clock_drift_paused: validator.is_clock_drift_paused(),
+            banned_miners: validator.banned_miners().cloned().collect(),
+            unspent_outputs: validator.blockchain.unspent_output_count(),
+            clock_offset_ms: validator.get_clock_offset_ms(),
+        }
+    }
+}
+
+/// Serve admin requests on `address` until the listener fails
+pub async fn serve(
+    address: String,
+    token: String,
+    validator: ValidatorHandle,
+    snapshot: Snapshot,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let listener = TcpListener::bind(&address).await?;
+    info!(%address, "admin endpoint listening");
+
+    let token = Arc::new(token);
+    loop {
+        let (socket, peer) = listener.accept().await?;
+        let token = Arc::clone(&token);
+        let validator = validator.clone();
+        let snapshot = Arc::clone(&snapshot);
+        tokio::spawn(async move {
+            let mut transport = Transport::new(socket, DEFAULT_MAX_FRAME_LEN).with_timeouts(Timeouts::default());
+            while let Ok(Some(frame)) = transport.recv_frame().await {
+                let request = match serde_json::from_slice::<AdminRequest>(&frame) {
+                    Ok(request) => request,
+                    Err(e) => {
+                        let _ = transport.send(&AdminResponse::Error { message: format!("Invalid request: {}", e) }).await;
+                        break;
+                    }
+                };
+                if !token_matches(&request.token, &token) {
+                    warn!(%peer, "admin request with a bad token");
+                    let _ = transport.send(&AdminResponse::Error { message: "Bad admin token".to_string() }).await;
+                    break;
+                }
+                info!(%peer, command = ?request.command, "admin command");
+                let (command, snapshot) = (request.command, Arc::clone(&snapshot));
+                let response = validator.call(move |validator| execute(command, validator, &snapshot)).await;
+                if transport.send(&response).await.is_err() {
+                    break;
+                }
+            }
+            debug!(%peer, "admin connection closed");
+        });
+    }
+}
+
+/// Carry out one authorized command
+pub fn execute(command: AdminCommand, validator: &mut Validator, snapshot: &Snapshot) -> AdminResponse {
+    let done = |message: String| AdminResponse::Done { message };
+    match command {
+        AdminCommand::Pause => {
+            validator.set_paused(true);
+            done("Block acceptance paused".to_string())
+        }
+        AdminCommand::Resume => {
+            validator.set_paused(false);
+            done("Block acceptance resumed".to_string())
+        }
+        AdminCommand::ForceNewRound => {
+            validator.start_new_round();
+            done(format!("New round started with tonce {:?}", validator.get_current_tonce()))
+        }
+        AdminCommand::SetDifficulty { difficulty } => {
+            let result = difficulty.parse::<Difficulty>()
+                .and_then(|difficulty| validator.set_difficulty(difficulty));
+            match result {
+                Ok(()) => done(format!("Difficulty set to {}", validator.get_difficulty())),
+                Err(message) => AdminResponse::Error { message },
+            }
+        }
+        AdminCommand::BanMiner { miner_id } => match validator.ban_miner(&miner_id) {
+            true => done(format!("Banned {}", miner_id)),
+            false => done(format!("{} was already banned", miner_id)),
+        },
+        AdminCommand::UnbanMiner { miner_id } => match validator.unban_miner(&miner_id) {
+            true => done(format!("Unbanned {}", miner_id)),
+            false => done(format!("{} wasn't banned", miner_id)),
+        },
+        AdminCommand::DumpState => AdminResponse::State(Box::new(AdminStateData::from_validator(validator))),
+        AdminCommand::TriggerSnapshot => match snapshot(validator) {
+            Ok(files) if files.is_empty() => AdminResponse::Error { message: "No snapshot files are configured".to_string() },
+            Ok(files) => done(format!("Saved {}", files.join(", "))),
+            Err(message) => AdminResponse::Error { message },
+        },
+        AdminCommand::ExportRounds { path } => {
+            let path = Path::new(&path);
+            match validator.export_round_history(ExportFormat::from_path(path), path) {
+                Ok(()) => done(format!("Exported {} rounds to {}", validator.round_history().rounds().len(), path.display())),
+                Err(message) => AdminResponse::Error { message },
+            }
+        }
+    }
+}
+
+/// Send one command to the admin endpoint at `address`
+pub async fn send_command(address: &str, token: &str, command: AdminCommand) -> std::io::Result<AdminResponse> {
+    let request = AdminRequest { token: token.to_string(), command };
+    Transport::connect(address, Timeouts::default()).await?.request(&request).await
+}
+
+/// Compare tokens without stopping at the first difference
+fn token_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given.bytes().zip(expected.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_admin_commands_need_the_token() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let validator = ValidatorHandle::spawn(Validator::new(Difficulty::from_target(0x0FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF)));
+        let snapshot: Snapshot = Arc::new(|_| Ok(vec!["stats.json".to_string()]));
+        tokio::spawn(serve(address.clone(), "secret".to_string(), validator.clone(), snapshot));
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        match send_command(&address, "wrong", AdminCommand::Pause).await.unwrap() {
+            AdminResponse::Error { message } => assert_eq!(message, "Bad admin token"),
+            other => panic!("Wrong response: {:?}", other),
+        }
+        assert!(!validator.call(|validator| validator.is_paused()).await);
+
+        send_command(&address, "secret", AdminCommand::Pause).await.unwrap();
+        send_command(&address, "secret", AdminCommand::BanMiner { miner_id: "mallory".to_string() }).await.unwrap();
+        match send_command(&address, "secret", AdminCommand::DumpState).await.unwrap() {
+            AdminResponse::State(state) => {
+                assert!(state.paused);
+                assert_eq!(state.banned_miners, vec!["mallory".to_string()]);
+            }
+            other => panic!("Wrong response: {:?}", other),
+        }
+        match send_command(&address, "secret", AdminCommand::SetDifficulty { difficulty: "0xFF".to_string() }).await.unwrap() {
+            AdminResponse::Done { .. } => assert_eq!(validator.call(|validator| validator.get_difficulty()).await, Difficulty::from_target(0xFF)),
+            other => panic!("Wrong response: {:?}", other),
+        }
+        match send_command(&address, "secret", AdminCommand::TriggerSnapshot).await.unwrap() {
+            AdminResponse::Done { message } => assert_eq!(message, "Saved stats.json"),
+            other => panic!("Wrong response: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! Miner clock skew relative to the validator
+//!
+//! Each `GetRoundInfo` carries the miner's send time, and the reply carries
+//! the validator's receive and send times. With the time the reply arrives
+//! these are the four timestamps of an NTP exchange, so the same arithmetic
+//! (`SntpSample::from_times`) gives the validator's offset from the miner's
+//! clock.
+//!
+//! A single exchange is skewed by asymmetric network delay, and the longer
+//! the round trip the larger that error can be. Like NTP's clock filter, the
+//! estimate uses the recent sample with the shortest round trip.
+
+use std::collections::VecDeque;
+use crate::sntp::SntpSample;
+
+/// Exchanges remembered for the estimate
+pub const SKEW_SAMPLES: usize = 8;
+
+/// Recent offset samples against one validator
+#[derive(Debug, Clone, Default)]
+pub struct SkewEstimator {
+    samples: VecDeque<SntpSample>,
+}
+
+impl SkewEstimator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the sample from one exchange, forgetting the oldest past `SKEW_SAMPLES`
+    ///
+    /// Samples with a negative round trip (a clock stepped mid-exchange) are dropped.
+    pub fn add(&mut self, sample: SntpSample) {
+        if sample.delay_ms < 0 {
+            return;
+        }
+        self.samples.push_back(sample);
+        while self.samples.len() > SKEW_SAMPLES {
+            self.samples.pop_front();
+        }
+    }
+
+    /// The most trustworthy recent sample (shortest round trip)
+    pub fn best_sample(&self) -> Option<SntpSample> {
+        self.samples.iter().min_by_key(|sample| sample.delay_ms).copied()
+    }
+
+    /// How far the validator's clock is ahead of ours (milliseconds), once measured
+    pub fn offset_ms(&self) -> Option<i128> {
+        self.best_sample().map(|sample| sample.offset_ms)
+    }
+
+    /// `local_time` as the validator's clock would read it
+    pub fn to_validator_time(&self, local_time: u128) -> u128 {
+        (local_time as i128 + self.offset_ms().unwrap_or(0)).max(0) as u128
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skew_uses_shortest_round_trip() {
+        let mut estimator = SkewEstimator::new();
+        assert_eq!(estimator.offset_ms(), None);
+        assert_eq!(estimator.to_validator_time(1000), 1000);
+
+        // Validator 500ms ahead; the slow exchange's reply leg was delayed
+        estimator.add(SntpSample::from_times(1000, 1510, 1510, 1040));
+        estimator.add(SntpSample::from_times(2000, 2520, 2520, 2400));
+        estimator.add(SntpSample::from_times(3000, 3400, 3400, 2900));
+        assert_eq!(estimator.offset_ms(), Some(490));
+        assert_eq!(estimator.to_validator_time(10_000), 10_490);
+
+        // Old samples age out
+        for _ in 0..SKEW_SAMPLES {
+            estimator.add(SntpSample::from_times(5000, 4800, 4800, 5000));
+        }
+        assert_eq!(estimator.offset_ms(), Some(-200));
+    }
+}
@@ -0,0 +1,185 @@
+//! Frame compression, negotiated when a connection opens
+//!
+//! A miner that wants compression opens with `MinerMessage::Hello`, listing
+//! the algorithms it accepts in order of preference. The validator picks the
+//! first it also supports and answers `ValidatorMessage::Welcome`. From the
+//! frame after that on, every frame in both directions starts with a flag
+//! byte: `0` for a frame sent as is, `1` for one compressed with the chosen
+//! algorithm. Frames under the threshold, and frames that don't shrink, are
+//! sent as is. Miners that never say `Hello` get plain frames, as before.
+
+#[cfg(feature = "node")]
+use std::io::{self, Read, Write};
+#[cfg(feature = "node")]
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+/// Frames smaller than this aren't worth compressing (bytes)
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 4 * 1024;
+
+/// zstd level: fast, and most of the gain on repetitive JSON
+#[cfg(feature = "node")]
+const ZSTD_LEVEL: i32 = 3;
+
+#[cfg(feature = "node")]
+const FLAG_PLAIN: u8 = 0;
+#[cfg(feature = "node")]
+const FLAG_COMPRESSED: u8 = 1;
+
+/// A frame compression algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Compression {
+    Zstd,
+    Deflate,
+}
+
+impl Compression {
+    /// Every supported algorithm, best first
+    pub const ALL: [Compression; 2] = [Compression::Zstd, Compression::Deflate];
+
+    /// The first algorithm in `offered` that is also in `supported`
+    pub fn negotiate(offered: &[Compression], supported: &[Compression]) -> Option<Compression> {
+        offered.iter().copied().find(|algorithm| supported.contains(algorithm))
+    }
+
+    /// Parse a comma-separated list such as `zstd,deflate`; `none` is the empty list
+    pub fn parse_list(list: &str) -> Result<Vec<Compression>, String> {
+        if list.eq_ignore_ascii_case("none") {
+            return Ok(Vec::new());
+        }
+        list.split(',').map(|name| name.trim().parse()).collect()
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "zstd" => Ok(Compression::Zstd),
+            "deflate" => Ok(Compression::Deflate),
+            other => Err(format!("Unknown compression '{}'", other)),
+        }
+    }
+}
+
+/// How frames on a connection are compressed once negotiated
+#[cfg(feature = "node")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameCompression {
+    pub algorithm: Compression,
+    /// Frames of at least this many bytes are compressed
+    pub threshold: usize,
+}
+
+#[cfg(feature = "node")]
+impl FrameCompression {
+    pub fn new(algorithm: Compression) -> Self {
+        FrameCompression { algorithm, threshold: DEFAULT_COMPRESSION_THRESHOLD }
+    }
+
+    /// Flag `frame` and compress it if that's worthwhile
+    pub fn encode(&self, frame: &[u8]) -> io::Result<Vec<u8>> {
+        if frame.len() >= self.threshold {
+            let mut compressed = vec![FLAG_COMPRESSED];
+            match self.algorithm {
+                Compression::Zstd => zstd::stream::copy_encode(frame, &mut compressed, ZSTD_LEVEL)?,
+                Compression::Deflate => {
+                    let mut encoder = flate2::write::DeflateEncoder::new(compressed, flate2::Compression::default());
+                    encoder.write_all(frame)?;
+                    compressed = encoder.finish()?;
+                }
+            }
+            if compressed.len() <= frame.len() {
+                return Ok(compressed);
+            }
+        }
+
+        let mut plain = Vec::with_capacity(frame.len() + 1);
+        plain.push(FLAG_PLAIN);
+        plain.extend_from_slice(frame);
+        Ok(plain)
+    }
+
+    /// Undo `encode`, refusing frames that expand past `max_len` bytes
+    ///
+    /// A plain frame comes back as a view of `frame`, without copying it.
+    pub fn decode(&self, frame: Bytes, max_len: usize) -> io::Result<Bytes> {
+        let (flag, body) = frame.split_first()
+            .ok_or_else(|| invalid_data("Empty frame on a compressed connection".to_string()))?;
+        if *flag == FLAG_PLAIN {
+            return Ok(frame.slice(1..));
+        }
+        if *flag != FLAG_COMPRESSED {
+            return Err(invalid_data(format!("Unknown frame flag {}", flag)));
+        }
+
+        // Read one byte past the limit to tell a full frame from an oversized one
+        let limit = max_len as u64 + 1;
+        let mut decoded = Vec::new();
+        match self.algorithm {
+            Compression::Zstd => zstd::stream::read::Decoder::new(body)?.take(limit).read_to_end(&mut decoded)?,
+            Compression::Deflate => flate2::read::DeflateDecoder::new(body).take(limit).read_to_end(&mut decoded)?,
+        };
+        if decoded.len() > max_len {
+            return Err(invalid_data(format!("Compressed frame expands past {} bytes", max_len)));
+        }
+        Ok(Bytes::from(decoded))
+    }
+}
+
+#[cfg(feature = "node")]
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(all(test, feature = "node"))]
+mod tests {
+    use super::*;
+
+    fn block_like_frame() -> Vec<u8> {
+        serde_json::to_vec(&vec![("to_addr", "alice", 1.5, 1_762_800_000_000u64); 500]).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_both_algorithms() {
+        let frame = block_like_frame();
+        for algorithm in Compression::ALL {
+            let compression = FrameCompression::new(algorithm);
+            let encoded = compression.encode(&frame).unwrap();
+            assert_eq!(encoded[0], FLAG_COMPRESSED);
+            assert!(encoded.len() < frame.len() / 4, "{:?} barely compressed", algorithm);
+            assert_eq!(compression.decode(Bytes::from(encoded), frame.len()).unwrap(), frame);
+        }
+    }
+
+    #[test]
+    fn test_small_frames_sent_plain() {
+        let compression = FrameCompression::new(Compression::Zstd);
+        let encoded = compression.encode(b"{\"Ping\":null}").unwrap();
+        assert_eq!(encoded[0], FLAG_PLAIN);
+        assert_eq!(compression.decode(Bytes::from(encoded), 1024).unwrap(), &b"{\"Ping\":null}"[..]);
+    }
+
+    #[test]
+    fn test_expansion_past_limit_refused() {
+        let frame = block_like_frame();
+        for algorithm in Compression::ALL {
+            let compression = FrameCompression::new(algorithm);
+            let encoded = compression.encode(&frame).unwrap();
+            let error = compression.decode(Bytes::from(encoded), frame.len() - 1).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+        assert!(FrameCompression::new(Compression::Zstd).decode(Bytes::from_static(&[7, 1, 2]), 1024).is_err());
+    }
+
+    #[test]
+    fn test_negotiate_and_parse() {
+        assert_eq!(Compression::negotiate(&[Compression::Deflate, Compression::Zstd], &Compression::ALL),
+            Some(Compression::Deflate));
+        assert_eq!(Compression::negotiate(&[Compression::Zstd], &[Compression::Deflate]), None);
+        assert_eq!(Compression::parse_list("zstd, Deflate").unwrap(), Compression::ALL.to_vec());
+        assert_eq!(Compression::parse_list("none").unwrap(), Vec::new());
+        assert!(Compression::parse_list("lz4").is_err());
+    }
+}
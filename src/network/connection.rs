@@ -0,0 +1,488 @@
+//! A persistent, multiplexed connection from a miner to its validator
+//!
+//! Requests are sent as `MinerRequest`s tagged with an ID and may be in
+//! flight concurrently; a reader task hands each `ValidatorResponse` to the
+//! request with the same ID. A heartbeat task pings the validator while the
+//! connection is open and marks it closed once `Heartbeat::max_missed` pings
+//! in a row go unanswered, so the owner knows to reconnect. Each ping carries
+//! the miner's clock, and the `Pong`'s exchange times refine the owner's
+//! clock skew estimate. Connecting, writes and each reply are bounded by
+//! `Timeouts`. If the owner offers compression, the connection opens with a
+//! `Hello` and compresses frames as the validator's `Welcome` says.
+//! `Connection::watch_address` subscribes to an address; the untagged
+//! `AddressActivity` frames the validator then pushes go to its receiver.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use bytes::Bytes;
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tracing::{debug, warn};
+use crate::now;
+use super::clock_skew::SkewEstimator;
+use super::compression::{Compression, FrameCompression};
+use super::protocol::{AddressActivityData, MinerMessage, MinerRequest, ValidatorMessage, ValidatorResponse};
+use super::retry::{with_timeout, Timeouts};
+use super::stream::{BoxedStream, Dialer};
+use super::transport::codec;
+
+/// How often an open connection is pinged, and how many missed pings mean it's dead
+///
+/// The validator uses the same settings the other way round: a client that has
+/// pinged is dropped once it goes `timeout()` without sending anything.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    /// Pings in a row that may go unanswered (each waits up to `interval`)
+    pub max_missed: u32,
+}
+
+impl Heartbeat {
+    /// How long the other side may stay silent before the connection is given up
+    pub fn timeout(&self) -> Duration {
+        self.interval * self.max_missed.max(1)
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat { interval: Duration::from_secs(5), max_missed: 3 }
+    }
+}
+
+/// State shared between a connection and its tasks
+struct Shared {
+    outgoing: mpsc::UnboundedSender<Vec<u8>>,
+    pending: Mutex<HashMap<u64, oneshot::Sender<ValidatorMessage>>>,
+    /// Where pushed activity goes, by address
+    watchers: Mutex<HashMap<String, Vec<mpsc::UnboundedSender<AddressActivityData>>>>,
+    next_id: AtomicU64,
+    closed: AtomicBool,
+    /// How long a request waits for its reply
+    reply_timeout: Duration,
+}
+
+impl Shared {
+    async fn request(&self, message: MinerMessage) -> io::Result<ValidatorMessage> {
+        self.request_within(message, self.reply_timeout).await
+    }
+
+    async fn request_within(&self, message: MinerMessage, reply_timeout: Duration) -> io::Result<ValidatorMessage> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (reply, answer) = oneshot::channel();
+        {
+            // Checked under the lock so `close` can't miss this request
+            let mut pending = self.pending.lock().unwrap();
+            if self.closed.load(Ordering::SeqCst) {
+                return Err(io::Error::new(io::ErrorKind::NotConnected, "Connection to validator is closed"));
+            }
+            pending.insert(id, reply);
+        }
+
+        let frame = serde_json::to_vec(&MinerRequest { id, message })?;
+        if self.outgoing.send(frame).is_err() {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "Connection to validator is closed"));
+        }
+        let reply = async {
+            answer.await.map_err(|_| io::Error::new(io::ErrorKind::ConnectionAborted, "Connection to validator lost"))
+        };
+        let result = with_timeout(reply_timeout, "validator reply", reply).await;
+        if result.is_err() {
+            self.pending.lock().unwrap().remove(&id);
+        }
+        result
+    }
+
+    /// Mark the connection closed and fail every request still waiting
+    fn close(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        self.closed.store(true, Ordering::SeqCst);
+        pending.clear();
+        self.watchers.lock().unwrap().clear();
+    }
+
+    /// Hand pushed activity to the receivers watching its address, forgetting dropped ones
+    fn deliver(&self, activity: AddressActivityData) {
+        let mut watchers = self.watchers.lock().unwrap();
+        if let Some(senders) = watchers.get_mut(&activity.address) {
+            senders.retain(|sender| sender.send(activity.clone()).is_ok());
+        }
+    }
+}
+
+/// An open connection to a validator
+pub struct Connection {
+    shared: Arc<Shared>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Connection {
+    /// Connect to `address` through `dialer`, pinging as `heartbeat` says
+    ///
+    /// A non-empty `compression` is offered to the validator, best first. Each
+    /// timestamped `Pong` adds a sample to `skew`, if given.
+    pub async fn open(
+        dialer: &dyn Dialer,
+        address: &str,
+        max_frame_len: usize,
+        heartbeat: Heartbeat,
+        timeouts: Timeouts,
+        compression: &[Compression],
+        skew: Option<Arc<Mutex<SkewEstimator>>>,
+    ) -> io::Result<Self> {
+        let stream = with_timeout(timeouts.connect, "connect", dialer.dial(address)).await?;
+        let mut framed = Framed::new(stream, codec(max_frame_len));
+        let compression = match compression {
+            [] => None,
+            offered => negotiate(&mut framed, offered, timeouts).await?,
+        };
+        let (mut sink, mut frames) = framed.split();
+        let (outgoing, mut to_send) = mpsc::unbounded_channel::<Vec<u8>>();
+        let shared = Arc::new(Shared {
+            outgoing,
+            pending: Mutex::new(HashMap::new()),
+            watchers: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            closed: AtomicBool::new(false),
+            reply_timeout: timeouts.read,
+        });
+
+        let writer = {
+            let shared = Arc::clone(&shared);
+            tokio::spawn(async move {
+                while let Some(frame) = to_send.recv().await {
+                    let frame = match compression {
+                        Some(compression) => match compression.encode(&frame) {
+                            Ok(frame) => frame,
+                            Err(e) => {
+                                debug!(error = %e, "failed to compress a frame");
+                                break;
+                            }
+                        },
+                        None => frame,
+                    };
+                    if let Err(e) = with_timeout(timeouts.write, "write", sink.send(Bytes::from(frame))).await {
+                        debug!(error = %e, "failed to write to validator");
+                        break;
+                    }
+                }
+                shared.close();
+            })
+        };
+
+        let reader = {
+            let shared = Arc::clone(&shared);
+            tokio::spawn(async move {
+                while let Some(frame) = frames.next().await {
+                    let frame = match frame.and_then(|frame| match compression {
+                        Some(compression) => compression.decode(frame.freeze(), max_frame_len),
+                        None => Ok(frame.freeze()),
+                    }) {
+                        Ok(frame) => frame,
+                        Err(e) => {
+                            debug!(error = %e, "failed to read from validator");
+                            break;
+                        }
+                    };
+                    match serde_json::from_slice::<ValidatorResponse>(&frame) {
+                        Ok(response) => {
+                            if let Some(reply) = shared.pending.lock().unwrap().remove(&response.id) {
+                                let _ = reply.send(response.message);
+                            }
+                        }
+                        Err(e) => match serde_json::from_slice::<ValidatorMessage>(&frame) {
+                            Ok(ValidatorMessage::AddressActivity(activity)) => shared.deliver(activity),
+                            _ => warn!(error = %e, "undecodable reply from validator"),
+                        },
+                    }
+                }
+                shared.close();
+            })
+        };
+
+        let heartbeat = {
+            let shared = Arc::clone(&shared);
+            tokio::spawn(async move {
+                let mut ticks = tokio::time::interval(heartbeat.interval);
+                ticks.tick().await;
+                let mut missed = 0;
+                loop {
+                    ticks.tick().await;
+                    let sent_at = now();
+                    match shared.request_within(MinerMessage::Ping { sent_at: Some(sent_at) }, heartbeat.interval).await {
+                        Ok(ValidatorMessage::Pong { exchange_times }) => {
+                            missed = 0;
+                            if let Some(times) = exchange_times.filter(|times| times.client_sent_at == sent_at) {
+                                let sample = times.to_sample(now());
+                                debug!(round_trip_ms = sample.delay_ms, offset_ms = sample.offset_ms, "heartbeat");
+                                if let Some(skew) = &skew {
+                                    skew.lock().unwrap().add(sample);
+                                }
+                            }
+                        }
+                        // A rate-limited ping still shows the validator is there
+                        Ok(ValidatorMessage::RateLimited { .. }) => missed = 0,
+                        Err(e) if e.kind() == io::ErrorKind::TimedOut && missed + 1 < heartbeat.max_missed => {
+                            missed += 1;
+                            debug!(missed, "heartbeat unanswered");
+                        }
+                        _ => {
+                            if !shared.closed.load(Ordering::SeqCst) {
+                                warn!(missed = missed + 1, "validator stopped answering heartbeats");
+                            }
+                            shared.close();
+                            break;
+                        }
+                    }
+                }
+            })
+        };
+
+        Ok(Connection { shared, tasks: vec![writer, reader, heartbeat] })
+    }
+
+    /// Send `message` and wait for its reply; other requests may be in flight meanwhile
+    pub async fn request(&self, message: MinerMessage) -> io::Result<ValidatorMessage> {
+        self.shared.request(message).await
+    }
+
+    /// Watch `address`: the validator pushes activity for it to the returned receiver
+    ///
+    /// The receiver ends when the connection closes; watches don't carry over to a new connection.
+    pub async fn watch_address(&self, address: &str) -> io::Result<mpsc::UnboundedReceiver<AddressActivityData>> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        // Registered first, so activity pushed right after the reply isn't missed
+        self.shared.watchers.lock().unwrap().entry(address.to_string()).or_default().push(sender);
+        let error = match self.request(MinerMessage::WatchAddress { address: address.to_string() }).await {
+            Ok(ValidatorMessage::Watching { .. }) => return Ok(receiver),
+            Ok(other) => io::Error::other(format!("Validator refused to watch {}: {:?}", address, other)),
+            Err(e) => e,
+        };
+        drop(receiver);
+        if let Some(senders) = self.shared.watchers.lock().unwrap().get_mut(address) {
+            senders.retain(|sender| !sender.is_closed());
+        }
+        Err(error)
+    }
+
+    /// Whether the connection has failed and should be replaced
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::SeqCst)
+    }
+
+    /// Requests sent and not yet answered
+    pub fn in_flight(&self) -> usize {
+        self.shared.pending.lock().unwrap().len()
+    }
+}
+
+/// Offer `offered` in a `Hello` and return the compression the validator chose
+async fn negotiate(
+    framed: &mut Framed<BoxedStream, LengthDelimitedCodec>,
+    offered: &[Compression],
+    timeouts: Timeouts,
+) -> io::Result<Option<FrameCompression>> {
+    let hello = serde_json::to_vec(&MinerMessage::Hello { compression: offered.to_vec() })?;
+    with_timeout(timeouts.write, "write", framed.send(Bytes::from(hello))).await?;
+    let reply = with_timeout(timeouts.read, "validator reply", async {
+        framed.next().await.transpose()?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Validator closed the connection during the handshake"))
+    }).await?;
+
+    match serde_json::from_slice(&reply)? {
+        ValidatorMessage::Welcome { compression } => {
+            debug!(?compression, "handshake complete");
+            Ok(compression.map(FrameCompression::new))
+        }
+        other => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Expected Welcome, got {:?}", other))),
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::protocol::{AddressTxData, ExchangeTimesData, PageQuery};
+    use crate::network::stream::TcpDialer;
+    use crate::network::transport::{Transport, DEFAULT_MAX_FRAME_LEN};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_replies_matched_by_id() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        // A validator that holds two requests and answers them in reverse order
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut transport = Transport::new(socket, DEFAULT_MAX_FRAME_LEN);
+            let mut requests = Vec::new();
+            while requests.len() < 2 {
+                let frame = transport.recv_frame().await.unwrap().unwrap();
+                requests.push(serde_json::from_slice::<MinerRequest>(&frame).unwrap());
+            }
+            for request in requests.into_iter().rev() {
+                let message = match request.message {
+                    MinerMessage::GetBalance { address } => ValidatorMessage::Balance { address, balance: 1.0 },
+                    _ => ValidatorMessage::Pong { exchange_times: None },
+                };
+                transport.send(&ValidatorResponse { id: request.id, message }).await.unwrap();
+            }
+        });
+
+        let connection = Connection::open(&TcpDialer, &address, DEFAULT_MAX_FRAME_LEN, Heartbeat::default(), Timeouts::default(), &[], None).await.unwrap();
+        let (balance, pong) = tokio::join!(
+            connection.request(MinerMessage::GetBalance { address: "alice".to_string() }),
+            connection.request(MinerMessage::Ping { sent_at: None }),
+        );
+        assert!(matches!(balance.unwrap(), ValidatorMessage::Balance { address, .. } if address == "alice"));
+        assert!(matches!(pong.unwrap(), ValidatorMessage::Pong { .. }));
+        assert_eq!(connection.in_flight(), 0);
+
+        // The validator hung up; waiting requests fail rather than hang
+        while !connection.is_closed() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(connection.request(MinerMessage::Ping { sent_at: None }).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pushed_activity_reaches_watcher() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        // A validator that confirms the watch, then pushes activity for two addresses
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut transport = Transport::new(socket, DEFAULT_MAX_FRAME_LEN);
+            let frame = transport.recv_frame().await.unwrap().unwrap();
+            let request = serde_json::from_slice::<MinerRequest>(&frame).unwrap();
+            let address = match request.message {
+                MinerMessage::WatchAddress { address } => address,
+                other => panic!("expected WatchAddress, got {:?}", other),
+            };
+            transport.send(&ValidatorResponse { id: request.id, message: ValidatorMessage::Watching { address } }).await.unwrap();
+            for (address, height) in [("bob", None), ("alice", None), ("alice", Some(4))] {
+                let activity = AddressActivityData {
+                    address: address.to_string(), tx_hash: "ab".to_string(), received: 1.0, sent: 0.0, height,
+                };
+                transport.send(&ValidatorMessage::AddressActivity(activity)).await.unwrap();
+            }
+        });
+
+        let connection = Connection::open(&TcpDialer, &address, DEFAULT_MAX_FRAME_LEN, Heartbeat::default(), Timeouts::default(), &[], None).await.unwrap();
+        let mut activity = connection.watch_address("alice").await.unwrap();
+        assert_eq!(activity.recv().await.unwrap().height, None);
+        assert_eq!(activity.recv().await.unwrap().height, Some(4));
+
+        // The validator hung up, which ends the watch
+        assert!(activity.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_compression_negotiated_in_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        // A validator that only speaks deflate and answers with a large history
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut transport = Transport::new(socket, DEFAULT_MAX_FRAME_LEN);
+            let frame = transport.recv_frame().await.unwrap().unwrap();
+            let offered = match serde_json::from_slice(&frame).unwrap() {
+                MinerMessage::Hello { compression } => compression,
+                other => panic!("expected Hello, got {:?}", other),
+            };
+            let chosen = Compression::negotiate(&offered, &[Compression::Deflate]);
+            transport.send(&ValidatorMessage::Welcome { compression: chosen }).await.unwrap();
+            transport.set_compression(chosen.map(FrameCompression::new));
+
+            let frame = transport.recv_frame().await.unwrap().unwrap();
+            let request: MinerRequest = serde_json::from_slice(&frame).unwrap();
+            let transactions = (0..500).map(|i| AddressTxData {
+                block_index: i,
+                block_timestamp: 1_762_800_000_000,
+                tx_hash: "00".repeat(32),
+                received: 1.0,
+                sent: 0.0,
+            }).collect();
+            let message = ValidatorMessage::History { address: "alice".to_string(), transactions, next_cursor: None };
+            transport.send(&ValidatorResponse { id: request.id, message }).await.unwrap();
+        });
+
+        let connection = Connection::open(&TcpDialer, &address, DEFAULT_MAX_FRAME_LEN, Heartbeat::default(),
+            Timeouts::default(), &Compression::ALL, None).await.unwrap();
+        let reply = connection.request(MinerMessage::GetHistory { address: "alice".to_string(), page: PageQuery::default() }).await.unwrap();
+        assert!(matches!(reply, ValidatorMessage::History { transactions, .. } if transactions.len() == 500));
+    }
+
+    #[tokio::test]
+    async fn test_silent_validator_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            // Accept, then never answer
+            let (_socket, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        });
+
+        let timeouts = Timeouts { read: Duration::from_millis(50), ..Timeouts::default() };
+        let connection = Connection::open(&TcpDialer, &address, DEFAULT_MAX_FRAME_LEN, Heartbeat::default(), timeouts, &[], None).await.unwrap();
+        let error = connection.request(MinerMessage::GetBlockchainInfo).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(connection.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeats_measure_skew_and_detect_dead_validator() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        // A validator 500ms ahead that answers two pings, then hangs without closing
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut transport = Transport::new(socket, DEFAULT_MAX_FRAME_LEN);
+            for _ in 0..2 {
+                let frame = transport.recv_frame().await.unwrap().unwrap();
+                let request: MinerRequest = serde_json::from_slice(&frame).unwrap();
+                let sent_at = match request.message {
+                    MinerMessage::Ping { sent_at } => sent_at.unwrap(),
+                    other => panic!("expected Ping, got {:?}", other),
+                };
+                let exchange_times = Some(ExchangeTimesData {
+                    client_sent_at: sent_at,
+                    server_received_at: now() + 500,
+                    server_sent_at: now() + 500,
+                });
+                transport.send(&ValidatorResponse { id: request.id, message: ValidatorMessage::Pong { exchange_times } }).await.unwrap();
+            }
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        });
+
+        let skew = Arc::new(Mutex::new(SkewEstimator::new()));
+        let heartbeat = Heartbeat { interval: Duration::from_millis(50), max_missed: 2 };
+        let connection = Connection::open(&TcpDialer, &address, DEFAULT_MAX_FRAME_LEN, heartbeat, Timeouts::default(),
+            &[], Some(Arc::clone(&skew))).await.unwrap();
+
+        let started = std::time::Instant::now();
+        while !connection.is_closed() {
+            assert!(started.elapsed() < Duration::from_secs(5), "dead validator not detected");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let offset_ms = skew.lock().unwrap().offset_ms().unwrap();
+        assert!((450..=550).contains(&offset_ms), "offset {}", offset_ms);
+    }
+}
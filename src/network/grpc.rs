@@ -0,0 +1,644 @@
+//! gRPC service for the miner API (feature `grpc`)
+//!
+//! `proto/hourcoin.proto` mirrors `MinerMessage`/`ValidatorMessage` as one RPC
+//! per message, so miners in other languages can use generated stubs instead
+//! of the length-prefixed JSON protocol. Calls are answered by the same code
+//! as framed requests, rate limits included. Timestamps are `u128` here and
+//! `uint64` on the wire; values that don't fit are clamped.
+
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+use tracing::info;
+use crate::Difficulty;
+use crate::canonical::Canonical;
+use super::protocol::*;
+use super::stream::BoxFuture;
+
+/// Types and stubs generated from `proto/hourcoin.proto`
+pub mod proto {
+    #![allow(clippy::all)]
+    tonic::include_proto!("hourcoin");
+}
+
+use proto::validator_server::{Validator as ValidatorRpc, ValidatorServer as ValidatorRpcServer};
+
+/// Answers a miner message from the client at the given address
+pub type Answer = Arc<dyn Fn(MinerMessage, IpAddr) -> BoxFuture<'static, ValidatorMessage> + Send + Sync>;
+
+/// Trailer carrying `RateLimited::retry_after_ms`
+pub const RETRY_AFTER_METADATA: &str = "retry-after-ms";
+
+/// Serve the gRPC API on `address` until the listener fails
+pub async fn serve(address: String, answer: Answer) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let listener = TcpListener::bind(&address).await?;
+    info!(%address, "gRPC endpoint listening");
+    serve_on(listener, answer).await
+}
+
+async fn serve_on(listener: TcpListener, answer: Answer) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let incoming = TcpIncoming::from_listener(listener, true, None)?;
+    tonic::transport::Server::builder()
+        .add_service(ValidatorRpcServer::new(GrpcService { answer }))
+        .serve_with_incoming(incoming)
+        .await?;
+    Ok(())
+}
+
+struct GrpcService {
+    answer: Answer,
+}
+
+impl GrpcService {
+    /// Answer `message` for the caller of `request`, turning refusals into statuses
+    async fn call<T>(&self, request: &Request<T>, message: MinerMessage) -> Result<ValidatorMessage, Status> {
+        let ip = request.remote_addr().map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |address| address.ip());
+        match (self.answer)(message, ip).await {
+            ValidatorMessage::RateLimited { retry_after_ms } => {
+                let mut status = Status::resource_exhausted(format!("Rate limited; retry in {}ms", retry_after_ms));
+                if let Ok(value) = retry_after_ms.to_string().parse() {
+                    status.metadata_mut().insert(RETRY_AFTER_METADATA, value);
+                }
+                Err(status)
+            }
+            ValidatorMessage::Error { message } => Err(Status::failed_precondition(message)),
+            reply => Ok(reply),
+        }
+    }
+}
+
+fn unexpected(reply: ValidatorMessage) -> Status {
+    Status::internal(format!("Unexpected reply from validator: {:?}", reply))
+}
+
+fn clamp(value: u128) -> u64 {
+    value.min(u64::MAX as u128) as u64
+}
+
+#[tonic::async_trait]
+impl ValidatorRpc for GrpcService {
+    async fn get_round_info(&self, request: Request<proto::GetRoundInfoRequest>) -> Result<Response<proto::RoundInfo>, Status> {
+        let message = MinerMessage::GetRoundInfo {
+            miner_id: request.get_ref().miner_id.clone(),
+            sent_at: request.get_ref().sent_at.map(u128::from),
+        };
+        match self.call(&request, message).await? {
+            ValidatorMessage::RoundInfo(info) => Ok(Response::new(info.into())),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn commit_timestamp(&self, request: Request<proto::CommitTimestampRequest>) -> Result<Response<proto::CommitmentAccepted>, Status> {
+        let message = MinerMessage::CommitTimestamp {
+            miner_id: request.get_ref().miner_id.clone(),
+            commitment: request.get_ref().commitment.clone(),
+            request_id: request.get_ref().request_id.clone(),
+        };
+        match self.call(&request, message).await? {
+            ValidatorMessage::CommitmentAccepted { round_start, reveal_after_ms } => {
+                Ok(Response::new(proto::CommitmentAccepted { round_start: clamp(round_start), reveal_after_ms }))
+            }
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn submit_block(&self, request: Request<proto::SubmitBlockRequest>) -> Result<Response<proto::BlockResult>, Status> {
+        let block = request.get_ref().block.clone()
+            .ok_or_else(|| Status::invalid_argument("block is required"))?;
+        let message = MinerMessage::SubmitBlock {
+            miner_id: request.get_ref().miner_id.clone(),
+            block: BlockData::try_from(block).and_then(|block| block.to_block())
+                .map(|block| Box::new(Canonical(block))).map_err(Status::invalid_argument)?,
+            salt: request.get_ref().salt.clone(),
+            round_id: request.get_ref().round_id.clone(),
+            request_id: request.get_ref().request_id.clone(),
+        };
+        match self.call(&request, message).await? {
+            ValidatorMessage::BlockResult { result, message, receipt } => Ok(Response::new(proto::BlockResult {
+                result: proto::BlockResultType::from(result) as i32,
+                message,
+                receipt: receipt.map(proto::Receipt::from),
+            })),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn check_lockout(&self, request: Request<proto::CheckLockoutRequest>) -> Result<Response<proto::LockoutStatus>, Status> {
+        let message = MinerMessage::CheckLockout { miner_id: request.get_ref().miner_id.clone() };
+        match self.call(&request, message).await? {
+            ValidatorMessage::LockoutStatus { is_locked, seconds_remaining } => {
+                Ok(Response::new(proto::LockoutStatus { is_locked, seconds_remaining }))
+            }
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn get_blockchain_info(&self, request: Request<proto::GetBlockchainInfoRequest>) -> Result<Response<proto::BlockchainInfo>, Status> {
+        match self.call(&request, MinerMessage::GetBlockchainInfo).await? {
+            ValidatorMessage::BlockchainInfo { block_count, difficulty, finalized_height, clock_offset_ms, clock_drift_paused } => {
+                Ok(Response::new(proto::BlockchainInfo {
+                    block_count: block_count as u64,
+                    difficulty_bits: difficulty.to_compact(),
+                    finalized_height,
+                    clock_offset_ms: clock_offset_ms.clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+                    clock_drift_paused,
+                }))
+            }
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn get_chain_tip(&self, request: Request<proto::GetChainTipRequest>) -> Result<Response<proto::ChainTip>, Status> {
+        match self.call(&request, MinerMessage::GetChainTip).await? {
+            ValidatorMessage::ChainTip { tip_hash, next_index, difficulty } => Ok(Response::new(proto::ChainTip {
+                tip_hash,
+                next_index,
+                difficulty_bits: difficulty.to_compact(),
+            })),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn get_balance(&self, request: Request<proto::GetBalanceRequest>) -> Result<Response<proto::Balance>, Status> {
+        let message = MinerMessage::GetBalance { address: request.get_ref().address.clone() };
+        match self.call(&request, message).await? {
+            ValidatorMessage::Balance { address, balance } => Ok(Response::new(proto::Balance { address, balance })),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn get_history(&self, request: Request<proto::GetHistoryRequest>) -> Result<Response<proto::History>, Status> {
+        let get = request.get_ref();
+        let page = PageQuery { cursor: get.cursor.clone(), limit: get.limit, from_height: get.from_height, to_height: get.to_height };
+        let message = MinerMessage::GetHistory { address: get.address.clone(), page };
+        match self.call(&request, message).await? {
+            ValidatorMessage::History { address, transactions, next_cursor } => Ok(Response::new(proto::History {
+                address,
+                transactions: transactions.into_iter().map(proto::AddressTx::from).collect(),
+                next_cursor,
+            })),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn get_headers(&self, request: Request<proto::GetHeadersRequest>) -> Result<Response<proto::Headers>, Status> {
+        let message = MinerMessage::GetHeaders { from: request.get_ref().from, max: request.get_ref().max };
+        match self.call(&request, message).await? {
+            ValidatorMessage::Headers { headers } => Ok(Response::new(proto::Headers {
+                headers: headers.into_iter().map(proto::BlockHeader::from).collect(),
+            })),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn get_transaction_proof(&self, request: Request<proto::GetTransactionProofRequest>) -> Result<Response<proto::TransactionProof>, Status> {
+        let message = MinerMessage::GetTransactionProof {
+            height: request.get_ref().height,
+            tx_hash: request.get_ref().tx_hash.clone(),
+        };
+        match self.call(&request, message).await? {
+            ValidatorMessage::TransactionProof { height, proof } => Ok(Response::new(proto::TransactionProof {
+                height,
+                proof: Some(proto::MerkleProof {
+                    index: proof.index,
+                    leaf_count: proof.leaf_count,
+                    siblings: proof.siblings,
+                }),
+            })),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn get_miner_stats(&self, request: Request<proto::GetMinerStatsRequest>) -> Result<Response<proto::MinerStats>, Status> {
+        let message = MinerMessage::GetMinerStats { miner_id: request.get_ref().miner_id.clone() };
+        match self.call(&request, message).await? {
+            ValidatorMessage::MinerStats(stats) => Ok(Response::new(stats.into())),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn get_leaderboard(&self, request: Request<proto::GetLeaderboardRequest>) -> Result<Response<proto::Leaderboard>, Status> {
+        let message = MinerMessage::GetLeaderboard { limit: request.get_ref().limit };
+        match self.call(&request, message).await? {
+            ValidatorMessage::Leaderboard { miners } => Ok(Response::new(proto::Leaderboard {
+                miners: miners.into_iter().map(proto::MinerStats::from).collect(),
+            })),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn ping(&self, request: Request<proto::PingRequest>) -> Result<Response<proto::Pong>, Status> {
+        let message = MinerMessage::Ping { sent_at: request.get_ref().sent_at.map(u128::from) };
+        match self.call(&request, message).await? {
+            ValidatorMessage::Pong { exchange_times } => Ok(Response::new(proto::Pong {
+                exchange_times: exchange_times.map(proto::ExchangeTimes::from),
+            })),
+            other => Err(unexpected(other)),
+        }
+    }
+}
+
+impl From<RoundInfoData> for proto::RoundInfo {
+    fn from(info: RoundInfoData) -> Self {
+        proto::RoundInfo {
+            round_start: clamp(info.round_start),
+            tonce: info.tonce.map(u32::from),
+            challenge_seconds_remaining: info.challenge_seconds_remaining,
+            attempted_miners: info.attempted_miners as u64,
+            active_lockouts: info.active_lockouts as u64,
+            difficulty_bits: info.difficulty.to_compact(),
+            vrf_proof: info.vrf_proof.map(|proof| proto::VrfProof {
+                public_key: proof.public_key,
+                input: proof.input,
+                pre_output: proof.pre_output,
+                proof: proof.proof,
+                tonce_bits: u32::from(proof.tonce_bits),
+            }),
+            commit_reveal: info.commit_reveal,
+            utxo_commitment: info.utxo_commitment,
+            exchange_times: info.exchange_times.map(proto::ExchangeTimes::from),
+            remaining_attempts: info.remaining_attempts,
+            round_id: info.round_id,
+            round_signature: info.round_signature,
+        }
+    }
+}
+
+impl From<ExchangeTimesData> for proto::ExchangeTimes {
+    fn from(times: ExchangeTimesData) -> Self {
+        proto::ExchangeTimes {
+            client_sent_at: clamp(times.client_sent_at),
+            server_received_at: clamp(times.server_received_at),
+            server_sent_at: clamp(times.server_sent_at),
+        }
+    }
+}
+
+impl From<BlockResultType> for proto::BlockResultType {
+    fn from(result: BlockResultType) -> Self {
+        match result {
+            BlockResultType::Accepted => proto::BlockResultType::Accepted,
+            BlockResultType::RejectedInvalidHash => proto::BlockResultType::RejectedInvalidHash,
+            BlockResultType::RejectedInvalidTimestamp => proto::BlockResultType::RejectedInvalidTimestamp,
+            BlockResultType::RejectedTonceChallenge => proto::BlockResultType::RejectedTonceChallenge,
+            BlockResultType::RejectedMinerInLockout => proto::BlockResultType::RejectedMinerInLockout,
+            BlockResultType::RejectedMinerAlreadyAttempted => proto::BlockResultType::RejectedMinerAlreadyAttempted,
+            BlockResultType::RejectedMissingCommitment => proto::BlockResultType::RejectedMissingCommitment,
+            BlockResultType::RejectedCommitmentMismatch => proto::BlockResultType::RejectedCommitmentMismatch,
+            BlockResultType::RejectedRevealOutsideWindow => proto::BlockResultType::RejectedRevealOutsideWindow,
+            BlockResultType::RejectedBlockchainValidation => proto::BlockResultType::RejectedBlockchainValidation,
+            BlockResultType::RejectedDuplicateBlock => proto::BlockResultType::RejectedDuplicateBlock,
+            BlockResultType::RejectedClockDrift => proto::BlockResultType::RejectedClockDrift,
+            BlockResultType::RejectedPaused => proto::BlockResultType::RejectedPaused,
+            BlockResultType::RejectedMinerBanned => proto::BlockResultType::RejectedMinerBanned,
+            BlockResultType::RejectedStaleRound => proto::BlockResultType::RejectedStaleRound,
+            BlockResultType::RejectedByHook => proto::BlockResultType::RejectedByHook,
+        }
+    }
+}
+
+impl From<ReceiptData> for proto::Receipt {
+    fn from(receipt: ReceiptData) -> Self {
+        proto::Receipt {
+            block_hash: receipt.block_hash,
+            height: receipt.height,
+            miner_id: receipt.miner_id,
+            accepted_at: clamp(receipt.accepted_at),
+            validator_public_key: receipt.validator_public_key,
+            signature: receipt.signature,
+        }
+    }
+}
+
+impl From<AddressTxData> for proto::AddressTx {
+    fn from(tx: AddressTxData) -> Self {
+        proto::AddressTx {
+            block_index: tx.block_index,
+            block_timestamp: clamp(tx.block_timestamp),
+            tx_hash: tx.tx_hash,
+            received: tx.received,
+            sent: tx.sent,
+        }
+    }
+}
+
+impl From<BlockHeaderData> for proto::BlockHeader {
+    fn from(header: BlockHeaderData) -> Self {
+        proto::BlockHeader {
+            index: header.index,
+            timestamp: clamp(header.timestamp),
+            prev_block_hash: header.prev_block_hash,
+            utxo_commitment: header.utxo_commitment,
+            round: header.round.map(proto::RoundCommitment::from),
+            merkle_root: header.merkle_root,
+            bits: header.bits.to_compact(),
+            nonce: header.nonce,
+            receipt: header.receipt.map(proto::Receipt::from),
+        }
+    }
+}
+
+impl From<RoundCommitmentData> for proto::RoundCommitment {
+    fn from(round: RoundCommitmentData) -> Self {
+        proto::RoundCommitment {
+            round_start: clamp(round.round_start),
+            tonce: u32::from(round.tonce),
+            signature: round.signature,
+        }
+    }
+}
+
+impl TryFrom<proto::RoundCommitment> for RoundCommitmentData {
+    type Error = String;
+
+    fn try_from(round: proto::RoundCommitment) -> Result<Self, String> {
+        Ok(RoundCommitmentData {
+            round_start: u128::from(round.round_start),
+            tonce: u8::try_from(round.tonce).map_err(|_| format!("Invalid tonce: {}", round.tonce))?,
+            signature: round.signature,
+        })
+    }
+}
+
+impl From<MinerStatsData> for proto::MinerStats {
+    fn from(stats: MinerStatsData) -> Self {
+        proto::MinerStats {
+            miner_id: stats.miner_id,
+            blocks_accepted: stats.blocks_accepted,
+            rejections: stats.rejections.into_iter().collect(),
+            average_time_to_solution_ms: stats.average_time_to_solution_ms.map(clamp),
+            total_lockout_ms: clamp(stats.total_lockout_ms),
+            last_accepted_at: stats.last_accepted_at.map(clamp),
+        }
+    }
+}
+
+impl From<BlockData> for proto::Block {
+    fn from(block: BlockData) -> Self {
+        proto::Block {
+            index: block.index,
+            timestamp: clamp(block.timestamp),
+            hash: block.hash,
+            prev_block_hash: block.prev_block_hash,
+            utxo_commitment: block.utxo_commitment,
+            round: block.round.map(proto::RoundCommitment::from),
+            bits: block.bits.to_compact(),
+            nonce: block.nonce,
+            transactions: block.transactions.into_iter().map(proto::Transaction::from).collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::Block> for BlockData {
+    type Error = String;
+
+    fn try_from(block: proto::Block) -> Result<Self, String> {
+        Ok(BlockData {
+            index: block.index,
+            timestamp: u128::from(block.timestamp),
+            hash: block.hash,
+            prev_block_hash: block.prev_block_hash,
+            utxo_commitment: block.utxo_commitment,
+            round: block.round.map(RoundCommitmentData::try_from).transpose()?,
+            bits: Difficulty::from_compact(block.bits)?,
+            nonce: block.nonce,
+            transactions: block.transactions.into_iter()
+                .map(TransactionData::try_from)
+                .collect::<Result<Vec<_>, String>>()?,
+        })
+    }
+}
+
+impl From<TransactionData> for proto::Transaction {
+    fn from(tx: TransactionData) -> Self {
+        proto::Transaction {
+            inputs: tx.inputs.into_iter().map(proto::Output::from).collect(),
+            outputs: tx.outputs.into_iter().map(proto::Output::from).collect(),
+            witnesses: tx.witnesses.into_iter().map(proto::Witness::from).collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::Transaction> for TransactionData {
+    type Error = String;
+
+    fn try_from(tx: proto::Transaction) -> Result<Self, String> {
+        Ok(TransactionData {
+            inputs: tx.inputs.into_iter().map(OutputData::from).collect(),
+            outputs: tx.outputs.into_iter().map(OutputData::from).collect(),
+            witnesses: tx.witnesses.into_iter()
+                .map(WitnessData::try_from)
+                .collect::<Result<Vec<_>, String>>()?,
+        })
+    }
+}
+
+impl From<OutputData> for proto::Output {
+    fn from(output: OutputData) -> Self {
+        proto::Output {
+            to_addr: output.to_addr,
+            value: output.value,
+            timestamp: clamp(output.timestamp),
+            lock_until: output.lock_until.map(clamp),
+        }
+    }
+}
+
+impl From<proto::Output> for OutputData {
+    fn from(output: proto::Output) -> Self {
+        OutputData {
+            to_addr: output.to_addr,
+            value: output.value,
+            timestamp: u128::from(output.timestamp),
+            lock_until: output.lock_until.map(u128::from),
+        }
+    }
+}
+
+impl From<WitnessData> for proto::Witness {
+    fn from(witness: WitnessData) -> Self {
+        use proto::witness::Kind;
+
+        let kind = match witness {
+            WitnessData::Htlc { payment_hash, recipient, refund_address, timeout, preimage } => {
+                Kind::Htlc(proto::HtlcWitness { payment_hash, recipient, refund_address, timeout: clamp(timeout), preimage })
+            }
+            WitnessData::ChannelClose {
+                party_addresses, party_public_keys, dispute_period_ms, sequence, balances,
+                revocation_hashes, signatures, closing, unilateral,
+            } => Kind::ChannelClose(proto::ChannelCloseWitness {
+                party_addresses: party_addresses.to_vec(),
+                party_public_keys: party_public_keys.to_vec(),
+                dispute_period_ms: clamp(dispute_period_ms),
+                sequence,
+                balances: balances.to_vec(),
+                revocation_hashes: revocation_hashes.to_vec(),
+                signatures: signatures.to_vec(),
+                closing,
+                unilateral: unilateral.map(|(closer, timeout)| proto::UnilateralClose {
+                    closer: closer as u32,
+                    timeout: clamp(timeout),
+                }),
+            }),
+            WitnessData::Signature { public_key, signature } => Kind::Signature(proto::SignatureWitness { public_key, signature }),
+            WitnessData::Multisig { threshold, public_keys, signatures } => Kind::Multisig(proto::MultisigWitness {
+                threshold: threshold as u32,
+                public_keys,
+                signatures: signatures.into_iter().map(Option::unwrap_or_default).collect(),
+            }),
+            WitnessData::ExtraNonce { extra_nonce } => Kind::ExtraNonce(extra_nonce),
+            WitnessData::Message { text } => Kind::Message(text),
+        };
+        proto::Witness { kind: Some(kind) }
+    }
+}
+
+impl TryFrom<proto::Witness> for WitnessData {
+    type Error = String;
+
+    fn try_from(witness: proto::Witness) -> Result<Self, String> {
+        use proto::witness::Kind;
+
+        Ok(match witness.kind.ok_or_else(|| "Witness has no kind".to_string())? {
+            Kind::Htlc(htlc) => WitnessData::Htlc {
+                payment_hash: htlc.payment_hash,
+                recipient: htlc.recipient,
+                refund_address: htlc.refund_address,
+                timeout: u128::from(htlc.timeout),
+                preimage: htlc.preimage,
+            },
+            Kind::ChannelClose(close) => WitnessData::ChannelClose {
+                party_addresses: pair(close.party_addresses, "party_addresses")?,
+                party_public_keys: pair(close.party_public_keys, "party_public_keys")?,
+                dispute_period_ms: u128::from(close.dispute_period_ms),
+                sequence: close.sequence,
+                balances: pair(close.balances, "balances")?,
+                revocation_hashes: pair(close.revocation_hashes, "revocation_hashes")?,
+                signatures: pair(close.signatures, "signatures")?,
+                closing: close.closing,
+                unilateral: close.unilateral.map(|close| (close.closer as usize, u128::from(close.timeout))),
+            },
+            Kind::Signature(signature) => WitnessData::Signature {
+                public_key: signature.public_key,
+                signature: signature.signature,
+            },
+            Kind::Multisig(multisig) => WitnessData::Multisig {
+                threshold: multisig.threshold as usize,
+                public_keys: multisig.public_keys,
+                signatures: multisig.signatures.into_iter()
+                    .map(|signature| Some(signature).filter(|signature| !signature.is_empty()))
+                    .collect(),
+            },
+            Kind::ExtraNonce(extra_nonce) => WitnessData::ExtraNonce { extra_nonce },
+            Kind::Message(text) => WitnessData::Message { text },
+        })
+    }
+}
+
+/// The two entries of a per-party channel field
+fn pair<T>(values: Vec<T>, field: &str) -> Result<[T; 2], String> {
+    <[T; 2]>::try_from(values).map_err(|values| format!("{} needs 2 entries, got {}", field, values.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::validator_client::ValidatorClient;
+
+    #[tokio::test]
+    async fn test_calls_answered_through_the_miner_api() {
+        let answer: Answer = Arc::new(|message, _ip| Box::pin(async move {
+            match message {
+                MinerMessage::GetBalance { address } => ValidatorMessage::Balance { address, balance: 2.5 },
+                MinerMessage::SubmitBlock { miner_id, block, .. } => ValidatorMessage::BlockResult {
+                    result: BlockResultType::RejectedInvalidHash,
+                    message: format!("{} sent block {} with {} transactions", miner_id, block.0.header.index, block.0.body.transactions.len()),
+                    receipt: None,
+                },
+                MinerMessage::CheckLockout { .. } => ValidatorMessage::Error { message: "Unknown miner".to_string() },
+                _ => ValidatorMessage::RateLimited { retry_after_ms: 250 },
+            }
+        }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_on(listener, answer));
+
+        let channel = tonic::transport::Channel::from_shared(format!("http://{}", address)).unwrap()
+            .connect().await.unwrap();
+        let mut client = ValidatorClient::new(channel);
+
+        let balance = client.get_balance(proto::GetBalanceRequest { address: "alice".to_string() }).await.unwrap();
+        assert_eq!(balance.get_ref().address, "alice");
+        assert_eq!(balance.get_ref().balance, 2.5);
+
+        let block = proto::Block {
+            index: 7,
+            hash: "00".repeat(32),
+            prev_block_hash: "00".repeat(32),
+            transactions: vec![proto::Transaction::default()],
+            ..proto::Block::default()
+        };
+        let result = client.submit_block(proto::SubmitBlockRequest {
+            miner_id: "alice".to_string(),
+            block: Some(block),
+            salt: None,
+            round_id: None,
+            request_id: None,
+        }).await.unwrap().into_inner();
+        assert_eq!(result.result(), proto::BlockResultType::RejectedInvalidHash);
+        assert_eq!(result.message, "alice sent block 7 with 1 transactions");
+
+        let missing = client.submit_block(proto::SubmitBlockRequest::default()).await.unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::InvalidArgument);
+
+        let refused = client.check_lockout(proto::CheckLockoutRequest { miner_id: "bob".to_string() }).await.unwrap_err();
+        assert_eq!(refused.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(refused.message(), "Unknown miner");
+
+        let limited = client.ping(proto::PingRequest { sent_at: None }).await.unwrap_err();
+        assert_eq!(limited.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(limited.metadata().get(RETRY_AFTER_METADATA).unwrap(), "250");
+    }
+
+    #[test]
+    fn test_witnesses_survive_the_round_trip() {
+        let witnesses = vec![
+            WitnessData::Signature { public_key: "aa".to_string(), signature: "bb".to_string() },
+            WitnessData::Multisig {
+                threshold: 1,
+                public_keys: vec!["aa".to_string(), "cc".to_string()],
+                signatures: vec![None, Some("dd".to_string())],
+            },
+            WitnessData::ChannelClose {
+                party_addresses: ["alice".to_string(), "bob".to_string()],
+                party_public_keys: ["aa".to_string(), "bb".to_string()],
+                dispute_period_ms: 60_000,
+                sequence: 3,
+                balances: [1.5, 2.5],
+                revocation_hashes: ["01".to_string(), "02".to_string()],
+                signatures: ["03".to_string(), "04".to_string()],
+                closing: true,
+                unilateral: Some((1, 1_000)),
+            },
+            WitnessData::ExtraNonce { extra_nonce: 42 },
+            WitnessData::Message { text: "genesis".to_string() },
+        ];
+        for witness in witnesses {
+            let json = serde_json::to_string(&witness).unwrap();
+            let back = WitnessData::try_from(proto::Witness::from(witness)).unwrap();
+            assert_eq!(serde_json::to_string(&back).unwrap(), json);
+        }
+
+        let lopsided = proto::Witness {
+            kind: Some(proto::witness::Kind::ChannelClose(proto::ChannelCloseWitness {
+                party_addresses: vec!["alice".to_string()],
+                ..proto::ChannelCloseWitness::default()
+            })),
+        };
+        assert!(WitnessData::try_from(lopsided).is_err());
+        assert!(WitnessData::try_from(proto::Witness { kind: None }).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+//! Answering retried requests from a cache
+//!
+//! Timestamp commitments, block submissions and faucet requests change the
+//! validator's state, so processing one twice isn't harmless: a retried
+//! `SubmitBlock` would be refused because the first copy already used the
+//! miner's attempt. Clients tag such requests with a random `request_id`.
+//! The validator keeps the answer to each tagged request for a while, and a
+//! retry with the same ID gets that answer back instead of being processed
+//! again. A retry that arrives while the first copy is still being processed
+//! waits for its answer.
+//!
+//! IDs are scoped to the miner the request names, so one miner can't read
+//! another's answers by guessing IDs. An ID names a single request: reusing
+//! it for a different one returns the first one's answer.
+
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use super::protocol::ValidatorMessage;
+
+/// Longest `request_id` the validator accepts
+pub const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// Answers kept by default
+pub const DEFAULT_CAPACITY: usize = 4096;
+
+/// How long an answer is kept by default
+pub const DEFAULT_TTL: Duration = Duration::from_secs(600);
+
+/// A fresh random request ID (hex)
+pub fn new_request_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+/// The miner a request names (empty if none) and its request ID
+type RequestKey = (String, String);
+
+#[derive(Debug, Default)]
+struct Entries {
+    answers: HashMap<RequestKey, Arc<OnceCell<ValidatorMessage>>>,
+    /// Keys in the order they were first seen, with when
+    order: VecDeque<(Instant, RequestKey)>,
+}
+
+/// The validator's answers to recent tagged requests
+#[derive(Debug)]
+pub struct ResponseCache {
+    capacity: usize,
+    ttl: Duration,
+    entries: Mutex<Entries>,
+}
+
+impl Default for ResponseCache {
+    fn default() -> Self {
+        ResponseCache::new(DEFAULT_CAPACITY, DEFAULT_TTL)
+    }
+}
+
+impl ResponseCache {
+    /// Keep up to `capacity` answers, each for `ttl` after its request first arrived
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        ResponseCache { capacity: capacity.max(1), ttl, entries: Mutex::new(Entries::default()) }
+    }
+
+    /// Answers currently kept, including ones still being worked out
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().answers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Answer the request `request_id` from `miner_id`, running `process` only
+    /// if no earlier copy of it has been answered
+    ///
+    /// If the copy being processed is abandoned before it finishes, the next
+    /// copy runs `process` itself.
+    pub async fn answer<F, Fut>(&self, miner_id: Option<&str>, request_id: &str, now: Instant, process: F) -> ValidatorMessage
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = ValidatorMessage>,
+    {
+        let key = (miner_id.unwrap_or_default().to_string(), request_id.to_string());
+        let slot = self.slot(key, now);
+        slot.get_or_init(process).await.clone()
+    }
+
+    /// The answer slot for `key`, made if it's new, after dropping expired and excess answers
+    fn slot(&self, key: RequestKey, now: Instant) -> Arc<OnceCell<ValidatorMessage>> {
+        let mut entries = self.entries.lock().unwrap();
+        let entries = &mut *entries;
+        while entries.order.front().is_some_and(|(seen_at, _)| now.saturating_duration_since(*seen_at) >= self.ttl) {
+            Self::drop_oldest(entries);
+        }
+
+        if let Some(slot) = entries.answers.get(&key) {
+            return Arc::clone(slot);
+        }
+        while entries.order.len() >= self.capacity {
+            Self::drop_oldest(entries);
+        }
+        let slot = Arc::new(OnceCell::new());
+        entries.answers.insert(key.clone(), Arc::clone(&slot));
+        entries.order.push_back((now, key));
+        slot
+    }
+
+    fn drop_oldest(entries: &mut Entries) {
+        if let Some((_, key)) = entries.order.pop_front() {
+            entries.answers.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn error(message: &str) -> ValidatorMessage {
+        ValidatorMessage::Error { message: message.to_string() }
+    }
+
+    fn message_of(answer: ValidatorMessage) -> String {
+        match answer {
+            ValidatorMessage::Error { message } => message,
+            other => panic!("Wrong message type: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_get_the_first_answer() {
+        let cache = ResponseCache::default();
+        let now = Instant::now();
+        let runs = AtomicU32::new(0);
+        let process = |answer: &'static str| {
+            let runs = &runs;
+            move || async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                error(answer)
+            }
+        };
+
+        assert_eq!(message_of(cache.answer(Some("alice"), "r1", now, process("first")).await), "first");
+        assert_eq!(message_of(cache.answer(Some("alice"), "r1", now, process("second")).await), "first");
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // another miner's request, or another request ID, is processed
+        assert_eq!(message_of(cache.answer(Some("bob"), "r1", now, process("bob's")).await), "bob's");
+        assert_eq!(message_of(cache.answer(Some("alice"), "r2", now, process("r2")).await), "r2");
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_copies_wait_for_one_answer() {
+        let cache = ResponseCache::default();
+        let now = Instant::now();
+        let runs = AtomicU32::new(0);
+        let process = || async {
+            runs.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            error("done")
+        };
+
+        let (first, second) = tokio::join!(
+            cache.answer(Some("alice"), "r1", now, process),
+            cache.answer(Some("alice"), "r1", now, process),
+        );
+        assert_eq!(message_of(first), "done");
+        assert_eq!(message_of(second), "done");
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_answers_expire_and_are_bounded() {
+        let cache = ResponseCache::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+        cache.answer(Some("alice"), "r1", start, || async { error("old") }).await;
+        let later = start + Duration::from_secs(61);
+        assert_eq!(message_of(cache.answer(Some("alice"), "r1", later, || async { error("new") }).await), "new");
+
+        cache.answer(Some("alice"), "r2", later, || async { error("r2") }).await;
+        cache.answer(Some("alice"), "r3", later, || async { error("r3") }).await;
+        assert_eq!(cache.len(), 2);
+        // the oldest answer made room
+        assert_eq!(message_of(cache.answer(Some("alice"), "r1", later, || async { error("again") }).await), "again");
+    }
+}
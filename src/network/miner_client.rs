@@ -2,15 +2,92 @@
 ///
 /// Connects to a validator server, mines blocks, and submits them
 
-use tokio::net::TcpStream;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use crate::{Block, now, find_valid_timestamp, transaction};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, info, info_span, warn, Instrument};
+use crate::{estimate_mining_time, Block, BlockHash, Difficulty, Hashable, MiningProgress, RoundCommitment, TimeSync, TonceChallenge, now};
+use crate::time_source::TimeSource;
+use crate::tonce::{TimestampCandidates, TimestampWindow};
+use crate::tx_builder::TransactionBuilder;
+use crate::canonical::Canonical;
+use crate::commitment::{generate_salt, timestamp_commitment};
+use crate::merkle::MerkleProof;
+use crate::receipt::verify_receipt;
+use super::clock_skew::SkewEstimator;
+use super::mining::MiningHandle;
 use super::protocol::*;
+use super::compression::Compression;
+use super::connection::{Connection, Heartbeat};
+use super::idempotency::new_request_id;
+use super::retry::{RetryPolicy, Timeouts};
+use super::stream::{Dialer, TcpDialer};
+use super::transport::DEFAULT_MAX_FRAME_LEN;
 
 /// Miner client that connects to a validator
 pub struct MinerClient {
     miner_id: String,
     validator_address: String,
+    /// Validator VRF key that round proofs must come from, if pinned
+    vrf_public_key: Option<Vec<u8>>,
+    /// Validator node key that block receipts must be signed with, if pinned
+    receipt_public_key: Option<Vec<u8>>,
+    /// Threads searching the nonce space in parallel
+    mining_threads: usize,
+    /// Progress of the latest mining run
+    last_progress: Arc<Mutex<Option<MiningProgress>>>,
+    /// Our clock's offset from the validator's, measured from round info exchanges and heartbeats
+    skew: Arc<Mutex<SkewEstimator>>,
+    /// Largest reply accepted from the validator (bytes)
+    max_frame_len: usize,
+    /// How often the open connection is pinged, and how many missed pings mean the validator is gone
+    heartbeat: Heartbeat,
+    timeouts: Timeouts,
+    /// How connection failures and failed read-only requests are retried
+    retry_policy: RetryPolicy,
+    /// How connections to the validator are opened (TCP, TLS or QUIC)
+    dialer: Arc<dyn Dialer>,
+    /// Frame compression offered to the validator, best first (none by default)
+    compression: Vec<Compression>,
+    /// Timestamps the validator accepts around its clock (mainnet's by default)
+    timestamp_window: TimestampWindow,
+    /// Signalled when a transport pushes the start of a new round
+    round_started: Arc<tokio::sync::Notify>,
+    /// Connection shared by all requests, opened on first use
+    connection: Arc<tokio::sync::Mutex<Option<Arc<Connection>>>>,
+}
+
+/// The validator's clock as this miner estimates it
+struct ValidatorClock(Arc<Mutex<SkewEstimator>>);
+
+impl TimeSource for ValidatorClock {
+    fn now(&self) -> u128 {
+        self.0.lock().unwrap().to_validator_time(now())
+    }
+}
+
+/// How often mining progress is logged
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How far ahead of the validator's clock candidate timestamps are searched (milliseconds)
+const TIMESTAMP_SEARCH_SPAN_MS: u128 = 100_000;
+
+/// Candidate timestamps found per block
+const MAX_TIMESTAMP_CANDIDATES: usize = 16;
+
+/// Longest wait between round info polls while waiting for the next round
+const ROUND_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to wait before polling round info again
+///
+/// Rounds end when a block is accepted, which is most likely once the tonce
+/// challenge expires and any timestamp will do, so poll again right after it
+/// does rather than a full interval later.
+fn round_poll_delay(info: &RoundInfoData) -> Duration {
+    match info.challenge_seconds_remaining {
+        0 => ROUND_POLL_INTERVAL,
+        seconds => Duration::from_secs(seconds + 1).min(ROUND_POLL_INTERVAL),
+    }
 }
 
 impl MinerClient {
@@ -19,69 +96,186 @@ impl MinerClient {
         MinerClient {
             miner_id,
             validator_address,
+            vrf_public_key: None,
+            receipt_public_key: None,
+            mining_threads: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            last_progress: Arc::new(Mutex::new(None)),
+            skew: Arc::new(Mutex::new(SkewEstimator::new())),
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            heartbeat: Heartbeat::default(),
+            timeouts: Timeouts::default(),
+            retry_policy: RetryPolicy::default(),
+            dialer: Arc::new(TcpDialer),
+            compression: Vec::new(),
+            timestamp_window: TimestampWindow::default(),
+            round_started: Arc::new(tokio::sync::Notify::new()),
+            connection: Arc::new(tokio::sync::Mutex::new(None)),
         }
     }
 
-    /// Connect to the validator
-    async fn connect(&self) -> Result<TcpStream, Box<dyn std::error::Error>> {
-        let stream = TcpStream::connect(&self.validator_address).await?;
-        Ok(stream)
+    /// Hashes per second of the latest mining run (0 before the first run)
+    pub fn hashrate(&self) -> f64 {
+        self.last_mining_progress().map_or(0.0, |progress| progress.hashrate())
     }
 
-    /// Send a message to the validator and receive a response
-    async fn send_message(
-        &self,
-        stream: &mut TcpStream,
-        message: MinerMessage,
-    ) -> Result<ValidatorMessage, Box<dyn std::error::Error>> {
-        // Serialize message
-        let message_json = serde_json::to_vec(&message)?;
-        let len_bytes = (message_json.len() as u32).to_be_bytes();
+    /// Hashes tried and time spent by the latest mining run
+    pub fn last_mining_progress(&self) -> Option<MiningProgress> {
+        *self.last_progress.lock().unwrap()
+    }
+
+    /// How far the validator's clock is ahead of ours (milliseconds), once measured
+    pub fn validator_clock_skew_ms(&self) -> Option<i128> {
+        self.skew.lock().unwrap().offset_ms()
+    }
+
+    /// The current time by the validator's clock, as best we can tell
+    ///
+    /// Candidate timestamps are searched from here, so a skewed local clock
+    /// doesn't produce blocks the validator rejects as out of its window.
+    pub fn validator_now(&self) -> u128 {
+        self.skew.lock().unwrap().to_validator_time(now())
+    }
+
+    /// Mine with this many threads (defaults to the number of CPUs)
+    pub fn set_mining_threads(&mut self, threads: usize) {
+        self.mining_threads = threads.max(1);
+    }
 
-        // Send message
-        stream.write_all(&len_bytes).await?;
-        stream.write_all(&message_json).await?;
-        stream.flush().await?;
+    /// Only accept VRF round proofs signed by this validator key
+    pub fn set_vrf_public_key(&mut self, public_key: Vec<u8>) {
+        self.vrf_public_key = Some(public_key);
+    }
 
-        // Read response length
-        let mut len_buffer = [0u8; 4];
-        stream.read_exact(&mut len_buffer).await?;
-        let response_len = u32::from_be_bytes(len_buffer) as usize;
+    /// Only trust block receipts signed by this validator node key
+    pub fn set_receipt_public_key(&mut self, public_key: Vec<u8>) {
+        self.receipt_public_key = Some(public_key);
+    }
 
-        // Read response
-        let mut response_buffer = vec![0u8; response_len];
-        stream.read_exact(&mut response_buffer).await?;
+    /// Refuse validator replies over `max_frame_len` bytes
+    pub fn set_max_frame_len(&mut self, max_frame_len: usize) {
+        self.max_frame_len = max_frame_len;
+    }
 
-        let response: ValidatorMessage = serde_json::from_slice(&response_buffer)?;
+    /// Ping the validator as `heartbeat` says while connected
+    pub fn set_heartbeat(&mut self, heartbeat: Heartbeat) {
+        self.heartbeat = heartbeat;
+    }
+
+    /// Bound connecting, writes and the wait for each reply
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /// Reach the validator through `dialer` instead of plain TCP
+    pub fn set_dialer(&mut self, dialer: Arc<dyn Dialer>) {
+        self.dialer = dialer;
+    }
+
+    /// Offer these compression algorithms, best first, when connecting
+    ///
+    /// Validators that predate compression drop a connection that offers it, so it is off by default.
+    pub fn set_compression(&mut self, compression: Vec<Compression>) {
+        self.compression = compression;
+    }
+
+    /// Expect the validator to accept timestamps within `window` of its clock
+    pub fn set_timestamp_window(&mut self, window: TimestampWindow) {
+        self.timestamp_window = window;
+    }
+
+    /// Retry failed connections and read-only requests under `policy`
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// The open connection to the validator, reconnecting if it was lost
+    async fn connection(&self) -> std::io::Result<Arc<Connection>> {
+        let mut slot = self.connection.lock().await;
+        if let Some(connection) = slot.as_ref().filter(|connection| !connection.is_closed()) {
+            return Ok(Arc::clone(connection));
+        }
+        *slot = None;
+
+        let connection = Arc::new(Connection::open(&*self.dialer, &self.validator_address, self.max_frame_len, self.heartbeat,
+            self.timeouts, &self.compression, Some(Arc::clone(&self.skew))).await?);
+        *slot = Some(Arc::clone(&connection));
+        Ok(connection)
+    }
+
+    /// Send a message to the validator and receive a response
+    ///
+    /// Requests share one connection and may be in flight concurrently. A
+    /// failure to connect is retried under the retry policy, as is any failure
+    /// of an idempotent request. Untagged submissions are sent at most once;
+    /// tagged ones are resent, and the validator answers a resent copy with
+    /// the first copy's result.
+    async fn send_message(&self, message: MinerMessage) -> Result<ValidatorMessage, Box<dyn std::error::Error>> {
+        let idempotent = message.is_idempotent();
+        let sent = AtomicBool::new(false);
+        let response = self.retry_policy.retry("validator request", || async {
+            let connection = self.connection().await?;
+            sent.store(true, Ordering::SeqCst);
+            connection.request(message.clone()).await
+        }, |_| idempotent || !sent.load(Ordering::SeqCst)).await?;
+        if let ValidatorMessage::RateLimited { retry_after_ms } = response {
+            return Err(format!("Rate limited by validator; retry in {}ms", retry_after_ms).into());
+        }
         Ok(response)
     }
 
     /// Get current round information from validator
     pub async fn get_round_info(&self) -> Result<RoundInfoData, Box<dyn std::error::Error>> {
-        let mut stream = self.connect().await?;
-
+        let sent_at = now();
         let message = MinerMessage::GetRoundInfo {
             miner_id: self.miner_id.clone(),
+            sent_at: Some(sent_at),
         };
 
-        let response = self.send_message(&mut stream, message).await?;
+        let response = self.send_message(message).await?;
+        let received_at = now();
 
         match response {
-            ValidatorMessage::RoundInfo(info) => Ok(info),
+            ValidatorMessage::RoundInfo(info) => {
+                // Older validators don't timestamp the reply
+                if let Some(times) = info.exchange_times.filter(|times| times.client_sent_at == sent_at) {
+                    self.skew.lock().unwrap().add(times.to_sample(received_at));
+                }
+                Ok(info)
+            }
             ValidatorMessage::Error { message } => Err(message.into()),
             _ => Err("Unexpected response".into()),
         }
     }
 
+    /// Wake a mining loop that is waiting for the next round
+    ///
+    /// For transports that push round starts. Without them the loop polls
+    /// round info instead, so this only cuts the wait short.
+    pub fn notify_round_started(&self) {
+        self.round_started.notify_one();
+    }
+
+    /// Wait until the validator starts a round after `round`, returning the new round's info
+    async fn wait_for_next_round(&self, round: RoundInfoData) -> Result<RoundInfoData, Box<dyn std::error::Error>> {
+        let round_start = round.round_start;
+        let mut info = round;
+        while info.round_start == round_start {
+            tokio::select! {
+                _ = tokio::time::sleep(round_poll_delay(&info)) => {}
+                _ = self.round_started.notified() => {}
+            }
+            info = self.get_round_info().await?;
+        }
+        Ok(info)
+    }
+
     /// Check lockout status
     pub async fn check_lockout(&self) -> Result<(bool, u64), Box<dyn std::error::Error>> {
-        let mut stream = self.connect().await?;
-
         let message = MinerMessage::CheckLockout {
             miner_id: self.miner_id.clone(),
         };
 
-        let response = self.send_message(&mut stream, message).await?;
+        let response = self.send_message(message).await?;
 
         match response {
             ValidatorMessage::LockoutStatus { is_locked, seconds_remaining } => {
@@ -92,112 +286,422 @@ impl MinerClient {
         }
     }
 
+    /// Commit to the timestamp we will submit this round
+    ///
+    /// Returns the salt to reveal, and how long the validator wants us to wait before revealing it.
+    pub async fn commit_timestamp(&self, timestamp: u128) -> Result<(Vec<u8>, Duration), Box<dyn std::error::Error>> {
+        let salt = generate_salt();
+        let message = MinerMessage::CommitTimestamp {
+            miner_id: self.miner_id.clone(),
+            commitment: hex::encode(timestamp_commitment(&self.miner_id, timestamp, &salt)),
+            request_id: Some(new_request_id()),
+        };
+
+        let response = self.send_message(message).await?;
+
+        match response {
+            ValidatorMessage::CommitmentAccepted { reveal_after_ms, .. } => Ok((salt, Duration::from_millis(reveal_after_ms))),
+            ValidatorMessage::Error { message } => Err(message.into()),
+            _ => Err("Unexpected response".into()),
+        }
+    }
+
+    /// Number of blocks in the validator's chain
+    pub async fn get_block_count(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        let response = self.send_message(MinerMessage::GetBlockchainInfo).await?;
+
+        match response {
+            ValidatorMessage::BlockchainInfo { block_count, .. } => Ok(block_count),
+            ValidatorMessage::Error { message } => Err(message.into()),
+            _ => Err("Unexpected response".into()),
+        }
+    }
+
+    /// The validator's tip: the hash and index a new block must extend, and the difficulty to mine it at
+    pub async fn get_chain_tip(&self) -> Result<(BlockHash, u32, Difficulty), Box<dyn std::error::Error>> {
+        let response = self.send_message(MinerMessage::GetChainTip).await?;
+
+        match response {
+            ValidatorMessage::ChainTip { tip_hash, next_index, difficulty } => {
+                let tip_hash = tip_hash.parse::<BlockHash>()
+                    .map_err(|e| format!("Invalid tip_hash: {}", e))?;
+                Ok((tip_hash, next_index, difficulty))
+            }
+            ValidatorMessage::Error { message } => Err(message.into()),
+            _ => Err("Unexpected response".into()),
+        }
+    }
+
+    /// Submit a block mined for the round `round_id` names, revealing `salt` if we committed to its timestamp
+    pub async fn submit_block(&self, block: &Block, salt: Option<String>, round_id: Option<String>) -> Result<ValidatorMessage, Box<dyn std::error::Error>> {
+        let message = MinerMessage::SubmitBlock {
+            miner_id: self.miner_id.clone(),
+            block: Box::new(Canonical(block.clone())),
+            salt,
+            round_id,
+            request_id: Some(new_request_id()),
+        };
+        self.send_message(message).await
+    }
+
+    /// Get the unspent balance of an address
+    pub async fn get_balance(&self, address: &str) -> Result<f64, Box<dyn std::error::Error>> {
+        let message = MinerMessage::GetBalance {
+            address: address.to_string(),
+        };
+
+        let response = self.send_message(message).await?;
+
+        match response {
+            ValidatorMessage::Balance { balance, .. } => Ok(balance),
+            ValidatorMessage::Error { message } => Err(message.into()),
+            _ => Err("Unexpected response".into()),
+        }
+    }
+
+    /// Ask the validator's faucet to pay `address`, returning the payment's transaction hash
+    pub async fn request_faucet(&self, address: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let message = MinerMessage::RequestFaucet {
+            address: address.to_string(),
+            request_id: Some(new_request_id()),
+        };
+
+        let response = self.send_message(message).await?;
+
+        match response {
+            ValidatorMessage::FaucetPayment { tx_hash, .. } => Ok(tx_hash),
+            ValidatorMessage::Error { message } => Err(message.into()),
+            _ => Err("Unexpected response".into()),
+        }
+    }
+
+    /// Get the transactions that sent or received value for an address, fetching every page
+    pub async fn get_history(&self, address: &str) -> Result<Vec<AddressTxData>, Box<dyn std::error::Error>> {
+        let mut page = PageQuery::default();
+        let mut transactions = vec![];
+        loop {
+            let (mut more, next_cursor) = self.get_history_page(address, page.clone()).await?;
+            transactions.append(&mut more);
+            match next_cursor {
+                Some(cursor) => page = page.next(cursor),
+                None => return Ok(transactions),
+            }
+        }
+    }
+
+    /// Get one page of an address's transactions, and the cursor of the next page if there is one
+    pub async fn get_history_page(&self, address: &str, page: PageQuery) -> Result<(Vec<AddressTxData>, Option<String>), Box<dyn std::error::Error>> {
+        let message = MinerMessage::GetHistory {
+            address: address.to_string(),
+            page,
+        };
+
+        let response = self.send_message(message).await?;
+
+        match response {
+            ValidatorMessage::History { transactions, next_cursor, .. } => Ok((transactions, next_cursor)),
+            ValidatorMessage::Error { message } => Err(message.into()),
+            _ => Err("Unexpected response".into()),
+        }
+    }
+
+    /// Get up to `max` block headers starting at height `from`
+    pub async fn get_headers(&self, from: u32, max: u32) -> Result<Vec<BlockHeaderData>, Box<dyn std::error::Error>> {
+        let message = MinerMessage::GetHeaders { from, max };
+
+        let response = self.send_message(message).await?;
+
+        match response {
+            ValidatorMessage::Headers { headers } => Ok(headers),
+            ValidatorMessage::Error { message } => Err(message.into()),
+            _ => Err("Unexpected response".into()),
+        }
+    }
+
+    /// Get a merkle proof that a transaction is in the block at `height`
+    pub async fn get_transaction_proof(&self, height: u32, tx_hash: &[u8]) -> Result<MerkleProof, Box<dyn std::error::Error>> {
+        let message = MinerMessage::GetTransactionProof {
+            height,
+            tx_hash: hex::encode(tx_hash),
+        };
+
+        let response = self.send_message(message).await?;
+
+        match response {
+            ValidatorMessage::TransactionProof { proof, .. } => Ok(proof.to_proof()?),
+            ValidatorMessage::Error { message } => Err(message.into()),
+            _ => Err("Unexpected response".into()),
+        }
+    }
+
+    /// Get a miner's statistics (this miner's if `miner_id` is None)
+    pub async fn get_miner_stats(&self, miner_id: Option<&str>) -> Result<MinerStatsData, Box<dyn std::error::Error>> {
+        let message = MinerMessage::GetMinerStats {
+            miner_id: miner_id.unwrap_or(&self.miner_id).to_string(),
+        };
+
+        let response = self.send_message(message).await?;
+
+        match response {
+            ValidatorMessage::MinerStats(stats) => Ok(stats),
+            ValidatorMessage::Error { message } => Err(message.into()),
+            _ => Err("Unexpected response".into()),
+        }
+    }
+
+    /// Get the top `limit` miners by blocks accepted
+    pub async fn get_leaderboard(&self, limit: u32) -> Result<Vec<MinerStatsData>, Box<dyn std::error::Error>> {
+        let message = MinerMessage::GetLeaderboard { limit };
+
+        let response = self.send_message(message).await?;
+
+        match response {
+            ValidatorMessage::Leaderboard { miners } => Ok(miners),
+            ValidatorMessage::Error { message } => Err(message.into()),
+            _ => Err("Unexpected response".into()),
+        }
+    }
+
+    /// Check that a receipt is signed by the pinned validator key and names this miner
+    ///
+    /// A receipt carries its signer's key, so without a pinned key anyone
+    /// could sign one; it fails rather than trusting that key.
+    pub fn check_receipt(&self, receipt: &ReceiptData) -> Result<(), String> {
+        let public_key = self.receipt_public_key.as_deref()
+            .ok_or("No validator receipt key is pinned to check receipts against")?;
+        let receipt = receipt.to_receipt()?;
+        if receipt.miner_id != self.miner_id {
+            return Err(format!("Receipt is for miner '{}'", receipt.miner_id));
+        }
+        verify_receipt(&receipt, Some(public_key))
+    }
+
+    /// Timestamp of the block at `index`, checking it is the block hashing to `hash`
+    async fn block_timestamp(&self, index: u32, hash: &BlockHash) -> Result<u128, Box<dyn std::error::Error>> {
+        let header = self.get_headers(index, 1).await?.into_iter().next()
+            .ok_or_else(|| format!("Validator has no block at height {}", index))?
+            .to_header()?;
+        if header.hash() != *hash {
+            return Err(format!("Block at height {} is not {}", index, hash).into());
+        }
+        Ok(header.timestamp)
+    }
+
+    /// Check a mined block against the validator's current tip and round
+    ///
+    /// Catches blocks the validator would reject anyway, because another block
+    /// won while we mined or the timestamp aged out of its window, before they
+    /// use up this round's attempt.
+    pub async fn validate_locally(&self, block: &Block) -> Result<(), Box<dyn std::error::Error>> {
+        let (tip, _, difficulty) = self.get_chain_tip().await?;
+        let round = self.get_round_info().await?;
+        let remaining_ms = u128::from(round.challenge_seconds_remaining) * 1000;
+        let tonce = TonceChallenge::for_round(&tip, round.tonce.unwrap_or(1), remaining_ms, self.validator_now());
+
+        let mut time_sync = TimeSync::new_with_tolerance(self.timestamp_window.tolerance_ms);
+        time_sync.max_age_ms = self.timestamp_window.max_age_ms;
+        time_sync.set_time_source(Arc::new(ValidatorClock(self.skew.clone())));
+
+        block.validate_against(&tip, difficulty, &tonce, &time_sync)?;
+        Ok(())
+    }
+
     /// Mine and submit a block
     pub async fn mine_and_submit(
         &self,
-        prev_hash: Vec<u8>,
+        prev_hash: BlockHash,
         index: u32,
-        difficulty: u128,
+        difficulty: Difficulty,
         reward_address: &str,
     ) -> Result<ValidatorMessage, Box<dyn std::error::Error>> {
-        println!("Mining block #{}...", index);
+        let span = info_span!("block_submission", miner_id = %self.miner_id, index);
+        self.mine_and_submit_inner(prev_hash, index, difficulty, reward_address)
+            .instrument(span)
+            .await
+    }
+
+    async fn mine_and_submit_inner(
+        &self,
+        prev_hash: BlockHash,
+        index: u32,
+        difficulty: Difficulty,
+        reward_address: &str,
+    ) -> Result<ValidatorMessage, Box<dyn std::error::Error>> {
+        info!("mining block");
 
         // Get round info to know the tonce
         let round_info = self.get_round_info().await?;
 
+        // the VRF proof must be for the round after our tip, which needs the tip's timestamp
+        let prev_timestamp = match (&round_info.vrf_proof, index.checked_sub(1)) {
+            (Some(_), Some(prev_index)) => Some(self.block_timestamp(prev_index, &prev_hash).await?),
+            _ => None,
+        };
+        match round_info.verify_vrf_tonce(self.vrf_public_key.as_deref(), &prev_hash, prev_timestamp) {
+            Ok(true) => info!("round VRF proof verified"),
+            Ok(false) if self.vrf_public_key.is_some() => {
+                return Err("Validator did not publish a VRF proof for this round".into());
+            }
+            Ok(false) => {}
+            Err(e) => return Err(format!("Invalid round challenge: {}", e).into()),
+        }
+
         if let Some(tonce) = round_info.tonce {
-            println!("  Tonce: {}", tonce);
-            println!("  Challenge time remaining: {} seconds", round_info.challenge_seconds_remaining);
+            info!(tonce, challenge_seconds_remaining = round_info.challenge_seconds_remaining,
+                "round challenge");
 
-            // Find valid timestamp
-            let start_time = now();
-            let valid_timestamp = find_valid_timestamp(tonce, start_time, 100000)
+            // Find valid timestamp, on the validator's clock
+            let start_time = self.validator_now();
+            if let Some(skew_ms) = self.validator_clock_skew_ms() {
+                info!(skew_ms, "adjusting for validator clock skew");
+            }
+            // Aim for when the block should be ready, going by the work it needs and our last hashrate
+            let expected_mining_ms = estimate_mining_time(difficulty, self.hashrate()).mean()
+                .map_or(0, |mean| mean.as_millis());
+            let candidates = TimestampCandidates::search(tonce, start_time, TIMESTAMP_SEARCH_SPAN_MS,
+                MAX_TIMESTAMP_CANDIDATES, self.timestamp_window);
+            let valid_timestamp = candidates.best_for(start_time + expected_mining_ms)
                 .ok_or("Failed to find valid timestamp")?;
 
-            println!("  Found valid timestamp: {}", valid_timestamp);
+            info!(timestamp = %valid_timestamp, candidates = candidates.timestamps().len(), expected_mining_ms,
+                "picked valid timestamp");
 
-            // Create coinbase transaction
-            let coinbase = transaction::Transaction {
-                inputs: vec![],
-                outputs: vec![transaction::Output {
-                    to_addr: reward_address.to_owned(),
-                    value: 2.0,
-                    timestamp: valid_timestamp,
-                }],
+            // Bind ourselves to this timestamp before the validator sees the block
+            let reveal = if round_info.commit_reveal {
+                let (salt, reveal_after) = self.commit_timestamp(valid_timestamp).await?;
+                info!(reveal_after_ms = reveal_after.as_millis() as u64, "timestamp commitment accepted");
+                Some((hex::encode(salt), Instant::now() + reveal_after))
+            } else {
+                None
             };
 
-            // Create and mine block
-            let mut block = Block::new(index, valid_timestamp, prev_hash, vec![coinbase]);
-            block.mine(difficulty);
-
-            println!("  ✓ Block mined! Hash: {}", hex::encode(&block.hash[..8]));
-            println!("  Nonce: {}", block.nonce);
-
-            // Submit block
-            println!("  Submitting to validator...");
-
-            let mut stream = self.connect().await?;
+            // Create coinbase transaction
+            let coinbase = TransactionBuilder::new(valid_timestamp)
+                .pay_to(reward_address, 2.0)
+                .build_coinbase()?;
 
-            let message = MinerMessage::SubmitBlock {
-                miner_id: self.miner_id.clone(),
-                block: BlockData::from_block(&block),
+            // Create and mine block, off the async runtime
+            let mut block = Block::new(index, valid_timestamp, prev_hash, vec![coinbase]);
+            block.header.utxo_commitment = match &round_info.utxo_commitment {
+                Some(commitment) => Some(commitment.parse::<BlockHash>()
+                    .map_err(|e| format!("Invalid utxo_commitment: {}", e))?),
+                None => None,
+            };
+            let signature = match &round_info.round_signature {
+                Some(signature) => Some(hex::decode(signature)
+                    .map_err(|e| format!("Invalid round_signature: {}", e))?),
+                None => None,
+            };
+            block.header.round = Some(RoundCommitment { round_start: round_info.round_start, tonce, signature });
+            block.header.bits = difficulty;
+            let last_progress = self.last_progress.clone();
+            let mut mining = MiningHandle::spawn(block, difficulty, self.mining_threads, PROGRESS_INTERVAL, move |progress| {
+                *last_progress.lock().unwrap() = Some(progress);
+                info!(hashes = progress.hashes, elapsed_secs = progress.elapsed.as_secs(),
+                    hashrate = progress.hashrate() as u64, "mining progress");
+            });
+            let block = mining.wait().await?.ok_or("Exhausted the nonce space")?;
+
+            info!(hash = %hex::encode(&block.hash.as_bytes()[..8]), nonce = block.header.nonce.0, threads = self.mining_threads,
+                hashrate = self.hashrate() as u64, "block mined");
+
+            // Mining took a while; don't spend the round's attempt on a block the validator would turn away
+            self.validate_locally(&block).await
+                .map_err(|e| format!("Block failed local validation, not submitting: {}", e))?;
+
+            // The validator turns away reveals that come too soon after the commitment
+            let salt = match reveal {
+                Some((salt, reveal_at)) => {
+                    let wait = reveal_at.saturating_duration_since(Instant::now());
+                    if !wait.is_zero() {
+                        info!(wait_ms = wait.as_millis() as u64, "waiting to reveal the commitment");
+                        tokio::time::sleep(wait).await;
+                    }
+                    Some(salt)
+                }
+                None => None,
             };
 
-            let response = self.send_message(&mut stream, message).await?;
-            Ok(response)
+            // Submit block
+            info!("submitting to validator");
+            self.submit_block(&block, salt, round_info.round_id.clone()).await
         } else {
             Err("No tonce available".into())
         }
     }
 
     /// Start continuous mining (mine until lockout, wait, repeat)
-    pub async fn start_mining(
-        &self,
-        initial_prev_hash: Vec<u8>,
-        initial_index: u32,
-        difficulty: u128,
-        reward_address: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut prev_hash = initial_prev_hash;
-        let mut index = initial_index;
-
+    ///
+    /// Each attempt builds on the tip the validator reports when it starts, so
+    /// blocks accepted from other miners are extended rather than competed with.
+    pub async fn start_mining(&self, reward_address: &str) -> Result<(), Box<dyn std::error::Error>> {
         loop {
+            let (prev_hash, index, difficulty) = self.get_chain_tip().await?;
+            let round_span = info_span!("round", index);
+
+            // Another submission this round would only be turned away
+            let round = self.get_round_info().instrument(round_span.clone()).await?;
+            if round.remaining_attempts == Some(0) {
+                round_span.in_scope(|| info!(challenge_seconds_remaining = round.challenge_seconds_remaining,
+                    "no attempts left this round, waiting for the next"));
+                self.wait_for_next_round(round).instrument(round_span.clone()).await?;
+                continue;
+            }
+
             // Check if we're in lockout
-            let (is_locked, seconds_remaining) = self.check_lockout().await?;
+            let (is_locked, seconds_remaining) = self.check_lockout().instrument(round_span.clone()).await?;
 
             if is_locked {
-                println!("\n⏳ In lockout period. Waiting {} seconds...\n", seconds_remaining);
+                round_span.in_scope(|| info!(seconds_remaining, "in lockout period, waiting"));
                 tokio::time::sleep(tokio::time::Duration::from_secs(seconds_remaining + 1)).await;
                 continue;
             }
 
             // Mine and submit
-            match self.mine_and_submit(prev_hash.clone(), index, difficulty, reward_address).await {
-                Ok(ValidatorMessage::BlockResult { result, message }) => {
+            let outcome = self.mine_and_submit(prev_hash, index, difficulty, reward_address)
+                .instrument(round_span.clone())
+                .await;
+
+            match outcome {
+                Ok(ValidatorMessage::BlockResult { result, message, receipt }) => {
                     match result {
                         BlockResultType::Accepted => {
-                            println!("  ✓ {}", message);
-                            // For demonstration, increment index (in real scenario, get from validator)
-                            index += 1;
-                            // Note: In production, we'd query the validator for the latest block hash
+                            round_span.in_scope(|| info!(%message, "block accepted"));
+                            if let Some(receipt) = receipt.filter(|_| self.receipt_public_key.is_some()) {
+                                match self.check_receipt(&receipt) {
+                                    Ok(()) => round_span.in_scope(|| info!(height = receipt.height,
+                                        validator = %receipt.validator_public_key, "signed receipt verified")),
+                                    Err(e) => round_span.in_scope(|| warn!(error = %e, "receipt failed verification")),
+                                }
+                            }
+                        }
+                        BlockResultType::RejectedInvalidTimestamp => {
+                            // The next round info exchange refines the skew estimate before we retry
+                            round_span.in_scope(|| warn!(%message, skew_ms = ?self.validator_clock_skew_ms(),
+                                "block rejected for its timestamp; re-measuring validator clock skew"));
+                            if let Err(e) = self.get_round_info().instrument(round_span.clone()).await {
+                                round_span.in_scope(|| warn!(error = %e, "round info request failed"));
+                            }
+                        }
+                        BlockResultType::RejectedMinerAlreadyAttempted => {
+                            // The next pass waits out the round
+                            round_span.in_scope(|| warn!(%message, "block rejected, already attempted this round"));
                         }
                         _ => {
-                            println!("  ✗ Block rejected: {}", message);
+                            round_span.in_scope(|| warn!(%message, "block rejected"));
                             // Wait a bit before retrying
                             tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
                         }
                     }
                 }
                 Ok(msg) => {
-                    println!("  Unexpected response: {:?}", msg);
+                    round_span.in_scope(|| warn!(response = ?msg, "unexpected response"));
                 }
                 Err(e) => {
-                    eprintln!("  Error: {}", e);
+                    round_span.in_scope(|| error!(error = %e, "mining attempt failed"));
                     tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
                 }
             }
-
-            println!();
         }
     }
 }
@@ -211,5 +715,37 @@ mod tests {
         let client = MinerClient::new("test_miner".to_string(), "127.0.0.1:8080".to_string());
         assert_eq!(client.miner_id, "test_miner");
         assert_eq!(client.validator_address, "127.0.0.1:8080");
+        assert_eq!(client.hashrate(), 0.0);
+        assert!(client.last_mining_progress().is_none());
+        assert_eq!(client.validator_clock_skew_ms(), None);
+    }
+
+    #[test]
+    fn test_check_receipt_needs_pinned_key() {
+        use crate::node_key::NodeKey;
+        use crate::receipt::BlockReceipt;
+
+        let validator_key = NodeKey::generate();
+        let receipt = ReceiptData::from_receipt(&BlockReceipt::sign(&validator_key, &BlockHash::ZERO, 0, "test_miner", 1));
+        let mut client = MinerClient::new("test_miner".to_string(), "127.0.0.1:8080".to_string());
+        assert!(client.check_receipt(&receipt).is_err());
+
+        client.set_receipt_public_key(NodeKey::generate().public_key());
+        assert!(client.check_receipt(&receipt).is_err());
+        client.set_receipt_public_key(validator_key.public_key());
+        assert_eq!(client.check_receipt(&receipt), Ok(()));
+    }
+
+    #[test]
+    fn test_round_poll_delay() {
+        let mut info: RoundInfoData = serde_json::from_str(
+            r#"{"round_start":0,"tonce":7,"challenge_seconds_remaining":12,"attempted_miners":1,"active_lockouts":0,"difficulty":285278207}"#,
+        ).unwrap();
+        assert_eq!(round_poll_delay(&info), Duration::from_secs(13));
+
+        info.challenge_seconds_remaining = 45;
+        assert_eq!(round_poll_delay(&info), ROUND_POLL_INTERVAL);
+        info.challenge_seconds_remaining = 0;
+        assert_eq!(round_poll_delay(&info), ROUND_POLL_INTERVAL);
     }
 }
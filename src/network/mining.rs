@@ -0,0 +1,95 @@
+//! Mining off the async runtime
+//!
+//! Hashing is CPU-bound and can run for minutes, so it must not run on a tokio
+//! worker thread. `MiningHandle` moves it onto the blocking thread pool and
+//! gives the async side a way to await the result or call the search off.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::Span;
+use crate::{Block, Difficulty, MiningProgress};
+
+/// A block being mined on the blocking thread pool
+///
+/// Dropping the handle cancels the search, so abandoning a round (for example
+/// in a `tokio::select!`) doesn't leave threads hashing in the background.
+pub struct MiningHandle {
+    cancel: Arc<AtomicBool>,
+    task: JoinHandle<Option<Block>>,
+}
+
+impl MiningHandle {
+    /// Start mining `block` with `num_threads` threads, calling `on_progress` every `interval`
+    pub fn spawn<F>(mut block: Block, difficulty: Difficulty, num_threads: usize, interval: Duration, on_progress: F) -> Self
+    where
+        F: FnMut(MiningProgress) + Send + 'static,
+    {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let task_cancel = cancel.clone();
+        // keep the caller's span so progress logs stay attached to the round
+        let span = Span::current();
+
+        let task = tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            let mut on_progress = on_progress;
+            // a full nonce space without a hit moves on to the coinbase's next extra-nonce
+            while block.mine_cancellable(difficulty, num_threads, interval, &task_cancel, &mut on_progress).is_none() {
+                if task_cancel.load(Ordering::Relaxed) || !block.roll_extra_nonce() {
+                    return None;
+                }
+            }
+            Some(block)
+        });
+
+        MiningHandle { cancel, task }
+    }
+
+    /// Ask the mining threads to stop; `wait` then returns `None`
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    /// Wait for mining to finish
+    ///
+    /// Returns the mined block, or `None` if mining was cancelled or the nonce and extra-nonce space ran out.
+    pub async fn wait(&mut self) -> Result<Option<Block>, String> {
+        (&mut self.task).await.map_err(|e| format!("Mining task failed: {}", e))
+    }
+}
+
+impl Drop for MiningHandle {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::check_blockhash;
+    use crate::hashable::Hashable;
+    use crate::BlockHash;
+
+    #[tokio::test]
+    async fn test_mining_handle() {
+        let block = Block::new(0, 1000, BlockHash::ZERO, vec![]);
+        let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+        let mut handle = MiningHandle::spawn(block, difficulty, 2, Duration::MAX, |_| {});
+        let mined = handle.wait().await.unwrap().unwrap();
+        assert!(check_blockhash(&mined.hash(), difficulty));
+
+        // An impossible difficulty only ends when cancelled
+        let block = Block::new(0, 1000, BlockHash::ZERO, vec![]);
+        let mut handle = MiningHandle::spawn(block, Difficulty::from_target(0), 2, Duration::MAX, |_| {});
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        handle.cancel();
+        assert!(handle.is_cancelled());
+        assert!(handle.wait().await.unwrap().is_none());
+    }
+}
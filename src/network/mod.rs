@@ -1,9 +1,65 @@
-/// Network module for distributed Hourcoin mining
+//! Network module for distributed Hourcoin mining
+//!
+//! Without the `node` feature only the wire types in `protocol` are built.
 
+#[cfg(feature = "node")]
+pub mod actor;
+#[cfg(feature = "node")]
+pub mod admin;
+#[cfg(feature = "node")]
+pub mod clock_skew;
+pub mod compression;
+#[cfg(feature = "node")]
+pub mod connection;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "node")]
+pub mod idempotency;
+#[cfg(feature = "noise")]
+pub mod noise;
+#[cfg(feature = "node")]
+pub mod peers;
+#[cfg(feature = "node")]
+pub mod pubsub;
 pub mod protocol;
+#[cfg(feature = "node")]
+pub mod rate_limit;
+#[cfg(feature = "node")]
+pub mod rest;
+#[cfg(feature = "node")]
+pub mod retry;
+#[cfg(feature = "node")]
+pub mod stream;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "quic")]
+pub mod quic;
+#[cfg(feature = "node")]
+pub mod transport;
+#[cfg(feature = "node")]
 pub mod validator_server;
+#[cfg(feature = "node")]
+pub mod webhook;
+#[cfg(feature = "node")]
 pub mod miner_client;
+#[cfg(feature = "node")]
+pub mod mining;
 
 pub use protocol::*;
-pub use validator_server::ValidatorServer;
+#[cfg(feature = "node")]
+pub use actor::ValidatorHandle;
+pub use compression::Compression;
+#[cfg(feature = "node")]
+pub use compression::FrameCompression;
+#[cfg(feature = "node")]
+pub use connection::Heartbeat;
+#[cfg(feature = "node")]
+pub use peers::PeerBook;
+#[cfg(feature = "node")]
+pub use retry::{RetryPolicy, Timeouts};
+#[cfg(feature = "node")]
+pub use validator_server::{ServerHandle, TimeResyncConfig, ValidatorServer};
+#[cfg(feature = "node")]
 pub use miner_client::MinerClient;
+#[cfg(feature = "node")]
+pub use mining::MiningHandle;
@@ -0,0 +1,430 @@
+//! Noise-encrypted miner connections over TCP (feature `noise`)
+//!
+//! A lighter alternative to TLS: no certificates, just a static X25519 key
+//! on each side. Connections open with a `Noise_XX` handshake, after which
+//! both ends know each other's static public key and every byte is
+//! encrypted. Miners pin the validator's key; a validator may restrict
+//! miners to an allowlist of keys. Keys live in small hex files next to the
+//! other identity files (`validator.noise.key`, `<miner_id>.noise.key`).
+//!
+//! On the wire each Noise message is a 2-byte big-endian length followed by
+//! the message. After the handshake a pump task turns the encrypted messages
+//! back into a plain byte stream for the framing layer.
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use snow::params::DHChoice;
+use snow::resolvers::{CryptoResolver, DefaultResolver};
+use snow::{HandshakeState, TransportState};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+use super::stream::{BoxFuture, BoxedStream, Dialer, Listener};
+
+/// Handshake pattern, DH function, cipher and hash
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+/// Largest Noise message
+const MAX_MESSAGE_LEN: usize = 65535;
+
+/// Authentication tag added to every encrypted message
+const TAG_LEN: usize = 16;
+
+/// Largest plaintext carried in one message
+const MAX_PLAINTEXT_LEN: usize = MAX_MESSAGE_LEN - TAG_LEN;
+
+/// How long a client has to finish the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handshaken streams waiting for `accept`
+const ACCEPT_QUEUE: usize = 64;
+
+/// A static X25519 key identifying a miner or validator
+#[derive(Clone)]
+pub struct NoiseKey {
+    secret: [u8; 32],
+    public: [u8; 32],
+}
+
+impl NoiseKey {
+    /// Generate a fresh random key
+    pub fn generate() -> Self {
+        let mut secret = [0u8; 32];
+        rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut secret);
+        Self::from_secret(secret)
+    }
+
+    /// Load a key from its 32-byte secret
+    pub fn from_secret_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let secret = <[u8; 32]>::try_from(bytes)
+            .map_err(|_| format!("Noise secret key must be 32 bytes, got {}", bytes.len()))?;
+        Ok(Self::from_secret(secret))
+    }
+
+    fn from_secret(secret: [u8; 32]) -> Self {
+        let mut dh = DefaultResolver.resolve_dh(&DHChoice::Curve25519).expect("X25519 is built in");
+        dh.set(&secret);
+        let mut public = [0u8; 32];
+        public.copy_from_slice(dh.pubkey());
+        NoiseKey { secret, public }
+    }
+
+    /// The 32-byte secret (keep private)
+    pub fn secret_bytes(&self) -> [u8; 32] {
+        self.secret
+    }
+
+    /// The 32-byte public key peers pin or allow
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public
+    }
+
+    /// Read a key saved with `save`
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let bytes = hex::decode(text.trim()).map_err(|e| format!("Invalid key hex in {}: {}", path.display(), e))?;
+        Self::from_secret_bytes(&bytes)
+    }
+
+    /// Load the key at `path`, or generate one and save it there
+    pub fn load_or_generate(path: &Path) -> Result<Self, String> {
+        if path.exists() {
+            return Self::load(path);
+        }
+        let key = Self::generate();
+        key.save(path)?;
+        Ok(key)
+    }
+
+    /// Write the secret to `path` as hex
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        std::fs::write(path, hex::encode(self.secret))
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+}
+
+/// Parse a hex public key, as printed by validators and miners
+pub fn parse_public_key(hex_key: &str) -> Result<[u8; 32], String> {
+    let bytes = hex::decode(hex_key.trim()).map_err(|e| format!("Invalid public key hex: {}", e))?;
+    <[u8; 32]>::try_from(bytes.as_slice())
+        .map_err(|_| format!("Noise public key must be 32 bytes, got {}", bytes.len()))
+}
+
+/// Read an allowlist file: one hex public key per line, `#` comments allowed
+pub fn load_allowlist(path: &Path) -> Result<HashSet<[u8; 32]>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(parse_public_key)
+        .collect()
+}
+
+fn noise_error(error: snow::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Noise: {}", error))
+}
+
+fn refused(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, message)
+}
+
+fn builder() -> snow::Builder<'static> {
+    snow::Builder::new(NOISE_PARAMS.parse().expect("valid Noise parameters"))
+}
+
+async fn send_message<W: AsyncWrite + Unpin>(writer: &mut W, message: &[u8]) -> io::Result<()> {
+    writer.write_all(&(message.len() as u16).to_be_bytes()).await?;
+    writer.write_all(message).await?;
+    writer.flush().await
+}
+
+/// The next message, or `None` if the stream closed cleanly between messages
+async fn recv_message<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 2];
+    match reader.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut message = vec![0u8; u16::from_be_bytes(len) as usize];
+    reader.read_exact(&mut message).await?;
+    Ok(Some(message))
+}
+
+async fn expect_message(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    recv_message(stream).await?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed during the Noise handshake"))
+}
+
+/// Run one handshake step that sends a message
+async fn write_step(stream: &mut TcpStream, handshake: &mut HandshakeState) -> io::Result<()> {
+    let mut message = vec![0u8; MAX_MESSAGE_LEN];
+    let len = handshake.write_message(&[], &mut message).map_err(noise_error)?;
+    send_message(stream, &message[..len]).await
+}
+
+/// Run one handshake step that receives a message
+async fn read_step(stream: &mut TcpStream, handshake: &mut HandshakeState) -> io::Result<()> {
+    let message = expect_message(stream).await?;
+    let mut payload = vec![0u8; MAX_MESSAGE_LEN];
+    handshake.read_message(&message, &mut payload).map_err(noise_error)?;
+    Ok(())
+}
+
+fn remote_key(handshake: &HandshakeState) -> io::Result<[u8; 32]> {
+    handshake.get_remote_static()
+        .and_then(|key| <[u8; 32]>::try_from(key).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Noise handshake ended without a remote key"))
+}
+
+/// Run the `XX` handshake as the miner, checking the validator's key
+async fn initiate(stream: &mut TcpStream, key: &NoiseKey, validator_key: &[u8; 32]) -> io::Result<TransportState> {
+    let mut handshake = builder().local_private_key(&key.secret).build_initiator().map_err(noise_error)?;
+    write_step(stream, &mut handshake).await?; // -> e
+    read_step(stream, &mut handshake).await?; // <- e, ee, s, es
+    if remote_key(&handshake)? != *validator_key {
+        return Err(refused("Validator presented an unexpected Noise key".to_string()));
+    }
+    write_step(stream, &mut handshake).await?; // -> s, se
+    handshake.into_transport_mode().map_err(noise_error)
+}
+
+/// Run the `XX` handshake as the validator, returning the miner's key
+async fn respond(stream: &mut TcpStream, key: &NoiseKey) -> io::Result<(TransportState, [u8; 32])> {
+    let mut handshake = builder().local_private_key(&key.secret).build_responder().map_err(noise_error)?;
+    read_step(stream, &mut handshake).await?;
+    write_step(stream, &mut handshake).await?;
+    read_step(stream, &mut handshake).await?;
+    let miner_key = remote_key(&handshake)?;
+    Ok((handshake.into_transport_mode().map_err(noise_error)?, miner_key))
+}
+
+/// Bridge an encrypted TCP stream to a plain one, until either side closes
+fn spawn_pump(stream: TcpStream, transport: TransportState) -> BoxedStream {
+    let (local, remote) = tokio::io::duplex(2 * MAX_MESSAGE_LEN);
+    let (mut plain_reader, mut plain_writer) = tokio::io::split(remote);
+    let (mut net_reader, mut net_writer) = stream.into_split();
+    let transport = Arc::new(Mutex::new(transport));
+
+    let sealing = Arc::clone(&transport);
+    tokio::spawn(async move {
+        let mut plain = vec![0u8; MAX_PLAINTEXT_LEN];
+        let mut sealed = vec![0u8; MAX_MESSAGE_LEN];
+        loop {
+            let n = match plain_reader.read(&mut plain).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            let len = match sealing.lock().unwrap().write_message(&plain[..n], &mut sealed) {
+                Ok(len) => len,
+                Err(e) => {
+                    debug!(error = %e, "Noise encryption failed");
+                    break;
+                }
+            };
+            if send_message(&mut net_writer, &sealed[..len]).await.is_err() {
+                break;
+            }
+        }
+        let _ = net_writer.shutdown().await;
+    });
+
+    tokio::spawn(async move {
+        let mut plain = vec![0u8; MAX_MESSAGE_LEN];
+        while let Ok(Some(sealed)) = recv_message(&mut net_reader).await {
+            let len = match transport.lock().unwrap().read_message(&sealed, &mut plain) {
+                Ok(len) => len,
+                Err(e) => {
+                    debug!(error = %e, "Noise message failed to decrypt");
+                    break;
+                }
+            };
+            if plain_writer.write_all(&plain[..len]).await.is_err() {
+                break;
+            }
+        }
+        let _ = plain_writer.shutdown().await;
+    });
+
+    Box::new(local)
+}
+
+/// Noise over TCP, accepting only the validator with the pinned key
+#[derive(Clone)]
+pub struct NoiseDialer {
+    key: NoiseKey,
+    validator_key: [u8; 32],
+}
+
+impl NoiseDialer {
+    pub fn new(key: NoiseKey, validator_key: [u8; 32]) -> Self {
+        NoiseDialer { key, validator_key }
+    }
+}
+
+impl Dialer for NoiseDialer {
+    fn dial<'a>(&'a self, address: &'a str) -> BoxFuture<'a, io::Result<BoxedStream>> {
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(address).await?;
+            stream.set_nodelay(true)?;
+            let transport = initiate(&mut stream, &self.key, &self.validator_key).await?;
+            Ok(spawn_pump(stream, transport))
+        })
+    }
+}
+
+/// Accepts Noise over TCP, optionally only from allowlisted miner keys
+pub struct NoiseListener {
+    local_addr: SocketAddr,
+    streams: mpsc::Receiver<(BoxedStream, SocketAddr)>,
+    task: JoinHandle<()>,
+}
+
+impl NoiseListener {
+    /// Listen on `address` as `key`; `allowed` limits which miner keys may connect
+    pub async fn bind(address: &str, key: NoiseKey, allowed: Option<HashSet<[u8; 32]>>) -> io::Result<Self> {
+        let tcp = TcpListener::bind(address).await?;
+        let local_addr = tcp.local_addr()?;
+        let (ready, streams) = mpsc::channel(ACCEPT_QUEUE);
+        let key = Arc::new(key);
+        let allowed = Arc::new(allowed);
+
+        let task = tokio::spawn(async move {
+            loop {
+                let (mut socket, peer) = match tcp.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        debug!(error = %e, "Noise accept failed");
+                        continue;
+                    }
+                };
+                let key = Arc::clone(&key);
+                let allowed = Arc::clone(&allowed);
+                let ready = ready.clone();
+                tokio::spawn(async move {
+                    let (transport, miner_key) = match tokio::time::timeout(HANDSHAKE_TIMEOUT, respond(&mut socket, &key)).await {
+                        Ok(Ok(handshaken)) => handshaken,
+                        Ok(Err(e)) => {
+                            debug!(%peer, error = %e, "Noise handshake failed");
+                            return;
+                        }
+                        Err(_) => {
+                            debug!(%peer, "Noise handshake timed out");
+                            return;
+                        }
+                    };
+                    if let Some(allowed) = allowed.as_ref() {
+                        if !allowed.contains(&miner_key) {
+                            info!(%peer, miner_key = %hex::encode(miner_key), "Noise key not on the allowlist; closing");
+                            return;
+                        }
+                    }
+                    debug!(%peer, miner_key = %hex::encode(miner_key), "Noise handshake complete");
+                    let _ = socket.set_nodelay(true);
+                    let _ = ready.send((spawn_pump(socket, transport), peer)).await;
+                });
+            }
+        });
+
+        Ok(NoiseListener { local_addr, streams, task })
+    }
+}
+
+impl Listener for NoiseListener {
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<(BoxedStream, SocketAddr)>> {
+        Box::pin(async move {
+            self.streams.recv().await
+                .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "Noise listener stopped"))
+        })
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+}
+
+impl Drop for NoiseListener {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::transport::{Transport, DEFAULT_MAX_FRAME_LEN};
+
+    async fn echo_listener(validator: &NoiseKey, allowed: Option<HashSet<[u8; 32]>>) -> String {
+        let mut listener = NoiseListener::bind("127.0.0.1:0", validator.clone(), allowed).await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut server = Transport::new(stream, DEFAULT_MAX_FRAME_LEN);
+                    while let Ok(Some(frame)) = server.recv_frame().await {
+                        server.send_frame(frame).await.unwrap();
+                    }
+                });
+            }
+        });
+        address
+    }
+
+    #[tokio::test]
+    async fn test_noise_round_trip() {
+        let validator = NoiseKey::generate();
+        let miner = NoiseKey::generate();
+        let address = echo_listener(&validator, Some(HashSet::from([miner.public_key()]))).await;
+
+        let dialer = NoiseDialer::new(miner, validator.public_key());
+        let mut client = Transport::new(dialer.dial(&address).await.unwrap(), DEFAULT_MAX_FRAME_LEN);
+        // Larger than one Noise message, so it is split and rejoined
+        let big = "x".repeat(3 * MAX_MESSAGE_LEN);
+        let echo: String = client.request(&big).await.unwrap();
+        assert_eq!(echo, big);
+    }
+
+    #[tokio::test]
+    async fn test_wrong_keys_refused() {
+        let validator = NoiseKey::generate();
+        let miner = NoiseKey::generate();
+        let address = echo_listener(&validator, Some(HashSet::new())).await;
+
+        // The miner refuses a validator it didn't pin
+        let impostor = NoiseDialer::new(miner.clone(), NoiseKey::generate().public_key());
+        let error = impostor.dial(&address).await.err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+
+        // The validator drops a miner that isn't allowlisted
+        let dialer = NoiseDialer::new(miner, validator.public_key());
+        let mut client = Transport::new(dialer.dial(&address).await.unwrap(), DEFAULT_MAX_FRAME_LEN);
+        assert!(client.request::<_, String>(&"hello").await.is_err());
+    }
+
+    #[test]
+    fn test_keys_and_allowlist_files() {
+        let dir = std::env::temp_dir();
+        let key_path = dir.join(format!("hourcoin-noise-{}.key", std::process::id()));
+        let list_path = dir.join(format!("hourcoin-noise-{}.allow", std::process::id()));
+
+        let generated = NoiseKey::load_or_generate(&key_path).unwrap();
+        let loaded = NoiseKey::load_or_generate(&key_path).unwrap();
+        assert_eq!(loaded.public_key(), generated.public_key());
+        assert_ne!(generated.public_key(), generated.secret_bytes());
+
+        std::fs::write(&list_path, format!("# miners\n{}  # alice\n\n", hex::encode(generated.public_key()))).unwrap();
+        assert_eq!(load_allowlist(&list_path).unwrap(), HashSet::from([generated.public_key()]));
+
+        std::fs::remove_file(&key_path).unwrap();
+        std::fs::remove_file(&list_path).unwrap();
+        assert!(parse_public_key("abcd").is_err());
+    }
+}
@@ -0,0 +1,226 @@
+//! Peer discovery: the validator addresses a node knows about
+//!
+//! A validator starts from its seed list and asks every address it knows for
+//! more with `PeerMessage::GetPeers`, which also tells the peer where to
+//! reach the asker. What it learns goes into a `PeerBook`. Each entry records
+//! when the address last answered and how many times in a row it has failed.
+//! An address that fails `MAX_PEER_FAILURES` times in a row is evicted, and
+//! when the book is full the address heard from longest ago makes room. The
+//! book is saved as JSON, so a restarted node doesn't need its seeds again.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::atomic_file::atomic_write;
+
+/// How many addresses a book holds by default
+pub const DEFAULT_MAX_PEERS: usize = 256;
+
+/// Consecutive failed contacts before an address is evicted
+pub const MAX_PEER_FAILURES: u32 = 3;
+
+/// Most addresses shared in one `Peers` reply
+pub const MAX_SHARED_PEERS: usize = 32;
+
+/// How often a validator asks its peers for theirs
+pub const DISCOVERY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What a book knows about one address
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerEntry {
+    /// When the address last answered (TAI milliseconds); 0 if it never has
+    pub last_seen: u128,
+    /// Failed contacts since it last answered
+    pub failures: u32,
+}
+
+/// Known peer validator addresses, bounded and persisted between restarts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerBook {
+    peers: HashMap<String, PeerEntry>,
+    #[serde(skip, default = "default_max_peers")]
+    max_peers: usize,
+}
+
+fn default_max_peers() -> usize {
+    DEFAULT_MAX_PEERS
+}
+
+impl Default for PeerBook {
+    fn default() -> Self {
+        PeerBook::new(DEFAULT_MAX_PEERS)
+    }
+}
+
+impl PeerBook {
+    /// An empty book holding at most `max_peers` addresses
+    pub fn new(max_peers: usize) -> Self {
+        PeerBook { peers: HashMap::new(), max_peers: max_peers.max(1) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    pub fn get(&self, address: &str) -> Option<&PeerEntry> {
+        self.peers.get(address)
+    }
+
+    /// Every known address, most recently seen first
+    pub fn addresses(&self) -> Vec<String> {
+        let mut entries: Vec<(&String, &PeerEntry)> = self.peers.iter().collect();
+        entries.sort_by(|a, b| b.1.last_seen.cmp(&a.1.last_seen).then_with(|| a.0.cmp(b.0)));
+        entries.into_iter().map(|(address, _)| address.clone()).collect()
+    }
+
+    /// Up to `limit` addresses worth passing on: those that have answered, most recently seen first
+    pub fn share(&self, limit: usize) -> Vec<String> {
+        self.addresses().into_iter()
+            .filter(|address| self.peers[address].last_seen > 0)
+            .take(limit)
+            .collect()
+    }
+
+    /// Remember `address` if it's new and well formed; returns whether it was added
+    ///
+    /// A full book evicts the address heard from longest ago to make room.
+    pub fn add(&mut self, address: &str) -> bool {
+        if self.peers.contains_key(address) || !is_peer_address(address) {
+            return false;
+        }
+        if self.peers.len() >= self.max_peers {
+            self.evict_stalest();
+        }
+        self.peers.insert(address.to_string(), PeerEntry::default());
+        true
+    }
+
+    /// Record that `address` answered at `now`
+    pub fn mark_seen(&mut self, address: &str, now: u128) {
+        self.add(address);
+        if let Some(entry) = self.peers.get_mut(address) {
+            entry.last_seen = now;
+            entry.failures = 0;
+        }
+    }
+
+    /// Record that `address` couldn't be reached; returns whether it was evicted
+    pub fn mark_failed(&mut self, address: &str) -> bool {
+        let evict = match self.peers.get_mut(address) {
+            Some(entry) => {
+                entry.failures += 1;
+                entry.failures >= MAX_PEER_FAILURES
+            }
+            None => false,
+        };
+        if evict {
+            self.peers.remove(address);
+        }
+        evict
+    }
+
+    fn evict_stalest(&mut self) {
+        let stalest = self.peers.iter()
+            .min_by(|a, b| a.1.last_seen.cmp(&b.1.last_seen).then_with(|| b.1.failures.cmp(&a.1.failures)))
+            .map(|(address, _)| address.clone());
+        if let Some(address) = stalest {
+            self.peers.remove(&address);
+        }
+    }
+
+    /// Write the book to `path` as JSON
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(self)
+            .map_err(|e| format!("Failed to encode peer book: {}", e))?;
+        atomic_write(path, &json)
+    }
+
+    /// Read a book written by `save`, keeping at most `max_peers` addresses
+    pub fn load(path: &Path, max_peers: usize) -> Result<Self, String> {
+        let json = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let saved: PeerBook = serde_json::from_slice(&json)
+            .map_err(|e| format!("Invalid peer book in {}: {}", path.display(), e))?;
+
+        let mut book = PeerBook::new(max_peers);
+        let mut entries: Vec<(String, PeerEntry)> = saved.peers.into_iter()
+            .filter(|(address, _)| is_peer_address(address))
+            .collect();
+        entries.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.last_seen));
+        book.peers.extend(entries.into_iter().take(book.max_peers));
+        Ok(book)
+    }
+}
+
+/// Whether `address` looks like `host:port`, so junk from a peer isn't stored
+pub fn is_peer_address(address: &str) -> bool {
+    match address.rsplit_once(':') {
+        Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok_and(|port| port != 0)
+            && !host.chars().any(char::is_whitespace),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failures_evict() {
+        let mut book = PeerBook::default();
+        assert!(book.add("10.0.0.1:8080"));
+        assert!(!book.add("10.0.0.1:8080"));
+
+        assert!(!book.mark_failed("10.0.0.1:8080"));
+        book.mark_seen("10.0.0.1:8080", 1_000);
+        assert_eq!(book.get("10.0.0.1:8080").unwrap().failures, 0);
+
+        for _ in 1..MAX_PEER_FAILURES {
+            assert!(!book.mark_failed("10.0.0.1:8080"));
+        }
+        assert!(book.mark_failed("10.0.0.1:8080"));
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn test_full_book_evicts_stalest() {
+        let mut book = PeerBook::new(2);
+        book.mark_seen("a.example:8080", 2_000);
+        book.mark_seen("b.example:8080", 1_000);
+        assert!(book.add("c.example:8080"));
+
+        assert_eq!(book.len(), 2);
+        assert!(book.get("b.example:8080").is_none());
+        assert_eq!(book.addresses(), vec!["a.example:8080", "c.example:8080"]);
+        // Only addresses that have answered are passed on
+        assert_eq!(book.share(MAX_SHARED_PEERS), vec!["a.example:8080"]);
+    }
+
+    #[test]
+    fn test_rejects_malformed_addresses() {
+        let mut book = PeerBook::default();
+        for address in ["", "no-port", ":8080", "host:0", "host:99999", "two words:8080"] {
+            assert!(!book.add(address), "{:?} accepted", address);
+        }
+        assert!(book.add("[::1]:8080"));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("hourcoin-peers-{}.json", std::process::id()));
+        let mut book = PeerBook::default();
+        book.mark_seen("a.example:8080", 3_000);
+        book.mark_seen("b.example:8080", 2_000);
+        book.add("c.example:8080");
+        book.save(&path).unwrap();
+
+        let loaded = PeerBook::load(&path, 2).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.addresses(), vec!["a.example:8080", "b.example:8080"]);
+        assert_eq!(loaded.get("a.example:8080").unwrap().last_seen, 3_000);
+    }
+}
@@ -1,20 +1,78 @@
 /// Network protocol definitions for Hourcoin
 ///
-/// Defines the message types exchanged between miners and validators
+/// Defines the message types exchanged between miners and validators.
+/// Blocks, transactions and outputs travel in their canonical encoding
+/// (`Canonical`), the bytes their hashes cover.
 
+use std::collections::BTreeMap;
 use serde::{Deserialize, Serialize};
-use crate::{Block, ValidationResult, RoundInfo};
+use crate::{AddressTx, Block, BlockBody, BlockHash, BlockHeader, Blockchain, Difficulty, Nonce, RoundCommitment, Txid};
+use crate::canonical::Canonical;
+#[cfg(feature = "node")]
+use crate::{ValidationResult, RoundInfo};
+use crate::tonce::TonceChallenge;
+use crate::transaction::{Output, Transaction, Witness};
+use crate::compact_block::CompactBlock;
+use crate::channels::{ChannelParty, ChannelState, ChannelTerms, SignedState, UnilateralClose};
+use crate::consensus::{AcceptanceVote, QuorumCertificate};
+use crate::federation::FederatedReceipt;
+use crate::htlc::HtlcContract;
+use crate::merkle::MerkleProof;
+use crate::miner_stats::MinerStats;
+use crate::multisig::MultisigPolicy;
+use crate::receipt::BlockReceipt;
+use crate::round_history::{RoundHistory, RoundSummary};
+use crate::sntp::SntpSample;
+use crate::utxo_snapshot::UtxoSnapshot;
+use crate::vrf::{round_input, VrfProof};
+use crate::watch::AddressActivity;
+use super::compression::Compression;
+
+/// Most headers a validator returns for one `GetHeaders` request
+pub const MAX_HEADERS_PER_MESSAGE: u32 = 2000;
+
+/// Most blocks a validator returns for one `GetBlocks` request
+pub const MAX_BLOCKS_PER_MESSAGE: u32 = 20;
+
+/// Most transactions or rounds a validator returns for one page of `GetHistory` or `GetRoundHistory`
+pub const MAX_PAGE_SIZE: u32 = 500;
+
+/// Most outputs a validator returns for one `GetUtxoSnapshot` request
+pub const MAX_SNAPSHOT_OUTPUTS_PER_MESSAGE: u32 = 4000;
 
 /// Messages sent from miner to validator
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum MinerMessage {
     /// Miner requests current round information
-    GetRoundInfo { miner_id: String },
+    GetRoundInfo {
+        miner_id: String,
+        /// Miner's clock when the request was sent, echoed back for skew estimation (TAI ms)
+        #[serde(default)]
+        sent_at: Option<u128>,
+    },
+
+    /// Miner commits to the timestamp it will submit this round
+    CommitTimestamp {
+        miner_id: String,
+        commitment: String, // Hex encoded
+        /// Client-chosen ID; a retry with the same ID gets the first answer
+        #[serde(default)]
+        request_id: Option<String>,
+    },
 
     /// Miner submits a block for validation
     SubmitBlock {
         miner_id: String,
-        block: BlockData,
+        block: Box<Canonical<Block>>,
+        /// Hex salt opening the miner's timestamp commitment, if one was made
+        #[serde(default)]
+        salt: Option<String>,
+        /// The `round_id` of the round the block was mined for (hex); required
+        #[serde(default)]
+        round_id: Option<String>,
+        /// Client-chosen ID; a retry with the same ID gets the first answer
+        #[serde(default)]
+        request_id: Option<String>,
     },
 
     /// Miner checks their lockout status
@@ -22,6 +80,100 @@ pub enum MinerMessage {
 
     /// Miner requests blockchain info
     GetBlockchainInfo,
+
+    /// Request the block the next block must build on
+    GetChainTip,
+
+    /// Request the unspent balance of an address
+    GetBalance { address: String },
+
+    /// Request a page of the transactions touching an address
+    GetHistory {
+        address: String,
+        #[serde(default)]
+        page: PageQuery,
+    },
+
+    /// Request a page of the recent rounds, optionally only those `miner` took part in
+    GetRoundHistory {
+        #[serde(default)]
+        page: PageQuery,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        miner: Option<String>,
+    },
+
+    /// Request up to `max` block headers starting at height `from` (for light clients)
+    GetHeaders { from: u32, max: u32 },
+
+    /// Request a merkle proof that a transaction is in the block at `height`
+    GetTransactionProof {
+        height: u32,
+        tx_hash: String, // Hex encoded
+    },
+
+    /// Request a miner's statistics
+    GetMinerStats { miner_id: String },
+
+    /// Request the top `limit` miners by blocks accepted
+    GetLeaderboard { limit: u32 },
+
+    /// Heartbeat; the validator answers `Pong`
+    Ping {
+        /// Miner's clock when the ping was sent, echoed back for skew estimation (TAI ms)
+        #[serde(default)]
+        sent_at: Option<u128>,
+    },
+
+    /// First message on a connection: the frame compression the miner accepts, best first
+    Hello { compression: Vec<Compression> },
+
+    /// Push an `AddressActivity` on this connection whenever a transaction touching
+    /// `address` enters the mempool or is confirmed in a block
+    WatchAddress { address: String },
+
+    /// Ask the validator's faucet to pay `address` (test networks only)
+    RequestFaucet {
+        address: String,
+        /// Client-chosen ID; a retry with the same ID gets the first answer
+        #[serde(default)]
+        request_id: Option<String>,
+    },
+}
+
+impl MinerMessage {
+    /// The miner making the request, for messages that name one
+    pub fn miner_id(&self) -> Option<&str> {
+        match self {
+            MinerMessage::GetRoundInfo { miner_id, .. }
+            | MinerMessage::CommitTimestamp { miner_id, .. }
+            | MinerMessage::SubmitBlock { miner_id, .. }
+            | MinerMessage::CheckLockout { miner_id } => Some(miner_id),
+            _ => None,
+        }
+    }
+
+    /// Whether the message only reads state, so sending it twice is harmless
+    ///
+    /// Commitments and submissions count against the miner's round, and faucet
+    /// requests pay out, so they are only retried under a `request_id`.
+    pub fn is_read_only(&self) -> bool {
+        !matches!(self, MinerMessage::CommitTimestamp { .. } | MinerMessage::SubmitBlock { .. } | MinerMessage::RequestFaucet { .. })
+    }
+
+    /// The client's ID for a state-changing request, if it gave one
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            MinerMessage::CommitTimestamp { request_id, .. }
+            | MinerMessage::SubmitBlock { request_id, .. }
+            | MinerMessage::RequestFaucet { request_id, .. } => request_id.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Whether sending the message again can't change its outcome
+    pub fn is_idempotent(&self) -> bool {
+        self.is_read_only() || self.request_id().is_some()
+    }
 }
 
 /// Messages sent from validator to miner
@@ -30,10 +182,21 @@ pub enum ValidatorMessage {
     /// Round information response
     RoundInfo(RoundInfoData),
 
+    /// Timestamp commitment recorded for the round
+    CommitmentAccepted {
+        round_start: u128,
+        /// How long to wait before revealing (milliseconds); 0 from validators that don't say
+        #[serde(default)]
+        reveal_after_ms: u64,
+    },
+
     /// Block submission result
     BlockResult {
         result: BlockResultType,
         message: String,
+        /// Validator-signed receipt, present for accepted blocks
+        #[serde(default)]
+        receipt: Option<ReceiptData>,
     },
 
     /// Lockout status response
@@ -45,13 +208,320 @@ pub enum ValidatorMessage {
     /// Blockchain information
     BlockchainInfo {
         block_count: usize,
-        difficulty: String,
+        difficulty: Difficulty,
+        /// Highest height finalized by a validator quorum, when consensus is enabled
+        #[serde(default)]
+        finalized_height: Option<u32>,
+        /// Offset of external time from the validator's clock (milliseconds)
+        #[serde(default)]
+        clock_offset_ms: i128,
+        /// Whether block acceptance is paused because the validator's clock has drifted
+        #[serde(default)]
+        clock_drift_paused: bool,
+    },
+
+    /// The block the next block must build on
+    ChainTip {
+        /// Hash of the latest block; all zeros before the genesis block
+        tip_hash: String, // Hex encoded
+        /// Index the next block takes, which is the number of blocks in the chain
+        next_index: u32,
+        /// Difficulty the next block must be mined at
+        difficulty: Difficulty,
+    },
+
+    /// Unspent balance of an address
+    Balance { address: String, balance: f64 },
+
+    /// Transactions touching an address, oldest first
+    History {
+        address: String,
+        transactions: Vec<AddressTxData>,
+        /// Asks for the next page; absent on the last one
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next_cursor: Option<String>,
+    },
+
+    /// Recent rounds, oldest first
+    RoundHistory {
+        rounds: Vec<RoundSummary>,
+        /// Asks for the next page; absent on the last one
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next_cursor: Option<String>,
+    },
+
+    /// Consecutive block headers, lowest height first
+    Headers { headers: Vec<BlockHeaderData> },
+
+    /// Merkle proof for a transaction in a block
+    TransactionProof { height: u32, proof: MerkleProofData },
+
+    /// The request was refused because the client is sending too fast
+    RateLimited { retry_after_ms: u64 },
+
+    /// Answer to a heartbeat `Ping`, with the exchange's times if the ping was timestamped
+    Pong {
+        #[serde(default)]
+        exchange_times: Option<ExchangeTimesData>,
+    },
+
+    /// Answer to `Hello`: the compression both sides use from the next frame on, if any
+    Welcome { compression: Option<Compression> },
+
+    /// A miner's statistics (all zero for a miner the validator hasn't seen)
+    MinerStats(MinerStatsData),
+
+    /// Miners ranked by blocks accepted
+    Leaderboard { miners: Vec<MinerStatsData> },
+
+    /// Answer to `WatchAddress`: activity for the address will follow
+    Watching { address: String },
+
+    /// Pushed, untagged, to connections watching the address
+    AddressActivity(AddressActivityData),
+
+    /// Answer to `RequestFaucet`: the payment is in the mempool
+    FaucetPayment {
+        address: String,
+        tx_hash: String, // Hex encoded
+        amount: f64,
     },
 
     /// Error message
     Error { message: String },
 }
 
+/// Messages exchanged between validators taking part in consensus
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PeerMessage {
+    /// A validator accepted a block and asks its peers to vote for it
+    ProposeAcceptance {
+        miner_id: String,
+        block: Box<Canonical<Block>>,
+        vote: VoteData,
+    },
+
+    /// Like `ProposeAcceptance`, but carrying a compact block the peer rebuilds from its own transactions
+    ProposeCompactAcceptance {
+        miner_id: String,
+        block: Box<CompactBlockData>,
+        vote: VoteData,
+    },
+
+    /// Reply to a compact proposal the peer couldn't rebuild: the positions of the transactions to send in full
+    MissingTransactions {
+        block_hash: String, // Hex encoded
+        indexes: Vec<u32>,
+    },
+
+    /// A validator's acceptance vote (reply to a proposal)
+    Vote(VoteData),
+
+    /// A quorum certificate finalizing a block
+    Finalized(CertificateData),
+
+    /// Ask a federation member for fresh round-one nonce commitments
+    FederationCommit,
+
+    /// Reply to `FederationCommit`
+    FederationCommitments {
+        member_id: u16,
+        commitments: String, // Hex encoded
+    },
+
+    /// Ask a federation member to sign a receipt for the block it holds at `height`
+    FederationSign {
+        height: u32,
+        miner_id: String,
+        accepted_at: u128,
+        package: String, // Hex encoded
+    },
+
+    /// Reply to `FederationSign`: the member's signature share
+    FederationShare {
+        member_id: u16,
+        share: String, // Hex encoded
+    },
+
+    /// A receipt co-signed by the federation
+    FederatedReceipt(FederatedReceiptData),
+
+    /// Ask a validator for the peers it knows, telling it where to reach us (if we're reachable)
+    GetPeers { address: Option<String> },
+
+    /// Reply to `GetPeers`: addresses that have answered recently
+    Peers { addresses: Vec<String> },
+
+    /// Ask a validator for up to `max` full blocks from height `from`, lowest first
+    ///
+    /// With `to` the blocks stop at that height; with `address` only blocks
+    /// with a transaction touching it are returned.
+    GetBlocks {
+        from: u32,
+        max: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        to: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        address: Option<String>,
+    },
+
+    /// Reply to `GetBlocks`: the blocks, none past the tip, and the `from` of the next page if there is one
+    Blocks {
+        blocks: Vec<Canonical<Block>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next_from: Option<u32>,
+    },
+
+    /// Ask a validator for part of the signed UTXO snapshot it serves to fast-syncing validators
+    GetUtxoSnapshot { offset: u32, max: u32 },
+
+    /// Reply to `GetUtxoSnapshot`
+    UtxoSnapshot(UtxoSnapshotData),
+
+    /// Acknowledgement of a vote or certificate
+    Ack,
+
+    /// The peer refused the request
+    Rejected { message: String },
+}
+
+/// A miner message tagged with an ID, so several can be in flight on one connection
+///
+/// The validator may answer tagged requests out of order; each reply carries
+/// the ID of the request it answers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MinerRequest {
+    pub id: u64,
+    pub message: MinerMessage,
+}
+
+/// The reply to the `MinerRequest` with the same ID
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidatorResponse {
+    pub id: u64,
+    pub message: ValidatorMessage,
+}
+
+/// Any message a validator may receive, from a miner or a peer validator
+#[derive(Debug, Clone)]
+pub enum IncomingMessage {
+    Miner(MinerMessage),
+    Request(MinerRequest),
+    Peer(PeerMessage),
+}
+
+impl IncomingMessage {
+    /// Decode a miner message, falling back to a tagged request and then a peer message
+    ///
+    /// (`#[serde(untagged)]` can't be used here: it buffers values and loses u128 timestamps.)
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, serde_json::Error> {
+        serde_json::from_slice(bytes)
+            .map(IncomingMessage::Miner)
+            .or_else(|_| serde_json::from_slice(bytes).map(IncomingMessage::Request))
+            .or_else(|_| serde_json::from_slice(bytes).map(IncomingMessage::Peer))
+    }
+}
+
+/// Which page of a long listing to return
+///
+/// Listings run oldest first. Without a cursor the first page is returned;
+/// each reply's `next_cursor`, passed back unchanged, asks for the page after
+/// it. Cursors are opaque and only mean something to the listing that gave them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PageQuery {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    /// Most items to return; the listing's own cap if absent or larger
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+    /// Only items from blocks at this height or above
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_height: Option<u32>,
+    /// Only items from blocks at this height or below
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_height: Option<u32>,
+}
+
+impl PageQuery {
+    /// The page after the one that returned `next_cursor`, with the same limit and filters
+    pub fn next(&self, next_cursor: String) -> Self {
+        PageQuery { cursor: Some(next_cursor), ..self.clone() }
+    }
+
+    /// Read `cursor`, `limit`, `from_height` and `to_height` from a URL query string, ignoring other parameters
+    pub fn from_query(query: &str) -> Result<Self, String> {
+        let mut page = PageQuery::default();
+        for (name, value) in query.split('&').filter_map(|param| param.split_once('=')) {
+            let number = || value.parse::<u32>().map_err(|_| format!("{} must be a number", name));
+            match name {
+                "cursor" => page.cursor = Some(value.to_string()),
+                "limit" => page.limit = Some(number()?),
+                "from_height" => page.from_height = Some(number()?),
+                "to_height" => page.to_height = Some(number()?),
+                _ => {}
+            }
+        }
+        Ok(page)
+    }
+
+    fn has_heights(&self) -> bool {
+        self.from_height.is_some() || self.to_height.is_some()
+    }
+
+    fn contains_height(&self, height: u32) -> bool {
+        self.from_height.is_none_or(|from| height >= from) && self.to_height.is_none_or(|to| height <= to)
+    }
+
+    /// Take one page of at most `cap` items from `items`, which are keyed in ascending order
+    ///
+    /// The cursor is the key of the page's first item.
+    fn paginate<T>(&self, items: impl Iterator<Item = (u128, T)>, cap: u32) -> Result<(Vec<T>, Option<String>), String> {
+        let start = match &self.cursor {
+            Some(cursor) => cursor.parse::<u128>().map_err(|_| format!("Invalid cursor: {}", cursor))?,
+            None => 0,
+        };
+        let limit = self.limit.unwrap_or(cap).clamp(1, cap) as usize;
+        let mut items = items.skip_while(|(key, _)| *key < start);
+        let page = items.by_ref().take(limit).map(|(_, item)| item).collect();
+        Ok((page, items.next().map(|(key, _)| key.to_string())))
+    }
+
+    /// A page of the transactions touching `address`
+    pub fn history(&self, chain: &Blockchain, address: &str) -> Result<(Vec<AddressTxData>, Option<String>), String> {
+        let history = chain.history_of(&address.to_string()).iter().enumerate()
+            .filter(|(_, tx)| self.contains_height(tx.block_index))
+            .map(|(position, tx)| (position as u128, AddressTxData::from_address_tx(tx)));
+        self.paginate(history, MAX_PAGE_SIZE)
+    }
+
+    /// A page of the full blocks in `chain`, only those touching `address` if given
+    ///
+    /// Blocks below a UTXO snapshot the chain was synced from have no transactions, and are left out.
+    pub fn blocks<'a>(&self, chain: &'a Blockchain, address: Option<&str>) -> Result<(Vec<&'a Block>, Option<String>), String> {
+        let synced_from = chain.utxo_base().map_or(0, |base| base.height as usize + 1);
+        let first = synced_from.max(self.from_height.unwrap_or(0) as usize);
+        let blocks = chain.blocks.iter().enumerate().skip(first)
+            .take_while(|(height, _)| self.to_height.is_none_or(|to| *height <= to as usize))
+            .filter(|(_, block)| address.is_none_or(|address| block.body.transactions.iter()
+                .any(|tx| tx.inputs.iter().chain(&tx.outputs).any(|output| output.to_addr == address))))
+            .map(|(height, block)| (height as u128, block));
+        self.paginate(blocks, MAX_BLOCKS_PER_MESSAGE)
+    }
+
+    /// A page of the rounds in `history`, only those `miner` submitted a block in or won if given
+    ///
+    /// Rounds have no height, so a height range is refused.
+    pub fn rounds(&self, history: &RoundHistory, miner: Option<&str>) -> Result<(Vec<RoundSummary>, Option<String>), String> {
+        if self.has_heights() {
+            return Err("Round history can't be filtered by height".to_string());
+        }
+        let rounds = history.rounds().iter()
+            .filter(|round| miner.is_none_or(|miner| round.miners.contains_key(miner) || round.winner.as_deref() == Some(miner)))
+            .map(|round| (round.round_start, round.clone()));
+        self.paginate(rounds, MAX_PAGE_SIZE)
+    }
+}
+
 /// Serializable block data
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockData {
@@ -59,6 +529,13 @@ pub struct BlockData {
     pub timestamp: u128,
     pub hash: String, // Hex encoded
     pub prev_block_hash: String, // Hex encoded
+    /// Commitment to the UTXO set the block spends from, if the miner included one
+    #[serde(default)]
+    pub utxo_commitment: Option<String>, // Hex encoded
+    /// The validator round the block commits to, if the miner included one
+    #[serde(default)]
+    pub round: Option<RoundCommitmentData>,
+    pub bits: Difficulty,
     pub nonce: u64,
     pub transactions: Vec<TransactionData>,
 }
@@ -66,34 +543,203 @@ pub struct BlockData {
 impl BlockData {
     pub fn from_block(block: &Block) -> Self {
         BlockData {
-            index: block.index,
-            timestamp: block.timestamp,
-            hash: hex::encode(&block.hash),
-            prev_block_hash: hex::encode(&block.prev_block_hash),
-            nonce: block.nonce,
-            transactions: block.transactions.iter()
+            index: block.header.index,
+            timestamp: block.header.timestamp,
+            hash: block.hash.to_string(),
+            prev_block_hash: block.header.prev_block_hash.to_string(),
+            utxo_commitment: block.header.utxo_commitment.as_ref().map(hex::encode),
+            round: block.header.round.as_ref().map(RoundCommitmentData::from_commitment),
+            bits: block.header.bits,
+            nonce: block.header.nonce.0,
+            transactions: block.body.transactions.iter()
                 .map(TransactionData::from_transaction)
                 .collect(),
         }
     }
 
     pub fn to_block(&self) -> Result<Block, String> {
-        let hash = hex::decode(&self.hash)
-            .map_err(|e| format!("Invalid hash hex: {}", e))?;
-        let prev_block_hash = hex::decode(&self.prev_block_hash)
-            .map_err(|e| format!("Invalid prev_block_hash hex: {}", e))?;
+        let hash = self.hash.parse::<BlockHash>()
+            .map_err(|e| format!("Invalid hash: {}", e))?;
+        let prev_block_hash = self.prev_block_hash.parse::<BlockHash>()
+            .map_err(|e| format!("Invalid prev_block_hash: {}", e))?;
+        let utxo_commitment = match &self.utxo_commitment {
+            Some(commitment) => Some(commitment.parse::<BlockHash>()
+                .map_err(|e| format!("Invalid utxo_commitment: {}", e))?),
+            None => None,
+        };
 
         let transactions: Result<Vec<_>, String> = self.transactions.iter()
             .map(|t| t.to_transaction())
             .collect();
 
+        let body = BlockBody { transactions: transactions? };
         Ok(Block {
+            header: BlockHeader {
+                index: self.index,
+                timestamp: self.timestamp,
+                prev_block_hash,
+                utxo_commitment,
+                round: self.round.as_ref().map(RoundCommitmentData::to_commitment).transpose()?,
+                merkle_root: body.merkle_root(),
+                bits: self.bits,
+                nonce: Nonce(self.nonce),
+            },
+            hash,
+            body,
+        })
+    }
+}
+
+/// Serializable round commitment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoundCommitmentData {
+    pub round_start: u128,
+    pub tonce: u8,
+    /// The validator's round signature, if it signed the round
+    #[serde(default)]
+    pub signature: Option<String>, // Hex encoded
+}
+
+impl RoundCommitmentData {
+    pub fn from_commitment(commitment: &RoundCommitment) -> Self {
+        RoundCommitmentData {
+            round_start: commitment.round_start,
+            tonce: commitment.tonce,
+            signature: commitment.signature.as_ref().map(hex::encode),
+        }
+    }
+
+    pub fn to_commitment(&self) -> Result<RoundCommitment, String> {
+        Ok(RoundCommitment {
+            round_start: self.round_start,
+            tonce: self.tonce,
+            signature: match &self.signature {
+                Some(signature) => Some(hex::decode(signature)
+                    .map_err(|e| format!("Invalid round signature hex: {}", e))?),
+                None => None,
+            },
+        })
+    }
+}
+
+/// Serializable block header data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockHeaderData {
+    pub index: u32,
+    pub timestamp: u128,
+    pub prev_block_hash: String, // Hex encoded
+    #[serde(default)]
+    pub utxo_commitment: Option<String>, // Hex encoded
+    #[serde(default)]
+    pub round: Option<RoundCommitmentData>,
+    pub merkle_root: String, // Hex encoded
+    pub bits: Difficulty,
+    pub nonce: u64,
+    /// The validator's receipt for the block, if it signed one
+    #[serde(default)]
+    pub receipt: Option<ReceiptData>,
+}
+
+impl BlockHeaderData {
+    pub fn from_header(header: &BlockHeader, receipt: Option<&BlockReceipt>) -> Self {
+        BlockHeaderData {
+            index: header.index,
+            timestamp: header.timestamp,
+            prev_block_hash: header.prev_block_hash.to_string(),
+            utxo_commitment: header.utxo_commitment.as_ref().map(hex::encode),
+            round: header.round.as_ref().map(RoundCommitmentData::from_commitment),
+            merkle_root: header.merkle_root.to_string(),
+            bits: header.bits,
+            nonce: header.nonce.0,
+            receipt: receipt.map(ReceiptData::from_receipt),
+        }
+    }
+
+    pub fn to_header(&self) -> Result<BlockHeader, String> {
+        Ok(BlockHeader {
             index: self.index,
             timestamp: self.timestamp,
-            hash,
-            prev_block_hash,
-            nonce: self.nonce,
-            transactions: transactions?,
+            prev_block_hash: self.prev_block_hash.parse::<BlockHash>()
+                .map_err(|e| format!("Invalid prev_block_hash: {}", e))?,
+            utxo_commitment: match &self.utxo_commitment {
+                Some(commitment) => Some(commitment.parse::<BlockHash>()
+                    .map_err(|e| format!("Invalid utxo_commitment: {}", e))?),
+                None => None,
+            },
+            round: self.round.as_ref().map(RoundCommitmentData::to_commitment).transpose()?,
+            merkle_root: self.merkle_root.parse::<BlockHash>()
+                .map_err(|e| format!("Invalid merkle_root: {}", e))?,
+            bits: self.bits,
+            nonce: Nonce(self.nonce),
+        })
+    }
+}
+
+/// Serializable compact block data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompactBlockData {
+    pub header: Canonical<BlockHeader>,
+    pub short_ids: Vec<u64>,
+    pub prefilled: Vec<PrefilledTransactionData>,
+}
+
+/// A transaction sent in full within a compact block
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrefilledTransactionData {
+    pub index: u32,
+    pub transaction: Canonical<Transaction>,
+}
+
+impl CompactBlockData {
+    pub fn from_compact(compact: &CompactBlock) -> Self {
+        CompactBlockData {
+            header: Canonical(compact.header.clone()),
+            short_ids: compact.short_ids.clone(),
+            prefilled: compact.prefilled.iter()
+                .map(|(index, transaction)| PrefilledTransactionData {
+                    index: *index,
+                    transaction: Canonical(transaction.clone()),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn to_compact(&self) -> CompactBlock {
+        CompactBlock {
+            header: self.header.0.clone(),
+            short_ids: self.short_ids.clone(),
+            prefilled: self.prefilled.iter()
+                .map(|prefilled| (prefilled.index, prefilled.transaction.0.clone()))
+                .collect(),
+        }
+    }
+}
+
+/// Serializable merkle proof data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MerkleProofData {
+    pub index: u32,
+    pub leaf_count: u32,
+    pub siblings: Vec<String>, // Hex encoded
+}
+
+impl MerkleProofData {
+    pub fn from_proof(proof: &MerkleProof) -> Self {
+        MerkleProofData {
+            index: proof.index,
+            leaf_count: proof.leaf_count,
+            siblings: proof.siblings.iter().map(hex::encode).collect(),
+        }
+    }
+
+    pub fn to_proof(&self) -> Result<MerkleProof, String> {
+        let siblings = self.siblings.iter()
+            .map(|sibling| sibling.parse::<BlockHash>().map_err(|e| format!("Invalid sibling: {}", e)))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(MerkleProof {
+            index: self.index,
+            leaf_count: self.leaf_count,
+            siblings,
         })
     }
 }
@@ -103,6 +749,8 @@ impl BlockData {
 pub struct TransactionData {
     pub inputs: Vec<OutputData>,
     pub outputs: Vec<OutputData>,
+    #[serde(default)]
+    pub witnesses: Vec<WitnessData>,
 }
 
 impl TransactionData {
@@ -110,6 +758,7 @@ impl TransactionData {
         TransactionData {
             inputs: tx.inputs.iter().map(OutputData::from_output).collect(),
             outputs: tx.outputs.iter().map(OutputData::from_output).collect(),
+            witnesses: tx.witnesses.iter().map(WitnessData::from_witness).collect(),
         }
     }
 
@@ -120,20 +769,181 @@ impl TransactionData {
         let outputs: Vec<_> = self.outputs.iter()
             .map(|o| o.to_output())
             .collect();
+        let witnesses: Result<Vec<_>, String> = self.witnesses.iter()
+            .map(|w| w.to_witness())
+            .collect();
 
         Ok(crate::transaction::Transaction {
             inputs,
             outputs,
+            witnesses: witnesses?,
         })
     }
 }
 
+/// Serializable transaction witness data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WitnessData {
+    Htlc {
+        payment_hash: String, // Hex encoded
+        recipient: String,
+        refund_address: String,
+        timeout: u128,
+        preimage: Option<String>, // Hex encoded
+    },
+    ChannelClose {
+        party_addresses: [String; 2],
+        party_public_keys: [String; 2], // Hex encoded
+        dispute_period_ms: u128,
+        sequence: u64,
+        balances: [f64; 2],
+        revocation_hashes: [String; 2], // Hex encoded
+        signatures: [String; 2], // Hex encoded
+        closing: bool,
+        /// Closing party and payout timeout, for a unilateral close
+        unilateral: Option<(usize, u128)>,
+    },
+    Signature {
+        public_key: String, // Hex encoded
+        signature: String, // Hex encoded
+    },
+    Multisig {
+        threshold: usize,
+        public_keys: Vec<String>, // Hex encoded
+        /// One slot per public key; None where that cosigner did not sign
+        signatures: Vec<Option<String>>, // Hex encoded
+    },
+    ExtraNonce {
+        extra_nonce: u64,
+    },
+    Message {
+        text: String,
+    },
+}
+
+impl WitnessData {
+    pub fn from_witness(witness: &Witness) -> Self {
+        match witness {
+            Witness::Htlc { contract, preimage } => WitnessData::Htlc {
+                payment_hash: hex::encode(&contract.payment_hash),
+                recipient: contract.recipient.clone(),
+                refund_address: contract.refund_address.clone(),
+                timeout: contract.timeout,
+                preimage: preimage.as_ref().map(hex::encode),
+            },
+            Witness::ChannelClose { terms, state, unilateral } => WitnessData::ChannelClose {
+                party_addresses: [terms.parties[0].address.clone(), terms.parties[1].address.clone()],
+                party_public_keys: [hex::encode(&terms.parties[0].public_key), hex::encode(&terms.parties[1].public_key)],
+                dispute_period_ms: terms.dispute_period_ms,
+                sequence: state.state.sequence,
+                balances: state.state.balances,
+                revocation_hashes: [hex::encode(&state.state.revocation_hashes[0]), hex::encode(&state.state.revocation_hashes[1])],
+                signatures: [hex::encode(&state.signatures[0]), hex::encode(&state.signatures[1])],
+                closing: state.state.closing,
+                unilateral: unilateral.as_ref().map(|close| (close.closer, close.timeout)),
+            },
+            Witness::Signature { public_key, signature } => WitnessData::Signature {
+                public_key: hex::encode(public_key),
+                signature: hex::encode(signature),
+            },
+            Witness::Multisig { policy, signatures } => WitnessData::Multisig {
+                threshold: policy.threshold,
+                public_keys: policy.public_keys.iter().map(hex::encode).collect(),
+                signatures: signatures.iter().map(|signature| signature.as_ref().map(hex::encode)).collect(),
+            },
+            Witness::ExtraNonce(extra_nonce) => WitnessData::ExtraNonce { extra_nonce: *extra_nonce },
+            Witness::Message(text) => WitnessData::Message { text: text.clone() },
+        }
+    }
+
+    pub fn to_witness(&self) -> Result<Witness, String> {
+        match self {
+            WitnessData::Htlc { payment_hash, recipient, refund_address, timeout, preimage } => {
+                let payment_hash = hex::decode(payment_hash)
+                    .map_err(|e| format!("Invalid payment_hash hex: {}", e))?;
+                let preimage = match preimage {
+                    Some(preimage) => Some(hex::decode(preimage)
+                        .map_err(|e| format!("Invalid preimage hex: {}", e))?),
+                    None => None,
+                };
+
+                Ok(Witness::Htlc {
+                    contract: HtlcContract::new(payment_hash, recipient, refund_address, *timeout),
+                    preimage,
+                })
+            }
+            WitnessData::ChannelClose {
+                party_addresses, party_public_keys, dispute_period_ms, sequence, balances,
+                revocation_hashes, signatures, closing, unilateral,
+            } => {
+                let decode = |field: &str, value: &String| hex::decode(value)
+                    .map_err(|e| format!("Invalid {} hex: {}", field, e));
+                let party = |i: usize| -> Result<ChannelParty, String> {
+                    Ok(ChannelParty {
+                        address: party_addresses[i].clone(),
+                        public_key: decode("party_public_key", &party_public_keys[i])?,
+                    })
+                };
+
+                Ok(Witness::ChannelClose {
+                    terms: ChannelTerms {
+                        parties: [party(0)?, party(1)?],
+                        dispute_period_ms: *dispute_period_ms,
+                    },
+                    state: SignedState {
+                        state: ChannelState {
+                            sequence: *sequence,
+                            balances: *balances,
+                            revocation_hashes: [
+                                decode("revocation_hash", &revocation_hashes[0])?,
+                                decode("revocation_hash", &revocation_hashes[1])?,
+                            ],
+                            closing: *closing,
+                        },
+                        signatures: [decode("signature", &signatures[0])?, decode("signature", &signatures[1])?],
+                    },
+                    unilateral: unilateral.map(|(closer, timeout)| UnilateralClose { closer, timeout }),
+                })
+            }
+            WitnessData::Signature { public_key, signature } => Ok(Witness::Signature {
+                public_key: hex::decode(public_key)
+                    .map_err(|e| format!("Invalid public_key hex: {}", e))?,
+                signature: hex::decode(signature)
+                    .map_err(|e| format!("Invalid signature hex: {}", e))?,
+            }),
+            WitnessData::Multisig { threshold, public_keys, signatures } => {
+                let public_keys = public_keys.iter()
+                    .map(|key| hex::decode(key).map_err(|e| format!("Invalid public_key hex: {}", e)))
+                    .collect::<Result<Vec<_>, String>>()?;
+                let signatures = signatures.iter()
+                    .map(|signature| match signature {
+                        Some(signature) => hex::decode(signature)
+                            .map(Some)
+                            .map_err(|e| format!("Invalid signature hex: {}", e)),
+                        None => Ok(None),
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+
+                Ok(Witness::Multisig {
+                    policy: MultisigPolicy::new(*threshold, public_keys)?,
+                    signatures,
+                })
+            }
+            WitnessData::ExtraNonce { extra_nonce } => Ok(Witness::ExtraNonce(*extra_nonce)),
+            WitnessData::Message { text } => Ok(Witness::Message(text.clone())),
+        }
+    }
+}
+
 /// Serializable output data
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OutputData {
     pub to_addr: String,
     pub value: f64,
     pub timestamp: u128,
+    /// Earliest block timestamp that may spend the output
+    #[serde(default)]
+    pub lock_until: Option<u128>,
 }
 
 impl OutputData {
@@ -142,6 +952,7 @@ impl OutputData {
             to_addr: output.to_addr.clone(),
             value: output.value,
             timestamp: output.timestamp,
+            lock_until: output.lock_until,
         }
     }
 
@@ -150,31 +961,444 @@ impl OutputData {
             to_addr: self.to_addr.clone(),
             value: self.value,
             timestamp: self.timestamp,
+            lock_until: self.lock_until,
         }
     }
 }
 
+/// Part of a validator's signed UTXO snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UtxoSnapshotData {
+    pub height: u32,
+    pub block_hash: String, // Hex encoded
+    /// Outputs in the whole snapshot
+    pub total_outputs: u32,
+    /// Position of the first of `outputs` in the snapshot
+    pub offset: u32,
+    pub outputs: Vec<Canonical<Output>>,
+    pub public_key: String, // Hex encoded
+    pub signature: String, // Hex encoded
+}
+
+impl UtxoSnapshotData {
+    /// Up to `max` of `snapshot`'s outputs, starting at `offset`
+    pub fn from_snapshot(snapshot: &UtxoSnapshot, offset: u32, max: u32) -> Self {
+        UtxoSnapshotData {
+            height: snapshot.height,
+            block_hash: snapshot.block_hash.to_string(),
+            total_outputs: snapshot.outputs.len() as u32,
+            offset,
+            outputs: snapshot.outputs.iter()
+                .skip(offset as usize)
+                .take(max as usize)
+                .cloned()
+                .map(Canonical)
+                .collect(),
+            public_key: hex::encode(&snapshot.public_key),
+            signature: hex::encode(&snapshot.signature),
+        }
+    }
+
+    /// Put a snapshot back together from its parts, which must all be of the same one and cover it in order
+    pub fn to_snapshot(parts: &[UtxoSnapshotData]) -> Result<UtxoSnapshot, String> {
+        let first = parts.first().ok_or("No snapshot parts")?;
+        let mut outputs = Vec::with_capacity(first.total_outputs as usize);
+        for part in parts {
+            if (part.height, &part.block_hash, &part.signature) != (first.height, &first.block_hash, &first.signature) {
+                return Err(format!("Snapshot changed from height {} to {} while it was downloaded", first.height, part.height));
+            }
+            if part.offset as usize != outputs.len() {
+                return Err(format!("Snapshot part starts at output {}, expected {}", part.offset, outputs.len()));
+            }
+            outputs.extend(part.outputs.iter().map(|output| output.0.clone()));
+        }
+        if outputs.len() != first.total_outputs as usize {
+            return Err(format!("Snapshot has {} outputs, but {} were received", first.total_outputs, outputs.len()));
+        }
+        Ok(UtxoSnapshot {
+            height: first.height,
+            block_hash: first.block_hash.parse::<BlockHash>()
+                .map_err(|e| format!("Invalid block_hash: {}", e))?,
+            outputs,
+            public_key: hex::decode(&first.public_key).map_err(|e| format!("Invalid public key hex: {}", e))?,
+            signature: hex::decode(&first.signature).map_err(|e| format!("Invalid signature hex: {}", e))?,
+        })
+    }
+}
+
+/// Most miners a validator returns for one `GetLeaderboard` request
+pub const MAX_LEADERBOARD_SIZE: u32 = 100;
+
+/// Serializable miner statistics
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MinerStatsData {
+    pub miner_id: String,
+    pub blocks_accepted: u64,
+    /// Rejected submissions, by reason
+    pub rejections: BTreeMap<String, u64>,
+    pub average_time_to_solution_ms: Option<u128>,
+    pub total_lockout_ms: u128,
+    pub last_accepted_at: Option<u128>,
+}
+
+impl MinerStatsData {
+    pub fn from_stats(stats: &MinerStats) -> Self {
+        MinerStatsData {
+            miner_id: stats.miner_id.clone(),
+            blocks_accepted: stats.blocks_accepted,
+            rejections: stats.rejections.clone(),
+            average_time_to_solution_ms: stats.average_time_to_solution_ms(),
+            total_lockout_ms: stats.total_lockout_ms,
+            last_accepted_at: stats.last_accepted_at,
+        }
+    }
+}
+
+/// Serializable address history entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddressTxData {
+    pub block_index: u32,
+    pub block_timestamp: u128,
+    pub tx_hash: String, // Hex encoded
+    pub received: f64,
+    pub sent: f64,
+}
+
+/// Serializable address activity, pushed to watching connections
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddressActivityData {
+    pub address: String,
+    pub tx_hash: String, // Hex encoded
+    pub received: f64,
+    pub sent: f64,
+    /// Height of the confirming block; absent while the transaction is in the mempool
+    #[serde(default)]
+    pub height: Option<u32>,
+}
+
+impl AddressActivityData {
+    pub fn from_activity(activity: &AddressActivity) -> Self {
+        AddressActivityData {
+            address: activity.address.clone(),
+            tx_hash: activity.tx_hash.to_string(),
+            received: activity.received,
+            sent: activity.sent,
+            height: activity.height,
+        }
+    }
+}
+
+impl AddressTxData {
+    pub fn from_address_tx(tx: &AddressTx) -> Self {
+        AddressTxData {
+            block_index: tx.block_index,
+            block_timestamp: tx.block_timestamp,
+            tx_hash: tx.tx_hash.to_string(),
+            received: tx.received,
+            sent: tx.sent,
+        }
+    }
+
+    pub fn to_address_tx(&self) -> Result<AddressTx, String> {
+        Ok(AddressTx {
+            block_index: self.block_index,
+            block_timestamp: self.block_timestamp,
+            tx_hash: self.tx_hash.parse::<Txid>()
+                .map_err(|e| format!("Invalid tx_hash: {}", e))?,
+            received: self.received,
+            sent: self.sent,
+        })
+    }
+}
+
 /// Round information data
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RoundInfoData {
+    /// Identifies the round; submissions echo it (hex)
+    #[serde(default)]
+    pub round_id: Option<String>,
     pub round_start: u128,
     pub tonce: Option<u8>,
     pub challenge_seconds_remaining: u64,
     pub attempted_miners: usize,
     pub active_lockouts: usize,
-    pub difficulty: String,
+    pub difficulty: Difficulty,
+    /// Present when the validator derives the tonce from a VRF
+    #[serde(default)]
+    pub vrf_proof: Option<VrfProofData>,
+    /// Submitted blocks must reveal a timestamp commitment
+    #[serde(default)]
+    pub commit_reveal: bool,
+    /// Commitment to the current UTXO set, for the miner to put in its block
+    #[serde(default)]
+    pub utxo_commitment: Option<String>, // Hex encoded
+    /// Timestamps of this exchange, when the request carried a send time
+    #[serde(default)]
+    pub exchange_times: Option<ExchangeTimesData>,
+    /// Submissions the requesting miner has left this round
+    #[serde(default)]
+    pub remaining_attempts: Option<u32>,
+    /// The validator's signature over the round and its tonce, for the miner to put in its block
+    #[serde(default)]
+    pub round_signature: Option<String>, // Hex encoded
 }
 
 impl RoundInfoData {
-    pub fn from_round_info(info: &RoundInfo, difficulty: u128) -> Self {
+    #[cfg(feature = "node")]
+    pub fn from_round_info(info: &RoundInfo, difficulty: Difficulty) -> Self {
         RoundInfoData {
+            round_id: Some(info.round_id.to_string()),
             round_start: info.round_start,
             tonce: info.tonce,
             challenge_seconds_remaining: info.challenge_seconds_remaining,
             attempted_miners: info.attempted_miners,
             active_lockouts: info.active_lockouts,
-            difficulty: format!("0x{:X}", difficulty),
+            difficulty,
+            vrf_proof: info.vrf_proof.as_ref()
+                .map(|proof| VrfProofData::from_proof(proof, info.tonce_bits)),
+            commit_reveal: info.commit_reveal,
+            utxo_commitment: Some(info.utxo_commitment.to_string()),
+            exchange_times: None,
+            remaining_attempts: info.remaining_attempts,
+            round_signature: info.round_signature.as_ref().map(hex::encode),
+        }
+    }
+
+    /// Verify the VRF proof (if any) and check it derives the advertised tonce
+    ///
+    /// The proof's input must be the `round_input` of the tip, the block at
+    /// `prev_block_hash`, so a proof from an earlier round can't be replayed.
+    /// Without `prev_block_timestamp` only the hash is checked: the first round
+    /// of an empty chain takes its timestamp from the validator's clock.
+    /// If `expected_public_key` is given, the proof must also come from that key.
+    /// Returns Ok(false) when the round carries no proof.
+    pub fn verify_vrf_tonce(
+        &self,
+        expected_public_key: Option<&[u8]>,
+        prev_block_hash: &BlockHash,
+        prev_block_timestamp: Option<u128>,
+    ) -> Result<bool, String> {
+        let proof_data = match &self.vrf_proof {
+            Some(proof_data) => proof_data,
+            None => return Ok(false),
+        };
+        let proof = proof_data.to_proof()?;
+
+        let for_tip = match prev_block_timestamp {
+            Some(timestamp) => proof.input == round_input(prev_block_hash, timestamp),
+            None => proof.input.len() == round_input(prev_block_hash, 0).len() && proof.input.starts_with(prev_block_hash.as_bytes()),
+        };
+        if !for_tip {
+            return Err(format!("VRF proof is not for a round after block {}", prev_block_hash));
         }
+
+        if let Some(expected) = expected_public_key {
+            if proof.public_key != expected {
+                return Err("VRF proof is not from the expected validator key".to_string());
+            }
+        }
+
+        let randomness = proof.verify()?;
+        let tonce = TonceChallenge::tonce_from_randomness(&randomness, proof_data.tonce_bits)?;
+        if self.tonce != Some(tonce) {
+            return Err(format!("VRF proof derives tonce {} but round advertises {:?}", tonce, self.tonce));
+        }
+
+        Ok(true)
+    }
+}
+
+/// Times of one request/reply exchange, as in NTP (TAI milliseconds)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ExchangeTimesData {
+    /// Requester's clock when the request was sent (t1)
+    pub client_sent_at: u128,
+    /// Responder's clock when the request arrived (t2)
+    pub server_received_at: u128,
+    /// Responder's clock when the reply was sent (t3)
+    pub server_sent_at: u128,
+}
+
+impl ExchangeTimesData {
+    /// Offset and round trip, given the requester's clock when the reply arrived (t4)
+    pub fn to_sample(&self, client_received_at: u128) -> SntpSample {
+        SntpSample::from_times(
+            self.client_sent_at as i128,
+            self.server_received_at as i128,
+            self.server_sent_at as i128,
+            client_received_at as i128,
+        )
+    }
+}
+
+/// Serializable VRF proof data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VrfProofData {
+    pub public_key: String, // Hex encoded
+    pub input: String, // Hex encoded
+    pub pre_output: String, // Hex encoded
+    pub proof: String, // Hex encoded
+    /// Bit width used to extract the tonce from the VRF output
+    pub tonce_bits: u8,
+}
+
+impl VrfProofData {
+    pub fn from_proof(proof: &VrfProof, tonce_bits: u8) -> Self {
+        VrfProofData {
+            public_key: hex::encode(&proof.public_key),
+            input: hex::encode(&proof.input),
+            pre_output: hex::encode(&proof.pre_output),
+            proof: hex::encode(&proof.proof),
+            tonce_bits,
+        }
+    }
+
+    pub fn to_proof(&self) -> Result<VrfProof, String> {
+        Ok(VrfProof {
+            public_key: hex::decode(&self.public_key)
+                .map_err(|e| format!("Invalid public_key hex: {}", e))?,
+            input: hex::decode(&self.input)
+                .map_err(|e| format!("Invalid input hex: {}", e))?,
+            pre_output: hex::decode(&self.pre_output)
+                .map_err(|e| format!("Invalid pre_output hex: {}", e))?,
+            proof: hex::decode(&self.proof)
+                .map_err(|e| format!("Invalid proof hex: {}", e))?,
+        })
+    }
+}
+
+/// Serializable block receipt data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceiptData {
+    pub block_hash: String, // Hex encoded
+    pub height: u32,
+    pub miner_id: String,
+    pub accepted_at: u128,
+    pub validator_public_key: String, // Hex encoded
+    pub signature: String, // Hex encoded
+}
+
+impl ReceiptData {
+    pub fn from_receipt(receipt: &BlockReceipt) -> Self {
+        ReceiptData {
+            block_hash: receipt.block_hash.to_string(),
+            height: receipt.height,
+            miner_id: receipt.miner_id.clone(),
+            accepted_at: receipt.accepted_at,
+            validator_public_key: hex::encode(&receipt.validator_public_key),
+            signature: hex::encode(&receipt.signature),
+        }
+    }
+
+    pub fn to_receipt(&self) -> Result<BlockReceipt, String> {
+        Ok(BlockReceipt {
+            block_hash: self.block_hash.parse::<BlockHash>()
+                .map_err(|e| format!("Invalid block_hash: {}", e))?,
+            height: self.height,
+            miner_id: self.miner_id.clone(),
+            accepted_at: self.accepted_at,
+            validator_public_key: hex::decode(&self.validator_public_key)
+                .map_err(|e| format!("Invalid validator_public_key hex: {}", e))?,
+            signature: hex::decode(&self.signature)
+                .map_err(|e| format!("Invalid signature hex: {}", e))?,
+        })
+    }
+}
+
+/// Serializable acceptance vote data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoteData {
+    pub block_hash: String, // Hex encoded
+    pub height: u32,
+    pub voter: String, // Hex encoded
+    pub signature: String, // Hex encoded
+}
+
+impl VoteData {
+    pub fn from_vote(vote: &AcceptanceVote) -> Self {
+        VoteData {
+            block_hash: vote.block_hash.to_string(),
+            height: vote.height,
+            voter: hex::encode(&vote.voter),
+            signature: hex::encode(&vote.signature),
+        }
+    }
+
+    pub fn to_vote(&self) -> Result<AcceptanceVote, String> {
+        Ok(AcceptanceVote {
+            block_hash: self.block_hash.parse::<BlockHash>()
+                .map_err(|e| format!("Invalid block_hash: {}", e))?,
+            height: self.height,
+            voter: hex::decode(&self.voter)
+                .map_err(|e| format!("Invalid voter hex: {}", e))?,
+            signature: hex::decode(&self.signature)
+                .map_err(|e| format!("Invalid signature hex: {}", e))?,
+        })
+    }
+}
+
+/// Serializable quorum certificate data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CertificateData {
+    pub block_hash: String, // Hex encoded
+    pub height: u32,
+    pub votes: Vec<VoteData>,
+}
+
+impl CertificateData {
+    pub fn from_certificate(certificate: &QuorumCertificate) -> Self {
+        CertificateData {
+            block_hash: certificate.block_hash.to_string(),
+            height: certificate.height,
+            votes: certificate.votes.iter().map(VoteData::from_vote).collect(),
+        }
+    }
+
+    pub fn to_certificate(&self) -> Result<QuorumCertificate, String> {
+        let votes: Result<Vec<_>, String> = self.votes.iter()
+            .map(|v| v.to_vote())
+            .collect();
+
+        Ok(QuorumCertificate {
+            block_hash: self.block_hash.parse::<BlockHash>()
+                .map_err(|e| format!("Invalid block_hash: {}", e))?,
+            height: self.height,
+            votes: votes?,
+        })
+    }
+}
+
+/// Serializable federated receipt data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FederatedReceiptData {
+    pub block_hash: String, // Hex encoded
+    pub height: u32,
+    pub miner_id: String,
+    pub accepted_at: u128,
+    pub signature: String, // Hex encoded
+}
+
+impl FederatedReceiptData {
+    pub fn from_receipt(receipt: &FederatedReceipt) -> Self {
+        FederatedReceiptData {
+            block_hash: receipt.block_hash.to_string(),
+            height: receipt.height,
+            miner_id: receipt.miner_id.clone(),
+            accepted_at: receipt.accepted_at,
+            signature: hex::encode(&receipt.signature),
+        }
+    }
+
+    pub fn to_receipt(&self) -> Result<FederatedReceipt, String> {
+        Ok(FederatedReceipt {
+            block_hash: self.block_hash.parse::<BlockHash>()
+                .map_err(|e| format!("Invalid block_hash: {}", e))?,
+            height: self.height,
+            miner_id: self.miner_id.clone(),
+            accepted_at: self.accepted_at,
+            signature: hex::decode(&self.signature)
+                .map_err(|e| format!("Invalid signature hex: {}", e))?,
+        })
     }
 }
 
@@ -187,9 +1411,19 @@ pub enum BlockResultType {
     RejectedTonceChallenge,
     RejectedMinerInLockout,
     RejectedMinerAlreadyAttempted,
+    RejectedMissingCommitment,
+    RejectedCommitmentMismatch,
+    RejectedRevealOutsideWindow,
     RejectedBlockchainValidation,
+    RejectedDuplicateBlock,
+    RejectedClockDrift,
+    RejectedPaused,
+    RejectedMinerBanned,
+    RejectedStaleRound,
+    RejectedByHook,
 }
 
+#[cfg(feature = "node")]
 impl From<&ValidationResult> for BlockResultType {
     fn from(result: &ValidationResult) -> Self {
         match result {
@@ -199,7 +1433,16 @@ impl From<&ValidationResult> for BlockResultType {
             ValidationResult::RejectedTonceChallenge => BlockResultType::RejectedTonceChallenge,
             ValidationResult::RejectedMinerInLockout => BlockResultType::RejectedMinerInLockout,
             ValidationResult::RejectedMinerAlreadyAttempted => BlockResultType::RejectedMinerAlreadyAttempted,
+            ValidationResult::RejectedMissingCommitment => BlockResultType::RejectedMissingCommitment,
+            ValidationResult::RejectedCommitmentMismatch => BlockResultType::RejectedCommitmentMismatch,
+            ValidationResult::RejectedRevealOutsideWindow => BlockResultType::RejectedRevealOutsideWindow,
             ValidationResult::RejectedBlockchainValidation(_) => BlockResultType::RejectedBlockchainValidation,
+            ValidationResult::RejectedDuplicateBlock => BlockResultType::RejectedDuplicateBlock,
+            ValidationResult::RejectedClockDrift => BlockResultType::RejectedClockDrift,
+            ValidationResult::RejectedPaused => BlockResultType::RejectedPaused,
+            ValidationResult::RejectedMinerBanned => BlockResultType::RejectedMinerBanned,
+            ValidationResult::RejectedStaleRound => BlockResultType::RejectedStaleRound,
+            ValidationResult::RejectedByHook(_) => BlockResultType::RejectedByHook,
         }
     }
 }
@@ -213,8 +1456,11 @@ mod tests {
         let block_data = BlockData {
             index: 0,
             timestamp: 1000,
-            hash: "abcd".to_string(),
-            prev_block_hash: "0000".to_string(),
+            hash: "ab".repeat(32),
+            prev_block_hash: "00".repeat(32),
+            utxo_commitment: Some("ef".repeat(32)),
+            round: Some(RoundCommitmentData { round_start: 900, tonce: 3, signature: Some("5a".repeat(64)) }),
+            bits: Difficulty::EASIEST,
             nonce: 123,
             transactions: vec![],
         };
@@ -224,23 +1470,81 @@ mod tests {
 
         assert_eq!(block_data.index, deserialized.index);
         assert_eq!(block_data.timestamp, deserialized.timestamp);
+        assert_eq!(block_data.to_block().unwrap().header.utxo_commitment, Some(BlockHash::from_bytes([0xef; 32])));
+        assert_eq!(block_data.to_block().unwrap().header.round,
+            Some(RoundCommitment { round_start: 900, tonce: 3, signature: Some(vec![0x5a; 64]) }));
+    }
+
+    #[test]
+    fn test_block_data_preserves_canonical_bytes() {
+        use crate::canonical::{CanonicalDeserialize, CanonicalSerialize};
+        use crate::hashable::Hashable;
+        use crate::transaction::{Output, Transaction};
+
+        let mut block = Block::new(3, u64::MAX as u128 + 1, BlockHash::from_bytes([9; 32]), vec![Transaction {
+            inputs: vec![],
+            outputs: vec![Output { to_addr: "alice".to_string(), value: 0.1, timestamp: 1000, lock_until: Some(2000) }],
+            witnesses: vec![Witness::Signature { public_key: vec![1; 32], signature: vec![2; 64] }],
+        }]);
+        block.hash = block.hash();
+
+        // JSON and the binary encoding carry exactly the same block
+        let json = serde_json::to_string(&BlockData::from_block(&block)).unwrap();
+        let from_json = serde_json::from_str::<BlockData>(&json).unwrap().to_block().unwrap();
+        assert_eq!(from_json.canonical_bytes(), block.canonical_bytes());
+        assert_eq!(from_json.hash, Block::from_canonical_bytes(&block.canonical_bytes()).unwrap().hash);
+    }
+
+    #[test]
+    fn test_compact_block_data_roundtrip() {
+        use crate::compact_block::TransactionPool;
+        use crate::hashable::Hashable;
+        use crate::transaction::{Output, Transaction};
+
+        let output = |to_addr: &str| Output { to_addr: to_addr.to_string(), value: 1.0, timestamp: 1000, lock_until: None };
+        let payment = Transaction { inputs: vec![output("alice")], outputs: vec![output("bob")], witnesses: vec![] };
+        let block = Block::new(1, 2000, BlockHash::from_bytes([9; 32]), vec![
+            Transaction { inputs: vec![], outputs: vec![output("miner")], witnesses: vec![] },
+            payment.clone(),
+        ]);
+
+        let json = serde_json::to_string(&CompactBlockData::from_compact(&CompactBlock::from_block(&block))).unwrap();
+        let compact = serde_json::from_str::<CompactBlockData>(&json).unwrap().to_compact();
+        let mut pool = TransactionPool::new(10);
+        pool.insert(payment);
+        assert_eq!(compact.reconstruct(&pool).unwrap().hash, block.hash());
     }
 
     #[test]
     fn test_miner_message_serialization() {
         let msg = MinerMessage::GetRoundInfo {
             miner_id: "test_miner".to_string(),
+            sent_at: Some(1000),
         };
 
         let json = serde_json::to_string(&msg).unwrap();
         let deserialized: MinerMessage = serde_json::from_str(&json).unwrap();
 
         match deserialized {
-            MinerMessage::GetRoundInfo { miner_id } => {
+            MinerMessage::GetRoundInfo { miner_id, sent_at } => {
                 assert_eq!(miner_id, "test_miner");
+                assert_eq!(sent_at, Some(1000));
             }
             _ => panic!("Wrong message type"),
         }
+
+        // Requests from miners that don't timestamp still parse
+        let legacy: MinerMessage = serde_json::from_str(r#"{"GetRoundInfo":{"miner_id":"old"}}"#).unwrap();
+        assert!(matches!(legacy, MinerMessage::GetRoundInfo { sent_at: None, .. }));
+    }
+
+    #[test]
+    fn test_exchange_times_sample() {
+        // Validator 500ms ahead, 20ms each way
+        let times = ExchangeTimesData { client_sent_at: 1000, server_received_at: 1520, server_sent_at: 1521 };
+        let sample = times.to_sample(1041);
+        assert_eq!(sample.offset_ms, 500);
+        assert_eq!(sample.delay_ms, 40);
     }
 
     #[test]
@@ -248,17 +1552,255 @@ mod tests {
         let msg = ValidatorMessage::BlockResult {
             result: BlockResultType::Accepted,
             message: "Block accepted!".to_string(),
+            receipt: None,
         };
 
         let json = serde_json::to_string(&msg).unwrap();
         let deserialized: ValidatorMessage = serde_json::from_str(&json).unwrap();
 
         match deserialized {
-            ValidatorMessage::BlockResult { result, message } => {
+            ValidatorMessage::BlockResult { result, message, .. } => {
                 assert!(matches!(result, BlockResultType::Accepted));
                 assert_eq!(message, "Block accepted!");
             }
             _ => panic!("Wrong message type"),
         }
     }
+
+    #[test]
+    fn test_incoming_message_dispatch() {
+        let miner = serde_json::to_vec(&MinerMessage::GetBlockchainInfo).unwrap();
+        assert!(matches!(IncomingMessage::from_slice(&miner).unwrap(), IncomingMessage::Miner(_)));
+
+        use crate::canonical::CanonicalSerialize;
+        use crate::hashable::Hashable;
+
+        // Blocks travel as their canonical bytes, and u128 timestamps must survive dispatch
+        let block = Block::new(0, u64::MAX as u128 + 1, BlockHash::ZERO, vec![]);
+        let submit = MinerMessage::SubmitBlock {
+            miner_id: "m".to_string(),
+            block: Box::new(Canonical(block.clone())),
+            salt: None,
+            round_id: None,
+            request_id: None,
+        };
+        let json = serde_json::to_string(&submit).unwrap();
+        assert!(json.contains(&format!("\"block\":\"{}\"", hex::encode(block.canonical_bytes()))));
+        match IncomingMessage::from_slice(json.as_bytes()).unwrap() {
+            IncomingMessage::Miner(MinerMessage::SubmitBlock { block: received, .. }) => {
+                assert_eq!(received.0.header.timestamp, u64::MAX as u128 + 1);
+                assert_eq!(received.0.hash, block.hash());
+            }
+            other => panic!("Wrong message type: {:?}", other),
+        }
+
+        // ...including inside a tagged request
+        let tagged = serde_json::to_vec(&MinerRequest { id: 7, message: submit }).unwrap();
+        match IncomingMessage::from_slice(&tagged).unwrap() {
+            IncomingMessage::Request(MinerRequest { id: 7, message: MinerMessage::SubmitBlock { block, .. } }) => {
+                assert_eq!(block.0.header.timestamp, u64::MAX as u128 + 1);
+            }
+            other => panic!("Wrong message type: {:?}", other),
+        }
+        let ping = serde_json::to_vec(&MinerRequest { id: 8, message: MinerMessage::Ping { sent_at: Some(1000) } }).unwrap();
+        assert!(matches!(IncomingMessage::from_slice(&ping).unwrap(),
+            IncomingMessage::Request(MinerRequest { id: 8, message: MinerMessage::Ping { sent_at: Some(1000) } })));
+
+        let key = crate::node_key::NodeKey::generate();
+        let vote = AcceptanceVote::sign(&key, &BlockHash::from_bytes([1; 32]), 0);
+        let peer = serde_json::to_vec(&PeerMessage::Vote(VoteData::from_vote(&vote))).unwrap();
+        match IncomingMessage::from_slice(&peer).unwrap() {
+            IncomingMessage::Peer(PeerMessage::Vote(data)) => assert_eq!(data.to_vote(), Ok(vote)),
+            other => panic!("Wrong message type: {:?}", other),
+        }
+
+        let get_peers = serde_json::to_vec(&PeerMessage::GetPeers { address: Some("10.0.0.1:8080".to_string()) }).unwrap();
+        assert!(matches!(IncomingMessage::from_slice(&get_peers).unwrap(),
+            IncomingMessage::Peer(PeerMessage::GetPeers { address: Some(_) })));
+    }
+
+    #[test]
+    fn test_receipt_data_roundtrip() {
+        let key = crate::node_key::NodeKey::generate();
+        let receipt = BlockReceipt::sign(&key, &BlockHash::from_bytes([1; 32]), 5, "alice", 1_000_000);
+
+        let json = serde_json::to_string(&ReceiptData::from_receipt(&receipt)).unwrap();
+        let data: ReceiptData = serde_json::from_str(&json).unwrap();
+        let restored = data.to_receipt().unwrap();
+
+        assert_eq!(restored, receipt);
+        assert_eq!(crate::receipt::verify_receipt(&restored, Some(&key.public_key())), Ok(()));
+    }
+
+    #[test]
+    fn test_utxo_snapshot_parts() {
+        let key = crate::node_key::NodeKey::generate();
+        let outputs = (1..=5).map(|i| crate::transaction::Output { to_addr: "alice".to_string(), value: i as f64, timestamp: 1000, lock_until: None }).collect();
+        let snapshot = UtxoSnapshot::new(9, BlockHash::from_bytes([2; 32]), outputs).sign(&key);
+
+        let parts: Vec<UtxoSnapshotData> = [0, 2, 4].iter()
+            .map(|offset| UtxoSnapshotData::from_snapshot(&snapshot, *offset, 2))
+            .map(|part| serde_json::from_str(&serde_json::to_string(&PeerMessage::UtxoSnapshot(part)).unwrap()).unwrap())
+            .map(|message| match message {
+                PeerMessage::UtxoSnapshot(part) => part,
+                other => panic!("Wrong message type: {:?}", other),
+            })
+            .collect();
+        let restored = UtxoSnapshotData::to_snapshot(&parts).unwrap();
+        assert_eq!((restored.height, restored.commitment()), (9, snapshot.commitment()));
+        assert_eq!(restored.verify_signature(), Ok(()));
+
+        // parts must cover the snapshot in order
+        assert!(UtxoSnapshotData::to_snapshot(&parts[..2]).is_err());
+        assert!(UtxoSnapshotData::to_snapshot(&[parts[0].clone(), parts[2].clone()]).is_err());
+    }
+
+    #[test]
+    fn test_history_roundtrip() {
+        let tx = AddressTx {
+            block_index: 3,
+            block_timestamp: u64::MAX as u128 + 1,
+            tx_hash: Txid::from_bytes([7; 32]),
+            received: 1.5,
+            sent: 0.0,
+        };
+        let msg = ValidatorMessage::History {
+            address: "alice".to_string(),
+            transactions: vec![AddressTxData::from_address_tx(&tx)],
+            next_cursor: Some("1".to_string()),
+        };
+
+        let json = serde_json::to_string(&msg).unwrap();
+        match serde_json::from_str::<ValidatorMessage>(&json).unwrap() {
+            ValidatorMessage::History { address, transactions, next_cursor } => {
+                assert_eq!(address, "alice");
+                assert_eq!(transactions[0].to_address_tx(), Ok(tx));
+                assert_eq!(next_cursor.as_deref(), Some("1"));
+            }
+            _ => panic!("Wrong message type"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "node")]
+    fn test_page_query() {
+        let mut validator = crate::Validator::new_with_params(crate::ChainParams::regtest());
+        validator.generate_blocks(5, "Alice").unwrap();
+        let chain = &validator.blockchain;
+
+        let mut page = PageQuery { limit: Some(2), from_height: Some(1), ..PageQuery::default() };
+        let mut heights = vec![];
+        loop {
+            let (blocks, next_cursor) = page.blocks(chain, None).unwrap();
+            assert!(blocks.len() <= 2);
+            heights.extend(blocks.iter().map(|block| block.header.index));
+            match next_cursor {
+                Some(cursor) => page = page.next(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(heights, vec![1, 2, 3, 4]);
+
+        let (transactions, next_cursor) = PageQuery { to_height: Some(1), ..PageQuery::default() }.history(chain, "Alice").unwrap();
+        assert_eq!((transactions.iter().map(|tx| tx.block_index).collect::<Vec<_>>(), next_cursor), (vec![0, 1], None));
+        assert!(PageQuery { cursor: Some("soon".to_string()), ..PageQuery::default() }.history(chain, "Alice").is_err());
+        assert!(PageQuery { from_height: Some(1), ..PageQuery::default() }.rounds(&RoundHistory::default(), None).is_err());
+
+        assert_eq!(PageQuery::from_query("limit=5&cursor=12&miner=bob"),
+            Ok(PageQuery { cursor: Some("12".to_string()), limit: Some(5), ..PageQuery::default() }));
+        assert!(PageQuery::from_query("to_height=tip").is_err());
+
+        // requests from before pagination still parse, as the first page of everything
+        match serde_json::from_str::<MinerMessage>(r#"{"GetHistory":{"address":"alice"}}"#).unwrap() {
+            MinerMessage::GetHistory { page, .. } => assert_eq!(page, PageQuery::default()),
+            other => panic!("Wrong message type: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_witness_data_roundtrip() {
+        let witnesses = vec![
+            Witness::Htlc {
+                contract: HtlcContract::new(vec![1; 32], "bob", "alice", u64::MAX as u128 + 1),
+                preimage: Some(b"secret".to_vec()),
+            },
+            Witness::ChannelClose {
+                terms: ChannelTerms {
+                    parties: [
+                        ChannelParty { address: "alice".to_string(), public_key: vec![2; 32] },
+                        ChannelParty { address: "bob".to_string(), public_key: vec![3; 32] },
+                    ],
+                    dispute_period_ms: 3_600_000,
+                },
+                state: SignedState {
+                    state: ChannelState {
+                        sequence: 4,
+                        balances: [1.5, 0.5],
+                        revocation_hashes: [vec![4; 32], vec![5; 32]],
+                        closing: false,
+                    },
+                    signatures: [vec![6; 64], vec![7; 64]],
+                },
+                unilateral: Some(UnilateralClose { closer: 1, timeout: u64::MAX as u128 + 2 }),
+            },
+            Witness::Signature { public_key: vec![8; 32], signature: vec![9; 64] },
+            Witness::Multisig {
+                policy: MultisigPolicy::new(1, vec![vec![10; 32], vec![11; 32]]).unwrap(),
+                signatures: vec![None, Some(vec![12; 64])],
+            },
+            Witness::ExtraNonce(u64::MAX),
+            Witness::Message("The Times 03/Jan/2009".to_string()),
+        ];
+
+        for witness in witnesses {
+            let json = serde_json::to_string(&WitnessData::from_witness(&witness)).unwrap();
+            let data: WitnessData = serde_json::from_str(&json).unwrap();
+            assert_eq!(data.to_witness(), Ok(witness));
+        }
+    }
+
+    #[test]
+    fn test_submit_block_without_salt() {
+        // Miners that predate commit-reveal omit the salt field entirely
+        use crate::canonical::CanonicalSerialize;
+
+        let block = hex::encode(Block::new(0, 1000, BlockHash::ZERO, vec![]).canonical_bytes());
+        let json = format!(r#"{{"SubmitBlock":{{"miner_id":"m","block":"{}"}}}}"#, block);
+        match serde_json::from_str::<MinerMessage>(&json).unwrap() {
+            MinerMessage::SubmitBlock { salt, .. } => assert!(salt.is_none()),
+            _ => panic!("Wrong message type"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "node")]
+    fn test_round_info_vrf_proof() {
+        let mut validator = crate::Validator::new_with_params(crate::ChainParams::regtest());
+        validator.generate_blocks(1, "Alice").unwrap();
+        let keypair = crate::vrf::VrfKeypair::generate();
+        let public_key = keypair.public_key();
+        validator.set_vrf_keypair(keypair);
+        validator.start_new_round();
+
+        let data = RoundInfoData::from_round_info(&validator.get_round_info(), Difficulty::from_target(0xFF));
+        let json = serde_json::to_string(&data).unwrap();
+        let mut data: RoundInfoData = serde_json::from_str(&json).unwrap();
+        let tip = validator.blockchain.blocks.last().unwrap();
+        let (tip_hash, tip_timestamp) = (tip.hash, tip.header.timestamp);
+
+        assert_eq!(data.verify_vrf_tonce(Some(&public_key), &tip_hash, Some(tip_timestamp)), Ok(true));
+        assert_eq!(data.verify_vrf_tonce(Some(&public_key), &tip_hash, None), Ok(true));
+        assert!(data.verify_vrf_tonce(Some(&[0; 32]), &tip_hash, Some(tip_timestamp)).is_err());
+
+        // a proof from the round after another block, or another timestamp, is refused
+        assert!(data.verify_vrf_tonce(None, &BlockHash::from_bytes([1; 32]), None).is_err());
+        assert!(data.verify_vrf_tonce(None, &tip_hash, Some(tip_timestamp + 1)).is_err());
+
+        data.tonce = data.tonce.map(|t| t % 31 + 1);
+        assert!(data.verify_vrf_tonce(None, &tip_hash, Some(tip_timestamp)).is_err());
+
+        // Rounds from validators without a VRF key carry no proof
+        data.vrf_proof = None;
+        assert_eq!(data.verify_vrf_tonce(None, &tip_hash, None), Ok(false));
+    }
 }
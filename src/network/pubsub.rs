@@ -0,0 +1,264 @@
+//! Pub/sub endpoint for raw blocks and transactions
+//!
+//! Indexers that want the chain as it grows subscribe to topics instead of
+//! polling, much like bitcoind's ZMQ notifications:
+//!
+//! - `hashblock` / `rawblock` — the hash, or the canonical bytes, of every accepted block
+//! - `hashtx` / `rawtx` — the same for every transaction entering the mempool,
+//!   and again for every transaction in an accepted block
+//!
+//! A subscriber connects and sends one line naming the topics it wants,
+//! separated by spaces (an empty line subscribes to all of them). The
+//! endpoint answers `OK` or `ERR <reason>` on a line of its own, then sends
+//! every message on those topics. A message has three parts, each a 4-byte
+//! big-endian length followed by that many bytes: the topic name, the body,
+//! and a 4-byte little-endian sequence number counting that topic's messages
+//! from 0. A subscriber that falls behind skips messages, which shows as a
+//! gap in the sequence. `PubSubMessage::read` decodes a message.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+use crate::canonical::CanonicalSerialize;
+use crate::hashable::Hashable;
+use crate::{Block, Transaction};
+use super::actor::ValidatorHandle;
+use super::retry::with_timeout;
+
+/// Messages buffered for each subscriber; one that falls further behind skips the oldest
+pub const PUBSUB_FEED_CAPACITY: usize = 1024;
+
+/// Subscription lines longer than this are refused
+const MAX_SUBSCRIPTION_LEN: usize = 1024;
+
+/// How long a new connection has to send its subscription
+const SUBSCRIPTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest part `PubSubMessage::read` accepts
+const MAX_PART_LEN: usize = 64 * 1024 * 1024;
+
+/// What a subscriber can ask for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topic {
+    HashBlock,
+    HashTx,
+    RawBlock,
+    RawTx,
+}
+
+impl Topic {
+    pub const ALL: [Topic; 4] = [Topic::HashBlock, Topic::HashTx, Topic::RawBlock, Topic::RawTx];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Topic::HashBlock => "hashblock",
+            Topic::HashTx => "hashtx",
+            Topic::RawBlock => "rawblock",
+            Topic::RawTx => "rawtx",
+        }
+    }
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Topic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        Topic::ALL.iter().copied().find(|topic| topic.name() == s)
+            .ok_or_else(|| format!("Unknown topic {}", s))
+    }
+}
+
+/// A block or transaction the validator accepted, as published by `Validator::subscribe_raw`
+#[derive(Clone)]
+pub enum RawPublication {
+    Block(Block),
+    /// A transaction entering the mempool, or confirmed in a block
+    Transaction(Transaction),
+}
+
+/// One message on a topic
+#[derive(Debug, Clone, PartialEq)]
+pub struct PubSubMessage {
+    pub topic: Topic,
+    pub body: Vec<u8>,
+    /// Messages on the topic before this one
+    pub sequence: u32,
+}
+
+impl PubSubMessage {
+    fn encode(&self) -> Vec<u8> {
+        let sequence = self.sequence.to_le_bytes();
+        let parts: [&[u8]; 3] = [self.topic.name().as_bytes(), &self.body, &sequence];
+        let mut bytes = Vec::with_capacity(parts.iter().map(|part| 4 + part.len()).sum());
+        for part in parts {
+            bytes.extend_from_slice(&(part.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(part);
+        }
+        bytes
+    }
+
+    /// Read the next message a pub/sub endpoint sends
+    pub async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Self> {
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+        let topic = read_part(reader).await?;
+        let topic = String::from_utf8_lossy(&topic).parse::<Topic>().map_err(invalid)?;
+        let body = read_part(reader).await?;
+        let sequence = read_part(reader).await?;
+        let sequence = <[u8; 4]>::try_from(sequence.as_slice())
+            .map_err(|_| invalid(format!("Sequence has {} bytes, not 4", sequence.len())))?;
+        Ok(PubSubMessage { topic, body, sequence: u32::from_le_bytes(sequence) })
+    }
+}
+
+async fn read_part<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let len = reader.read_u32().await? as usize;
+    if len > MAX_PART_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Message part of {} bytes is too large", len)));
+    }
+    let mut part = vec![0; len];
+    reader.read_exact(&mut part).await?;
+    Ok(part)
+}
+
+/// Serve subscribers on `address` until the listener fails
+pub async fn serve(address: String, validator: ValidatorHandle) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let listener = TcpListener::bind(&address).await?;
+    info!(%address, "pub/sub endpoint listening");
+    run(listener, validator).await
+}
+
+async fn run(listener: TcpListener, validator: ValidatorHandle) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let publications = validator.call(|validator| validator.subscribe_raw()).await;
+    let (messages, _) = broadcast::channel(PUBSUB_FEED_CAPACITY);
+    tokio::spawn(number_messages(publications, messages.clone()));
+
+    loop {
+        let (socket, peer) = listener.accept().await?;
+        let messages = messages.subscribe();
+        tokio::spawn(async move {
+            if let Err(e) = handle_subscriber(socket, messages).await {
+                debug!(%peer, error = %e, "pub/sub subscriber disconnected");
+            }
+        });
+    }
+}
+
+/// Turn the validator's publications into messages, numbering each topic's messages
+async fn number_messages(mut publications: broadcast::Receiver<RawPublication>, messages: broadcast::Sender<Arc<PubSubMessage>>) {
+    let mut sequences = [0u32; Topic::ALL.len()];
+    loop {
+        let publication = match publications.recv().await {
+            Ok(publication) => publication,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!(skipped, "pub/sub endpoint fell behind the validator; publications dropped");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let parts = match &publication {
+            RawPublication::Block(block) => [(Topic::HashBlock, block.hash.as_bytes().to_vec()), (Topic::RawBlock, block.canonical_bytes())],
+            RawPublication::Transaction(tx) => [(Topic::HashTx, tx.hash().as_bytes().to_vec()), (Topic::RawTx, tx.canonical_bytes())],
+        };
+        for (topic, body) in parts {
+            let sequence = &mut sequences[topic as usize];
+            // with no subscribers the send fails, but the topic's count still moves on
+            let _ = messages.send(Arc::new(PubSubMessage { topic, body, sequence: *sequence }));
+            *sequence = sequence.wrapping_add(1);
+        }
+    }
+}
+
+async fn handle_subscriber(socket: TcpStream, mut messages: broadcast::Receiver<Arc<PubSubMessage>>) -> io::Result<()> {
+    let mut socket = BufReader::new(socket);
+    let mut line = String::new();
+    with_timeout(SUBSCRIPTION_TIMEOUT, "subscription", async {
+        (&mut socket).take(MAX_SUBSCRIPTION_LEN as u64).read_line(&mut line).await
+    }).await?;
+
+    let topics = match parse_subscription(&line) {
+        Ok(topics) => topics,
+        Err(e) => {
+            socket.write_all(format!("ERR {}\n", e).as_bytes()).await?;
+            return Ok(());
+        }
+    };
+    socket.write_all(b"OK\n").await?;
+
+    loop {
+        match messages.recv().await {
+            Ok(message) if topics.contains(&message.topic) => socket.write_all(&message.encode()).await?,
+            Ok(_) => {}
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!(skipped, "pub/sub subscriber fell behind; messages dropped");
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+/// The topics named on a subscription line; none means all of them
+fn parse_subscription(line: &str) -> Result<Vec<Topic>, String> {
+    if !line.ends_with('\n') {
+        return Err("Subscription must be one line".to_string());
+    }
+    let topics = line.split_whitespace().map(str::parse).collect::<Result<Vec<Topic>, _>>()?;
+    Ok(if topics.is_empty() { Topic::ALL.to_vec() } else { topics })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canonical::CanonicalDeserialize;
+    use crate::{ChainParams, Validator};
+
+    #[test]
+    fn test_parse_subscription() {
+        assert_eq!(parse_subscription("rawblock hashtx\n"), Ok(vec![Topic::RawBlock, Topic::HashTx]));
+        assert_eq!(parse_subscription("\n"), Ok(Topic::ALL.to_vec()));
+        assert!(parse_subscription("rawblock sequence\n").is_err());
+        assert!(parse_subscription("rawblock").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_receives_its_topics() {
+        let validator = ValidatorHandle::spawn(Validator::new_with_params(ChainParams::regtest()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(run(listener, validator.clone()));
+
+        let mut subscriber = BufReader::new(TcpStream::connect(address).await.unwrap());
+        subscriber.write_all(b"hashblock rawtx\n").await.unwrap();
+        let mut answer = String::new();
+        subscriber.read_line(&mut answer).await.unwrap();
+        assert_eq!(answer, "OK\n");
+
+        let hashes = validator.call(|validator| validator.generate_blocks(2, "Alice")).await.unwrap();
+        for (sequence, hash) in hashes.iter().enumerate() {
+            let message = PubSubMessage::read(&mut subscriber).await.unwrap();
+            assert_eq!((message.topic, message.body.as_slice(), message.sequence), (Topic::HashBlock, &hash.as_bytes()[..], sequence as u32));
+            // each generated block holds just its coinbase
+            let message = PubSubMessage::read(&mut subscriber).await.unwrap();
+            assert_eq!((message.topic, message.sequence), (Topic::RawTx, sequence as u32));
+            assert_eq!(Transaction::from_canonical_bytes(&message.body).unwrap().outputs[0].to_addr, "Alice");
+        }
+
+        let mut refused = BufReader::new(TcpStream::connect(address).await.unwrap());
+        refused.write_all(b"rawblock mempool\n").await.unwrap();
+        let mut answer = String::new();
+        refused.read_line(&mut answer).await.unwrap();
+        assert_eq!(answer, "ERR Unknown topic mempool\n");
+    }
+}
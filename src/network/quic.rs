@@ -0,0 +1,184 @@
+//! QUIC for miner connections (feature `quic`)
+//!
+//! QUIC runs over UDP with TLS 1.3 built in, using the same `TlsIdentity`
+//! and `TlsTrust` as TLS over TCP. A miner keeps one QUIC connection per
+//! validator and opens a bidirectional stream on it for each dial. The
+//! connection survives the miner's address changing (a phone moving from
+//! Wi-Fi to mobile data), and keepalives stop NAT mappings from expiring.
+
+use std::convert::TryFrom;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{Endpoint, TransportConfig};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tracing::debug;
+use super::stream::{host_of, BoxFuture, BoxedStream, Dialer, Listener};
+use super::tls::{TlsIdentity, TlsTrust};
+
+/// QUIC keepalive, well inside common NAT timeouts
+const QUIC_KEEPALIVE: Duration = Duration::from_secs(10);
+
+/// Streams waiting for `accept`
+const ACCEPT_QUEUE: usize = 64;
+
+fn other_error(error: impl std::fmt::Display) -> io::Error {
+    io::Error::other(error.to_string())
+}
+
+fn transport_config() -> Arc<TransportConfig> {
+    let mut config = TransportConfig::default();
+    config.keep_alive_interval(Some(QUIC_KEEPALIVE));
+    Arc::new(config)
+}
+
+/// Look up `address`, preferring IPv4 (the client endpoint is bound to an IPv4 socket)
+async fn resolve(address: &str) -> io::Result<SocketAddr> {
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host(address).await?.collect();
+    addresses.iter().find(|address| address.is_ipv4()).or_else(|| addresses.first())
+        .copied()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No address for {}", address)))
+}
+
+/// QUIC, checking the validator's certificate against a `TlsTrust`
+pub struct QuicDialer {
+    endpoint: Endpoint,
+    /// The open connection and the address it goes to
+    connection: Mutex<Option<(String, quinn::Connection)>>,
+}
+
+impl QuicDialer {
+    pub fn new(trust: &TlsTrust) -> io::Result<Self> {
+        let crypto = QuicClientConfig::try_from(trust.client_config().map_err(other_error)?).map_err(other_error)?;
+        let mut config = quinn::ClientConfig::new(Arc::new(crypto));
+        config.transport_config(transport_config());
+
+        let mut endpoint = Endpoint::client(SocketAddr::from(([0, 0, 0, 0], 0)))?;
+        endpoint.set_default_client_config(config);
+        Ok(QuicDialer { endpoint, connection: Mutex::new(None) })
+    }
+
+    /// The open connection to `address`, connecting if there is none
+    async fn connection(&self, address: &str) -> io::Result<quinn::Connection> {
+        let mut cached = self.connection.lock().await;
+        if let Some((cached_address, connection)) = cached.as_ref() {
+            if cached_address == address && connection.close_reason().is_none() {
+                return Ok(connection.clone());
+            }
+        }
+
+        let remote = resolve(address).await?;
+        let connection = self.endpoint.connect(remote, host_of(address)).map_err(other_error)?.await?;
+        *cached = Some((address.to_string(), connection.clone()));
+        Ok(connection)
+    }
+}
+
+impl Dialer for QuicDialer {
+    fn dial<'a>(&'a self, address: &'a str) -> BoxFuture<'a, io::Result<BoxedStream>> {
+        Box::pin(async move {
+            let (send, recv) = self.connection(address).await?.open_bi().await?;
+            Ok(Box::new(tokio::io::join(recv, send)) as BoxedStream)
+        })
+    }
+}
+
+/// Accepts QUIC connections and hands out each stream a miner opens
+pub struct QuicListener {
+    endpoint: Endpoint,
+    streams: mpsc::Receiver<(BoxedStream, SocketAddr)>,
+    task: JoinHandle<()>,
+}
+
+impl QuicListener {
+    pub async fn bind(address: &str, identity: &TlsIdentity) -> io::Result<Self> {
+        let crypto = QuicServerConfig::try_from(identity.server_config().map_err(other_error)?).map_err(other_error)?;
+        let mut config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        config.transport_config(transport_config());
+
+        let endpoint = Endpoint::server(config, resolve(address).await?)?;
+        let (ready, streams) = mpsc::channel(ACCEPT_QUEUE);
+
+        let accepting = endpoint.clone();
+        let task = tokio::spawn(async move {
+            while let Some(incoming) = accepting.accept().await {
+                let ready = ready.clone();
+                tokio::spawn(async move {
+                    let connection = match incoming.await {
+                        Ok(connection) => connection,
+                        Err(e) => {
+                            debug!(error = %e, "QUIC handshake failed");
+                            return;
+                        }
+                    };
+                    // Streams are attributed to the address the connection was opened from
+                    let peer = connection.remote_address();
+                    while let Ok((send, recv)) = connection.accept_bi().await {
+                        let stream = Box::new(tokio::io::join(recv, send)) as BoxedStream;
+                        if ready.send((stream, peer)).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        Ok(QuicListener { endpoint, streams, task })
+    }
+}
+
+impl Listener for QuicListener {
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<(BoxedStream, SocketAddr)>> {
+        Box::pin(async move {
+            self.streams.recv().await
+                .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "QUIC listener stopped"))
+        })
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.endpoint.local_addr()
+    }
+}
+
+impl Drop for QuicListener {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::transport::{Transport, DEFAULT_MAX_FRAME_LEN};
+
+    #[tokio::test]
+    async fn test_quic_streams_share_a_connection() {
+        let identity = TlsIdentity::self_signed(vec!["localhost".to_string()]).unwrap();
+        let mut listener = QuicListener::bind("127.0.0.1:0", &identity).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut server = Transport::new(stream, DEFAULT_MAX_FRAME_LEN);
+                    while let Some(frame) = server.recv_frame().await.unwrap() {
+                        server.send_frame(frame).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        let dialer = QuicDialer::new(&TlsTrust::from_pem(identity.certificate_pem()).unwrap()).unwrap();
+        let address = format!("localhost:{}", port);
+        for word in ["hello", "again"] {
+            let mut client = Transport::new(dialer.dial(&address).await.unwrap(), DEFAULT_MAX_FRAME_LEN);
+            let echo: String = client.request(&word).await.unwrap();
+            assert_eq!(echo, word);
+        }
+        assert_eq!(dialer.endpoint.open_connections(), 1);
+    }
+}
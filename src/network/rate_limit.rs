@@ -0,0 +1,221 @@
+//! Token-bucket rate limiting for the validator server
+//!
+//! Every request costs a token from the bucket of the IP address it came
+//! from and, for requests that name a miner, from that miner's bucket too.
+//! Buckets refill continuously up to their burst size, so a well-behaved
+//! miner polling once a second never notices the limit while a client
+//! spamming `GetRoundInfo` is refused with `RateLimited`. The number of open
+//! connections per IP is capped separately.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets kept before idle (full) ones are dropped
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Sustained rate and burst allowance of one bucket
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Requests that can be made at once after a quiet period
+    pub burst: u32,
+    /// Requests per second refilled
+    pub per_second: f64,
+}
+
+/// Limits applied by `ValidatorServer`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitConfig {
+    /// Requests per client IP address
+    pub per_ip: RateLimit,
+    /// Requests naming each miner ID
+    pub per_miner: RateLimit,
+    /// Connections one IP address may hold open at once
+    pub max_connections_per_ip: usize,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            per_ip: RateLimit { burst: 50, per_second: 20.0 },
+            per_miner: RateLimit { burst: 10, per_second: 2.0 },
+            max_connections_per_ip: 32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token buckets keyed by client
+#[derive(Debug)]
+pub struct RateLimiter<K> {
+    limit: RateLimit,
+    buckets: HashMap<K, TokenBucket>,
+}
+
+impl<K: Hash + Eq + Clone> RateLimiter<K> {
+    pub fn new(limit: RateLimit) -> Self {
+        RateLimiter { limit, buckets: HashMap::new() }
+    }
+
+    /// Take a token for `key` at time `now`
+    ///
+    /// Returns how long until a token is available if the bucket is empty.
+    pub fn check(&mut self, key: &K, now: Instant) -> Result<(), Duration> {
+        if self.buckets.len() >= PRUNE_THRESHOLD && !self.buckets.contains_key(key) {
+            self.prune(now);
+        }
+
+        let limit = self.limit;
+        let burst = limit.burst as f64;
+        let bucket = self.buckets.entry(key.clone())
+            .or_insert(TokenBucket { tokens: burst, last_refill: now });
+        let elapsed = now.saturating_duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.per_second).min(burst);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if limit.per_second > 0.0 {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / limit.per_second))
+        } else {
+            Err(Duration::MAX)
+        }
+    }
+
+    /// Forget buckets that have refilled completely; they behave like new ones
+    fn prune(&mut self, now: Instant) {
+        let limit = self.limit;
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.last_refill).as_secs_f64();
+            bucket.tokens + elapsed * limit.per_second < limit.burst as f64
+        });
+    }
+}
+
+/// All the server's limits, shared between connections
+#[derive(Debug)]
+pub struct RateLimits {
+    config: RateLimitConfig,
+    per_ip: Mutex<RateLimiter<IpAddr>>,
+    per_miner: Mutex<RateLimiter<String>>,
+    connections: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl RateLimits {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimits {
+            config,
+            per_ip: Mutex::new(RateLimiter::new(config.per_ip)),
+            per_miner: Mutex::new(RateLimiter::new(config.per_miner)),
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> RateLimitConfig {
+        self.config
+    }
+
+    /// Take a token for a request from `ip`, naming `miner_id` if it does
+    pub fn check_request(&self, ip: IpAddr, miner_id: Option<&str>) -> Result<(), Duration> {
+        let now = Instant::now();
+        self.per_ip.lock().unwrap().check(&ip, now)?;
+        match miner_id {
+            Some(miner_id) => self.per_miner.lock().unwrap().check(&miner_id.to_string(), now),
+            None => Ok(()),
+        }
+    }
+
+    /// Count a new connection from `ip`, or refuse it if the IP is at its limit
+    ///
+    /// Call `close_connection` when an accepted connection ends.
+    pub fn open_connection(&self, ip: IpAddr) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+        let open = connections.entry(ip).or_insert(0);
+        if *open >= self.config.max_connections_per_ip {
+            return false;
+        }
+        *open += 1;
+        true
+    }
+
+    /// Connections open from every IP together
+    pub fn open_connections(&self) -> usize {
+        self.connections.lock().unwrap().values().sum()
+    }
+
+    pub fn close_connection(&self, ip: IpAddr) {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(open) = connections.get_mut(&ip) {
+            *open -= 1;
+            if *open == 0 {
+                connections.remove(&ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket_refill() {
+        let mut limiter = RateLimiter::new(RateLimit { burst: 3, per_second: 2.0 });
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check(&"alice", start).is_ok());
+        }
+        assert_eq!(limiter.check(&"alice", start), Err(Duration::from_millis(500)));
+        // Other keys have their own bucket
+        assert!(limiter.check(&"bob", start).is_ok());
+
+        // Half a second buys one more request, not a new burst
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.check(&"alice", later).is_ok());
+        assert!(limiter.check(&"alice", later).is_err());
+
+        // Buckets never hold more than the burst
+        let much_later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(limiter.check(&"alice", much_later).is_ok());
+        }
+        assert!(limiter.check(&"alice", much_later).is_err());
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let limits = RateLimits::new(RateLimitConfig { max_connections_per_ip: 2, ..RateLimitConfig::default() });
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        assert!(limits.open_connection(ip));
+        assert!(limits.open_connection(ip));
+        assert!(!limits.open_connection(ip));
+        assert!(limits.open_connection("10.0.0.2".parse().unwrap()));
+        assert_eq!(limits.open_connections(), 3);
+
+        limits.close_connection(ip);
+        assert!(limits.open_connection(ip));
+    }
+
+    #[test]
+    fn test_per_miner_limit_spans_addresses() {
+        let config = RateLimitConfig {
+            per_miner: RateLimit { burst: 1, per_second: 0.0 },
+            ..RateLimitConfig::default()
+        };
+        let limits = RateLimits::new(config);
+
+        assert!(limits.check_request("10.0.0.1".parse().unwrap(), Some("alice")).is_ok());
+        assert!(limits.check_request("10.0.0.2".parse().unwrap(), Some("alice")).is_err());
+        assert!(limits.check_request("10.0.0.2".parse().unwrap(), None).is_ok());
+    }
+}
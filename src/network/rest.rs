@@ -0,0 +1,272 @@
+//! Read-only HTTP endpoints for miner statistics
+//!
+//! Dashboards and scripts can't speak the length-prefixed protocol, so the
+//! validator can also answer a few plain HTTP GETs with JSON:
+//!
+//! - `GET /miners/<miner_id>` — the `MinerStatsData` of one miner
+//! - `GET /leaderboard?limit=<n>` — the top miners (10 by default)
+//! - `GET /analytics?bucket_secs=<n>` — the `FairnessReport` of the decision
+//!   log, if the validator keeps one (see the `analytics` module)
+//! - `GET /storage` — hits and misses of the block store's caches, if the
+//!   validator keeps a store (see the `block_store` module)
+//! - `GET /history/<address>` — the transactions touching an address
+//! - `GET /blocks?address=<address>` — full blocks, only those touching the
+//!   address if given
+//! - `GET /rounds?miner=<miner_id>` — round summaries, only the rounds the
+//!   miner took part in if given
+//!
+//! The last three are paginated: each takes `cursor` and `limit`, and the
+//! first two `from_height` and `to_height` (see `PageQuery`). Their reply
+//! carries a `next_cursor` while there are more pages.
+//!
+//! Each connection serves one request and is closed.
+
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info};
+use crate::analytics::{FairnessReport, DEFAULT_BUCKET_MS};
+use crate::block_store::BlockStore;
+use crate::miner_stats::{MinerStats, MinerStatsBook};
+use crate::validator::Validator;
+use super::actor::ValidatorHandle;
+use super::protocol::{BlockData, MinerStatsData, PageQuery, MAX_LEADERBOARD_SIZE};
+
+/// Requests larger than this are refused
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+/// Miners on the leaderboard when the request doesn't say
+const DEFAULT_LEADERBOARD_SIZE: u32 = 10;
+
+/// Serve the endpoints on `address` until the listener fails
+pub async fn serve(address: String, validator: ValidatorHandle) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let listener = TcpListener::bind(&address).await?;
+    info!(%address, "REST endpoint listening");
+
+    loop {
+        let (socket, peer) = listener.accept().await?;
+        let validator = validator.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(socket, validator).await {
+                debug!(%peer, error = %e, "REST request failed");
+            }
+        });
+    }
+}
+
+async fn handle_request(mut socket: TcpStream, validator: ValidatorHandle) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let n = socket.read(&mut buffer).await?;
+        if n == 0 || request.len() + n > MAX_REQUEST_LEN {
+            break;
+        }
+        request.extend_from_slice(&buffer[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split_whitespace();
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(path)) if path.split('?').next() == Some("/analytics") => {
+            let (log, tonce_bits) = validator.call(|validator| {
+                (validator.decision_log_path().map(Path::to_path_buf), validator.get_params().tonce_bits)
+            }).await;
+            analytics(path, log.as_deref(), tonce_bits)
+        }
+        (Some("GET"), Some(path)) if path.split('?').next() == Some("/storage") => {
+            match validator.call(|validator| validator.block_store().map(BlockStore::cache_stats)).await {
+                Some(stats) => (200, to_json(&stats)),
+                None => (404, error_body("The validator keeps no block store")),
+            }
+        }
+        (Some("GET"), Some(path)) => {
+            let path = path.to_string();
+            validator.call(move |validator| {
+                listing(&path, validator).unwrap_or_else(|| route(&path, validator.miner_stats()))
+            }).await
+        }
+        (Some(_), Some(_)) => (405, error_body("Only GET is supported")),
+        _ => (400, error_body("Malformed request")),
+    };
+
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        500 => "Internal Server Error",
+        _ => "Method Not Allowed",
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, reason, body.len(), body
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+/// Status code and JSON body for a GET of `path`
+pub fn route(path: &str, stats: &MinerStatsBook) -> (u16, String) {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+
+    if let Some(miner_id) = path.strip_prefix("/miners/").filter(|id| !id.is_empty() && !id.contains('/')) {
+        let stats = stats.get(miner_id).cloned().unwrap_or_else(|| MinerStats::new(miner_id));
+        return (200, to_json(&MinerStatsData::from_stats(&stats)));
+    }
+
+    if path == "/leaderboard" {
+        let limit = match query.split('&').find_map(|param| param.strip_prefix("limit=")) {
+            Some(limit) => match limit.parse::<u32>() {
+                Ok(limit) => limit.min(MAX_LEADERBOARD_SIZE),
+                Err(_) => return (400, error_body("limit must be a number")),
+            },
+            None => DEFAULT_LEADERBOARD_SIZE,
+        };
+        let miners: Vec<MinerStatsData> = stats.leaderboard(limit as usize).into_iter()
+            .map(MinerStatsData::from_stats)
+            .collect();
+        return (200, to_json(&miners));
+    }
+
+    (404, error_body("Unknown endpoint"))
+}
+
+/// Status code and JSON body for a GET of one of the paginated listings, or `None` if `path` isn't one
+pub fn listing(path: &str, validator: &Validator) -> Option<(u16, String)> {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let param = |name: &str| query.split('&')
+        .find_map(|param| param.strip_prefix(name).and_then(|rest| rest.strip_prefix('=')))
+        .filter(|value| !value.is_empty());
+
+    let page = match PageQuery::from_query(query) {
+        Ok(page) => page,
+        Err(e) => return Some((400, error_body(&e))),
+    };
+    let body = if let Some(address) = path.strip_prefix("/history/").filter(|address| !address.is_empty() && !address.contains('/')) {
+        page.history(&validator.blockchain, address).map(|(transactions, next_cursor)| {
+            serde_json::json!({ "address": address, "transactions": transactions, "next_cursor": next_cursor })
+        })
+    } else if path == "/blocks" {
+        page.blocks(&validator.blockchain, param("address")).map(|(blocks, next_cursor)| {
+            let blocks: Vec<BlockData> = blocks.into_iter().map(BlockData::from_block).collect();
+            serde_json::json!({ "blocks": blocks, "next_cursor": next_cursor })
+        })
+    } else if path == "/rounds" {
+        page.rounds(validator.round_history(), param("miner")).map(|(rounds, next_cursor)| {
+            serde_json::json!({ "rounds": rounds, "next_cursor": next_cursor })
+        })
+    } else {
+        return None;
+    };
+    Some(match body {
+        Ok(body) => (200, body.to_string()),
+        Err(e) => (400, error_body(&e)),
+    })
+}
+
+/// Status code and JSON body for `GET /analytics`, reporting on the decision log at `log`
+pub fn analytics(path: &str, log: Option<&Path>, tonce_bits: u8) -> (u16, String) {
+    let query = path.split_once('?').map_or("", |(_, query)| query);
+    let bucket_ms = match query.split('&').find_map(|param| param.strip_prefix("bucket_secs=")) {
+        Some(secs) => match secs.parse::<u64>() {
+            Ok(secs) if secs > 0 => u128::from(secs) * 1000,
+            _ => return (400, error_body("bucket_secs must be a positive number")),
+        },
+        None => DEFAULT_BUCKET_MS,
+    };
+    let Some(log) = log else {
+        return (404, error_body("The validator keeps no decision log"));
+    };
+    match FairnessReport::from_log(log, tonce_bits, bucket_ms) {
+        Ok(report) => (200, to_json(&report)),
+        Err(e) => (500, error_body(&e)),
+    }
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_else(|e| error_body(&e.to_string()))
+}
+
+fn error_body(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChainParams;
+
+    #[test]
+    fn test_routes() {
+        let mut stats = MinerStatsBook::new();
+        stats.record_accepted("alice", 30_000, 3_600_000, 1000);
+        stats.record_accepted("bob", 10_000, 3_600_000, 2000);
+        stats.record_accepted("bob", 10_000, 3_600_000, 3000);
+
+        let (status, body) = route("/miners/alice", &stats);
+        assert_eq!(status, 200);
+        let alice: MinerStatsData = serde_json::from_str(&body).unwrap();
+        assert_eq!(alice.blocks_accepted, 1);
+        assert_eq!(alice.average_time_to_solution_ms, Some(30_000));
+
+        let (status, body) = route("/leaderboard?limit=1", &stats);
+        assert_eq!(status, 200);
+        let top: Vec<MinerStatsData> = serde_json::from_str(&body).unwrap();
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].miner_id, "bob");
+
+        assert_eq!(route("/leaderboard", &stats).0, 200);
+        assert_eq!(route("/leaderboard?limit=lots", &stats).0, 400);
+        assert_eq!(route("/miners/", &stats).0, 404);
+        assert_eq!(route("/blocks", &stats).0, 404);
+    }
+
+    #[test]
+    fn test_listings() {
+        let mut validator = Validator::new_with_params(ChainParams::regtest());
+        validator.generate_blocks(3, "Alice").unwrap();
+        validator.generate_blocks(2, "Bob").unwrap();
+
+        let (status, body) = listing("/blocks?limit=2&from_height=1", &validator).unwrap();
+        assert_eq!(status, 200);
+        let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(page["blocks"].as_array().unwrap().iter().map(|block| block["index"].as_u64().unwrap()).collect::<Vec<_>>(), vec![1, 2]);
+        let cursor = page["next_cursor"].as_str().unwrap().to_string();
+
+        let (_, body) = listing(&format!("/blocks?limit=2&from_height=1&cursor={}", cursor), &validator).unwrap();
+        let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(page["blocks"].as_array().unwrap().len(), 2);
+        assert!(page["next_cursor"].is_null());
+
+        let (_, body) = listing("/blocks?address=Bob", &validator).unwrap();
+        let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(page["blocks"].as_array().unwrap().len(), 2);
+
+        let (status, body) = listing("/history/Alice?limit=1", &validator).unwrap();
+        assert_eq!(status, 200);
+        let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!((page["address"].as_str(), page["transactions"].as_array().unwrap().len()), (Some("Alice"), 1));
+        assert!(page["next_cursor"].is_string());
+
+        assert_eq!(listing("/rounds?miner=Alice", &validator).unwrap().0, 200);
+        assert_eq!(listing("/rounds?from_height=1", &validator).unwrap().0, 400);
+        assert_eq!(listing("/blocks?limit=lots", &validator).unwrap().0, 400);
+        assert_eq!(listing("/blocks?cursor=later", &validator).unwrap().0, 400);
+        assert!(listing("/leaderboard", &validator).is_none());
+    }
+
+    #[test]
+    fn test_analytics() {
+        assert_eq!(analytics("/analytics", None, 5).0, 404);
+        assert_eq!(analytics("/analytics?bucket_secs=0", None, 5).0, 400);
+
+        let path = std::env::temp_dir().join(format!("hourcoin-rest-analytics-{}.log", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let (status, body) = analytics("/analytics?bucket_secs=30", Some(&path), 5);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(status, 200);
+        let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(report["decisions"], 0);
+        assert_eq!(report["tonces"]["tonce_bits"], 5);
+    }
+}
@@ -0,0 +1,159 @@
+//! Timeouts and retries for network connections
+//!
+//! Every connect, read and write runs under a `Timeouts` limit, so a stalled
+//! peer fails the operation instead of hanging its task. Operations that may
+//! safely run again are retried under a `RetryPolicy`, which waits an
+//! exponentially growing delay between attempts.
+
+use std::fmt::Display;
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+use tracing::warn;
+
+/// Limits on how long each step of a connection may take
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+    /// Establishing a TCP connection
+    pub connect: Duration,
+    /// Waiting for a reply (clients) or the next request (servers)
+    pub read: Duration,
+    /// Writing one message
+    pub write: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: Duration::from_secs(5),
+            read: Duration::from_secs(30),
+            write: Duration::from_secs(10),
+        }
+    }
+}
+
+/// How often, and how patiently, a failed operation is retried
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first (at least 1)
+    pub max_attempts: u32,
+    /// Delay before the first retry; each later delay doubles
+    pub initial_backoff: Duration,
+    /// Longest delay between attempts
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Try once and never retry
+    pub fn none() -> Self {
+        RetryPolicy { max_attempts: 1, ..Self::default() }
+    }
+
+    /// Delay after failed attempt number `attempt` (counting from 1)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let doublings = attempt.saturating_sub(1).min(31);
+        self.initial_backoff.saturating_mul(1 << doublings).min(self.max_backoff)
+    }
+
+    /// Run `operation` until it succeeds, fails with an error `should_retry`
+    /// refuses, or runs out of attempts
+    pub async fn retry<T, E, F, Fut>(&self, what: &str, mut operation: F, should_retry: impl Fn(&E) -> bool) -> Result<T, E>
+    where
+        E: Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < self.max_attempts && should_retry(&e) => {
+                    let delay = self.backoff(attempt);
+                    warn!(what, attempt, retry_in_ms = delay.as_millis() as u64, error = %e, "retrying");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Run `future`, failing with `TimedOut` if it takes longer than `limit`
+pub async fn with_timeout<T>(limit: Duration, what: &str, future: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+    match tokio::time::timeout(limit, future).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("{} timed out after {}ms", what, limit.as_millis()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(6), Duration::from_millis(3200));
+        assert_eq!(policy.backoff(7), policy.max_backoff);
+        assert_eq!(policy.backoff(u32::MAX), policy.max_backoff);
+    }
+
+    #[tokio::test]
+    async fn test_retry_stops_on_success_or_refusal() {
+        let policy = RetryPolicy { initial_backoff: Duration::from_millis(1), ..RetryPolicy::default() };
+
+        // Succeeds on the third attempt
+        let calls = AtomicU32::new(0);
+        let result: Result<u32, String> = policy.retry("test", || async {
+            let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            if n < 3 { Err(format!("attempt {}", n)) } else { Ok(n) }
+        }, |_| true).await;
+        assert_eq!(result, Ok(3));
+
+        // Gives up after max_attempts
+        calls.store(0, Ordering::SeqCst);
+        let result: Result<(), String> = policy.retry("test", || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err("down".to_string())
+        }, |_| true).await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), policy.max_attempts);
+
+        // Errors the caller won't retry end it at once
+        calls.store(0, Ordering::SeqCst);
+        let _: Result<(), String> = policy.retry("test", || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err("fatal".to_string())
+        }, |e| e != "fatal").await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_with_timeout() {
+        let slow = with_timeout(Duration::from_millis(10), "read", async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        }).await;
+        assert_eq!(slow.unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+        let fast = with_timeout(Duration::from_secs(5), "read", async { Ok(1) }).await;
+        assert_eq!(fast.unwrap(), 1);
+    }
+}
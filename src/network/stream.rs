@@ -0,0 +1,100 @@
+//! Byte-stream transports for miner connections
+//!
+//! The protocol layer (`Transport` framing, multiplexed `Connection`s) runs
+//! over any ordered byte stream. A `Dialer` opens streams to a validator and
+//! a `Listener` accepts them, so plain TCP, TLS over TCP (feature `tls`) and
+//! QUIC (feature `quic`) carry the same messages.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A bidirectional byte stream the protocol can run over
+pub trait ByteStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ByteStream for T {}
+
+pub type BoxedStream = Box<dyn ByteStream>;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Opens streams to a validator
+pub trait Dialer: Send + Sync {
+    /// Open a stream to the validator at `address` (`host:port`)
+    fn dial<'a>(&'a self, address: &'a str) -> BoxFuture<'a, io::Result<BoxedStream>>;
+}
+
+/// Accepts streams from miners
+pub trait Listener: Send {
+    /// Wait for the next stream and the address it came from
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<(BoxedStream, SocketAddr)>>;
+
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+/// Plain TCP
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpDialer;
+
+impl Dialer for TcpDialer {
+    fn dial<'a>(&'a self, address: &'a str) -> BoxFuture<'a, io::Result<BoxedStream>> {
+        Box::pin(async move {
+            let stream = TcpStream::connect(address).await?;
+            Ok(Box::new(stream) as BoxedStream)
+        })
+    }
+}
+
+impl Listener for TcpListener {
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<(BoxedStream, SocketAddr)>> {
+        Box::pin(async move {
+            let (stream, address) = TcpListener::accept(self).await?;
+            Ok((Box::new(stream) as BoxedStream, address))
+        })
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpListener::local_addr(self)
+    }
+}
+
+/// The host part of a `host:port` address
+pub fn host_of(address: &str) -> &str {
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::transport::{Transport, DEFAULT_MAX_FRAME_LEN};
+
+    #[test]
+    fn test_host_of() {
+        assert_eq!(host_of("validator.example:8080"), "validator.example");
+        assert_eq!(host_of("127.0.0.1:8080"), "127.0.0.1");
+        assert_eq!(host_of("[::1]:8080"), "::1");
+        assert_eq!(host_of("localhost"), "localhost");
+    }
+
+    #[tokio::test]
+    async fn test_tcp_round_trip() {
+        let mut listener: Box<dyn Listener> = Box::new(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        let address = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut server = Transport::new(stream, DEFAULT_MAX_FRAME_LEN);
+            let frame = server.recv_frame().await.unwrap().unwrap();
+            server.send_frame(frame).await.unwrap();
+        });
+
+        let stream = TcpDialer.dial(&address).await.unwrap();
+        let mut client = Transport::new(stream, DEFAULT_MAX_FRAME_LEN);
+        let echo: String = client.request(&"hello").await.unwrap();
+        assert_eq!(echo, "hello");
+    }
+}
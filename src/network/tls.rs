@@ -0,0 +1,294 @@
+//! TLS over TCP for miner connections (feature `tls`)
+//!
+//! The validator presents a `TlsIdentity`: a certificate and key loaded from
+//! PEM files, or a self-signed pair generated on first start and saved for
+//! next time. Miners trust it through a `TlsTrust` built from the
+//! certificate's PEM, so a self-signed validator works once its certificate
+//! is copied to the miner. Only TLS 1.3 is offered.
+
+use std::convert::TryFrom;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::RootCertStore;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tracing::debug;
+use super::stream::{host_of, BoxFuture, BoxedStream, Dialer, Listener};
+
+/// ALPN protocol name offered by miners and validators
+pub const ALPN_PROTOCOL: &[u8] = b"hourcoin";
+
+/// How long a client has to finish the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handshaken streams waiting for `accept`
+const ACCEPT_QUEUE: usize = 64;
+
+pub(crate) fn crypto_provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// A validator's certificate chain and private key, PEM encoded
+#[derive(Clone)]
+pub struct TlsIdentity {
+    cert_pem: String,
+    key_pem: String,
+}
+
+impl TlsIdentity {
+    /// A self-signed certificate valid for `names` (hostnames or IP addresses)
+    pub fn self_signed(names: Vec<String>) -> Result<Self, String> {
+        let certified = rcgen::generate_simple_self_signed(names)
+            .map_err(|e| format!("Failed to generate certificate: {}", e))?;
+        Ok(TlsIdentity { cert_pem: certified.cert.pem(), key_pem: certified.key_pair.serialize_pem() })
+    }
+
+    pub fn from_pem(cert_pem: String, key_pem: String) -> Result<Self, String> {
+        let identity = TlsIdentity { cert_pem, key_pem };
+        identity.certificates()?;
+        identity.private_key()?;
+        Ok(identity)
+    }
+
+    pub fn load(cert_path: &Path, key_path: &Path) -> Result<Self, String> {
+        let read = |path: &Path| std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e));
+        Self::from_pem(read(cert_path)?, read(key_path)?)
+    }
+
+    /// Load the identity at the given paths, or generate a self-signed one for `names` and save it there
+    pub fn load_or_generate(cert_path: &Path, key_path: &Path, names: Vec<String>) -> Result<Self, String> {
+        if cert_path.exists() || key_path.exists() {
+            return Self::load(cert_path, key_path);
+        }
+        let identity = Self::self_signed(names)?;
+        identity.save(cert_path, key_path)?;
+        Ok(identity)
+    }
+
+    pub fn save(&self, cert_path: &Path, key_path: &Path) -> Result<(), String> {
+        std::fs::write(cert_path, &self.cert_pem)
+            .map_err(|e| format!("Failed to write {}: {}", cert_path.display(), e))?;
+        std::fs::write(key_path, &self.key_pem)
+            .map_err(|e| format!("Failed to write {}: {}", key_path.display(), e))
+    }
+
+    /// The certificate chain, for miners to trust
+    pub fn certificate_pem(&self) -> &str {
+        &self.cert_pem
+    }
+
+    fn certificates(&self) -> Result<Vec<CertificateDer<'static>>, String> {
+        let certs = rustls_pemfile::certs(&mut self.cert_pem.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Invalid certificate PEM: {}", e))?;
+        if certs.is_empty() {
+            return Err("No certificate in PEM".to_string());
+        }
+        Ok(certs)
+    }
+
+    fn private_key(&self) -> Result<PrivateKeyDer<'static>, String> {
+        rustls_pemfile::private_key(&mut self.key_pem.as_bytes())
+            .map_err(|e| format!("Invalid private key PEM: {}", e))?
+            .ok_or_else(|| "No private key in PEM".to_string())
+    }
+
+    pub(crate) fn server_config(&self) -> Result<rustls::ServerConfig, String> {
+        let mut config = rustls::ServerConfig::builder_with_provider(crypto_provider())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(|e| e.to_string())?
+            .with_no_client_auth()
+            .with_single_cert(self.certificates()?, self.private_key()?)
+            .map_err(|e| format!("Certificate and key don't match: {}", e))?;
+        config.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
+        Ok(config)
+    }
+}
+
+/// The certificates a miner accepts from validators
+#[derive(Debug, Clone)]
+pub struct TlsTrust {
+    roots: RootCertStore,
+}
+
+impl TlsTrust {
+    /// Trust every certificate in `pem`
+    pub fn from_pem(pem: &str) -> Result<Self, String> {
+        let mut roots = RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut pem.as_bytes()) {
+            let cert = cert.map_err(|e| format!("Invalid certificate PEM: {}", e))?;
+            roots.add(cert).map_err(|e| format!("Unusable certificate: {}", e))?;
+        }
+        if roots.is_empty() {
+            return Err("No certificate in PEM".to_string());
+        }
+        Ok(TlsTrust { roots })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let pem = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::from_pem(&pem)
+    }
+
+    pub(crate) fn client_config(&self) -> Result<rustls::ClientConfig, String> {
+        let mut config = rustls::ClientConfig::builder_with_provider(crypto_provider())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(|e| e.to_string())?
+            .with_root_certificates(self.roots.clone())
+            .with_no_client_auth();
+        config.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
+        Ok(config)
+    }
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// TLS over TCP, checking the validator's certificate against a `TlsTrust`
+#[derive(Clone)]
+pub struct TlsDialer {
+    connector: TlsConnector,
+}
+
+impl TlsDialer {
+    pub fn new(trust: &TlsTrust) -> Result<Self, String> {
+        Ok(TlsDialer { connector: TlsConnector::from(Arc::new(trust.client_config()?)) })
+    }
+}
+
+impl Dialer for TlsDialer {
+    fn dial<'a>(&'a self, address: &'a str) -> BoxFuture<'a, io::Result<BoxedStream>> {
+        Box::pin(async move {
+            let name = ServerName::try_from(host_of(address).to_string())
+                .map_err(|e| invalid_input(format!("Invalid server name in {}: {}", address, e)))?;
+            let tcp = TcpStream::connect(address).await?;
+            let stream = self.connector.connect(name, tcp).await?;
+            Ok(Box::new(stream) as BoxedStream)
+        })
+    }
+}
+
+/// Accepts TLS over TCP
+///
+/// Handshakes run in their own tasks, so a slow client can't hold up the others.
+pub struct TlsListener {
+    local_addr: SocketAddr,
+    streams: mpsc::Receiver<(BoxedStream, SocketAddr)>,
+    task: JoinHandle<()>,
+}
+
+impl TlsListener {
+    pub async fn bind(address: &str, identity: &TlsIdentity) -> io::Result<Self> {
+        let acceptor = TlsAcceptor::from(Arc::new(identity.server_config().map_err(invalid_input)?));
+        let tcp = TcpListener::bind(address).await?;
+        let local_addr = tcp.local_addr()?;
+        let (ready, streams) = mpsc::channel(ACCEPT_QUEUE);
+
+        let task = tokio::spawn(async move {
+            loop {
+                let (socket, peer) = match tcp.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        debug!(error = %e, "TLS accept failed");
+                        continue;
+                    }
+                };
+                let acceptor = acceptor.clone();
+                let ready = ready.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(socket)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = ready.send((Box::new(stream) as BoxedStream, peer)).await;
+                        }
+                        Ok(Err(e)) => debug!(%peer, error = %e, "TLS handshake failed"),
+                        Err(_) => debug!(%peer, "TLS handshake timed out"),
+                    }
+                });
+            }
+        });
+
+        Ok(TlsListener { local_addr, streams, task })
+    }
+}
+
+impl Listener for TlsListener {
+    fn accept(&mut self) -> BoxFuture<'_, io::Result<(BoxedStream, SocketAddr)>> {
+        Box::pin(async move {
+            self.streams.recv().await
+                .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "TLS listener stopped"))
+        })
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+}
+
+impl Drop for TlsListener {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::transport::{Transport, DEFAULT_MAX_FRAME_LEN};
+
+    #[tokio::test]
+    async fn test_tls_round_trip() {
+        let identity = TlsIdentity::self_signed(vec!["localhost".to_string()]).unwrap();
+        let mut listener = TlsListener::bind("127.0.0.1:0", &identity).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut server = Transport::new(stream, DEFAULT_MAX_FRAME_LEN);
+            let frame = server.recv_frame().await.unwrap().unwrap();
+            server.send_frame(frame).await.unwrap();
+        });
+
+        let trust = TlsTrust::from_pem(identity.certificate_pem()).unwrap();
+        let dialer = TlsDialer::new(&trust).unwrap();
+        let stream = dialer.dial(&format!("localhost:{}", port)).await.unwrap();
+        let mut client = Transport::new(stream, DEFAULT_MAX_FRAME_LEN);
+        let echo: String = client.request(&"hello").await.unwrap();
+        assert_eq!(echo, "hello");
+    }
+
+    #[tokio::test]
+    async fn test_untrusted_certificate_refused() {
+        let identity = TlsIdentity::self_signed(vec!["localhost".to_string()]).unwrap();
+        let listener = TlsListener::bind("127.0.0.1:0", &identity).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let other = TlsIdentity::self_signed(vec!["localhost".to_string()]).unwrap();
+        let dialer = TlsDialer::new(&TlsTrust::from_pem(other.certificate_pem()).unwrap()).unwrap();
+        assert!(dialer.dial(&format!("localhost:{}", port)).await.is_err());
+    }
+
+    #[test]
+    fn test_identity_save_and_load() {
+        let dir = std::env::temp_dir();
+        let cert_path = dir.join(format!("hourcoin-tls-cert-{}.pem", std::process::id()));
+        let key_path = dir.join(format!("hourcoin-tls-key-{}.pem", std::process::id()));
+
+        let generated = TlsIdentity::load_or_generate(&cert_path, &key_path, vec!["localhost".to_string()]).unwrap();
+        let loaded = TlsIdentity::load_or_generate(&cert_path, &key_path, vec![]).unwrap();
+        assert_eq!(loaded.certificate_pem(), generated.certificate_pem());
+        assert!(loaded.server_config().is_ok());
+
+        std::fs::remove_file(&cert_path).unwrap();
+        std::fs::remove_file(&key_path).unwrap();
+        assert!(TlsTrust::from_pem("not a certificate").is_err());
+    }
+}
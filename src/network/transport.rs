@@ -0,0 +1,242 @@
+//! Length-delimited message framing
+//!
+//! Every message on the wire is a 4-byte big-endian length followed by that
+//! many bytes of JSON. `Transport` wraps a stream in tokio_util's
+//! `LengthDelimitedCodec`, which buffers partial reads until a whole frame
+//! has arrived and refuses frames over the configured maximum before
+//! allocating for them. Reads and writes can be bounded by `Timeouts`, and
+//! frames can be compressed once both ends agree (see `compression`).
+//!
+//! Frames are `Bytes` rather than fresh `Vec`s. A received frame is a view of
+//! the codec's read buffer, and messages are encoded into a write buffer that
+//! each `Transport` keeps. Once a frame is dropped, its space is reused for
+//! the next one, so a long-lived connection stops allocating per message.
+
+use std::io;
+use std::time::Duration;
+use bytes::{BufMut, Bytes, BytesMut};
+use futures_util::{SinkExt, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use super::compression::FrameCompression;
+use super::retry::{with_timeout, Timeouts};
+
+/// Largest frame accepted unless configured otherwise (1MB)
+pub const DEFAULT_MAX_FRAME_LEN: usize = 1024 * 1024;
+
+/// The codec used by validators and miners, refusing frames over `max_frame_len` bytes
+pub fn codec(max_frame_len: usize) -> LengthDelimitedCodec {
+    LengthDelimitedCodec::builder()
+        .length_field_length(4)
+        .big_endian()
+        .max_frame_length(max_frame_len)
+        .new_codec()
+}
+
+/// A connection carrying length-delimited JSON messages
+#[derive(Debug)]
+pub struct Transport<T = TcpStream> {
+    framed: Framed<T, LengthDelimitedCodec>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    compression: Option<FrameCompression>,
+    /// Where outgoing messages are encoded; frames split off it give their space back once sent
+    write_buffer: BytesMut,
+}
+
+impl Transport<TcpStream> {
+    /// Connect to `address` with the default frame limit, applying `timeouts` to every step
+    pub async fn connect(address: &str, timeouts: Timeouts) -> io::Result<Self> {
+        let stream = with_timeout(timeouts.connect, "connect", TcpStream::connect(address)).await?;
+        Ok(Transport::new(stream, DEFAULT_MAX_FRAME_LEN).with_timeouts(timeouts))
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Transport<T> {
+    pub fn new(io: T, max_frame_len: usize) -> Self {
+        Transport {
+            framed: Framed::new(io, codec(max_frame_len)),
+            read_timeout: None,
+            write_timeout: None,
+            compression: None,
+            write_buffer: BytesMut::new(),
+        }
+    }
+
+    /// Fail reads and writes that take longer than `timeouts` allows
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.read_timeout = Some(timeouts.read);
+        self.write_timeout = Some(timeouts.write);
+        self
+    }
+
+    /// Compress frames from now on, as negotiated with the peer (`None` for plain frames)
+    pub fn set_compression(&mut self, compression: Option<FrameCompression>) {
+        self.compression = compression;
+    }
+
+    /// Send one frame
+    pub async fn send_frame(&mut self, frame: impl Into<Bytes>) -> io::Result<()> {
+        let frame = frame.into();
+        let frame = match &self.compression {
+            Some(compression) => Bytes::from(compression.encode(&frame)?),
+            None => frame,
+        };
+        let send = self.framed.send(frame);
+        match self.write_timeout {
+            Some(limit) => with_timeout(limit, "write", send).await,
+            None => send.await,
+        }
+    }
+
+    /// Wait for the next whole frame; `None` once the peer closes the connection
+    pub async fn recv_frame(&mut self) -> io::Result<Option<Bytes>> {
+        let framed = &mut self.framed;
+        let compression = self.compression;
+        let next = async {
+            match framed.next().await {
+                Some(frame) => {
+                    let frame = frame?.freeze();
+                    match compression {
+                        Some(compression) => Ok(Some(compression.decode(frame, framed.codec().max_frame_length())?)),
+                        None => Ok(Some(frame)),
+                    }
+                }
+                None => Ok(None),
+            }
+        };
+        match self.read_timeout {
+            Some(limit) => with_timeout(limit, "read", next).await,
+            None => next.await,
+        }
+    }
+
+    /// Send `message` as JSON
+    pub async fn send<M: Serialize>(&mut self, message: &M) -> io::Result<()> {
+        let frame = encode_json(&mut self.write_buffer, message)?;
+        self.send_frame(frame).await
+    }
+
+    /// Send `message` and wait for the JSON reply
+    pub async fn request<M: Serialize, R: DeserializeOwned>(&mut self, message: &M) -> io::Result<R> {
+        self.send(message).await?;
+        match self.recv_frame().await? {
+            Some(frame) => Ok(serde_json::from_slice(&frame)?),
+            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed before a reply")),
+        }
+    }
+}
+
+/// Encode `message` as JSON at the end of `buffer` and split it off as a frame
+///
+/// The buffer's spare capacity is used first, and space held by earlier
+/// frames is reclaimed once they have all been dropped.
+pub fn encode_json<M: Serialize>(buffer: &mut BytesMut, message: &M) -> serde_json::Result<Bytes> {
+    if let Err(e) = serde_json::to_writer((&mut *buffer).writer(), message) {
+        buffer.clear();
+        return Err(e);
+    }
+    Ok(buffer.split().freeze())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+    use crate::network::compression::Compression;
+
+    #[tokio::test]
+    async fn test_frames_survive_partial_reads() {
+        let (client, mut server) = tokio::io::duplex(64);
+        let mut transport = Transport::new(client, DEFAULT_MAX_FRAME_LEN);
+
+        // A frame written a few bytes at a time still arrives whole
+        let message = serde_json::to_vec(&vec!["tonce"; 20]).unwrap();
+        let mut wire = (message.len() as u32).to_be_bytes().to_vec();
+        wire.extend_from_slice(&message);
+        tokio::spawn(async move {
+            for chunk in wire.chunks(3) {
+                server.write_all(chunk).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        });
+
+        assert_eq!(transport.recv_frame().await.unwrap(), Some(Bytes::from(message)));
+        assert_eq!(transport.recv_frame().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_frame_refused() {
+        let (client, server) = tokio::io::duplex(1024);
+        let mut sender = Transport::new(client, DEFAULT_MAX_FRAME_LEN);
+        let mut receiver = Transport::new(server, 16);
+
+        sender.send_frame(vec![0u8; 17]).await.unwrap();
+        assert!(receiver.recv_frame().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_request_round_trip() {
+        let (client, server) = tokio::io::duplex(1024);
+        let mut client = Transport::new(client, DEFAULT_MAX_FRAME_LEN);
+        let mut server = Transport::new(server, DEFAULT_MAX_FRAME_LEN);
+
+        tokio::spawn(async move {
+            let frame = server.recv_frame().await.unwrap().unwrap();
+            let n: u32 = serde_json::from_slice(&frame).unwrap();
+            server.send(&(n + 1)).await.unwrap();
+        });
+
+        let reply: u32 = client.request(&41u32).await.unwrap();
+        assert_eq!(reply, 42);
+    }
+
+    #[tokio::test]
+    async fn test_compressed_frames() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let mut client = Transport::new(client, DEFAULT_MAX_FRAME_LEN);
+        let mut server = Transport::new(server, DEFAULT_MAX_FRAME_LEN);
+        let compression = Some(FrameCompression::new(Compression::Deflate));
+        client.set_compression(compression);
+        server.set_compression(compression);
+
+        let big = vec!["coinbase"; 2000];
+        client.send(&big).await.unwrap();
+        client.send(&"small").await.unwrap();
+        let frame = server.recv_frame().await.unwrap().unwrap();
+        assert_eq!(serde_json::from_slice::<Vec<String>>(&frame).unwrap().len(), 2000);
+        let frame = server.recv_frame().await.unwrap().unwrap();
+        assert_eq!(serde_json::from_slice::<String>(&frame).unwrap(), "small");
+    }
+
+    #[test]
+    fn test_write_buffer_reused() {
+        let mut buffer = BytesMut::new();
+        let message = vec!["coinbase"; 100];
+        let first = encode_json(&mut buffer, &message).unwrap();
+        assert_eq!(first, serde_json::to_vec(&message).unwrap());
+        let start = first.as_ptr();
+
+        // the next frame goes where the last one was, once it's gone
+        drop(first);
+        let second = encode_json(&mut buffer, &message).unwrap();
+        assert_eq!(second.as_ptr(), start);
+        // but never over a frame that is still held
+        let third = encode_json(&mut buffer, &message).unwrap();
+        assert_ne!(third.as_ptr(), start);
+        assert_eq!(second, third);
+    }
+
+    #[tokio::test]
+    async fn test_read_timeout() {
+        let (client, _server) = tokio::io::duplex(1024);
+        let timeouts = Timeouts { read: Duration::from_millis(20), ..Timeouts::default() };
+        let mut transport = Transport::new(client, DEFAULT_MAX_FRAME_LEN).with_timeouts(timeouts);
+
+        let error = transport.recv_frame().await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+}
@@ -3,141 +3,1035 @@
 /// Runs a TCP server that accepts connections from miners,
 /// validates blocks, and maintains the blockchain
 
+use std::collections::HashSet;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use tokio::net::{TcpListener, TcpStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use crate::{Validator, ValidationResult};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use bytes::Bytes;
+use tokio::sync::{broadcast, mpsc};
+use tokio::net::TcpListener;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use crate::{AttemptPolicy, Block, BlockHash, Difficulty, LockoutPolicy, Txid, Validator, ValidationResult};
+use crate::block_store::BlockStore;
+use crate::canonical::Canonical;
+use crate::compact_block::CompactBlock;
+use crate::consensus::AcceptanceVote;
+use crate::hashable::Hashable;
+use crate::chain_params::ChainParams;
+use crate::consensus::ValidatorSet;
+use crate::faucet::Faucet;
+use crate::federation::{decode_commitments, decode_signature_share, decode_signing_package, encode_commitments,
+    encode_signature_share, encode_signing_package, FederatedReceipt, Federation, FederationMember};
+use crate::miner_stats::{MinerStats, MinerStatsBook};
+use crate::node_key::NodeKey;
+use crate::receipt::BlockReceipt;
+use crate::decision_log::DecisionLog;
+use crate::snapshot::ValidatorSnapshot;
+use crate::vrf::VrfKeypair;
+use crate::watch::AddressActivity;
+use super::compression::{Compression, FrameCompression};
+use super::connection::Heartbeat;
+use super::peers::{PeerBook, DISCOVERY_INTERVAL, MAX_SHARED_PEERS};
 use super::protocol::*;
+use super::idempotency::{ResponseCache, MAX_REQUEST_ID_LEN};
+use super::rate_limit::{RateLimitConfig, RateLimits};
+use super::actor::{ValidatorHandle, ValidatorTask};
+use super::admin;
+use super::pubsub;
+use super::rest;
+#[cfg(feature = "grpc")]
+use super::grpc;
+use super::stream::{host_of, BoxedStream, Listener};
+use super::retry::{RetryPolicy, Timeouts};
+use super::transport::{Transport, DEFAULT_MAX_FRAME_LEN};
+use super::webhook::{WebhookConfig, Webhooks};
+
+/// How often miner statistics are saved, when a stats path is set
+pub const STATS_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the validator snapshot is saved, when a snapshot path is set
+pub const SNAPSHOT_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Most addresses one connection may watch
+pub const MAX_WATCHED_ADDRESSES: usize = 1000;
+
+/// How often the server resyncs its clock, and what counts as drift
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeResyncConfig {
+    /// Time between syncs with the external time sources
+    pub interval: Duration,
+    /// Raise the drift alarm when the local clock is further than this from external time (milliseconds)
+    pub max_offset_ms: u128,
+    /// Refuse blocks while the alarm is raised
+    pub pause_on_drift: bool,
+}
+
+impl Default for TimeResyncConfig {
+    fn default() -> Self {
+        TimeResyncConfig {
+            interval: Duration::from_secs(600),
+            max_offset_ms: 1_000,
+            pause_on_drift: false,
+        }
+    }
+}
+
+/// The other validators in the set, and how patiently to talk to them
+#[derive(Debug, Clone, Default)]
+struct PeerLinks {
+    addresses: Vec<String>,
+    timeouts: Timeouts,
+    retry_policy: RetryPolicy,
+    /// Where discovery starts when the book knows nobody
+    seeds: Vec<String>,
+    /// Peers found through discovery
+    book: Arc<std::sync::Mutex<PeerBook>>,
+    /// Where other validators can reach us, sent with `GetPeers`
+    advertised: Option<String>,
+}
+
+impl PeerLinks {
+    /// The configured peers and every discovered one, without duplicates or ourselves
+    fn all_addresses(&self) -> Vec<String> {
+        let mut addresses = self.addresses.clone();
+        for address in self.book.lock().unwrap().addresses() {
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+        addresses.retain(|address| Some(address) != self.advertised.as_ref());
+        addresses
+    }
+
+    /// Send one message to a peer validator and wait for its reply, retrying connection failures
+    async fn send(&self, address: &str, message: &PeerMessage) -> Result<PeerMessage, Box<dyn std::error::Error + Send + Sync>> {
+        let reply = self.retry_policy.retry("peer request", || self.ask(address, message), |_| true).await?;
+        Ok(reply)
+    }
+
+    /// Ask a peer validator what a miner would, retrying connection failures
+    async fn query(&self, address: &str, message: &MinerMessage) -> Result<ValidatorMessage, Box<dyn std::error::Error + Send + Sync>> {
+        let reply = self.retry_policy.retry("peer query", || async {
+            Transport::connect(address, self.timeouts).await?.request(message).await
+        }, |_| true).await?;
+        Ok(reply)
+    }
+
+    /// Send one message to a peer validator and wait for its reply, without retrying
+    async fn ask(&self, address: &str, message: &PeerMessage) -> std::io::Result<PeerMessage> {
+        Transport::connect(address, self.timeouts).await?.request(message).await
+    }
+}
+
+/// The addresses one connection watches, and the validator's activity feed once it watches any
+#[derive(Default)]
+struct AddressWatch {
+    addresses: HashSet<String>,
+    feed: Option<broadcast::Receiver<AddressActivity>>,
+}
+
+impl AddressWatch {
+    fn is_active(&self) -> bool {
+        self.feed.is_some()
+    }
+
+    /// Start watching `address`, subscribing to the feed on the first one
+    async fn add(&mut self, address: String, validator: &ValidatorHandle) -> ValidatorMessage {
+        if !self.addresses.contains(&address) && self.addresses.len() >= MAX_WATCHED_ADDRESSES {
+            return ValidatorMessage::Error {
+                message: format!("At most {} addresses can be watched per connection", MAX_WATCHED_ADDRESSES),
+            };
+        }
+        if self.feed.is_none() {
+            self.feed = Some(validator.call(|validator| validator.subscribe_activity()).await);
+        }
+        debug!(address = %address, "watching address");
+        self.addresses.insert(address.clone());
+        ValidatorMessage::Watching { address }
+    }
+
+    /// The next activity for a watched address
+    async fn next(&mut self) -> Option<AddressActivity> {
+        let feed = self.feed.as_mut()?;
+        loop {
+            match feed.recv().await {
+                Ok(activity) if self.addresses.contains(&activity.address) => return Some(activity),
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!(skipped, "watching connection fell behind; activity dropped");
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    self.feed = None;
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// The state of a running server, for a status display beside it
+#[derive(Clone)]
+pub struct ServerHandle {
+    validator: ValidatorHandle,
+    rate_limits: Arc<RateLimits>,
+}
+
+impl ServerHandle {
+    pub fn validator(&self) -> &ValidatorHandle {
+        &self.validator
+    }
+
+    /// Client connections open now
+    pub fn open_connections(&self) -> usize {
+        self.rate_limits.open_connections()
+    }
+}
 
 /// Validator server that manages the proof of time consensus
 pub struct ValidatorServer {
-    validator: Arc<Mutex<Validator>>,
+    validator: ValidatorHandle,
+    /// The task that owns the validator, until `start` runs it (see the `actor` module)
+    validator_task: Option<ValidatorTask>,
     address: String,
+    soak_mode: bool,
+    lockout_policy: LockoutPolicy,
+    attempt_policy: AttemptPolicy,
+    vrf_keypair: Option<VrfKeypair>,
+    commit_reveal: bool,
+    node_key: Option<NodeKey>,
+    validator_set: Option<ValidatorSet>,
+    federation: Option<(Federation, FederationMember)>,
+    /// The other validators in the set
+    peers: Arc<PeerLinks>,
+    time_resync: Option<TimeResyncConfig>,
+    /// Where miner statistics are saved between restarts
+    stats_path: Option<PathBuf>,
+    /// Where discovered peers are saved between restarts
+    peers_path: Option<PathBuf>,
+    /// Where the validator snapshot is saved, and restored from at startup
+    snapshot_path: Option<PathBuf>,
+    /// Where judged blocks are logged, and replayed from at startup
+    decision_log_path: Option<PathBuf>,
+    block_store_path: Option<PathBuf>,
+    /// Where an empty chain is fast synced from at startup
+    fast_sync_peer: Option<String>,
+    /// Public key the fast sync snapshot must be signed with, unless a validator set vouches for it
+    fast_sync_key: Option<Vec<u8>>,
+    /// Where validator events are POSTed, if anywhere
+    webhooks: Option<WebhookConfig>,
+    /// Request and connection limits per client
+    rate_limits: Arc<RateLimits>,
+    /// Answers to recent tagged requests, replayed to retries
+    responses: Arc<ResponseCache>,
+    /// Largest message accepted from a client (bytes)
+    max_frame_len: usize,
+    /// Limits on client connections; `read` is how long a connection may sit idle
+    timeouts: Timeouts,
+    /// Frame compression miners may ask for in their `Hello`
+    compression: Arc<Vec<Compression>>,
+    /// Clients that have pinged are dropped after missing `heartbeat.max_missed` pings
+    heartbeat: Heartbeat,
+    /// Address of the REST endpoint, if enabled
+    rest_address: Option<String>,
+    /// Address of the pub/sub endpoint for raw blocks and transactions, if enabled
+    pubsub_address: Option<String>,
+    /// Address and token of the admin endpoint, if enabled
+    admin: Option<(String, String)>,
+    /// Address of the gRPC endpoint, if enabled
+    #[cfg(feature = "grpc")]
+    grpc_address: Option<String>,
+    /// Where miners connect (TLS or QUIC); plain TCP on `address` if unset
+    listener: Option<Box<dyn Listener>>,
+    /// Pays test coins to clients that ask, if enabled
+    faucet: Option<Faucet>,
 }
 
 impl ValidatorServer {
     /// Create a new validator server
-    pub fn new(difficulty: u128, address: String) -> Self {
-        let validator = Validator::new(difficulty);
+    pub fn new(difficulty: Difficulty, address: String) -> Self {
+        Self::new_with_params(ChainParams::mainnet().with_difficulty(difficulty), address)
+    }
+
+    /// Create a new validator server for a network described by its chain parameters
+    pub fn new_with_params(params: ChainParams, address: String) -> Self {
+        let (validator, validator_task) = ValidatorHandle::new(Validator::new_with_params(params));
         ValidatorServer {
-            validator: Arc::new(Mutex::new(validator)),
+            validator,
+            validator_task: Some(validator_task),
             address,
+            soak_mode: false,
+            lockout_policy: LockoutPolicy::Fixed,
+            attempt_policy: AttemptPolicy::default(),
+            vrf_keypair: None,
+            commit_reveal: false,
+            node_key: None,
+            validator_set: None,
+            federation: None,
+            peers: Arc::new(PeerLinks::default()),
+            time_resync: None,
+            stats_path: None,
+            peers_path: None,
+            snapshot_path: None,
+            decision_log_path: None,
+            block_store_path: None,
+            fast_sync_peer: None,
+            fast_sync_key: None,
+            webhooks: None,
+            rate_limits: Arc::new(RateLimits::new(RateLimitConfig::default())),
+            responses: Arc::new(ResponseCache::default()),
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            timeouts: Timeouts::default(),
+            compression: Arc::new(Compression::ALL.to_vec()),
+            heartbeat: Heartbeat::default(),
+            rest_address: None,
+            pubsub_address: None,
+            admin: None,
+            #[cfg(feature = "grpc")]
+            grpc_address: None,
+            listener: None,
+            faucet: None,
+        }
+    }
+
+    /// A handle to the server's state that stays valid while it runs
+    ///
+    /// Take it after `set_rate_limits`, which replaces what the handle counts connections with.
+    pub fn handle(&self) -> ServerHandle {
+        ServerHandle {
+            validator: self.validator.clone(),
+            rate_limits: Arc::clone(&self.rate_limits),
         }
     }
 
+    /// Check validator invariants after every accepted block (for long stability runs)
+    pub fn set_soak_mode(&mut self, enabled: bool) {
+        self.soak_mode = enabled;
+    }
+
+    /// Select how lockouts grow for miners who keep winning
+    pub fn set_lockout_policy(&mut self, policy: LockoutPolicy) {
+        self.lockout_policy = policy;
+    }
+
+    /// Select which failed submissions use up a miner's attempts for the round
+    pub fn set_attempt_policy(&mut self, policy: AttemptPolicy) {
+        self.attempt_policy = policy;
+    }
+
+    /// Derive round challenges from a VRF and publish a proof with every round
+    pub fn set_vrf_keypair(&mut self, keypair: VrfKeypair) {
+        self.vrf_keypair = Some(keypair);
+    }
+
+    /// Require miners to commit to their timestamp before every submission
+    pub fn set_commit_reveal(&mut self, enabled: bool) {
+        self.commit_reveal = enabled;
+    }
+
+    /// Sign a receipt for every accepted block with this node key
+    pub fn set_node_key(&mut self, node_key: NodeKey) {
+        self.node_key = Some(node_key);
+    }
+
+    /// Finalize blocks only once a quorum of `validator_set` agrees
+    ///
+    /// Accepted blocks are proposed to `peers`, which vote on them. The node key
+    /// must belong to the set.
+    pub fn set_consensus(&mut self, validator_set: ValidatorSet, peers: Vec<String>) {
+        self.validator_set = Some(validator_set);
+        Arc::make_mut(&mut self.peers).addresses = peers;
+    }
+
+    /// Co-sign a receipt for every block accepted here with the other federation members
+    ///
+    /// The members are reached through the consensus peers, which hold the same blocks.
+    pub fn set_federation(&mut self, federation: Federation, member: FederationMember) {
+        self.federation = Some((federation, member));
+    }
+
+    /// Find other validators starting from these addresses (see the `peers` module)
+    pub fn set_seeds(&mut self, seeds: Vec<String>) {
+        Arc::make_mut(&mut self.peers).seeds = seeds;
+    }
+
+    /// Tell peers to reach us at `address` (by default the server address, unless it's unspecified)
+    pub fn set_advertised_address(&mut self, address: String) {
+        Arc::make_mut(&mut self.peers).advertised = Some(address);
+    }
+
+    /// Load discovered peers from `path` at startup and save them there after every discovery round
+    pub fn set_peers_path(&mut self, path: impl Into<PathBuf>) {
+        self.peers_path = Some(path.into());
+    }
+
+    /// Bound client and peer connections; clients idle for longer than `timeouts.read` are dropped
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+        Arc::make_mut(&mut self.peers).timeouts = timeouts;
+    }
+
+    /// Retry failed requests to peer validators under `policy`
+    pub fn set_peer_retry_policy(&mut self, policy: RetryPolicy) {
+        Arc::make_mut(&mut self.peers).retry_policy = policy;
+    }
+
+    /// Resync the clock in the background and alarm when it drifts (see `resync_time`)
+    pub fn set_time_resync(&mut self, config: TimeResyncConfig) {
+        self.time_resync = Some(config);
+    }
+
+    /// Restore the validator from the snapshot at `path` at startup and save it there every `SNAPSHOT_SAVE_INTERVAL`
+    pub fn set_snapshot_path(&mut self, path: impl Into<PathBuf>) {
+        self.snapshot_path = Some(path.into());
+    }
+
+    /// Log every judged block to `path`, replaying the decisions past the restored state at startup
+    pub fn set_decision_log_path(&mut self, path: impl Into<PathBuf>) {
+        self.decision_log_path = Some(path.into());
+    }
+
+    /// Sync an empty chain from `peer`'s UTXO snapshot at startup instead of from genesis
+    ///
+    /// See `Validator::adopt_utxo_snapshot` for what is checked. The snapshot
+    /// must be signed by `trusted_key` or, with consensus enabled, a member of
+    /// the validator set. A chain that already holds blocks past genesis isn't
+    /// touched.
+    pub fn set_fast_sync_peer(&mut self, peer: String, trusted_key: Option<Vec<u8>>) {
+        self.fast_sync_peer = Some(peer);
+        self.fast_sync_key = trusted_key;
+    }
+
+    /// POST accepted blocks, lockouts and new rounds to the URLs in `config` (see `webhook`)
+    pub fn set_webhooks(&mut self, config: WebhookConfig) {
+        self.webhooks = Some(config);
+    }
+
+    /// Append accepted blocks to the block store in the directory `path` (see `block_store`)
+    pub fn set_block_store_path(&mut self, path: impl Into<PathBuf>) {
+        self.block_store_path = Some(path.into());
+    }
+
+    /// Load miner statistics from `path` at startup and save them there every `STATS_SAVE_INTERVAL`
+    pub fn set_stats_path(&mut self, path: impl Into<PathBuf>) {
+        self.stats_path = Some(path.into());
+    }
+
+    /// Refuse (and disconnect) clients sending messages over `max_frame_len` bytes
+    pub fn set_max_frame_len(&mut self, max_frame_len: usize) {
+        self.max_frame_len = max_frame_len;
+    }
+
+    /// Limit how fast each IP address and miner ID may send requests
+    pub fn set_rate_limits(&mut self, config: RateLimitConfig) {
+        self.rate_limits = Arc::new(RateLimits::new(config));
+    }
+
+    /// Accept miners from `listener` instead of a TCP socket on the server address
+    pub fn set_listener(&mut self, listener: Box<dyn Listener>) {
+        self.listener = Some(listener);
+    }
+
+    /// Serve miner statistics over HTTP on `address` (see the `rest` module)
+    pub fn set_rest_address(&mut self, address: String) {
+        self.rest_address = Some(address);
+    }
+
+    /// Publish raw blocks and transactions to subscribers on `address` (see the `pubsub` module)
+    pub fn set_pubsub_address(&mut self, address: String) {
+        self.pubsub_address = Some(address);
+    }
+
+    /// Accept operator commands carrying `token` on `address` (see the `admin` module)
+    pub fn set_admin(&mut self, address: String, token: String) {
+        self.admin = Some((address, token));
+    }
+
+    /// Serve the miner API over gRPC on `address` (see the `grpc` module)
+    #[cfg(feature = "grpc")]
+    pub fn set_grpc_address(&mut self, address: String) {
+        self.grpc_address = Some(address);
+    }
+
+    /// Drop clients that ping once they go `heartbeat.timeout()` without sending anything
+    pub fn set_heartbeat(&mut self, heartbeat: Heartbeat) {
+        self.heartbeat = heartbeat;
+    }
+
+    /// Answer `RequestFaucet` by paying from `faucet` (see the `faucet` module)
+    pub fn set_faucet(&mut self, faucet: Faucet) {
+        self.faucet = Some(faucet);
+    }
+
+    /// Compression algorithms to accept from miners (empty to always answer uncompressed)
+    pub fn set_compression(&mut self, supported: Vec<Compression>) {
+        self.compression = Arc::new(supported);
+    }
+
     /// Start the validator server
     pub async fn start(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.peers.advertised.is_none() && !host_of(&self.address).parse::<IpAddr>().is_ok_and(|ip| ip.is_unspecified()) {
+            Arc::make_mut(&mut self.peers).advertised = Some(self.address.clone());
+        }
+        if let Some(path) = self.peers_path.as_ref().filter(|path| path.exists()) {
+            let book = PeerBook::load(path, super::peers::DEFAULT_MAX_PEERS)?;
+            info!(path = %path.display(), peers = book.len(), "peer book loaded");
+            *self.peers.book.lock().unwrap() = book;
+        }
+        tokio::spawn(Self::discover_peers(Arc::clone(&self.peers), self.peers_path.clone()));
+
+        if let Some(task) = self.validator_task.take() {
+            tokio::spawn(task.run());
+        }
+
         // Initialize the first mining round
-        {
-            let mut validator = self.validator.lock().await;
+        let (soak_mode, lockout_policy, attempt_policy, commit_reveal) =
+            (self.soak_mode, self.lockout_policy, self.attempt_policy, self.commit_reveal);
+        let (node_key, vrf_keypair, faucet, validator_set, federation) =
+            (self.node_key.take(), self.vrf_keypair.take(), self.faucet.take(), self.validator_set.take(), self.federation.take());
+        let peer_count = self.peers.addresses.len();
+        self.validator.call(move |validator| {
+            validator.set_soak_mode(soak_mode);
+            validator.set_lockout_policy(lockout_policy);
+            validator.set_attempt_policy(attempt_policy);
+            validator.set_commit_reveal(commit_reveal);
+            if let Some(node_key) = node_key {
+                validator.set_node_key(node_key);
+            }
+            if let Some(keypair) = vrf_keypair {
+                validator.set_vrf_keypair(keypair);
+            }
+            if let Some(faucet) = faucet {
+                info!(address = faucet.address(), amount = faucet.config().amount, "faucet enabled");
+                validator.set_faucet(faucet);
+            }
+            if let Some(validator_set) = validator_set {
+                info!(validators = validator_set.members().len(), quorum = validator_set.quorum(),
+                    peers = peer_count, "consensus enabled");
+                validator.set_validator_set(validator_set)?;
+            }
+            if let Some((federation, member)) = federation {
+                info!(member = member.member_id(), threshold = federation.threshold(),
+                    group_key = %hex::encode(federation.group_public_key()), "federation enabled");
+                validator.set_federation(federation, member)?;
+            }
             validator.start_new_round();
+            Ok::<(), String>(())
+        }).await?;
+
+        if self.soak_mode {
+            info!("soak mode enabled: checking invariants after every accepted block");
         }
 
-        println!("Validator server starting on {}", self.address);
-        println!("Waiting for miner connections...\n");
+        if let Some(path) = self.snapshot_path.clone() {
+            if path.exists() {
+                let snapshot = ValidatorSnapshot::load(&path)?;
+                let blocks = self.validator.call(|validator| {
+                    validator.restore(snapshot)?;
+                    Ok::<_, String>(validator.blockchain.blocks.len())
+                }).await?;
+                info!(path = %path.display(), blocks, "validator restored from snapshot");
+            }
+            tokio::spawn(Self::save_snapshots(self.validator.clone(), path));
+        }
+
+        if let Some(path) = self.decision_log_path.clone() {
+            let records = if path.exists() { Some(DecisionLog::read(&path)?) } else { None };
+            let log = DecisionLog::open(&path)?;
+            self.validator.call(move |validator| {
+                if let Some(records) = records {
+                    let replayed = validator.replay_decisions(&records)?;
+                    info!(path = %path.display(), replayed, blocks = validator.blockchain.blocks.len(), "decision log replayed");
+                }
+                validator.set_decision_log(log);
+                Ok::<(), String>(())
+            }).await?;
+        }
 
-        let listener = TcpListener::bind(&self.address).await?;
+        if let Some(peer) = self.fast_sync_peer.clone() {
+            let blocks = self.validator.call(|validator| validator.get_block_count()).await;
+            if blocks > 1 {
+                info!(%peer, blocks, "chain already holds blocks; not fast syncing");
+            } else {
+                let (height, blocks) = Self::fast_sync(&self.validator, &self.peers, &peer, self.fast_sync_key.clone()).await?;
+                info!(%peer, snapshot_height = height, blocks, "fast synced from peer's UTXO snapshot");
+            }
+        }
+
+        if let Some(path) = self.block_store_path.clone() {
+            let store = BlockStore::open(&path)?;
+            let blocks = self.validator.call(move |validator| {
+                validator.set_block_store(store)?;
+                Ok::<_, String>(validator.block_store().map_or(0, BlockStore::len))
+            }).await?;
+            info!(path = %path.display(), blocks, "block store opened");
+        }
+
+        // registered once startup has caught up, so restored and replayed blocks aren't announced again
+        if let Some(config) = self.webhooks.take().filter(|config| !config.urls.is_empty()) {
+            let urls = config.urls.len();
+            let webhooks = Webhooks::spawn(config)?;
+            self.validator.call(move |validator| validator.add_validation_hook(Box::new(webhooks))).await;
+            info!(urls, "webhooks enabled");
+        }
+
+        if let Some(path) = self.stats_path.clone() {
+            if path.exists() {
+                let stats = MinerStatsBook::load(&path)?;
+                info!(path = %path.display(), miners = stats.len(), "miner statistics loaded");
+                self.validator.call(|validator| validator.set_miner_stats(stats)).await;
+            }
+            tokio::spawn(Self::save_stats(self.validator.clone(), path));
+        }
+
+        if let Some(address) = self.rest_address.clone() {
+            let validator = self.validator.clone();
+            tokio::spawn(async move {
+                if let Err(e) = rest::serve(address, validator).await {
+                    error!(error = %e, "REST endpoint stopped");
+                }
+            });
+        }
+
+        if let Some(address) = self.pubsub_address.clone() {
+            let validator = self.validator.clone();
+            tokio::spawn(async move {
+                if let Err(e) = pubsub::serve(address, validator).await {
+                    error!(error = %e, "pub/sub endpoint stopped");
+                }
+            });
+        }
+
+        if let Some((address, token)) = self.admin.clone() {
+            let validator = self.validator.clone();
+            let snapshot = self.snapshot();
+            tokio::spawn(async move {
+                if let Err(e) = admin::serve(address, token, validator, snapshot).await {
+                    error!(error = %e, "admin endpoint stopped");
+                }
+            });
+        }
+
+        #[cfg(feature = "grpc")]
+        if let Some(address) = self.grpc_address.clone() {
+            let validator = self.validator.clone();
+            let peers = Arc::clone(&self.peers);
+            let rate_limits = Arc::clone(&self.rate_limits);
+            let responses = Arc::clone(&self.responses);
+            let answer: grpc::Answer = Arc::new(move |message, ip| {
+                let validator = validator.clone();
+                let peers = Arc::clone(&peers);
+                let rate_limits = Arc::clone(&rate_limits);
+                let responses = Arc::clone(&responses);
+                Box::pin(async move { Self::answer_miner(message, ip, &validator, &peers, &rate_limits, &responses).await })
+            });
+            tokio::spawn(async move {
+                if let Err(e) = grpc::serve(address, answer).await {
+                    error!(error = %e, "gRPC endpoint stopped");
+                }
+            });
+        }
+
+        if let Some(config) = self.time_resync {
+            info!(interval_secs = config.interval.as_secs(), max_offset_ms = config.max_offset_ms,
+                pause_on_drift = config.pause_on_drift, "background time resync enabled");
+            tokio::spawn(Self::resync_time(self.validator.clone(), config));
+        }
+
+        info!(address = %self.address, "validator server starting");
+
+        let mut listener = match self.listener.take() {
+            Some(listener) => listener,
+            None => Box::new(TcpListener::bind(&self.address).await?),
+        };
+        info!("waiting for miner connections");
 
         loop {
             let (socket, addr) = listener.accept().await?;
-            println!("New connection from: {}", addr);
+            let span = info_span!("connection", peer = %addr);
 
-            let validator = Arc::clone(&self.validator);
+            let ip = addr.ip();
+            if !self.rate_limits.open_connection(ip) {
+                span.in_scope(|| warn!(max = self.rate_limits.config().max_connections_per_ip,
+                    "too many connections from this address; closing"));
+                continue;
+            }
+            span.in_scope(|| info!("new connection"));
+
+            let validator = self.validator.clone();
+            let peers = Arc::clone(&self.peers);
+            let rate_limits = Arc::clone(&self.rate_limits);
+            let responses = Arc::clone(&self.responses);
+            let compression = Arc::clone(&self.compression);
+            let heartbeat = self.heartbeat;
+            let transport = Transport::new(socket, self.max_frame_len).with_timeouts(self.timeouts);
 
             // Spawn a new task for each connection
             tokio::spawn(async move {
-                if let Err(e) = Self::handle_connection(socket, validator).await {
-                    eprintln!("Error handling connection from {}: {}", addr, e);
+                if let Err(e) = Self::handle_connection(transport, ip, validator, peers, Arc::clone(&rate_limits), responses, compression, heartbeat).await {
+                    error!(error = %e, "error handling connection");
                 }
-            });
+                rate_limits.close_connection(ip);
+                debug!("connection closed");
+            }.instrument(span));
         }
     }
 
-    /// Handle a single miner connection
-    async fn handle_connection(
-        mut socket: TcpStream,
-        validator: Arc<Mutex<Validator>>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut buffer = vec![0u8; 1024 * 1024]; // 1MB buffer
-
+    /// Resync the validator's clock every `config.interval`, forever
+    ///
+    /// The sources are polled on a copy of the `TimeSync` so the validator
+    /// isn't locked during network round trips. When the offset passes
+    /// `config.max_offset_ms` a `clock drift` warning is logged on every sync
+    /// and, with `pause_on_drift`, block acceptance is paused until it recovers.
+    pub async fn resync_time(validator: ValidatorHandle, config: TimeResyncConfig) {
+        let mut ticker = tokio::time::interval(config.interval);
         loop {
-            // Read message length (4 bytes)
-            let n = socket.read(&mut buffer[..4]).await?;
-            if n == 0 {
-                return Ok(()); // Connection closed
+            ticker.tick().await;
+
+            let time_sync = validator.call(|validator| validator.time_sync().clone()).await;
+            let readings = time_sync.poll_sources().await;
+
+            let sources = readings.len();
+            let reached = validator.call(move |validator| {
+                validator.time_sync_mut().apply_readings(&readings);
+                let reached = readings.iter().any(Result::is_ok);
+                if reached {
+                    Self::check_clock_drift(validator, &config);
+                }
+                reached
+            }).await;
+            if !reached {
+                warn!(sources, "time resync failed: no source reachable; keeping the last offset");
             }
+        }
+    }
 
-            let msg_len = u32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as usize;
+    /// Save the miner statistics to `path` every `STATS_SAVE_INTERVAL`, forever
+    /// Save whatever state has a file configured: the validator snapshot, miner statistics and the peer book
+    fn snapshot(&self) -> admin::Snapshot {
+        let snapshot_path = self.snapshot_path.clone();
+        let stats_path = self.stats_path.clone();
+        let peers_path = self.peers_path.clone();
+        let book = Arc::clone(&self.peers.book);
+        Arc::new(move |validator: &Validator| {
+            let mut files = Vec::new();
+            if let Some(path) = &snapshot_path {
+                validator.snapshot().save(path)?;
+                files.push(path.display().to_string());
+            }
+            if let Some(path) = &stats_path {
+                validator.miner_stats().save(path)?;
+                files.push(path.display().to_string());
+            }
+            if let Some(path) = &peers_path {
+                book.lock().unwrap().save(path)?;
+                files.push(path.display().to_string());
+            }
+            Ok(files)
+        })
+    }
 
-            if msg_len > buffer.len() {
-                return Err("Message too large".into());
+    async fn save_snapshots(validator: ValidatorHandle, path: PathBuf) {
+        let mut ticker = tokio::time::interval(SNAPSHOT_SAVE_INTERVAL);
+        // the first tick would only rewrite the snapshot just restored
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let snapshot = validator.call(|validator| validator.snapshot()).await;
+            if let Err(e) = snapshot.save(&path) {
+                warn!(error = %e, "failed to save validator snapshot");
             }
+        }
+    }
 
-            // Read the actual message
-            let n = socket.read_exact(&mut buffer[..msg_len]).await?;
-            if n == 0 {
-                return Ok(());
+    async fn save_stats(validator: ValidatorHandle, path: PathBuf) {
+        let mut ticker = tokio::time::interval(STATS_SAVE_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let stats = validator.call(|validator| validator.miner_stats().clone()).await;
+            if let Err(e) = stats.save(&path) {
+                warn!(error = %e, "failed to save miner statistics");
             }
+        }
+    }
 
-            let request: MinerMessage = serde_json::from_slice(&buffer[..msg_len])?;
+    /// Raise or clear the drift alarm for the validator's current offset
+    ///
+    /// Returns whether the clock has drifted past `config.max_offset_ms`.
+    fn check_clock_drift(validator: &mut Validator, config: &TimeResyncConfig) -> bool {
+        let offset_ms = validator.get_clock_offset_ms();
+        let drift_ms_per_hour = validator.time_sync().drift_ms_per_hour();
+        let drifting = offset_ms.unsigned_abs() > config.max_offset_ms;
 
-            let response = Self::process_message(request, &validator).await;
+        if drifting {
+            warn!(offset_ms, ?drift_ms_per_hour, max_offset_ms = config.max_offset_ms,
+                paused = config.pause_on_drift, "clock drift");
+        } else {
+            debug!(offset_ms, ?drift_ms_per_hour, "clock in sync");
+            if validator.is_clock_drift_paused() {
+                info!(offset_ms, "clock back in sync; accepting blocks again");
+            }
+        }
+        validator.set_clock_drift_paused(drifting && config.pause_on_drift);
+        drifting
+    }
 
-            // Send response
-            let response_json = serde_json::to_vec(&response)?;
-            let len_bytes = (response_json.len() as u32).to_be_bytes();
+    /// Handle a single miner or peer validator connection
+    ///
+    /// Untagged messages are answered one at a time, in order. Tagged
+    /// `MinerRequest`s are processed concurrently and answered as they finish.
+    /// A `Hello` switches the connection to the compression it negotiates.
+    /// Once a client pings it's expected to keep pinging, and is dropped if it
+    /// goes `heartbeat.timeout()` without sending anything.
+    #[allow(clippy::too_many_arguments)]
+    async fn handle_connection(
+        mut transport: Transport<BoxedStream>,
+        ip: IpAddr,
+        validator: ValidatorHandle,
+        peers: Arc<PeerLinks>,
+        rate_limits: Arc<RateLimits>,
+        responses: Arc<ResponseCache>,
+        compression: Arc<Vec<Compression>>,
+        heartbeat: Heartbeat,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (replies, mut finished) = mpsc::unbounded_channel::<Bytes>();
+        let mut heard_at = tokio::time::Instant::now();
+        let mut heartbeating = false;
+        let mut watch = AddressWatch::default();
+
+        loop {
+            tokio::select! {
+                frame = transport.recv_frame() => {
+                    let frame = match frame? {
+                        Some(frame) => frame,
+                        None => return Ok(()), // Connection closed
+                    };
+                    heard_at = tokio::time::Instant::now();
 
-            socket.write_all(&len_bytes).await?;
-            socket.write_all(&response_json).await?;
-            socket.flush().await?;
+                    // the message owns its data, so the frame can give its space back to the read buffer now
+                    let message = IncomingMessage::from_slice(&frame)?;
+                    drop(frame);
+                    match message {
+                        IncomingMessage::Request(MinerRequest { id, message: MinerMessage::WatchAddress { address } }) => {
+                            let message = watch.add(address, &validator).await;
+                            transport.send(&ValidatorResponse { id, message }).await?;
+                        }
+                        IncomingMessage::Request(MinerRequest { id, message }) => {
+                            heartbeating |= matches!(message, MinerMessage::Ping { .. });
+                            let replies = replies.clone();
+                            let validator = validator.clone();
+                            let peers = Arc::clone(&peers);
+                            let rate_limits = Arc::clone(&rate_limits);
+                            let responses = Arc::clone(&responses);
+                            tokio::spawn(async move {
+                                let message = Self::answer_miner(message, ip, &validator, &peers, &rate_limits, &responses).await;
+                                match serde_json::to_vec(&ValidatorResponse { id, message }) {
+                                    Ok(json) => { let _ = replies.send(Bytes::from(json)); }
+                                    Err(e) => error!(error = %e, "failed to encode response"),
+                                }
+                            }.in_current_span());
+                        }
+                        IncomingMessage::Miner(MinerMessage::Hello { compression: offered }) => {
+                            let chosen = Compression::negotiate(&offered, &compression);
+                            debug!(?chosen, "handshake");
+                            transport.send(&ValidatorMessage::Welcome { compression: chosen }).await?;
+                            transport.set_compression(chosen.map(FrameCompression::new));
+                        }
+                        IncomingMessage::Miner(MinerMessage::WatchAddress { address }) => {
+                            transport.send(&watch.add(address, &validator).await).await?;
+                        }
+                        IncomingMessage::Miner(message) => {
+                            heartbeating |= matches!(message, MinerMessage::Ping { .. });
+                            let answer = Self::answer_miner(message, ip, &validator, &peers, &rate_limits, &responses).await;
+                            transport.send(&answer).await?;
+                        }
+                        IncomingMessage::Peer(message) => {
+                            let response = match rate_limits.check_request(ip, None) {
+                                Ok(()) => Self::process_peer_message(message, &validator, &peers).await,
+                                Err(_) => PeerMessage::Rejected { message: "Rate limited".to_string() },
+                            };
+                            transport.send(&response).await?;
+                        }
+                    }
+                }
+                Some(reply) = finished.recv() => transport.send_frame(reply).await?,
+                Some(activity) = watch.next(), if watch.is_active() => {
+                    transport.send(&ValidatorMessage::AddressActivity(AddressActivityData::from_activity(&activity))).await?;
+                }
+                _ = tokio::time::sleep_until(heard_at + heartbeat.timeout()), if heartbeating => {
+                    warn!(missed = heartbeat.max_missed, "client stopped sending heartbeats; closing");
+                    return Ok(());
+                }
+            }
         }
     }
 
+    /// Rate limit and then process a message from a miner
+    ///
+    /// A retry of a request tagged with a `request_id` gets the first copy's answer.
+    async fn answer_miner(
+        message: MinerMessage,
+        ip: IpAddr,
+        validator: &ValidatorHandle,
+        peers: &Arc<PeerLinks>,
+        rate_limits: &RateLimits,
+        responses: &ResponseCache,
+    ) -> ValidatorMessage {
+        let miner_id = message.miner_id();
+        if let Err(retry_after) = rate_limits.check_request(ip, miner_id) {
+            debug!(miner_id, "rate limited");
+            let retry_after_ms = retry_after.as_millis().min(u64::MAX as u128) as u64;
+            return ValidatorMessage::RateLimited { retry_after_ms };
+        }
+        match message.request_id() {
+            Some(request_id) if request_id.len() > MAX_REQUEST_ID_LEN => ValidatorMessage::Error {
+                message: format!("request_id is longer than {} characters", MAX_REQUEST_ID_LEN),
+            },
+            Some(request_id) => {
+                let (miner_id, request_id) = (miner_id.map(str::to_string), request_id.to_string());
+                responses.answer(miner_id.as_deref(), &request_id, Instant::now(),
+                    || Self::answer_request(message, ip, validator, peers)).await
+            }
+            None => Self::answer_request(message, ip, validator, peers).await,
+        }
+    }
+
+    /// Process a message from a miner that passed the rate limits
+    async fn answer_request(
+        message: MinerMessage,
+        ip: IpAddr,
+        validator: &ValidatorHandle,
+        peers: &Arc<PeerLinks>,
+    ) -> ValidatorMessage {
+        // the faucet limits each client IP, which only this path knows
+        if let MinerMessage::RequestFaucet { address, .. } = message {
+            return validator.call(move |validator| match validator.request_faucet(ip, &address) {
+                Ok(payment) => ValidatorMessage::FaucetPayment { address, tx_hash: payment.tx_hash.to_string(), amount: payment.amount },
+                Err(e) => ValidatorMessage::Error { message: e.to_string() },
+            }).await;
+        }
+        Self::process_message(message, validator, peers).await
+    }
+
     /// Process a message from a miner
     async fn process_message(
         message: MinerMessage,
-        validator: &Arc<Mutex<Validator>>,
+        validator: &ValidatorHandle,
+        peers: &Arc<PeerLinks>,
+    ) -> ValidatorMessage {
+        let (handle, peers) = (validator.clone(), Arc::clone(peers));
+        validator.call(move |validator| Self::apply_message(message, validator, &handle, &peers)).await
+    }
+
+    /// Process a message from a miner, on the validator task
+    fn apply_message(
+        message: MinerMessage,
+        validator: &mut Validator,
+        handle: &ValidatorHandle,
+        peers: &Arc<PeerLinks>,
     ) -> ValidatorMessage {
         match message {
-            MinerMessage::GetRoundInfo { miner_id } => {
-                let validator = validator.lock().await;
-                let round_info = validator.get_round_info();
+            MinerMessage::GetRoundInfo { miner_id, sent_at } => {
+                let received_at = validator.get_current_time();
+                let round_info = validator.get_round_info_for(&miner_id);
                 let difficulty = validator.get_difficulty();
 
-                println!("Miner '{}' requested round info", miner_id);
+                info!(miner_id = %miner_id, "round info requested");
+
+                let mut data = RoundInfoData::from_round_info(&round_info, difficulty);
+                data.exchange_times = sent_at.map(|client_sent_at| ExchangeTimesData {
+                    client_sent_at,
+                    server_received_at: received_at,
+                    server_sent_at: validator.get_current_time(),
+                });
+                ValidatorMessage::RoundInfo(data)
+            }
+
+            MinerMessage::CommitTimestamp { miner_id, commitment, .. } => {
+                let result = hex::decode(&commitment)
+                    .map_err(|e| format!("Invalid commitment hex: {}", e))
+                    .and_then(|commitment| validator.commit_timestamp(miner_id.clone(), commitment));
 
-                ValidatorMessage::RoundInfo(RoundInfoData::from_round_info(&round_info, difficulty))
+                match result {
+                    Ok(()) => {
+                        info!(miner_id = %miner_id, "timestamp commitment recorded");
+                        ValidatorMessage::CommitmentAccepted {
+                            round_start: validator.get_round_info().round_start,
+                            reveal_after_ms: validator.reveal_delay_ms() as u64,
+                        }
+                    }
+                    Err(message) => {
+                        warn!(miner_id = %miner_id, error = %message, "timestamp commitment rejected");
+                        ValidatorMessage::Error { message }
+                    }
+                }
             }
 
-            MinerMessage::SubmitBlock { miner_id, block } => {
-                println!("Miner '{}' submitting block #{}", miner_id, block.index);
+            MinerMessage::SubmitBlock { miner_id, block, salt, round_id, .. } => {
+                let Canonical(block) = *block;
+                let round_span = info_span!("round", start = validator.get_round_info().round_start);
+                let submission_span = info_span!(parent: &round_span, "block_submission",
+                    miner_id = %miner_id, index = block.header.index);
+                let _enter = submission_span.enter();
+
+                info!("block submitted");
 
-                let block = match block.to_block() {
-                    Ok(b) => b,
+                let salt = match salt.map(hex::decode).transpose() {
+                    Ok(salt) => salt,
                     Err(e) => {
+                        warn!(error = %e, "invalid salt");
                         return ValidatorMessage::Error {
-                            message: format!("Invalid block data: {}", e),
+                            message: format!("Invalid salt hex: {}", e),
                         };
                     }
                 };
 
-                let mut validator = validator.lock().await;
-                let result = validator.validate_block_submission(block, miner_id.clone());
+                // a submission must name its round, so one delayed past the round's end can't be judged against the next tonce
+                let round_id = match round_id.as_deref().map(str::parse::<BlockHash>) {
+                    Some(Ok(round_id)) => round_id,
+                    Some(Err(e)) => {
+                        warn!(error = %e, "invalid round_id");
+                        return ValidatorMessage::Error {
+                            message: format!("Invalid round_id: {}", e),
+                        };
+                    }
+                    None => {
+                        warn!("submission without round_id");
+                        return ValidatorMessage::Error {
+                            message: "Submissions must echo the round_id from RoundInfo".to_string(),
+                        };
+                    }
+                };
+
+                let result = validator.validate_round_submission(block, miner_id.clone(), salt.as_deref(), &round_id);
 
                 match &result {
                     ValidationResult::Accepted => {
-                        println!("✓ Block ACCEPTED from miner '{}'", miner_id);
-                        println!("  Miner entered 1-hour lockout");
-                        println!("  Blockchain now has {} blocks\n", validator.get_block_count());
+                        let lockout_seconds = validator.get_miner_lockout_remaining(&miner_id);
+                        info!(block_count = validator.get_block_count(), lockout_seconds,
+                            "block accepted, miner entered lockout");
+
+                        if let (Some(vote), Some(block)) = (validator.get_last_vote(), validator.blockchain.blocks.last()) {
+                            let proposal = Self::propose_to_peers(miner_id.clone(), block.clone(), vote.clone(), handle.clone(), Arc::clone(peers));
+                            // peers sign a receipt only for a block they hold, so co-signing waits for the proposal
+                            let receipt = validator.get_last_receipt().filter(|_| validator.federation().is_some()).cloned();
+                            let (handle, peers) = (handle.clone(), Arc::clone(peers));
+                            tokio::spawn(async move {
+                                proposal.await;
+                                if let Some(receipt) = receipt {
+                                    Self::co_sign_receipt(receipt, handle, peers).await;
+                                }
+                            }.instrument(tracing::Span::current()));
+                        }
 
                         ValidatorMessage::BlockResult {
                             result: BlockResultType::from(&result),
-                            message: "Block accepted! You are now in 1-hour lockout.".to_string(),
+                            message: format!("Block accepted! You are now in lockout for {} seconds.", lockout_seconds),
+                            receipt: validator.get_last_receipt().map(ReceiptData::from_receipt),
                         }
                     }
                     _ => {
-                        println!("✗ Block REJECTED from miner '{}': {:?}", miner_id, result);
+                        warn!(result = ?result, "block rejected");
 
                         let message = match &result {
                             ValidationResult::RejectedMinerInLockout => {
@@ -153,22 +1047,41 @@ impl ValidatorServer {
                             ValidationResult::RejectedMinerAlreadyAttempted => {
                                 "Already attempted this round".to_string()
                             }
+                            ValidationResult::RejectedMissingCommitment => {
+                                "No timestamp commitment for this round".to_string()
+                            }
+                            ValidationResult::RejectedCommitmentMismatch => {
+                                "Timestamp does not match commitment".to_string()
+                            }
+                            ValidationResult::RejectedRevealOutsideWindow => {
+                                format!("Reveal must come {}ms after the commitment, and before the challenge it was made for expires",
+                                    validator.reveal_delay_ms())
+                            }
                             ValidationResult::RejectedBlockchainValidation(e) => {
                                 format!("Blockchain validation failed: {}", e)
                             }
+                            ValidationResult::RejectedDuplicateBlock => {
+                                "Block was already submitted; your attempt this round is unused".to_string()
+                            }
+                            ValidationResult::RejectedStaleRound => {
+                                "Block was mined for a round that has ended; your attempt this round is unused".to_string()
+                            }
+                            ValidationResult::RejectedByHook(e) => {
+                                format!("Rejected by validator policy: {}", e)
+                            }
                             _ => format!("{:?}", result),
                         };
 
                         ValidatorMessage::BlockResult {
                             result: BlockResultType::from(&result),
                             message,
+                            receipt: None,
                         }
                     }
                 }
             }
 
             MinerMessage::CheckLockout { miner_id } => {
-                let validator = validator.lock().await;
                 let is_locked = validator.is_miner_in_lockout(&miner_id);
                 let seconds_remaining = validator.get_miner_lockout_remaining(&miner_id);
 
@@ -178,16 +1091,647 @@ impl ValidatorServer {
                 }
             }
 
+            MinerMessage::Ping { sent_at } => {
+                let exchange_times = match sent_at {
+                    Some(client_sent_at) => {
+                        let server_received_at = validator.get_current_time();
+                        Some(ExchangeTimesData { client_sent_at, server_received_at, server_sent_at: validator.get_current_time() })
+                    }
+                    None => None,
+                };
+                ValidatorMessage::Pong { exchange_times }
+            }
+
+            MinerMessage::Hello { .. } => ValidatorMessage::Error {
+                message: "Hello must be sent untagged, as the first message on a connection".to_string(),
+            },
+
+            MinerMessage::RequestFaucet { .. } => ValidatorMessage::Error {
+                message: "Faucet requests need a client connection".to_string(),
+            },
+
+            MinerMessage::WatchAddress { .. } => ValidatorMessage::Error {
+                message: "WatchAddress needs a connection to push activity on".to_string(),
+            },
+
             MinerMessage::GetBlockchainInfo => {
-                let validator = validator.lock().await;
                 let block_count = validator.get_block_count();
                 let difficulty = validator.get_difficulty();
 
                 ValidatorMessage::BlockchainInfo {
                     block_count,
-                    difficulty: format!("0x{:X}", difficulty),
+                    difficulty,
+                    finalized_height: validator.finalized_height(),
+                    clock_offset_ms: validator.get_clock_offset_ms(),
+                    clock_drift_paused: validator.is_clock_drift_paused(),
+                }
+            }
+
+            MinerMessage::GetChainTip => {
+                let tip_hash = validator.blockchain.blocks.last().map_or(BlockHash::ZERO, |block| block.hash);
+
+                ValidatorMessage::ChainTip {
+                    tip_hash: tip_hash.to_string(),
+                    next_index: validator.get_block_count() as u32,
+                    difficulty: validator.get_difficulty(),
+                }
+            }
+
+            MinerMessage::GetBalance { address } => {
+                let balance = validator.blockchain.balance_of(&address);
+
+                ValidatorMessage::Balance { address, balance }
+            }
+
+            MinerMessage::GetMinerStats { miner_id } => {
+                let stats = validator.miner_stats().get(&miner_id).cloned()
+                    .unwrap_or_else(|| MinerStats::new(&miner_id));
+
+                ValidatorMessage::MinerStats(MinerStatsData::from_stats(&stats))
+            }
+
+            MinerMessage::GetLeaderboard { limit } => {
+                let miners = validator.miner_stats().leaderboard(limit.min(MAX_LEADERBOARD_SIZE) as usize).into_iter()
+                    .map(MinerStatsData::from_stats)
+                    .collect();
+
+                ValidatorMessage::Leaderboard { miners }
+            }
+
+            MinerMessage::GetHistory { address, page } => {
+                match page.history(&validator.blockchain, &address) {
+                    Ok((transactions, next_cursor)) => ValidatorMessage::History { address, transactions, next_cursor },
+                    Err(message) => ValidatorMessage::Error { message },
+                }
+            }
+
+            MinerMessage::GetRoundHistory { page, miner } => {
+                match page.rounds(validator.round_history(), miner.as_deref()) {
+                    Ok((rounds, next_cursor)) => ValidatorMessage::RoundHistory { rounds, next_cursor },
+                    Err(message) => ValidatorMessage::Error { message },
+                }
+            }
+
+            MinerMessage::GetHeaders { from, max } => {
+                let headers = validator.blockchain.blocks.iter()
+                    .skip(from as usize)
+                    .take(max.min(MAX_HEADERS_PER_MESSAGE) as usize)
+                    .map(|block| BlockHeaderData::from_header(&block.header, validator.get_receipt(block.header.index)))
+                    .collect();
+
+                ValidatorMessage::Headers { headers }
+            }
+
+            MinerMessage::GetTransactionProof { height, tx_hash } => {
+                let proof = tx_hash.parse::<Txid>().ok().and_then(|tx_hash| {
+                    let block = validator.blockchain.blocks.get(height as usize)?;
+                    let position = block.transaction_hashes().iter().position(|hash| *hash == tx_hash)?;
+                    block.merkle_proof(position)
+                });
+
+                match proof {
+                    Some(proof) => ValidatorMessage::TransactionProof { height, proof: MerkleProofData::from_proof(&proof) },
+                    None => ValidatorMessage::Error {
+                        message: format!("Transaction {} is not in block {}", tx_hash, height),
+                    },
+                }
+            }
+        }
+    }
+
+    /// Process a message from a peer validator
+    async fn process_peer_message(
+        message: PeerMessage,
+        validator: &ValidatorHandle,
+        peers: &PeerLinks,
+    ) -> PeerMessage {
+        if let PeerMessage::GetPeers { address } = message {
+            return Self::share_peers(address, peers);
+        }
+        let finalized = matches!(message, PeerMessage::Finalized(_));
+        let reply = validator.call(move |validator| Self::apply_peer_message(message, validator)).await;
+        if finalized {
+            Self::sync_finalized_blocks(validator, peers).await;
+        }
+        reply
+    }
+
+    /// Fetch finalized blocks we don't hold, e.g. after a certificate rolled our chain back
+    ///
+    /// Each is taken from the first peer serving the certified hash.
+    async fn sync_finalized_blocks(validator: &ValidatorHandle, peers: &PeerLinks) {
+        while let Some((height, hash)) = validator.call(|validator| validator.missing_finalized_block()).await {
+            let mut synced = false;
+            for peer in peers.all_addresses() {
+                let block = match Self::fetch_blocks(peers, &peer, height, 1).await
+                    .map(|blocks| blocks.into_iter().next())
+                {
+                    Ok(Some(Canonical(block))) if block.hash == hash => block,
+                    Ok(_) => continue,
+                    Err(e) => {
+                        debug!(%peer, height, error = %e, "failed to fetch finalized block");
+                        continue;
+                    }
+                };
+                match validator.call(move |validator| validator.apply_synced_blocks(vec![block])).await {
+                    Ok(()) => {
+                        synced = true;
+                        break;
+                    }
+                    Err(e) => warn!(%peer, height, error = %e, "finalized block from peer is invalid"),
+                }
+            }
+            if !synced {
+                warn!(height, "no peer served the finalized block");
+                return;
+            }
+        }
+    }
+
+    /// Process a message from a peer validator, on the validator task
+    fn apply_peer_message(message: PeerMessage, validator: &mut Validator) -> PeerMessage {
+        let result = match message {
+            PeerMessage::ProposeAcceptance { miner_id, block, vote } => {
+                let Canonical(block) = *block;
+                let span = info_span!("peer_proposal", miner_id = %miner_id, index = block.header.index);
+                let _enter = span.enter();
+
+                vote.to_vote()
+                    .and_then(|vote| validator.handle_peer_proposal(block, miner_id, vote))
+                    .map(|vote| {
+                        info!("voted for peer block");
+                        PeerMessage::Vote(VoteData::from_vote(&vote))
+                    })
+            }
+
+            PeerMessage::ProposeCompactAcceptance { miner_id, block, vote } => {
+                let span = info_span!("peer_proposal", miner_id = %miner_id, index = block.header.0.index);
+                let _enter = span.enter();
+
+                let compact = block.to_compact();
+                match validator.reconstruct_block(&compact) {
+                    Ok(block) => vote.to_vote()
+                        .and_then(|vote| validator.handle_peer_proposal(block, miner_id, vote))
+                        .map(|vote| {
+                            info!("voted for peer block");
+                            PeerMessage::Vote(VoteData::from_vote(&vote))
+                        }),
+                    Err(indexes) => {
+                        debug!(missing = indexes.len(), "requesting transactions for compact block");
+                        Ok(PeerMessage::MissingTransactions { block_hash: hex::encode(compact.header.hash()), indexes })
+                    }
+                }
+            }
+
+            PeerMessage::Vote(vote) => {
+                vote.to_vote()
+                    .and_then(|vote| validator.add_peer_vote(vote))
+                    .map(|certificate| {
+                        if let Some(certificate) = certificate {
+                            info!(height = certificate.height, "block finalized");
+                        }
+                        PeerMessage::Ack
+                    })
+            }
+
+            PeerMessage::Finalized(certificate) => {
+                certificate.to_certificate()
+                    .and_then(|certificate| {
+                        let height = certificate.height;
+                        validator.add_certificate(certificate)?;
+                        info!(height, "block finalized by peer certificate");
+                        Ok(PeerMessage::Ack)
+                    })
+            }
+
+            PeerMessage::GetBlocks { from, max, to, address } => {
+                let synced_from = validator.blockchain.utxo_base().map_or(0, |base| base.height + 1);
+                if from < synced_from {
+                    Err(format!("Blocks below height {} were never synced", synced_from))
+                } else {
+                    let page = PageQuery { from_height: Some(from), to_height: to, limit: Some(max), cursor: None };
+                    page.blocks(&validator.blockchain, address.as_deref()).map(|(blocks, next_cursor)| PeerMessage::Blocks {
+                        blocks: blocks.into_iter().cloned().map(Canonical).collect(),
+                        next_from: next_cursor.and_then(|cursor| cursor.parse().ok()),
+                    })
                 }
             }
+
+            PeerMessage::GetUtxoSnapshot { offset, max } => {
+                validator.utxo_snapshot().map(|snapshot| {
+                    PeerMessage::UtxoSnapshot(UtxoSnapshotData::from_snapshot(snapshot, offset, max.min(MAX_SNAPSHOT_OUTPUTS_PER_MESSAGE)))
+                })
+            }
+
+            PeerMessage::FederationCommit => {
+                validator.federation_commit().and_then(|(member_id, commitments)| Ok(PeerMessage::FederationCommitments {
+                    member_id,
+                    commitments: encode_commitments(&commitments)?,
+                }))
+            }
+
+            PeerMessage::FederationSign { height, miner_id, accepted_at, package } => {
+                decode_signing_package(&package)
+                    .and_then(|package| validator.federation_sign(&package, height, &miner_id, accepted_at))
+                    .map(|(member_id, share)| PeerMessage::FederationShare { member_id, share: encode_signature_share(&share) })
+            }
+
+            PeerMessage::FederatedReceipt(receipt) => {
+                receipt.to_receipt()
+                    .and_then(|receipt| {
+                        let height = receipt.height;
+                        validator.add_federated_receipt(receipt)?;
+                        info!(height, "receipt co-signed by the federation");
+                        Ok(PeerMessage::Ack)
+                    })
+            }
+
+            PeerMessage::Ack | PeerMessage::Rejected { .. } | PeerMessage::MissingTransactions { .. }
+            | PeerMessage::GetPeers { .. } | PeerMessage::Peers { .. }
+            | PeerMessage::Blocks { .. } | PeerMessage::UtxoSnapshot(_)
+            | PeerMessage::FederationCommitments { .. } | PeerMessage::FederationShare { .. } => {
+                Err("Unexpected peer message".to_string())
+            }
+        };
+
+        result.unwrap_or_else(|message| {
+            warn!(error = %message, "peer request rejected");
+            PeerMessage::Rejected { message }
+        })
+    }
+
+    /// Answer `GetPeers` with the peers that have answered us, remembering the asker
+    ///
+    /// The asker's address isn't passed on until our own discovery reaches it.
+    fn share_peers(asker: Option<String>, peers: &PeerLinks) -> PeerMessage {
+        let mut book = peers.book.lock().unwrap();
+        let addresses = book.share(MAX_SHARED_PEERS + 1).into_iter()
+            .filter(|address| Some(address) != asker.as_ref())
+            .take(MAX_SHARED_PEERS)
+            .collect();
+        if let Some(asker) = asker.filter(|asker| Some(asker) != peers.advertised.as_ref()) {
+            if book.add(&asker) {
+                debug!(peer = %asker, "learned peer from GetPeers");
+            }
         }
+        PeerMessage::Peers { addresses }
+    }
+
+    /// Ask the seeds and every known peer for their peers every `DISCOVERY_INTERVAL`, forever
+    ///
+    /// Each address gets one attempt per round: the book counts failures and evicts
+    /// addresses that keep failing, so retrying here would only slow the round down.
+    async fn discover_peers(peers: Arc<PeerLinks>, path: Option<PathBuf>) {
+        let request = PeerMessage::GetPeers { address: peers.advertised.clone() };
+        let mut ticker = tokio::time::interval(DISCOVERY_INTERVAL);
+        loop {
+            ticker.tick().await;
+
+            let mut targets = peers.seeds.clone();
+            for address in peers.all_addresses() {
+                if !targets.contains(&address) {
+                    targets.push(address);
+                }
+            }
+            targets.retain(|address| Some(address) != peers.advertised.as_ref());
+
+            let mut learned = 0;
+            for address in targets {
+                let response = peers.ask(&address, &request).await;
+
+                let mut book = peers.book.lock().unwrap();
+                match response {
+                    Ok(PeerMessage::Peers { addresses }) => {
+                        book.mark_seen(&address, crate::now());
+                        for shared in addresses.iter().filter(|shared| Some(*shared) != peers.advertised.as_ref()) {
+                            if book.add(shared) {
+                                learned += 1;
+                            }
+                        }
+                    }
+                    other => {
+                        let error = match other {
+                            Ok(message) => format!("Unexpected response: {:?}", message),
+                            Err(e) => e.to_string(),
+                        };
+                        let evicted = book.mark_failed(&address);
+                        debug!(peer = %address, error = %error, evicted, "peer discovery failed");
+                    }
+                }
+            }
+
+            let book = peers.book.lock().unwrap().clone();
+            if learned > 0 {
+                info!(learned, known = book.len(), "discovered peers");
+            }
+            if let Some(path) = &path {
+                if let Err(e) = book.save(path) {
+                    warn!(error = %e, "failed to save peer book");
+                }
+            }
+        }
+    }
+
+    /// Sync an empty chain from `peer`: its UTXO snapshot, the headers up to it and the blocks after it
+    ///
+    /// The snapshot is checked against the headers and the block after it,
+    /// which must apply on top of it before the chain is replaced (see
+    /// `Validator::adopt_utxo_snapshot`). The blocks after that are validated
+    /// in full. Returns the snapshot's height and the blocks the chain ends up with.
+    async fn fast_sync(validator: &ValidatorHandle, peers: &PeerLinks, peer: &str, trusted_key: Option<Vec<u8>>) -> Result<(u32, usize), String> {
+        let mut parts: Vec<UtxoSnapshotData> = vec![];
+        let mut received = 0;
+        loop {
+            let request = PeerMessage::GetUtxoSnapshot { offset: received, max: MAX_SNAPSHOT_OUTPUTS_PER_MESSAGE };
+            let part = match peers.send(peer, &request).await.map_err(|e| e.to_string())? {
+                PeerMessage::UtxoSnapshot(part) => part,
+                PeerMessage::Rejected { message } => return Err(format!("{} refused its UTXO snapshot: {}", peer, message)),
+                other => return Err(format!("Unexpected response: {:?}", other)),
+            };
+            received += part.outputs.len() as u32;
+            let done = part.outputs.is_empty() || received >= part.total_outputs;
+            parts.push(part);
+            if done {
+                break;
+            }
+        }
+        let snapshot = UtxoSnapshotData::to_snapshot(&parts)?;
+        let height = snapshot.height;
+        debug!(%peer, height, outputs = snapshot.outputs.len(), "UTXO snapshot downloaded");
+
+        // the headers up to the snapshot's block
+        let mut headers = vec![];
+        while headers.len() <= height as usize {
+            let from = headers.len() as u32;
+            let request = MinerMessage::GetHeaders { from, max: height + 1 - from };
+            let batch = match peers.query(peer, &request).await.map_err(|e| e.to_string())? {
+                ValidatorMessage::Headers { headers } if !headers.is_empty() => headers,
+                ValidatorMessage::Headers { .. } => return Err(format!("{} has no headers from height {}", peer, from)),
+                other => return Err(format!("Unexpected response: {:?}", other)),
+            };
+            for header in batch {
+                headers.push(header.to_header()?);
+            }
+        }
+        headers.truncate(height as usize + 1);
+
+        // the block committing to the snapshot, without which the snapshot isn't taken
+        let mut from = height + 1;
+        let next = match Self::fetch_blocks(peers, peer, from, 1).await?.into_iter().next() {
+            Some(Canonical(block)) => block,
+            None => return Err(format!("{} has no block at height {} to check its snapshot against", peer, from)),
+        };
+        validator.call(move |validator| validator.adopt_utxo_snapshot(&headers, snapshot, next, trusted_key.as_deref())).await?;
+
+        from += 1;
+        loop {
+            let blocks = Self::fetch_blocks(peers, peer, from, MAX_BLOCKS_PER_MESSAGE).await?;
+            if blocks.is_empty() {
+                break;
+            }
+            let blocks = blocks.into_iter().map(|Canonical(block)| block).collect::<Vec<Block>>();
+            from += blocks.len() as u32;
+            validator.call(move |validator| validator.apply_synced_blocks(blocks)).await?;
+        }
+        Ok((height, from as usize))
+    }
+
+    // up to `max` of `peer`'s blocks from height `from`
+    async fn fetch_blocks(peers: &PeerLinks, peer: &str, from: u32, max: u32) -> Result<Vec<Canonical<Block>>, String> {
+        let request = PeerMessage::GetBlocks { from, max, to: None, address: None };
+        match peers.send(peer, &request).await.map_err(|e| e.to_string())? {
+            PeerMessage::Blocks { blocks, .. } => Ok(blocks),
+            PeerMessage::Rejected { message } => Err(format!("{} refused blocks from height {}: {}", peer, from, message)),
+            other => Err(format!("Unexpected response: {:?}", other)),
+        }
+    }
+
+    /// Have a threshold of federation members co-sign `receipt`, then share the federated receipt
+    ///
+    /// We commit first, then peers until the threshold is met; every member
+    /// that committed must then sign, or the attempt is given up.
+    async fn co_sign_receipt(receipt: BlockReceipt, validator: ValidatorHandle, peers: Arc<PeerLinks>) {
+        let height = receipt.height;
+        let result = async {
+            let federation = validator.call(|validator| validator.federation().cloned()).await
+                .ok_or("This validator is not in a federation")?;
+            let message = FederatedReceipt::message(&receipt.block_hash, height, &receipt.miner_id, receipt.accepted_at);
+            let mut session = federation.signing_session(&message);
+
+            // who committed: None for ourselves
+            let mut signers = vec![];
+            let (member_id, commitments) = validator.call(|validator| validator.federation_commit()).await?;
+            session.add_commitments(member_id, commitments)?;
+            signers.push(None);
+            for peer in peers.all_addresses() {
+                if signers.len() >= federation.threshold() as usize {
+                    break;
+                }
+                let commitments = match peers.send(&peer, &PeerMessage::FederationCommit).await {
+                    Ok(PeerMessage::FederationCommitments { member_id, commitments }) => decode_commitments(&commitments)
+                        .and_then(|commitments| session.add_commitments(member_id, commitments)),
+                    Ok(PeerMessage::Rejected { message }) => Err(message),
+                    Ok(other) => Err(format!("Unexpected response: {:?}", other)),
+                    Err(e) => Err(e.to_string()),
+                };
+                match commitments {
+                    Ok(()) => signers.push(Some(peer)),
+                    Err(e) => debug!(%peer, error = %e, "peer did not commit to co-signing"),
+                }
+            }
+
+            let package = session.signing_package()?;
+            for signer in signers {
+                let (member_id, share) = match signer {
+                    None => {
+                        let (package, miner_id, accepted_at) = (package.clone(), receipt.miner_id.clone(), receipt.accepted_at);
+                        validator.call(move |validator| validator.federation_sign(&package, height, &miner_id, accepted_at)).await?
+                    }
+                    Some(peer) => {
+                        let request = PeerMessage::FederationSign {
+                            height,
+                            miner_id: receipt.miner_id.clone(),
+                            accepted_at: receipt.accepted_at,
+                            package: encode_signing_package(&package)?,
+                        };
+                        match peers.send(&peer, &request).await.map_err(|e| e.to_string())? {
+                            PeerMessage::FederationShare { member_id, share } => (member_id, decode_signature_share(&share)?),
+                            PeerMessage::Rejected { message } => return Err(format!("{} refused to sign: {}", peer, message)),
+                            other => return Err(format!("Unexpected response: {:?}", other)),
+                        }
+                    }
+                };
+                session.add_share(member_id, share)?;
+            }
+
+            let federated = FederatedReceipt {
+                block_hash: receipt.block_hash,
+                height,
+                miner_id: receipt.miner_id.clone(),
+                accepted_at: receipt.accepted_at,
+                signature: session.aggregate()?,
+            };
+            let shared = PeerMessage::FederatedReceipt(FederatedReceiptData::from_receipt(&federated));
+            validator.call(move |validator| validator.add_federated_receipt(federated)).await?;
+            Ok::<_, String>(shared)
+        }.await;
+
+        match result {
+            Ok(message) => {
+                info!(height, "receipt co-signed by the federation");
+                for peer in peers.all_addresses().iter() {
+                    if let Err(e) = peers.send(peer, &message).await {
+                        warn!(%peer, error = %e, "failed to share federated receipt");
+                    }
+                }
+            }
+            Err(e) => warn!(height, error = %e, "federation did not co-sign the receipt"),
+        }
+    }
+
+    /// Ask every peer to vote for a block we accepted, then share the certificate if a quorum formed
+    ///
+    /// Every peer gets the proposal, even after a quorum is reached, so they all apply the block.
+    /// The block is sent compact; a peer missing some of its transactions gets them in a second round trip.
+    async fn propose_to_peers(
+        miner_id: String,
+        block: Block,
+        vote: AcceptanceVote,
+        validator: ValidatorHandle,
+        peers: Arc<PeerLinks>,
+    ) {
+        let mut finalized = None;
+        let proposal = |compact: CompactBlock| PeerMessage::ProposeCompactAcceptance {
+            miner_id: miner_id.clone(),
+            block: Box::new(CompactBlockData::from_compact(&compact)),
+            vote: VoteData::from_vote(&vote),
+        };
+        let compact_proposal = proposal(CompactBlock::from_block(&block));
+
+        for peer in peers.all_addresses().iter() {
+            let mut response = peers.send(peer, &compact_proposal).await;
+            if let Ok(PeerMessage::MissingTransactions { indexes, .. }) = &response {
+                debug!(%peer, missing = indexes.len(), "peer is missing transactions, resending");
+                let mut positions = indexes.clone();
+                positions.push(0);
+                response = peers.send(peer, &proposal(CompactBlock::from_block_with(&block, &positions))).await;
+            }
+
+            let vote = match response {
+                Ok(PeerMessage::Vote(vote)) => vote.to_vote(),
+                Ok(PeerMessage::Rejected { message }) => Err(message),
+                Ok(other) => Err(format!("Unexpected response: {:?}", other)),
+                Err(e) => Err(e.to_string()),
+            };
+
+            let certificate = match vote {
+                Ok(vote) => validator.call(move |validator| validator.add_peer_vote(vote)).await,
+                Err(e) => Err(e),
+            };
+
+            match certificate {
+                Ok(Some(certificate)) => {
+                    info!(height = certificate.height, "block finalized");
+                    finalized = Some(certificate);
+                }
+                Ok(None) => {}
+                Err(e) => warn!(%peer, error = %e, "peer did not vote for block"),
+            }
+        }
+
+        if let Some(certificate) = finalized {
+            let message = PeerMessage::Finalized(CertificateData::from_certificate(&certificate));
+            for peer in peers.all_addresses().iter() {
+                if let Err(e) = peers.send(peer, &message).await {
+                    warn!(%peer, error = %e, "failed to share certificate");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tx_builder::TransactionBuilder;
+
+    async fn chain_tip(validator: &ValidatorHandle) -> (String, u32, Difficulty) {
+        let peers = Arc::new(PeerLinks::default());
+        match ValidatorServer::process_message(MinerMessage::GetChainTip, validator, &peers).await {
+            ValidatorMessage::ChainTip { tip_hash, next_index, difficulty } => (tip_hash, next_index, difficulty),
+            other => panic!("Wrong message type: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain_tip_follows_accepted_blocks() {
+        let params = ChainParams::regtest();
+        let validator = ValidatorHandle::spawn(Validator::new_with_params(params.clone()));
+        assert_eq!(chain_tip(&validator).await, (BlockHash::ZERO.to_string(), 0, params.difficulty));
+
+        let coinbase = TransactionBuilder::new(1000).pay_to("alice", params.coinbase_value).build_coinbase().unwrap();
+        let mut genesis = Block::new(0, 1000, BlockHash::ZERO, vec![coinbase]);
+        genesis.mine(params.difficulty);
+        let block = genesis.clone();
+        validator.call(move |validator| validator.blockchain.update_with_block(block)).await.unwrap();
+        assert_eq!(chain_tip(&validator).await, (genesis.hash.to_string(), 1, params.difficulty));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fast_sync_from_peer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let mut server = ValidatorServer::new_with_params(ChainParams::regtest(), address.clone());
+        server.set_node_key(NodeKey::generate());
+        server.set_listener(Box::new(listener));
+        let source = server.handle().validator().clone();
+        tokio::spawn(async move { server.start().await.map_err(|e| e.to_string()) });
+        source.call(|validator| validator.generate_blocks(5, "alice")).await.unwrap();
+
+        let key = source.call(|validator| validator.utxo_snapshot().map(|snapshot| snapshot.public_key.clone())).await.unwrap();
+        let synced = ValidatorHandle::spawn(Validator::new_with_params(ChainParams::regtest()));
+        let peers = PeerLinks::default();
+        // a snapshot is only taken from a signer we trust
+        assert!(ValidatorServer::fast_sync(&synced, &peers, &address, None).await.is_err());
+        assert_eq!(synced.call(|validator| validator.get_block_count()).await, 0);
+        assert_eq!(ValidatorServer::fast_sync(&synced, &peers, &address, Some(key.clone())).await, Ok((3, 5)));
+        let ours = synced.call(|validator| validator.blockchain.utxo_commitment()).await;
+        assert_eq!(ours, source.call(|validator| validator.blockchain.utxo_commitment()).await);
+
+        // the synced chain can't be synced again, or serve the blocks it skipped
+        assert!(ValidatorServer::fast_sync(&synced, &peers, &address, Some(key)).await.is_err());
+        let reply = synced.call(|validator| ValidatorServer::apply_peer_message(PeerMessage::GetBlocks { from: 0, max: 10, to: None, address: None }, validator)).await;
+        assert!(matches!(reply, PeerMessage::Rejected { .. }), "{:?}", reply);
+    }
+
+    #[tokio::test]
+    async fn test_retried_requests_get_the_first_answer() {
+        let mut validator = Validator::new_with_params(ChainParams::regtest());
+        validator.set_commit_reveal(true);
+        validator.start_new_round();
+        let validator = ValidatorHandle::spawn(validator);
+        let peers = Arc::new(PeerLinks::default());
+        let rate_limits = RateLimits::new(RateLimitConfig::default());
+        let responses = ResponseCache::default();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let commit = |miner_id: &str, request_id: Option<&str>| MinerMessage::CommitTimestamp {
+            miner_id: miner_id.to_string(),
+            commitment: hex::encode([7; 32]),
+            request_id: request_id.map(str::to_string),
+        };
+        let answer = |message| ValidatorServer::answer_miner(message, ip, &validator, &peers, &rate_limits, &responses);
+
+        // a resent commitment replays the first answer instead of being refused
+        for _ in 0..2 {
+            let reply = answer(commit("alice", Some("r1"))).await;
+            assert!(matches!(reply, ValidatorMessage::CommitmentAccepted { .. }), "{:?}", reply);
+        }
+        assert_eq!(responses.len(), 1);
+
+        // untagged resends are processed again
+        assert!(matches!(answer(commit("bob", None)).await, ValidatorMessage::CommitmentAccepted { .. }));
+        assert!(matches!(answer(commit("bob", None)).await, ValidatorMessage::Error { .. }));
+        let long_id = "x".repeat(MAX_REQUEST_ID_LEN + 1);
+        assert!(matches!(answer(commit("carol", Some(&long_id))).await, ValidatorMessage::Error { .. }));
     }
 }
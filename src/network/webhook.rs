@@ -0,0 +1,329 @@
+//! HTTP webhooks for validator events
+//!
+//! External systems (chat bots, monitoring) that can't hold a miner
+//! connection open can have the validator POST its events to them instead.
+//! Each event is a JSON `WebhookPayload` tagged with its kind:
+//!
+//! - `block_accepted` — a block was added to the chain
+//! - `miner_locked_out` — the miner who won it can't submit again until `until`
+//! - `round_started` — a new mining round began
+//!
+//! `Webhooks` is a `ValidationHook`, so it hears about events on the validator
+//! task; it only queues them. Each URL has its own delivery task and queue,
+//! so a slow or unreachable endpoint doesn't hold up the others. A failed
+//! delivery is retried under the configured `RetryPolicy` while the endpoint
+//! is unreachable or answers 429 or 5xx; other answers drop the event. When
+//! an endpoint's queue is full, new events for it are dropped and logged.
+//!
+//! With a secret configured, every request carries `X-Hourcoin-Timestamp`
+//! (milliseconds since the Unix epoch, UTC) and `X-Hourcoin-Signature`:
+//! `sha256=` and the hex HMAC-SHA256 of the timestamp, a `.`, and the body.
+//! Receivers check it with `verify_payload`, and should refuse stale
+//! timestamps so a captured request can't be replayed later.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::mpsc;
+use tracing::warn;
+use crate::decision_log::DecisionSource;
+use crate::validation_hook::{HookContext, RoundStart, ValidationHook};
+use crate::{now_utc, Block};
+use super::retry::RetryPolicy;
+
+/// Header carrying the time a request was signed
+pub const TIMESTAMP_HEADER: &str = "X-Hourcoin-Timestamp";
+
+/// Header carrying the payload signature
+pub const SIGNATURE_HEADER: &str = "X-Hourcoin-Signature";
+
+/// Events waiting for each endpoint before new ones are dropped
+const WEBHOOK_QUEUE_CAPACITY: usize = 1024;
+
+/// Where events are sent, and how
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    /// Every event is POSTed to each of these
+    pub urls: Vec<String>,
+    /// Signs each request if set (see the module docs)
+    pub secret: Option<String>,
+    /// How failed deliveries are retried
+    pub retry: RetryPolicy,
+    /// Longest a single request may take
+    pub timeout: Duration,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            urls: vec![],
+            secret: None,
+            retry: RetryPolicy::default(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Something that happened on the validator
+///
+/// Times are milliseconds since the Unix epoch, as `u64`: serde can't buffer a `u128` for a tagged enum.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    BlockAccepted {
+        height: u32,
+        block_hash: String,
+        miner_id: String,
+        source: DecisionSource,
+        timestamp: u64,
+        transactions: usize,
+    },
+    MinerLockedOut {
+        miner_id: String,
+        until: u64,
+    },
+    RoundStarted {
+        round_id: String,
+        round_start: u64,
+        height: u32,
+        tonce: Option<u8>,
+    },
+}
+
+/// The body of a webhook request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    /// Counts events from 0 since the validator started, so receivers can spot gaps
+    pub sequence: u64,
+    /// When the event happened, in milliseconds since the Unix epoch (UTC)
+    pub created_at: u64,
+    #[serde(flatten)]
+    pub event: WebhookEvent,
+}
+
+/// Queues validator events for delivery to the configured endpoints
+pub struct Webhooks {
+    endpoints: Vec<(String, mpsc::Sender<Arc<String>>)>,
+    sequence: u64,
+}
+
+impl Webhooks {
+    /// Start a delivery task for each URL in `config`; needs a Tokio runtime
+    pub fn spawn(config: WebhookConfig) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .map_err(|e| format!("Failed to build webhook client: {}", e))?;
+        let (secret, retry) = (config.secret.map(Arc::new), config.retry);
+
+        let endpoints = config.urls.into_iter().map(|url| {
+            let (sender, receiver) = mpsc::channel(WEBHOOK_QUEUE_CAPACITY);
+            tokio::spawn(deliver_all(client.clone(), url.clone(), secret.clone(), retry, receiver));
+            (url, sender)
+        }).collect();
+        Ok(Webhooks { endpoints, sequence: 0 })
+    }
+
+    /// Queue `event` for every endpoint
+    pub fn publish(&mut self, event: WebhookEvent) {
+        let payload = WebhookPayload { sequence: self.sequence, created_at: now_utc() as u64, event };
+        self.sequence += 1;
+        let body = match serde_json::to_string(&payload) {
+            Ok(body) => Arc::new(body),
+            Err(e) => {
+                warn!(error = %e, "failed to encode webhook event");
+                return;
+            }
+        };
+        for (url, queue) in &self.endpoints {
+            if queue.try_send(Arc::clone(&body)).is_err() {
+                warn!(%url, sequence = payload.sequence, "webhook queue full or closed; event dropped");
+            }
+        }
+    }
+}
+
+impl ValidationHook for Webhooks {
+    fn name(&self) -> &str {
+        "webhooks"
+    }
+
+    fn on_block_accepted(&mut self, block: &Block, context: &HookContext) {
+        self.publish(WebhookEvent::BlockAccepted {
+            height: block.header.index,
+            block_hash: block.hash.to_string(),
+            miner_id: context.miner_id.to_string(),
+            source: context.source,
+            timestamp: block.header.timestamp as u64,
+            transactions: block.body.transactions.len(),
+        });
+    }
+
+    fn on_miner_locked_out(&mut self, miner_id: &str, until: u128) {
+        self.publish(WebhookEvent::MinerLockedOut { miner_id: miner_id.to_string(), until: until as u64 });
+    }
+
+    fn on_round_started(&mut self, round: &RoundStart) {
+        self.publish(WebhookEvent::RoundStarted {
+            round_id: round.round_id.to_string(),
+            round_start: round.round_start as u64,
+            height: round.height,
+            tonce: round.tonce,
+        });
+    }
+}
+
+/// The `X-Hourcoin-Signature` value for `body` sent at `timestamp`
+pub fn sign_payload(secret: &str, timestamp: &str, body: &str) -> String {
+    format!("sha256={}", hex::encode(payload_mac(secret, timestamp, body).finalize().into_bytes()))
+}
+
+/// Whether `signature` is the `X-Hourcoin-Signature` of `body` sent at `timestamp`
+pub fn verify_payload(secret: &str, timestamp: &str, body: &str, signature: &str) -> bool {
+    signature.strip_prefix("sha256=")
+        .and_then(|hex_mac| hex::decode(hex_mac).ok())
+        .is_some_and(|mac| payload_mac(secret, timestamp, body).verify_slice(&mac).is_ok())
+}
+
+fn payload_mac(secret: &str, timestamp: &str, body: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    mac
+}
+
+/// Why a delivery failed, and whether trying again might help
+#[derive(Debug)]
+struct DeliveryError {
+    message: String,
+    retryable: bool,
+}
+
+impl fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+async fn deliver_all(
+    client: reqwest::Client,
+    url: String,
+    secret: Option<Arc<String>>,
+    retry: RetryPolicy,
+    mut queue: mpsc::Receiver<Arc<String>>,
+) {
+    while let Some(body) = queue.recv().await {
+        let delivered = retry.retry(
+            "webhook",
+            || deliver(&client, &url, secret.as_deref().map(String::as_str), &body),
+            |e: &DeliveryError| e.retryable,
+        ).await;
+        if let Err(e) = delivered {
+            warn!(%url, error = %e, "webhook delivery failed; event dropped");
+        }
+    }
+}
+
+async fn deliver(client: &reqwest::Client, url: &str, secret: Option<&str>, body: &str) -> Result<(), DeliveryError> {
+    let timestamp = now_utc().to_string();
+    let mut request = client.post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(TIMESTAMP_HEADER, &timestamp)
+        .body(body.to_string());
+    if let Some(secret) = secret {
+        request = request.header(SIGNATURE_HEADER, sign_payload(secret, &timestamp, body));
+    }
+
+    let response = request.send().await
+        .map_err(|e| DeliveryError { message: format!("Request failed: {}", e), retryable: true })?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    Err(DeliveryError {
+        message: format!("Endpoint answered {}", status),
+        retryable: status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Read one HTTP request, answer it with `status`, and return the request
+    async fn answer(listener: &TcpListener, status: &str) -> String {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 4096];
+        loop {
+            let n = socket.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..n]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head.lines()
+                    .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|n| n.trim().parse::<usize>().unwrap()))
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    socket.write_all(format!("HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status).as_bytes()).await.unwrap();
+                    return text;
+                }
+            }
+        }
+    }
+
+    fn header<'a>(request: &'a str, name: &str) -> &'a str {
+        request.lines()
+            .find_map(|line| line.split_once(':').filter(|(key, _)| key.eq_ignore_ascii_case(name)))
+            .map(|(_, value)| value.trim())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_payload_signature() {
+        let signature = sign_payload("secret", "1700000000000", "{}");
+        assert!(verify_payload("secret", "1700000000000", "{}", &signature));
+        assert!(!verify_payload("secret", "1700000000001", "{}", &signature));
+        assert!(!verify_payload("other", "1700000000000", "{}", &signature));
+        assert!(!verify_payload("secret", "1700000000000", "{}", "sha256=00"));
+    }
+
+    #[tokio::test]
+    async fn test_delivery_is_signed_and_retried() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let mut webhooks = Webhooks::spawn(WebhookConfig {
+            urls: vec![url],
+            secret: Some("secret".to_string()),
+            retry: RetryPolicy { initial_backoff: Duration::from_millis(1), ..RetryPolicy::default() },
+            ..WebhookConfig::default()
+        }).unwrap();
+
+        webhooks.on_miner_locked_out("alice", 5_000);
+        // the first attempt fails, so the same event is sent again
+        let failed = answer(&listener, "503 Service Unavailable").await;
+        let request = answer(&listener, "200 OK").await;
+        let body = request.split_once("\r\n\r\n").unwrap().1;
+        assert_eq!(failed.split_once("\r\n\r\n").unwrap().1, body);
+        assert!(request.starts_with("POST /hook "));
+        assert!(verify_payload("secret", header(&request, TIMESTAMP_HEADER), body, header(&request, SIGNATURE_HEADER)));
+
+        let payload: WebhookPayload = serde_json::from_str(body).unwrap();
+        assert_eq!(payload.sequence, 0);
+        assert_eq!(payload.event, WebhookEvent::MinerLockedOut { miner_id: "alice".to_string(), until: 5_000 });
+        assert!(body.contains(r#""event":"miner_locked_out""#));
+
+        // a refusal isn't retried; the next event is sent next
+        webhooks.on_miner_locked_out("bob", 6_000);
+        webhooks.on_miner_locked_out("carol", 7_000);
+        answer(&listener, "400 Bad Request").await;
+        let request = answer(&listener, "200 OK").await;
+        let payload: WebhookPayload = serde_json::from_str(request.split_once("\r\n\r\n").unwrap().1).unwrap();
+        assert_eq!(payload.sequence, 2);
+    }
+}
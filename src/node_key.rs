@@ -0,0 +1,161 @@
+//! Validator node identity key
+//!
+//! Validators sign what they attest to (block receipts, and anything later
+//! exchanged between validators) with a long-lived sr25519 node key. Every
+//! signature is bound to a signing context so a signature made for one
+//! purpose can never be replayed as another.
+
+use schnorrkel::{signing_context, verify_batch, ExpansionMode, Keypair, MiniSecretKey, PublicKey, Signature};
+
+/// An sr25519 keypair kept with the 32-byte seed it was expanded from
+///
+/// The node key and the VRF key are both stored as their seed.
+pub(crate) struct SeededKeypair {
+    secret: MiniSecretKey,
+    pub(crate) keypair: Keypair,
+}
+
+impl SeededKeypair {
+    pub(crate) fn generate() -> Self {
+        Self::from_mini_secret(MiniSecretKey::generate())
+    }
+
+    /// Expand a 32-byte seed; `kind` names the key in the error
+    pub(crate) fn from_secret_bytes(bytes: &[u8], kind: &str) -> Result<Self, String> {
+        let secret = MiniSecretKey::from_bytes(bytes)
+            .map_err(|e| format!("Invalid {} secret key: {}", kind, e))?;
+        Ok(Self::from_mini_secret(secret))
+    }
+
+    fn from_mini_secret(secret: MiniSecretKey) -> Self {
+        let keypair = secret.expand_to_keypair(ExpansionMode::Ed25519);
+        SeededKeypair { secret, keypair }
+    }
+
+    pub(crate) fn secret_bytes(&self) -> [u8; 32] {
+        self.secret.to_bytes()
+    }
+
+    pub(crate) fn public_key(&self) -> Vec<u8> {
+        self.keypair.public.to_bytes().to_vec()
+    }
+}
+
+/// Long-lived signing key identifying a validator node
+pub struct NodeKey(SeededKeypair);
+
+impl NodeKey {
+    /// Generate a fresh random node key
+    pub fn generate() -> Self {
+        NodeKey(SeededKeypair::generate())
+    }
+
+    /// Load a node key from its 32-byte secret seed
+    pub fn from_secret_bytes(bytes: &[u8]) -> Result<Self, String> {
+        SeededKeypair::from_secret_bytes(bytes, "node").map(NodeKey)
+    }
+
+    /// The 32-byte secret seed (keep private)
+    pub fn secret_bytes(&self) -> [u8; 32] {
+        self.0.secret_bytes()
+    }
+
+    /// The 32-byte public key others use to verify this node's signatures
+    pub fn public_key(&self) -> Vec<u8> {
+        self.0.public_key()
+    }
+
+    /// Sign `message` under a signing context, returning the 64-byte signature
+    pub fn sign(&self, context: &[u8], message: &[u8]) -> Vec<u8> {
+        self.0.keypair.sign(signing_context(context).bytes(message)).to_bytes().to_vec()
+    }
+}
+
+/// A public key and a signature made with it
+pub type Signer<'a> = (&'a [u8], &'a [u8]);
+
+/// Verify a signature made with `NodeKey::sign`
+pub fn verify_signature(public_key: &[u8], context: &[u8], message: &[u8], signature: &[u8]) -> Result<(), String> {
+    let public_key = PublicKey::from_bytes(public_key)
+        .map_err(|e| format!("Invalid public key: {}", e))?;
+    let signature = Signature::from_bytes(signature)
+        .map_err(|e| format!("Invalid signature: {}", e))?;
+
+    public_key.verify(signing_context(context).bytes(message), &signature)
+        .map_err(|e| format!("Signature does not verify: {}", e))
+}
+
+/// Verify several `(public key, message, signature)` triples signed under one context
+///
+/// They are checked together in one batch, which costs about half as much per
+/// signature as `verify_signature` on each. If the batch fails they are
+/// checked one by one, so the error names the first bad signature.
+pub fn verify_signatures(context: &[u8], signed: &[(&[u8], &[u8], &[u8])]) -> Result<(), String> {
+    let check_each = || signed.iter()
+        .try_for_each(|(public_key, message, signature)| verify_signature(public_key, context, message, signature));
+    if signed.len() < 2 {
+        return check_each();
+    }
+
+    let mut public_keys = Vec::with_capacity(signed.len());
+    let mut signatures = Vec::with_capacity(signed.len());
+    for (public_key, _, signature) in signed {
+        match (PublicKey::from_bytes(public_key), Signature::from_bytes(signature)) {
+            (Ok(public_key), Ok(signature)) => {
+                public_keys.push(public_key);
+                signatures.push(signature);
+            }
+            _ => return check_each(),
+        }
+    }
+    let context = signing_context(context);
+    let transcripts = signed.iter().map(|(_, message, _)| context.bytes(message));
+    match verify_batch(transcripts, &signatures, &public_keys, false) {
+        Ok(()) => Ok(()),
+        Err(e) => check_each().and(Err(format!("Signature batch does not verify: {}", e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = NodeKey::generate();
+        let signature = key.sign(b"test", b"message");
+
+        assert_eq!(verify_signature(&key.public_key(), b"test", b"message", &signature), Ok(()));
+        assert!(verify_signature(&key.public_key(), b"test", b"other", &signature).is_err());
+        assert!(verify_signature(&key.public_key(), b"other", b"message", &signature).is_err());
+        assert!(verify_signature(&NodeKey::generate().public_key(), b"test", b"message", &signature).is_err());
+    }
+
+    #[test]
+    fn test_verify_signatures() {
+        let keys: Vec<NodeKey> = (0..3).map(|_| NodeKey::generate()).collect();
+        let messages: [&[u8]; 3] = [b"one", b"two", b"three"];
+        let signatures: Vec<Vec<u8>> = keys.iter().zip(messages).map(|(key, message)| key.sign(b"test", message)).collect();
+        let public_keys: Vec<Vec<u8>> = keys.iter().map(NodeKey::public_key).collect();
+        let mut signed: Vec<(&[u8], &[u8], &[u8])> = (0..3)
+            .map(|i| (public_keys[i].as_slice(), messages[i], signatures[i].as_slice()))
+            .collect();
+        assert_eq!(verify_signatures(b"test", &signed), Ok(()));
+        assert_eq!(verify_signatures(b"test", &[]), Ok(()));
+        assert!(verify_signatures(b"other", &signed).is_err());
+
+        // a swapped message fails the batch, and the error comes from checking one by one
+        signed[1].1 = b"one";
+        assert!(verify_signatures(b"test", &signed).unwrap_err().starts_with("Signature does not verify"));
+        signed[1] = (public_keys[1].as_slice(), messages[1], &signatures[1][..10]);
+        assert!(verify_signatures(b"test", &signed).unwrap_err().starts_with("Invalid signature"));
+    }
+
+    #[test]
+    fn test_restore_from_secret() {
+        let key = NodeKey::generate();
+        let restored = NodeKey::from_secret_bytes(&key.secret_bytes()).unwrap();
+        assert_eq!(key.public_key(), restored.public_key());
+        assert!(NodeKey::from_secret_bytes(&[0; 4]).is_err());
+    }
+}
@@ -0,0 +1,197 @@
+//! Pool worker: mines shares for a `PoolServer`
+//!
+//! The worker mines the pool's template against the share target instead of
+//! the block difficulty. Each share found is submitted and the worker moves
+//! on to its next extra-nonce, so it never sends the same hash twice. Jobs
+//! are polled every `JOB_POLL_INTERVAL`; a new job abandons the old one.
+
+use std::error::Error;
+use std::io;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use crate::Block;
+use crate::network::MiningHandle;
+use crate::network::retry::Timeouts;
+use crate::network::transport::Transport;
+use super::protocol::*;
+
+/// How often a worker asks the pool whether the job has changed
+pub const JOB_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often mining progress is logged
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A worker mining for a pool
+pub struct PoolClient {
+    worker: String,
+    payout_address: String,
+    pool_address: String,
+    mining_threads: usize,
+    timeouts: Timeouts,
+    /// Connection shared by all requests, reopened after an error
+    connection: tokio::sync::Mutex<Option<Transport<TcpStream>>>,
+}
+
+impl PoolClient {
+    /// A worker named `worker`, paid to `payout_address`, mining for the pool at `pool_address`
+    pub fn new(worker: String, payout_address: String, pool_address: String) -> Self {
+        PoolClient {
+            worker,
+            payout_address,
+            pool_address,
+            mining_threads: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            timeouts: Timeouts::default(),
+            connection: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// Set how many threads hash each job (at least 1)
+    pub fn set_mining_threads(&mut self, threads: usize) {
+        self.mining_threads = threads.max(1);
+    }
+
+    /// Set connect, read and write timeouts for requests to the pool
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    async fn request(&self, message: &WorkerMessage) -> io::Result<PoolMessage> {
+        let mut connection = self.connection.lock().await;
+        let transport = match connection.as_mut() {
+            Some(transport) => transport,
+            None => {
+                let mut transport = Transport::connect(&self.pool_address, self.timeouts).await?;
+                // the pool only takes this worker's messages on connections that subscribed it
+                if matches!(message, WorkerMessage::GetJob { .. } | WorkerMessage::SubmitShare { .. }) {
+                    transport.request::<_, PoolMessage>(&self.subscribe_message()).await?;
+                }
+                connection.insert(transport)
+            }
+        };
+        let response = transport.request(message).await;
+        if response.is_err() {
+            // the stream may be mid-frame; start clean next time
+            *connection = None;
+        }
+        response
+    }
+
+    fn subscribe_message(&self) -> WorkerMessage {
+        WorkerMessage::Subscribe {
+            worker: self.worker.clone(),
+            payout_address: self.payout_address.clone(),
+        }
+    }
+
+    /// Join the pool, returning this worker's extra-nonce prefix
+    pub async fn subscribe(&self) -> Result<u32, Box<dyn Error>> {
+        match self.request(&self.subscribe_message()).await? {
+            PoolMessage::Subscribed { extra_nonce_prefix } => Ok(extra_nonce_prefix),
+            PoolMessage::Error { message } => Err(message.into()),
+            other => Err(format!("Unexpected response: {:?}", other).into()),
+        }
+    }
+
+    /// The current job, or `Err(retry_after)` if the pool has none
+    pub async fn get_job(&self) -> Result<Result<JobData, Duration>, Box<dyn Error>> {
+        match self.request(&WorkerMessage::GetJob { worker: self.worker.clone() }).await? {
+            PoolMessage::Job(job) => Ok(Ok(job)),
+            PoolMessage::NoJob { retry_after_ms } => Ok(Err(Duration::from_millis(retry_after_ms))),
+            PoolMessage::Error { message } => Err(message.into()),
+            other => Err(format!("Unexpected response: {:?}", other).into()),
+        }
+    }
+
+    /// Submit a share, returning whether it also won the round
+    pub async fn submit_share(&self, job_id: u64, extra_nonce: u64, nonce: u64) -> Result<bool, Box<dyn Error>> {
+        let message = WorkerMessage::SubmitShare { worker: self.worker.clone(), job_id, extra_nonce, nonce };
+        match self.request(&message).await? {
+            PoolMessage::ShareAccepted { block_found } => Ok(block_found),
+            PoolMessage::ShareRejected { reason } => Err(format!("Share rejected: {}", reason).into()),
+            PoolMessage::Error { message } => Err(message.into()),
+            other => Err(format!("Unexpected response: {:?}", other).into()),
+        }
+    }
+
+    /// The pool's share counts and balances
+    pub async fn get_stats(&self) -> Result<PoolStatsData, Box<dyn Error>> {
+        match self.request(&WorkerMessage::GetPoolStats).await? {
+            PoolMessage::PoolStats(stats) => Ok(stats),
+            PoolMessage::Error { message } => Err(message.into()),
+            other => Err(format!("Unexpected response: {:?}", other).into()),
+        }
+    }
+
+    /// Mine shares for the pool until an error the worker can't retry
+    pub async fn start_mining(&self) -> Result<(), Box<dyn Error>> {
+        let prefix = self.subscribe().await?;
+        info!(worker = %self.worker, prefix, "subscribed to pool");
+
+        loop {
+            let job = match self.get_job().await.map_err(|e| e.to_string()) {
+                Ok(Ok(job)) => job,
+                Ok(Err(retry_after)) => {
+                    debug!(?retry_after, "pool has no job");
+                    tokio::time::sleep(retry_after).await;
+                    continue;
+                }
+                Err(e) => {
+                    error!(error = %e, "failed to get a job");
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+            let span = info_span!("job", job_id = job.job_id);
+            if let Err(e) = self.mine_job(job).instrument(span).await {
+                warn!(error = %e, "job abandoned");
+            }
+        }
+    }
+
+    /// Submit shares for `job` until the pool moves on to another
+    async fn mine_job(&self, job: JobData) -> Result<(), Box<dyn Error>> {
+        let share_target = job.share_target;
+        let mut block = job.block.0;
+        info!(index = block.header.index, share_target = %job.share_target, "mining job");
+
+        loop {
+            let mut mining = MiningHandle::spawn(block.clone(), share_target, self.mining_threads, PROGRESS_INTERVAL, |progress| {
+                debug!(hashes = progress.hashes, hashrate = progress.hashrate() as u64, "mining progress");
+            });
+            let share: Block = loop {
+                tokio::select! {
+                    mined = mining.wait() => break mined?.ok_or("Exhausted the nonce space")?,
+                    _ = tokio::time::sleep(JOB_POLL_INTERVAL) => {
+                        if !self.still_current(job.job_id).await {
+                            return Ok(());
+                        }
+                    }
+                }
+            };
+
+            let extra_nonce = share.extra_nonce().ok_or("Job has no coinbase extra-nonce")?;
+            match self.submit_share(job.job_id, extra_nonce, share.header.nonce.0).await.map_err(|e| e.to_string()) {
+                Ok(true) => info!(hash = %hex::encode(&share.hash.as_bytes()[..8]), "share found a block"),
+                Ok(false) => debug!(nonce = share.header.nonce.0, "share accepted"),
+                Err(e) => {
+                    warn!(error = %e, "share not counted");
+                    if !self.still_current(job.job_id).await {
+                        return Ok(());
+                    }
+                }
+            }
+
+            // carry on from the next extra-nonce so no share is found twice
+            block = share;
+            if !block.roll_extra_nonce() {
+                return Err("Exhausted the extra-nonce space".into());
+            }
+        }
+    }
+
+    /// Whether the pool still has `job_id` as its job
+    async fn still_current(&self, job_id: u64) -> bool {
+        matches!(self.get_job().await, Ok(Ok(job)) if job.job_id == job_id)
+    }
+}
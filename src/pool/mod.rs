@@ -0,0 +1,16 @@
+//! Mining pool for Hourcoin
+//!
+//! A pool takes part in the Proof of Time rounds as one miner. It builds the
+//! block for each round, hands its workers an easier share target, and
+//! submits the block when a worker's share also meets the real difficulty.
+//! Block rewards are split among workers in proportion to their shares.
+
+pub mod protocol;
+pub mod shares;
+pub mod server;
+pub mod client;
+
+pub use protocol::*;
+pub use shares::{PayoutLedger, ShareCounts};
+pub use server::PoolServer;
+pub use client::PoolClient;
@@ -0,0 +1,109 @@
+//! Messages exchanged between a pool and its workers
+//!
+//! Like the miner protocol, each message is a length-prefixed JSON frame,
+//! with the job's block in its canonical encoding, and every `WorkerMessage`
+//! gets exactly one `PoolMessage` back.
+
+use serde::{Deserialize, Serialize};
+use crate::canonical::Canonical;
+use crate::{Block, Difficulty};
+
+/// Messages sent from a worker to the pool
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WorkerMessage {
+    /// Join the pool; the worker's shares are credited to `payout_address`
+    ///
+    /// Must be sent on every connection before `GetJob` or `SubmitShare`. A
+    /// worker that is already subscribed keeps its payout address; asking for
+    /// a different one is an error.
+    Subscribe { worker: String, payout_address: String },
+
+    /// Ask for the current job
+    GetJob { worker: String },
+
+    /// A nonce whose block hash meets the job's share target
+    SubmitShare {
+        worker: String,
+        job_id: u64,
+        extra_nonce: u64,
+        nonce: u64,
+    },
+
+    /// Request the pool's share counts and balances
+    GetPoolStats,
+}
+
+/// Messages sent from the pool to a worker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PoolMessage {
+    /// The worker joined; its extra-nonces must start with this prefix (the high 32 bits)
+    Subscribed { extra_nonce_prefix: u32 },
+
+    /// Work to do
+    Job(JobData),
+
+    /// No work right now (the pool is locked out or between rounds); ask again later
+    NoJob { retry_after_ms: u64 },
+
+    /// The share counted; `block_found` if it also won the round
+    ShareAccepted { block_found: bool },
+
+    /// The share didn't count
+    ShareRejected { reason: String },
+
+    /// Share counts and balances
+    PoolStats(PoolStatsData),
+
+    /// Error message
+    Error { message: String },
+}
+
+/// One block template for one worker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobData {
+    pub job_id: u64,
+    /// The block to mine; its coinbase already carries the worker's first extra-nonce
+    pub block: Canonical<Block>,
+    /// Hashes meeting this count as shares
+    pub share_target: Difficulty,
+}
+
+/// Pool-wide statistics
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PoolStatsData {
+    pub workers: usize,
+    /// Shares per payout address since the pool last found a block
+    pub round_shares: Vec<(String, u64)>,
+    pub shares_accepted: u64,
+    pub shares_rejected: u64,
+    pub blocks_found: u64,
+    /// What the pool owes each payout address
+    pub balances: Vec<(String, f64)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_roundtrip() {
+        let share = WorkerMessage::SubmitShare { worker: "w1".to_string(), job_id: 3, extra_nonce: 1 << 32, nonce: 9 };
+        match serde_json::from_slice(&serde_json::to_vec(&share).unwrap()).unwrap() {
+            WorkerMessage::SubmitShare { worker, job_id: 3, extra_nonce, nonce: 9 } => {
+                assert_eq!(worker, "w1");
+                assert_eq!(extra_nonce, 1 << 32);
+            }
+            other => panic!("Wrong message type: {:?}", other),
+        }
+
+        let job = PoolMessage::Job(JobData {
+            job_id: 3,
+            block: Canonical(Block::new(0, 1000, crate::BlockHash::ZERO, vec![])),
+            share_target: Difficulty::from_target(0x0FFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF),
+        });
+        match serde_json::from_slice(&serde_json::to_vec(&job).unwrap()).unwrap() {
+            PoolMessage::Job(data) => assert_eq!(data.share_target, Difficulty::from_target(0x0FFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF_FFFF)),
+            other => panic!("Wrong message type: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,488 @@
+//! Pool server: one miner to the validator, many workers behind it
+//!
+//! Every `JOB_REFRESH_INTERVAL` the pool asks the validator for the round and
+//! the chain tip. When either moves on it builds a new block template: a
+//! timestamp that passes the round's tonce and a coinbase paying the pool.
+//! Each worker gets the template with its own extra-nonce prefix in the
+//! coinbase, so no two workers search the same hashes, and a share target
+//! `share_factor` times easier than the real difficulty.
+//!
+//! A share is checked by rebuilding the worker's block from the template.
+//! The first share that also meets the real difficulty is submitted; the pool
+//! has one attempt per round like any miner. If the validator accepts it, the
+//! reward is split over the shares counted since the last block found and
+//! credited to the `PayoutLedger`.
+//!
+//! A worker name belongs to the payout address it was first subscribed with,
+//! and only connections that subscribed it may ask for its jobs or submit its
+//! shares. Anyone can reconnect as a worker, but only to keep paying it.
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use crate::{find_valid_timestamp_parallel, Block, BlockHash, Difficulty, Nonce, RoundCommitment};
+use crate::block::check_blockhash;
+use crate::canonical::Canonical;
+use crate::hashable::Hashable;
+use crate::network::{BlockResultType, MinerClient, ValidatorMessage};
+use crate::network::stream::{BoxedStream, Listener};
+use crate::network::transport::{Transport, DEFAULT_MAX_FRAME_LEN};
+use crate::tx_builder::TransactionBuilder;
+use super::protocol::*;
+use super::shares::{PayoutLedger, ShareCounts};
+
+/// How much easier a share is than a block, by default
+pub const DEFAULT_SHARE_FACTOR: u128 = 1024;
+
+/// How often the pool checks the validator for a new round or tip
+pub const JOB_REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What the coinbase pays, as for a solo miner
+pub const BLOCK_REWARD: f64 = 2.0;
+
+/// How long a worker waits before asking again when there is no job
+const NO_JOB_RETRY_MS: u64 = 1_000;
+
+/// The block template for the current round
+struct Job {
+    id: u64,
+    template: Block,
+    round_start: u128,
+    difficulty: Difficulty,
+    share_target: Difficulty,
+    /// Opens our timestamp commitment, if the round asked for one
+    salt: Option<String>,
+    /// When the validator starts taking the reveal of that commitment
+    reveal_at: Option<Instant>,
+    /// The round's ID, echoed back with the block
+    round_id: Option<String>,
+    /// A block from this job went to the validator; no more can
+    submitted: bool,
+    /// (extra-nonce, nonce) pairs already counted
+    seen: HashSet<(u64, u64)>,
+}
+
+impl Job {
+    /// The template with a worker's `extra_nonce` and `nonce` filled in
+    fn block_for(&self, extra_nonce: u64, nonce: u64) -> Block {
+        let mut block = self.template.clone();
+        if let Some(coinbase) = block.body.transactions.first_mut() {
+            coinbase.set_extra_nonce(extra_nonce);
+        }
+        block.update_merkle_root();
+        block.header.nonce = Nonce(nonce);
+        block.hash = block.hash();
+        block
+    }
+}
+
+/// A share that also wins the round, with what the validator needs alongside it
+struct Winner {
+    block: Block,
+    salt: Option<String>,
+    reveal_at: Option<Instant>,
+    round_id: Option<String>,
+}
+
+struct Worker {
+    payout_address: String,
+    prefix: u32,
+}
+
+#[derive(Default)]
+struct PoolState {
+    job: Option<Job>,
+    next_job_id: u64,
+    workers: HashMap<String, Worker>,
+    next_prefix: u32,
+    shares: ShareCounts,
+    ledger: PayoutLedger,
+    shares_accepted: u64,
+    shares_rejected: u64,
+    blocks_found: u64,
+}
+
+/// A mining pool: a miner to the validator and a job server to its workers
+pub struct PoolServer {
+    address: String,
+    /// The pool's own connection to the validator, mining as the pool's miner ID
+    client: MinerClient,
+    /// Where block rewards are paid before they're shared out
+    reward_address: String,
+    share_factor: u128,
+    /// Where the payout ledger is saved after every block found
+    ledger_path: Option<PathBuf>,
+    /// Where workers connect; plain TCP on `address` if unset
+    listener: Mutex<Option<Box<dyn Listener>>>,
+    state: Mutex<PoolState>,
+}
+
+impl PoolServer {
+    /// A pool for workers connecting on `address`, mining through `client`
+    pub fn new(address: String, client: MinerClient, reward_address: String) -> Self {
+        PoolServer {
+            address,
+            client,
+            reward_address,
+            share_factor: DEFAULT_SHARE_FACTOR,
+            ledger_path: None,
+            listener: Mutex::new(None),
+            state: Mutex::new(PoolState::default()),
+        }
+    }
+
+    /// Make shares `factor` times easier than blocks
+    pub fn set_share_factor(&mut self, factor: u128) {
+        self.share_factor = factor.max(1);
+    }
+
+    /// Load the payout ledger from `path` at startup and save it there after every block found
+    pub fn set_ledger_path(&mut self, path: impl Into<PathBuf>) {
+        self.ledger_path = Some(path.into());
+    }
+
+    /// Accept workers from `listener` instead of a TCP socket on the server address
+    pub fn set_listener(&mut self, listener: Box<dyn Listener>) {
+        self.listener = Mutex::new(Some(listener));
+    }
+
+    /// Start the pool server
+    pub async fn start(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(path) = self.ledger_path.as_ref().filter(|path| path.exists()) {
+            let ledger = PayoutLedger::load(path)?;
+            info!(path = %path.display(), "payout ledger loaded");
+            self.state.get_mut().unwrap().ledger = ledger;
+        }
+
+        info!(address = %self.address, share_factor = %self.share_factor, "pool server starting");
+        let mut listener: Box<dyn Listener> = match self.listener.get_mut().unwrap().take() {
+            Some(listener) => listener,
+            None => Box::new(TcpListener::bind(&self.address).await?),
+        };
+
+        let pool = Arc::new(self);
+        tokio::spawn(Arc::clone(&pool).refresh_jobs());
+
+        loop {
+            let (socket, addr) = listener.accept().await?;
+            let span = info_span!("worker_connection", peer = %addr);
+            let pool = Arc::clone(&pool);
+            tokio::spawn(async move {
+                if let Err(e) = pool.handle_connection(Transport::new(socket, DEFAULT_MAX_FRAME_LEN)).await {
+                    debug!(error = %e, "worker connection ended");
+                }
+            }.instrument(span));
+        }
+    }
+
+    /// Keep the job in step with the validator's round and tip, forever
+    async fn refresh_jobs(self: Arc<Self>) {
+        let mut ticker = tokio::time::interval(JOB_REFRESH_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.refresh_job().await {
+                warn!(error = %e, "failed to refresh the pool job");
+            }
+        }
+    }
+
+    async fn refresh_job(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let (is_locked, seconds_remaining) = self.client.check_lockout().await?;
+        if is_locked {
+            if self.state.lock().unwrap().job.take().is_some() {
+                info!(seconds_remaining, "pool is in lockout; pausing workers");
+            }
+            return Ok(());
+        }
+
+        let round = self.client.get_round_info().await?;
+        let tonce = match round.tonce {
+            Some(tonce) if round.remaining_attempts != Some(0) => tonce,
+            _ => {
+                self.state.lock().unwrap().job = None;
+                return Ok(());
+            }
+        };
+        let (prev_hash, index, difficulty) = self.client.get_chain_tip().await?;
+
+        if let Some(job) = &self.state.lock().unwrap().job {
+            if job.round_start == round.round_start && job.template.header.index == index
+                && job.template.header.prev_block_hash == prev_hash {
+                return Ok(());
+            }
+        }
+
+        let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let timestamp = find_valid_timestamp_parallel(tonce, self.client.validator_now(), 100000, threads)
+            .ok_or("Failed to find valid timestamp")?;
+        let (salt, reveal_at) = if round.commit_reveal {
+            let (salt, reveal_after) = self.client.commit_timestamp(timestamp).await?;
+            (Some(hex::encode(salt)), Some(Instant::now() + reveal_after))
+        } else {
+            (None, None)
+        };
+        let coinbase = TransactionBuilder::new(timestamp)
+            .pay_to(&self.reward_address, BLOCK_REWARD)
+            .build_coinbase()?;
+        let mut template = Block::new(index, timestamp, prev_hash, vec![coinbase]);
+        template.header.utxo_commitment = match &round.utxo_commitment {
+            Some(commitment) => Some(commitment.parse::<BlockHash>()
+                .map_err(|e| format!("Invalid utxo_commitment: {}", e))?),
+            None => None,
+        };
+        let signature = match &round.round_signature {
+            Some(signature) => Some(hex::decode(signature)
+                .map_err(|e| format!("Invalid round_signature: {}", e))?),
+            None => None,
+        };
+        template.header.round = Some(RoundCommitment { round_start: round.round_start, tonce, signature });
+        template.header.bits = difficulty;
+
+        let mut state = self.state.lock().unwrap();
+        state.next_job_id += 1;
+        let job = Job {
+            id: state.next_job_id,
+            template,
+            round_start: round.round_start,
+            difficulty,
+            share_target: Difficulty::from_target(difficulty.target().saturating_mul(self.share_factor)),
+            salt,
+            reveal_at,
+            round_id: round.round_id.clone(),
+            submitted: false,
+            seen: HashSet::new(),
+        };
+        info!(job_id = job.id, index, tonce, share_target = %job.share_target, "new pool job");
+        state.job = Some(job);
+        Ok(())
+    }
+
+    /// Answer one worker's messages until it disconnects
+    async fn handle_connection(&self, mut transport: Transport<BoxedStream>) -> Result<(), Box<dyn std::error::Error>> {
+        // the workers this connection has subscribed
+        let mut subscribed = HashSet::new();
+        while let Some(frame) = transport.recv_frame().await? {
+            let response = match serde_json::from_slice::<WorkerMessage>(&frame) {
+                Ok(message) => self.process_message(message, &mut subscribed).await,
+                Err(e) => PoolMessage::Error { message: format!("Invalid message: {}", e) },
+            };
+            transport.send(&response).await?;
+        }
+        Ok(())
+    }
+
+    async fn process_message(&self, message: WorkerMessage, subscribed: &mut HashSet<String>) -> PoolMessage {
+        match &message {
+            WorkerMessage::GetJob { worker } | WorkerMessage::SubmitShare { worker, .. } if !subscribed.contains(worker) => {
+                return PoolMessage::Error { message: "Subscribe first".to_string() };
+            }
+            _ => {}
+        }
+
+        match message {
+            WorkerMessage::Subscribe { worker, payout_address } => {
+                let mut state = self.state.lock().unwrap();
+                if let Some(existing) = state.workers.get(&worker) {
+                    if existing.payout_address != payout_address {
+                        warn!(%worker, %payout_address, "refused resubscription with another payout address");
+                        return PoolMessage::Error { message: format!("Worker {} is paid to another address", worker) };
+                    }
+                    let prefix = existing.prefix;
+                    subscribed.insert(worker);
+                    return PoolMessage::Subscribed { extra_nonce_prefix: prefix };
+                }
+                let prefix = state.next_prefix;
+                state.next_prefix = match prefix.checked_add(1) {
+                    Some(next) => next,
+                    None => return PoolMessage::Error { message: "Pool is full".to_string() },
+                };
+                info!(%worker, %payout_address, prefix, "worker subscribed");
+                state.workers.insert(worker.clone(), Worker { payout_address, prefix });
+                subscribed.insert(worker);
+                PoolMessage::Subscribed { extra_nonce_prefix: prefix }
+            }
+
+            WorkerMessage::GetJob { worker } => {
+                let state = self.state.lock().unwrap();
+                let prefix = match state.workers.get(&worker) {
+                    Some(worker) => worker.prefix,
+                    None => return PoolMessage::Error { message: "Subscribe first".to_string() },
+                };
+                match state.job.as_ref().filter(|job| !job.submitted) {
+                    Some(job) => PoolMessage::Job(JobData {
+                        job_id: job.id,
+                        block: Canonical(job.block_for((prefix as u64) << 32, 0)),
+                        share_target: job.share_target,
+                    }),
+                    None => PoolMessage::NoJob { retry_after_ms: NO_JOB_RETRY_MS },
+                }
+            }
+
+            WorkerMessage::SubmitShare { worker, job_id, extra_nonce, nonce } => {
+                let span = info_span!("share", %worker, job_id);
+                self.process_share(&worker, job_id, extra_nonce, nonce).instrument(span).await
+            }
+
+            WorkerMessage::GetPoolStats => {
+                let state = self.state.lock().unwrap();
+                PoolMessage::PoolStats(PoolStatsData {
+                    workers: state.workers.len(),
+                    round_shares: state.shares.iter().map(|(address, &shares)| (address.clone(), shares)).collect(),
+                    shares_accepted: state.shares_accepted,
+                    shares_rejected: state.shares_rejected,
+                    blocks_found: state.blocks_found,
+                    balances: state.ledger.balances(),
+                })
+            }
+        }
+    }
+
+    async fn process_share(&self, worker: &str, job_id: u64, extra_nonce: u64, nonce: u64) -> PoolMessage {
+        let Winner { block, salt, reveal_at, round_id } = match self.count_share(worker, job_id, extra_nonce, nonce) {
+            Ok(Some(winner)) => winner,
+            Ok(None) => return PoolMessage::ShareAccepted { block_found: false },
+            Err(reason) => {
+                debug!(%reason, "share rejected");
+                self.state.lock().unwrap().shares_rejected += 1;
+                return PoolMessage::ShareRejected { reason };
+            }
+        };
+
+        // the validator turns away reveals that come too soon after the commitment
+        if let Some(wait) = reveal_at.map(|at| at.saturating_duration_since(Instant::now())).filter(|wait| !wait.is_zero()) {
+            info!(wait_ms = wait.as_millis() as u64, "waiting to reveal the commitment");
+            tokio::time::sleep(wait).await;
+        }
+        info!(hash = %hex::encode(&block.hash.as_bytes()[..8]), "share meets the block difficulty; submitting");
+        let accepted = match self.client.submit_block(&block, salt, round_id).await {
+            Ok(ValidatorMessage::BlockResult { result: BlockResultType::Accepted, .. }) => true,
+            Ok(ValidatorMessage::BlockResult { result, message, .. }) => {
+                warn!(?result, %message, "pool block rejected");
+                false
+            }
+            Ok(other) => {
+                warn!(response = ?other, "unexpected response to pool block");
+                false
+            }
+            Err(e) => {
+                error!(error = %e, "failed to submit pool block");
+                false
+            }
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state.job = None;
+        if accepted {
+            let reward: f64 = block.body.transactions.first()
+                .map_or(0.0, |coinbase| coinbase.outputs.iter().map(|output| output.value).sum());
+            let splits = state.shares.split(reward);
+            info!(height = block.header.index, reward, workers = splits.len(), "pool block accepted; crediting shares");
+            state.ledger.credit(&splits);
+            state.shares.clear();
+            state.blocks_found += 1;
+            if let Some(path) = &self.ledger_path {
+                if let Err(e) = state.ledger.save(path) {
+                    warn!(error = %e, "failed to save payout ledger");
+                }
+            }
+        }
+        PoolMessage::ShareAccepted { block_found: accepted }
+    }
+
+    /// Check and count a share; returns the block to submit if it also wins the round
+    fn count_share(&self, worker: &str, job_id: u64, extra_nonce: u64, nonce: u64) -> Result<Option<Winner>, String> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let worker = state.workers.get(worker).ok_or("Subscribe first")?;
+        let job = state.job.as_mut().filter(|job| job.id == job_id).ok_or("Stale job")?;
+        if (extra_nonce >> 32) as u32 != worker.prefix {
+            return Err("Extra-nonce outside the worker's prefix".to_string());
+        }
+        if job.seen.contains(&(extra_nonce, nonce)) {
+            return Err("Duplicate share".to_string());
+        }
+
+        let block = job.block_for(extra_nonce, nonce);
+        if !check_blockhash(&block.hash, job.share_target) {
+            return Err("Hash above the share target".to_string());
+        }
+        job.seen.insert((extra_nonce, nonce));
+        state.shares.add(&worker.payout_address);
+        state.shares_accepted += 1;
+
+        if check_blockhash(&block.hash, job.difficulty) && !job.submitted {
+            job.submitted = true;
+            return Ok(Some(Winner { block, salt: job.salt.clone(), reveal_at: job.reveal_at, round_id: job.round_id.clone() }));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::ValidatorServer;
+    use crate::pool::PoolClient;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pool_shares_block_reward() {
+        let validator_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let validator_address = validator_listener.local_addr().unwrap().to_string();
+        let mut validator = ValidatorServer::new(Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF), validator_address.clone());
+        validator.set_listener(Box::new(validator_listener));
+        tokio::spawn(async move { validator.start().await.map_err(|e| e.to_string()) });
+
+        let pool_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let pool_address = pool_listener.local_addr().unwrap().to_string();
+        let client = MinerClient::new("pool".to_string(), validator_address);
+        let mut pool = PoolServer::new(pool_address.clone(), client, "pool_rewards".to_string());
+        pool.set_share_factor(16);
+        pool.set_listener(Box::new(pool_listener));
+        tokio::spawn(async move { pool.start().await.map_err(|e| e.to_string()) });
+
+        let mut worker = PoolClient::new("w1".to_string(), "alice".to_string(), pool_address.clone());
+        worker.set_mining_threads(1);
+        tokio::spawn(async move { worker.start_mining().await.map_err(|e| e.to_string()) });
+
+        let observer = PoolClient::new("observer".to_string(), "bob".to_string(), pool_address);
+        let stats = tokio::time::timeout(Duration::from_secs(30), async {
+            loop {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                if let Ok(stats) = observer.get_stats().await {
+                    if stats.blocks_found > 0 {
+                        return stats;
+                    }
+                }
+            }
+        }).await.expect("pool never found a block");
+
+        assert!(stats.shares_accepted >= 1);
+        assert_eq!(stats.balances, vec![("alice".to_string(), BLOCK_REWARD)]);
+    }
+
+    #[tokio::test]
+    async fn test_worker_cannot_be_taken_over() {
+        let pool = PoolServer::new("127.0.0.1:0".to_string(), MinerClient::new("pool".to_string(), "127.0.0.1:1".to_string()), "pool".to_string());
+        let subscribe = |payout_address: &str| WorkerMessage::Subscribe { worker: "w1".to_string(), payout_address: payout_address.to_string() };
+        let get_job = || WorkerMessage::GetJob { worker: "w1".to_string() };
+
+        let mut owner = HashSet::new();
+        assert!(matches!(pool.process_message(subscribe("alice"), &mut owner).await, PoolMessage::Subscribed { extra_nonce_prefix: 0 }));
+        assert!(matches!(pool.process_message(get_job(), &mut owner).await, PoolMessage::NoJob { .. }));
+
+        // another connection can neither redirect the worker's payouts nor act for it
+        let mut other = HashSet::new();
+        assert!(matches!(pool.process_message(subscribe("mallory"), &mut other).await, PoolMessage::Error { .. }));
+        assert!(matches!(pool.process_message(get_job(), &mut other).await, PoolMessage::Error { .. }));
+        let share = WorkerMessage::SubmitShare { worker: "w1".to_string(), job_id: 1, extra_nonce: 0, nonce: 0 };
+        assert!(matches!(pool.process_message(share, &mut other).await, PoolMessage::Error { .. }));
+        assert_eq!(pool.state.lock().unwrap().workers["w1"].payout_address, "alice");
+
+        // reconnecting with the same payout address keeps the prefix
+        assert!(matches!(pool.process_message(subscribe("alice"), &mut other).await, PoolMessage::Subscribed { extra_nonce_prefix: 0 }));
+        assert!(matches!(pool.process_message(get_job(), &mut other).await, PoolMessage::NoJob { .. }));
+    }
+}
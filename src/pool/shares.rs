@@ -0,0 +1,134 @@
+//! Share accounting and proportional payouts
+//!
+//! Shares are counted per payout address from one block found to the next.
+//! When the pool finds a block its reward is split in proportion to those
+//! counts and credited to the `PayoutLedger`, and counting starts over.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::atomic_file::atomic_write;
+
+/// Shares per payout address since the pool last found a block
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShareCounts {
+    shares: BTreeMap<String, u64>,
+}
+
+impl ShareCounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count one share for `payout_address`
+    pub fn add(&mut self, payout_address: &str) {
+        *self.shares.entry(payout_address.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn get(&self, payout_address: &str) -> u64 {
+        self.shares.get(payout_address).copied().unwrap_or(0)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.shares.values().sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &u64)> {
+        self.shares.iter()
+    }
+
+    /// Split `reward` in proportion to the shares counted
+    pub fn split(&self, reward: f64) -> Vec<(String, f64)> {
+        let total = self.total();
+        if total == 0 {
+            return Vec::new();
+        }
+        self.shares.iter()
+            .map(|(address, &shares)| (address.clone(), reward * shares as f64 / total as f64))
+            .collect()
+    }
+
+    /// Start counting from zero (after a block is found)
+    pub fn clear(&mut self) {
+        self.shares.clear();
+    }
+}
+
+/// What the pool owes each payout address
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PayoutLedger {
+    balances: BTreeMap<String, f64>,
+}
+
+impl PayoutLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add each address's cut of a block reward
+    pub fn credit(&mut self, splits: &[(String, f64)]) {
+        for (address, amount) in splits {
+            *self.balances.entry(address.clone()).or_insert(0.0) += amount;
+        }
+    }
+
+    pub fn balance(&self, address: &str) -> f64 {
+        self.balances.get(address).copied().unwrap_or(0.0)
+    }
+
+    pub fn balances(&self) -> Vec<(String, f64)> {
+        self.balances.iter().map(|(address, &balance)| (address.clone(), balance)).collect()
+    }
+
+    /// Record that `amount` was paid to `address`
+    pub fn debit(&mut self, address: &str, amount: f64) -> Result<(), String> {
+        let balance = self.balance(address);
+        if amount > balance {
+            return Err(format!("{} is owed {}, not {}", address, balance, amount));
+        }
+        self.balances.insert(address.to_string(), balance - amount);
+        Ok(())
+    }
+
+    /// Write the ledger to `path` as JSON
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(self)
+            .map_err(|e| format!("Failed to encode payout ledger: {}", e))?;
+        atomic_write(path, &json)
+    }
+
+    /// Read a ledger written by `save`
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_slice(&json).map_err(|e| format!("Invalid payout ledger in {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proportional_split() {
+        let mut shares = ShareCounts::new();
+        assert!(shares.split(2.0).is_empty());
+        for _ in 0..3 {
+            shares.add("alice");
+        }
+        shares.add("bob");
+        assert_eq!(shares.total(), 4);
+
+        let splits = shares.split(2.0);
+        assert_eq!(splits, vec![("alice".to_string(), 1.5), ("bob".to_string(), 0.5)]);
+
+        let mut ledger = PayoutLedger::new();
+        ledger.credit(&splits);
+        ledger.credit(&splits);
+        assert_eq!(ledger.balance("alice"), 3.0);
+        assert_eq!(ledger.balance("carol"), 0.0);
+
+        ledger.debit("bob", 1.0).unwrap();
+        assert_eq!(ledger.balance("bob"), 0.0);
+        assert!(ledger.debit("bob", 0.1).is_err());
+    }
+}
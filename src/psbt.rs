@@ -0,0 +1,371 @@
+//! Partially signed transactions for offline signing
+//!
+//! An online machine that knows the UTXO set builds a transaction and wraps it
+//! in a `PartiallySignedTransaction`. It adds hints to the wrapper, such as a
+//! derivation path or the multisig policy behind each input, and exports it as
+//! JSON or base64. An air-gapped `Wallet` imports it, adds signatures for the
+//! inputs it holds keys for, and exports it again. Multisig cosigners each
+//! sign their own copy, and the copies are merged with `combine`. Once every
+//! input that needs authorizing is signed, `finalize` turns the signatures into
+//! witnesses and returns a transaction ready to broadcast.
+//!
+//! Signatures cover the transaction's signing hash (inputs and outputs only),
+//! so adding witnesses never invalidates them.
+
+use std::collections::BTreeMap;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use crate::multisig::{is_multisig_address, MultisigPolicy};
+use crate::network::protocol::TransactionData;
+use crate::node_key::{verify_signature, NodeKey};
+use crate::BlockHash;
+use crate::transaction::{Transaction, Witness, TX_SIGNING_CONTEXT};
+use crate::wallet::{address_from_public_key, is_key_address, DerivationPath};
+
+/// Format version written into every export
+pub const PSBT_VERSION: u32 = 1;
+
+/// Signing hints and collected signatures for one input
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PsbtInput {
+    /// Path of the key that owns the input, to save the signer a search
+    #[serde(default)]
+    pub derivation_path: Option<String>,
+    /// Policy behind a multisig input
+    #[serde(default)]
+    pub multisig: Option<MultisigPolicyData>,
+    /// Signatures collected so far, keyed by public key (hex encoded)
+    #[serde(default)]
+    pub signatures: BTreeMap<String, String>,
+}
+
+/// Serializable multisig policy
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MultisigPolicyData {
+    pub threshold: usize,
+    pub public_keys: Vec<String>, // Hex encoded
+}
+
+impl MultisigPolicyData {
+    pub fn from_policy(policy: &MultisigPolicy) -> Self {
+        MultisigPolicyData {
+            threshold: policy.threshold,
+            public_keys: policy.public_keys.iter().map(hex::encode).collect(),
+        }
+    }
+
+    pub fn to_policy(&self) -> Result<MultisigPolicy, String> {
+        let public_keys = self.public_keys.iter()
+            .map(|key| hex::decode(key).map_err(|e| format!("Invalid public_key hex: {}", e)))
+            .collect::<Result<Vec<_>, String>>()?;
+        MultisigPolicy::new(self.threshold, public_keys)
+    }
+}
+
+/// An unsigned or partially signed transaction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartiallySignedTransaction {
+    pub version: u32,
+    /// The transaction being signed, with any non-signature witnesses (HTLC, channel) already attached
+    pub transaction: TransactionData,
+    /// One entry per transaction input
+    pub inputs: Vec<PsbtInput>,
+}
+
+impl PartiallySignedTransaction {
+    /// Wrap a transaction for signing
+    pub fn new(transaction: &Transaction) -> Self {
+        PartiallySignedTransaction {
+            version: PSBT_VERSION,
+            transaction: TransactionData::from_transaction(transaction),
+            inputs: vec![PsbtInput::default(); transaction.inputs.len()],
+        }
+    }
+
+    /// The wrapped transaction, without the signatures collected so far
+    pub fn unsigned_transaction(&self) -> Result<Transaction, String> {
+        let transaction = self.transaction.to_transaction()?;
+        if transaction.inputs.len() != self.inputs.len() {
+            return Err(format!("{} inputs but {} input entries", transaction.inputs.len(), self.inputs.len()));
+        }
+        Ok(transaction)
+    }
+
+    /// The hash every input signature commits to
+    pub fn signing_hash(&self) -> Result<BlockHash, String> {
+        Ok(self.unsigned_transaction()?.signing_hash())
+    }
+
+    fn input_mut(&mut self, index: usize) -> Result<&mut PsbtInput, String> {
+        self.inputs.get_mut(index).ok_or_else(|| format!("No input {}", index))
+    }
+
+    /// Record which wallet key owns an input
+    pub fn set_derivation_path(&mut self, index: usize, path: &DerivationPath) -> Result<(), String> {
+        self.input_mut(index)?.derivation_path = Some(path.to_string());
+        Ok(())
+    }
+
+    /// Record the policy behind a multisig input
+    pub fn set_multisig_policy(&mut self, index: usize, policy: &MultisigPolicy) -> Result<(), String> {
+        let address = self.transaction.inputs.get(index)
+            .map(|input| input.to_addr.clone())
+            .ok_or_else(|| format!("No input {}", index))?;
+        if address != policy.address() {
+            return Err(format!("Input {} does not pay to this policy", index));
+        }
+        self.input_mut(index)?.multisig = Some(MultisigPolicyData::from_policy(policy));
+        Ok(())
+    }
+
+    /// Add a signature for an input, checking it against the signing hash
+    pub fn add_signature(&mut self, index: usize, public_key: &[u8], signature: &[u8]) -> Result<(), String> {
+        verify_signature(public_key, TX_SIGNING_CONTEXT, self.signing_hash()?.as_bytes(), signature)?;
+        self.input_mut(index)?.signatures.insert(hex::encode(public_key), hex::encode(signature));
+        Ok(())
+    }
+
+    /// Sign every input `key` can authorize, returning how many signatures were added
+    ///
+    /// That is each input paying to the key's address, and each multisig input
+    /// whose recorded policy includes the key. Inputs it already signed are skipped.
+    pub fn sign_with_key(&mut self, key: &NodeKey) -> Result<usize, String> {
+        let message = self.signing_hash()?;
+        let transaction = self.unsigned_transaction()?;
+        let public_key = key.public_key();
+        let address = address_from_public_key(&public_key);
+        let signature = key.sign(TX_SIGNING_CONTEXT, message.as_bytes());
+
+        let mut added = 0;
+        for (index, input) in transaction.inputs.iter().enumerate() {
+            let owns_input = match &self.inputs[index].multisig {
+                Some(policy) => policy.to_policy()?.position(&public_key).is_some(),
+                None => input.to_addr == address,
+            };
+            if owns_input && !self.inputs[index].signatures.contains_key(&hex::encode(&public_key)) {
+                self.add_signature(index, &public_key, &signature)?;
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    /// Merge signatures another signer added to a copy of the same transaction
+    ///
+    /// Every incoming signature is checked against the signing hash first; if
+    /// any fails, nothing is merged.
+    pub fn combine(&mut self, other: &PartiallySignedTransaction) -> Result<(), String> {
+        let message = self.signing_hash()?;
+        if message != other.signing_hash()? {
+            return Err("Cannot combine signatures for different transactions".to_string());
+        }
+        for (index, theirs) in other.inputs.iter().enumerate() {
+            for (public_key, signature) in &theirs.signatures {
+                let public_key = hex::decode(public_key).map_err(|e| format!("Invalid public_key hex: {}", e))?;
+                let signature = hex::decode(signature).map_err(|e| format!("Invalid signature hex: {}", e))?;
+                verify_signature(&public_key, TX_SIGNING_CONTEXT, message.as_bytes(), &signature)
+                    .map_err(|e| format!("Input {} carries a bad signature: {}", index, e))?;
+            }
+        }
+
+        for (input, theirs) in self.inputs.iter_mut().zip(&other.inputs) {
+            input.signatures.extend(theirs.signatures.clone());
+            if input.derivation_path.is_none() {
+                input.derivation_path = theirs.derivation_path.clone();
+            }
+            if input.multisig.is_none() {
+                input.multisig = theirs.multisig.clone();
+            }
+        }
+        Ok(())
+    }
+
+    /// The witness authorizing an input, if it has enough signatures
+    fn input_witness(&self, index: usize, address: &str) -> Result<Option<Witness>, String> {
+        let input = &self.inputs[index];
+
+        if is_key_address(address) {
+            for (public_key, signature) in &input.signatures {
+                let public_key = hex::decode(public_key)
+                    .map_err(|e| format!("Invalid public_key hex: {}", e))?;
+                if address_from_public_key(&public_key) == address {
+                    let signature = hex::decode(signature)
+                        .map_err(|e| format!("Invalid signature hex: {}", e))?;
+                    return Ok(Some(Witness::Signature { public_key, signature }));
+                }
+            }
+            return Err(format!("Input {} is not signed", index));
+        }
+
+        if is_multisig_address(address) {
+            let policy = input.multisig.as_ref()
+                .ok_or_else(|| format!("Input {} is missing its multisig policy", index))?
+                .to_policy()?;
+            let signatures = policy.public_keys.iter()
+                .map(|public_key| match input.signatures.get(&hex::encode(public_key)) {
+                    Some(signature) => hex::decode(signature)
+                        .map(Some)
+                        .map_err(|e| format!("Invalid signature hex: {}", e)),
+                    None => Ok(None),
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+
+            let signed = signatures.iter().filter(|signature| signature.is_some()).count();
+            if signed < policy.threshold {
+                return Err(format!("Input {} has {} of {} required signatures", index, signed, policy.threshold));
+            }
+            return Ok(Some(Witness::Multisig { policy, signatures }));
+        }
+
+        // HTLC and channel inputs are opened by witnesses that are already attached
+        Ok(None)
+    }
+
+    /// Whether every input that needs authorizing has enough signatures
+    pub fn is_complete(&self) -> bool {
+        self.finalize().is_ok()
+    }
+
+    /// Attach the collected signatures as witnesses, producing a transaction ready to broadcast
+    pub fn finalize(&self) -> Result<Transaction, String> {
+        let mut transaction = self.unsigned_transaction()?;
+
+        for (index, input) in transaction.inputs.clone().iter().enumerate() {
+            if let Some(witness) = self.input_witness(index, &input.to_addr)? {
+                // inputs paying to the same address share one witness
+                if !transaction.witnesses.contains(&witness) {
+                    transaction.witnesses.push(witness);
+                }
+            }
+        }
+        Ok(transaction)
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| format!("Failed to serialize PSBT: {}", e))
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let psbt: PartiallySignedTransaction = serde_json::from_str(json)
+            .map_err(|e| format!("Invalid PSBT: {}", e))?;
+        if psbt.version != PSBT_VERSION {
+            return Err(format!("Unsupported PSBT version {}", psbt.version));
+        }
+        psbt.unsigned_transaction()?;
+        Ok(psbt)
+    }
+
+    pub fn to_base64(&self) -> Result<String, String> {
+        Ok(BASE64.encode(self.to_json()?))
+    }
+
+    pub fn from_base64(encoded: &str) -> Result<Self, String> {
+        let json = BASE64.decode(encoded.trim())
+            .map_err(|e| format!("Invalid PSBT base64: {}", e))?;
+        Self::from_json(&String::from_utf8(json).map_err(|e| format!("Invalid PSBT: {}", e))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Output;
+    use crate::wallet::{Mnemonic, Wallet};
+
+    fn wallet(phrase_seed: u8) -> Wallet {
+        let mnemonic = Mnemonic::from_entropy(&[phrase_seed; 16]).unwrap();
+        Wallet::from_mnemonic(&mnemonic, "")
+    }
+
+    fn spend(from: &str) -> Transaction {
+        Transaction {
+            inputs: vec![Output { to_addr: from.to_owned(), value: 5.0, timestamp: 1000, lock_until: None }],
+            outputs: vec![Output { to_addr: "Carol".to_owned(), value: 4.5, timestamp: 2000, lock_until: None }],
+            witnesses: vec![],
+        }
+    }
+
+    #[test]
+    fn test_offline_signing_roundtrip() {
+        let cold = wallet(1);
+        let transaction = spend(&cold.receive_address(3).unwrap());
+
+        // Online: export the unsigned transaction
+        let psbt = PartiallySignedTransaction::new(&transaction);
+        assert!(!psbt.is_complete());
+        let exported = psbt.to_base64().unwrap();
+
+        // Offline: sign and export again
+        let mut offline = PartiallySignedTransaction::from_base64(&exported).unwrap();
+        assert_eq!(wallet(2).sign(&mut offline), Ok(0));
+        assert_eq!(cold.sign(&mut offline), Ok(1));
+        let signed = offline.to_json().unwrap();
+
+        // Online: finalize for broadcast
+        let signed = PartiallySignedTransaction::from_json(&signed).unwrap().finalize().unwrap();
+        assert_eq!(signed.signing_hash(), transaction.signing_hash());
+        assert_eq!(crate::signature_cache::verify_transaction(&signed), Ok(()));
+    }
+
+    #[test]
+    fn test_multisig_signing() {
+        let wallets = [wallet(1), wallet(2), wallet(3)];
+        let public_keys = wallets.iter()
+            .map(|wallet| wallet.receive_key(0).unwrap().public_key())
+            .collect();
+        let policy = MultisigPolicy::new(2, public_keys).unwrap();
+
+        let mut psbt = PartiallySignedTransaction::new(&spend(&policy.address()));
+        assert!(psbt.set_multisig_policy(0, &MultisigPolicy::new(1, vec![vec![1; 32]]).unwrap()).is_err());
+        psbt.set_multisig_policy(0, &policy).unwrap();
+
+        // Cosigners sign separate copies
+        let mut first = PartiallySignedTransaction::from_base64(&psbt.to_base64().unwrap()).unwrap();
+        let mut third = first.clone();
+        assert_eq!(wallets[0].sign(&mut first), Ok(1));
+        assert_eq!(wallets[2].sign(&mut third), Ok(1));
+        assert!(!first.is_complete());
+
+        // A copy carrying a forged signature is refused, and none of it is merged
+        let mut forged = third.clone();
+        forged.inputs[0].signatures.insert(hex::encode(wallets[1].receive_key(0).unwrap().public_key()), hex::encode([7u8; 64]));
+        assert!(first.combine(&forged).is_err());
+        assert_eq!(first.inputs[0].signatures.len(), 1);
+
+        first.combine(&third).unwrap();
+        let signed = first.finalize().unwrap();
+        assert_eq!(crate::signature_cache::verify_transaction(&signed), Ok(()));
+
+        // Signatures for a different transaction can't be merged
+        let other = PartiallySignedTransaction::new(&spend("Dave"));
+        assert!(first.combine(&other).is_err());
+    }
+
+    #[test]
+    fn test_sign_with_key() {
+        let key = NodeKey::from_secret_bytes(&[4; 32]).unwrap();
+        let mut psbt = PartiallySignedTransaction::new(&spend(&address_from_public_key(&key.public_key())));
+        assert_eq!(psbt.sign_with_key(&NodeKey::from_secret_bytes(&[5; 32]).unwrap()), Ok(0));
+        assert_eq!(psbt.sign_with_key(&key), Ok(1));
+        assert_eq!(psbt.sign_with_key(&key), Ok(0));
+        assert!(psbt.is_complete());
+    }
+
+    #[test]
+    fn test_rejects_bad_signatures() {
+        let cold = wallet(1);
+        let mut psbt = PartiallySignedTransaction::new(&spend(&cold.receive_address(0).unwrap()));
+        let key = cold.receive_key(0).unwrap();
+
+        let wrong = key.sign(TX_SIGNING_CONTEXT, b"something else");
+        assert!(psbt.add_signature(0, &key.public_key(), &wrong).is_err());
+
+        let right = key.sign(TX_SIGNING_CONTEXT, psbt.signing_hash().unwrap().as_bytes());
+        assert!(psbt.add_signature(1, &key.public_key(), &right).is_err());
+        assert_eq!(psbt.add_signature(0, &key.public_key(), &right), Ok(()));
+        assert!(psbt.is_complete());
+
+        assert!(PartiallySignedTransaction::from_base64("not base64!").is_err());
+    }
+}
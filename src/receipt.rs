@@ -0,0 +1,161 @@
+//! Validator-signed receipts for accepted blocks
+//!
+//! When the validator accepts a block it signs a receipt naming the block
+//! hash, height, winning miner and acceptance time. The miner can keep the
+//! receipt as proof of its win and anyone holding the validator's public key
+//! can check it offline with `verify_receipt`.
+//!
+//! The validator also signs each round it announces: the previous block's
+//! hash, the round's start and its tonce. Miners copy the signature into the
+//! round commitment of the blocks they mine, so a node syncing the chain
+//! later can check with `verify_round_commitment` that every block was mined
+//! against a tonce the validator really set.
+
+use crate::node_key::{verify_signature, NodeKey};
+use crate::{u32_bytes, u128_bytes, BlockHash, BlockHeader, RoundCommitment};
+use crate::hashes::HASH_LEN;
+
+/// Signing context for block receipts
+const RECEIPT_SIGNING_CONTEXT: &[u8] = b"hourcoin-block-receipt";
+
+/// Signing context for round signatures
+const ROUND_SIGNING_CONTEXT: &[u8] = b"hourcoin-round";
+
+/// Signed statement that a validator accepted a block
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockReceipt {
+    pub block_hash: BlockHash,
+    pub height: u32,
+    pub miner_id: String,
+    /// When the validator accepted the block (TAI milliseconds)
+    pub accepted_at: u128,
+    /// Public key of the validator that signed the receipt
+    pub validator_public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl BlockReceipt {
+    /// Sign a receipt for an accepted block
+    pub fn sign(node_key: &NodeKey, block_hash: &BlockHash, height: u32, miner_id: &str, accepted_at: u128) -> Self {
+        let message = Self::signing_bytes(block_hash, height, miner_id, accepted_at);
+        BlockReceipt {
+            block_hash: *block_hash,
+            height,
+            miner_id: miner_id.to_string(),
+            accepted_at,
+            validator_public_key: node_key.public_key(),
+            signature: node_key.sign(RECEIPT_SIGNING_CONTEXT, &message),
+        }
+    }
+
+    /// Bytes covered by the signature
+    pub(crate) fn signing_bytes(block_hash: &BlockHash, height: u32, miner_id: &str, accepted_at: u128) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend(&u32_bytes(&(HASH_LEN as u32)));
+        bytes.extend(block_hash.as_bytes());
+        bytes.extend(&u32_bytes(&height));
+        bytes.extend(&u32_bytes(&(miner_id.len() as u32)));
+        bytes.extend(miner_id.as_bytes());
+        bytes.extend(&u128_bytes(&accepted_at));
+        bytes
+    }
+}
+
+/// Check a receipt's signature, optionally requiring a specific validator key
+pub fn verify_receipt(receipt: &BlockReceipt, expected_validator: Option<&[u8]>) -> Result<(), String> {
+    if let Some(expected) = expected_validator {
+        if receipt.validator_public_key != expected {
+            return Err("Receipt was not signed by the expected validator".to_string());
+        }
+    }
+
+    let message = BlockReceipt::signing_bytes(&receipt.block_hash, receipt.height, &receipt.miner_id, receipt.accepted_at);
+    verify_signature(&receipt.validator_public_key, RECEIPT_SIGNING_CONTEXT, &message, &receipt.signature)
+}
+
+/// Sign the round following `prev_block_hash` that started at `round_start` with `tonce`
+pub fn sign_round(node_key: &NodeKey, prev_block_hash: &BlockHash, round_start: u128, tonce: u8) -> Vec<u8> {
+    node_key.sign(ROUND_SIGNING_CONTEXT, &round_signing_bytes(prev_block_hash, round_start, tonce))
+}
+
+/// Bytes covered by a round signature
+fn round_signing_bytes(prev_block_hash: &BlockHash, round_start: u128, tonce: u8) -> Vec<u8> {
+    let mut bytes = vec![];
+    bytes.extend(&u32_bytes(&(HASH_LEN as u32)));
+    bytes.extend(prev_block_hash.as_bytes());
+    bytes.extend(&u128_bytes(&round_start));
+    bytes.push(tonce);
+    bytes
+}
+
+/// Check that the validator with `validator_public_key` signed `round`, the round after `prev_block_hash`
+pub fn verify_round_signature(round: &RoundCommitment, prev_block_hash: &BlockHash, validator_public_key: &[u8]) -> Result<(), String> {
+    let signature = round.signature.as_ref().ok_or("Round commitment is not signed")?;
+    let message = round_signing_bytes(prev_block_hash, round.round_start, round.tonce);
+    verify_signature(validator_public_key, ROUND_SIGNING_CONTEXT, &message, signature)
+}
+
+/// Check that `header` commits to a round the validator with `validator_public_key` announced
+pub fn verify_round_commitment(header: &BlockHeader, validator_public_key: &[u8]) -> Result<(), String> {
+    let round = header.round.as_ref()
+        .ok_or_else(|| format!("Block {} does not commit to a round", header.index))?;
+    verify_round_signature(round, &header.prev_block_hash, validator_public_key)
+        .map_err(|e| format!("Block {}: {}", header.index, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_receipt_verifies() {
+        let key = NodeKey::generate();
+        let receipt = BlockReceipt::sign(&key, &BlockHash::from_bytes([7; 32]), 3, "alice", 1_000_000);
+
+        assert_eq!(verify_receipt(&receipt, None), Ok(()));
+        assert_eq!(verify_receipt(&receipt, Some(&key.public_key())), Ok(()));
+        assert!(verify_receipt(&receipt, Some(&NodeKey::generate().public_key())).is_err());
+    }
+
+    #[test]
+    fn test_tampered_receipt_fails() {
+        let key = NodeKey::generate();
+        let receipt = BlockReceipt::sign(&key, &BlockHash::from_bytes([7; 32]), 3, "alice", 1_000_000);
+
+        let mut wrong_miner = receipt.clone();
+        wrong_miner.miner_id = "mallory".to_string();
+        assert!(verify_receipt(&wrong_miner, None).is_err());
+
+        let mut wrong_height = receipt.clone();
+        wrong_height.height = 4;
+        assert!(verify_receipt(&wrong_height, None).is_err());
+
+        let mut wrong_key = receipt;
+        wrong_key.validator_public_key = NodeKey::generate().public_key();
+        assert!(verify_receipt(&wrong_key, None).is_err());
+    }
+
+    #[test]
+    fn test_round_commitment_verifies() {
+        let key = NodeKey::generate();
+        let prev_hash = BlockHash::from_bytes([7; 32]);
+        let mut header = crate::Block::new(4, 2_000_000, prev_hash, vec![]).header;
+        assert!(verify_round_commitment(&header, &key.public_key()).is_err());
+
+        let signature = sign_round(&key, &prev_hash, 1_999_000, 13);
+        header.round = Some(RoundCommitment { round_start: 1_999_000, tonce: 13, signature: Some(signature) });
+        assert_eq!(verify_round_commitment(&header, &key.public_key()), Ok(()));
+        assert!(verify_round_commitment(&header, &NodeKey::generate().public_key()).is_err());
+
+        // the signature covers the tonce, the round's start and the previous block
+        let mut wrong_tonce = header.clone();
+        wrong_tonce.round.as_mut().unwrap().tonce = 1;
+        assert!(verify_round_commitment(&wrong_tonce, &key.public_key()).is_err());
+        let mut wrong_start = header.clone();
+        wrong_start.round.as_mut().unwrap().round_start += 1;
+        assert!(verify_round_commitment(&wrong_start, &key.public_key()).is_err());
+        let mut wrong_parent = header;
+        wrong_parent.prev_block_hash = BlockHash::ZERO;
+        assert!(verify_round_commitment(&wrong_parent, &key.public_key()).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+//! A summary of each recent round, for offline analysis
+//!
+//! The validator keeps one `RoundSummary` per round it judged blocks in: when
+//! the round started and ended, its tonce, who won it, and for every miner
+//! that took part, how many blocks it submitted and why they were refused.
+//! A round ends when a block is accepted in it, so the round still being
+//! mined has no end or winner yet.
+//!
+//! Unlike the decision log this is kept in memory whether or not a log is
+//! configured, holds no blocks, and only covers the last
+//! `ROUND_HISTORY_CAPACITY` rounds. `Validator::export_round_history` writes
+//! it out as CSV or JSON.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use crate::analytics::csv_field;
+
+/// Rounds a `RoundHistory` keeps by default
+pub const ROUND_HISTORY_CAPACITY: usize = 1024;
+
+/// The `result` of an accepted block
+#[cfg(feature = "node")]
+const ACCEPTED: &str = "accepted";
+
+/// File formats the round history can be exported in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One row per miner per round; rounds nobody submitted to get a row of their own
+    Csv,
+    /// An array of `RoundSummary`
+    Json,
+}
+
+impl ExportFormat {
+    /// CSV for a `.csv` path, JSON for anything else
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("csv") => ExportFormat::Csv,
+            _ => ExportFormat::Json,
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(format!("Unknown export format: {} (expected csv or json)", s)),
+        }
+    }
+}
+
+/// What one miner did in a round
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MinerRound {
+    /// Blocks the miner submitted
+    pub attempts: u32,
+    /// Refused blocks, counted by `ValidationResult::reason`
+    pub rejections: BTreeMap<String, u32>,
+}
+
+/// One round, as far as it has gone
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoundSummary {
+    /// Validator time the round started at (TAI milliseconds)
+    pub round_start: u128,
+    /// When its block was accepted, if it has been
+    pub round_end: Option<u128>,
+    pub tonce: Option<u8>,
+    /// Miner whose block was accepted (for a peer's block, the miner the peer named)
+    pub winner: Option<String>,
+    /// Miners that submitted blocks, by ID
+    pub miners: BTreeMap<String, MinerRound>,
+}
+
+/// The most recent rounds, oldest first
+#[derive(Debug, Clone)]
+pub struct RoundHistory {
+    // only `record`, which just the validator calls, fills the history up to it
+    #[cfg_attr(not(feature = "node"), allow(dead_code))]
+    capacity: usize,
+    rounds: VecDeque<RoundSummary>,
+}
+
+impl Default for RoundHistory {
+    fn default() -> Self {
+        RoundHistory::new(ROUND_HISTORY_CAPACITY)
+    }
+}
+
+impl RoundHistory {
+    /// Keep up to `capacity` rounds, dropping the oldest first
+    pub fn new(capacity: usize) -> Self {
+        RoundHistory { capacity: capacity.max(1), rounds: VecDeque::new() }
+    }
+
+    pub fn rounds(&self) -> &VecDeque<RoundSummary> {
+        &self.rounds
+    }
+
+    /// Count a block judged at `judged_at` in the round that started at `round_start`
+    ///
+    /// `from_miner` is false for blocks proposed by peers, which may win a
+    /// round but aren't anyone's attempt.
+    #[cfg(feature = "node")]
+    pub(crate) fn record(&mut self, round_start: u128, tonce: Option<u8>, judged_at: u128, miner_id: &str, from_miner: bool, result: &str) {
+        let round = match self.rounds.iter().rposition(|round| round.round_start == round_start) {
+            Some(i) => &mut self.rounds[i],
+            None => {
+                if self.rounds.len() == self.capacity {
+                    self.rounds.pop_front();
+                }
+                self.rounds.push_back(RoundSummary {
+                    round_start,
+                    round_end: None,
+                    tonce,
+                    winner: None,
+                    miners: BTreeMap::new(),
+                });
+                self.rounds.back_mut().unwrap()
+            }
+        };
+        round.tonce = round.tonce.or(tonce);
+
+        if result == ACCEPTED {
+            round.round_end = Some(judged_at);
+            round.winner = Some(miner_id.to_string());
+        }
+        if from_miner {
+            let miner = round.miners.entry(miner_id.to_string()).or_default();
+            miner.attempts += 1;
+            if result != ACCEPTED {
+                *miner.rejections.entry(result.to_string()).or_insert(0) += 1;
+            }
+        }
+    }
+
+    /// The rounds as an indented JSON array
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(&self.rounds).map_err(|e| format!("Failed to encode round history: {}", e))
+    }
+
+    /// The rounds as CSV, one row per miner per round
+    ///
+    /// `rejections` lists `reason=count` pairs separated by `;`.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("round_start,round_end,tonce,winner,miner_id,attempts,rejections\n");
+        for round in &self.rounds {
+            let round_fields = format!(
+                "{},{},{},{}",
+                round.round_start,
+                round.round_end.map(|end| end.to_string()).unwrap_or_default(),
+                round.tonce.map(|tonce| tonce.to_string()).unwrap_or_default(),
+                csv_field(round.winner.as_deref().unwrap_or_default()),
+            );
+            if round.miners.is_empty() {
+                csv.push_str(&format!("{},,,\n", round_fields));
+            }
+            for (miner_id, miner) in &round.miners {
+                let rejections: Vec<String> = miner.rejections.iter()
+                    .map(|(reason, count)| format!("{}={}", reason, count))
+                    .collect();
+                csv.push_str(&format!("{},{},{},{}\n", round_fields, csv_field(miner_id), miner.attempts, rejections.join(";")));
+            }
+        }
+        csv
+    }
+
+    /// Write the rounds to `path` in `format`
+    pub fn export(&self, format: ExportFormat, path: &Path) -> Result<(), String> {
+        let contents = match format {
+            ExportFormat::Csv => self.to_csv(),
+            ExportFormat::Json => self.to_json()?,
+        };
+        std::fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(all(test, feature = "node"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_history() {
+        let mut history = RoundHistory::new(2);
+        history.record(1_000, Some(4), 2_000, "bob", true, "tonce_challenge");
+        history.record(1_000, Some(4), 3_000, "bob", true, "invalid_timestamp");
+        history.record(1_000, Some(4), 4_000, "alice", true, ACCEPTED);
+        history.record(5_000, Some(2), 6_000, "carol,jr", true, "lockout");
+        // a late block for an earlier round still counts towards it
+        history.record(1_000, Some(4), 4_500, "bob", true, "stale_round");
+
+        let first = &history.rounds()[0];
+        assert_eq!((first.round_end, first.winner.as_deref()), (Some(4_000), Some("alice")));
+        assert_eq!(first.miners["bob"].attempts, 3);
+        assert_eq!(first.miners["bob"].rejections["tonce_challenge"], 1);
+        assert!(first.miners["alice"].rejections.is_empty());
+
+        let csv = history.to_csv();
+        assert_eq!(csv.lines().count(), 4);
+        assert!(csv.contains("\n1000,4000,4,alice,bob,3,invalid_timestamp=1;stale_round=1;tonce_challenge=1\n"));
+        assert!(csv.ends_with("\n5000,,2,,\"carol,jr\",1,lockout=1\n"));
+        let rounds: Vec<RoundSummary> = serde_json::from_str(&history.to_json().unwrap()).unwrap();
+        assert_eq!(rounds, history.rounds().iter().cloned().collect::<Vec<_>>());
+
+        // a peer's block ends the round without counting as an attempt; the oldest round makes room
+        history.record(9_000, Some(3), 9_500, "dave", false, ACCEPTED);
+        assert_eq!(history.rounds().len(), 2);
+        let last = history.rounds().back().unwrap();
+        assert_eq!((last.winner.as_deref(), last.miners.len()), (Some("dave"), 0));
+        assert!(history.to_csv().ends_with("\n9000,9500,3,dave,,,\n"));
+
+        assert_eq!("JSON".parse::<ExportFormat>(), Ok(ExportFormat::Json));
+        assert!("xml".parse::<ExportFormat>().is_err());
+        assert_eq!(ExportFormat::from_path(Path::new("rounds.CSV")), ExportFormat::Csv);
+        assert_eq!(ExportFormat::from_path(Path::new("rounds.json")), ExportFormat::Json);
+    }
+}
@@ -0,0 +1,181 @@
+//! Batched, cached verification of transaction signatures
+//!
+//! A transaction's signatures are checked when the mempool admits it, again
+//! when a block carrying it is validated, and again whenever a rewind or
+//! reorganization replays that block. `SignatureCache` remembers the
+//! signatures that verified so each is only checked once. An entry is the
+//! signature together with the key and the transaction signing hash it was
+//! checked against, so a cached signature never vouches for anything else.
+//! The cache holds up to `SIGNATURE_CACHE_CAPACITY` entries and forgets the
+//! least recently used first.
+//!
+//! The signatures that aren't cached are verified together in one sr25519
+//! batch (see `node_key::verify_signatures`). A `Blockchain` shares its cache
+//! with the replicas it validates on, and the validator's mempool checks
+//! transactions through the same chain.
+
+use std::sync::Mutex;
+use sha2::{Digest, Sha256};
+use crate::lru_cache::LruCache;
+use crate::htlc;
+use crate::multisig;
+use crate::node_key::{verify_signatures, Signer};
+use crate::transaction::{Transaction, TX_SIGNING_CONTEXT};
+use crate::wallet;
+
+/// Signatures a `SignatureCache` remembers by default
+pub const SIGNATURE_CACHE_CAPACITY: usize = 100_000;
+
+/// Signatures that have verified, most recently used last
+pub struct SignatureCache {
+    entries: Mutex<LruCache<[u8; 32], ()>>,
+}
+
+impl Default for SignatureCache {
+    fn default() -> Self {
+        SignatureCache::new(SIGNATURE_CACHE_CAPACITY)
+    }
+}
+
+impl SignatureCache {
+    /// Remember up to `capacity` signatures
+    pub fn new(capacity: usize) -> Self {
+        SignatureCache { entries: Mutex::new(LruCache::new(capacity.max(1))) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Signatures found in the cache, and signatures that had to be verified, so far
+    pub fn hits_and_misses(&self) -> (u64, u64) {
+        let stats = self.entries.lock().unwrap().stats();
+        (stats.hits, stats.misses)
+    }
+
+    /// Check every signature `transactions` need to spend their key and multisig inputs
+    ///
+    /// Signatures verified before are taken from the cache; the rest are
+    /// verified in one batch and remembered if they all pass.
+    pub fn verify_transactions<'a>(&self, transactions: impl IntoIterator<Item = &'a Transaction>) -> Result<(), String> {
+        let mut signed = vec![];
+        for transaction in transactions {
+            let signers = required_signatures(transaction)?;
+            if !signers.is_empty() {
+                signed.push((transaction.signing_hash(), signers));
+            }
+        }
+
+        let mut unverified: Vec<(&[u8], &[u8], &[u8])> = vec![];
+        let mut keys = vec![];
+        {
+            let mut entries = self.entries.lock().unwrap();
+            for (signing_hash, signers) in &signed {
+                for (public_key, signature) in signers {
+                    let key = entry_key(signing_hash.as_bytes(), public_key, signature);
+                    if entries.get(&key).is_none() {
+                        unverified.push((*public_key, signing_hash.as_bytes(), *signature));
+                        keys.push(key);
+                    }
+                }
+            }
+        }
+
+        verify_signatures(TX_SIGNING_CONTEXT, &unverified)?;
+        let mut entries = self.entries.lock().unwrap();
+        for key in keys {
+            entries.insert(key, ());
+        }
+        Ok(())
+    }
+}
+
+fn entry_key(message: &[u8], public_key: &[u8], signature: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in [message, public_key, signature] {
+        hasher.update((part.len() as u32).to_le_bytes());
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// Check the signatures `transaction` needs to spend its inputs, without a cache
+pub fn verify_transaction(transaction: &Transaction) -> Result<(), String> {
+    let signing_hash = transaction.signing_hash();
+    let signed: Vec<(&[u8], &[u8], &[u8])> = required_signatures(transaction)?.into_iter()
+        .map(|(public_key, signature)| (public_key, signing_hash.as_bytes().as_slice(), signature))
+        .collect();
+    verify_signatures(TX_SIGNING_CONTEXT, &signed)
+}
+
+/// The public keys and signatures that must verify over `transaction`'s signing hash for it to spend its inputs
+///
+/// Fails if a key, multisig or HTLC input is missing its witness, a multisig
+/// witness has too few signatures, or an HTLC spend isn't signed by its payee.
+/// Inputs of other addresses need none.
+pub fn required_signatures(transaction: &Transaction) -> Result<Vec<Signer<'_>>, String> {
+    let mut signers = vec![];
+    for input in &transaction.inputs {
+        if wallet::is_key_address(&input.to_addr) {
+            signers.push(wallet::input_signer(input, transaction)?);
+        } else if multisig::is_multisig_address(&input.to_addr) {
+            signers.extend(multisig::input_signers(input, transaction)?);
+        } else if htlc::is_htlc_address(&input.to_addr) {
+            signers.push(htlc::input_signer(input, transaction)?);
+        }
+    }
+    Ok(signers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node_key::NodeKey;
+    use crate::transaction::{Output, Witness};
+
+    fn signed_spend(key: &NodeKey, value: f64) -> Transaction {
+        let input = Output { to_addr: wallet::address_from_public_key(&key.public_key()), value, timestamp: 1000, lock_until: None };
+        let mut transaction = Transaction {
+            inputs: vec![input],
+            outputs: vec![Output { to_addr: "Bob".to_owned(), value, timestamp: 2000, lock_until: None }],
+            witnesses: vec![],
+        };
+        let signature = key.sign(TX_SIGNING_CONTEXT, transaction.signing_hash().as_bytes());
+        transaction.witnesses.push(Witness::Signature { public_key: key.public_key(), signature });
+        transaction
+    }
+
+    #[test]
+    fn test_signature_cache() {
+        let cache = SignatureCache::new(2);
+        let key = NodeKey::generate();
+        let (first, second, third) = (signed_spend(&key, 1.0), signed_spend(&key, 2.0), signed_spend(&key, 3.0));
+
+        assert_eq!(cache.verify_transactions([&first, &second]), Ok(()));
+        assert_eq!((cache.len(), cache.hits_and_misses()), (2, (0, 2)));
+        assert_eq!(cache.verify_transactions([&first]), Ok(()));
+        assert_eq!(cache.hits_and_misses(), (1, 2));
+
+        // the least recently used signature makes room
+        assert_eq!(cache.verify_transactions([&third]), Ok(()));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.verify_transactions([&first, &second]), Ok(()));
+        assert_eq!(cache.hits_and_misses(), (2, 4));
+
+        // a cached signature doesn't vouch for a transaction it didn't sign
+        let mut forged = signed_spend(&key, 1.0);
+        forged.outputs[0].to_addr = "Mallory".to_owned();
+        assert!(cache.verify_transactions([&first, &forged]).is_err());
+        let mut unsigned = first.clone();
+        unsigned.witnesses.clear();
+        assert!(cache.verify_transactions([&unsigned]).unwrap_err().starts_with("No signature"));
+
+        // plain addresses need no signatures
+        let plain = Transaction { inputs: vec![Output { to_addr: "Alice".to_owned(), value: 1.0, timestamp: 1000, lock_until: None }], outputs: vec![], witnesses: vec![] };
+        assert!(required_signatures(&plain).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,352 @@
+//! Regtest simulations of many miners
+//!
+//! Lockouts and tonce challenges play out over minutes to hours, which makes
+//! consensus changes slow to evaluate against real miners. A `Simulation`
+//! runs an in-process `Validator` on a `MockTimeSource` with any number of
+//! simulated miners, jumping virtual time from one submission to the next,
+//! so thousands of rounds take seconds.
+//!
+//! Each miner has a hashrate. Once it may mine (it isn't locked out and has
+//! attempts left this round), the time it needs to find a block is drawn from
+//! the exponential distribution with mean `work / hashrate`. It submits at the
+//! first timestamp after that which passes the round's tonce, or as soon as
+//! the challenge ends. The earliest submission is mined for real, so keep the
+//! difficulty low (regtest's is), and judged by the validator; when it is
+//! accepted every miner starts over on the new round. Runs with the same
+//! seed are identical.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use crate::time_source::{MockTimeSource, TimeSource};
+use crate::tx_builder::TransactionBuilder;
+use crate::{find_valid_timestamp, Block, BlockHash, ChainParams, ValidationResult, Validator};
+
+/// Virtual time a simulation starts at (TAI milliseconds)
+pub const SIMULATION_EPOCH: u128 = 1_700_000_000_000;
+
+/// A miner taking part in a simulation
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedMiner {
+    pub id: String,
+    /// Hashes per second of virtual time
+    pub hashrate: f64,
+    /// Whether the miner waits out its lockout, or keeps submitting through it
+    pub respects_lockout: bool,
+}
+
+impl SimulatedMiner {
+    pub fn new(id: impl Into<String>, hashrate: f64) -> Self {
+        SimulatedMiner { id: id.into(), hashrate, respects_lockout: true }
+    }
+
+    /// The same miner, submitting while it is locked out
+    pub fn ignoring_lockout(mut self) -> Self {
+        self.respects_lockout = false;
+        self
+    }
+}
+
+/// What one miner did over a simulation
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MinerReport {
+    pub id: String,
+    pub hashrate: f64,
+    pub blocks: u32,
+    pub submissions: u32,
+    /// Rejected submissions by `ValidationResult::reason`
+    pub rejections: BTreeMap<&'static str, u32>,
+    /// Total lockout imposed for the miner's blocks (milliseconds)
+    pub lockout_ms: u128,
+}
+
+/// Statistics collected by a simulation
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SimulationReport {
+    pub blocks: u32,
+    /// Virtual time simulated (milliseconds)
+    pub elapsed_ms: u128,
+    /// Number of rounds opened with each tonce
+    pub tonce_histogram: BTreeMap<u8, u32>,
+    /// Blocks accepted while the tonce challenge was active
+    pub challenge_blocks: u32,
+    /// Blocks accepted after the challenge dropped to the race
+    pub race_blocks: u32,
+    /// Time from each round's start to its accepted block (milliseconds)
+    pub round_durations_ms: Vec<u128>,
+    pub miners: Vec<MinerReport>,
+}
+
+impl SimulationReport {
+    /// Mean time from a round's start to its accepted block (milliseconds)
+    pub fn mean_round_ms(&self) -> f64 {
+        if self.round_durations_ms.is_empty() {
+            return 0.0;
+        }
+        self.round_durations_ms.iter().sum::<u128>() as f64 / self.round_durations_ms.len() as f64
+    }
+
+    /// Share of the blocks `miner_id` won
+    pub fn block_share(&self, miner_id: &str) -> f64 {
+        let blocks = self.miners.iter().find(|miner| miner.id == miner_id).map_or(0, |miner| miner.blocks);
+        if self.blocks == 0 { 0.0 } else { f64::from(blocks) / f64::from(self.blocks) }
+    }
+
+    /// Jain's fairness index of blocks won per unit of hashrate
+    ///
+    /// 1 when every miner's share of blocks matches its share of hashrate,
+    /// falling to 1/n when one miner of n wins everything.
+    pub fn fairness_index(&self) -> f64 {
+        let rates: Vec<f64> = self.miners.iter()
+            .filter(|miner| miner.hashrate > 0.0)
+            .map(|miner| f64::from(miner.blocks) / miner.hashrate)
+            .collect();
+        let sum_of_squares: f64 = rates.iter().map(|rate| rate * rate).sum();
+        if sum_of_squares == 0.0 {
+            return 1.0;
+        }
+        rates.iter().sum::<f64>().powi(2) / (rates.len() as f64 * sum_of_squares)
+    }
+
+    /// The report as a table
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{} blocks in {:.1}s of virtual time, {:.0}ms per round on average\n\
+             {} won during the tonce challenge, {} in the race after it; fairness index {:.3}\n\
+             tonce histogram: {:?}\n\n{:<12} {:>10} {:>8} {:>8} {:>12} {:>12}  rejections\n",
+            self.blocks, self.elapsed_ms as f64 / 1000.0, self.mean_round_ms(),
+            self.challenge_blocks, self.race_blocks, self.fairness_index(),
+            self.tonce_histogram,
+            "miner", "hashrate", "blocks", "share", "submissions", "lockout (s)",
+        );
+        for miner in &self.miners {
+            summary.push_str(&format!(
+                "{:<12} {:>10.1} {:>8} {:>7.1}% {:>12} {:>12.1}  {:?}\n",
+                miner.id, miner.hashrate, miner.blocks, 100.0 * self.block_share(&miner.id),
+                miner.submissions, miner.lockout_ms as f64 / 1000.0, miner.rejections,
+            ));
+        }
+        summary
+    }
+}
+
+// when a miner will submit this round, and the timestamp its block carries
+#[derive(Debug, Clone, Copy)]
+struct Submission {
+    at: u128,
+    timestamp: u128,
+}
+
+/// An in-process validator and simulated miners sharing a virtual clock
+pub struct Simulation {
+    validator: Validator,
+    clock: MockTimeSource,
+    miners: Vec<SimulatedMiner>,
+    rng: StdRng,
+    report: SimulationReport,
+}
+
+impl Simulation {
+    /// A fresh chain under `params`, at `SIMULATION_EPOCH`
+    pub fn new(params: ChainParams, miners: Vec<SimulatedMiner>, seed: u64) -> Self {
+        let clock = MockTimeSource::new(SIMULATION_EPOCH);
+        let mut validator = Validator::new_with_params(params);
+        validator.set_time_source(Arc::new(clock.clone()));
+        validator.start_new_round();
+
+        let report = SimulationReport {
+            miners: miners.iter()
+                .map(|miner| MinerReport { id: miner.id.clone(), hashrate: miner.hashrate, ..MinerReport::default() })
+                .collect(),
+            ..SimulationReport::default()
+        };
+        Simulation { validator, clock, miners, rng: StdRng::seed_from_u64(seed), report }
+    }
+
+    /// The validator, to inspect its chain and miner statistics
+    pub fn validator(&self) -> &Validator {
+        &self.validator
+    }
+
+    /// The validator, to change its policies before running
+    pub fn validator_mut(&mut self) -> &mut Validator {
+        &mut self.validator
+    }
+
+    pub fn report(&self) -> &SimulationReport {
+        &self.report
+    }
+
+    /// Virtual time now (TAI milliseconds)
+    pub fn now(&self) -> u128 {
+        self.clock.now()
+    }
+
+    /// Run until `blocks` more blocks are accepted, `max_duration_ms` of virtual
+    /// time passes, or no miner can submit anything
+    pub fn run(&mut self, blocks: u32, max_duration_ms: u128) -> &SimulationReport {
+        let started_at = self.now();
+        let deadline = started_at.saturating_add(max_duration_ms);
+        let target = self.report.blocks.saturating_add(blocks);
+
+        while self.report.blocks < target {
+            let round_start = self.now();
+            if let Some(tonce) = self.validator.get_current_tonce() {
+                *self.report.tonce_histogram.entry(tonce).or_insert(0) += 1;
+            }
+            let mut plans: Vec<Option<Submission>> = (0..self.miners.len()).map(|miner| self.plan(miner)).collect();
+
+            // submissions in time order until one is accepted
+            let accepted = loop {
+                let next = plans.iter().enumerate()
+                    .filter_map(|(miner, plan)| plan.map(|plan| (miner, plan)))
+                    .min_by_key(|(miner, plan)| (plan.at, *miner));
+                let Some((miner, submission)) = next else {
+                    break false;
+                };
+                if submission.at > deadline {
+                    self.clock.set(deadline);
+                    break false;
+                }
+                self.clock.set(submission.at);
+                if self.submit(miner, submission.timestamp) {
+                    break true;
+                }
+                plans[miner] = self.plan(miner);
+            };
+            if !accepted {
+                break;
+            }
+            self.report.round_durations_ms.push(self.now() - round_start);
+        }
+
+        self.report.elapsed_ms += self.now() - started_at;
+        &self.report
+    }
+
+    // when the miner will next submit this round, if it can
+    fn plan(&mut self, miner: usize) -> Option<Submission> {
+        let SimulatedMiner { id, hashrate, respects_lockout } = &self.miners[miner];
+        if *hashrate <= 0.0 || self.validator.remaining_attempts(id) == 0 {
+            return None;
+        }
+        let now = self.now();
+        let start = match self.validator.miner_session(id) {
+            Some(session) if *respects_lockout => session.must_wait_until.max(now),
+            _ => now,
+        };
+
+        // hashes are independent trials, so the time to a block is exponential
+        let mean_ms = self.validator.get_difficulty().work() as f64 / hashrate * 1000.0;
+        let solved_at = start + (-(1.0 - self.rng.gen::<f64>()).ln() * mean_ms).max(1.0) as u128;
+
+        let challenge_end = self.challenge_end();
+        let tonce = self.validator.get_current_tonce().unwrap_or(1);
+        let timestamp = if solved_at < challenge_end {
+            let window = u32::try_from(challenge_end - solved_at).unwrap_or(u32::MAX);
+            find_valid_timestamp(tonce, solved_at, window).unwrap_or(challenge_end)
+        } else {
+            solved_at
+        };
+        Some(Submission { at: timestamp, timestamp })
+    }
+
+    // the challenge runs from the tip's timestamp, or from the round's start before the first block
+    fn challenge_end(&self) -> u128 {
+        let challenge_start = self.validator.blockchain.blocks.last()
+            .map_or(self.validator.get_round_info().round_start, |tip| tip.header.timestamp);
+        challenge_start + self.validator.get_params().tonce_challenge_duration_ms
+    }
+
+    // mine and submit the miner's block, returning whether it was accepted
+    fn submit(&mut self, miner: usize, timestamp: u128) -> bool {
+        let id = self.miners[miner].id.clone();
+        let (index, prev_block_hash) = self.validator.blockchain.blocks.last()
+            .map_or((0, BlockHash::ZERO), |tip| (tip.header.index + 1, tip.hash));
+        let coinbase = TransactionBuilder::new(timestamp)
+            .pay_to(&id, self.validator.get_params().coinbase_value)
+            .build_coinbase()
+            .expect("a coinbase paying one miner is valid");
+        let mut block = Block::new(index, timestamp, prev_block_hash, vec![coinbase]);
+        block.mine(self.validator.get_difficulty());
+
+        let challenge_active = self.now() < self.challenge_end();
+        let result = self.validator.validate_block_submission(block, id.clone());
+
+        let report = &mut self.report.miners[miner];
+        report.submissions += 1;
+        if result != ValidationResult::Accepted {
+            *report.rejections.entry(result.reason()).or_insert(0) += 1;
+            return false;
+        }
+        report.blocks += 1;
+        if let Some(session) = self.validator.miner_session(&id) {
+            report.lockout_ms += session.must_wait_until - session.block_accepted_at;
+        }
+        self.report.blocks += 1;
+        if challenge_active {
+            self.report.challenge_blocks += 1;
+        } else {
+            self.report.race_blocks += 1;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn miners() -> Vec<SimulatedMiner> {
+        vec![SimulatedMiner::new("big", 40.0), SimulatedMiner::new("medium", 20.0), SimulatedMiner::new("small", 10.0)]
+    }
+
+    #[test]
+    fn test_simulation_runs_rounds() {
+        let params = ChainParams::regtest();
+        let mut simulation = Simulation::new(params.clone(), miners(), 7);
+        let report = simulation.run(150, u128::MAX).clone();
+
+        assert_eq!(report.blocks, 150);
+        assert_eq!(simulation.validator().get_block_count(), 150);
+        assert_eq!(report.challenge_blocks + report.race_blocks, 150);
+        assert_eq!(report.round_durations_ms.len(), 150);
+        assert_eq!(report.tonce_histogram.values().sum::<u32>(), 150);
+        assert!(report.tonce_histogram.keys().all(|tonce| (1..32).contains(tonce)));
+        assert_eq!(report.elapsed_ms, simulation.now() - SIMULATION_EPOCH);
+
+        // honest miners are never turned away, and every win costs a lockout
+        for miner in &report.miners {
+            assert!(miner.rejections.is_empty(), "{:?}", miner);
+            assert_eq!(miner.submissions, miner.blocks);
+            assert_eq!(miner.lockout_ms, u128::from(miner.blocks) * params.lockout_duration_ms);
+        }
+        assert!(report.block_share("big") > report.block_share("small"));
+        assert!(report.fairness_index() > 0.5 && report.fairness_index() <= 1.0);
+
+        // the same seed replays the same history
+        let mut replay = Simulation::new(params, miners(), 7);
+        assert_eq!(replay.run(150, u128::MAX), &report);
+    }
+
+    #[test]
+    fn test_lockout_ignoring_miner_is_rejected() {
+        let mut miners = miners();
+        miners.push(SimulatedMiner::new("greedy", 40.0).ignoring_lockout());
+        let mut simulation = Simulation::new(ChainParams::regtest(), miners, 11);
+        let report = simulation.run(100, u128::MAX);
+
+        let greedy = report.miners.iter().find(|miner| miner.id == "greedy").unwrap();
+        assert!(greedy.rejections.get("lockout").copied().unwrap_or(0) > 0);
+        assert_eq!(greedy.submissions, greedy.blocks + greedy.rejections.values().sum::<u32>());
+
+        // a deadline stops the run early
+        let before = report.blocks;
+        let elapsed = report.elapsed_ms;
+        let report = simulation.run(1_000, 2_000);
+        assert!(report.blocks < before + 1_000);
+        assert_eq!(report.elapsed_ms, elapsed + 2_000);
+    }
+}
@@ -0,0 +1,144 @@
+//! Validator state snapshots
+//!
+//! `Validator::snapshot` captures what a validator needs to pick up where it
+//! left off: the chain up to its tip, the UTXO set, miners in lockout and the
+//! round in progress. Snapshots are plain data, saved as JSON, so they can be
+//! kept as backups or copied to another host.
+//!
+//! `Validator::restore` doesn't trust the file. It replays the blocks under
+//! the restoring validator's own chain rules, then checks that the tip and
+//! UTXO set it arrives at are the ones the snapshot recorded. Blocks are
+//! stored in their canonical encoding (hex), the same bytes their hashes
+//! cover, and the snapshot records the `CANONICAL_VERSION` it was written at.
+//!
+//! A validator that was fast synced from a UTXO snapshot (see the
+//! `utxo_snapshot` module) has no blocks to replay below it. Its snapshots
+//! carry that UTXO snapshot and the headers under it instead, and restoring
+//! one checks them again as `Blockchain::from_utxo_snapshot` does.
+
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::atomic_file::atomic_write;
+use crate::canonical::{self, CanonicalDeserialize, CanonicalSerialize};
+use crate::utxo_snapshot::UtxoSnapshot;
+use crate::{Block, BlockHeader, Difficulty};
+
+/// Bumped whenever the snapshot layout changes incompatibly
+pub const SNAPSHOT_VERSION: u32 = 3;
+
+/// Everything `Validator::restore` needs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValidatorSnapshot {
+    pub version: u32,
+    /// Encoding of `blocks` and `base`
+    pub canonical_version: u32,
+    /// Height and hash (hex) of the last block, if there is one
+    pub tip: Option<(u32, String)>,
+    /// Every block from genesis, or from after `base`, canonically encoded (hex)
+    pub blocks: Vec<String>,
+    /// Where the chain starts, if it was synced from a UTXO snapshot
+    #[serde(default)]
+    pub base: Option<SnapshotBase>,
+    /// First height and compact bits of every difficulty the chain has used
+    pub difficulty_history: Vec<(usize, Difficulty)>,
+    /// Unspent output hashes (hex), sorted
+    pub utxo_set: Vec<String>,
+    /// Miners in lockout
+    pub sessions: Vec<SessionSnapshot>,
+    /// Consecutive wins per miner: (miner, wins, when the last lockout ended)
+    pub win_streaks: Vec<(String, u32, u128)>,
+    pub round: RoundSnapshot,
+    /// Operator state (see the `admin` module)
+    pub paused: bool,
+    pub banned_miners: Vec<String>,
+}
+
+/// The UTXO snapshot a chain was synced from, and the headers under it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotBase {
+    /// Headers from genesis to the snapshot's block, canonically encoded (hex)
+    pub headers: Vec<String>,
+    /// The signed UTXO snapshot, canonically encoded (hex)
+    pub utxo_snapshot: String,
+}
+
+impl SnapshotBase {
+    /// Record `utxo_snapshot` and the headers of `blocks`, the chain up to its block
+    pub fn new(blocks: &[Block], utxo_snapshot: &UtxoSnapshot) -> Self {
+        SnapshotBase {
+            headers: blocks.iter().map(|block| hex::encode(block.header.canonical_bytes())).collect(),
+            utxo_snapshot: hex::encode(utxo_snapshot.canonical_bytes()),
+        }
+    }
+
+    /// Decode the headers and the UTXO snapshot
+    pub fn decode(&self) -> Result<(Vec<BlockHeader>, UtxoSnapshot), String> {
+        let headers = self.headers.iter().enumerate()
+            .map(|(height, encoded)| {
+                let bytes = hex::decode(encoded).map_err(|e| format!("Invalid hex for header {}: {}", height, e))?;
+                BlockHeader::from_canonical_bytes(&bytes).map_err(|e| format!("Invalid header {}: {}", height, e))
+            })
+            .collect::<Result<_, String>>()?;
+        let bytes = hex::decode(&self.utxo_snapshot).map_err(|e| format!("Invalid hex for the UTXO snapshot: {}", e))?;
+        let utxo_snapshot = UtxoSnapshot::from_canonical_bytes(&bytes).map_err(|e| format!("Invalid UTXO snapshot: {}", e))?;
+        Ok((headers, utxo_snapshot))
+    }
+}
+
+/// A miner's lockout
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionSnapshot {
+    pub miner_id: String,
+    pub block_accepted_at: u128,
+    pub must_wait_until: u128,
+}
+
+/// The round in progress
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoundSnapshot {
+    pub round_start: u128,
+    pub tonce: Option<u8>,
+    /// Attempts each miner has used this round
+    pub attempts: Vec<(String, u32)>,
+}
+
+impl ValidatorSnapshot {
+    /// Write the snapshot to `path` as JSON
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_vec(self)
+            .map_err(|e| format!("Failed to encode snapshot: {}", e))?;
+        atomic_write(path, &json)
+    }
+
+    /// Read a snapshot written by `save`
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let snapshot: ValidatorSnapshot = serde_json::from_slice(&json)
+            .map_err(|e| format!("Invalid snapshot in {}: {}", path.display(), e))?;
+        snapshot.check_versions().map_err(|e| format!("{} ({})", e, path.display()))?;
+        Ok(snapshot)
+    }
+
+    /// Fail unless the snapshot's layout and encoding are the ones this build writes
+    pub fn check_versions(&self) -> Result<(), String> {
+        if self.version != SNAPSHOT_VERSION {
+            return Err(format!("Snapshot is version {}, expected {}", self.version, SNAPSHOT_VERSION));
+        }
+        canonical::check_version(self.canonical_version).map_err(|e| format!("Snapshot blocks: {}", e))
+    }
+
+    /// Decode the stored blocks
+    pub fn decode_blocks(&self) -> Result<Vec<Block>, String> {
+        self.blocks.iter().enumerate()
+            .map(|(height, encoded)| {
+                let bytes = hex::decode(encoded).map_err(|e| format!("Invalid hex for block {}: {}", height, e))?;
+                Block::from_canonical_bytes(&bytes).map_err(|e| format!("Invalid block {}: {}", height, e))
+            })
+            .collect()
+    }
+}
+
+/// Encode a block for `ValidatorSnapshot::blocks`
+pub fn encode_block(block: &Block) -> String {
+    hex::encode(block.canonical_bytes())
+}
@@ -0,0 +1,196 @@
+//! Simple Network Time Protocol client (RFC 4330)
+//!
+//! One UDP round trip to an NTP server gives four timestamps: when we sent
+//! the request (t1), when the server received it (t2) and replied (t3), and
+//! when the reply arrived (t4). From these,
+//!
+//! - offset = ((t2 - t1) + (t3 - t4)) / 2, how far the local clock is behind
+//! - delay = (t4 - t1) - (t3 - t2), the network round trip
+//!
+//! The offset is exact when the two legs of the trip take equally long, so
+//! unlike the HTTP sources it is good to a few milliseconds. The local side
+//! uses a `TimeSource` (TAI); the server's UTC times are converted to TAI
+//! before comparing.
+
+#[cfg(feature = "node")]
+use std::time::Duration;
+#[cfg(feature = "node")]
+use tokio::net::UdpSocket;
+#[cfg(feature = "node")]
+use crate::leap_seconds::utc_to_tai_millis;
+#[cfg(feature = "node")]
+use crate::time_source::TimeSource;
+
+/// Seconds from the NTP epoch (1900) to the Unix epoch (1970)
+const NTP_UNIX_EPOCH_DELTA_SECS: u64 = 2_208_988_800;
+
+/// Size of an NTP packet without extensions
+const NTP_PACKET_LEN: usize = 48;
+
+/// Replies slower than this round trip are too uncertain to use (milliseconds)
+pub const MAX_ROUND_TRIP_MS: i128 = 2_000;
+
+/// How long to wait for a reply
+#[cfg(feature = "node")]
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Convert Unix milliseconds to a 64-bit NTP timestamp (32.32 fixed point seconds)
+pub fn unix_ms_to_ntp(unix_ms: i64) -> u64 {
+    let seconds = unix_ms.div_euclid(1000) as u64 + NTP_UNIX_EPOCH_DELTA_SECS;
+    let fraction = ((unix_ms.rem_euclid(1000) as u64) << 32) / 1000;
+    (seconds << 32) | fraction
+}
+
+/// Convert a 64-bit NTP timestamp to Unix milliseconds
+pub fn ntp_to_unix_ms(ntp: u64) -> i64 {
+    let seconds = (ntp >> 32) as i64 - NTP_UNIX_EPOCH_DELTA_SECS as i64;
+    let millis = ((ntp & 0xFFFF_FFFF) * 1000 + (1 << 31)) >> 32;
+    seconds * 1000 + millis as i64
+}
+
+/// A client request (version 4, mode 3) carrying `transmit` for the server to echo back
+pub fn build_request(transmit: u64) -> [u8; NTP_PACKET_LEN] {
+    let mut packet = [0u8; NTP_PACKET_LEN];
+    packet[0] = 0x23; // leap indicator 0, version 4, mode 3 (client)
+    packet[40..48].copy_from_slice(&transmit.to_be_bytes());
+    packet
+}
+
+/// The fields of a server reply a client needs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NtpReply {
+    pub stratum: u8,
+    /// Our transmit timestamp, echoed back
+    pub originate: u64,
+    /// When the server received the request (t2)
+    pub receive: u64,
+    /// When the server sent the reply (t3)
+    pub transmit: u64,
+}
+
+/// Parse a server reply to the request sent with transmit timestamp `sent`
+pub fn parse_reply(packet: &[u8], sent: u64) -> Result<NtpReply, String> {
+    if packet.len() < NTP_PACKET_LEN {
+        return Err(format!("NTP reply is {} bytes, expected {}", packet.len(), NTP_PACKET_LEN));
+    }
+    let mode = packet[0] & 0x07;
+    if mode != 4 {
+        return Err(format!("NTP reply has mode {}, expected 4 (server)", mode));
+    }
+    if packet[0] >> 6 == 3 {
+        return Err("NTP server clock is unsynchronized".to_string());
+    }
+
+    let timestamp = |at: usize| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&packet[at..at + 8]);
+        u64::from_be_bytes(bytes)
+    };
+    let reply = NtpReply {
+        stratum: packet[1],
+        originate: timestamp(24),
+        receive: timestamp(32),
+        transmit: timestamp(40),
+    };
+
+    // stratum 0 is a "kiss-o'-death" asking us to back off
+    if reply.stratum == 0 {
+        return Err("NTP server sent kiss-o'-death".to_string());
+    }
+    if reply.originate != sent {
+        return Err("NTP reply does not answer our request".to_string());
+    }
+    Ok(reply)
+}
+
+/// Offset and round trip of one exchange
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SntpSample {
+    /// How far the local clock is behind the server (milliseconds)
+    pub offset_ms: i128,
+    /// Network round trip, excluding the server's processing time (milliseconds)
+    pub delay_ms: i128,
+}
+
+impl SntpSample {
+    /// Compute from the four exchange times, all in the same time scale (milliseconds)
+    pub fn from_times(t1: i128, t2: i128, t3: i128, t4: i128) -> Self {
+        SntpSample {
+            offset_ms: ((t2 - t1) + (t3 - t4)) / 2,
+            delay_ms: (t4 - t1) - (t3 - t2),
+        }
+    }
+}
+
+#[cfg(feature = "node")]
+/// Query an NTP server (`host:port`), timing the exchange with `clock`
+pub async fn query(server: &str, clock: &dyn TimeSource) -> Result<SntpSample, String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await
+        .map_err(|e| format!("Failed to open UDP socket: {}", e))?;
+    socket.connect(server).await
+        .map_err(|e| format!("Failed to resolve {}: {}", server, e))?;
+
+    let t1 = clock.now();
+    let sent = unix_ms_to_ntp(t1 as i64);
+    socket.send(&build_request(sent)).await
+        .map_err(|e| format!("Failed to send NTP request: {}", e))?;
+
+    let mut buffer = [0u8; 128];
+    let len = tokio::time::timeout(QUERY_TIMEOUT, socket.recv(&mut buffer)).await
+        .map_err(|_| format!("No reply from {}", server))?
+        .map_err(|e| format!("Failed to read NTP reply: {}", e))?;
+    let t4 = clock.now();
+
+    let reply = parse_reply(&buffer[..len], sent)?;
+    let t2 = utc_to_tai_millis(ntp_to_unix_ms(reply.receive));
+    let t3 = utc_to_tai_millis(ntp_to_unix_ms(reply.transmit));
+    let sample = SntpSample::from_times(t1 as i128, t2 as i128, t3 as i128, t4 as i128);
+
+    if sample.delay_ms < 0 || sample.delay_ms > MAX_ROUND_TRIP_MS {
+        return Err(format!("NTP round trip of {}ms is unusable", sample.delay_ms));
+    }
+    Ok(sample)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ntp_timestamp_conversion() {
+        let unix_ms = 1_700_000_000_123;
+        assert_eq!(ntp_to_unix_ms(unix_ms_to_ntp(unix_ms)), unix_ms);
+        assert_eq!(unix_ms_to_ntp(0) >> 32, NTP_UNIX_EPOCH_DELTA_SECS);
+        assert_eq!(unix_ms_to_ntp(500) & 0xFFFF_FFFF, 1 << 31);
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        let sent = unix_ms_to_ntp(1_700_000_000_000);
+        let mut packet = [0u8; NTP_PACKET_LEN];
+        packet[0] = 0x24; // version 4, server
+        packet[1] = 2;
+        packet[24..32].copy_from_slice(&sent.to_be_bytes());
+        packet[32..40].copy_from_slice(&unix_ms_to_ntp(1_700_000_000_050).to_be_bytes());
+        packet[40..48].copy_from_slice(&unix_ms_to_ntp(1_700_000_000_051).to_be_bytes());
+
+        let reply = parse_reply(&packet, sent).unwrap();
+        assert_eq!(ntp_to_unix_ms(reply.transmit), 1_700_000_000_051);
+
+        // Replies to someone else's request, kiss-o'-death and client packets are refused
+        assert!(parse_reply(&packet, sent + 1).is_err());
+        let mut kod = packet;
+        kod[1] = 0;
+        assert!(parse_reply(&kod, sent).is_err());
+        assert!(parse_reply(&build_request(sent), sent).is_err());
+        assert!(parse_reply(&packet[..40], sent).is_err());
+    }
+
+    #[test]
+    fn test_sample_offset_and_delay() {
+        // Local clock 100ms behind, 20ms each way, 5ms at the server
+        let sample = SntpSample::from_times(1000, 1120, 1125, 1045);
+        assert_eq!(sample.offset_ms, 100);
+        assert_eq!(sample.delay_ms, 40);
+    }
+}
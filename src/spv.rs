@@ -0,0 +1,260 @@
+//! Header-only light client
+//!
+//! An `SpvClient` keeps block headers, not blocks. It checks that each header
+//! extends the last one, meets the network's proof-of-work target and, for
+//! deterministic tonces, passes the previous block's tonce challenge. A header
+//! is confirmed once it carries a receipt signed by the pinned validator key.
+//!
+//! To check a payment the client asks a validator for a `MerkleProof` and
+//! verifies it against the merkle root in the header, without downloading the
+//! block. Each header records the difficulty it was mined at; the client
+//! holds it to that, and rejects any header claiming less than the network's
+//! starting difficulty.
+
+use std::collections::HashMap;
+use crate::chain_params::ChainParams;
+use crate::hashable::Hashable;
+use crate::merkle::MerkleProof;
+#[cfg(feature = "node")]
+use crate::network::protocol::MAX_HEADERS_PER_MESSAGE;
+#[cfg(feature = "node")]
+use crate::network::MinerClient;
+use crate::receipt::{verify_receipt, BlockReceipt};
+use crate::tonce::TonceChallenge;
+use crate::{BlockHash, BlockHeader, Txid};
+
+/// A chain of verified block headers
+pub struct SpvClient {
+    params: ChainParams,
+    headers: Vec<BlockHeader>,
+    /// Hash of each header, by height
+    hashes: Vec<BlockHash>,
+    receipts: HashMap<u32, BlockReceipt>,
+    /// Validator key receipts must be signed with; receipts are ignored without one
+    validator_key: Option<Vec<u8>>,
+    check_tonce: bool,
+}
+
+impl SpvClient {
+    /// An empty client for the network described by `params`
+    pub fn new(params: ChainParams) -> Self {
+        SpvClient {
+            params,
+            headers: vec![],
+            hashes: vec![],
+            receipts: HashMap::new(),
+            validator_key: None,
+            check_tonce: true,
+        }
+    }
+
+    /// Trust receipts signed by this validator key
+    pub fn with_validator_key(mut self, public_key: Vec<u8>) -> Self {
+        self.validator_key = Some(public_key);
+        self
+    }
+
+    /// Check each header against the previous block's tonce (off for VRF-derived tonces)
+    pub fn set_check_tonce(&mut self, enabled: bool) {
+        self.check_tonce = enabled;
+    }
+
+    /// Number of headers held
+    pub fn height(&self) -> u32 {
+        self.headers.len() as u32
+    }
+
+    pub fn header(&self, height: u32) -> Option<&BlockHeader> {
+        self.headers.get(height as usize)
+    }
+
+    /// Hash of the latest header, or None before the genesis header
+    pub fn tip_hash(&self) -> Option<&BlockHash> {
+        self.hashes.last()
+    }
+
+    /// Verify a header and append it to the chain
+    pub fn add_header(&mut self, header: BlockHeader) -> Result<(), String> {
+        if header.index != self.height() {
+            return Err(format!("Expected header {}, got {}", self.height(), header.index));
+        }
+        let parent = self.headers.last().map(|prev| (prev, &self.hashes[prev.index as usize]));
+        let hash = verify_header(&header, parent, &self.params, self.check_tonce)?;
+
+        self.headers.push(header);
+        self.hashes.push(hash);
+        Ok(())
+    }
+
+    /// Attach a validator receipt to the header it names
+    pub fn add_receipt(&mut self, receipt: BlockReceipt) -> Result<(), String> {
+        let validator_key = self.validator_key.as_ref()
+            .ok_or_else(|| "No validator key to check receipts against".to_string())?;
+        verify_receipt(&receipt, Some(validator_key))?;
+
+        match self.hashes.get(receipt.height as usize) {
+            Some(hash) if *hash == receipt.block_hash => {
+                self.receipts.insert(receipt.height, receipt);
+                Ok(())
+            }
+            Some(_) => Err(format!("Receipt does not match header {}", receipt.height)),
+            None => Err(format!("No header at height {}", receipt.height)),
+        }
+    }
+
+    /// Whether the pinned validator has signed a receipt for the block at `height`
+    pub fn is_confirmed(&self, height: u32) -> bool {
+        self.receipts.contains_key(&height)
+    }
+
+    /// Check that a transaction is in the block at `height`
+    pub fn verify_transaction(&self, tx_hash: &Txid, height: u32, proof: &MerkleProof) -> bool {
+        self.header(height)
+            .is_some_and(|header| proof.verify(tx_hash, &header.merkle_root))
+    }
+
+    #[cfg(feature = "node")]
+    /// Download and verify headers past the current tip
+    ///
+    /// Returns the number of headers added.
+    pub async fn sync(&mut self, client: &MinerClient) -> Result<u32, Box<dyn std::error::Error>> {
+        let start = self.height();
+        loop {
+            let headers = client.get_headers(self.height(), MAX_HEADERS_PER_MESSAGE).await?;
+            if headers.is_empty() {
+                return Ok(self.height() - start);
+            }
+
+            for data in headers {
+                self.add_header(data.to_header()?)?;
+                if let (Some(receipt), Some(_)) = (data.receipt, &self.validator_key) {
+                    self.add_receipt(receipt.to_receipt()?)?;
+                }
+            }
+        }
+    }
+}
+
+/// Check a header on its own and against its parent (`None` for the genesis header)
+///
+/// `parent` carries the parent header with its hash. The header must meet the
+/// difficulty it records, which may not be easier than the network's, and must
+/// follow the parent in index, hash and time; with `check_tonce` it must also
+/// pass the parent's tonce. Returns the header's hash.
+pub fn verify_header(header: &BlockHeader, parent: Option<(&BlockHeader, &BlockHash)>, params: &ChainParams, check_tonce: bool) -> Result<BlockHash, String> {
+    // validators may raise the difficulty above the network's, never lower it
+    if header.bits.is_easier_than(&params.difficulty) {
+        return Err(format!("Header {} claims difficulty {}, easier than the network's", header.index, header.bits));
+    }
+    let hash = header.hash();
+    if !header.bits.is_met_by(&hash) {
+        return Err(format!("Header {} does not meet the difficulty target", header.index));
+    }
+
+    match parent {
+        Some((prev, prev_hash)) => {
+            if header.index != prev.index + 1 {
+                return Err(format!("Header {} does not follow header {}", header.index, prev.index));
+            }
+            if header.prev_block_hash != *prev_hash {
+                return Err(format!("Header {} does not extend the chain", header.index));
+            }
+            if header.timestamp <= prev.timestamp {
+                return Err(format!("Header {} is not later than its parent", header.index));
+            }
+            if check_tonce {
+                let mut tonce = TonceChallenge::new_with_params(prev_hash, prev.timestamp, params);
+                if !tonce.validate_timestamp(header.timestamp, header.timestamp) {
+                    return Err(format!("Header {} fails the tonce challenge", header.index));
+                }
+            }
+        }
+        None => {
+            if header.prev_block_hash != BlockHash::ZERO {
+                return Err("Genesis header has a parent".to_string());
+            }
+        }
+    }
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node_key::NodeKey;
+    use crate::transaction::{Output, Transaction};
+    use crate::{Block, Difficulty};
+
+    fn mined_block(index: u32, timestamp: u128, prev_block_hash: BlockHash, difficulty: Difficulty) -> Block {
+        let coinbase = Transaction {
+            inputs: vec![],
+            outputs: vec![Output { to_addr: "Alice".to_owned(), value: 50.0, timestamp, lock_until: None }],
+            witnesses: vec![],
+        };
+        let payment = Transaction {
+            inputs: vec![],
+            outputs: vec![Output { to_addr: "Bob".to_owned(), value: 1.0, timestamp, lock_until: None }],
+            witnesses: vec![],
+        };
+        let mut block = Block::new(index, timestamp, prev_block_hash, vec![coinbase, payment]);
+        block.mine(difficulty);
+        block
+    }
+
+    #[test]
+    fn test_spv_header_chain() {
+        let params = ChainParams::regtest();
+        let key = NodeKey::generate();
+        let mut client = SpvClient::new(params.clone()).with_validator_key(key.public_key());
+        client.set_check_tonce(false);
+
+        let genesis = mined_block(0, 1000, BlockHash::ZERO, params.difficulty);
+        let next = mined_block(1, 2000, genesis.hash, params.difficulty);
+
+        // Headers must arrive in order
+        assert!(client.add_header(next.header.clone()).is_err());
+        client.add_header(genesis.header.clone()).unwrap();
+        assert_eq!(client.tip_hash(), Some(&genesis.hash));
+
+        let mut orphan = next.header.clone();
+        orphan.prev_block_hash = BlockHash::from_bytes([9; 32]);
+        assert!(client.add_header(orphan).is_err());
+        client.add_header(next.header.clone()).unwrap();
+        assert_eq!(client.height(), 2);
+
+        // Receipts must come from the pinned key and name the header's hash
+        assert!(!client.is_confirmed(1));
+        assert!(client.add_receipt(BlockReceipt::sign(&NodeKey::generate(), &next.hash, 1, "alice", 2500)).is_err());
+        assert!(client.add_receipt(BlockReceipt::sign(&key, &genesis.hash, 1, "alice", 2500)).is_err());
+        client.add_receipt(BlockReceipt::sign(&key, &next.hash, 1, "alice", 2500)).unwrap();
+        assert!(client.is_confirmed(1));
+
+        // A transaction is proven against the header's merkle root
+        let tx_hash = next.transaction_hashes()[1];
+        let proof = next.merkle_proof(1).unwrap();
+        assert!(client.verify_transaction(&tx_hash, 1, &proof));
+        assert!(!client.verify_transaction(&tx_hash, 0, &proof));
+        assert!(!client.verify_transaction(&genesis.transaction_hashes()[0], 1, &proof));
+    }
+
+    #[test]
+    fn test_spv_rejects_weak_headers() {
+        let params = ChainParams::regtest().with_difficulty(Difficulty::from_target(0x0000FFFFFFFFFFFFFFFFFFFFFFFFFFFF));
+        let mut client = SpvClient::new(params);
+
+        let mut header = Block::new(0, 1000, BlockHash::ZERO, vec![]).header.clone();
+        header.bits = Difficulty::from_target(0x0000FFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+        while header.bits.is_met_by(&header.hash()) {
+            header.nonce.0 += 1;
+        }
+        assert!(client.add_header(header.clone()).is_err());
+
+        // Nor can a header dodge the work by claiming an easier difficulty
+        header.bits = Difficulty::EASIEST;
+        while !header.bits.is_met_by(&header.hash()) {
+            header.nonce.0 += 1;
+        }
+        assert!(client.add_header(header).is_err());
+        assert_eq!(client.height(), 0);
+    }
+}
@@ -0,0 +1,80 @@
+//! Clocks for consensus timing
+//!
+//! Everything that compares a block timestamp with "now" — `TimeSync`,
+//! `Validator` and, through the times the validator passes it,
+//! `TonceChallenge` — reads the time from a `TimeSource`. Nodes use
+//! `SystemTimeSource`. Tests and simulations use a `MockTimeSource`, which only
+//! moves when told to, so lockouts, tonce windows and timestamp limits can be
+//! exercised deterministically.
+
+use std::sync::{Arc, Mutex};
+use crate::leap_seconds::now_tai_millis;
+
+/// A source of the current time in TAI milliseconds
+pub trait TimeSource: Send + Sync {
+    fn now(&self) -> u128;
+}
+
+/// The system clock, converted to TAI
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemTimeSource;
+
+impl TimeSource for SystemTimeSource {
+    fn now(&self) -> u128 {
+        now_tai_millis() as u128
+    }
+}
+
+/// A clock that only moves when set or advanced
+///
+/// Clones share the same time, so a test can keep one and hand another to the code under test.
+#[derive(Debug, Clone, Default)]
+pub struct MockTimeSource {
+    time: Arc<Mutex<u128>>,
+}
+
+impl MockTimeSource {
+    /// A clock stopped at `time` (TAI milliseconds)
+    pub fn new(time: u128) -> Self {
+        MockTimeSource { time: Arc::new(Mutex::new(time)) }
+    }
+
+    pub fn set(&self, time: u128) {
+        *self.time.lock().unwrap() = time;
+    }
+
+    /// Move the clock forward by `millis`
+    pub fn advance(&self, millis: u128) {
+        *self.time.lock().unwrap() += millis;
+    }
+}
+
+impl TimeSource for MockTimeSource {
+    fn now(&self) -> u128 {
+        *self.time.lock().unwrap()
+    }
+}
+
+/// The clock used when none is given
+pub fn system_clock() -> Arc<dyn TimeSource> {
+    Arc::new(SystemTimeSource)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_time_source() {
+        let clock = MockTimeSource::new(1000);
+        let shared: Arc<dyn TimeSource> = Arc::new(clock.clone());
+        assert_eq!(shared.now(), 1000);
+
+        clock.advance(500);
+        assert_eq!(shared.now(), 1500);
+        clock.set(42);
+        assert_eq!(shared.now(), 42);
+
+        assert!(SystemTimeSource.now() > 1_600_000_000_000);
+    }
+}
@@ -15,9 +15,21 @@
 /// - Duplicate timestamps
 /// - Consensus disagreements between nodes
 
-use chrono::Utc;
+#[cfg(feature = "node")]
+use chrono::DateTime;
 use serde::{Deserialize, Serialize};
-use crate::leap_seconds::{now_tai_millis, utc_to_tai_millis};
+use crate::leap_seconds::now_tai_millis;
+#[cfg(feature = "node")]
+use crate::leap_seconds::utc_to_tai_millis;
+use crate::chain_params::ChainParams;
+#[cfg(feature = "node")]
+use crate::sntp;
+#[cfg(feature = "node")]
+use crate::time_source::SystemTimeSource;
+use crate::time_source::{system_clock, TimeSource};
+use std::collections::VecDeque;
+use tracing::warn;
+use std::sync::Arc;
 
 /// Represents a trusted time response from an external source
 /// Timestamps are in TAI (International Atomic Time) to handle leap seconds properly
@@ -27,31 +39,327 @@ pub struct TrustedTime {
     pub source: String,
 }
 
+/// Offsets further than this from the median are outliers, however tightly the rest agree
+/// (HTTP sources only report whole seconds)
+pub const MIN_OUTLIER_THRESHOLD_MS: i128 = 2_000;
+
+/// Each sync moves the smoothed offset this fraction (1/n) of the way to the new estimate,
+/// so one noisy sync can't jump the validator's clock
+const OFFSET_SMOOTHING_DIVISOR: i128 = 4;
+
+/// Number of recent syncs drift is measured across
+pub const DRIFT_HISTORY_LEN: usize = 24;
+
+/// An external service that reports the current time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ExternalTimeSource {
+    /// worldtimeapi.org's JSON API (whole seconds)
+    WorldTimeApi,
+    /// Cloudflare's `/cdn-cgi/trace` endpoint (milliseconds)
+    Cloudflare,
+    /// The `Date` header of any HTTP server, such as Google's (whole seconds)
+    HttpDate { url: String },
+    /// An NTP server (`host:port`), queried over SNTP (milliseconds)
+    Ntp { server: String },
+}
+
+impl ExternalTimeSource {
+    pub fn name(&self) -> String {
+        match self {
+            ExternalTimeSource::WorldTimeApi => "worldtimeapi.org".to_string(),
+            ExternalTimeSource::Cloudflare => "cloudflare.com".to_string(),
+            ExternalTimeSource::HttpDate { url } => url.clone(),
+            ExternalTimeSource::Ntp { server } => format!("ntp://{}", server),
+        }
+    }
+
+    #[cfg(feature = "node")]
+    /// Half the granularity of the source's readings, added to centre them (milliseconds)
+    fn resolution_correction_ms(&self) -> i64 {
+        match self {
+            ExternalTimeSource::Cloudflare | ExternalTimeSource::Ntp { .. } => 0,
+            ExternalTimeSource::WorldTimeApi | ExternalTimeSource::HttpDate { .. } => 500,
+        }
+    }
+
+    #[cfg(feature = "node")]
+    /// How far `clock` is behind the source (milliseconds)
+    ///
+    /// HTTP sources are assumed to read their clock halfway through the request.
+    pub async fn measure_offset(&self, client: &reqwest::Client, clock: &dyn TimeSource) -> Result<i128, String> {
+        if let ExternalTimeSource::Ntp { server } = self {
+            return sntp::query(server, clock).await.map(|sample| sample.offset_ms);
+        }
+
+        let sent_at = clock.now();
+        let remote = self.fetch(client).await?;
+        let received_at = clock.now();
+        let local = (sent_at + received_at) / 2;
+        Ok(remote.timestamp_ms as i128 - local as i128)
+    }
+
+    #[cfg(feature = "node")]
+    /// Ask the source for the time
+    pub async fn fetch(&self, client: &reqwest::Client) -> Result<TrustedTime, String> {
+        let utc_timestamp_ms = match self {
+            ExternalTimeSource::Ntp { server } => {
+                let clock = SystemTimeSource;
+                let sample = sntp::query(server, &clock).await?;
+                return Ok(TrustedTime {
+                    timestamp_ms: (clock.now() as i128 + sample.offset_ms) as u128,
+                    source: self.name(),
+                });
+            }
+            ExternalTimeSource::WorldTimeApi => {
+                let response = Self::get(client, "http://worldtimeapi.org/api/timezone/Etc/UTC").await?;
+                let json: serde_json::Value = response
+                    .json()
+                    .await
+                    .map_err(|e| format!("Failed to parse response: {}", e))?;
+                parse_worldtimeapi(&json)?
+            }
+            ExternalTimeSource::Cloudflare => {
+                let response = Self::get(client, "https://cloudflare.com/cdn-cgi/trace").await?;
+                let body = response.text().await.map_err(|e| format!("Failed to read response: {}", e))?;
+                parse_cloudflare_trace(&body)?
+            }
+            ExternalTimeSource::HttpDate { url } => {
+                let response = Self::get(client, url).await?;
+                let date = response.headers().get(reqwest::header::DATE)
+                    .ok_or("Missing Date header")?
+                    .to_str()
+                    .map_err(|e| format!("Invalid Date header: {}", e))?;
+                parse_http_date(date)?
+            }
+        };
+
+        // Convert UTC to TAI to handle leap seconds properly
+        let tai_timestamp_ms = utc_to_tai_millis(utc_timestamp_ms + self.resolution_correction_ms());
+
+        Ok(TrustedTime {
+            timestamp_ms: tai_timestamp_ms as u128,
+            source: self.name(),
+        })
+    }
+
+    #[cfg(feature = "node")]
+    async fn get(client: &reqwest::Client, url: &str) -> Result<reqwest::Response, String> {
+        let response = client
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to fetch time: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(format!("HTTP error: {}", response.status()));
+        }
+        Ok(response)
+    }
+}
+
+/// The sources a new `TimeSync` queries
+pub fn default_time_sources() -> Vec<ExternalTimeSource> {
+    vec![
+        ExternalTimeSource::WorldTimeApi,
+        ExternalTimeSource::Cloudflare,
+        ExternalTimeSource::HttpDate { url: "https://www.google.com".to_string() },
+        ExternalTimeSource::Ntp { server: "pool.ntp.org:123".to_string() },
+        ExternalTimeSource::Ntp { server: "time.google.com:123".to_string() },
+    ]
+}
+
+#[cfg(feature = "node")]
+/// UTC milliseconds from a worldtimeapi.org response
+fn parse_worldtimeapi(json: &serde_json::Value) -> Result<i64, String> {
+    // Extract unixtime in seconds (this is UTC)
+    let unixtime_secs = json["unixtime"]
+        .as_i64()
+        .ok_or("Missing unixtime field")?;
+    Ok(unixtime_secs * 1000)
+}
+
+#[cfg(feature = "node")]
+/// UTC milliseconds from the `ts=<seconds>.<fraction>` line of a Cloudflare trace
+fn parse_cloudflare_trace(body: &str) -> Result<i64, String> {
+    let ts = body.lines()
+        .find_map(|line| line.strip_prefix("ts="))
+        .ok_or("Missing ts field")?;
+    let seconds: f64 = ts.trim().parse().map_err(|e| format!("Invalid ts field: {}", e))?;
+    Ok((seconds * 1000.0).round() as i64)
+}
+
+#[cfg(feature = "node")]
+/// UTC milliseconds from an HTTP `Date` header
+fn parse_http_date(date: &str) -> Result<i64, String> {
+    DateTime::parse_from_rfc2822(date)
+        .map(|date| date.timestamp_millis())
+        .map_err(|e| format!("Invalid Date header: {}", e))
+}
+
+/// How one external source has behaved across syncs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceHealth {
+    pub source: String,
+    pub successes: u64,
+    pub failures: u64,
+    /// Offset from the local clock at the last successful query (milliseconds)
+    pub last_offset_ms: Option<i128>,
+    pub last_error: Option<String>,
+    /// Whether the last reading was discarded for disagreeing with the other sources
+    pub outlier: bool,
+}
+
+impl SourceHealth {
+    fn new(source: &ExternalTimeSource) -> Self {
+        SourceHealth {
+            source: source.name(),
+            successes: 0,
+            failures: 0,
+            last_offset_ms: None,
+            last_error: None,
+            outlier: false,
+        }
+    }
+}
+
+/// Outcome of the most recent multi-source sync
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimeSyncStatus {
+    /// Median offset of the agreeing sources from the local clock (milliseconds)
+    pub offset_ms: Option<i128>,
+    /// The true offset is within this many milliseconds of `offset_ms`, by the agreeing sources
+    pub confidence_ms: Option<u128>,
+    /// Offset applied when validating timestamps, smoothed across syncs (milliseconds)
+    pub smoothed_offset_ms: Option<i128>,
+    /// Rate the offset is changing at (see `TimeSync::drift_ms_per_hour`)
+    pub drift_ms_per_hour: Option<f64>,
+    pub last_sync_time: Option<u128>,
+    pub sources: Vec<SourceHealth>,
+}
+
+/// A clock offset agreed by several sources
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OffsetEstimate {
+    pub offset_ms: i128,
+    /// Half-width of the interval around `offset_ms` holding every agreeing reading
+    pub confidence_ms: u128,
+    /// Readings further than this from the median of all readings were discarded
+    pub outlier_threshold_ms: u128,
+    /// Median of all readings, outliers included
+    raw_median_ms: i128,
+}
+
+impl OffsetEstimate {
+    /// Whether a reading was kept rather than discarded as an outlier
+    pub fn agrees(&self, offset_ms: i128) -> bool {
+        (offset_ms - self.raw_median_ms).unsigned_abs() <= self.outlier_threshold_ms
+    }
+}
+
+fn median(sorted: &[i128]) -> i128 {
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2
+    } else {
+        sorted[mid]
+    }
+}
+
+/// Combine offset readings from several sources
+///
+/// Readings more than three median absolute deviations (and at least
+/// `MIN_OUTLIER_THRESHOLD_MS`) from the median are discarded, and the median
+/// of the rest is the estimate. Returns None without any readings.
+pub fn estimate_offset(offsets: &[i128]) -> Option<OffsetEstimate> {
+    if offsets.is_empty() {
+        return None;
+    }
+    let mut sorted = offsets.to_vec();
+    sorted.sort_unstable();
+    let raw_median_ms = median(&sorted);
+
+    let mut deviations: Vec<i128> = sorted.iter().map(|offset| (offset - raw_median_ms).abs()).collect();
+    deviations.sort_unstable();
+    let outlier_threshold_ms = (3 * median(&deviations)).max(MIN_OUTLIER_THRESHOLD_MS) as u128;
+
+    let kept: Vec<i128> = sorted.into_iter()
+        .filter(|offset| (offset - raw_median_ms).unsigned_abs() <= outlier_threshold_ms)
+        .collect();
+    let offset_ms = median(&kept);
+    let confidence_ms = kept.iter().map(|offset| (offset - offset_ms).unsigned_abs()).max().unwrap_or(0);
+
+    Some(OffsetEstimate { offset_ms, confidence_ms, outlier_threshold_ms, raw_median_ms })
+}
+
 /// Time synchronization service for validating timestamps
+#[derive(Clone)]
 pub struct TimeSync {
     /// Maximum allowed deviation from trusted time (in milliseconds)
     pub tolerance_ms: u128,
+    /// Maximum allowed age of a timestamp, to absorb network delays (in milliseconds)
+    pub max_age_ms: u128,
     /// Last known good timestamp from trusted source
     last_sync_time: Option<u128>,
+    /// Where the current time comes from (the system clock unless replaced for tests)
+    clock: Arc<dyn TimeSource>,
+    /// External services queried on sync
+    sources: Vec<ExternalTimeSource>,
+    /// Health of each source, in the same order
+    source_health: Vec<SourceHealth>,
+    /// Offset agreed by the sources at the last successful sync
+    offset_estimate: Option<OffsetEstimate>,
+    /// Running average of the offsets, applied by `validate_timestamp`
+    smoothed_offset_ms: Option<i128>,
+    /// (local time, offset) at recent successful syncs, oldest first
+    offset_history: VecDeque<(u128, i128)>,
 }
 
 impl TimeSync {
     /// Create a new TimeSync instance with default tolerance (500ms)
     pub fn new() -> Self {
-        TimeSync {
-            tolerance_ms: 500,
-            last_sync_time: None,
-        }
+        Self::new_with_params(&ChainParams::mainnet())
     }
 
     /// Create a new TimeSync instance with custom tolerance
     pub fn new_with_tolerance(tolerance_ms: u128) -> Self {
         TimeSync {
             tolerance_ms,
+            max_age_ms: ChainParams::mainnet().max_timestamp_age_ms,
             last_sync_time: None,
+            clock: system_clock(),
+            sources: default_time_sources(),
+            source_health: default_time_sources().iter().map(SourceHealth::new).collect(),
+            offset_estimate: None,
+            smoothed_offset_ms: None,
+            offset_history: VecDeque::new(),
         }
     }
 
+    /// Create a new TimeSync instance using a network's chain parameters
+    pub fn new_with_params(params: &ChainParams) -> Self {
+        TimeSync {
+            tolerance_ms: params.time_tolerance_ms,
+            max_age_ms: params.max_timestamp_age_ms,
+            last_sync_time: None,
+            clock: system_clock(),
+            sources: default_time_sources(),
+            source_health: default_time_sources().iter().map(SourceHealth::new).collect(),
+            offset_estimate: None,
+            smoothed_offset_ms: None,
+            offset_history: VecDeque::new(),
+        }
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn set_time_source(&mut self, clock: Arc<dyn TimeSource>) {
+        self.clock = clock;
+    }
+
+    /// Current time from this instance's clock (TAI milliseconds)
+    pub fn now(&self) -> u128 {
+        self.clock.now()
+    }
+
     /// Get current system time in TAI milliseconds since UNIX epoch
     /// Uses TAI (International Atomic Time) for leap-second-safe timing
     /// Platform-agnostic precision via chrono
@@ -59,79 +367,154 @@ impl TimeSync {
         now_tai_millis() as u128
     }
 
-    /// Sync with external time source
+    #[cfg(feature = "node")]
+    /// Sync with the configured external time sources
     ///
-    /// Queries world time API for accurate external time
-    /// Falls back to system time if external source is unavailable
+    /// Queries every source, discards outliers and takes the median offset
+    /// from the local clock (see `TimeSyncStatus`).
+    /// Falls back to system time if no source is available
     pub async fn sync_with_external_source(&mut self) -> Result<TrustedTime, String> {
-        // Try to get time from external source
-        match Self::fetch_external_time().await {
-            Ok(trusted_time) => {
-                self.last_sync_time = Some(trusted_time.timestamp_ms);
-                Ok(trusted_time)
+        let readings = self.poll_sources().await;
+        Ok(self.apply_readings(&readings))
+    }
+
+    /// Update the offset and source health from readings taken by `poll_sources`
+    ///
+    /// Polling and applying are separate so a caller can query the network
+    /// without holding a lock on the `TimeSync`.
+    pub fn apply_readings(&mut self, readings: &[Result<i128, String>]) -> TrustedTime {
+        let offsets: Vec<i128> = readings.iter().filter_map(|reading| reading.as_ref().ok().copied()).collect();
+        let estimate = estimate_offset(&offsets);
+
+        let mut agreeing = 0;
+        for (health, reading) in self.source_health.iter_mut().zip(readings) {
+            match reading {
+                Ok(offset) => {
+                    health.successes += 1;
+                    health.last_offset_ms = Some(*offset);
+                    health.last_error = None;
+                    health.outlier = estimate.as_ref().is_some_and(|estimate| !estimate.agrees(*offset));
+                    if !health.outlier {
+                        agreeing += 1;
+                    }
+                }
+                Err(e) => {
+                    health.failures += 1;
+                    health.last_error = Some(e.clone());
+                }
+            }
+        }
+
+        let trusted_time = match estimate {
+            Some(estimate) => {
+                self.apply_offset_estimate(estimate);
+                TrustedTime {
+                    timestamp_ms: (self.now() as i128 + estimate.offset_ms) as u128,
+                    source: format!("median of {} sources", agreeing),
+                }
             }
-            Err(e) => {
+            None => {
                 // Fall back to system time
-                eprintln!("Warning: External time sync failed ({}), using system time", e);
-                let timestamp = Self::get_system_time();
-                let trusted_time = TrustedTime {
-                    timestamp_ms: timestamp,
+                warn!("external time sync failed (no source reachable), using system time");
+                TrustedTime {
+                    timestamp_ms: self.now(),
                     source: "system".to_string(),
-                };
-                self.last_sync_time = Some(timestamp);
-                Ok(trusted_time)
+                }
             }
-        }
+        };
+        self.last_sync_time = Some(trusted_time.timestamp_ms);
+        trusted_time
     }
 
-    /// Fetch time from external source (World Time API)
-    async fn fetch_external_time() -> Result<TrustedTime, String> {
-        // Use World Time API as it's simple and doesn't require authentication
-        // Alternative: http://worldtimeapi.org/api/timezone/Etc/UTC
-        let url = "http://worldtimeapi.org/api/timezone/Etc/UTC";
-
-        let client = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_secs(5))
-            .build()
-            .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    /// Record a new offset estimate and fold it into the smoothed offset
+    fn apply_offset_estimate(&mut self, estimate: OffsetEstimate) {
+        self.offset_estimate = Some(estimate);
+        self.offset_history.push_back((self.now(), estimate.offset_ms));
+        while self.offset_history.len() > DRIFT_HISTORY_LEN {
+            self.offset_history.pop_front();
+        }
+        self.smoothed_offset_ms = Some(match self.smoothed_offset_ms {
+            Some(smoothed) => smoothed + (estimate.offset_ms - smoothed) / OFFSET_SMOOTHING_DIVISOR,
+            None => estimate.offset_ms,
+        });
+    }
 
-        let response = client
-            .get(url)
-            .send()
-            .await
-            .map_err(|e| format!("Failed to fetch time: {}", e))?;
+    /// Estimated offset of external time from the local clock (external - local, milliseconds)
+    ///
+    /// Zero until the first successful sync.
+    pub fn current_offset_ms(&self) -> i128 {
+        self.smoothed_offset_ms.unwrap_or(0)
+    }
 
-        if !response.status().is_success() {
-            return Err(format!("HTTP error: {}", response.status()));
+    /// How fast the local clock is drifting from external time (milliseconds per hour)
+    ///
+    /// Measured across the last `DRIFT_HISTORY_LEN` syncs; None until two syncs
+    /// have succeeded at different times. Positive means the local clock is falling behind.
+    pub fn drift_ms_per_hour(&self) -> Option<f64> {
+        let (first_time, first_offset) = self.offset_history.front()?;
+        let (last_time, last_offset) = self.offset_history.back()?;
+        if last_time <= first_time {
+            return None;
         }
+        Some((last_offset - first_offset) as f64 * 3_600_000.0 / (last_time - first_time) as f64)
+    }
 
-        let json: serde_json::Value = response
-            .json()
-            .await
-            .map_err(|e| format!("Failed to parse response: {}", e))?;
+    /// Current time corrected by `current_offset_ms` (TAI milliseconds)
+    pub fn trusted_now(&self) -> u128 {
+        (self.now() as i128 + self.current_offset_ms()).max(0) as u128
+    }
 
-        // Extract unixtime in seconds (this is UTC)
-        let unixtime_secs = json["unixtime"]
-            .as_i64()
-            .ok_or("Missing unixtime field")?;
+    #[cfg(feature = "node")]
+    /// Query every configured source for its offset from the local clock (milliseconds)
+    pub async fn poll_sources(&self) -> Vec<Result<i128, String>> {
+        let client = match reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(5))
+            .build()
+        {
+            Ok(client) => client,
+            Err(e) => return self.sources.iter().map(|_| Err(format!("Failed to create HTTP client: {}", e))).collect(),
+        };
+
+        // query all sources at once, so a slow one doesn't skew the others' timing
+        let polls: Vec<_> = self.sources.iter().cloned().map(|source| {
+            let (client, clock) = (client.clone(), self.clock.clone());
+            tokio::spawn(async move { source.measure_offset(&client, clock.as_ref()).await })
+        }).collect();
+
+        let mut readings = vec![];
+        for poll in polls {
+            readings.push(poll.await.unwrap_or_else(|e| Err(format!("Time query failed: {}", e))));
+        }
+        readings
+    }
 
-        // Convert UTC to milliseconds
-        let utc_timestamp_ms = unixtime_secs * 1000;
+    /// Replace the external sources queried by `sync_with_external_source`
+    pub fn set_sources(&mut self, sources: Vec<ExternalTimeSource>) {
+        self.source_health = sources.iter().map(SourceHealth::new).collect();
+        self.sources = sources;
+    }
 
-        // Convert UTC to TAI to handle leap seconds properly
-        let tai_timestamp_ms = utc_to_tai_millis(utc_timestamp_ms);
+    pub fn sources(&self) -> &[ExternalTimeSource] {
+        &self.sources
+    }
 
-        Ok(TrustedTime {
-            timestamp_ms: tai_timestamp_ms as u128,
-            source: "worldtimeapi.org".to_string(),
-        })
+    /// Result of the last sync and the health of each source
+    pub fn status(&self) -> TimeSyncStatus {
+        TimeSyncStatus {
+            offset_ms: self.offset_estimate.map(|estimate| estimate.offset_ms),
+            smoothed_offset_ms: self.smoothed_offset_ms,
+            drift_ms_per_hour: self.drift_ms_per_hour(),
+            confidence_ms: self.offset_estimate.map(|estimate| estimate.confidence_ms),
+            last_sync_time: self.last_sync_time,
+            sources: self.source_health.clone(),
+        }
     }
 
     /// Validate a timestamp against trusted time
     ///
     /// Returns true if the timestamp is within tolerance of the current trusted time
     pub fn validate_timestamp(&self, timestamp: u128) -> bool {
-        let current_time = Self::get_system_time();
+        let current_time = self.trusted_now();
 
         // Check if timestamp is not too far in the future
         if timestamp > current_time + self.tolerance_ms {
@@ -139,29 +522,29 @@ impl TimeSync {
         }
 
         // Check if timestamp is not too far in the past
-        // Allow up to 5 minutes in the past to account for network delays
-        if timestamp < current_time.saturating_sub(300_000) {
+        // Allow up to max_age_ms (5 minutes on mainnet) to account for network delays
+        if timestamp < current_time.saturating_sub(self.max_age_ms) {
             return false;
         }
 
         true
     }
 
-    /// Calculate the time difference between a timestamp and current time
+    /// Calculate the time difference between a timestamp and current trusted time
     pub fn time_diff(&self, timestamp: u128) -> i128 {
-        let current_time = Self::get_system_time();
+        let current_time = self.trusted_now();
         timestamp as i128 - current_time as i128
     }
 
     /// Check if enough time has passed since a previous timestamp (for hourly checks)
     pub fn has_hour_passed(&self, previous_timestamp: u128) -> bool {
-        let current_time = Self::get_system_time();
+        let current_time = self.trusted_now();
         current_time >= previous_timestamp + 3_600_000 // 1 hour in milliseconds
     }
 
     /// Get seconds remaining until an hour has passed since a timestamp
     pub fn seconds_until_hour_passed(&self, previous_timestamp: u128) -> u64 {
-        let current_time = Self::get_system_time();
+        let current_time = self.trusted_now();
         let target_time = previous_timestamp + 3_600_000;
 
         if current_time >= target_time {
@@ -194,6 +577,14 @@ mod tests {
         assert_eq!(time_sync.tolerance_ms, 1000);
     }
 
+    #[test]
+    fn test_time_sync_with_params() {
+        let params = ChainParams::regtest();
+        let time_sync = TimeSync::new_with_params(&params);
+        assert_eq!(time_sync.tolerance_ms, params.time_tolerance_ms);
+        assert_eq!(time_sync.max_age_ms, params.max_timestamp_age_ms);
+    }
+
     #[test]
     fn test_get_system_time() {
         let time1 = TimeSync::get_system_time();
@@ -227,6 +618,116 @@ mod tests {
         assert!(!time_sync.validate_timestamp(very_old_time));
     }
 
+    #[test]
+    fn test_mock_clock() {
+        use crate::time_source::MockTimeSource;
+
+        let clock = MockTimeSource::new(10_000_000);
+        let mut time_sync = TimeSync::new();
+        time_sync.set_time_source(Arc::new(clock.clone()));
+        assert!(time_sync.validate_timestamp(10_000_000));
+        assert!(!time_sync.validate_timestamp(10_000_000 + 1000));
+
+        // The same timestamp goes stale as the clock moves on
+        clock.advance(400_000);
+        assert!(!time_sync.validate_timestamp(10_000_000));
+        assert_eq!(time_sync.time_diff(10_000_000), -400_000);
+        assert!(!time_sync.has_hour_passed(10_000_000));
+        clock.advance(3_200_000);
+        assert!(time_sync.has_hour_passed(10_000_000));
+    }
+
+    #[test]
+    #[cfg(feature = "node")]
+    fn test_parse_time_responses() {
+        let json = serde_json::json!({ "unixtime": 1_700_000_000 });
+        assert_eq!(parse_worldtimeapi(&json), Ok(1_700_000_000_000));
+        assert!(parse_worldtimeapi(&serde_json::json!({})).is_err());
+
+        let trace = "fl=123\nh=cloudflare.com\nts=1700000000.123\nvisit_scheme=https\n";
+        assert_eq!(parse_cloudflare_trace(trace), Ok(1_700_000_000_123));
+        assert!(parse_cloudflare_trace("fl=123\n").is_err());
+
+        assert_eq!(parse_http_date("Tue, 14 Nov 2023 22:13:20 GMT"), Ok(1_700_000_000_000));
+        assert!(parse_http_date("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_estimate_offset() {
+        assert_eq!(estimate_offset(&[]), None);
+
+        // One source far off is discarded
+        let estimate = estimate_offset(&[120, 80, 100, 60_000]).unwrap();
+        assert_eq!(estimate.offset_ms, 100);
+        assert_eq!(estimate.confidence_ms, 20);
+        assert!(estimate.agrees(80));
+        assert!(!estimate.agrees(60_000));
+
+        // Second-granularity jitter is not an outlier
+        let estimate = estimate_offset(&[0, 900, -700]).unwrap();
+        assert_eq!(estimate.offset_ms, 0);
+        assert_eq!(estimate.confidence_ms, 900);
+    }
+
+    #[test]
+    fn test_default_sources_status() {
+        let mut time_sync = TimeSync::new();
+        let status = time_sync.status();
+        assert_eq!(status.offset_ms, None);
+        assert_eq!(status.sources.len(), default_time_sources().len());
+
+        time_sync.set_sources(vec![ExternalTimeSource::Cloudflare]);
+        assert_eq!(time_sync.status().sources[0].source, "cloudflare.com");
+    }
+
+    #[test]
+    fn test_smoothed_offset_shifts_validation() {
+        use crate::time_source::MockTimeSource;
+
+        let clock = MockTimeSource::new(10_000_000);
+        let mut time_sync = TimeSync::new();
+        time_sync.set_time_source(Arc::new(clock));
+        let ahead = 10_000_000 + 1_000;
+        assert!(!time_sync.validate_timestamp(ahead));
+
+        // Sources agree the local clock is 1.2s slow; the correction is applied gradually
+        let estimate = estimate_offset(&[1_200, 1_200, 1_200]).unwrap();
+        time_sync.apply_offset_estimate(estimate);
+        assert_eq!(time_sync.trusted_now(), 10_001_200);
+        assert!(time_sync.validate_timestamp(ahead));
+
+        assert!(!time_sync.has_hour_passed(10_001_200 - 3_600_000 + 1));
+        assert!(time_sync.has_hour_passed(10_001_200 - 3_600_000));
+        assert_eq!(time_sync.time_diff(ahead), -200);
+
+        time_sync.apply_offset_estimate(estimate_offset(&[0]).unwrap());
+        assert_eq!(time_sync.current_offset_ms(), 900);
+        assert_eq!(time_sync.status().smoothed_offset_ms, Some(900));
+        assert_eq!(time_sync.status().offset_ms, Some(0));
+    }
+
+    #[test]
+    fn test_drift_tracking() {
+        use crate::time_source::MockTimeSource;
+
+        let clock = MockTimeSource::new(10_000_000);
+        let mut time_sync = TimeSync::new();
+        time_sync.set_time_source(Arc::new(clock.clone()));
+        time_sync.set_sources(vec![ExternalTimeSource::Cloudflare, ExternalTimeSource::WorldTimeApi]);
+        assert_eq!(time_sync.drift_ms_per_hour(), None);
+
+        // The local clock loses 30ms over half an hour
+        time_sync.apply_readings(&[Ok(100), Err("timed out".to_string())]);
+        clock.advance(1_800_000);
+        time_sync.apply_readings(&[Ok(130), Ok(130)]);
+        assert_eq!(time_sync.drift_ms_per_hour(), Some(60.0));
+
+        let status = time_sync.status();
+        assert_eq!(status.sources[0].successes, 2);
+        assert_eq!(status.sources[1].failures, 1);
+        assert_eq!(status.sources[1].last_error, None);
+    }
+
     #[test]
     fn test_time_diff() {
         let time_sync = TimeSync::new();
@@ -1,50 +1,137 @@
 /// Tonce (Time-Only-Used-Once) module for Hourcoin
 ///
 /// The tonce system implements a time-based mining challenge that:
-/// 1. Takes the timestamp of the previous accepted block
-/// 2. Hashes it and extracts the least significant 5 bits
-/// 3. For the first 60 seconds, only accepts blocks whose timestamp hash is divisible by this tonce
-/// 4. After 60 seconds, reduces tonce to 1 (accepts any block - becomes a race)
+/// 1. Takes the hash and timestamp of the previous accepted block
+/// 2. Hashes them together and extracts the least significant 5 bits (configurable up to 8)
+/// 3. For the first 60 seconds (configurable), only accepts blocks whose timestamp hash is divisible by this tonce
+/// 4. After the challenge window, reduces tonce to 1 (accepts any block - becomes a race)
 ///
 /// This creates a randomized difficulty for miners during each hour-long mining round.
 
-use crate::u128_bytes;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+use sha2::{Digest, Sha256};
+use crate::{u128_bytes, BlockHash};
+use crate::chain_params::ChainParams;
 
-const TONCE_CHALLENGE_DURATION_MS: u128 = 60_000; // 60 seconds in milliseconds
+/// Widest tonce supported
+///
+/// The tonce is a `u8` on the wire and in block headers, so 8 bits (tonces of
+/// 1 to 255) is as wide as it goes. Wider ranges would need a new tonce type.
+pub const MAX_TONCE_BITS: u8 = 8;
+
+/// Fails unless `tonce_bits` is between 1 and `MAX_TONCE_BITS`
+pub fn check_tonce_bits(tonce_bits: u8) -> Result<(), String> {
+    if tonce_bits == 0 || tonce_bits > MAX_TONCE_BITS {
+        return Err(format!("Tonce bits must be between 1 and {}, not {}", MAX_TONCE_BITS, tonce_bits));
+    }
+    Ok(())
+}
 
 /// Represents a tonce challenge for a mining round
 #[derive(Debug, Clone)]
 pub struct TonceChallenge {
+    /// The previous block's hash (all zeros before the genesis block)
+    pub prev_block_hash: BlockHash,
     /// The previous block's acceptance timestamp
     pub prev_block_timestamp: u128,
-    /// The tonce divisor (1-31, derived from 5 bits)
+    /// The tonce divisor (1 to 2^tonce_bits - 1, e.g. 1-31 for 5 bits)
     pub tonce: u8,
     /// Whether the challenge period has expired
     pub challenge_expired: bool,
+    /// How long the divisibility challenge lasts (milliseconds)
+    pub challenge_duration_ms: u128,
+    /// Number of hash bits the tonce was derived from
+    pub tonce_bits: u8,
 }
 
 impl TonceChallenge {
-    /// Create a new tonce challenge based on the previous block's timestamp
-    pub fn new(prev_block_timestamp: u128) -> Self {
-        let tonce = Self::calculate_tonce(prev_block_timestamp);
+    /// Create a new tonce challenge based on the previous block's hash and timestamp
+    pub fn new(prev_block_hash: &BlockHash, prev_block_timestamp: u128) -> Self {
+        Self::new_with_params(prev_block_hash, prev_block_timestamp, &ChainParams::mainnet())
+    }
+
+    /// Create a new tonce challenge using a network's chain parameters
+    ///
+    /// Takes the challenge duration and tonce bit-width from `params`, so test
+    /// networks can use short windows and busy networks a wider tonce range.
+    /// Panics if the bit-width isn't between 1 and `MAX_TONCE_BITS`;
+    /// `ChainParams::check` catches that up front.
+    pub fn new_with_params(prev_block_hash: &BlockHash, prev_block_timestamp: u128, params: &ChainParams) -> Self {
+        let tonce_bits = params.tonce_bits;
+        if let Err(e) = check_tonce_bits(tonce_bits) {
+            panic!("{}", e);
+        }
+        let tonce = Self::calculate_tonce(prev_block_hash, prev_block_timestamp, tonce_bits);
         TonceChallenge {
+            prev_block_hash: *prev_block_hash,
             prev_block_timestamp,
             tonce,
             challenge_expired: false,
+            challenge_duration_ms: params.tonce_challenge_duration_ms,
+            tonce_bits,
         }
     }
 
-    /// Calculate the tonce value from a timestamp
+    /// Create a tonce challenge from validator-published VRF randomness
+    ///
+    /// The randomness replaces the SHA-256 derivation, so the tonce cannot be
+    /// predicted before the validator publishes its proof for the round.
+    pub fn new_from_randomness(
+        prev_block_hash: &BlockHash,
+        prev_block_timestamp: u128,
+        randomness: &[u8; 32],
+        params: &ChainParams,
+    ) -> Self {
+        let mut challenge = Self::new_with_params(prev_block_hash, prev_block_timestamp, params);
+        challenge.tonce = Self::mask_tonce(randomness, challenge.tonce_bits);
+        challenge
+    }
+
+    /// The challenge as a miner sees it in round info: `tonce`, with `remaining_ms` of the challenge left at `now`
     ///
-    /// Hashes the timestamp and extracts the least significant 5 bits (1-31)
+    /// Miners don't know when the previous block was accepted or how long the
+    /// validator's challenge lasts, so the window is measured from `now`. The
+    /// result checks timestamps exactly as the validator's own challenge does.
+    pub fn for_round(prev_block_hash: &BlockHash, tonce: u8, remaining_ms: u128, now: u128) -> Self {
+        TonceChallenge {
+            prev_block_hash: *prev_block_hash,
+            prev_block_timestamp: now,
+            tonce,
+            challenge_expired: remaining_ms == 0,
+            challenge_duration_ms: remaining_ms,
+            tonce_bits: MAX_TONCE_BITS,
+        }
+    }
+
+    /// Calculate the tonce value from the previous block's hash and timestamp
+    ///
+    /// Hashes prev_hash || timestamp and extracts the least significant `tonce_bits` bits (1-31 for 5 bits).
+    /// Mixing in the block hash stops the winning miner from grinding the next
+    /// tonce through the timestamp alone, since the hash also commits to the nonce.
     /// A tonce of 0 would mean everything is divisible, so we ensure it's at least 1
-    fn calculate_tonce(timestamp: u128) -> u8 {
-        let timestamp_bytes = u128_bytes(&timestamp);
-        let hash = crypto_hash::digest(crypto_hash::Algorithm::SHA256, &timestamp_bytes);
+    fn calculate_tonce(prev_block_hash: &BlockHash, timestamp: u128, tonce_bits: u8) -> u8 {
+        let mut preimage = prev_block_hash.to_vec();
+        preimage.extend(&u128_bytes(&timestamp));
+        let hash = Sha256::digest(&preimage);
 
-        // Get the last byte and extract the least significant 5 bits
-        let last_byte = hash[31];
-        let tonce = last_byte & 0b00011111; // Mask to get last 5 bits
+        Self::mask_tonce(&hash.into(), tonce_bits)
+    }
+
+    /// Extract a tonce from 32 bytes of randomness (a hash or VRF output)
+    ///
+    /// The bit-width may come off the wire, so one out of range is refused.
+    pub fn tonce_from_randomness(randomness: &[u8; 32], tonce_bits: u8) -> Result<u8, String> {
+        check_tonce_bits(tonce_bits)?;
+        Ok(Self::mask_tonce(randomness, tonce_bits))
+    }
+
+    // `tonce_bits` must already be in range
+    fn mask_tonce(randomness: &[u8; 32], tonce_bits: u8) -> u8 {
+        // Get the last byte and extract the least significant bits
+        let last_byte = randomness[31];
+        let mask = ((1u16 << tonce_bits) - 1) as u8;
+        let tonce = last_byte & mask;
 
         // Ensure tonce is at least 1 (0 would make everything pass)
         if tonce == 0 {
@@ -56,17 +143,17 @@ impl TonceChallenge {
 
     /// Check if a timestamp passes the tonce challenge
     ///
-    /// For the first 60 seconds after the previous block:
+    /// For the challenge duration (60 seconds on mainnet) after the previous block:
     /// - Calculate the hash of the proposed timestamp
     /// - Check if it's divisible by the tonce
     ///
-    /// After 60 seconds:
+    /// After the challenge duration:
     /// - Any timestamp passes (race to submit)
     pub fn validate_timestamp(&mut self, timestamp: u128, current_time: u128) -> bool {
         // Check if challenge period has expired
         let time_since_prev_block = current_time.saturating_sub(self.prev_block_timestamp);
 
-        if time_since_prev_block >= TONCE_CHALLENGE_DURATION_MS {
+        if time_since_prev_block >= self.challenge_duration_ms {
             self.challenge_expired = true;
             self.tonce = 1; // Reduce to 1 - race condition
             return true; // Accept any timestamp after challenge period
@@ -78,23 +165,17 @@ impl TonceChallenge {
 
     /// Check if a timestamp hash is divisible by the tonce
     fn is_timestamp_divisible(&self, timestamp: u128) -> bool {
-        let timestamp_bytes = u128_bytes(&timestamp);
-        let hash = crypto_hash::digest(crypto_hash::Algorithm::SHA256, &timestamp_bytes);
-
-        // Convert last 4 bytes to u32 for divisibility check
-        let hash_value = u32::from_be_bytes([hash[28], hash[29], hash[30], hash[31]]);
-
-        hash_value % (self.tonce as u32) == 0
+        passes_tonce(self.tonce, timestamp)
     }
 
     /// Get the time remaining in the challenge period (in seconds)
     pub fn seconds_remaining(&self, current_time: u128) -> u64 {
         let time_since_prev_block = current_time.saturating_sub(self.prev_block_timestamp);
 
-        if time_since_prev_block >= TONCE_CHALLENGE_DURATION_MS {
+        if time_since_prev_block >= self.challenge_duration_ms {
             0
         } else {
-            ((TONCE_CHALLENGE_DURATION_MS - time_since_prev_block) / 1000) as u64
+            ((self.challenge_duration_ms - time_since_prev_block) / 1000) as u64
         }
     }
 
@@ -106,41 +187,166 @@ impl TonceChallenge {
     /// Check if the challenge period has expired
     pub fn is_expired(&self, current_time: u128) -> bool {
         let time_since_prev_block = current_time.saturating_sub(self.prev_block_timestamp);
-        time_since_prev_block >= TONCE_CHALLENGE_DURATION_MS
+        time_since_prev_block >= self.challenge_duration_ms
     }
 }
 
+/// Check if a timestamp's hash is divisible by `tonce`
+///
+/// Hashes the timestamp and takes the last 4 bytes as a big-endian u32. A
+/// tonce of 0 or 1 accepts every timestamp.
+pub fn passes_tonce(tonce: u8, timestamp: u128) -> bool {
+    if tonce <= 1 {
+        return true;
+    }
+    let timestamp_bytes = u128_bytes(&timestamp);
+    let hash = Sha256::digest(timestamp_bytes);
+    let hash_value = u32::from_be_bytes([hash[28], hash[29], hash[30], hash[31]]);
+
+    hash_value % (tonce as u32) == 0
+}
+
 /// Helper to find a timestamp that satisfies the tonce challenge
 ///
 /// Used by miners to find valid timestamps for block submission
 pub fn find_valid_timestamp(tonce: u8, start_time: u128, max_attempts: u32) -> Option<u128> {
-    if tonce == 0 || tonce == 1 {
-        return Some(start_time); // Any timestamp works
+    (0..max_attempts)
+        .map(|i| start_time + i as u128)
+        .find(|candidate| passes_tonce(tonce, *candidate))
+}
+
+/// `find_valid_timestamp` spread over `num_threads` threads
+///
+/// Thread `t` checks every `num_threads`th timestamp from `start_time + t`.
+/// The threads share the earliest offset found so far and each stops once it
+/// passes it, so the result is the same timestamp the serial search returns.
+pub fn find_valid_timestamp_parallel(tonce: u8, start_time: u128, max_attempts: u32, num_threads: usize) -> Option<u128> {
+    let num_threads = num_threads.clamp(1, max_attempts.max(1) as usize) as u32;
+    if tonce <= 1 || num_threads == 1 {
+        return find_valid_timestamp(tonce, start_time, max_attempts);
     }
+    let first = AtomicU32::new(max_attempts);
+
+    thread::scope(|scope| {
+        for t in 0..num_threads {
+            let first = &first;
+            scope.spawn(move || {
+                for offset in (t..max_attempts).step_by(num_threads as usize) {
+                    if offset >= first.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    if passes_tonce(tonce, start_time + offset as u128) {
+                        first.fetch_min(offset, Ordering::Relaxed);
+                        break;
+                    }
+                }
+            });
+        }
+    });
 
-    for i in 0..max_attempts {
-        let candidate_timestamp = start_time + i as u128;
-        let timestamp_bytes = u128_bytes(&candidate_timestamp);
-        let hash = crypto_hash::digest(crypto_hash::Algorithm::SHA256, &timestamp_bytes);
-        let hash_value = u32::from_be_bytes([hash[28], hash[29], hash[30], hash[31]]);
+    let first = first.into_inner();
+    (first < max_attempts).then(|| start_time + first as u128)
+}
 
-        if hash_value % (tonce as u32) == 0 {
-            return Some(candidate_timestamp);
+/// Every timestamp from `start_time` on that passes `tonce`, in order, found as they are asked for
+///
+/// Lets a caller take as many candidates as it needs, or stop at a deadline,
+/// without fixing a batch size or search span up front.
+pub fn valid_timestamps(tonce: u8, start_time: u128) -> impl Iterator<Item = u128> {
+    (start_time..u128::MAX).filter(move |candidate| passes_tonce(tonce, *candidate))
+}
+
+/// How far a block timestamp may stray from the validator's clock and still be accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimestampWindow {
+    /// How far ahead of the validator's clock (milliseconds)
+    pub tolerance_ms: u128,
+    /// How far behind it (milliseconds)
+    pub max_age_ms: u128,
+}
+
+impl TimestampWindow {
+    /// The window a validator enforcing `params` accepts
+    pub fn from_params(params: &ChainParams) -> Self {
+        TimestampWindow {
+            tolerance_ms: params.time_tolerance_ms,
+            max_age_ms: params.max_timestamp_age_ms,
         }
     }
 
-    None // Failed to find valid timestamp
+    /// Whether a validator whose clock reads `now` accepts `timestamp`
+    pub fn accepts(&self, timestamp: u128, now: u128) -> bool {
+        timestamp <= now + self.tolerance_ms && timestamp >= now.saturating_sub(self.max_age_ms)
+    }
+}
+
+impl Default for TimestampWindow {
+    /// Mainnet's window, the strictest of the presets
+    fn default() -> Self {
+        Self::from_params(&ChainParams::mainnet())
+    }
+}
+
+/// A batch of timestamps that pass a tonce, found ahead of time
+///
+/// A block's timestamp is fixed before its nonce is searched, so a miner that
+/// takes the first valid timestamp from now submits a block whose timestamp
+/// has aged by the whole mining time. Searching a batch up front lets it pick
+/// the candidate closest to when it expects to submit instead, and check that
+/// candidate again once the block is mined.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimestampCandidates {
+    tonce: u8,
+    window: TimestampWindow,
+    /// Ascending
+    timestamps: Vec<u128>,
+}
+
+impl TimestampCandidates {
+    /// Up to `max_candidates` timestamps passing `tonce`, from `start_time` over the next `span_ms`
+    pub fn search(tonce: u8, start_time: u128, span_ms: u128, max_candidates: usize, window: TimestampWindow) -> Self {
+        let end = start_time.saturating_add(span_ms);
+        let timestamps = valid_timestamps(tonce, start_time)
+            .take_while(|timestamp| *timestamp < end)
+            .take(max_candidates)
+            .collect();
+        TimestampCandidates { tonce, window, timestamps }
+    }
+
+    pub fn timestamps(&self) -> &[u128] {
+        &self.timestamps
+    }
+
+    /// The candidate closest to `submit_at` that the validator will accept then
+    ///
+    /// Falls back to the latest candidate when none will be, which at least
+    /// keeps the block as young as the batch allows.
+    pub fn best_for(&self, submit_at: u128) -> Option<u128> {
+        self.timestamps.iter()
+            .filter(|timestamp| self.window.accepts(**timestamp, submit_at))
+            .min_by_key(|timestamp| timestamp.abs_diff(submit_at))
+            .or(self.timestamps.last())
+            .copied()
+    }
+
+    /// Check a chosen timestamp again just before submitting, against the validator's clock reading `now`
+    pub fn revalidate(&self, timestamp: u128, now: u128) -> bool {
+        passes_tonce(self.tonce, timestamp) && self.window.accepts(timestamp, now)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const GENESIS_PREV_HASH: BlockHash = BlockHash::ZERO;
+
     #[test]
     fn test_tonce_creation() {
         let timestamp = 1000000;
-        let challenge = TonceChallenge::new(timestamp);
+        let challenge = TonceChallenge::new(&GENESIS_PREV_HASH, timestamp);
 
+        assert_eq!(challenge.prev_block_hash, GENESIS_PREV_HASH);
         assert_eq!(challenge.prev_block_timestamp, timestamp);
         assert!(challenge.tonce >= 1 && challenge.tonce <= 31);
         assert!(!challenge.challenge_expired);
@@ -151,15 +357,47 @@ mod tests {
         // Test multiple timestamps to ensure tonce is always in valid range
         for i in 0..100 {
             let timestamp = 1000000 + i * 1000;
-            let challenge = TonceChallenge::new(timestamp);
+            let challenge = TonceChallenge::new(&GENESIS_PREV_HASH, timestamp);
             assert!(challenge.tonce >= 1 && challenge.tonce <= 31);
         }
     }
 
+    #[test]
+    fn test_tonce_bit_width_from_params() {
+        let mut params = ChainParams::mainnet();
+        params.tonce_bits = 8;
+
+        let mut saw_wide_tonce = false;
+        for i in 0..100 {
+            let challenge = TonceChallenge::new_with_params(&GENESIS_PREV_HASH, 1000000 + i * 1000, &params);
+            assert_eq!(challenge.tonce_bits, 8);
+            assert!(challenge.tonce >= 1);
+            saw_wide_tonce |= challenge.tonce > 31;
+        }
+        assert!(saw_wide_tonce);
+
+        // Out-of-range widths are refused, not clamped
+        let randomness = [0xFF; 32];
+        assert_eq!(TonceChallenge::tonce_from_randomness(&randomness, MAX_TONCE_BITS), Ok(255));
+        for tonce_bits in [0, MAX_TONCE_BITS + 1, 200] {
+            assert!(TonceChallenge::tonce_from_randomness(&randomness, tonce_bits).is_err());
+            params.tonce_bits = tonce_bits;
+            assert!(params.check().is_err());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Tonce bits must be between 1 and 8")]
+    fn test_tonce_bits_out_of_range() {
+        let mut params = ChainParams::mainnet();
+        params.tonce_bits = 9;
+        TonceChallenge::new_with_params(&GENESIS_PREV_HASH, 1000000, &params);
+    }
+
     #[test]
     fn test_challenge_expiration() {
         let prev_timestamp = 1000000;
-        let mut challenge = TonceChallenge::new(prev_timestamp);
+        let mut challenge = TonceChallenge::new(&GENESIS_PREV_HASH, prev_timestamp);
 
         // Within challenge period (30 seconds later)
         let current_time = prev_timestamp + 30_000;
@@ -173,7 +411,7 @@ mod tests {
     #[test]
     fn test_validation_after_expiration() {
         let prev_timestamp = 1000000;
-        let mut challenge = TonceChallenge::new(prev_timestamp);
+        let mut challenge = TonceChallenge::new(&GENESIS_PREV_HASH, prev_timestamp);
 
         // After 60 seconds, any timestamp should pass
         let current_time = prev_timestamp + 61_000;
@@ -184,10 +422,20 @@ mod tests {
         assert_eq!(challenge.tonce, 1);
     }
 
+    #[test]
+    fn test_challenge_duration_from_params() {
+        let prev_timestamp = 1000000;
+        let challenge = TonceChallenge::new_with_params(&GENESIS_PREV_HASH, prev_timestamp, &ChainParams::regtest());
+
+        assert_eq!(challenge.challenge_duration_ms, ChainParams::regtest().tonce_challenge_duration_ms);
+        assert!(challenge.is_expired(prev_timestamp + ChainParams::regtest().tonce_challenge_duration_ms));
+        assert!(!TonceChallenge::new(&GENESIS_PREV_HASH, prev_timestamp).is_expired(prev_timestamp + 30_000));
+    }
+
     #[test]
     fn test_seconds_remaining() {
         let prev_timestamp = 1000000;
-        let challenge = TonceChallenge::new(prev_timestamp);
+        let challenge = TonceChallenge::new(&GENESIS_PREV_HASH, prev_timestamp);
 
         // 30 seconds after previous block
         let current_time = prev_timestamp + 30_000;
@@ -213,16 +461,64 @@ mod tests {
         // If found, verify it actually passes divisibility
         if let Some(ts) = result {
             let timestamp_bytes = u128_bytes(&ts);
-            let hash = crypto_hash::digest(crypto_hash::Algorithm::SHA256, &timestamp_bytes);
+            let hash = Sha256::digest(timestamp_bytes);
             let hash_value = u32::from_be_bytes([hash[28], hash[29], hash[30], hash[31]]);
             assert_eq!(hash_value % 5, 0);
         }
     }
 
+    #[test]
+    fn test_parallel_and_streaming_search() {
+        for tonce in [1, 7, 31, 255] {
+            let serial = find_valid_timestamp(tonce, 1_000_000, 100_000);
+            assert!(serial.is_some());
+            for threads in [1, 3, 8] {
+                assert_eq!(find_valid_timestamp_parallel(tonce, 1_000_000, 100_000, threads), serial);
+            }
+            assert_eq!(valid_timestamps(tonce, 1_000_000).next(), serial);
+        }
+
+        // Nothing found within the attempts, whatever the thread count
+        let first = find_valid_timestamp(255, 1_000_000, 100_000).unwrap();
+        let attempts = (first - 1_000_000) as u32;
+        assert_eq!(find_valid_timestamp_parallel(255, 1_000_000, attempts, 4), None);
+        assert_eq!(find_valid_timestamp_parallel(255, 1_000_000, 0, 4), None);
+
+        let streamed: Vec<u128> = valid_timestamps(31, 1_000_000).take(5).collect();
+        assert!(streamed.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(find_valid_timestamp(31, streamed[2] + 1, 100_000), Some(streamed[3]));
+    }
+
+    #[test]
+    fn test_timestamp_candidates() {
+        let window = TimestampWindow { tolerance_ms: 500, max_age_ms: 2_000 };
+        let candidates = TimestampCandidates::search(7, 1_000_000, 10_000, 8, window);
+        let timestamps = candidates.timestamps();
+        assert_eq!(timestamps.len(), 8);
+        assert!(timestamps.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(timestamps.iter().all(|timestamp| passes_tonce(7, *timestamp)));
+        assert_eq!(timestamps[0], find_valid_timestamp(7, 1_000_000, 10_000).unwrap());
+
+        // The pick tracks the expected submission time, within the window
+        let submit_at = timestamps[4] + 1;
+        assert_eq!(candidates.best_for(submit_at), Some(timestamps[4]));
+        assert_eq!(candidates.best_for(timestamps[0]), Some(timestamps[0]));
+        assert_eq!(candidates.best_for(u128::MAX / 2), Some(timestamps[7]));
+
+        // A timestamp is rechecked against the clock at submission
+        assert!(candidates.revalidate(timestamps[4], submit_at));
+        assert!(!candidates.revalidate(timestamps[4], timestamps[4] + 2_001));
+        assert!(!candidates.revalidate(timestamps[4], timestamps[4] - 501));
+        let failing = (1_000_000..).find(|timestamp| !passes_tonce(7, *timestamp)).unwrap();
+        assert!(!candidates.revalidate(failing, failing));
+
+        assert!(TimestampCandidates::search(7, 1_000_000, 0, 8, window).best_for(1_000_000).is_none());
+    }
+
     #[test]
     fn test_is_timestamp_divisible() {
         let prev_timestamp = 1000000;
-        let challenge = TonceChallenge::new(prev_timestamp);
+        let challenge = TonceChallenge::new(&GENESIS_PREV_HASH, prev_timestamp);
 
         // Find a valid timestamp for this challenge
         if let Some(valid_ts) = find_valid_timestamp(challenge.tonce, 1000000, 10000) {
@@ -232,15 +528,15 @@ mod tests {
 
     #[test]
     fn test_different_timestamps_different_tonces() {
-        let challenge1 = TonceChallenge::new(1000000);
-        let challenge2 = TonceChallenge::new(2000000);
+        let challenge1 = TonceChallenge::new(&GENESIS_PREV_HASH, 1000000);
+        let challenge2 = TonceChallenge::new(&GENESIS_PREV_HASH, 2000000);
 
         // Different timestamps should (usually) produce different tonces
         // Note: There's a small chance they could be the same, so we test multiple
         let mut different_found = false;
         for i in 0..10 {
-            let c1 = TonceChallenge::new(1000000 + i * 1000);
-            let c2 = TonceChallenge::new(2000000 + i * 1000);
+            let c1 = TonceChallenge::new(&GENESIS_PREV_HASH, 1000000 + i * 1000);
+            let c2 = TonceChallenge::new(&GENESIS_PREV_HASH, 2000000 + i * 1000);
             if c1.tonce != c2.tonce {
                 different_found = true;
                 break;
@@ -248,4 +544,30 @@ mod tests {
         }
         assert!(different_found);
     }
+
+    #[test]
+    fn test_tonce_from_randomness() {
+        let params = ChainParams::mainnet();
+        let mut randomness = [0u8; 32];
+
+        randomness[31] = 0b1110_0111;
+        let challenge = TonceChallenge::new_from_randomness(&GENESIS_PREV_HASH, 1000000, &randomness, &params);
+        assert_eq!(challenge.tonce, 0b0_0111);
+
+        // Zero still maps to 1
+        randomness[31] = 0b1110_0000;
+        let challenge = TonceChallenge::new_from_randomness(&GENESIS_PREV_HASH, 1000000, &randomness, &params);
+        assert_eq!(challenge.tonce, 1);
+    }
+
+    #[test]
+    fn test_prev_hash_changes_tonce() {
+        // Same timestamp with different previous hashes should (usually) give different tonces
+        let timestamp = 1000000;
+        let base = TonceChallenge::new(&GENESIS_PREV_HASH, timestamp);
+        let different_found = (1..=10u8)
+            .map(|i| TonceChallenge::new(&BlockHash::from_bytes([i; 32]), timestamp))
+            .any(|c| c.tonce != base.tonce);
+        assert!(different_found);
+    }
 }
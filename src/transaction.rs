@@ -1,21 +1,143 @@
 use super::*;
+use crate::canonical::{CanonicalDeserialize, CanonicalReader, CanonicalSerialize};
+use crate::chain_params::ChainParams;
+use crate::channels::{ChannelTerms, SignedState, UnilateralClose};
+use crate::htlc::HtlcContract;
+use crate::multisig::MultisigPolicy;
 use std::collections::HashSet;
 
+// signing context for signatures authorizing a transaction's inputs
+pub const TX_SIGNING_CONTEXT: &[u8] = b"hourcoin-transaction";
+
 #[derive(Clone)]
 pub struct Output {
 	pub to_addr: Address,
 	pub value: f64,
 	pub timestamp: u128,
+	pub lock_until: Option<u128>, // can't be spent by a block timestamped before this
+}
+
+impl Output {
+	// whether a block with this timestamp may spend the output
+	pub fn is_spendable_at (&self, block_timestamp: u128) -> bool {
+		self.lock_until.is_none_or(|lock_until| block_timestamp >= lock_until)
+	}
+
+	// value left at `time` after demurrage, decaying by `rate_per_hour` for every hour since the output's timestamp
+	pub fn spendable_value_at (&self, time: u128, rate_per_hour: f64) -> f64 {
+		if rate_per_hour <= 0.0 || time <= self.timestamp {
+			return self.value;
+		}
+		let hours = (time - self.timestamp) as f64 / 3_600_000.0;
+		self.value * (1.0 - rate_per_hour.min(1.0)).powf(hours)
+	}
+}
+
+impl CanonicalSerialize for Output {
+	fn write_canonical (&self, out: &mut Vec<u8>) {
+		self.to_addr.write_canonical(out);
+		self.value.write_canonical(out);
+		self.timestamp.write_canonical(out);
+		self.lock_until.write_canonical(out);
+	}
+}
+
+impl CanonicalDeserialize for Output {
+	fn read_canonical (reader: &mut CanonicalReader) -> Result<Self, String> {
+		Ok(Output {
+			to_addr: String::read_canonical(reader)?,
+			value: f64::read_canonical(reader)?,
+			timestamp: u128::read_canonical(reader)?,
+			lock_until: Option::read_canonical(reader)?,
+		})
+	}
 }
 
 impl Hashable for Output {
+	type Hash = BlockHash;
+
 	fn bytes (&self) -> Vec<u8> {
-		let mut bytes = vec![];
-		bytes.extend(self.to_addr.as_bytes());
-		bytes.extend(&self.value.to_be_bytes());
-		bytes.extend(&self.timestamp.to_be_bytes());
+		self.canonical_bytes()
+	}
+}
+
+// data a transaction reveals to satisfy the spending conditions of its inputs
+#[derive(Clone, Debug, PartialEq)]
+pub enum Witness {
+	// opens an HTLC address: the contract terms, plus the preimage when redeeming (none for a refund)
+	Htlc { contract: HtlcContract, preimage: Option<Vec<u8>> },
+	// closes a payment channel: its terms and a state both parties signed, plus who closed it alone (none if cooperative)
+	ChannelClose { terms: ChannelTerms, state: SignedState, unilateral: Option<UnilateralClose> },
+	// opens a key address: the public key it hashes to and a signature over the transaction's signing hash
+	Signature { public_key: Vec<u8>, signature: Vec<u8> },
+	// opens a multisig address: the policy and one signature slot per cosigner key (none if that cosigner did not sign)
+	Multisig { policy: MultisigPolicy, signatures: Vec<Option<Vec<u8>>> },
+	// coinbase only: a counter the miner rolls to get fresh block hashes once the header nonce runs out
+	ExtraNonce(u64),
+	// coinbase only: free text stamped into the block, such as a genesis headline
+	Message(String),
+}
+
+impl CanonicalSerialize for Witness {
+	fn write_canonical (&self, out: &mut Vec<u8>) {
+		match self {
+			Witness::Htlc { contract, preimage } => {
+				out.push(0x01);
+				contract.write_canonical(out);
+				preimage.write_canonical(out);
+			}
+			Witness::ChannelClose { terms, state, unilateral } => {
+				out.push(0x02);
+				terms.write_canonical(out);
+				state.write_canonical(out);
+				unilateral.write_canonical(out);
+			}
+			Witness::Signature { public_key, signature } => {
+				out.push(0x03);
+				public_key.write_canonical(out);
+				signature.write_canonical(out);
+			}
+			Witness::Multisig { policy, signatures } => {
+				out.push(0x04);
+				policy.write_canonical(out);
+				signatures.write_canonical(out);
+			}
+			Witness::ExtraNonce(extra_nonce) => {
+				out.push(0x05);
+				extra_nonce.write_canonical(out);
+			}
+			Witness::Message(text) => {
+				out.push(0x06);
+				text.write_canonical(out);
+			}
+		}
+	}
+}
 
-		bytes
+impl CanonicalDeserialize for Witness {
+	fn read_canonical (reader: &mut CanonicalReader) -> Result<Self, String> {
+		match reader.tag()? {
+			0x01 => Ok(Witness::Htlc {
+				contract: HtlcContract::read_canonical(reader)?,
+				preimage: Option::read_canonical(reader)?,
+			}),
+			0x02 => Ok(Witness::ChannelClose {
+				terms: ChannelTerms::read_canonical(reader)?,
+				state: SignedState::read_canonical(reader)?,
+				unilateral: Option::read_canonical(reader)?,
+			}),
+			0x03 => Ok(Witness::Signature {
+				public_key: Vec::read_canonical(reader)?,
+				signature: Vec::read_canonical(reader)?,
+			}),
+			0x04 => Ok(Witness::Multisig {
+				policy: MultisigPolicy::read_canonical(reader)?,
+				signatures: Vec::read_canonical(reader)?,
+			}),
+			0x05 => Ok(Witness::ExtraNonce(u64::read_canonical(reader)?)),
+			0x06 => Ok(Witness::Message(String::read_canonical(reader)?)),
+			tag => Err(format!("Unknown witness tag {}", tag)),
+		}
 	}
 }
 
@@ -23,6 +145,7 @@ impl Hashable for Output {
 pub struct Transaction {
 	pub inputs: Vec<Output>,
 	pub outputs: Vec<Output>,
+	pub witnesses: Vec<Witness>,
 }
 
 impl Transaction {
@@ -32,6 +155,13 @@ impl Transaction {
 			.sum()
 	}
 
+	// input value remaining at `time` once demurrage is applied
+	pub fn input_sum_at (&self, time: u128, rate_per_hour: f64) -> f64 {
+		self.inputs.iter()
+			.map(|input| input.spendable_value_at(time, rate_per_hour))
+			.sum()
+	}
+
 	pub fn output_sum (&self) -> f64 {
 		self.outputs.iter()
 			.map(|output| output.value)
@@ -50,23 +180,60 @@ impl Transaction {
 			.collect::<HashSet<BlockHash>>()
 	}
 
+	// hash of the inputs and outputs that signatures commit to (witnesses can't sign themselves)
+	pub fn signing_hash (&self) -> BlockHash {
+		let mut bytes = self.inputs.canonical_bytes();
+		self.outputs.write_canonical(&mut bytes);
+		BlockHash::digest(&bytes)
+	}
+
 	pub fn is_coinbase (&self) -> bool {
-		(self.inputs.len() == 0) && (self.output_sum() == 2.0)
+		self.is_coinbase_with_value(ChainParams::mainnet().coinbase_value)
+	}
+
+	pub fn is_coinbase_with_value (&self, coinbase_value: f64) -> bool {
+		(self.inputs.len() == 0) && (self.output_sum() == coinbase_value)
+	}
+
+	pub fn extra_nonce (&self) -> Option<u64> {
+		self.witnesses.iter().find_map(|witness| match witness {
+			Witness::ExtraNonce(extra_nonce) => Some(*extra_nonce),
+			_ => None,
+		})
+	}
+
+	// replaces the extra-nonce witness, or adds one
+	pub fn set_extra_nonce (&mut self, extra_nonce: u64) {
+		match self.witnesses.iter_mut().find(|witness| matches!(witness, Witness::ExtraNonce(_))) {
+			Some(witness) => *witness = Witness::ExtraNonce(extra_nonce),
+			None => self.witnesses.push(Witness::ExtraNonce(extra_nonce)),
+		}
 	}
 }
 
-impl Hashable for Transaction {
-	fn bytes (&self) -> Vec<u8> {
-		let mut bytes = vec![];
+impl CanonicalSerialize for Transaction {
+	fn write_canonical (&self, out: &mut Vec<u8>) {
+		self.inputs.write_canonical(out);
+		self.outputs.write_canonical(out);
+		self.witnesses.write_canonical(out);
+	}
+}
 
-		bytes.extend(self.inputs.iter()
-								.flat_map(|input| input.bytes())
-								.collect::<Vec<u8>>());
-		bytes.extend(self.outputs.iter()
-								.flat_map(|output| output.bytes())
-								.collect::<Vec<u8>>());
+impl CanonicalDeserialize for Transaction {
+	fn read_canonical (reader: &mut CanonicalReader) -> Result<Self, String> {
+		Ok(Transaction {
+			inputs: Vec::read_canonical(reader)?,
+			outputs: Vec::read_canonical(reader)?,
+			witnesses: Vec::read_canonical(reader)?,
+		})
+	}
+}
+
+impl Hashable for Transaction {
+	type Hash = Txid;
 
-		bytes
+	fn bytes (&self) -> Vec<u8> {
+		self.canonical_bytes()
 	}
 }
 
@@ -80,6 +247,7 @@ mod tests {
 			to_addr: "Alice".to_owned(),
 			value: 10.0,
 			timestamp: 1000,
+			lock_until: None,
 		};
 		assert_eq!(output.to_addr, "Alice");
 		assert_eq!(output.value, 10.0);
@@ -92,16 +260,19 @@ mod tests {
 			to_addr: "Alice".to_owned(),
 			value: 10.0,
 			timestamp: 1000,
+			lock_until: None,
 		};
 		let output2 = Output {
 			to_addr: "Alice".to_owned(),
 			value: 10.0,
 			timestamp: 1000,
+			lock_until: None,
 		};
 		let output3 = Output {
 			to_addr: "Bob".to_owned(),
 			value: 10.0,
 			timestamp: 1000,
+			lock_until: None,
 		};
 
 		// Same outputs should produce same hash
@@ -119,7 +290,9 @@ mod tests {
 				to_addr: "Miner".to_owned(),
 				value: 2.0,
 				timestamp: 1000,
+				lock_until: None,
 			}],
+			witnesses: vec![],
 		};
 
 		assert!(coinbase.is_coinbase());
@@ -134,19 +307,23 @@ mod tests {
 				to_addr: "Alice".to_owned(),
 				value: 10.0,
 				timestamp: 1000,
+				lock_until: None,
 			}],
 			outputs: vec![
 				Output {
 					to_addr: "Bob".to_owned(),
 					value: 7.0,
 					timestamp: 2000,
+					lock_until: None,
 				},
 				Output {
 					to_addr: "Alice".to_owned(),
 					value: 2.5,
 					timestamp: 2000,
+					lock_until: None,
 				},
 			],
+			witnesses: vec![],
 		};
 
 		assert!(!transaction.is_coinbase());
@@ -160,16 +337,19 @@ mod tests {
 			to_addr: "Alice".to_owned(),
 			value: 10.0,
 			timestamp: 1000,
+			lock_until: None,
 		};
 		let input2 = Output {
 			to_addr: "Bob".to_owned(),
 			value: 5.0,
 			timestamp: 1000,
+			lock_until: None,
 		};
 
 		let transaction = Transaction {
 			inputs: vec![input1.clone(), input2.clone()],
 			outputs: vec![],
+			witnesses: vec![],
 		};
 
 		let input_hashes = transaction.input_hashes();
@@ -184,16 +364,19 @@ mod tests {
 			to_addr: "Alice".to_owned(),
 			value: 10.0,
 			timestamp: 2000,
+			lock_until: None,
 		};
 		let output2 = Output {
 			to_addr: "Bob".to_owned(),
 			value: 5.0,
 			timestamp: 2000,
+			lock_until: None,
 		};
 
 		let transaction = Transaction {
 			inputs: vec![],
 			outputs: vec![output1.clone(), output2.clone()],
+			witnesses: vec![],
 		};
 
 		let output_hashes = transaction.output_hashes();
@@ -210,7 +393,9 @@ mod tests {
 				to_addr: "Miner".to_owned(),
 				value: 5.0, // Wrong value - should be 2.0
 				timestamp: 1000,
+				lock_until: None,
 			}],
+			witnesses: vec![],
 		};
 
 		assert!(!transaction.is_coinbase());
@@ -223,17 +408,65 @@ mod tests {
 				to_addr: "Someone".to_owned(),
 				value: 2.0,
 				timestamp: 1000,
+				lock_until: None,
 			}],
 			outputs: vec![Output {
 				to_addr: "Miner".to_owned(),
 				value: 2.0,
 				timestamp: 1000,
+				lock_until: None,
 			}],
+			witnesses: vec![],
 		};
 
 		assert!(!transaction.is_coinbase());
 	}
 
+	#[test]
+	fn test_time_locked_output() {
+		let mut output = Output {
+			to_addr: "Alice".to_owned(),
+			value: 10.0,
+			timestamp: 1000,
+			lock_until: None,
+		};
+		let unlocked_hash = output.hash();
+		assert!(output.is_spendable_at(0));
+
+		output.lock_until = Some(5000);
+		assert!(!output.is_spendable_at(4999));
+		assert!(output.is_spendable_at(5000));
+
+		// The lock is committed to by the output hash
+		assert_ne!(output.hash(), unlocked_hash);
+	}
+
+	#[test]
+	fn test_demurrage() {
+		let output = Output {
+			to_addr: "Alice".to_owned(),
+			value: 10.0,
+			timestamp: 1000,
+			lock_until: None,
+		};
+
+		// Disabled, or no time elapsed: full value
+		assert_eq!(output.spendable_value_at(1000 + 7_200_000, 0.0), 10.0);
+		assert_eq!(output.spendable_value_at(1000, 0.1), 10.0);
+
+		// 10% per hour compounds over two hours
+		assert!((output.spendable_value_at(1000 + 7_200_000, 0.1) - 8.1).abs() < 1e-9);
+		assert!((output.spendable_value_at(1000 + 1_800_000, 0.1) - 10.0 * 0.9f64.sqrt()).abs() < 1e-9);
+
+		let transaction = Transaction {
+			inputs: vec![output.clone(), output],
+			outputs: vec![],
+			witnesses: vec![],
+		};
+		assert!((transaction.input_sum_at(1000 + 3_600_000, 0.1) - 18.0).abs() < 1e-9);
+		assert_eq!(transaction.input_sum(), 20.0);
+	}
+
 	#[test]
 	fn test_transaction_with_fractional_values() {
 		let transaction = Transaction {
@@ -241,19 +474,23 @@ mod tests {
 				to_addr: "Alice".to_owned(),
 				value: 10.5,
 				timestamp: 1000,
+				lock_until: None,
 			}],
 			outputs: vec![
 				Output {
 					to_addr: "Bob".to_owned(),
 					value: 7.25,
 					timestamp: 2000,
+					lock_until: None,
 				},
 				Output {
 					to_addr: "Charlie".to_owned(),
 					value: 3.0,
 					timestamp: 2000,
+					lock_until: None,
 				},
 			],
+			witnesses: vec![],
 		};
 
 		assert_eq!(transaction.input_sum(), 10.5);
@@ -0,0 +1,388 @@
+//! Transaction builder with coin selection, fees and change
+//!
+//! Instead of filling in input and output vectors by hand, callers list the
+//! payments they want to make and the UTXOs they may spend (for example from
+//! `Blockchain::unspent_outputs_of`). The builder then:
+//!
+//! 1. picks inputs in the order set by a `CoinSelection` strategy until they
+//!    cover the payments plus the fee;
+//! 2. charges `fee_per_byte` for the transaction's encoded size, including the
+//!    signatures it will carry;
+//! 3. returns whatever is left over to a change address, unless the change is
+//!    dust, in which case it goes to the fee.
+//!
+//! The result is a signed `Transaction` (`build_signed`), or a
+//! `PartiallySignedTransaction` to sign offline (`build_psbt`). Miners use
+//! `build_coinbase` for the block reward, which spends nothing and carries
+//! the extra-nonce they roll once a block's nonce space is used up.
+//!
+//! To bump the fee of a transaction still waiting in the mempool, build its
+//! replacement with `replacing(&pending)` and a higher fee rate: the
+//! replacement spends all of the pending transaction's inputs, as the
+//! mempool's replacement policy requires.
+
+use crate::BlockHash;
+use crate::hashable::Hashable;
+use crate::psbt::PartiallySignedTransaction;
+use crate::transaction::{Output, Transaction};
+use crate::wallet::{is_key_address, Wallet};
+
+/// Change below this is added to the fee rather than creating an output
+pub const DUST_THRESHOLD: f64 = 0.000_001;
+
+/// Size of the `Witness::Signature` each key address needs: tag, two length prefixes, key and signature
+const SIGNATURE_WITNESS_SIZE: usize = 1 + 4 + 32 + 4 + 64;
+
+/// The order in which UTXOs are tried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoinSelection {
+    /// Fewest inputs, lowest fee
+    LargestFirst,
+    /// Consolidates small outputs
+    SmallestFirst,
+    /// Spends the oldest outputs first, before demurrage wears them down further
+    OldestFirst,
+}
+
+/// Builds a transaction paying a set of outputs from a pool of UTXOs
+#[derive(Clone)]
+pub struct TransactionBuilder {
+    utxos: Vec<Output>,
+    /// Inputs that must be spent: those of the transaction being replaced
+    required_inputs: Vec<Output>,
+    payments: Vec<(String, f64)>,
+    change_address: Option<String>,
+    fee_per_byte: f64,
+    coin_selection: CoinSelection,
+    demurrage_rate: f64,
+    extra_nonce: u64,
+    timestamp: u128,
+}
+
+impl TransactionBuilder {
+    /// A builder for a transaction whose outputs are timestamped `timestamp`
+    pub fn new(timestamp: u128) -> Self {
+        TransactionBuilder {
+            utxos: vec![],
+            required_inputs: vec![],
+            payments: vec![],
+            change_address: None,
+            fee_per_byte: 0.0,
+            coin_selection: CoinSelection::LargestFirst,
+            demurrage_rate: 0.0,
+            extra_nonce: 0,
+            timestamp,
+        }
+    }
+
+    /// Offer UTXOs the transaction may spend
+    pub fn add_utxos(mut self, utxos: impl IntoIterator<Item = Output>) -> Self {
+        self.utxos.extend(utxos);
+        self
+    }
+
+    /// Replace `pending`, spending all of its inputs (plus others if the fee needs them)
+    pub fn replacing(mut self, pending: &Transaction) -> Self {
+        self.required_inputs = pending.inputs.clone();
+        self
+    }
+
+    /// Pay `value` to `address`
+    pub fn pay_to(mut self, address: &str, value: f64) -> Self {
+        self.payments.push((address.to_string(), value));
+        self
+    }
+
+    pub fn with_change_address(mut self, address: &str) -> Self {
+        self.change_address = Some(address.to_string());
+        self
+    }
+
+    pub fn with_fee_rate(mut self, fee_per_byte: f64) -> Self {
+        self.fee_per_byte = fee_per_byte;
+        self
+    }
+
+    pub fn with_coin_selection(mut self, coin_selection: CoinSelection) -> Self {
+        self.coin_selection = coin_selection;
+        self
+    }
+
+    /// Value inputs at `timestamp` after this hourly demurrage (see `ChainParams::demurrage_rate_per_hour`)
+    pub fn with_demurrage(mut self, rate_per_hour: f64) -> Self {
+        self.demurrage_rate = rate_per_hour;
+        self
+    }
+
+    /// Starting extra-nonce for `build_coinbase`
+    pub fn with_extra_nonce(mut self, extra_nonce: u64) -> Self {
+        self.extra_nonce = extra_nonce;
+        self
+    }
+
+    fn input_value(&self, input: &Output) -> f64 {
+        input.spendable_value_at(self.timestamp, self.demurrage_rate)
+    }
+
+    fn output(&self, address: &str, value: f64) -> Output {
+        Output {
+            to_addr: address.to_string(),
+            value,
+            timestamp: self.timestamp,
+            lock_until: None,
+        }
+    }
+
+    /// Fee for a transaction with these inputs and outputs once it is signed
+    fn fee_for(&self, inputs: &[Output], outputs: &[Output]) -> f64 {
+        let unsigned = Transaction {
+            inputs: inputs.to_vec(),
+            outputs: outputs.to_vec(),
+            witnesses: vec![],
+        };
+        let mut signed_addresses: Vec<&str> = inputs.iter()
+            .map(|input| input.to_addr.as_str())
+            .filter(|address| is_key_address(address))
+            .collect();
+        signed_addresses.sort_unstable();
+        signed_addresses.dedup();
+
+        let size = unsigned.bytes().len() + signed_addresses.len() * SIGNATURE_WITNESS_SIZE;
+        size as f64 * self.fee_per_byte
+    }
+
+    /// Select inputs and assemble the unsigned transaction
+    fn assemble(&self, change_address: Option<&str>) -> Result<Transaction, String> {
+        if self.payments.is_empty() {
+            return Err("Transaction has no payments".to_string());
+        }
+        if let Some((address, value)) = self.payments.iter().find(|(_, value)| *value <= 0.0) {
+            return Err(format!("Payment of {} to {} must be positive", value, address));
+        }
+
+        let target: f64 = self.payments.iter().map(|(_, value)| value).sum();
+        let payments: Vec<Output> = self.payments.iter()
+            .map(|(address, value)| self.output(address, *value))
+            .collect();
+
+        let required: Vec<BlockHash> = self.required_inputs.iter().map(|input| input.hash()).collect();
+        let mut candidates: Vec<&Output> = self.utxos.iter()
+            .filter(|utxo| utxo.is_spendable_at(self.timestamp) && utxo.timestamp <= self.timestamp)
+            .filter(|utxo| !required.contains(&utxo.hash()))
+            .collect();
+        match self.coin_selection {
+            CoinSelection::LargestFirst => candidates.sort_by(|a, b| self.input_value(b).total_cmp(&self.input_value(a))),
+            CoinSelection::SmallestFirst => candidates.sort_by(|a, b| self.input_value(a).total_cmp(&self.input_value(b))),
+            CoinSelection::OldestFirst => candidates.sort_by_key(|utxo| utxo.timestamp),
+        }
+
+        // the change output is counted in the fee until we know it won't be dust
+        let with_change = |outputs: &[Output]| {
+            let mut outputs = outputs.to_vec();
+            outputs.push(self.output(change_address.unwrap_or_default(), 0.0));
+            outputs
+        };
+
+        let mut inputs: Vec<Output> = self.required_inputs.clone();
+        let mut total: f64 = inputs.iter().map(|input| self.input_value(input)).sum();
+        for utxo in candidates {
+            if total >= target + self.fee_for(&inputs, &with_change(&payments)) {
+                break;
+            }
+            inputs.push(utxo.clone());
+            total += self.input_value(utxo);
+        }
+
+        let fee = self.fee_for(&inputs, &with_change(&payments));
+        if total < target + fee {
+            let fee = self.fee_for(&inputs, &payments);
+            if total < target + fee {
+                return Err(format!("Insufficient funds: need {} plus {} fee, have {}", target, fee, total));
+            }
+        }
+
+        let mut outputs = payments;
+        let change = total - target - fee;
+        if change >= DUST_THRESHOLD {
+            let change_address = change_address.ok_or("No change address for leftover value")?;
+            outputs.push(self.output(change_address, change));
+        }
+
+        Ok(Transaction {
+            inputs,
+            outputs,
+            witnesses: vec![],
+        })
+    }
+
+    /// The unsigned transaction, wrapped for signing offline
+    pub fn build_psbt(&self) -> Result<PartiallySignedTransaction, String> {
+        Ok(PartiallySignedTransaction::new(&self.assemble(self.change_address.as_deref())?))
+    }
+
+    /// The transaction signed by `wallet`, sending change to the wallet's first receive address unless set
+    pub fn build_signed(&self, wallet: &Wallet) -> Result<Transaction, String> {
+        let change_address = match &self.change_address {
+            Some(address) => address.clone(),
+            None => wallet.receive_address(0)?,
+        };
+
+        let mut psbt = PartiallySignedTransaction::new(&self.assemble(Some(&change_address))?);
+        wallet.sign(&mut psbt)?;
+        psbt.finalize()
+    }
+
+    /// A coinbase paying the block reward to the payees, with an extra-nonce witness
+    pub fn build_coinbase(&self) -> Result<Transaction, String> {
+        if self.payments.is_empty() {
+            return Err("Coinbase has no payments".to_string());
+        }
+        let mut coinbase = Transaction {
+            inputs: vec![],
+            outputs: self.payments.iter().map(|(address, value)| self.output(address, *value)).collect(),
+            witnesses: vec![],
+        };
+        coinbase.set_extra_nonce(self.extra_nonce);
+        Ok(coinbase)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signature_cache::verify_transaction;
+    use crate::wallet::Mnemonic;
+
+    fn wallet() -> Wallet {
+        Wallet::from_mnemonic(&Mnemonic::from_entropy(&[5; 16]).unwrap(), "")
+    }
+
+    fn utxos(wallet: &Wallet) -> Vec<Output> {
+        [(1.0, 100), (4.0, 200), (2.0, 300)].iter().enumerate()
+            .map(|(i, (value, timestamp))| Output {
+                to_addr: wallet.receive_address(i as u32).unwrap(),
+                value: *value,
+                timestamp: *timestamp,
+                lock_until: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_change_and_fee() {
+        let wallet = wallet();
+        let builder = TransactionBuilder::new(1000)
+            .add_utxos(utxos(&wallet))
+            .pay_to("Bob", 3.0)
+            .with_fee_rate(0.0001);
+
+        let transaction = builder.build_signed(&wallet).unwrap();
+        assert_eq!(transaction.inputs.len(), 1);
+        assert_eq!(transaction.inputs[0].value, 4.0);
+        assert_eq!(transaction.outputs[0].to_addr, "Bob");
+        assert_eq!(transaction.outputs[1].to_addr, wallet.receive_address(0).unwrap());
+
+        // The fee pays for the signed size
+        let fee = transaction.input_sum() - transaction.output_sum();
+        assert!((fee - transaction.bytes().len() as f64 * 0.0001).abs() < 1e-9);
+        assert_eq!(verify_transaction(&transaction), Ok(()));
+    }
+
+    #[test]
+    fn test_coin_selection_strategies() {
+        let wallet = wallet();
+        let selected = |coin_selection| {
+            let transaction = TransactionBuilder::new(1000)
+                .add_utxos(utxos(&wallet))
+                .pay_to("Bob", 0.5)
+                .with_coin_selection(coin_selection)
+                .build_signed(&wallet)
+                .unwrap();
+            transaction.inputs.iter().map(|input| input.value).collect::<Vec<f64>>()
+        };
+
+        assert_eq!(selected(CoinSelection::LargestFirst), vec![4.0]);
+        assert_eq!(selected(CoinSelection::SmallestFirst), vec![1.0]);
+        assert_eq!(selected(CoinSelection::OldestFirst), vec![1.0]);
+
+        // Larger payments pull in more inputs
+        let transaction = TransactionBuilder::new(1000)
+            .add_utxos(utxos(&wallet))
+            .pay_to("Bob", 6.5)
+            .with_coin_selection(CoinSelection::SmallestFirst)
+            .build_signed(&wallet)
+            .unwrap();
+        assert_eq!(transaction.inputs.len(), 3);
+        assert_eq!(transaction.witnesses.len(), 3);
+    }
+
+    #[test]
+    fn test_fee_bump_replaces_pending() {
+        use crate::mempool::{EvictionReason, Mempool, MempoolConfig};
+
+        let wallet = wallet();
+        let builder = TransactionBuilder::new(1000)
+            .add_utxos(utxos(&wallet))
+            .pay_to("Bob", 0.5)
+            .with_coin_selection(CoinSelection::SmallestFirst)
+            .with_fee_rate(0.0001);
+        let pending = builder.build_signed(&wallet).unwrap();
+
+        // the bump keeps the pending input even though LargestFirst would pick another
+        let bumped = builder.clone()
+            .with_coin_selection(CoinSelection::LargestFirst)
+            .with_fee_rate(0.001)
+            .replacing(&pending)
+            .build_signed(&wallet)
+            .unwrap();
+        assert_eq!(bumped.input_hashes(), pending.input_hashes());
+
+        let mut mempool = Mempool::new(MempoolConfig::default());
+        mempool.insert(pending.clone(), None, 1, 1000).unwrap();
+        let evicted = mempool.insert(bumped, None, 1, 1000).unwrap();
+        assert_eq!(evicted[0].tx_hash, pending.hash());
+        assert_eq!(evicted[0].reason, EvictionReason::Replaced);
+    }
+
+    #[test]
+    fn test_dust_and_insufficient_funds() {
+        let wallet = wallet();
+
+        // Exact payment: no change output
+        let transaction = TransactionBuilder::new(1000)
+            .add_utxos(utxos(&wallet))
+            .pay_to("Bob", 7.0)
+            .build_signed(&wallet)
+            .unwrap();
+        assert_eq!(transaction.outputs.len(), 1);
+
+        assert!(TransactionBuilder::new(1000).add_utxos(utxos(&wallet)).pay_to("Bob", 7.5).build_signed(&wallet).is_err());
+        assert!(TransactionBuilder::new(1000).add_utxos(utxos(&wallet)).pay_to("Bob", -1.0).build_signed(&wallet).is_err());
+
+        // Outputs newer than the transaction can't be spent by it
+        assert!(TransactionBuilder::new(150).add_utxos(utxos(&wallet)).pay_to("Bob", 2.0).build_signed(&wallet).is_err());
+
+        // An unsigned build needs somewhere to send change
+        assert!(TransactionBuilder::new(1000).add_utxos(utxos(&wallet)).pay_to("Bob", 1.0).build_psbt().is_err());
+        let psbt = TransactionBuilder::new(1000)
+            .add_utxos(utxos(&wallet))
+            .pay_to("Bob", 1.0)
+            .with_change_address("Change")
+            .build_psbt()
+            .unwrap();
+        assert!(!psbt.is_complete());
+    }
+
+    #[test]
+    fn test_coinbase() {
+        let coinbase = TransactionBuilder::new(1000)
+            .pay_to("Miner", 2.0)
+            .with_extra_nonce(7)
+            .build_coinbase()
+            .unwrap();
+        assert!(coinbase.is_coinbase());
+        assert_eq!(coinbase.extra_nonce(), Some(7));
+        assert_eq!(coinbase.outputs[0].timestamp, 1000);
+        assert!(TransactionBuilder::new(1000).build_coinbase().is_err());
+    }
+}
@@ -0,0 +1,138 @@
+//! Signed UTXO set snapshots for fast sync
+//!
+//! A validator joining an established network doesn't have to replay every
+//! block from genesis. A peer hands it a `UtxoSnapshot`: the unspent outputs
+//! as they stood after the block at some height, signed with the peer's node
+//! key. The joining validator downloads only the headers up to that height
+//! and checks them like any block's (links, difficulty, proof of work,
+//! checkpoints). It then fetches the block after them, whose
+//! `utxo_commitment` must commit to exactly the snapshot's outputs, and
+//! validates it in full against them.
+//!
+//! None of this proves the blocks under the snapshot were valid: a miner can
+//! put any commitment in a header it mines. The snapshot is taken on the word
+//! of its signer, which must be a key the operator trusts (`--fast-sync-key`)
+//! or a member of the validator set. See `Blockchain::from_utxo_snapshot`.
+
+use std::collections::HashSet;
+use crate::canonical::{CanonicalDeserialize, CanonicalReader, CanonicalSerialize};
+use crate::node_key::{verify_signature, NodeKey};
+use crate::transaction::Output;
+use crate::{BlockHash, Hashable};
+
+/// Signing context for UTXO snapshots
+const SNAPSHOT_SIGNING_CONTEXT: &[u8] = b"hourcoin-utxo-snapshot";
+
+/// The unspent outputs after the block at `height`
+#[derive(Clone)]
+pub struct UtxoSnapshot {
+    pub height: u32,
+    /// Hash of the block at `height`
+    pub block_hash: BlockHash,
+    /// Every unspent output, sorted by hash
+    pub outputs: Vec<Output>,
+    /// Public key of the validator that signed the snapshot (empty if unsigned)
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl UtxoSnapshot {
+    /// An unsigned snapshot of `outputs` after the block at `height`
+    pub fn new(height: u32, block_hash: BlockHash, mut outputs: Vec<Output>) -> Self {
+        outputs.sort_by_cached_key(Output::hash);
+        UtxoSnapshot { height, block_hash, outputs, public_key: vec![], signature: vec![] }
+    }
+
+    /// What the header after the snapshot's block commits to, as `Blockchain::utxo_commitment` computes it
+    pub fn commitment(&self) -> BlockHash {
+        let mut hashes: Vec<BlockHash> = self.outputs.iter().map(Output::hash).collect();
+        hashes.sort_unstable();
+        BlockHash::digest(&hashes.canonical_bytes())
+    }
+
+    /// Fails if two outputs have the same hash, which a UTXO set can't hold
+    pub fn check_outputs(&self) -> Result<(), String> {
+        let mut seen = HashSet::with_capacity(self.outputs.len());
+        match self.outputs.iter().map(Output::hash).find(|hash| !seen.insert(*hash)) {
+            Some(hash) => Err(format!("Snapshot lists output {} twice", hash)),
+            None => Ok(()),
+        }
+    }
+
+    /// Sign the snapshot with `node_key`
+    pub fn sign(mut self, node_key: &NodeKey) -> Self {
+        self.public_key = node_key.public_key();
+        self.signature = node_key.sign(SNAPSHOT_SIGNING_CONTEXT, &self.signing_bytes());
+        self
+    }
+
+    /// Check the snapshot's signature (an unsigned snapshot fails)
+    pub fn verify_signature(&self) -> Result<(), String> {
+        if self.public_key.is_empty() {
+            return Err("Snapshot is not signed".to_string());
+        }
+        verify_signature(&self.public_key, SNAPSHOT_SIGNING_CONTEXT, &self.signing_bytes(), &self.signature)
+    }
+
+    // the height, block and commitment; the signature covers the outputs through the commitment
+    fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        self.height.write_canonical(&mut bytes);
+        self.block_hash.write_canonical(&mut bytes);
+        self.commitment().write_canonical(&mut bytes);
+        bytes
+    }
+}
+
+impl CanonicalSerialize for UtxoSnapshot {
+    fn write_canonical(&self, out: &mut Vec<u8>) {
+        self.height.write_canonical(out);
+        self.block_hash.write_canonical(out);
+        self.outputs.write_canonical(out);
+        self.public_key.write_canonical(out);
+        self.signature.write_canonical(out);
+    }
+}
+
+impl CanonicalDeserialize for UtxoSnapshot {
+    fn read_canonical(reader: &mut CanonicalReader) -> Result<Self, String> {
+        Ok(UtxoSnapshot {
+            height: u32::read_canonical(reader)?,
+            block_hash: BlockHash::read_canonical(reader)?,
+            outputs: Vec::read_canonical(reader)?,
+            public_key: Vec::read_canonical(reader)?,
+            signature: Vec::read_canonical(reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(value: f64) -> Output {
+        Output { to_addr: "Alice".to_owned(), value, timestamp: 1000, lock_until: None }
+    }
+
+    #[test]
+    fn test_signed_snapshot() {
+        let key = NodeKey::generate();
+        let block_hash = BlockHash::digest(b"block");
+        let snapshot = UtxoSnapshot::new(7, block_hash, vec![output(2.0), output(1.0)]);
+        assert!(snapshot.verify_signature().is_err());
+        // the commitment doesn't depend on the order the outputs were listed in
+        assert_eq!(snapshot.commitment(), UtxoSnapshot::new(7, block_hash, vec![output(1.0), output(2.0)]).commitment());
+
+        let signed = snapshot.sign(&key);
+        assert_eq!(signed.verify_signature(), Ok(()));
+        let decoded = UtxoSnapshot::from_canonical_bytes(&signed.canonical_bytes()).unwrap();
+        assert_eq!((decoded.height, decoded.commitment(), decoded.verify_signature()), (7, signed.commitment(), Ok(())));
+
+        let mut tampered = signed.clone();
+        tampered.outputs[0].value = 100.0;
+        assert!(tampered.verify_signature().is_err());
+        tampered.outputs[0] = tampered.outputs[1].clone();
+        assert!(tampered.check_outputs().is_err());
+        assert_eq!(signed.check_outputs(), Ok(()));
+    }
+}
@@ -0,0 +1,75 @@
+//! Hooks for custom block acceptance rules and side effects
+//!
+//! Code embedding a validator can register `ValidationHook`s with
+//! `Validator::add_validation_hook` instead of forking the crate. A hook sees
+//! every block the validator judges, from miners and from peers:
+//!
+//! - `check_block` runs on miner submissions, after the round checks
+//!   (lockout, attempts, timestamp, tonce) and before the chain rules.
+//!   Returning an error rejects the block with
+//!   `ValidationResult::RejectedByHook`, which counts as an attempt, like a
+//!   block the chain rules refuse. This is where compliance filters and extra
+//!   business rules go. Blocks proposed by peer validators skip it: other
+//!   validators don't run the same hooks, and refusing a block they accept
+//!   would stall consensus. A rejected block isn't remembered as a duplicate,
+//!   so a miner may resubmit it once the hook would allow it.
+//! - `on_block_accepted` and `on_block_rejected` run once the outcome is
+//!   known, for side effects such as notifying another service. An accepted
+//!   block also starts its miner's lockout (`on_miner_locked_out`) and a new
+//!   round (`on_round_started`), in that order.
+//!
+//! Hooks run in the order they were added, on the validator task, so they
+//! must not block: a hook that talks to the network or a database should hand
+//! the work to a task of its own. The first hook to reject a block decides;
+//! later ones aren't asked. Blocks re-applied by `Validator::replay_decisions`
+//! were judged before and don't run the hooks again.
+
+use crate::decision_log::DecisionSource;
+use crate::validator::ValidationResult;
+use crate::{Block, BlockHash};
+
+/// The circumstances a block is judged in
+#[derive(Debug, Clone, Copy)]
+pub struct HookContext<'a> {
+    /// The miner credited with the block
+    pub miner_id: &'a str,
+    /// Whether a miner submitted the block or a peer validator proposed it
+    pub source: DecisionSource,
+    /// When the block was judged, in milliseconds since the Unix epoch
+    pub judged_at: u128,
+}
+
+/// A mining round that has just started
+#[derive(Debug, Clone, Copy)]
+pub struct RoundStart {
+    /// Identifier of the round (see `round_id`)
+    pub round_id: BlockHash,
+    /// When the round started, in milliseconds since the Unix epoch
+    pub round_start: u128,
+    /// Height of the block the round is for
+    pub height: u32,
+    pub tonce: Option<u8>,
+}
+
+/// Custom checks and side effects for blocks a validator judges
+pub trait ValidationHook: Send {
+    /// Name of the hook, shown in rejections and logs
+    fn name(&self) -> &str;
+
+    /// Check a miner's block before the chain rules; an error rejects it with the given reason
+    fn check_block(&mut self, _block: &Block, _context: &HookContext) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called after a block is added to the chain
+    fn on_block_accepted(&mut self, _block: &Block, _context: &HookContext) {}
+
+    /// Called after a block is refused, by any check
+    fn on_block_rejected(&mut self, _block: &Block, _context: &HookContext, _result: &ValidationResult) {}
+
+    /// Called when a miner whose block was accepted starts a lockout ending at `until` (milliseconds)
+    fn on_miner_locked_out(&mut self, _miner_id: &str, _until: u128) {}
+
+    /// Called whenever a new mining round starts
+    fn on_round_started(&mut self, _round: &RoundStart) {}
+}
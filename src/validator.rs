@@ -7,10 +7,73 @@
 /// 4. Enforcing the miner sacrifice protocol (1-hour lockout)
 /// 5. Managing mining sessions and tracking miner attempts
 
-use crate::{Block, Blockchain, now};
+use crate::{u128_bytes, Block, BlockHash, BlockHeader, Blockchain, Difficulty, RoundCommitment, Transaction};
+use crate::block_store::BlockStore;
+use crate::canonical::{CanonicalDeserialize, CANONICAL_VERSION};
+use crate::hashable::Hashable;
+use crate::chain_params::{ChainParams, Network};
+use crate::commitment::TimestampCommitment;
+use crate::compact_block::{CompactBlock, TransactionPool, TRANSACTION_POOL_CAPACITY};
+use crate::consensus::{AcceptanceVote, Consensus, QuorumCertificate, ValidatorSet};
+use crate::faucet::{Faucet, FaucetError, FaucetPayment};
+use crate::federation::{FederatedReceipt, Federation, FederationCommitments, FederationMember, FederationSignatureShare, FederationSigningPackage};
+use crate::mempool::{Eviction, Expiry, Mempool, MempoolConfig};
+use crate::network::pubsub::{RawPublication, PUBSUB_FEED_CAPACITY};
+use crate::miner_stats::MinerStatsBook;
+use crate::node_key::NodeKey;
+use crate::receipt::{sign_round, verify_round_signature, BlockReceipt};
+use crate::time_source::TimeSource;
 use crate::time_sync::TimeSync;
-use crate::tonce::TonceChallenge;
-use std::collections::{HashMap, HashSet};
+use crate::tonce::{find_valid_timestamp, TonceChallenge};
+use crate::tx_builder::TransactionBuilder;
+use crate::decision_log::{DecisionLog, DecisionRecord, DecisionSource};
+use crate::round_history::{ExportFormat, RoundHistory};
+use crate::snapshot::{encode_block, RoundSnapshot, SessionSnapshot, SnapshotBase, ValidatorSnapshot, SNAPSHOT_VERSION};
+use crate::utxo_snapshot::UtxoSnapshot;
+use crate::validation_hook::{HookContext, RoundStart, ValidationHook};
+use crate::vrf::{round_input, VrfKeypair, VrfProof};
+use crate::watch::{AddressActivity, ACTIVITY_FEED_CAPACITY};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::broadcast;
+
+/// How many recently submitted block hashes are remembered for duplicate detection
+pub const SEEN_BLOCK_CAPACITY: usize = 1024;
+
+/// How many decisions `Validator::recent_decisions` keeps
+pub const RECENT_DECISION_CAPACITY: usize = 32;
+
+/// Miner ID that `Validator::generate_blocks` credits its blocks to
+pub const GENERATED_BLOCK_MINER_ID: &str = "regtest";
+
+/// How far the tip may move past the UTXO snapshot `Validator::utxo_snapshot` serves before a newer one is taken
+pub const UTXO_SNAPSHOT_INTERVAL: u32 = 1000;
+
+/// Identifier of the round that started at `round_start` on top of `prev_block_hash`
+///
+/// Both change when a block is accepted, so a submission echoing an earlier
+/// round's ID can be told apart from one mined for the current round.
+pub fn round_id(round_start: u128, prev_block_hash: &BlockHash) -> BlockHash {
+    let mut preimage = prev_block_hash.to_vec();
+    preimage.extend(&u128_bytes(&round_start));
+    BlockHash::digest(&preimage)
+}
+
+/// A judged block, as kept in memory for status displays
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecentDecision {
+    /// Validator time the block was judged at (TAI milliseconds)
+    pub judged_at: u128,
+    pub source: DecisionSource,
+    pub miner_id: String,
+    /// Chain length when the block was judged
+    pub height: u32,
+    /// `ValidationResult::reason` of the outcome
+    pub result: String,
+}
 
 /// Represents a miner's session with the validator
 #[derive(Debug, Clone)]
@@ -22,9 +85,14 @@ pub struct MinerSession {
 }
 
 impl MinerSession {
-    /// Create a new miner session
+    /// Create a new miner session with the mainnet 1-hour lockout
     pub fn new(miner_id: String, block_accepted_at: u128) -> Self {
-        let must_wait_until = block_accepted_at + 3_600_000; // 1 hour in milliseconds
+        Self::new_with_lockout(miner_id, block_accepted_at, ChainParams::mainnet().lockout_duration_ms)
+    }
+
+    /// Create a new miner session with a custom lockout length (milliseconds)
+    pub fn new_with_lockout(miner_id: String, block_accepted_at: u128, lockout_duration_ms: u128) -> Self {
+        let must_wait_until = block_accepted_at + lockout_duration_ms;
 
         MinerSession {
             miner_id,
@@ -49,6 +117,74 @@ impl MinerSession {
     }
 }
 
+/// How a miner's lockout grows when they keep winning blocks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LockoutPolicy {
+    /// Every accepted block costs the same lockout
+    Fixed,
+    /// Each consecutive win doubles the lockout, capped at `max_duration_ms`
+    Exponential { max_duration_ms: u128 },
+}
+
+impl LockoutPolicy {
+    /// Lockout length for a miner on their `streak`-th consecutive win (starting at 1)
+    pub fn lockout_duration(&self, base_duration_ms: u128, streak: u32) -> u128 {
+        match *self {
+            LockoutPolicy::Fixed => base_duration_ms,
+            LockoutPolicy::Exponential { max_duration_ms } => {
+                let doublings = streak.saturating_sub(1).min(127);
+                base_duration_ms
+                    .saturating_mul(1u128 << doublings)
+                    .min(max_duration_ms)
+                    .max(base_duration_ms)
+            }
+        }
+    }
+
+    /// Longest lockout this policy can hand out
+    pub fn max_duration(&self, base_duration_ms: u128) -> u128 {
+        match *self {
+            LockoutPolicy::Fixed => base_duration_ms,
+            LockoutPolicy::Exponential { max_duration_ms } => max_duration_ms.max(base_duration_ms),
+        }
+    }
+}
+
+/// Which submissions use up a miner's attempts for the round
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AttemptPolicy {
+    /// Submissions that count against a miner each round
+    pub attempts_per_round: u32,
+    /// Give the attempt back when a block with valid proof of work fails only
+    /// the timestamp window or tonce check, which a skewed clock can cause
+    pub refund_timing_failures: bool,
+}
+
+impl AttemptPolicy {
+    /// One attempt per round, whatever the outcome
+    pub fn strict() -> Self {
+        AttemptPolicy { attempts_per_round: 1, refund_timing_failures: false }
+    }
+
+    /// One attempt per round, refunded on timing failures
+    pub fn forgiving() -> Self {
+        AttemptPolicy { attempts_per_round: 1, refund_timing_failures: true }
+    }
+}
+
+impl Default for AttemptPolicy {
+    fn default() -> Self {
+        Self::strict()
+    }
+}
+
+/// Consecutive wins by a miner, used by the exponential lockout policy
+#[derive(Debug, Clone)]
+struct WinStreak {
+    wins: u32,
+    lockout_ends_at: u128,
+}
+
 /// Validation result for block submissions
 #[derive(Debug, PartialEq)]
 pub enum ValidationResult {
@@ -58,264 +194,2752 @@ pub enum ValidationResult {
     RejectedTonceChallenge,
     RejectedMinerInLockout,
     RejectedMinerAlreadyAttempted,
+    RejectedMissingCommitment,
+    RejectedCommitmentMismatch,
+    /// The reveal came too soon after the commitment, or after the challenge it was made for
+    RejectedRevealOutsideWindow,
     RejectedBlockchainValidation(String),
+    /// The same block was already accepted or failed the chain rules; the miner's round attempt is not used up
+    RejectedDuplicateBlock,
+    /// Block acceptance is paused while the local clock has drifted; the attempt is not used up
+    RejectedClockDrift,
+    /// An operator paused block acceptance; the attempt is not used up
+    RejectedPaused,
+    /// An operator banned the miner
+    RejectedMinerBanned,
+    /// The submission names an earlier round; the attempt is not used up
+    RejectedStaleRound,
+    /// A registered `ValidationHook` refused the block, for the given reason
+    RejectedByHook(String),
+}
+
+impl ValidationResult {
+    /// Short name of the outcome, as used to count rejections in `MinerStats`
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ValidationResult::Accepted => "accepted",
+            ValidationResult::RejectedInvalidHash => "invalid_hash",
+            ValidationResult::RejectedInvalidTimestamp => "invalid_timestamp",
+            ValidationResult::RejectedTonceChallenge => "tonce_challenge",
+            ValidationResult::RejectedMinerInLockout => "lockout",
+            ValidationResult::RejectedMinerAlreadyAttempted => "already_attempted",
+            ValidationResult::RejectedMissingCommitment => "missing_commitment",
+            ValidationResult::RejectedCommitmentMismatch => "commitment_mismatch",
+            ValidationResult::RejectedRevealOutsideWindow => "reveal_outside_window",
+            ValidationResult::RejectedBlockchainValidation(_) => "blockchain_validation",
+            ValidationResult::RejectedDuplicateBlock => "duplicate_block",
+            ValidationResult::RejectedClockDrift => "clock_drift",
+            ValidationResult::RejectedPaused => "paused",
+            ValidationResult::RejectedMinerBanned => "banned",
+            ValidationResult::RejectedStaleRound => "stale_round",
+            ValidationResult::RejectedByHook(_) => "hook",
+        }
+    }
 }
 
 /// The Validator node that manages the proof of time consensus
 pub struct Validator {
     /// The canonical blockchain maintained by the validator
     pub blockchain: Blockchain,
+    /// Consensus parameters for the network this validator serves
+    params: ChainParams,
     /// Time synchronization service
     time_sync: TimeSync,
     /// Current tonce challenge
     current_tonce: Option<TonceChallenge>,
     /// Active miner sessions (miners in 1-hour lockout)
     active_sessions: HashMap<String, MinerSession>,
-    /// Miners who have attempted in the current mining round
-    attempted_this_round: HashSet<String>,
+    /// Attempts used by each miner in the current mining round (miners with none are absent)
+    attempted_this_round: HashMap<String, u32>,
+    /// Which submissions use up attempts
+    attempt_policy: AttemptPolicy,
     /// The timestamp when the current mining round started
     current_round_start: u128,
+    /// Check invariants after every accepted block and abort on violation
+    soak_mode: bool,
+    /// How lockouts grow for repeat winners
+    lockout_policy: LockoutPolicy,
+    /// Consecutive wins per miner (kept after their session expires)
+    win_streaks: HashMap<String, WinStreak>,
+    /// Key used to derive unpredictable round challenges (SHA-256 tonce if unset)
+    vrf_keypair: Option<VrfKeypair>,
+    /// Proof published for the current round's VRF-derived tonce
+    current_vrf_proof: Option<VrfProof>,
+    /// Require miners to commit to their timestamp before every submission
+    commit_reveal: bool,
+    /// Timestamp commitments made this round, by miner
+    commitments: HashMap<String, TimestampCommitment>,
+    /// Miners who have submitted a block this round, and so may not commit again
+    revealed_this_round: HashSet<String>,
+    /// Key used to sign receipts for accepted blocks
+    node_key: Option<NodeKey>,
+    /// Receipt for the most recently accepted block
+    last_receipt: Option<BlockReceipt>,
+    /// Receipts for every block accepted with a node key, by height
+    receipts: HashMap<u32, BlockReceipt>,
+    /// Agreement with peer validators, if this validator is part of a set
+    consensus: Option<Consensus>,
+    /// Our acceptance vote for the most recently accepted block
+    last_vote: Option<AcceptanceVote>,
+    /// The threshold federation we co-sign receipts in, and our share of its key
+    federation: Option<(Federation, FederationMember)>,
+    /// Receipts co-signed by the federation, by height
+    federated_receipts: HashMap<u32, FederatedReceipt>,
+    /// Hashes of recently validated submissions, oldest first
+    seen_blocks: VecDeque<BlockHash>,
+    /// The same hashes, for fast lookup
+    seen_block_set: HashSet<BlockHash>,
+    /// Transactions from recent submissions, for rebuilding compact blocks from peers
+    tx_pool: TransactionPool,
+    /// Transactions waiting to be mined
+    mempool: Mempool,
+    /// Refuse blocks until the clock is back in sync (set by the server's resync task)
+    clock_drift_paused: bool,
+    /// Refuse blocks until an operator resumes acceptance
+    paused: bool,
+    /// Miners whose submissions are refused outright
+    banned_miners: BTreeSet<String>,
+    /// Accepted blocks and rejections per miner
+    miner_stats: MinerStatsBook,
+    /// Where every judged block is recorded, if anywhere
+    decision_log: Option<DecisionLog>,
+    /// Where accepted blocks are stored on disk, if anywhere
+    block_store: Option<BlockStore>,
+    /// The signed UTXO snapshot served to validators fast syncing from this one
+    utxo_snapshot: Option<UtxoSnapshot>,
+    /// The last `RECENT_DECISION_CAPACITY` judged blocks, oldest first
+    recent_decisions: VecDeque<RecentDecision>,
+    /// Summaries of the most recent rounds
+    round_history: RoundHistory,
+    /// Where mempool and block activity is published for watching wallets
+    activity: broadcast::Sender<AddressActivity>,
+    /// Where accepted blocks and transactions are published for indexers
+    raw_feed: broadcast::Sender<RawPublication>,
+    /// Pays test coins to clients that ask, if enabled
+    faucet: Option<Faucet>,
+    /// Custom checks and side effects for judged blocks, in the order they run
+    hooks: Vec<Box<dyn ValidationHook>>,
+}
+
+/// The round a block is judged in, as recorded in the decision log
+struct RoundMark {
+    judged_at: u128,
+    height: u32,
+    round_start: u128,
+    tonce: Option<u8>,
+    challenge_seconds_remaining: u64,
 }
 
 impl Validator {
     /// Create a new validator with a specified blockchain difficulty
-    pub fn new(difficulty: u128) -> Self {
+    pub fn new(difficulty: Difficulty) -> Self {
+        Self::new_with_params(ChainParams::mainnet().with_difficulty(difficulty))
+    }
+
+    /// Create a new validator for a network described by its chain parameters
+    ///
+    /// A network with a fixed genesis block starts with it in place.
+    /// Panics if the parameters fail `ChainParams::check`, or that block doesn't
+    /// meet the network's own rules.
+    pub fn new_with_params(params: ChainParams) -> Self {
+        if let Err(e) = params.check() {
+            panic!("The chain parameters are invalid: {}", e);
+        }
+        let time_sync = TimeSync::new_with_params(&params);
+        let blockchain = match &params.genesis {
+            Some(genesis) => Blockchain::with_genesis(&params, genesis)
+                .unwrap_or_else(|e| panic!("The configured genesis block is invalid: {:?}", e)),
+            None => Blockchain::new_with_params(&params),
+        };
         Validator {
-            blockchain: Blockchain::new_with_diff(difficulty),
-            time_sync: TimeSync::new(),
+            blockchain,
+            current_round_start: time_sync.trusted_now(),
+            time_sync,
+            params,
             current_tonce: None,
             active_sessions: HashMap::new(),
-            attempted_this_round: HashSet::new(),
-            current_round_start: now(),
+            attempted_this_round: HashMap::new(),
+            attempt_policy: AttemptPolicy::default(),
+            soak_mode: false,
+            lockout_policy: LockoutPolicy::Fixed,
+            win_streaks: HashMap::new(),
+            vrf_keypair: None,
+            current_vrf_proof: None,
+            commit_reveal: false,
+            commitments: HashMap::new(),
+            revealed_this_round: HashSet::new(),
+            node_key: None,
+            last_receipt: None,
+            receipts: HashMap::new(),
+            consensus: None,
+            last_vote: None,
+            federation: None,
+            federated_receipts: HashMap::new(),
+            seen_blocks: VecDeque::new(),
+            seen_block_set: HashSet::new(),
+            tx_pool: TransactionPool::new(TRANSACTION_POOL_CAPACITY),
+            mempool: Mempool::new(MempoolConfig::default()),
+            clock_drift_paused: false,
+            paused: false,
+            banned_miners: BTreeSet::new(),
+            miner_stats: MinerStatsBook::new(),
+            decision_log: None,
+            block_store: None,
+            utxo_snapshot: None,
+            recent_decisions: VecDeque::new(),
+            round_history: RoundHistory::default(),
+            activity: broadcast::channel(ACTIVITY_FEED_CAPACITY).0,
+            raw_feed: broadcast::channel(PUBSUB_FEED_CAPACITY).0,
+            faucet: None,
+            hooks: Vec::new(),
         }
     }
 
-    /// Initialize the tonce challenge for a new mining round
-    pub fn start_new_round(&mut self) {
-        let prev_timestamp = if let Some(last_block) = self.blockchain.blocks.last() {
-            last_block.timestamp
-        } else {
-            now()
-        };
+    /// Join a validator set: accepted blocks only become final with a quorum of votes
+    ///
+    /// Requires a node key that belongs to the set.
+    pub fn set_validator_set(&mut self, validator_set: ValidatorSet) -> Result<(), String> {
+        let public_key = self.node_public_key()
+            .ok_or("A node key is required to take part in consensus")?;
+        if !validator_set.contains(&public_key) {
+            return Err("This validator's node key is not in the validator set".to_string());
+        }
 
-        self.current_tonce = Some(TonceChallenge::new(prev_timestamp));
-        self.current_round_start = now();
-        self.attempted_this_round.clear();
+        self.consensus = Some(Consensus::new(validator_set));
+        Ok(())
+    }
 
-        // Clean up expired sessions
-        let current_time = now();
-        self.active_sessions.retain(|_, session| {
-            !session.is_lockout_expired(current_time)
-        });
+    /// Our acceptance vote for the most recently accepted block
+    pub fn get_last_vote(&self) -> Option<&AcceptanceVote> {
+        self.last_vote.as_ref()
     }
 
-    /// Validate and potentially accept a block submission from a miner
-    pub fn validate_block_submission(
+    /// Validate a block another validator accepted and vote for it
+    ///
+    /// The proposer's own vote is recorded first. If we already hold the same
+    /// block at that height we simply vote again; a different block is refused.
+    pub fn handle_peer_proposal(
         &mut self,
         block: Block,
         miner_id: String,
-    ) -> ValidationResult {
-        let current_time = now();
+        proposer_vote: AcceptanceVote,
+    ) -> Result<AcceptanceVote, String> {
+        if proposer_vote.block_hash != block.hash || proposer_vote.height != block.header.index {
+            return Err("Proposer vote does not match the proposed block".to_string());
+        }
+        self.add_peer_vote(proposer_vote)?;
 
-        // Check if miner is in lockout period (miner sacrifice protocol)
-        if let Some(session) = self.active_sessions.get(&miner_id) {
-            if !session.is_lockout_expired(current_time) {
-                return ValidationResult::RejectedMinerInLockout;
+        if let Some(existing) = self.blockchain.blocks.get(block.header.index as usize) {
+            if existing.hash != block.hash {
+                return Err(format!("Already accepted a different block at height {}", block.header.index));
             }
+            return self.cast_vote(&block);
         }
 
-        // Check if miner has already attempted this round (prevent spam)
-        if self.attempted_this_round.contains(&miner_id) {
-            return ValidationResult::RejectedMinerAlreadyAttempted;
+        let mark = self.round_mark();
+        let logged = (self.decision_log.is_some() || !self.hooks.is_empty()).then(|| block.clone());
+        let result = self.validate_peer_block(block, miner_id.clone());
+        self.remember_decision(DecisionSource::Peer, &miner_id, &mark, &result);
+        if let Some(block) = logged {
+            self.notify_rejection(&block, &HookContext { miner_id: &miner_id, source: DecisionSource::Peer, judged_at: mark.judged_at }, &result);
+            self.log_decision(DecisionSource::Peer, &block, &miner_id, None, mark, &result);
+        }
+        match result {
+            ValidationResult::Accepted => self.last_vote.clone()
+                .ok_or_else(|| "No vote cast for accepted block".to_string()),
+            result => Err(format!("{:?}", result)),
         }
+    }
 
-        // Mark that this miner has attempted this round
-        self.attempted_this_round.insert(miner_id.clone());
+    /// Rebuild a compact block proposed by a peer from our transaction pool
+    ///
+    /// Returns the positions of the transactions the proposer must send in full if it can't be rebuilt.
+    pub fn reconstruct_block(&self, compact: &CompactBlock) -> Result<Block, Vec<u32>> {
+        compact.reconstruct(&self.tx_pool)
+    }
 
-        // Validate timestamp against time sync
-        if !self.time_sync.validate_timestamp(block.timestamp) {
-            return ValidationResult::RejectedInvalidTimestamp;
-        }
+    /// Record a vote from a peer validator, returning a certificate if it completes a quorum
+    pub fn add_peer_vote(&mut self, vote: AcceptanceVote) -> Result<Option<QuorumCertificate>, String> {
+        self.consensus.as_mut()
+            .ok_or("Consensus is not enabled on this validator")?
+            .add_vote(vote)
+    }
 
-        // Validate against tonce challenge
-        if let Some(ref mut tonce) = self.current_tonce {
-            if !tonce.validate_timestamp(block.timestamp, current_time) {
-                return ValidationResult::RejectedTonceChallenge;
+    /// Record a certificate finalizing a block
+    ///
+    /// Blocks are applied as soon as they're accepted, before a quorum agrees,
+    /// so the blocks past the finalized height are provisional. If the
+    /// certificate is for a different block at a height we hold, the chain is
+    /// rolled back to that height and the removed blocks' transactions return
+    /// to the mempool; the certified block is then reported by
+    /// `missing_finalized_block` until it is synced. A certificate that would
+    /// remove a finalized block is refused.
+    pub fn add_certificate(&mut self, certificate: QuorumCertificate) -> Result<(), String> {
+        let consensus = self.consensus.as_ref().ok_or("Consensus is not enabled on this validator")?;
+        certificate.verify(consensus.validator_set())?;
+
+        let height = certificate.height;
+        if self.blockchain.blocks.get(height as usize).is_some_and(|block| block.hash != certificate.block_hash) {
+            if (height..self.blockchain.blocks.len() as u32).any(|h| consensus.is_finalized(h)) {
+                return Err(format!("Local chain diverges from the finalized block at height {}", height));
             }
+            self.roll_back_to(height)?;
         }
 
-        // Validate against blockchain rules
-        match self.blockchain.update_with_block(block.clone()) {
-            Ok(_) => {
-                // Block accepted! Start miner sacrifice period
-                let session = MinerSession::new(miner_id.clone(), current_time);
-                self.active_sessions.insert(miner_id, session);
+        self.consensus.as_mut()
+            .expect("consensus is enabled")
+            .add_certificate(certificate)?;
+        self.forget_final_expiries();
+        Ok(())
+    }
 
-                // Start new mining round
-                self.start_new_round();
+    // a rollback restores mined transactions' expiries, but only unfinalized blocks can be rolled back
+    fn forget_final_expiries(&mut self) {
+        let final_height = match &self.consensus {
+            Some(consensus) => consensus.finalized_height(),
+            None => (self.blockchain.blocks.len() as u32).checked_sub(1),
+        };
+        if let Some(height) = final_height {
+            self.mempool.forget_mined(height);
+        }
+    }
 
-                ValidationResult::Accepted
+    /// Drop the provisional blocks from `height` up, returning their transactions to the mempool with their expiries
+    fn roll_back_to(&mut self, height: u32) -> Result<(), String> {
+        let removed = self.blockchain.rewind_to(height as usize)
+            .map_err(|e| format!("Failed to roll back to height {}: {:?}", height, e))?;
+        tracing::warn!(height, blocks = removed.len(), "a quorum finalized a different block; rolled back the chain");
+
+        self.receipts.retain(|index, _| *index < height);
+        self.federated_receipts.retain(|index, _| *index < height);
+        if self.last_receipt.as_ref().is_some_and(|receipt| receipt.height >= height) {
+            self.last_receipt = None;
+        }
+        let now = self.current_time();
+        for transaction in removed.iter().flat_map(|block| block.body.transactions.iter().skip(1)) {
+            if let Err(e) = self.mempool.restore(transaction.clone(), height, now) {
+                tracing::debug!(error = %e, "dropped a transaction from a rolled back block");
             }
-            Err(e) => ValidationResult::RejectedBlockchainValidation(format!("{:?}", e)),
         }
+        if let Err(e) = self.sync_block_store() {
+            tracing::error!(error = %e, "failed to store the rolled back chain");
+        }
+        self.start_new_round();
+        Ok(())
     }
 
-    /// Get the current tonce value
-    pub fn get_current_tonce(&self) -> Option<u8> {
-        self.current_tonce.as_ref().map(|t| t.get_tonce())
+    /// Height and hash of the finalized block following our tip, if a quorum certified one we don't hold
+    pub fn missing_finalized_block(&self) -> Option<(u32, BlockHash)> {
+        let height = self.blockchain.blocks.len() as u32;
+        self.consensus.as_ref()?
+            .certificate(height)
+            .map(|certificate| (height, certificate.block_hash))
     }
 
-    /// Get time remaining in current tonce challenge (seconds)
-    pub fn get_challenge_time_remaining(&self) -> u64 {
-        if let Some(ref tonce) = self.current_tonce {
-            tonce.seconds_remaining(now())
-        } else {
-            0
+    /// Co-sign block receipts with the other members of a threshold federation
+    ///
+    /// The server coordinates a signature for every block a miner gets
+    /// accepted here (`federation_commit`, `federation_sign`), and keeps the
+    /// result with `add_federated_receipt`.
+    pub fn set_federation(&mut self, federation: Federation, member: FederationMember) -> Result<(), String> {
+        if !federation.members().contains(&member.member_id()) {
+            return Err(format!("Member {} is not in the federation", member.member_id()));
         }
+        self.federation = Some((federation, member));
+        Ok(())
     }
 
-    /// Check if a miner is currently in lockout
-    pub fn is_miner_in_lockout(&self, miner_id: &str) -> bool {
-        if let Some(session) = self.active_sessions.get(miner_id) {
-            !session.is_lockout_expired(now())
-        } else {
-            false
+    pub fn federation(&self) -> Option<&Federation> {
+        self.federation.as_ref().map(|(federation, _)| federation)
+    }
+
+    /// Round one of co-signing a receipt: our member id and fresh nonce commitments
+    pub fn federation_commit(&mut self) -> Result<(u16, FederationCommitments), String> {
+        let (_, member) = self.federation.as_mut().ok_or("This validator is not in a federation")?;
+        Ok((member.member_id(), member.commit()))
+    }
+
+    /// Round two: sign `package`, once it is checked to be a receipt for the block we hold at `height`
+    pub fn federation_sign(&mut self, package: &FederationSigningPackage, height: u32, miner_id: &str, accepted_at: u128)
+        -> Result<(u16, FederationSignatureShare), String>
+    {
+        let block = self.blockchain.blocks.get(height as usize)
+            .ok_or_else(|| format!("No block at height {} to sign a receipt for", height))?;
+        let message = FederatedReceipt::message(&block.hash, height, miner_id, accepted_at);
+        let (_, member) = self.federation.as_mut().ok_or("This validator is not in a federation")?;
+        if package.message().as_slice() != message.as_slice() {
+            return Err(format!("The signing package isn't a receipt for our block at height {}", height));
         }
+        Ok((member.member_id(), member.sign(package)?))
     }
 
-    /// Get lockout time remaining for a miner (seconds)
-    pub fn get_miner_lockout_remaining(&self, miner_id: &str) -> u64 {
-        if let Some(session) = self.active_sessions.get(miner_id) {
-            session.seconds_remaining(now())
-        } else {
-            0
+    /// Keep a receipt co-signed by the federation, checking its group signature and block
+    pub fn add_federated_receipt(&mut self, receipt: FederatedReceipt) -> Result<(), String> {
+        let (federation, _) = self.federation.as_ref().ok_or("This validator is not in a federation")?;
+        if !federation.is_final(&receipt) {
+            return Err(format!("Federated receipt for height {} has an invalid signature", receipt.height));
+        }
+        if self.blockchain.blocks.get(receipt.height as usize).map(|block| block.hash) != Some(receipt.block_hash) {
+            return Err(format!("Federated receipt for height {} is for a block we don't hold", receipt.height));
         }
+        self.federated_receipts.insert(receipt.height, receipt);
+        Ok(())
     }
 
-    /// Get the number of blocks in the blockchain
-    pub fn get_block_count(&self) -> usize {
-        self.blockchain.blocks.len()
+    /// The federation's receipt for the block at a height, once co-signed
+    pub fn get_federated_receipt(&self, height: u32) -> Option<&FederatedReceipt> {
+        self.federated_receipts.get(&height)
     }
 
-    /// Get the current difficulty
-    pub fn get_difficulty(&self) -> u128 {
-        self.blockchain.get_difficulty()
+    /// Whether the block at a height is final (always true without consensus)
+    pub fn is_block_final(&self, height: u32) -> bool {
+        match &self.consensus {
+            Some(consensus) => consensus.is_finalized(height),
+            None => (height as usize) < self.blockchain.blocks.len(),
+        }
     }
 
-    /// Get information about the current mining round
-    pub fn get_round_info(&self) -> RoundInfo {
-        RoundInfo {
-            round_start: self.current_round_start,
-            tonce: self.get_current_tonce(),
-            challenge_seconds_remaining: self.get_challenge_time_remaining(),
-            attempted_miners: self.attempted_this_round.len(),
-            active_lockouts: self.active_sessions.len(),
+    /// Highest height finalized by a quorum, if consensus is enabled
+    pub fn finalized_height(&self) -> Option<u32> {
+        self.consensus.as_ref().and_then(|consensus| consensus.finalized_height())
+    }
+
+    /// Sign our acceptance vote for a block in our chain
+    fn cast_vote(&mut self, block: &Block) -> Result<AcceptanceVote, String> {
+        let node_key = self.node_key.as_ref().ok_or("No node key configured")?;
+        let consensus = self.consensus.as_mut().ok_or("Consensus is not enabled on this validator")?;
+        let vote = consensus.vote_for(node_key, &block.hash, block.header.index)?;
+        self.last_vote = Some(vote.clone());
+        Ok(vote)
+    }
+
+    /// Sign a receipt for every block accepted from now on
+    pub fn set_node_key(&mut self, node_key: NodeKey) {
+        self.node_key = Some(node_key);
+    }
+
+    /// Public key miners use to verify this validator's receipts
+    pub fn node_public_key(&self) -> Option<Vec<u8>> {
+        self.node_key.as_ref().map(|key| key.public_key())
+    }
+
+    /// Receipt for the most recently accepted block, if a node key is set
+    pub fn get_last_receipt(&self) -> Option<&BlockReceipt> {
+        self.last_receipt.as_ref()
+    }
+
+    /// Receipt for the block at a height, if this validator signed one
+    pub fn get_receipt(&self, height: u32) -> Option<&BlockReceipt> {
+        self.receipts.get(&height)
+    }
+
+    /// Require a timestamp commitment before every block submitted this round
+    pub fn set_commit_reveal(&mut self, enabled: bool) {
+        self.commit_reveal = enabled;
+    }
+
+    /// Record a miner's commitment to the timestamp they will submit this round
+    ///
+    /// Each miner may commit once per round, and not after submitting a block.
+    /// A commitment made during the tonce challenge must leave `reveal_delay_ms`
+    /// before the challenge expires, so commits close halfway through it.
+    pub fn commit_timestamp(&mut self, miner_id: String, commitment: Vec<u8>) -> Result<(), String> {
+        let current_time = self.current_time();
+
+        if !self.commit_reveal {
+            return Err("Commit-reveal is not enabled on this validator".to_string());
+        }
+        if commitment.len() != 32 {
+            return Err(format!("Commitment must be 32 bytes, got {}", commitment.len()));
         }
+        if self.is_miner_in_lockout(&miner_id) {
+            return Err("Miner in lockout".to_string());
+        }
+        if self.remaining_attempts(&miner_id) == 0 {
+            return Err("Already attempted this round".to_string());
+        }
+        if self.commitments.contains_key(&miner_id) {
+            return Err("Already committed this round".to_string());
+        }
+        if self.revealed_this_round.contains(&miner_id) {
+            return Err("Already submitted a block this round".to_string());
+        }
+        let challenge = self.current_tonce.as_ref().filter(|tonce| !tonce.is_expired(current_time));
+        if challenge.is_some_and(|tonce| tonce.is_expired(current_time + self.reveal_delay_ms())) {
+            return Err("Commitments for this challenge have closed; commit after it expires".to_string());
+        }
+
+        self.commitments.insert(miner_id, TimestampCommitment {
+            commitment,
+            committed_at: current_time,
+        });
+        Ok(())
     }
-}
 
-/// Information about the current mining round
-#[derive(Debug, Clone)]
-pub struct RoundInfo {
-    pub round_start: u128,
-    pub tonce: Option<u8>,
-    pub challenge_seconds_remaining: u64,
-    pub attempted_miners: usize,
-    pub active_lockouts: usize,
-}
+    /// How long after committing a miner must wait to reveal (milliseconds)
+    ///
+    /// Half the tonce challenge, so commitments made during the challenge are
+    /// all in before the first of them can be revealed.
+    pub fn reveal_delay_ms(&self) -> u128 {
+        self.params.tonce_challenge_duration_ms / 2
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::transaction::{Transaction, Output};
+    // Whether a reveal at `current_time` may open `commitment`: once the reveal delay
+    // has passed, and before the challenge expires if the commitment was made during it
+    fn in_reveal_window(&self, commitment: &TimestampCommitment, current_time: u128) -> bool {
+        let committed_during_challenge = self.current_tonce.as_ref()
+            .is_some_and(|tonce| !tonce.is_expired(commitment.committed_at));
+        let challenge_expired = self.current_tonce.as_ref().is_none_or(|tonce| tonce.is_expired(current_time));
+        current_time >= commitment.committed_at + self.reveal_delay_ms()
+            && !(committed_during_challenge && challenge_expired)
+    }
 
-    fn create_test_block(index: u32, timestamp: u128, prev_hash: Vec<u8>, difficulty: u128) -> Block {
-        let coinbase = Transaction {
-            inputs: vec![],
-            outputs: vec![Output {
-                to_addr: "Miner".to_owned(),
-                value: 2.0,
-                timestamp,
-            }],
-        };
+    /// Derive round challenges from a VRF keyed by this validator
+    ///
+    /// Takes effect from the next call to `start_new_round`.
+    pub fn set_vrf_keypair(&mut self, keypair: VrfKeypair) {
+        self.vrf_keypair = Some(keypair);
+    }
 
-        let mut block = Block::new(index, timestamp, prev_hash, vec![coinbase]);
-        block.mine(difficulty);
-        block
+    /// Select how lockouts grow for miners who keep winning
+    pub fn set_lockout_policy(&mut self, policy: LockoutPolicy) {
+        self.lockout_policy = policy;
     }
 
-    #[test]
-    fn test_validator_creation() {
-        let difficulty = 0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF;
-        let validator = Validator::new(difficulty);
+    /// Get the active lockout policy
+    pub fn get_lockout_policy(&self) -> LockoutPolicy {
+        self.lockout_policy
+    }
 
-        assert_eq!(validator.get_block_count(), 0);
-        assert_eq!(validator.get_difficulty(), difficulty);
+    /// Select which failed submissions use up a miner's attempts
+    pub fn set_attempt_policy(&mut self, policy: AttemptPolicy) {
+        self.attempt_policy = policy;
     }
 
-    #[test]
-    fn test_miner_session_creation() {
-        let session = MinerSession::new("miner1".to_string(), 1000000);
+    pub fn get_attempt_policy(&self) -> AttemptPolicy {
+        self.attempt_policy
+    }
 
-        assert_eq!(session.miner_id, "miner1");
-        assert_eq!(session.block_accepted_at, 1000000);
-        assert_eq!(session.must_wait_until, 1000000 + 3_600_000);
-        assert!(session.is_active);
+    /// Submissions a miner has left this round
+    pub fn remaining_attempts(&self, miner_id: &str) -> u32 {
+        let used = self.attempted_this_round.get(miner_id).copied().unwrap_or(0);
+        self.attempt_policy.attempts_per_round.saturating_sub(used)
     }
 
-    #[test]
-    fn test_miner_session_lockout() {
-        let session = MinerSession::new("miner1".to_string(), 1000000);
+    /// Give back an attempt used by a submission that failed only on timing
+    ///
+    /// Only blocks carrying valid proof of work are refunded, so free retries
+    /// still cost the miner a mined block each.
+    fn refund_attempt(&mut self, miner_id: &str, block: &Block) {
+        if !self.attempt_policy.refund_timing_failures || !self.get_difficulty().is_met_by(&block.hash()) {
+            return;
+        }
+        if let Some(used) = self.attempted_this_round.get_mut(miner_id) {
+            *used -= 1;
+            if *used == 0 {
+                self.attempted_this_round.remove(miner_id);
+            }
+        }
+    }
 
-        // During lockout
-        assert!(!session.is_lockout_expired(1000000 + 1000));
-        assert!(session.seconds_remaining(1000000 + 1000) > 0);
+    /// Record a win for a miner and return the lockout they must serve
+    ///
+    /// A win counts as consecutive if it comes within one base lockout period
+    /// after the miner's previous lockout ended; otherwise the streak resets.
+    fn next_lockout_duration(&mut self, miner_id: &str, current_time: u128) -> u128 {
+        let base = self.params.lockout_duration_ms;
 
-        // After lockout
-        assert!(session.is_lockout_expired(1000000 + 3_700_000));
-        assert_eq!(session.seconds_remaining(1000000 + 3_700_000), 0);
+        let wins = match self.win_streaks.get(miner_id) {
+            Some(streak) if current_time < streak.lockout_ends_at + base => streak.wins + 1,
+            _ => 1,
+        };
+
+        let duration = self.lockout_policy.lockout_duration(base, wins);
+        self.win_streaks.insert(miner_id.to_string(), WinStreak {
+            wins,
+            lockout_ends_at: current_time + duration,
+        });
+
+        duration
     }
 
-    #[test]
-    fn test_start_new_round() {
-        let difficulty = 0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF;
-        let mut validator = Validator::new(difficulty);
+    /// Read the time from `clock` instead of the system clock
+    ///
+    /// The validator reads it through its `TimeSync`, and the current round restarts its timing from it.
+    pub fn set_time_source(&mut self, clock: Arc<dyn TimeSource>) {
+        self.time_sync.set_time_source(clock);
+        self.current_round_start = self.current_time();
+    }
 
-        validator.start_new_round();
+    /// The time every validator check uses: the local clock corrected by the external time offset
+    fn current_time(&self) -> u128 {
+        self.time_sync.trusted_now()
+    }
 
-        assert!(validator.current_tonce.is_some());
-        assert!(validator.get_current_tonce().is_some());
-        assert_eq!(validator.attempted_this_round.len(), 0);
+    /// The validator's idea of the current time (TAI milliseconds), as used for every check
+    pub fn get_current_time(&self) -> u128 {
+        self.current_time()
     }
 
-    #[test]
-    fn test_get_round_info() {
-        let difficulty = 0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF;
-        let mut validator = Validator::new(difficulty);
+    /// Estimated offset of external time from the local clock (milliseconds)
+    pub fn get_clock_offset_ms(&self) -> i128 {
+        self.time_sync.current_offset_ms()
+    }
 
-        validator.start_new_round();
-        let info = validator.get_round_info();
+    /// Accepted blocks and rejections per miner
+    pub fn miner_stats(&self) -> &MinerStatsBook {
+        &self.miner_stats
+    }
 
-        assert!(info.tonce.is_some());
-        assert_eq!(info.attempted_miners, 0);
-        assert_eq!(info.active_lockouts, 0);
+    /// The most recently judged blocks, oldest first, whether or not a decision log is kept
+    pub fn recent_decisions(&self) -> &VecDeque<RecentDecision> {
+        &self.recent_decisions
     }
 
-    #[test]
-    fn test_miner_lockout_tracking() {
-        let difficulty = 0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF;
-        let mut validator = Validator::new(difficulty);
+    /// Summaries of the most recent rounds, oldest first (see the `round_history` module)
+    pub fn round_history(&self) -> &RoundHistory {
+        &self.round_history
+    }
 
-        // Miner not in lockout initially
-        assert!(!validator.is_miner_in_lockout("miner1"));
+    /// Write the recent rounds to `path` as CSV or JSON
+    pub fn export_round_history(&self, format: ExportFormat, path: &Path) -> Result<(), String> {
+        self.round_history.export(format, path)
+    }
 
-        // Add a session
-        let session = MinerSession::new("miner1".to_string(), now());
-        validator.active_sessions.insert("miner1".to_string(), session);
+    fn remember_decision(&mut self, source: DecisionSource, miner_id: &str, mark: &RoundMark, result: &ValidationResult) {
+        self.round_history.record(
+            mark.round_start,
+            mark.tonce,
+            mark.judged_at,
+            miner_id,
+            source == DecisionSource::Miner,
+            result.reason(),
+        );
+        if self.recent_decisions.len() == RECENT_DECISION_CAPACITY {
+            self.recent_decisions.pop_front();
+        }
+        self.recent_decisions.push_back(RecentDecision {
+            judged_at: mark.judged_at,
+            source,
+            miner_id: miner_id.to_string(),
+            height: mark.height,
+            result: result.reason().to_string(),
+        });
+    }
 
-        // Miner should now be in lockout
-        assert!(validator.is_miner_in_lockout("miner1"));
-        assert!(validator.get_miner_lockout_remaining("miner1") > 0);
+    /// Record every judged block in `log` (see the `decision_log` module)
+    pub fn set_decision_log(&mut self, log: DecisionLog) {
+        self.decision_log = Some(log);
+    }
+
+    /// Run `hook` on every block judged from now on (see the `validation_hook` module)
+    pub fn add_validation_hook(&mut self, hook: Box<dyn ValidationHook>) {
+        self.hooks.push(hook);
+    }
+
+    /// Where judged blocks are being recorded, if anywhere
+    pub fn decision_log_path(&self) -> Option<&Path> {
+        self.decision_log.as_ref().map(DecisionLog::path)
+    }
+
+    /// Append every accepted block to `store` (see the `block_store` module)
+    ///
+    /// The store is first brought in line with the chain: blocks past the
+    /// last one both agree on are dropped, and the chain's blocks after it
+    /// appended.
+    pub fn set_block_store(&mut self, store: BlockStore) -> Result<(), String> {
+        self.block_store = Some(store);
+        self.sync_block_store()
+    }
+
+    pub fn block_store(&self) -> Option<&BlockStore> {
+        self.block_store.as_ref()
+    }
+
+    fn sync_block_store(&mut self) -> Result<(), String> {
+        let Some(store) = self.block_store.as_mut() else { return Ok(()) };
+        let blocks = &self.blockchain.blocks;
+        let mut shared = store.len().min(blocks.len() as u32);
+        while shared > 0 && store.header(shared - 1)?.hash() != blocks[shared as usize - 1].hash {
+            shared -= 1;
+        }
+        // a chain synced from a UTXO snapshot has no transactions to store below it
+        if let Some(base) = self.blockchain.utxo_base().filter(|base| shared <= base.height) {
+            return Err(format!("The chain was synced from a UTXO snapshot at height {}, and the block store only holds {} of its blocks",
+                base.height, shared));
+        }
+        if shared < store.len() {
+            tracing::warn!(stored = store.len(), kept = shared, "block store diverges from the chain; dropping its tail");
+            store.truncate(shared)?;
+        }
+        for block in &blocks[shared as usize..] {
+            store.append(block)?;
+        }
+        Ok(())
+    }
+
+    /// A UTXO snapshot signed with the node key, for validators fast syncing from this one
+    ///
+    /// It's taken after the highest block whose successor commits to the UTXO
+    /// set, which is what a syncing validator checks it against, and served
+    /// until the tip moves `UTXO_SNAPSHOT_INTERVAL` blocks past it or a
+    /// reorganization replaces its block.
+    pub fn utxo_snapshot(&mut self) -> Result<&UtxoSnapshot, String> {
+        let blocks = &self.blockchain.blocks;
+        let current = self.utxo_snapshot.as_ref().is_some_and(|snapshot| {
+            blocks.get(snapshot.height as usize).is_some_and(|block| block.hash == snapshot.block_hash)
+                && blocks.len() as u32 - snapshot.height <= UTXO_SNAPSHOT_INTERVAL
+        });
+        if !current {
+            let node_key = self.node_key.as_ref().ok_or("No node key configured to sign UTXO snapshots")?;
+            let height = (1..blocks.len()).rev()
+                .find(|height| blocks[*height].header.utxo_commitment.is_some())
+                .ok_or("No block commits to the UTXO set it spends from")? - 1;
+            self.utxo_snapshot = Some(self.blockchain.utxo_snapshot(height)?.sign(node_key));
+        }
+        Ok(self.utxo_snapshot.as_ref().expect("the snapshot was just taken"))
+    }
+
+    /// Start the chain from a peer's UTXO snapshot instead of replaying it from genesis
+    ///
+    /// Only a chain with no blocks past genesis is replaced, and only once
+    /// `next`, the block after the snapshot's, has been applied on top of it
+    /// (see `Blockchain::from_utxo_snapshot`); on error nothing changes. The
+    /// snapshot must be signed by `trusted_key` or, with consensus enabled, a
+    /// member of the validator set. The blocks after `next` follow with
+    /// `apply_synced_blocks`.
+    pub fn adopt_utxo_snapshot(&mut self, headers: &[BlockHeader], snapshot: UtxoSnapshot, next: Block, trusted_key: Option<&[u8]>) -> Result<(), String> {
+        if self.blockchain.blocks.len() > 1 {
+            return Err(format!("The chain already holds {} blocks", self.blockchain.blocks.len()));
+        }
+        snapshot.verify_signature()?;
+        let validator_set = self.consensus.as_ref().map(|consensus| consensus.validator_set());
+        if trusted_key.is_none() && validator_set.is_none() {
+            return Err("No trusted key or validator set to check the snapshot's signer against".to_string());
+        }
+        if trusted_key != Some(&snapshot.public_key[..]) && !validator_set.is_some_and(|set| set.contains(&snapshot.public_key)) {
+            return Err(format!("Snapshot is signed by {}, which is not trusted", hex::encode(&snapshot.public_key)));
+        }
+        self.blockchain = Blockchain::from_utxo_snapshot(&self.params, headers, snapshot, next)?;
+        if let Err(e) = self.sync_block_store() {
+            tracing::error!(error = %e, "failed to store synced blocks");
+        }
+        self.start_new_round();
+        Ok(())
+    }
+
+    /// Apply blocks a peer sent while syncing, in order
+    ///
+    /// Each is validated in full, as `Blockchain::update_with_blocks` does; a
+    /// block mined at a higher difficulty raises the chain's first. Blocks at
+    /// one difficulty are applied all or none, and an invalid one ends the
+    /// sync, keeping the blocks at earlier difficulties.
+    pub fn apply_synced_blocks(&mut self, blocks: Vec<Block>) -> Result<(), String> {
+        let mut blocks = blocks.into_iter().peekable();
+        let mut result = Ok(());
+        while let Some(first) = blocks.next() {
+            let bits = first.header.bits;
+            let mut run = vec![first];
+            while let Some(block) = blocks.next_if(|block| block.header.bits == bits) {
+                run.push(block);
+            }
+            if bits.is_harder_than(&self.blockchain.get_difficulty()) {
+                if let Err(e) = self.blockchain.update_difficulty(bits) {
+                    result = Err(format!("Synced block {} has an invalid difficulty: {:?}", run[0].header.index, e));
+                    break;
+                }
+            }
+            if let Err((height, e)) = self.blockchain.update_with_blocks(run) {
+                result = Err(format!("Synced block {} is invalid: {:?}", height, e));
+                break;
+            }
+        }
+        if let Err(e) = self.sync_block_store() {
+            tracing::error!(error = %e, "failed to store synced blocks");
+        }
+        self.start_new_round();
+        result
+    }
+
+    /// Set the mempool's size and age limits
+    pub fn set_mempool_config(&mut self, config: MempoolConfig) {
+        self.mempool.set_config(config);
+    }
+
+    pub fn mempool(&self) -> &Mempool {
+        &self.mempool
+    }
+
+    /// Add a transaction to the mempool, returning the transactions it displaced
+    ///
+    /// Every input must be unspent on chain, and key and multisig inputs
+    /// signed; the signatures aren't checked again when the transaction is
+    /// mined. The transaction is dropped again once `expiry` passes, or when
+    /// a block spends one of its inputs.
+    pub fn submit_transaction(&mut self, transaction: Transaction, expiry: Option<Expiry>) -> Result<Vec<Eviction>, String> {
+        if let Some(input) = transaction.inputs.iter().find(|input| !self.blockchain.is_unspent(&input.hash())) {
+            return Err(format!("Input {} is not an unspent output", hex::encode(input.hash())));
+        }
+        self.blockchain.verify_signatures(&transaction)?;
+        let height = self.blockchain.blocks.len() as u32;
+        let evicted = self.mempool.insert(transaction.clone(), expiry, height, self.current_time())?;
+        Self::log_evictions(&evicted);
+        self.publish_activity(&transaction, None);
+        self.publish_raw(|| RawPublication::Transaction(transaction));
+        Ok(evicted)
+    }
+
+    /// Pay clients that send `RequestFaucet` from `faucet` (see the `faucet` module)
+    pub fn set_faucet(&mut self, faucet: Faucet) {
+        self.faucet = Some(faucet);
+    }
+
+    pub fn faucet(&self) -> Option<&Faucet> {
+        self.faucet.as_ref()
+    }
+
+    /// Pay `address` from the faucet for a client at `ip`
+    pub fn request_faucet(&mut self, ip: IpAddr, address: &str) -> Result<FaucetPayment, FaucetError> {
+        let mut faucet = self.faucet.take()
+            .ok_or_else(|| FaucetError::Unavailable("This validator doesn't run a faucet".to_string()))?;
+        let result = faucet.request(self, ip, address, Instant::now());
+        self.faucet = Some(faucet);
+        result
+    }
+
+    /// Hear about every address a mempool or confirmed transaction touches from now on
+    pub fn subscribe_activity(&self) -> broadcast::Receiver<AddressActivity> {
+        self.activity.subscribe()
+    }
+
+    fn publish_activity(&self, transaction: &Transaction, height: Option<u32>) {
+        if self.activity.receiver_count() == 0 {
+            return;
+        }
+        for activity in AddressActivity::of_transaction(transaction, height) {
+            let _ = self.activity.send(activity);
+        }
+    }
+
+    /// Hear about every accepted block, and every transaction entering the mempool or a block, from now on
+    ///
+    /// A block comes before the transactions in it.
+    pub fn subscribe_raw(&self) -> broadcast::Receiver<RawPublication> {
+        self.raw_feed.subscribe()
+    }
+
+    fn publish_raw(&self, publication: impl FnOnce() -> RawPublication) {
+        if self.raw_feed.receiver_count() > 0 {
+            let _ = self.raw_feed.send(publication());
+        }
+    }
+
+    fn log_evictions(evicted: &[Eviction]) {
+        for eviction in evicted {
+            tracing::debug!(tx = %eviction.tx_hash.to_string(), reason = ?eviction.reason, "evicted from mempool");
+        }
+    }
+
+    /// Replace the miner statistics, e.g. with a book saved before a restart
+    pub fn set_miner_stats(&mut self, stats: MinerStatsBook) {
+        self.miner_stats = stats;
+    }
+
+    pub fn time_sync(&self) -> &TimeSync {
+        &self.time_sync
+    }
+
+    /// Mutable access to the time sync, e.g. to apply new readings
+    pub fn time_sync_mut(&mut self) -> &mut TimeSync {
+        &mut self.time_sync
+    }
+
+    /// Pause or resume block acceptance because of local clock drift
+    ///
+    /// While paused, submissions and peer proposals are refused with
+    /// `RejectedClockDrift`; timestamps can't be judged fairly on a bad clock.
+    pub fn set_clock_drift_paused(&mut self, paused: bool) {
+        self.clock_drift_paused = paused;
+    }
+
+    pub fn is_clock_drift_paused(&self) -> bool {
+        self.clock_drift_paused
+    }
+
+    /// Pause or resume block acceptance at an operator's request
+    ///
+    /// While paused, submissions and peer proposals are refused with `RejectedPaused`.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Refuse every submission from `miner_id`; returns false if it was already banned
+    pub fn ban_miner(&mut self, miner_id: &str) -> bool {
+        self.banned_miners.insert(miner_id.to_string())
+    }
+
+    /// Lift a ban; returns false if `miner_id` wasn't banned
+    pub fn unban_miner(&mut self, miner_id: &str) -> bool {
+        self.banned_miners.remove(miner_id)
+    }
+
+    pub fn is_miner_banned(&self, miner_id: &str) -> bool {
+        self.banned_miners.contains(miner_id)
+    }
+
+    pub fn banned_miners(&self) -> impl Iterator<Item = &String> {
+        self.banned_miners.iter()
+    }
+
+    /// Set the target new blocks must meet
+    ///
+    /// The chain only allows the target to be lowered (blocks made harder).
+    pub fn set_difficulty(&mut self, difficulty: Difficulty) -> Result<(), String> {
+        self.blockchain.update_difficulty(difficulty)
+            .map_err(|_| format!("Difficulty {} is easier than the current {}", difficulty, self.get_difficulty()))
+    }
+
+    /// Enable or disable soak mode (invariant checks after every accepted block)
+    pub fn set_soak_mode(&mut self, enabled: bool) {
+        self.soak_mode = enabled;
+    }
+
+    /// Initialize the tonce challenge for a new mining round
+    pub fn start_new_round(&mut self) {
+        let (prev_hash, prev_timestamp) = if let Some(last_block) = self.blockchain.blocks.last() {
+            (last_block.hash, last_block.header.timestamp)
+        } else {
+            (BlockHash::ZERO, self.current_time())
+        };
+
+        // hybrid chains replay the tonce from the previous block, so it can't come from the VRF
+        self.current_tonce = Some(match &self.vrf_keypair {
+            Some(keypair) if !self.params.hybrid_consensus => {
+                let (randomness, proof) = keypair.evaluate(&round_input(&prev_hash, prev_timestamp));
+                self.current_vrf_proof = Some(proof);
+                TonceChallenge::new_from_randomness(&prev_hash, prev_timestamp, &randomness, &self.params)
+            }
+            _ => TonceChallenge::new_with_params(&prev_hash, prev_timestamp, &self.params),
+        });
+        self.current_round_start = self.current_time();
+        self.attempted_this_round.clear();
+        self.commitments.clear();
+        self.revealed_this_round.clear();
+
+        // Clean up expired sessions
+        let current_time = self.current_time();
+        self.active_sessions.retain(|_, session| {
+            !session.is_lockout_expired(current_time)
+        });
+
+        if !self.hooks.is_empty() {
+            let round = RoundStart {
+                round_id: self.current_round_id(),
+                round_start: self.current_round_start,
+                height: self.blockchain.blocks.len() as u32,
+                tonce: self.get_current_tonce(),
+            };
+            for hook in &mut self.hooks {
+                hook.on_round_started(&round);
+            }
+        }
+    }
+
+    /// Validate and potentially accept a block submission from a miner
+    pub fn validate_block_submission(
+        &mut self,
+        block: Block,
+        miner_id: String,
+    ) -> ValidationResult {
+        self.validate_block_reveal(block, miner_id, None)
+    }
+
+    /// Validate a block submission that reveals the salt of an earlier commitment
+    ///
+    /// With commit-reveal enabled, every block submitted this round must open the
+    /// miner's commitment, no sooner than `reveal_delay_ms` after it was made and,
+    /// for a commitment made during the tonce challenge, before the challenge expires.
+    pub fn validate_block_reveal(
+        &mut self,
+        block: Block,
+        miner_id: String,
+        salt: Option<&[u8]>,
+    ) -> ValidationResult {
+        self.validate_submission(block, miner_id, salt, None)
+    }
+
+    /// Validate a submission mined for the round `round_id` names (see `RoundInfo::round_id`)
+    ///
+    /// A submission naming any other round, such as one delayed past the block
+    /// that ended its round, is rejected with `RejectedStaleRound` before it
+    /// uses an attempt, rather than being judged against the current tonce.
+    pub fn validate_round_submission(
+        &mut self,
+        block: Block,
+        miner_id: String,
+        salt: Option<&[u8]>,
+        round_id: &BlockHash,
+    ) -> ValidationResult {
+        self.validate_submission(block, miner_id, salt, Some(round_id))
+    }
+
+    fn validate_submission(&mut self, block: Block, miner_id: String, salt: Option<&[u8]>, round_id: Option<&BlockHash>) -> ValidationResult {
+        let mark = self.round_mark();
+        let logged = (self.decision_log.is_some() || !self.hooks.is_empty()).then(|| block.clone());
+        let result = self.judge_submission(block, miner_id.clone(), salt, round_id);
+        if result != ValidationResult::Accepted {
+            self.miner_stats.record_rejection(&miner_id, result.reason());
+        }
+        self.remember_decision(DecisionSource::Miner, &miner_id, &mark, &result);
+        if let Some(block) = logged {
+            self.notify_rejection(&block, &HookContext { miner_id: &miner_id, source: DecisionSource::Miner, judged_at: mark.judged_at }, &result);
+            self.log_decision(DecisionSource::Miner, &block, &miner_id, salt, mark, &result);
+        }
+        result
+    }
+
+    /// The checks behind `validate_block_reveal` and `validate_round_submission`
+    fn judge_submission(&mut self, block: Block, miner_id: String, salt: Option<&[u8]>, round_id: Option<&BlockHash>) -> ValidationResult {
+        let current_time = self.current_time();
+
+        if self.paused {
+            return ValidationResult::RejectedPaused;
+        }
+        if self.clock_drift_paused {
+            return ValidationResult::RejectedClockDrift;
+        }
+        if self.banned_miners.contains(&miner_id) {
+            return ValidationResult::RejectedMinerBanned;
+        }
+        if round_id.is_some_and(|round_id| *round_id != self.current_round_id()) {
+            return ValidationResult::RejectedStaleRound;
+        }
+        // a block committing to another round was mined for it, whatever round_id came with it
+        if block.header.round.as_ref().is_some_and(|round| !self.is_current_round(round, &block.header.prev_block_hash)) {
+            return ValidationResult::RejectedStaleRound;
+        }
+
+        // A block we have already judged gets the same answer without costing an attempt
+        let block_hash = block.hash();
+        if self.seen_block_set.contains(&block_hash) {
+            return ValidationResult::RejectedDuplicateBlock;
+        }
+        // losing submissions often share transactions with the block a peer proposes
+        self.tx_pool.insert_block(&block);
+
+        // Check if miner is in lockout period (miner sacrifice protocol)
+        if let Some(session) = self.active_sessions.get(&miner_id) {
+            if !session.is_lockout_expired(current_time) {
+                return ValidationResult::RejectedMinerInLockout;
+            }
+        }
+
+        // Check if miner has attempts left this round (prevent spam)
+        if self.remaining_attempts(&miner_id) == 0 {
+            return ValidationResult::RejectedMinerAlreadyAttempted;
+        }
+
+        // Charge the attempt; the attempt policy may refund timing failures below
+        *self.attempted_this_round.entry(miner_id.clone()).or_insert(0) += 1;
+
+        // The revealed timestamp must match the miner's commitment
+        if self.commit_reveal {
+            self.revealed_this_round.insert(miner_id.clone());
+            let commitment = match self.commitments.remove(&miner_id) {
+                Some(commitment) => commitment,
+                None => return ValidationResult::RejectedMissingCommitment,
+            };
+            let opened = salt.is_some_and(|salt| commitment.opens_with(&miner_id, block.header.timestamp, salt));
+            if !opened {
+                return ValidationResult::RejectedCommitmentMismatch;
+            }
+            if !self.in_reveal_window(&commitment, current_time) {
+                return ValidationResult::RejectedRevealOutsideWindow;
+            }
+        }
+
+        // Validate timestamp against time sync
+        if !self.time_sync.validate_timestamp(block.header.timestamp) {
+            self.refund_attempt(&miner_id, &block);
+            return ValidationResult::RejectedInvalidTimestamp;
+        }
+
+        // Validate against tonce challenge
+        if let Some(ref mut tonce) = self.current_tonce {
+            if !tonce.validate_timestamp(block.header.timestamp, current_time) {
+                self.refund_attempt(&miner_id, &block);
+                return ValidationResult::RejectedTonceChallenge;
+            }
+        }
+
+        let context = HookContext { miner_id: &miner_id, source: DecisionSource::Miner, judged_at: current_time };
+        if let Some(rejection) = self.check_hooks(&block, &context) {
+            return rejection;
+        }
+
+        // Timing and hook rejections may pass later; only these outcomes are final for the block
+        let result = self.accept_block(block, miner_id, DecisionSource::Miner, current_time);
+        if matches!(result, ValidationResult::Accepted | ValidationResult::RejectedBlockchainValidation(_)) {
+            self.remember_block(block_hash);
+        }
+        result
+    }
+
+    /// Validate a block proposed by a peer validator
+    ///
+    /// Round-local checks (attempts, commitments) belong to the proposer. The
+    /// lockout, timestamp window and chain rules are re-checked here, and so is
+    /// the tonce when it is derived deterministically from the previous block.
+    fn validate_peer_block(&mut self, block: Block, miner_id: String) -> ValidationResult {
+        let current_time = self.current_time();
+
+        if self.paused {
+            return ValidationResult::RejectedPaused;
+        }
+        if self.clock_drift_paused {
+            return ValidationResult::RejectedClockDrift;
+        }
+
+        if self.is_miner_in_lockout(&miner_id) {
+            return ValidationResult::RejectedMinerInLockout;
+        }
+
+        if !self.time_sync.validate_timestamp(block.header.timestamp) {
+            return ValidationResult::RejectedInvalidTimestamp;
+        }
+
+        // VRF tonces are specific to the proposer's key, and the genesis round has no previous block
+        if self.vrf_keypair.is_none() {
+            if let Some(prev) = self.blockchain.blocks.last() {
+                let mut tonce = TonceChallenge::new_with_params(&prev.hash, prev.header.timestamp, &self.params);
+                if !tonce.validate_timestamp(block.header.timestamp, current_time) {
+                    return ValidationResult::RejectedTonceChallenge;
+                }
+            }
+        }
+
+        // `check_block` hooks are local policy, so they judge miner submissions only:
+        // a validator refusing a block its peers accept would stall consensus
+        self.accept_block(block, miner_id, DecisionSource::Peer, current_time)
+    }
+
+    /// The first registered hook's rejection of `block`, if any
+    fn check_hooks(&mut self, block: &Block, context: &HookContext) -> Option<ValidationResult> {
+        self.hooks.iter_mut().find_map(|hook| {
+            hook.check_block(block, context).err()
+                .map(|reason| ValidationResult::RejectedByHook(format!("{}: {}", hook.name(), reason)))
+        })
+    }
+
+    /// Tell the registered hooks `block` was refused (`accept_block` tells them about accepted ones)
+    fn notify_rejection(&mut self, block: &Block, context: &HookContext, result: &ValidationResult) {
+        if *result == ValidationResult::Accepted {
+            return;
+        }
+        for hook in &mut self.hooks {
+            hook.on_block_rejected(block, context, result);
+        }
+    }
+
+    /// Record a submitted block hash, forgetting the oldest past `SEEN_BLOCK_CAPACITY`
+    fn remember_block(&mut self, block_hash: BlockHash) {
+        if self.seen_block_set.insert(block_hash) {
+            self.seen_blocks.push_back(block_hash);
+        }
+        while self.seen_blocks.len() > SEEN_BLOCK_CAPACITY {
+            if let Some(oldest) = self.seen_blocks.pop_front() {
+                self.seen_block_set.remove(&oldest);
+            }
+        }
+    }
+
+    /// Apply a validated block to the chain and start the miner's lockout
+    fn accept_block(&mut self, block: Block, miner_id: String, source: DecisionSource, current_time: u128) -> ValidationResult {
+        // Validate against blockchain rules
+        match self.blockchain.update_with_block_at(block.clone(), current_time) {
+            Ok(_) => {
+                // catches up on any block an earlier failure left out
+                if let Err(e) = self.sync_block_store() {
+                    tracing::error!(error = %e, height = block.header.index, "failed to store block");
+                }
+
+                // Block accepted! Start miner sacrifice period
+                let lockout_duration = self.next_lockout_duration(&miner_id, current_time);
+                let session = MinerSession::new_with_lockout(miner_id.clone(), current_time, lockout_duration);
+
+                if let Some(node_key) = &self.node_key {
+                    let receipt = BlockReceipt::sign(node_key, &block.hash, block.header.index, &miner_id, current_time);
+                    self.receipts.insert(block.header.index, receipt.clone());
+                    self.last_receipt = Some(receipt);
+                }
+
+                if self.consensus.is_some() {
+                    self.last_vote = self.cast_vote(&block).ok();
+                }
+
+                for transaction in &block.body.transactions {
+                    self.publish_activity(transaction, Some(block.header.index));
+                }
+                self.publish_raw(|| RawPublication::Block(block.clone()));
+                for transaction in &block.body.transactions {
+                    self.publish_raw(|| RawPublication::Transaction(transaction.clone()));
+                }
+
+                let mut evicted = self.mempool.remove_block(&block);
+                evicted.extend(self.mempool.expire(self.blockchain.blocks.len() as u32, current_time));
+                Self::log_evictions(&evicted);
+                self.forget_final_expiries();
+
+                let solution_ms = current_time.saturating_sub(self.current_round_start);
+                self.miner_stats.record_accepted(&miner_id, solution_ms, lockout_duration, current_time);
+
+                let context = HookContext { miner_id: &miner_id, source, judged_at: current_time };
+                for hook in &mut self.hooks {
+                    hook.on_block_accepted(&block, &context);
+                    hook.on_miner_locked_out(&miner_id, session.must_wait_until);
+                }
+                self.active_sessions.insert(miner_id, session);
+
+                // Start new mining round
+                self.start_new_round();
+
+                if self.soak_mode {
+                    self.assert_invariants();
+                }
+
+                ValidationResult::Accepted
+            }
+            Err(e) => ValidationResult::RejectedBlockchainValidation(format!("{:?}", e)),
+        }
+    }
+
+    /// Mine `count` blocks paying `address` straight onto the chain, returning their hashes
+    ///
+    /// Only on regtest, for tests that need a populated chain quickly. Each
+    /// block takes the next timestamp after both the tip and the current time
+    /// that passes the round's tonce, and carries every mempool transaction,
+    /// so the same starting state and clock always produce the same blocks.
+    /// Generated blocks skip the round and leave no lockout behind. Hooks
+    /// still check them and hear about them, won by `GENERATED_BLOCK_MINER_ID`.
+    pub fn generate_blocks(&mut self, count: u32, address: &str) -> Result<Vec<BlockHash>, String> {
+        if self.params.network != Network::Regtest {
+            return Err("Blocks can only be generated on regtest".to_string());
+        }
+
+        let mut hashes = vec![];
+        for _ in 0..count {
+            let (index, prev_hash, earliest) = match self.blockchain.blocks.last() {
+                Some(tip) => (tip.header.index + 1, tip.hash, tip.header.timestamp + 1),
+                None => (0, BlockHash::ZERO, 0),
+            };
+            let tonce = self.get_current_tonce().unwrap_or(1);
+            let timestamp = find_valid_timestamp(tonce, earliest.max(self.current_time()), u32::MAX)
+                .ok_or("No timestamp passes the tonce")?;
+
+            let transactions: Vec<Transaction> = self.mempool.by_fee_rate().into_iter()
+                .map(|entry| entry.transaction.clone())
+                .collect();
+            let coinbase = TransactionBuilder::new(timestamp)
+                .pay_to(address, self.params.coinbase_value)
+                .build_coinbase()?;
+
+            let mut block = Block::new(index, timestamp, prev_hash, std::iter::once(coinbase).chain(transactions).collect());
+            block.header.utxo_commitment = Some(self.blockchain.utxo_commitment());
+            let signature = self.node_key.as_ref().map(|key| sign_round(key, &prev_hash, self.current_round_start, tonce));
+            block.header.round = Some(RoundCommitment { round_start: self.current_round_start, tonce, signature });
+            block.mine(self.get_difficulty());
+            let block_hash = block.hash;
+
+            let judged_at = timestamp.max(self.current_time());
+            let context = HookContext { miner_id: GENERATED_BLOCK_MINER_ID, source: DecisionSource::Miner, judged_at };
+            let result = match self.check_hooks(&block, &context) {
+                Some(rejection) => rejection,
+                None => self.accept_block(block, GENERATED_BLOCK_MINER_ID.to_string(), DecisionSource::Miner, judged_at),
+            };
+            if result != ValidationResult::Accepted {
+                return Err(format!("Generated block {} was rejected: {:?}", index, result));
+            }
+            self.active_sessions.remove(GENERATED_BLOCK_MINER_ID);
+            self.win_streaks.remove(GENERATED_BLOCK_MINER_ID);
+            hashes.push(block_hash);
+        }
+        Ok(hashes)
+    }
+
+    /// Get the current tonce value
+    pub fn get_current_tonce(&self) -> Option<u8> {
+        self.current_tonce.as_ref().map(|t| t.get_tonce())
+    }
+
+    /// Get time remaining in current tonce challenge (seconds)
+    pub fn get_challenge_time_remaining(&self) -> u64 {
+        if let Some(ref tonce) = self.current_tonce {
+            tonce.seconds_remaining(self.current_time())
+        } else {
+            0
+        }
+    }
+
+    /// Check if a miner is currently in lockout
+    pub fn is_miner_in_lockout(&self, miner_id: &str) -> bool {
+        if let Some(session) = self.active_sessions.get(miner_id) {
+            !session.is_lockout_expired(self.current_time())
+        } else {
+            false
+        }
+    }
+
+    /// The miner's lockout session, if any (expired ones are dropped when a round starts)
+    pub fn miner_session(&self, miner_id: &str) -> Option<&MinerSession> {
+        self.active_sessions.get(miner_id)
+    }
+
+    /// Get lockout time remaining for a miner (seconds)
+    pub fn get_miner_lockout_remaining(&self, miner_id: &str) -> u64 {
+        if let Some(session) = self.active_sessions.get(miner_id) {
+            session.seconds_remaining(self.current_time())
+        } else {
+            0
+        }
+    }
+
+    /// Get the number of blocks in the blockchain
+    pub fn get_block_count(&self) -> usize {
+        self.blockchain.blocks.len()
+    }
+
+    /// Get the current difficulty
+    pub fn get_difficulty(&self) -> Difficulty {
+        self.blockchain.get_difficulty()
+    }
+
+    /// Get the chain parameters this validator enforces
+    pub fn get_params(&self) -> &ChainParams {
+        &self.params
+    }
+
+    /// Check chain and session state for internal consistency
+    ///
+    /// Covers the UTXO set and supply (via the blockchain), block indices,
+    /// and that every lockout session is coherent with the accepted blocks.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        self.blockchain.check_invariants()?;
+
+        if self.active_sessions.len() > self.blockchain.blocks.len() {
+            return Err(format!("{} lockout sessions but only {} accepted blocks",
+                self.active_sessions.len(), self.blockchain.blocks.len()));
+        }
+
+        for (miner_id, session) in &self.active_sessions {
+            if &session.miner_id != miner_id {
+                return Err(format!("session for '{}' is keyed as '{}'", session.miner_id, miner_id));
+            }
+            let base = self.params.lockout_duration_ms;
+            let lockout = session.must_wait_until.saturating_sub(session.block_accepted_at);
+            if lockout < base || lockout > self.lockout_policy.max_duration(base) {
+                return Err(format!("session for '{}' has lockout ending at {} for a block accepted at {}",
+                    miner_id, session.must_wait_until, session.block_accepted_at));
+            }
+        }
+
+        if self.current_tonce.is_none() {
+            return Err("no tonce challenge for the current round".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Dump the validator state in a human readable form (used on soak failures)
+    pub fn dump_state(&self) -> String {
+        let mut dump = format!("{:#?}\n", self.get_round_info());
+        dump += &format!("unspent outputs: {}\n", self.blockchain.unspent_output_count());
+        for session in self.active_sessions.values() {
+            dump += &format!("{:?}\n", session);
+        }
+        for block in &self.blockchain.blocks {
+            dump += &format!("{:?}\n", block);
+        }
+        dump
+    }
+
+    /// Capture the chain, UTXO set, lockouts and current round (see the `snapshot` module)
+    pub fn snapshot(&self) -> ValidatorSnapshot {
+        let mut sessions: Vec<SessionSnapshot> = self.active_sessions.values()
+            .map(|session| SessionSnapshot {
+                miner_id: session.miner_id.clone(),
+                block_accepted_at: session.block_accepted_at,
+                must_wait_until: session.must_wait_until,
+            })
+            .collect();
+        sessions.sort_by(|a, b| a.miner_id.cmp(&b.miner_id));
+        let mut win_streaks: Vec<(String, u32, u128)> = self.win_streaks.iter()
+            .map(|(miner_id, streak)| (miner_id.clone(), streak.wins, streak.lockout_ends_at))
+            .collect();
+        win_streaks.sort();
+        let mut attempts: Vec<(String, u32)> = self.attempted_this_round.iter()
+            .map(|(miner_id, attempts)| (miner_id.clone(), *attempts))
+            .collect();
+        attempts.sort();
+        let base = self.blockchain.utxo_base();
+        let start = base.map_or(0, |base| base.height as usize + 1);
+
+        ValidatorSnapshot {
+            version: SNAPSHOT_VERSION,
+            canonical_version: CANONICAL_VERSION,
+            tip: self.blockchain.blocks.last().map(|block| (block.header.index, block.hash.to_string())),
+            blocks: self.blockchain.blocks[start..].iter().map(encode_block).collect(),
+            base: base.map(|base| SnapshotBase::new(&self.blockchain.blocks[..start], base)),
+            difficulty_history: self.blockchain.difficulty_history().to_vec(),
+            utxo_set: self.blockchain.unspent_output_hashes().into_iter().map(hex::encode).collect(),
+            sessions,
+            win_streaks,
+            round: RoundSnapshot {
+                round_start: self.current_round_start,
+                tonce: self.get_current_tonce(),
+                attempts,
+            },
+            paused: self.paused,
+            banned_miners: self.banned_miners.iter().cloned().collect(),
+        }
+    }
+
+    /// Replace this validator's state with a snapshot
+    ///
+    /// The blocks are replayed under this validator's chain rules, and the
+    /// result must reach the snapshot's tip and UTXO set. A chain that was
+    /// synced from a UTXO snapshot is replayed from there. On error nothing changes.
+    pub fn restore(&mut self, snapshot: ValidatorSnapshot) -> Result<(), String> {
+        snapshot.check_versions()?;
+        let mut blocks = snapshot.decode_blocks()?.into_iter();
+        let mut blockchain = match &snapshot.base {
+            Some(base) => {
+                let (headers, utxo_snapshot) = base.decode()?;
+                // the first block after the base commits to the UTXO snapshot
+                let next = blocks.next().ok_or("Snapshot has no block after its base")?;
+                Blockchain::from_utxo_snapshot(&self.params, &headers, utxo_snapshot, next)
+                    .map_err(|e| format!("Snapshot base is invalid: {}", e))?
+            }
+            None => Blockchain::new_with_params(&self.params),
+        };
+        let start = blockchain.blocks.len();
+        let mut difficulties = snapshot.difficulty_history.iter().peekable();
+        // the base's headers already raised the difficulty as far as they needed
+        while difficulties.next_if(|(first_height, _)| *first_height < start).is_some() {}
+        for (height, block) in (start..).zip(blocks) {
+            while let Some((_, difficulty)) = difficulties.next_if(|(first_height, _)| *first_height <= height) {
+                blockchain.update_difficulty(*difficulty)
+                    .map_err(|e| format!("Snapshot difficulty at height {} doesn't apply: {:?}", height, e))?;
+            }
+            blockchain.update_with_block(block)
+                .map_err(|e| format!("Snapshot block {} is invalid: {:?}", height, e))?;
+        }
+        // changes made since the last block
+        for (_, difficulty) in difficulties {
+            blockchain.update_difficulty(*difficulty)
+                .map_err(|e| format!("Snapshot difficulty doesn't apply: {:?}", e))?;
+        }
+
+        let tip = blockchain.blocks.last().map(|block| (block.header.index, block.hash.to_string()));
+        if tip != snapshot.tip {
+            return Err(format!("Snapshot blocks end at {:?}, not the recorded tip {:?}", tip, snapshot.tip));
+        }
+        let utxo_set: Vec<String> = blockchain.unspent_output_hashes().into_iter().map(hex::encode).collect();
+        if utxo_set != snapshot.utxo_set {
+            return Err(format!("Snapshot blocks give {} unspent outputs, not the {} recorded",
+                utxo_set.len(), snapshot.utxo_set.len()));
+        }
+
+        self.blockchain = blockchain;
+        if let Err(e) = self.sync_block_store() {
+            tracing::error!(error = %e, "failed to bring the block store in line with the restored chain");
+        }
+        self.active_sessions = snapshot.sessions.into_iter()
+            .map(|session| (session.miner_id.clone(), MinerSession {
+                miner_id: session.miner_id,
+                block_accepted_at: session.block_accepted_at,
+                must_wait_until: session.must_wait_until,
+                is_active: true,
+            }))
+            .collect();
+        self.win_streaks = snapshot.win_streaks.into_iter()
+            .map(|(miner_id, wins, lockout_ends_at)| (miner_id, WinStreak { wins, lockout_ends_at }))
+            .collect();
+        self.paused = snapshot.paused;
+        self.banned_miners = snapshot.banned_miners.into_iter().collect();
+        self.seen_blocks.clear();
+        self.seen_block_set.clear();
+
+        // The round's tonce follows from the tip; its attempts still count if it's the same round
+        self.start_new_round();
+        if self.get_current_tonce() == snapshot.round.tonce {
+            self.current_round_start = snapshot.round.round_start;
+            self.attempted_this_round = snapshot.round.attempts.into_iter().collect();
+        }
+        Ok(())
+    }
+
+    fn round_mark(&self) -> RoundMark {
+        RoundMark {
+            judged_at: self.current_time(),
+            height: self.blockchain.blocks.len() as u32,
+            round_start: self.current_round_start,
+            tonce: self.get_current_tonce(),
+            challenge_seconds_remaining: self.get_challenge_time_remaining(),
+        }
+    }
+
+    /// Append a decision to the log; a write that fails is reported but doesn't undo the decision
+    fn log_decision(&mut self, source: DecisionSource, block: &Block, miner_id: &str, salt: Option<&[u8]>, mark: RoundMark, result: &ValidationResult) {
+        let record = DecisionRecord {
+            seq: 0,
+            judged_at: mark.judged_at,
+            source,
+            miner_id: miner_id.to_string(),
+            height: mark.height,
+            block_hash: block.hash.to_string(),
+            block: encode_block(block),
+            salt: salt.map(hex::encode),
+            round_start: mark.round_start,
+            tonce: mark.tonce,
+            challenge_seconds_remaining: mark.challenge_seconds_remaining,
+            result: result.reason().to_string(),
+            detail: match result {
+                ValidationResult::RejectedBlockchainValidation(detail) | ValidationResult::RejectedByHook(detail) => Some(detail.clone()),
+                _ => None,
+            },
+            attempts_used: self.attempted_this_round.get(miner_id).copied().unwrap_or(0),
+        };
+        if let Some(log) = self.decision_log.as_mut() {
+            if let Err(e) = log.append(record) {
+                tracing::error!(error = %e, "failed to record decision");
+            }
+        }
+    }
+
+    /// Re-apply logged decisions the chain doesn't reflect yet, e.g. after restoring a snapshot
+    ///
+    /// Accepted blocks are judged again by the chain rules as of the time they
+    /// were first accepted, and start the same lockouts. Attempts used in the
+    /// latest round are restored. Returns how many decisions were applied.
+    pub fn replay_decisions(&mut self, records: &[DecisionRecord]) -> Result<usize, String> {
+        // these blocks were judged before; hooks already heard about them
+        let hooks = std::mem::take(&mut self.hooks);
+        let replayed = self.replay_records(records);
+        self.hooks = hooks;
+        replayed
+    }
+
+    fn replay_records(&mut self, records: &[DecisionRecord]) -> Result<usize, String> {
+        let mut applied = 0;
+        for record in records {
+            let height = self.blockchain.blocks.len() as u32;
+            if record.height < height {
+                continue;
+            }
+            if record.height > height {
+                return Err(format!("Decision {} is at height {}, but the chain ends at {}", record.seq, record.height, height));
+            }
+
+            if record.result == ValidationResult::Accepted.reason() {
+                let bytes = hex::decode(&record.block).map_err(|e| format!("Invalid block hex in decision {}: {}", record.seq, e))?;
+                let block = Block::from_canonical_bytes(&bytes).map_err(|e| format!("Invalid block in decision {}: {}", record.seq, e))?;
+                self.current_round_start = record.round_start;
+                let result = self.accept_block(block, record.miner_id.clone(), record.source, record.judged_at);
+                if result != ValidationResult::Accepted {
+                    return Err(format!("Decision {} accepted a block that now fails with {:?}", record.seq, result));
+                }
+            } else if record.source == DecisionSource::Miner {
+                if record.round_start != self.current_round_start {
+                    self.current_round_start = record.round_start;
+                    self.attempted_this_round.clear();
+                }
+                if record.attempts_used > 0 {
+                    self.attempted_this_round.insert(record.miner_id.clone(), record.attempts_used);
+                }
+            }
+            applied += 1;
+        }
+        Ok(applied)
+    }
+
+    /// Abort the process with a state dump if any invariant is violated
+    fn assert_invariants(&self) {
+        if let Err(violation) = self.check_invariants() {
+            tracing::error!(violation = %violation, state = %self.dump_state(), "soak invariant violation");
+            std::process::abort();
+        }
+    }
+
+    /// Identifier of the current round (see `round_id`)
+    pub fn current_round_id(&self) -> BlockHash {
+        let prev_hash = self.blockchain.blocks.last().map_or(BlockHash::ZERO, |block| block.hash);
+        round_id(self.current_round_start, &prev_hash)
+    }
+
+    /// Whether `round` names the current round, signed by us if we have a node key
+    fn is_current_round(&self, round: &RoundCommitment, prev_block_hash: &BlockHash) -> bool {
+        round.round_start == self.current_round_start
+            && self.node_key.as_ref().is_none_or(|key| verify_round_signature(round, prev_block_hash, &key.public_key()).is_ok())
+    }
+
+    /// Our signature over the current round and its tonce, if we have a node key
+    fn current_round_signature(&self) -> Option<Vec<u8>> {
+        let prev_hash = self.blockchain.blocks.last().map_or(BlockHash::ZERO, |block| block.hash);
+        let tonce = self.get_current_tonce()?;
+        self.node_key.as_ref().map(|key| sign_round(key, &prev_hash, self.current_round_start, tonce))
+    }
+
+    /// Get information about the current mining round
+    pub fn get_round_info(&self) -> RoundInfo {
+        RoundInfo {
+            round_signature: self.current_round_signature(),
+            remaining_attempts: None,
+            round_id: self.current_round_id(),
+            round_start: self.current_round_start,
+            tonce: self.get_current_tonce(),
+            challenge_seconds_remaining: self.get_challenge_time_remaining(),
+            attempted_miners: self.attempted_this_round.len(),
+            active_lockouts: self.active_sessions.len(),
+            tonce_bits: self.params.tonce_bits,
+            vrf_proof: self.current_vrf_proof.clone(),
+            commit_reveal: self.commit_reveal,
+            utxo_commitment: self.blockchain.utxo_commitment(),
+        }
+    }
+
+    /// Round information with the attempts `miner_id` has left
+    pub fn get_round_info_for(&self, miner_id: &str) -> RoundInfo {
+        RoundInfo {
+            remaining_attempts: Some(self.remaining_attempts(miner_id)),
+            ..self.get_round_info()
+        }
+    }
+}
+
+/// Information about the current mining round
+#[derive(Debug, Clone)]
+pub struct RoundInfo {
+    /// Submissions for this round echo it (see `round_id`)
+    pub round_id: BlockHash,
+    pub round_start: u128,
+    pub tonce: Option<u8>,
+    pub challenge_seconds_remaining: u64,
+    pub attempted_miners: usize,
+    pub active_lockouts: usize,
+    /// Bit width the tonce was extracted with
+    pub tonce_bits: u8,
+    /// VRF proof for the tonce, if the validator derives challenges with a VRF
+    pub vrf_proof: Option<VrfProof>,
+    /// Whether submitted blocks must reveal a commitment
+    pub commit_reveal: bool,
+    /// Commitment to the UTXO set the next block spends from
+    pub utxo_commitment: BlockHash,
+    /// Submissions the requesting miner has left this round, when asked for a miner
+    pub remaining_attempts: Option<u32>,
+    /// Our signature over the round and its tonce, for blocks to carry (see `receipt::sign_round`)
+    pub round_signature: Option<Vec<u8>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::now;
+    use crate::transaction::{Transaction, Output};
+    use crate::commitment::{generate_salt, timestamp_commitment};
+
+    fn create_test_block(index: u32, timestamp: u128, prev_hash: BlockHash, difficulty: Difficulty) -> Block {
+        let coinbase = Transaction {
+            inputs: vec![],
+            outputs: vec![Output {
+                to_addr: "Miner".to_owned(),
+                value: 2.0,
+                timestamp,
+                lock_until: None,
+            }],
+            witnesses: vec![],
+        };
+
+        let mut block = Block::new(index, timestamp, prev_hash, vec![coinbase]);
+        block.mine(difficulty);
+        block
+    }
+
+    #[test]
+    fn test_validator_creation() {
+        let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+        let validator = Validator::new(difficulty);
+
+        assert_eq!(validator.get_block_count(), 0);
+        assert_eq!(validator.get_difficulty(), difficulty);
+    }
+
+    #[test]
+    fn test_miner_session_creation() {
+        let session = MinerSession::new("miner1".to_string(), 1000000);
+
+        assert_eq!(session.miner_id, "miner1");
+        assert_eq!(session.block_accepted_at, 1000000);
+        assert_eq!(session.must_wait_until, 1000000 + 3_600_000);
+        assert!(session.is_active);
+    }
+
+    #[test]
+    fn test_validator_with_regtest_params() {
+        let params = ChainParams::regtest();
+        let mut validator = Validator::new_with_params(params.clone());
+        validator.start_new_round();
+
+        assert_eq!(validator.get_difficulty(), params.difficulty);
+        assert_eq!(validator.get_params(), &params);
+        assert_eq!(
+            validator.current_tonce.as_ref().unwrap().challenge_duration_ms,
+            params.tonce_challenge_duration_ms
+        );
+    }
+
+    #[test]
+    fn test_miner_session_custom_lockout() {
+        let session = MinerSession::new_with_lockout("miner1".to_string(), 1000000, 5_000);
+        assert_eq!(session.must_wait_until, 1000000 + 5_000);
+        assert!(session.is_lockout_expired(1000000 + 5_000));
+    }
+
+    #[test]
+    fn test_lockout_policy_durations() {
+        let fixed = LockoutPolicy::Fixed;
+        assert_eq!(fixed.lockout_duration(1000, 1), 1000);
+        assert_eq!(fixed.lockout_duration(1000, 5), 1000);
+
+        let exponential = LockoutPolicy::Exponential { max_duration_ms: 5000 };
+        assert_eq!(exponential.lockout_duration(1000, 1), 1000);
+        assert_eq!(exponential.lockout_duration(1000, 2), 2000);
+        assert_eq!(exponential.lockout_duration(1000, 3), 4000);
+        assert_eq!(exponential.lockout_duration(1000, 4), 5000);
+        assert_eq!(exponential.lockout_duration(1000, 200), 5000);
+    }
+
+    #[test]
+    fn test_exponential_lockout_streaks() {
+        let params = ChainParams::mainnet();
+        let base = params.lockout_duration_ms;
+        let mut validator = Validator::new_with_params(params);
+        validator.set_lockout_policy(LockoutPolicy::Exponential { max_duration_ms: base * 8 });
+
+        // Winning again right after each lockout doubles the next one
+        let mut time = 1_000_000;
+        assert_eq!(validator.next_lockout_duration("miner1", time), base);
+        time += base;
+        assert_eq!(validator.next_lockout_duration("miner1", time), base * 2);
+        time += base * 2;
+        assert_eq!(validator.next_lockout_duration("miner1", time), base * 4);
+
+        // Sitting out a full base period after the lockout resets the streak
+        time += base * 4 + base;
+        assert_eq!(validator.next_lockout_duration("miner1", time), base);
+
+        // Streaks are tracked per miner
+        assert_eq!(validator.next_lockout_duration("miner2", time), base);
+    }
+
+    #[test]
+    fn test_miner_session_lockout() {
+        let session = MinerSession::new("miner1".to_string(), 1000000);
+
+        // During lockout
+        assert!(!session.is_lockout_expired(1000000 + 1000));
+        assert!(session.seconds_remaining(1000000 + 1000) > 0);
+
+        // After lockout
+        assert!(session.is_lockout_expired(1000000 + 3_700_000));
+        assert_eq!(session.seconds_remaining(1000000 + 3_700_000), 0);
+    }
+
+    #[test]
+    fn test_start_new_round() {
+        let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+        let mut validator = Validator::new(difficulty);
+
+        validator.start_new_round();
+
+        assert!(validator.current_tonce.is_some());
+        assert!(validator.get_current_tonce().is_some());
+        assert_eq!(validator.attempted_this_round.len(), 0);
+    }
+
+    #[test]
+    fn test_commit_reveal() {
+        use crate::time_source::MockTimeSource;
+
+        let params = ChainParams::regtest();
+        let clock = MockTimeSource::new(50_000_000);
+        let mut validator = Validator::new_with_params(params.clone());
+        validator.set_time_source(Arc::new(clock.clone()));
+        validator.start_new_round();
+        assert!(validator.commit_timestamp("alice".to_string(), vec![0; 32]).is_err());
+
+        validator.set_commit_reveal(true);
+        let tonce = validator.get_current_tonce().unwrap();
+        let timestamp = find_valid_timestamp(tonce, clock.now(), 100000).unwrap();
+        let block = create_test_block(0, timestamp, BlockHash::ZERO, params.difficulty);
+        let salt = generate_salt();
+        let commitment = timestamp_commitment("alice", timestamp, &salt);
+
+        assert!(validator.commit_timestamp("alice".to_string(), vec![0; 4]).is_err());
+        assert_eq!(validator.commit_timestamp("alice".to_string(), commitment.clone()), Ok(()));
+        assert!(validator.commit_timestamp("alice".to_string(), commitment.clone()).is_err());
+        assert_eq!(validator.commit_timestamp("carol".to_string(), commitment.clone()), Ok(()));
+
+        // Revealing straight after committing is too soon, and uses up the miner's commitment
+        let dave = timestamp_commitment("dave", timestamp, &salt);
+        assert_eq!(validator.commit_timestamp("dave".to_string(), dave.clone()), Ok(()));
+        assert_eq!(
+            validator.validate_block_reveal(block.clone(), "dave".to_string(), Some(&salt)),
+            ValidationResult::RejectedRevealOutsideWindow
+        );
+        assert!(validator.commit_timestamp("dave".to_string(), dave).is_err());
+
+        // Commitments close once there is no longer time to reveal before the challenge expires
+        clock.advance(validator.reveal_delay_ms());
+        assert!(validator.commit_timestamp("erin".to_string(), commitment.clone()).is_err());
+
+        // No commitment, or a reveal that doesn't open it, is rejected
+        assert_eq!(
+            validator.validate_block_reveal(block.clone(), "bob".to_string(), Some(&salt)),
+            ValidationResult::RejectedMissingCommitment
+        );
+        assert_eq!(
+            validator.validate_block_reveal(block.clone(), "carol".to_string(), Some(&salt)),
+            ValidationResult::RejectedCommitmentMismatch
+        );
+
+        assert_eq!(
+            validator.validate_block_reveal(block, "alice".to_string(), Some(&salt)),
+            ValidationResult::Accepted
+        );
+        assert!(validator.commitments.is_empty());
+    }
+
+    #[test]
+    fn test_commit_reveal_after_challenge_expires() {
+        use crate::time_source::MockTimeSource;
+
+        let params = ChainParams::regtest();
+        let clock = MockTimeSource::new(50_000_000);
+        let mut validator = Validator::new_with_params(params.clone());
+        validator.set_time_source(Arc::new(clock.clone()));
+        validator.set_commit_reveal(true);
+        validator.start_new_round();
+
+        // A commitment made for the challenge can't be revealed once it expires
+        let tonce = validator.get_current_tonce().unwrap();
+        let timestamp = find_valid_timestamp(tonce, clock.now(), 100000).unwrap();
+        let late = create_test_block(0, timestamp, BlockHash::ZERO, params.difficulty);
+        let salt = generate_salt();
+        let commitment = timestamp_commitment("carol", timestamp, &salt);
+        assert_eq!(validator.commit_timestamp("carol".to_string(), commitment), Ok(()));
+        clock.advance(params.tonce_challenge_duration_ms);
+        assert!(validator.current_tonce.as_ref().unwrap().is_expired(clock.now()));
+        assert_eq!(
+            validator.validate_block_reveal(late, "carol".to_string(), Some(&salt)),
+            ValidationResult::RejectedRevealOutsideWindow
+        );
+
+        // Once any timestamp passes the tonce, skipping the commitment still isn't allowed
+        let block = create_test_block(0, clock.now(), BlockHash::ZERO, params.difficulty);
+        assert_eq!(
+            validator.validate_block_reveal(block.clone(), "alice".to_string(), Some(&salt)),
+            ValidationResult::RejectedMissingCommitment
+        );
+
+        // Commitments made after the challenge still wait out the reveal delay
+        let commitment = timestamp_commitment("bob", block.header.timestamp, &salt);
+        assert_eq!(validator.commit_timestamp("bob".to_string(), commitment), Ok(()));
+        clock.advance(validator.reveal_delay_ms());
+        assert_eq!(
+            validator.validate_block_reveal(block, "bob".to_string(), Some(&salt)),
+            ValidationResult::Accepted
+        );
+    }
+
+    #[test]
+    fn test_mock_clock_drives_rounds_and_lockouts() {
+        use crate::time_source::MockTimeSource;
+
+        let params = ChainParams::regtest();
+        let clock = MockTimeSource::new(50_000_000);
+        let mut validator = Validator::new_with_params(params.clone());
+        validator.set_time_source(Arc::new(clock.clone()));
+        validator.start_new_round();
+
+        let tonce = validator.get_current_tonce().unwrap();
+        let timestamp = find_valid_timestamp(tonce, clock.now(), 100000).unwrap();
+        clock.set(timestamp);
+        let block = create_test_block(0, timestamp, BlockHash::ZERO, params.difficulty);
+        assert_eq!(validator.validate_block_submission(block, "alice".to_string()), ValidationResult::Accepted);
+        assert!(validator.is_miner_in_lockout("alice"));
+
+        // The lockout ends when the clock says so, not when the test has waited long enough
+        clock.advance(params.lockout_duration_ms - 1);
+        assert!(validator.is_miner_in_lockout("alice"));
+        clock.advance(1);
+        assert!(!validator.is_miner_in_lockout("alice"));
+    }
+
+    #[test]
+    fn test_attempt_policy_refunds_timing_failures() {
+        use crate::time_source::MockTimeSource;
+
+        let params = ChainParams::regtest().with_difficulty(Difficulty::from_target(0x000FFFFFFFFFFFFFFFFFFFFFFFFFFFFF));
+        let clock = MockTimeSource::new(50_000_000);
+        let mut validator = Validator::new_with_params(params.clone());
+        validator.set_time_source(Arc::new(clock.clone()));
+        validator.start_new_round();
+        assert_eq!(validator.get_round_info_for("alice").remaining_attempts, Some(1));
+
+        // A mined block from a clock an hour fast
+        let skewed = create_test_block(0, clock.now() + 3_600_000, BlockHash::ZERO, params.difficulty);
+        let mut unmined = Block::new(0, clock.now() + 3_600_001, BlockHash::ZERO, vec![]);
+        unmined.header.bits = params.difficulty;
+        while params.difficulty.is_met_by(&unmined.hash()) {
+            unmined.header.nonce.0 += 1;
+        }
+        unmined.hash = unmined.hash();
+
+        // Strict: the failure costs the round
+        assert_eq!(validator.validate_block_submission(skewed.clone(), "alice".to_string()), ValidationResult::RejectedInvalidTimestamp);
+        assert_eq!(validator.remaining_attempts("alice"), 0);
+
+        // Forgiving: a mined block gets its attempt back, an unmined one doesn't
+        validator.start_new_round();
+        validator.set_attempt_policy(AttemptPolicy::forgiving());
+        let mut retry = skewed;
+        retry.header.timestamp += 1;
+        retry.mine(params.difficulty);
+        assert_eq!(validator.validate_block_submission(retry, "alice".to_string()), ValidationResult::RejectedInvalidTimestamp);
+        assert_eq!(validator.remaining_attempts("alice"), 1);
+        assert_eq!(validator.get_round_info().attempted_miners, 0);
+
+        assert_eq!(validator.validate_block_submission(unmined, "bob".to_string()), ValidationResult::RejectedInvalidTimestamp);
+        assert_eq!(validator.remaining_attempts("bob"), 0);
+
+        let tonce = validator.get_current_tonce().unwrap();
+        let timestamp = find_valid_timestamp(tonce, clock.now(), 100000).unwrap();
+        clock.set(timestamp);
+        let block = create_test_block(0, timestamp, BlockHash::ZERO, params.difficulty);
+        assert_eq!(validator.validate_block_submission(block, "alice".to_string()), ValidationResult::Accepted);
+    }
+
+    #[test]
+    fn test_miner_stats_recorded() {
+        use crate::time_source::MockTimeSource;
+
+        let params = ChainParams::regtest();
+        let clock = MockTimeSource::new(50_000_000);
+        let mut validator = Validator::new_with_params(params.clone());
+        validator.set_time_source(Arc::new(clock.clone()));
+        validator.start_new_round();
+
+        let tonce = validator.get_current_tonce().unwrap();
+        let timestamp = find_valid_timestamp(tonce, clock.now(), 100000).unwrap();
+        clock.set(timestamp);
+        let block = create_test_block(0, timestamp, BlockHash::ZERO, params.difficulty);
+        clock.advance(1500);
+        assert_eq!(validator.validate_block_submission(block.clone(), "alice".to_string()), ValidationResult::Accepted);
+        assert_eq!(validator.validate_block_submission(block, "bob".to_string()), ValidationResult::RejectedDuplicateBlock);
+
+        let alice = validator.miner_stats().get("alice").unwrap();
+        assert_eq!(alice.blocks_accepted, 1);
+        assert_eq!(alice.total_lockout_ms, params.lockout_duration_ms);
+        assert_eq!(alice.average_time_to_solution_ms(), Some(timestamp - 50_000_000 + 1500));
+        assert_eq!(validator.miner_stats().get("bob").unwrap().rejections["duplicate_block"], 1);
+
+        let decisions: Vec<_> = validator.recent_decisions().iter()
+            .map(|decision| (decision.miner_id.as_str(), decision.height, decision.result.as_str()))
+            .collect();
+        assert_eq!(decisions, vec![("alice", 0, "accepted"), ("bob", 1, "duplicate_block")]);
+
+        // alice's block ended the first round; bob's was judged in the next
+        let rounds = validator.round_history().rounds();
+        assert_eq!(rounds.len(), 2);
+        assert_eq!((rounds[0].round_start, rounds[0].round_end), (50_000_000, Some(timestamp + 1500)));
+        assert_eq!((rounds[0].tonce, rounds[0].winner.as_deref()), (Some(tonce), Some("alice")));
+        assert_eq!(rounds[1].miners["bob"].rejections["duplicate_block"], 1);
+
+        let path = std::env::temp_dir().join(format!("hourcoin-round-history-{}.csv", std::process::id()));
+        validator.export_round_history(ExportFormat::Csv, &path).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(csv.lines().count(), 3);
+        assert!(csv.lines().nth(1).unwrap().ends_with(",alice,alice,1,"));
+    }
+
+    #[test]
+    fn test_clock_drift_pause() {
+        use crate::time_source::MockTimeSource;
+
+        let params = ChainParams::regtest();
+        let clock = MockTimeSource::new(50_000_000);
+        let mut validator = Validator::new_with_params(params.clone());
+        validator.set_time_source(Arc::new(clock.clone()));
+        validator.start_new_round();
+
+        let tonce = validator.get_current_tonce().unwrap();
+        let timestamp = find_valid_timestamp(tonce, clock.now(), 100000).unwrap();
+        clock.set(timestamp);
+        let block = create_test_block(0, timestamp, BlockHash::ZERO, params.difficulty);
+
+        // A paused validator refuses the block without using up the miner's attempt
+        validator.set_clock_drift_paused(true);
+        assert_eq!(validator.validate_block_submission(block.clone(), "alice".to_string()), ValidationResult::RejectedClockDrift);
+        validator.set_clock_drift_paused(false);
+        assert_eq!(validator.validate_block_submission(block, "alice".to_string()), ValidationResult::Accepted);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        use crate::time_source::MockTimeSource;
+
+        let params = ChainParams::regtest();
+        let clock = MockTimeSource::new(50_000_000);
+        let mut validator = Validator::new_with_params(params.clone());
+        validator.set_time_source(Arc::new(clock.clone()));
+        validator.start_new_round();
+
+        let tonce = validator.get_current_tonce().unwrap();
+        let timestamp = find_valid_timestamp(tonce, clock.now(), 100000).unwrap();
+        clock.set(timestamp);
+        let block = create_test_block(0, timestamp, BlockHash::ZERO, params.difficulty);
+        assert_eq!(validator.validate_block_submission(block, "alice".to_string()), ValidationResult::Accepted);
+        validator.ban_miner("mallory");
+
+        let snapshot = validator.snapshot();
+        let json = serde_json::to_vec(&snapshot).unwrap();
+        let snapshot: ValidatorSnapshot = serde_json::from_slice(&json).unwrap();
+
+        let mut restored = Validator::new_with_params(params.clone());
+        restored.set_time_source(Arc::new(clock.clone()));
+        restored.restore(snapshot.clone()).unwrap();
+        assert_eq!(restored.blockchain.blocks.len(), 1);
+        assert_eq!(restored.blockchain.utxo_commitment(), validator.blockchain.utxo_commitment());
+        assert!(restored.is_miner_in_lockout("alice"));
+        assert!(restored.is_miner_banned("mallory"));
+        assert_eq!(restored.get_current_tonce(), validator.get_current_tonce());
+        assert_eq!(restored.snapshot(), validator.snapshot());
+
+        // A snapshot whose blocks were encoded at another canonical version is refused
+        let mut older = snapshot.clone();
+        older.canonical_version = CANONICAL_VERSION - 1;
+        assert!(Validator::new_with_params(params.clone()).restore(older).unwrap_err().contains("canonical version"));
+
+        // A snapshot whose blocks don't give the recorded UTXO set is refused
+        let mut tampered = snapshot;
+        tampered.utxo_set.pop();
+        let mut fresh = Validator::new_with_params(params);
+        assert!(fresh.restore(tampered).is_err());
+        assert!(fresh.blockchain.blocks.is_empty());
+    }
+
+    #[test]
+    fn test_submit_transaction_to_mempool() {
+        use crate::time_source::MockTimeSource;
+
+        let params = ChainParams::regtest();
+        let clock = MockTimeSource::new(50_000_000);
+        let mut validator = Validator::new_with_params(params.clone());
+        validator.set_time_source(Arc::new(clock.clone()));
+        validator.start_new_round();
+        let mut activity = validator.subscribe_activity();
+
+        let tonce = validator.get_current_tonce().unwrap();
+        let timestamp = find_valid_timestamp(tonce, clock.now(), 100000).unwrap();
+        clock.set(timestamp);
+        let block = create_test_block(0, timestamp, BlockHash::ZERO, params.difficulty);
+        let reward = block.body.transactions[0].outputs[0].clone();
+        assert_eq!(validator.validate_block_submission(block, "alice".to_string()), ValidationResult::Accepted);
+        let confirmed = activity.try_recv().unwrap();
+        assert_eq!((confirmed.address, confirmed.received, confirmed.height), (reward.to_addr.clone(), reward.value, Some(0)));
+
+        let spend = |input: Output| Transaction {
+            inputs: vec![input],
+            outputs: vec![Output { to_addr: "Bob".to_owned(), value: 1.5, timestamp, lock_until: None }],
+            witnesses: vec![],
+        };
+        let unknown = Output { to_addr: "Miner".to_owned(), value: 2.0, timestamp: 1, lock_until: None };
+        assert!(validator.submit_transaction(spend(unknown), None).is_err());
+
+        // expired by the next block, so it never waits for one
+        assert!(validator.submit_transaction(spend(reward.clone()), Some(Expiry::Height(1))).is_err());
+        assert!(validator.submit_transaction(spend(reward.clone()), Some(Expiry::Height(2))).unwrap().is_empty());
+        assert_eq!(validator.mempool().len(), 1);
+
+        // pooled: the spender and the payee both hear of it, unconfirmed
+        let spent = activity.try_recv().unwrap();
+        assert_eq!((spent.address, spent.sent, spent.height), (reward.to_addr, reward.value, None));
+        let paid = activity.try_recv().unwrap();
+        assert_eq!((paid.address.as_str(), paid.received, paid.height), ("Bob", 1.5, None));
+        assert!(activity.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_generate_blocks() {
+        use crate::time_source::MockTimeSource;
+
+        let regtest = |clock: &MockTimeSource| {
+            let mut validator = Validator::new_with_params(ChainParams::regtest());
+            validator.set_time_source(Arc::new(clock.clone()));
+            validator.start_new_round();
+            validator
+        };
+        let clock = MockTimeSource::new(50_000_000);
+        let mut validator = regtest(&clock);
+
+        let hashes = validator.generate_blocks(5, "alice").unwrap();
+        assert_eq!(validator.get_block_count(), 5);
+        assert_eq!(validator.blockchain.balance_of(&"alice".to_string()), 5.0 * validator.get_params().coinbase_value);
+        assert!(!validator.is_miner_in_lockout(GENERATED_BLOCK_MINER_ID));
+        // the same state and clock give the same blocks
+        assert_eq!(regtest(&MockTimeSource::new(50_000_000)).generate_blocks(5, "alice").unwrap(), hashes);
+
+        // pooled transactions are mined
+        let reward = validator.blockchain.unspent_outputs_of(&"alice".to_string())[0].clone();
+        let timestamp = validator.blockchain.blocks.last().unwrap().header.timestamp;
+        let payment = Transaction {
+            inputs: vec![reward.clone()],
+            outputs: vec![Output { to_addr: "bob".to_owned(), value: reward.value - 0.5, timestamp, lock_until: None }],
+            witnesses: vec![],
+        };
+        validator.submit_transaction(payment, None).unwrap();
+        validator.generate_blocks(1, "carol").unwrap();
+        assert!(validator.mempool().is_empty());
+        assert_eq!(validator.blockchain.balance_of(&"bob".to_string()), reward.value - 0.5);
+        assert_eq!(validator.blockchain.balance_of(&"carol".to_string()), validator.get_params().coinbase_value);
+
+        // miners carry on from the generated tip
+        let tonce = validator.get_current_tonce().unwrap();
+        let timestamp = find_valid_timestamp(tonce, clock.now().max(validator.blockchain.blocks[5].header.timestamp + 1), 100_000).unwrap();
+        clock.set(timestamp);
+        let block = create_test_block(6, timestamp, validator.blockchain.blocks[5].hash, validator.get_difficulty());
+        assert_eq!(validator.validate_block_submission(block, "dave".to_string()), ValidationResult::Accepted);
+
+        let mut mainnet = Validator::new_with_params(ChainParams::mainnet());
+        assert!(mainnet.generate_blocks(1, "alice").is_err());
+    }
+
+    #[test]
+    fn test_decision_log_replay() {
+        use crate::time_source::MockTimeSource;
+
+        let path = std::env::temp_dir().join(format!("hourcoin-validator-decisions-{}.log", std::process::id()));
+        let params = ChainParams::regtest();
+        let clock = MockTimeSource::new(50_000_000);
+        let mut validator = Validator::new_with_params(params.clone());
+        validator.set_time_source(Arc::new(clock.clone()));
+        validator.set_decision_log(DecisionLog::open(&path).unwrap());
+        validator.start_new_round();
+
+        let tonce = validator.get_current_tonce().unwrap();
+        let timestamp = find_valid_timestamp(tonce, clock.now(), 100000).unwrap();
+        clock.set(timestamp);
+        let block = create_test_block(0, timestamp, BlockHash::ZERO, params.difficulty);
+        assert_eq!(validator.validate_block_submission(block.clone(), "alice".to_string()), ValidationResult::Accepted);
+
+        // bob's block in the next round is refused, using up his attempt
+        let mut bad = create_test_block(1, timestamp + 1, block.hash, params.difficulty);
+        bad.header.nonce.0 += 1;
+        assert_ne!(validator.validate_block_submission(bad, "bob".to_string()), ValidationResult::Accepted);
+        assert_eq!(validator.remaining_attempts("bob"), 0);
+
+        let records = DecisionLog::read(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].result, "accepted");
+        assert_eq!(records[0].tonce, Some(tonce));
+        assert_eq!(records[1].miner_id, "bob");
+
+        // a validator that crashed before snapshotting any of it catches up from the log
+        let mut restored = Validator::new_with_params(params);
+        restored.set_time_source(Arc::new(clock.clone()));
+        restored.start_new_round();
+        assert_eq!(restored.replay_decisions(&records).unwrap(), 2);
+        assert_eq!(restored.blockchain.blocks.len(), 1);
+        assert!(restored.is_miner_in_lockout("alice"));
+        assert_eq!(restored.remaining_attempts("bob"), 0);
+        // decisions the chain already reflects are skipped
+        assert_eq!(restored.replay_decisions(&records[..1]).unwrap(), 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_block_store_follows_the_chain() {
+        use crate::time_source::MockTimeSource;
+
+        let dir = std::env::temp_dir().join(format!("hourcoin-validator-blocks-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let regtest = |address: &str| {
+            let mut validator = Validator::new_with_params(ChainParams::regtest());
+            validator.set_time_source(Arc::new(MockTimeSource::new(50_000_000)));
+            validator.start_new_round();
+            validator.generate_blocks(3, address).unwrap();
+            validator
+        };
+
+        // blocks accepted before the store was set are caught up on
+        let mut validator = regtest("alice");
+        validator.set_block_store(BlockStore::open(&dir).unwrap()).unwrap();
+        validator.generate_blocks(2, "alice").unwrap();
+        let store = validator.block_store().unwrap();
+        assert_eq!(store.len(), 5);
+        assert_eq!(store.header(4).unwrap().hash(), validator.blockchain.blocks[4].hash);
+        drop(validator);
+
+        // a different chain replaces the blocks it doesn't share
+        let mut other = regtest("bob");
+        other.set_block_store(BlockStore::open(&dir).unwrap()).unwrap();
+        let store = other.block_store().unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.block(0).unwrap().hash, other.blockchain.blocks[0].hash);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fast_sync_from_utxo_snapshot() {
+        use crate::time_source::MockTimeSource;
+
+        let clock = MockTimeSource::new(50_000_000);
+        let regtest = || {
+            let mut validator = Validator::new_with_params(ChainParams::regtest());
+            validator.set_time_source(Arc::new(clock.clone()));
+            validator.start_new_round();
+            validator
+        };
+        let mut source = regtest();
+        source.generate_blocks(4, "alice").unwrap();
+        assert!(source.utxo_snapshot().is_err());
+        source.set_node_key(NodeKey::generate());
+        let snapshot = source.utxo_snapshot().unwrap().clone();
+        assert_eq!(snapshot.height, 2);
+        let headers: Vec<BlockHeader> = source.blockchain.blocks[..=2].iter().map(|block| block.header.clone()).collect();
+        let next = source.blockchain.blocks[3].clone();
+        let key = snapshot.public_key.clone();
+
+        // an unsigned snapshot isn't taken, nor one signed by a key nobody trusts
+        let mut synced = regtest();
+        assert!(synced.adopt_utxo_snapshot(&headers, source.blockchain.utxo_snapshot(2).unwrap(), next.clone(), Some(&key)).is_err());
+        assert!(synced.adopt_utxo_snapshot(&headers, snapshot.clone(), next.clone(), None).unwrap_err().contains("No trusted key"));
+        let stranger = NodeKey::generate().public_key();
+        assert!(synced.adopt_utxo_snapshot(&headers, snapshot.clone(), next.clone(), Some(&stranger)).unwrap_err().contains("not trusted"));
+        // nor without the block after it applying
+        let mut bad_next = next.clone();
+        bad_next.header.prev_block_hash = BlockHash::ZERO;
+        assert!(synced.adopt_utxo_snapshot(&headers, snapshot.clone(), bad_next, Some(&key)).is_err());
+        assert_eq!(synced.get_block_count(), 0);
+
+        synced.adopt_utxo_snapshot(&headers, snapshot, next, Some(&key)).unwrap();
+        assert_eq!(synced.get_block_count(), 4);
+        assert_eq!(synced.blockchain.utxo_commitment(), source.blockchain.utxo_commitment());
+        assert_eq!(synced.blockchain.utxo_commitment(), source.blockchain.utxo_commitment());
+        synced.generate_blocks(1, "bob").unwrap();
+        assert!(synced.blockchain.check_invariants().is_ok());
+
+        // its snapshots carry the base instead of blocks it never had
+        let saved = synced.snapshot();
+        assert_eq!(saved.blocks.len(), 2);
+        let mut restored = regtest();
+        restored.restore(saved).unwrap();
+        assert_eq!(restored.get_block_count(), 5);
+        assert_eq!(restored.blockchain.utxo_commitment(), synced.blockchain.utxo_commitment());
+    }
+
+    #[test]
+    fn test_operator_pause_and_ban() {
+        use crate::time_source::MockTimeSource;
+
+        let params = ChainParams::regtest();
+        let clock = MockTimeSource::new(50_000_000);
+        let mut validator = Validator::new_with_params(params.clone());
+        validator.set_time_source(Arc::new(clock.clone()));
+        validator.start_new_round();
+
+        let tonce = validator.get_current_tonce().unwrap();
+        let timestamp = find_valid_timestamp(tonce, clock.now(), 100000).unwrap();
+        clock.set(timestamp);
+        let block = create_test_block(0, timestamp, BlockHash::ZERO, params.difficulty);
+
+        // Neither refusal uses up the miner's attempt
+        validator.set_paused(true);
+        assert_eq!(validator.validate_block_submission(block.clone(), "alice".to_string()), ValidationResult::RejectedPaused);
+        validator.set_paused(false);
+        assert!(validator.ban_miner("alice"));
+        assert_eq!(validator.validate_block_submission(block.clone(), "alice".to_string()), ValidationResult::RejectedMinerBanned);
+        assert!(validator.unban_miner("alice"));
+        assert!(!validator.is_miner_banned("alice"));
+        assert_eq!(validator.validate_block_submission(block, "alice".to_string()), ValidationResult::Accepted);
+
+        // Only harder targets are allowed
+        let harder = Difficulty::from_target(params.difficulty.target() / 2);
+        assert!(validator.set_difficulty(Difficulty::EASIEST).is_err());
+        validator.set_difficulty(harder).unwrap();
+        assert_eq!(validator.get_difficulty(), harder);
+    }
+
+    #[test]
+    fn test_duplicate_block_submission() {
+        let difficulty = Difficulty::from_target(0x0FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+        let mut validator = Validator::new(difficulty);
+        validator.start_new_round();
+
+        // A block that fails the chain rules (wrong index) is remembered
+        let tonce = validator.get_current_tonce().unwrap();
+        let timestamp = find_valid_timestamp(tonce, now(), 100000).unwrap();
+        let bad_block = create_test_block(5, timestamp, BlockHash::ZERO, difficulty);
+        assert!(matches!(
+            validator.validate_block_submission(bad_block.clone(), "alice".to_string()),
+            ValidationResult::RejectedBlockchainValidation(_)
+        ));
+
+        // Resubmitting it, or another miner sending the same block, is a duplicate
+        assert_eq!(validator.validate_block_submission(bad_block.clone(), "alice".to_string()), ValidationResult::RejectedDuplicateBlock);
+        assert_eq!(validator.validate_block_submission(bad_block, "bob".to_string()), ValidationResult::RejectedDuplicateBlock);
+
+        // ...and doesn't burn bob's attempt
+        let block = create_test_block(0, timestamp, BlockHash::ZERO, difficulty);
+        assert_eq!(validator.validate_block_submission(block.clone(), "bob".to_string()), ValidationResult::Accepted);
+        assert_eq!(validator.validate_block_submission(block, "carol".to_string()), ValidationResult::RejectedDuplicateBlock);
+    }
+
+    #[test]
+    fn test_timing_rejection_is_not_remembered() {
+        use crate::time_source::MockTimeSource;
+
+        let params = ChainParams::regtest();
+        let clock = MockTimeSource::new(50_000_000);
+        let mut validator = Validator::new_with_params(params.clone());
+        validator.set_time_source(Arc::new(clock.clone()));
+        validator.start_new_round();
+
+        // Too far ahead of the clock now, but not once the clock catches up
+        let early = create_test_block(0, clock.now() + 3_600_000, BlockHash::ZERO, params.difficulty);
+        assert_eq!(validator.validate_block_submission(early.clone(), "alice".to_string()), ValidationResult::RejectedInvalidTimestamp);
+
+        clock.advance(3_600_000);
+        validator.start_new_round();
+        assert_ne!(validator.validate_block_submission(early, "alice".to_string()), ValidationResult::RejectedDuplicateBlock);
+    }
+
+    #[test]
+    fn test_validation_hooks() {
+        use std::sync::Mutex;
+
+        struct DenyList {
+            denied: Vec<String>,
+            events: Arc<Mutex<Vec<String>>>,
+        }
+        impl ValidationHook for DenyList {
+            fn name(&self) -> &str {
+                "deny_list"
+            }
+            fn check_block(&mut self, block: &Block, _context: &HookContext) -> Result<(), String> {
+                match block.body.transactions.iter().flat_map(|tx| &tx.outputs).find(|output| self.denied.contains(&output.to_addr)) {
+                    Some(output) => Err(format!("pays {}", output.to_addr)),
+                    None => Ok(()),
+                }
+            }
+            fn on_block_accepted(&mut self, block: &Block, context: &HookContext) {
+                self.events.lock().unwrap().push(format!("accepted {} from {}", block.header.index, context.miner_id));
+            }
+            fn on_block_rejected(&mut self, _block: &Block, context: &HookContext, result: &ValidationResult) {
+                self.events.lock().unwrap().push(format!("rejected {} from {}", result.reason(), context.miner_id));
+            }
+            fn on_miner_locked_out(&mut self, miner_id: &str, _until: u128) {
+                self.events.lock().unwrap().push(format!("{} locked out", miner_id));
+            }
+            fn on_round_started(&mut self, round: &RoundStart) {
+                self.events.lock().unwrap().push(format!("round for {}", round.height));
+            }
+        }
+
+        let difficulty = Difficulty::from_target(0x0FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+        let mut validator = Validator::new(difficulty);
+        let events = Arc::new(Mutex::new(vec![]));
+        validator.add_validation_hook(Box::new(DenyList { denied: vec!["Mallory".to_string()], events: events.clone() }));
+        validator.start_new_round();
+
+        let tonce = validator.get_current_tonce().unwrap();
+        let timestamp = find_valid_timestamp(tonce, now(), 100000).unwrap();
+        let mut denied = Block::new(0, timestamp, BlockHash::ZERO, vec![TransactionBuilder::new(timestamp).pay_to("Mallory", 2.0).build_coinbase().unwrap()]);
+        denied.mine(difficulty);
+        assert_eq!(
+            validator.validate_block_submission(denied, "alice".to_string()),
+            ValidationResult::RejectedByHook("deny_list: pays Mallory".to_string())
+        );
+        assert_eq!(validator.get_block_count(), 0);
+
+        let block = create_test_block(0, timestamp, BlockHash::ZERO, difficulty);
+        assert_eq!(validator.validate_block_submission(block, "bob".to_string()), ValidationResult::Accepted);
+        assert_eq!(*events.lock().unwrap(), vec!["round for 0", "rejected hook from alice", "accepted 0 from bob", "bob locked out", "round for 1"]);
+    }
+
+    #[test]
+    fn test_hooks_do_not_check_peer_blocks() {
+        struct RejectAll;
+        impl ValidationHook for RejectAll {
+            fn name(&self) -> &str {
+                "reject_all"
+            }
+            fn check_block(&mut self, _block: &Block, _context: &HookContext) -> Result<(), String> {
+                Err("no".to_string())
+            }
+        }
+
+        let difficulty = Difficulty::from_target(0x0FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+        let keys: Vec<NodeKey> = (0..2).map(|_| NodeKey::generate()).collect();
+        let set = ValidatorSet::with_majority(keys.iter().map(|k| k.public_key()).collect()).unwrap();
+        let mut validators: Vec<Validator> = keys.into_iter().map(|key| {
+            let mut validator = Validator::new(difficulty);
+            validator.set_node_key(key);
+            validator.set_validator_set(set.clone()).unwrap();
+            validator.start_new_round();
+            validator
+        }).collect();
+        validators[1].add_validation_hook(Box::new(RejectAll));
+
+        let timestamp = find_valid_timestamp(validators[0].get_current_tonce().unwrap(), now(), 100000).unwrap();
+        let block = create_test_block(0, timestamp, BlockHash::ZERO, difficulty);
+
+        // A block proposed by a peer is judged by the shared rules alone
+        assert_eq!(validators[0].validate_block_submission(block.clone(), "alice".to_string()), ValidationResult::Accepted);
+        let vote = validators[0].get_last_vote().cloned().unwrap();
+        assert!(validators[1].handle_peer_proposal(block, "alice".to_string(), vote).is_ok());
+        assert!(validators[1].is_block_final(0));
+    }
+
+    #[test]
+    fn test_stale_round_rejected() {
+        let difficulty = Difficulty::from_target(0x0FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+        let mut validator = Validator::new(difficulty);
+        validator.start_new_round();
+        let first_round = validator.get_round_info().round_id;
+        assert_eq!(first_round, round_id(validator.get_round_info().round_start, &BlockHash::ZERO));
+
+        let tonce = validator.get_current_tonce().unwrap();
+        let timestamp = find_valid_timestamp(tonce, now(), 100000).unwrap();
+        let block = create_test_block(0, timestamp, BlockHash::ZERO, difficulty);
+        assert_eq!(validator.validate_round_submission(block.clone(), "alice".to_string(), None, &first_round), ValidationResult::Accepted);
+        assert_ne!(validator.current_round_id(), first_round);
+
+        // bob's submission for the first round arrives after alice's block ended it
+        let tonce = validator.get_current_tonce().unwrap();
+        let timestamp = find_valid_timestamp(tonce, timestamp + 1, 100000).unwrap();
+        let late = create_test_block(1, timestamp, block.hash, difficulty);
+        let attempts = validator.remaining_attempts("bob");
+        assert_eq!(validator.validate_round_submission(late.clone(), "bob".to_string(), None, &first_round), ValidationResult::RejectedStaleRound);
+        assert_eq!(validator.remaining_attempts("bob"), attempts);
+
+        let current_round = validator.current_round_id();
+        assert_eq!(validator.validate_round_submission(late, "bob".to_string(), None, &current_round), ValidationResult::Accepted);
+    }
+
+    #[test]
+    fn test_hybrid_blocks_commit_to_their_round() {
+        use crate::time_source::MockTimeSource;
+
+        let clock = MockTimeSource::new(50_000_000);
+        let mut validator = Validator::new_with_params(ChainParams::regtest().with_hybrid_consensus());
+        validator.set_time_source(Arc::new(clock.clone()));
+        validator.start_new_round();
+        validator.generate_blocks(3, "alice").unwrap();
+        assert_eq!(validator.blockchain.consensus_engine().name(), "hybrid");
+        assert!(validator.blockchain.blocks.iter().all(|block| block.header.round.is_some()));
+        assert!(validator.blockchain.verify_chain().is_ok());
+
+        let tip = validator.blockchain.blocks[2].clone();
+        let tonce = validator.get_current_tonce().unwrap();
+        let timestamp = find_valid_timestamp(tonce, clock.now().max(tip.header.timestamp + 1), 100_000).unwrap();
+        clock.set(timestamp);
+        let round_start = validator.get_round_info().round_start;
+        let difficulty = validator.get_difficulty();
+        let block_for = |round: Option<RoundCommitment>| {
+            let mut block = create_test_block(3, timestamp, tip.hash, difficulty);
+            block.header.round = round;
+            block.mine(difficulty);
+            block
+        };
+
+        // a block committing to an earlier round is stale, without using the attempt
+        let stale = block_for(Some(RoundCommitment { round_start: round_start - 1, tonce, signature: None }));
+        assert_eq!(validator.validate_block_submission(stale, "bob".to_string()), ValidationResult::RejectedStaleRound);
+        assert_eq!(validator.remaining_attempts("bob"), 1);
+
+        assert!(matches!(validator.validate_block_submission(block_for(None), "carol".to_string()),
+            ValidationResult::RejectedBlockchainValidation(detail) if detail == "MissingRoundCommitment"));
+        let block = block_for(Some(RoundCommitment { round_start, tonce, signature: None }));
+        assert_eq!(validator.validate_block_submission(block, "bob".to_string()), ValidationResult::Accepted);
+        assert!(validator.blockchain.verify_chain().is_ok());
+    }
+
+    #[test]
+    fn test_round_commitments_are_signed() {
+        use crate::receipt::verify_round_commitment;
+        use crate::time_source::MockTimeSource;
+
+        let clock = MockTimeSource::new(50_000_000);
+        let mut validator = Validator::new_with_params(ChainParams::regtest());
+        validator.set_time_source(Arc::new(clock.clone()));
+        let node_key = NodeKey::generate();
+        let public_key = node_key.public_key();
+        validator.set_node_key(node_key);
+        validator.start_new_round();
+        validator.generate_blocks(2, "alice").unwrap();
+        for block in &validator.blockchain.blocks {
+            assert_eq!(verify_round_commitment(&block.header, &public_key), Ok(()));
+        }
+
+        let tip = validator.blockchain.blocks[1].clone();
+        let info = validator.get_round_info();
+        let tonce = info.tonce.unwrap();
+        let timestamp = find_valid_timestamp(tonce, clock.now().max(tip.header.timestamp + 1), 100_000).unwrap();
+        clock.set(timestamp);
+        let difficulty = validator.get_difficulty();
+        let block_for = |signature: Option<Vec<u8>>| {
+            let mut block = create_test_block(2, timestamp, tip.hash, difficulty);
+            block.header.round = Some(RoundCommitment { round_start: info.round_start, tonce, signature });
+            block.mine(difficulty);
+            block
+        };
+
+        // a commitment we didn't sign doesn't name one of our rounds
+        assert_eq!(validator.validate_block_submission(block_for(None), "bob".to_string()), ValidationResult::RejectedStaleRound);
+        let forged = sign_round(&NodeKey::generate(), &tip.hash, info.round_start, tonce);
+        assert_eq!(validator.validate_block_submission(block_for(Some(forged)), "bob".to_string()), ValidationResult::RejectedStaleRound);
+
+        let block = block_for(info.round_signature.clone());
+        assert_eq!(validator.validate_block_submission(block, "bob".to_string()), ValidationResult::Accepted);
+        assert_eq!(verify_round_commitment(&validator.blockchain.blocks[2].header, &public_key), Ok(()));
+    }
+
+    #[test]
+    fn test_accepted_block_receipt() {
+        let difficulty = Difficulty::from_target(0x0FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+        let mut validator = Validator::new(difficulty);
+        let node_key = NodeKey::generate();
+        let public_key = node_key.public_key();
+        validator.set_node_key(node_key);
+        validator.start_new_round();
+
+        let tonce = validator.get_current_tonce().unwrap();
+        let timestamp = find_valid_timestamp(tonce, now(), 100000).unwrap();
+        let block = create_test_block(0, timestamp, BlockHash::ZERO, difficulty);
+        assert_eq!(validator.validate_block_submission(block.clone(), "alice".to_string()), ValidationResult::Accepted);
+
+        let receipt = validator.get_last_receipt().expect("receipt for accepted block");
+        assert_eq!(receipt.block_hash, block.hash);
+        assert_eq!(receipt.height, 0);
+        assert_eq!(receipt.miner_id, "alice");
+        assert_eq!(crate::receipt::verify_receipt(receipt, Some(&public_key)), Ok(()));
+        assert_eq!(validator.get_receipt(0), Some(receipt));
+    }
+
+    #[test]
+    fn test_two_of_three_validators_finalize_block() {
+        let difficulty = Difficulty::from_target(0x0FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+        let keys: Vec<NodeKey> = (0..3).map(|_| NodeKey::generate()).collect();
+        let set = ValidatorSet::with_majority(keys.iter().map(|k| k.public_key()).collect()).unwrap();
+
+        let mut validators: Vec<Validator> = keys.into_iter().map(|key| {
+            let mut validator = Validator::new(difficulty);
+            validator.set_node_key(key);
+            validator.set_validator_set(set.clone()).unwrap();
+            validator.start_new_round();
+            validator
+        }).collect();
+
+        let tonce = validators[0].get_current_tonce().unwrap();
+        let timestamp = find_valid_timestamp(tonce, now(), 100000).unwrap();
+        let block = create_test_block(0, timestamp, BlockHash::ZERO, difficulty);
+
+        // Accepted locally, but not final until a peer agrees
+        assert_eq!(validators[0].validate_block_submission(block.clone(), "alice".to_string()), ValidationResult::Accepted);
+        assert!(!validators[0].is_block_final(0));
+        let proposer_vote = validators[0].get_last_vote().cloned().unwrap();
+
+        let peer_vote = validators[1].handle_peer_proposal(block.clone(), "alice".to_string(), proposer_vote).unwrap();
+        assert!(validators[1].is_block_final(0));
+        assert!(validators[1].is_miner_in_lockout("alice"));
+
+        let certificate = validators[0].add_peer_vote(peer_vote).unwrap().expect("quorum reached");
+        assert!(validators[0].is_block_final(0));
+
+        // The third validator learns of finality from the certificate alone
+        assert_eq!(validators[2].add_certificate(certificate), Ok(()));
+        assert_eq!(validators[2].finalized_height(), Some(0));
+
+        // A conflicting block at the same height is refused
+        let mut other = create_test_block(0, timestamp + 1, BlockHash::ZERO, difficulty);
+        other.body.transactions[0].outputs[0].to_addr = "Mallory".to_string();
+        other.mine(difficulty);
+        let bogus_vote = AcceptanceVote::sign(&NodeKey::generate(), &other.hash, 0);
+        assert!(validators[1].handle_peer_proposal(other, "mallory".to_string(), bogus_vote).is_err());
+    }
+
+    #[test]
+    fn test_certificate_rolls_back_provisional_block() {
+        let difficulty = Difficulty::from_target(0x0FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+        let keys: Vec<NodeKey> = (0..3).map(|_| NodeKey::generate()).collect();
+        let set = ValidatorSet::with_majority(keys.iter().map(|k| k.public_key()).collect()).unwrap();
+        let mut validators: Vec<Validator> = keys.into_iter().map(|key| {
+            let mut validator = Validator::new(difficulty);
+            validator.set_node_key(key);
+            validator.set_validator_set(set.clone()).unwrap();
+            validator.start_new_round();
+            validator
+        }).collect();
+
+        // The first two validators finalize a block...
+        let timestamp = find_valid_timestamp(validators[0].get_current_tonce().unwrap(), now(), 100000).unwrap();
+        let block = create_test_block(0, timestamp, BlockHash::ZERO, difficulty);
+        assert_eq!(validators[0].validate_block_submission(block.clone(), "alice".to_string()), ValidationResult::Accepted);
+        let proposer_vote = validators[0].get_last_vote().cloned().unwrap();
+        let peer_vote = validators[1].handle_peer_proposal(block.clone(), "alice".to_string(), proposer_vote).unwrap();
+        let certificate = validators[0].add_peer_vote(peer_vote).unwrap().expect("quorum reached");
+
+        // ...while the third had provisionally accepted another
+        let other_timestamp = find_valid_timestamp(validators[2].get_current_tonce().unwrap(), timestamp + 1, 100000).unwrap();
+        let other = create_test_block(0, other_timestamp, BlockHash::ZERO, difficulty);
+        assert_eq!(validators[2].validate_block_submission(other, "bob".to_string()), ValidationResult::Accepted);
+        assert!(validators[2].get_receipt(0).is_some());
+
+        assert_eq!(validators[2].add_certificate(certificate.clone()), Ok(()));
+        assert!(validators[2].blockchain.blocks.is_empty());
+        assert!(validators[2].get_receipt(0).is_none());
+        assert_eq!(validators[2].missing_finalized_block(), Some((0, block.hash)));
+
+        assert_eq!(validators[2].apply_synced_blocks(vec![block.clone()]), Ok(()));
+        assert_eq!(validators[2].blockchain.blocks[0].hash, block.hash);
+        assert_eq!(validators[2].missing_finalized_block(), None);
+        assert!(validators[2].is_block_final(0));
+
+        // A finalized block is never rolled back
+        let mut forged = certificate;
+        forged.block_hash = BlockHash::from_bytes([7; 32]);
+        assert!(validators[0].add_certificate(forged).is_err());
+        assert_eq!(validators[0].blockchain.blocks[0].hash, block.hash);
+    }
+
+    #[test]
+    fn test_federation_co_signs_receipt() {
+        use crate::federation::{Federation, FederatedReceipt};
+
+        let (federation, members) = Federation::trusted_dealer_keygen(3, 2).unwrap();
+        let mut validators: Vec<Validator> = members.into_iter().map(|member| {
+            let mut validator = Validator::new_with_params(ChainParams::regtest());
+            validator.set_federation(federation.clone(), member).unwrap();
+            validator
+        }).collect();
+        validators[0].generate_blocks(1, "alice").unwrap();
+        let block = validators[0].blockchain.blocks[0].clone();
+        validators[1].apply_synced_blocks(vec![block.clone()]).unwrap();
+
+        // The coordinator's two rounds, as the server runs them
+        let message = FederatedReceipt::message(&block.hash, 0, "alice", 1_000);
+        let mut session = federation.signing_session(&message);
+        for validator in &mut validators[..2] {
+            let (member_id, commitments) = validator.federation_commit().unwrap();
+            session.add_commitments(member_id, commitments).unwrap();
+        }
+        let package = session.signing_package().unwrap();
+
+        // A member only signs a receipt for the block it holds
+        assert!(validators[1].federation_sign(&package, 0, "bob", 1_000).is_err());
+        for validator in &mut validators[..2] {
+            let (member_id, share) = validator.federation_sign(&package, 0, "alice", 1_000).unwrap();
+            session.add_share(member_id, share).unwrap();
+        }
+
+        let receipt = FederatedReceipt { block_hash: block.hash, height: 0, miner_id: "alice".to_string(), accepted_at: 1_000,
+            signature: session.aggregate().unwrap() };
+        assert_eq!(validators[1].add_federated_receipt(receipt.clone()), Ok(()));
+        assert_eq!(validators[1].get_federated_receipt(0), Some(&receipt));
+        assert!(validators[2].add_federated_receipt(receipt.clone()).is_err());
+
+        let mut forged = receipt;
+        forged.miner_id = "mallory".to_string();
+        assert!(validators[0].add_federated_receipt(forged).is_err());
+    }
+
+    #[test]
+    fn test_validator_set_requires_member_key() {
+        let mut validator = Validator::new(Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF));
+        let set = ValidatorSet::with_majority(vec![NodeKey::generate().public_key()]).unwrap();
+        assert!(validator.set_validator_set(set.clone()).is_err());
+
+        validator.set_node_key(NodeKey::generate());
+        assert!(validator.set_validator_set(set).is_err());
+        assert!(!validator.is_block_final(0));
+    }
+
+    #[test]
+    fn test_vrf_round_challenge() {
+        let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+        let mut validator = Validator::new(difficulty);
+        validator.start_new_round();
+        assert!(validator.get_round_info().vrf_proof.is_none());
+
+        validator.set_vrf_keypair(VrfKeypair::generate());
+        validator.start_new_round();
+        let info = validator.get_round_info();
+
+        // Anyone holding the proof can recompute the published tonce
+        let randomness = info.vrf_proof.expect("VRF proof").verify().unwrap();
+        let tonce = TonceChallenge::tonce_from_randomness(&randomness, validator.get_params().tonce_bits).unwrap();
+        assert_eq!(info.tonce, Some(tonce));
+    }
+
+    #[test]
+    fn test_get_round_info() {
+        let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+        let mut validator = Validator::new(difficulty);
+
+        validator.start_new_round();
+        let info = validator.get_round_info();
+
+        assert!(info.tonce.is_some());
+        assert_eq!(info.attempted_miners, 0);
+        assert_eq!(info.active_lockouts, 0);
+    }
+
+    #[test]
+    fn test_miner_lockout_tracking() {
+        let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+        let mut validator = Validator::new(difficulty);
+
+        // Miner not in lockout initially
+        assert!(!validator.is_miner_in_lockout("miner1"));
+
+        // Add a session
+        let session = MinerSession::new("miner1".to_string(), now());
+        validator.active_sessions.insert("miner1".to_string(), session);
+
+        // Miner should now be in lockout
+        assert!(validator.is_miner_in_lockout("miner1"));
+        assert!(validator.get_miner_lockout_remaining("miner1") > 0);
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        let difficulty = Difficulty::from_target(0x00FFFFFFFFFFFFFFFFFFFFFFFFFFFFFF);
+        let mut validator = Validator::new(difficulty);
+        validator.start_new_round();
+        assert!(validator.check_invariants().is_ok());
+
+        // A lockout session without a matching accepted block is incoherent
+        let session = MinerSession::new("miner1".to_string(), now());
+        validator.active_sessions.insert("miner1".to_string(), session);
+        assert!(validator.check_invariants().is_err());
     }
 
     #[test]
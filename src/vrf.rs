@@ -0,0 +1,140 @@
+//! Verifiable random function for Hourcoin round challenges
+//!
+//! A SHA-256 tonce is predictable by anyone who knows the previous block. With
+//! a VRF the validator evaluates its secret key over the round input
+//! (previous block hash and timestamp) and publishes the output together with
+//! a proof. Nobody can predict the tonce before the validator publishes it,
+//! but every miner can check that it was derived honestly from the chain tip.
+//!
+//! Uses the sr25519 VRF from `schnorrkel`.
+
+use schnorrkel::vrf::{VRFPreOut, VRFProof};
+use schnorrkel::{signing_context, PublicKey};
+use crate::node_key::SeededKeypair;
+use crate::{u128_bytes, BlockHash};
+
+/// Domain separator for round challenge VRF evaluations
+const VRF_SIGNING_CONTEXT: &[u8] = b"hourcoin-round-challenge";
+
+/// Context used to extract randomness from the VRF output
+const VRF_OUTPUT_CONTEXT: &[u8] = b"hourcoin-tonce";
+
+/// Validator key used to evaluate round VRFs
+pub struct VrfKeypair(SeededKeypair);
+
+impl VrfKeypair {
+    /// Generate a fresh random keypair
+    pub fn generate() -> Self {
+        VrfKeypair(SeededKeypair::generate())
+    }
+
+    /// Load a keypair from its 32-byte secret seed
+    pub fn from_secret_bytes(bytes: &[u8]) -> Result<Self, String> {
+        SeededKeypair::from_secret_bytes(bytes, "VRF").map(VrfKeypair)
+    }
+
+    /// The 32-byte secret seed (keep private)
+    pub fn secret_bytes(&self) -> [u8; 32] {
+        self.0.secret_bytes()
+    }
+
+    /// The 32-byte public key miners use to verify proofs
+    pub fn public_key(&self) -> Vec<u8> {
+        self.0.public_key()
+    }
+
+    /// Evaluate the VRF over `input`, returning the randomness and its proof
+    pub fn evaluate(&self, input: &[u8]) -> ([u8; 32], VrfProof) {
+        let context = signing_context(VRF_SIGNING_CONTEXT);
+        let (in_out, proof, _) = self.0.keypair.vrf_sign(context.bytes(input));
+
+        let randomness = in_out.make_bytes::<[u8; 32]>(VRF_OUTPUT_CONTEXT);
+        let proof = VrfProof {
+            public_key: self.public_key(),
+            input: input.to_vec(),
+            pre_output: in_out.to_preout().to_bytes().to_vec(),
+            proof: proof.to_bytes().to_vec(),
+        };
+
+        (randomness, proof)
+    }
+}
+
+/// Publicly verifiable proof that a round's randomness came from the validator's key
+#[derive(Debug, Clone, PartialEq)]
+pub struct VrfProof {
+    /// Validator's VRF public key
+    pub public_key: Vec<u8>,
+    /// The round input the VRF was evaluated on
+    pub input: Vec<u8>,
+    /// VRF pre-output (32 bytes)
+    pub pre_output: Vec<u8>,
+    /// DLEQ proof (64 bytes)
+    pub proof: Vec<u8>,
+}
+
+impl VrfProof {
+    /// Check the proof and return the randomness it commits to
+    pub fn verify(&self) -> Result<[u8; 32], String> {
+        let public_key = PublicKey::from_bytes(&self.public_key)
+            .map_err(|e| format!("Invalid VRF public key: {}", e))?;
+        let pre_output = VRFPreOut::from_bytes(&self.pre_output)
+            .map_err(|e| format!("Invalid VRF output: {}", e))?;
+        let proof = VRFProof::from_bytes(&self.proof)
+            .map_err(|e| format!("Invalid VRF proof: {}", e))?;
+
+        let context = signing_context(VRF_SIGNING_CONTEXT);
+        let (in_out, _) = public_key.vrf_verify(context.bytes(&self.input), &pre_output, &proof)
+            .map_err(|e| format!("VRF proof does not verify: {}", e))?;
+
+        Ok(in_out.make_bytes::<[u8; 32]>(VRF_OUTPUT_CONTEXT))
+    }
+}
+
+/// Build the VRF input for a round from the previous block's hash and timestamp
+pub fn round_input(prev_block_hash: &BlockHash, prev_block_timestamp: u128) -> Vec<u8> {
+    let mut input = prev_block_hash.to_vec();
+    input.extend(&u128_bytes(&prev_block_timestamp));
+    input
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vrf_evaluate_and_verify() {
+        let keypair = VrfKeypair::generate();
+        let input = round_input(&BlockHash::ZERO, 1000000);
+
+        let (randomness, proof) = keypair.evaluate(&input);
+        assert_eq!(proof.verify(), Ok(randomness));
+        assert_eq!(proof.public_key, keypair.public_key());
+    }
+
+    #[test]
+    fn test_vrf_is_deterministic_per_key() {
+        let keypair = VrfKeypair::generate();
+        let restored = VrfKeypair::from_secret_bytes(&keypair.secret_bytes()).unwrap();
+        let input = round_input(&BlockHash::from_bytes([7; 32]), 42);
+
+        assert_eq!(keypair.evaluate(&input).0, restored.evaluate(&input).0);
+        assert_ne!(keypair.evaluate(&input).0, VrfKeypair::generate().evaluate(&input).0);
+    }
+
+    #[test]
+    fn test_vrf_rejects_tampering() {
+        let keypair = VrfKeypair::generate();
+        let (_, proof) = keypair.evaluate(&round_input(&BlockHash::ZERO, 1000000));
+
+        let mut wrong_input = proof.clone();
+        wrong_input.input = round_input(&BlockHash::from_bytes([1; 32]), 1000000);
+        assert!(wrong_input.verify().is_err());
+
+        let mut wrong_key = proof.clone();
+        wrong_key.public_key = VrfKeypair::generate().public_key();
+        assert!(wrong_key.verify().is_err());
+
+        assert!(VrfKeypair::from_secret_bytes(&[1, 2, 3]).is_err());
+    }
+}
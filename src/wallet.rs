@@ -0,0 +1,376 @@
+//! Hierarchical deterministic wallet keys
+//!
+//! A wallet is backed up as a single BIP39 mnemonic phrase. The phrase
+//! stretches to a 64-byte seed, and every key is derived from that seed along
+//! a path such as `m/44'/3600'/0'/0'/5'`.
+//!
+//! Hourcoin keys are sr25519, so BIP32's secp256k1 child arithmetic does not
+//! apply. Derivation follows SLIP-0010 for 25519 curves instead: HMAC-SHA512
+//! chains 32-byte secrets and chain codes, and every step is hardened. Each
+//! derived secret is used as the seed of a `NodeKey`.
+//!
+//! A key address (`hc` + hex of a public key hash) can only be spent by a
+//! transaction carrying a `Witness::Signature` from that key. `Wallet` signs
+//! such inputs, and its share of multisig inputs, in partially signed
+//! transactions, so the seed never has to leave an offline machine.
+
+use std::fmt;
+use std::str::FromStr;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256, Sha512};
+use crate::node_key::{NodeKey, Signer};
+use crate::psbt::PartiallySignedTransaction;
+use crate::transaction::{Output, Transaction, Witness, TX_SIGNING_CONTEXT};
+
+/// Offset marking a hardened derivation index
+pub const HARDENED: u32 = 0x8000_0000;
+
+/// SLIP-0044 style coin type used in Hourcoin paths (3600 seconds in an hour)
+pub const HOURCOIN_COIN_TYPE: u32 = 3600;
+
+/// Prefix of addresses that pay to a single public key
+pub const KEY_ADDRESS_PREFIX: &str = "hc";
+
+/// Receive addresses a wallet scans past the last one it finds a use for
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+/// HMAC key for the master node, as in SLIP-0010 for ed25519
+const MASTER_KEY: &[u8] = b"ed25519 seed";
+
+/// A BIP39 mnemonic seed phrase (English wordlist)
+pub struct Mnemonic {
+    inner: bip39::Mnemonic,
+}
+
+impl Mnemonic {
+    /// Generate a fresh phrase of 12, 15, 18, 21 or 24 words
+    pub fn generate(words: usize) -> Result<Self, String> {
+        let mnemonic_type = bip39::MnemonicType::for_word_count(words)
+            .map_err(|e| format!("Invalid mnemonic length: {}", e))?;
+        Ok(Mnemonic { inner: bip39::Mnemonic::new(mnemonic_type, bip39::Language::English) })
+    }
+
+    /// Restore from a phrase, checking its words and checksum
+    pub fn from_phrase(phrase: &str) -> Result<Self, String> {
+        let inner = bip39::Mnemonic::from_phrase(phrase, bip39::Language::English)
+            .map_err(|e| format!("Invalid mnemonic: {}", e))?;
+        Ok(Mnemonic { inner })
+    }
+
+    /// Restore from 16 to 32 bytes of entropy
+    pub fn from_entropy(entropy: &[u8]) -> Result<Self, String> {
+        let inner = bip39::Mnemonic::from_entropy(entropy, bip39::Language::English)
+            .map_err(|e| format!("Invalid mnemonic entropy: {}", e))?;
+        Ok(Mnemonic { inner })
+    }
+
+    /// The words to write down (keep private)
+    pub fn phrase(&self) -> &str {
+        self.inner.phrase()
+    }
+
+    /// The 64-byte BIP39 seed, optionally protected by a passphrase
+    pub fn to_seed(&self, passphrase: &str) -> Vec<u8> {
+        bip39::Seed::new(&self.inner, passphrase).as_bytes().to_vec()
+    }
+}
+
+/// A derivation path like `m/44'/3600'/0'/0'/0'`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivationPath(Vec<u32>);
+
+impl DerivationPath {
+    /// The standard path for an account's receive key (all hardened)
+    pub fn receive(account: u32, index: u32) -> Self {
+        DerivationPath(vec![
+            44 | HARDENED,
+            HOURCOIN_COIN_TYPE | HARDENED,
+            account | HARDENED,
+            HARDENED,
+            index | HARDENED,
+        ])
+    }
+
+    /// Path indices, with `HARDENED` set on hardened steps
+    pub fn indices(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('/');
+        if parts.next() != Some("m") {
+            return Err(format!("Derivation path '{}' must start with m", s));
+        }
+
+        parts
+            .map(|part| {
+                let (number, hardened) = match part.strip_suffix('\'').or_else(|| part.strip_suffix('h')) {
+                    Some(number) => (number, true),
+                    None => (part, false),
+                };
+                let index: u32 = number.parse()
+                    .map_err(|_| format!("Invalid path component '{}'", part))?;
+                if index >= HARDENED {
+                    return Err(format!("Path component '{}' is out of range", part));
+                }
+                Ok(if hardened { index | HARDENED } else { index })
+            })
+            .collect::<Result<Vec<u32>, String>>()
+            .map(DerivationPath)
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "m")?;
+        for index in &self.0 {
+            if index & HARDENED != 0 {
+                write!(f, "/{}'", index & !HARDENED)?;
+            } else {
+                write!(f, "/{}", index)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A secret and chain code at some point in the derivation tree
+#[derive(Clone)]
+struct ExtendedSecret {
+    secret: [u8; 32],
+    chain_code: [u8; 32],
+}
+
+impl ExtendedSecret {
+    fn from_hmac(key: &[u8], data: &[u8]) -> Self {
+        let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(data);
+        let output = mac.finalize().into_bytes();
+
+        let mut secret = [0u8; 32];
+        let mut chain_code = [0u8; 32];
+        secret.copy_from_slice(&output[..32]);
+        chain_code.copy_from_slice(&output[32..]);
+        ExtendedSecret { secret, chain_code }
+    }
+
+    fn master(seed: &[u8]) -> Self {
+        Self::from_hmac(MASTER_KEY, seed)
+    }
+
+    fn child(&self, index: u32) -> Result<Self, String> {
+        if index & HARDENED == 0 {
+            return Err(format!("Index {} must be hardened for sr25519 keys", index));
+        }
+
+        let mut data = vec![0u8];
+        data.extend(&self.secret);
+        data.extend(&index.to_be_bytes());
+        Ok(Self::from_hmac(&self.chain_code, &data))
+    }
+}
+
+/// Deterministic key tree derived from one seed
+pub struct HdWallet {
+    master: ExtendedSecret,
+}
+
+impl HdWallet {
+    /// Wallet for a raw seed (16 to 64 bytes)
+    pub fn from_seed(seed: &[u8]) -> Result<Self, String> {
+        if seed.len() < 16 || seed.len() > 64 {
+            return Err(format!("Seed must be 16 to 64 bytes, got {}", seed.len()));
+        }
+        Ok(HdWallet { master: ExtendedSecret::master(seed) })
+    }
+
+    /// Wallet for a mnemonic and optional passphrase
+    pub fn from_mnemonic(mnemonic: &Mnemonic, passphrase: &str) -> Self {
+        HdWallet { master: ExtendedSecret::master(&mnemonic.to_seed(passphrase)) }
+    }
+
+    /// Derive the 32-byte secret seed at a path
+    pub fn derive_secret(&self, path: &DerivationPath) -> Result<[u8; 32], String> {
+        let mut node = self.master.clone();
+        for index in path.indices() {
+            node = node.child(*index)?;
+        }
+        Ok(node.secret)
+    }
+
+    /// Derive the signing key at a path
+    pub fn derive(&self, path: &DerivationPath) -> Result<NodeKey, String> {
+        NodeKey::from_secret_bytes(&self.derive_secret(path)?)
+    }
+
+    /// Signing key for an account's `index`th receive address
+    pub fn receive_key(&self, account: u32, index: u32) -> Result<NodeKey, String> {
+        self.derive(&DerivationPath::receive(account, index))
+    }
+
+    /// An account's `index`th receive address
+    pub fn receive_address(&self, account: u32, index: u32) -> Result<String, String> {
+        Ok(address_from_public_key(&self.receive_key(account, index)?.public_key()))
+    }
+}
+
+/// A wallet account that signs offline
+pub struct Wallet {
+    keys: HdWallet,
+    account: u32,
+    gap_limit: u32,
+}
+
+impl Wallet {
+    /// Account 0 of the wallet behind a mnemonic
+    pub fn from_mnemonic(mnemonic: &Mnemonic, passphrase: &str) -> Self {
+        Self::new(HdWallet::from_mnemonic(mnemonic, passphrase), 0)
+    }
+
+    pub fn new(keys: HdWallet, account: u32) -> Self {
+        Wallet { keys, account, gap_limit: DEFAULT_GAP_LIMIT }
+    }
+
+    /// Set how many receive keys `sign` searches when an input has no derivation path
+    pub fn with_gap_limit(mut self, gap_limit: u32) -> Self {
+        self.gap_limit = gap_limit;
+        self
+    }
+
+    pub fn receive_key(&self, index: u32) -> Result<NodeKey, String> {
+        self.keys.receive_key(self.account, index)
+    }
+
+    pub fn receive_address(&self, index: u32) -> Result<String, String> {
+        self.keys.receive_address(self.account, index)
+    }
+
+    /// Sign every input this wallet holds a key for, returning how many signatures were added
+    pub fn sign(&self, psbt: &mut PartiallySignedTransaction) -> Result<usize, String> {
+        let message = psbt.signing_hash()?;
+        let transaction = psbt.unsigned_transaction()?;
+        let receive_keys = (0..self.gap_limit)
+            .map(|index| self.receive_key(index))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let mut added = 0;
+        for (index, input) in transaction.inputs.iter().enumerate() {
+            let hints = &psbt.inputs[index];
+            let hinted_key = match &hints.derivation_path {
+                Some(path) => Some(self.keys.derive(&path.parse()?)?),
+                None => None,
+            };
+            let policy = match &hints.multisig {
+                Some(policy) => Some(policy.to_policy()?),
+                None => None,
+            };
+
+            let mut signed = vec![];
+            for key in hinted_key.iter().chain(&receive_keys) {
+                let public_key = key.public_key();
+                let owns_input = match &policy {
+                    Some(policy) => policy.position(&public_key).is_some(),
+                    None => address_from_public_key(&public_key) == input.to_addr,
+                };
+                if !owns_input || signed.contains(&public_key) || psbt.inputs[index].signatures.contains_key(&hex::encode(&public_key)) {
+                    continue;
+                }
+
+                psbt.add_signature(index, &public_key, &key.sign(TX_SIGNING_CONTEXT, message.as_bytes()))?;
+                signed.push(public_key);
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+}
+
+/// The address paying to a public key: `hc` + hex of the first 20 bytes of its SHA-256
+pub fn address_from_public_key(public_key: &[u8]) -> String {
+    let digest = Sha256::digest(public_key);
+    format!("{}{}", KEY_ADDRESS_PREFIX, hex::encode(&digest[..20]))
+}
+
+/// Whether an address pays to a single public key
+pub fn is_key_address(address: &str) -> bool {
+    address.len() == KEY_ADDRESS_PREFIX.len() + 40
+        && address.strip_prefix(KEY_ADDRESS_PREFIX)
+            .is_some_and(|hash| hash.bytes().all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase()))
+}
+
+/// The public key and signature that must verify for `transaction` to spend `input`, a key address
+pub(crate) fn input_signer<'a>(input: &Output, transaction: &'a Transaction) -> Result<Signer<'a>, String> {
+    transaction.witnesses.iter()
+        .find_map(|witness| match witness {
+            Witness::Signature { public_key, signature } if address_from_public_key(public_key) == input.to_addr => {
+                Some((public_key.as_slice(), signature.as_slice()))
+            }
+            _ => None,
+        })
+        .ok_or_else(|| format!("No signature for input {}", input.to_addr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ABANDON: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    #[test]
+    fn test_bip39_seed_vector() {
+        let mnemonic = Mnemonic::from_phrase(ABANDON).unwrap();
+        assert_eq!(hex::encode(mnemonic.to_seed("TREZOR")),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04");
+
+        // Bad checksum
+        assert!(Mnemonic::from_phrase(&ABANDON.replace("about", "abandon")).is_err());
+        assert_eq!(Mnemonic::generate(24).unwrap().phrase().split(' ').count(), 24);
+        assert!(Mnemonic::generate(13).is_err());
+    }
+
+    #[test]
+    fn test_slip10_vector() {
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let master = ExtendedSecret::master(&seed);
+        assert_eq!(hex::encode(master.secret), "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7");
+        assert_eq!(hex::encode(master.chain_code), "90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb");
+
+        let wallet = HdWallet::from_seed(&seed).unwrap();
+        let child = wallet.derive_secret(&"m/0'".parse().unwrap()).unwrap();
+        assert_eq!(hex::encode(child), "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3");
+    }
+
+    #[test]
+    fn test_derivation_path() {
+        let path: DerivationPath = "m/44'/3600'/0'/0'/7'".parse().unwrap();
+        assert_eq!(path, DerivationPath::receive(0, 7));
+        assert_eq!(path.to_string(), "m/44'/3600'/0'/0'/7'");
+        assert_eq!("m/1h/2".parse::<DerivationPath>().unwrap().indices(), &[1 | HARDENED, 2]);
+
+        assert!("44'/0'".parse::<DerivationPath>().is_err());
+        assert!("m/x".parse::<DerivationPath>().is_err());
+        assert!("m/2147483648".parse::<DerivationPath>().is_err());
+
+        let wallet = HdWallet::from_seed(&[1; 32]).unwrap();
+        assert!(wallet.derive(&"m/0".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_receive_addresses_are_deterministic() {
+        let mnemonic = Mnemonic::from_phrase(ABANDON).unwrap();
+        let wallet = HdWallet::from_mnemonic(&mnemonic, "");
+        let restored = HdWallet::from_mnemonic(&Mnemonic::from_phrase(mnemonic.phrase()).unwrap(), "");
+
+        assert_eq!(wallet.receive_address(0, 0), restored.receive_address(0, 0));
+        assert_ne!(wallet.receive_address(0, 0), wallet.receive_address(0, 1));
+        assert_ne!(wallet.receive_address(0, 0), HdWallet::from_mnemonic(&mnemonic, "other").receive_address(0, 0));
+
+        let key = wallet.receive_key(0, 0).unwrap();
+        assert_eq!(wallet.receive_address(0, 0).unwrap(), address_from_public_key(&key.public_key()));
+        assert!(wallet.receive_address(0, 0).unwrap().starts_with("hc"));
+    }
+}
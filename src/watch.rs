@@ -0,0 +1,77 @@
+//! Address activity for watching wallets
+//!
+//! Whenever a transaction enters the mempool or is confirmed in a block, the
+//! validator publishes one `AddressActivity` per address it touches. The
+//! server forwards each to the connections watching that address (see
+//! `MinerMessage::WatchAddress`), so a wallet hears about a payment as soon as
+//! it is broadcast and again once it's mined.
+
+use crate::hashable::Hashable;
+use crate::{Transaction, Txid};
+
+/// Activity the feed buffers for each subscriber; one that falls further behind skips the oldest
+pub const ACTIVITY_FEED_CAPACITY: usize = 1024;
+
+/// How a transaction moved value for one address
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddressActivity {
+    pub address: String,
+    pub tx_hash: Txid,
+    /// Sum of the outputs paying the address
+    pub received: f64,
+    /// Sum of the inputs spent from the address
+    pub sent: f64,
+    /// Height of the block confirming the transaction; `None` while it waits in the mempool
+    pub height: Option<u32>,
+}
+
+impl AddressActivity {
+    /// The activity of every address `transaction` spends from or pays, in the order they appear
+    pub fn of_transaction(transaction: &Transaction, height: Option<u32>) -> Vec<AddressActivity> {
+        let tx_hash = transaction.hash();
+        let mut activity: Vec<AddressActivity> = Vec::new();
+        let touched = transaction.inputs.iter().map(|input| (input, 0.0, input.value))
+            .chain(transaction.outputs.iter().map(|output| (output, output.value, 0.0)));
+        for (output, received, sent) in touched {
+            match activity.iter_mut().find(|a| a.address == output.to_addr) {
+                Some(a) => {
+                    a.received += received;
+                    a.sent += sent;
+                }
+                None => activity.push(AddressActivity {
+                    address: output.to_addr.clone(),
+                    tx_hash,
+                    received,
+                    sent,
+                    height,
+                }),
+            }
+        }
+        activity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Output;
+
+    fn output(to_addr: &str, value: f64) -> Output {
+        Output { to_addr: to_addr.to_string(), value, timestamp: 0, lock_until: None }
+    }
+
+    #[test]
+    fn test_activity_sums_per_address() {
+        let transaction = Transaction {
+            inputs: vec![output("alice", 3.0), output("alice", 2.0)],
+            outputs: vec![output("bob", 4.0), output("alice", 0.9)],
+            witnesses: vec![],
+        };
+
+        let activity = AddressActivity::of_transaction(&transaction, Some(7));
+        assert_eq!(activity.len(), 2);
+        assert_eq!((activity[0].address.as_str(), activity[0].received, activity[0].sent), ("alice", 0.9, 5.0));
+        assert_eq!((activity[1].address.as_str(), activity[1].received, activity[1].sent), ("bob", 4.0, 0.0));
+        assert!(activity.iter().all(|a| a.height == Some(7) && a.tx_hash == transaction.hash()));
+    }
+}